@@ -0,0 +1,103 @@
+mod accounts;
+mod aliases;
+mod archive;
+mod auth;
+mod batch;
+mod budgets;
+mod bulk;
+mod callback;
+mod closing;
+mod commands;
+mod draft;
+mod expense;
+mod favorites;
+mod feed;
+mod format;
+mod in_flight;
+mod incoming;
+mod intent;
+mod keyboards;
+mod last;
+mod long;
+mod markdown;
+mod menu;
+mod notify;
+mod onboarding;
+mod orphans;
+mod parse;
+mod permits;
+mod queue;
+mod read_only;
+mod reconcile;
+#[cfg(test)]
+mod recording;
+mod repeat;
+mod reporter;
+mod resolve;
+mod review;
+mod rules;
+mod scheduler;
+mod settings;
+mod setup;
+mod sources;
+mod subscriptions;
+mod sweep;
+mod templates;
+
+pub use draft::{Drafts, SharedDrafts};
+pub use expense::SharedCallbacks;
+pub use feed::{Feeds, SharedFeeds};
+pub use incoming::{Hints, SharedHints};
+pub use menu::register as register_commands;
+pub use orphans::expire as expire_orphan_drafts;
+pub use permits::SharedCoordinator;
+pub use queue::{drain as drain_notifications, DRAIN_EVERY};
+pub use reporter::{deliver as deliver_errors, spawn, ErrorReporter};
+pub use scheduler::Scheduler;
+pub use subscriptions::{deliver as queue_reports, DELIVER_EVERY};
+pub use sweep::maintain as maintain_database;
+pub use sweep::{sweep, SWEEP_EVERY};
+
+use crate::model::Model;
+use std::sync::{Arc, Mutex};
+use teloxide::adaptors::DefaultParseMode;
+use teloxide::dispatching::UpdateHandler;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+/// The bot every handler talks through: all messages are MarkdownV2, see
+/// [`markdown`] for escaping.
+pub type Bot = DefaultParseMode<teloxide::Bot>;
+
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+pub type HandlerResult = Result<(), HandlerError>;
+pub type SharedModel = Arc<Mutex<Model>>;
+
+pub fn create_bot() -> Bot {
+    teloxide::Bot::from_env().parse_mode(ParseMode::MarkdownV2)
+}
+
+pub fn schema() -> UpdateHandler<HandlerError> {
+    dptree::entry()
+        .branch(
+            Update::filter_message()
+                .map(aliases::expand_message)
+                .filter_map_async(permits::for_message)
+                .branch(
+                    dptree::entry()
+                        .filter_command::<commands::Command>()
+                        .endpoint(commands::handle_command),
+                )
+                .branch(dptree::endpoint(expense::handle_message)),
+        )
+        .branch(
+            Update::filter_message_reaction_updated()
+                .filter_map_async(permits::for_reaction)
+                .endpoint(repeat::handle_reaction),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .filter_map_async(permits::for_callback)
+                .endpoint(expense::handle_callback_query),
+        )
+}
@@ -0,0 +1,240 @@
+//! `/account delete`: deleting an account created by mistake, or moving
+//! what refers to it to another account first. `/account limit`: the daily
+//! limit of an account, above which saving an expense asks to confirm.
+
+use super::format::format_amount;
+use super::{parse, resolve, SharedModel};
+use crate::model::{AmountLimits, DependencyReport, Error, Locale, User};
+
+fn counted(count: usize, one: &str, many: &str) -> Option<String> {
+    match count {
+        0 => None,
+        1 => Some(format!("1 {}", one)),
+        n => Some(format!("{} {}", n, many)),
+    }
+}
+
+/// What the report counts, like "2 expenses and 1 favorite".
+fn describe(report: &DependencyReport) -> String {
+    let parts: Vec<String> = [
+        counted(report.expenses, "expense", "expenses"),
+        counted(
+            report.archived_expenses,
+            "archived expense",
+            "archived expenses",
+        ),
+        counted(report.favorites, "favorite", "favorites"),
+        counted(report.reconciliations, "reconciliation", "reconciliations"),
+        counted(report.sources, "trusted source", "trusted sources"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    match parts.split_last() {
+        None => "nothing".to_string(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// `/account delete`: the reply to send. Without a target only an account
+/// nothing refers to is deleted, otherwise the reply tells what keeps it.
+pub fn handle_account(model: &SharedModel, user: &User, args: &str) -> Result<String, Error> {
+    let (name, target) = match parse::parse_account_delete(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let account = match resolve::account(&model, user.household, &name)? {
+        Ok(account) => account,
+        Err(reason) => return Ok(reason),
+    };
+    let Some(target) = target else {
+        let report = model.account_dependencies(user.household, account.id)?;
+        if report.archived_expenses > 0 {
+            return Ok(format!(
+                "{} can't be deleted, it has {}. Archived expenses can't be moved, so the account stays.",
+                account.name,
+                describe(&report)
+            ));
+        }
+        if !report.is_empty() {
+            return Ok(format!(
+                "{} can't be deleted, it has {}. To move them to another account in {} and delete it: /account delete \"{}\" into \"<account>\".",
+                account.name,
+                describe(&report),
+                account.currency,
+                account.name
+            ));
+        }
+        model.delete_account(user.household, user.telegram_id, account.id)?;
+        return Ok(format!("Deleted {}.", account.name));
+    };
+    let target = match resolve::account(&model, user.household, &target)? {
+        Ok(target) => target,
+        Err(reason) => return Ok(reason),
+    };
+    let ids = (account.id, target.id);
+    match model.move_and_delete_account(user.household, user.telegram_id, ids) {
+        Ok(moved) if moved.is_empty() => Ok(format!("Deleted {}.", account.name)),
+        Ok(moved) => Ok(format!(
+            "Moved {} to {} and deleted {}.",
+            describe(&moved),
+            target.name,
+            account.name
+        )),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(e) => Err(e),
+    }
+}
+
+/// `/account limit`: the reply to send. The limit is in the currency of
+/// the account, typed along to be sure of it.
+pub fn handle_limit(
+    model: &SharedModel,
+    user: &User,
+    args: &str,
+    (limits, locale): (AmountLimits, Locale),
+) -> Result<String, Error> {
+    let (name, limit) = match parse::parse_account_limit(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let account = match resolve::account(&model, user.household, &name)? {
+        Ok(account) => account,
+        Err(reason) => return Ok(reason),
+    };
+    let Some((amount, currency)) = limit else {
+        model.set_daily_limit(user.household, account.id, None)?;
+        return Ok(format!("{} has no daily limit now.", account.name));
+    };
+    if !currency.eq_ignore_ascii_case(&account.currency) {
+        return Ok(format!(
+            "{} is in {}, so is its daily limit.",
+            account.name, account.currency
+        ));
+    }
+    let amount = match limits.for_exponent(account.exponent).parse(&amount, locale) {
+        Ok(parsed) => parsed.amount,
+        Err(e) => return Ok(e.to_string()),
+    };
+    model.set_daily_limit(user.household, account.id, Some(amount))?;
+    Ok(format!(
+        "The daily limit of {} is {} {} now, expenses over it ask to confirm.",
+        account.name,
+        format_amount(amount, account.exponent),
+        account.currency
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Model, NewFavorite};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn account_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let alex = model.get_user(1001).unwrap().unwrap();
+        model.add_account(1, "Typo", 1).unwrap();
+        let wallet = model.add_account(1, "Wallet", 1).unwrap();
+        model
+            .add_favorite(
+                1,
+                &NewFavorite {
+                    user_id: 1001,
+                    label: "coffee".to_string(),
+                    amount: 3.0,
+                    account_id: wallet,
+                    category_id: 1,
+                    comment: None,
+                },
+            )
+            .unwrap();
+        let model = Arc::new(Mutex::new(model));
+        let account = |args| handle_account(&model, &alex, args).unwrap();
+
+        assert_eq!("Deleted Typo.", account("delete typo"));
+        assert!(account("delete typo").starts_with("No account"));
+        // Hanna Daily has two expenses.
+        assert_eq!(
+            "Hanna Daily can't be deleted, it has 2 expenses. To move them to another account in USD and delete it: /account delete \"Hanna Daily\" into \"<account>\".",
+            account("delete Hanna Daily")
+        );
+        assert_eq!(
+            "Wallet is in EUR and Family BYN in BYN, their expenses can't be moved between them.",
+            account("delete wallet into family")
+        );
+        assert_eq!(
+            "Moved 1 favorite to Alex Savings and deleted Wallet.",
+            account("delete wallet into \"alex savings\"")
+        );
+        assert_eq!(parse::ACCOUNT_USAGE, account("delete"));
+    }
+
+    #[test]
+    fn limit_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let alex = model.get_user(1001).unwrap().unwrap();
+        let model = Arc::new(Mutex::new(model));
+        let limit = |args| {
+            handle_limit(
+                &model,
+                &alex,
+                args,
+                (AmountLimits::default(), Locale::DecimalPoint),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            "The daily limit of Alex Savings is 20.00 EUR now, expenses over it ask to confirm.",
+            limit("limit \"alex savings\" 20 eur")
+        );
+        let day = (chrono::Utc::now(), chrono_tz::Tz::UTC);
+        let usage = model.lock().unwrap().daily_usage(1, 1, day, None).unwrap();
+        assert_eq!(Some(20.0), usage.map(|u| u.limit));
+        assert_eq!(
+            "Alex Savings is in EUR, so is its daily limit.",
+            limit("limit alex 20 USD")
+        );
+        assert_eq!("The amount can't be zero.", limit("limit alex 0 EUR"));
+        assert_eq!(
+            "Alex Savings has no daily limit now.",
+            limit("limit alex none")
+        );
+        assert!(model
+            .lock()
+            .unwrap()
+            .daily_usage(1, 1, day, None)
+            .unwrap()
+            .is_none());
+        assert!(limit("limit rent 5 EUR").starts_with("No account"));
+        assert_eq!(parse::ACCOUNT_USAGE, limit("limit"));
+    }
+
+    #[test]
+    fn reports() {
+        let report = DependencyReport {
+            expenses: 3,
+            favorites: 1,
+            sources: 2,
+            ..DependencyReport::default()
+        };
+        assert_eq!(
+            "3 expenses, 1 favorite and 2 trusted sources",
+            describe(&report)
+        );
+        assert_eq!(
+            "1 reconciliation",
+            describe(&DependencyReport {
+                reconciliations: 1,
+                ..DependencyReport::default()
+            })
+        );
+    }
+}
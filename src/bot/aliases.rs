@@ -0,0 +1,274 @@
+//! `/alias`: shorthands for commands, like `/rl` for `/report lastmonth`.
+//! They are expanded before commands are parsed, so every command can have
+//! one, and only once: an alias stands for a command, never another alias.
+
+use super::commands::Command;
+use super::parse::{self, ALIAS_USAGE};
+use super::SharedModel;
+use crate::model::Error;
+use log::*;
+use teloxide::prelude::*;
+use teloxide::types::{MediaKind, MediaText, MessageCommon, MessageKind};
+use teloxide::utils::command::BotCommands;
+
+/// Shorthands everyone has, unless they define an alias of the same name.
+pub const BUILT_IN: &[(&str, &str)] = &[("r", "report"), ("b", "balance")];
+
+const MAX_NAME: usize = 32;
+
+fn is_command(name: &str) -> bool {
+    Command::bot_commands()
+        .iter()
+        .any(|c| c.command.trim_start_matches('/') == name)
+}
+
+fn built_in(name: &str) -> Option<String> {
+    BUILT_IN
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, command)| command.to_string())
+}
+
+/// The text with its leading alias replaced by what it stands for, `None` if
+/// it doesn't start with one. `alias` looks up the user's own aliases.
+pub fn expand(text: &str, alias: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let command = text.strip_prefix('/')?;
+    let (head, rest) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    let (name, bot_name) = match head.split_once('@') {
+        Some((name, bot_name)) => (name, Some(bot_name)),
+        None => (head, None),
+    };
+    let name = name.to_lowercase();
+    if is_command(&name) {
+        return None;
+    }
+    let expansion = alias(&name).or_else(|| built_in(&name))?;
+    let (target, arguments) = expansion
+        .split_once(' ')
+        .unwrap_or((expansion.as_str(), ""));
+    let mut expanded = format!("/{}", target);
+    if let Some(bot_name) = bot_name {
+        expanded = format!("{}@{}", expanded, bot_name);
+    }
+    for part in [arguments, rest.trim()] {
+        if !part.is_empty() {
+            expanded = format!("{} {}", expanded, part);
+        }
+    }
+    Some(expanded)
+}
+
+/// The pre-dispatch hook: the message with the sender's alias expanded.
+/// Entities are left as they were, commands are parsed from the text.
+pub fn expand_message(mut message: Message, model: SharedModel) -> Message {
+    let Some(user_id) = message.from.as_ref().map(|u| u.id.0 as i64) else {
+        return message;
+    };
+    if let MessageKind::Common(MessageCommon {
+        media_kind: MediaKind::Text(MediaText { text, .. }),
+        ..
+    }) = &mut message.kind
+    {
+        if !text.starts_with('/') {
+            return message;
+        }
+        let model = model.lock().unwrap();
+        let expanded = expand(text, |name| match model.alias(user_id, name) {
+            Ok(expansion) => expansion,
+            Err(e) => {
+                warn!(
+                    "Looking up alias {} of user {} failed: {}",
+                    name, user_id, e
+                );
+                None
+            }
+        });
+        if let Some(expanded) = expanded {
+            debug!("Expanded '{}' to '{}'", text, expanded);
+            *text = expanded;
+        }
+    }
+    message
+}
+
+/// Why the alias can't be added, if it can't. It must not hide a command,
+/// and must stand for a command rather than itself or another alias.
+pub fn check(name: &str, expansion: &str, aliases: &[(String, String)]) -> Result<(), String> {
+    let valid = |s: &str| {
+        !s.is_empty()
+            && s.len() <= MAX_NAME
+            && s.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    };
+    if !valid(name) {
+        return Err(format!(
+            "Alias names are up to {} lowercase letters, digits and underscores.",
+            MAX_NAME
+        ));
+    }
+    if is_command(name) {
+        return Err(format!("/{} is a command already.", name));
+    }
+    let target = expansion
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if target == name {
+        return Err("An alias can't stand for itself.".to_string());
+    }
+    if aliases.iter().any(|(alias, _)| *alias == target) || built_in(&target).is_some() {
+        return Err(format!(
+            "/{} is an alias itself, aliases stand for commands only.",
+            target
+        ));
+    }
+    if !is_command(&target) {
+        return Err(format!("There is no command /{}.", target));
+    }
+    Ok(())
+}
+
+/// `/alias`: the reply to send.
+pub fn handle_alias(model: &SharedModel, user_id: i64, args: &str) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    let args = args.trim();
+    let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+    match action {
+        "" | "list" => {
+            let aliases = model.aliases(user_id)?;
+            let mut lines = vec!["Your aliases:".to_string()];
+            lines.extend(
+                aliases
+                    .iter()
+                    .map(|(name, expansion)| format!("/{} → /{}", name, expansion)),
+            );
+            lines.extend(
+                BUILT_IN
+                    .iter()
+                    .filter(|(name, _)| !aliases.iter().any(|(alias, _)| alias == name))
+                    .map(|(name, command)| format!("/{} → /{} (built in)", name, command)),
+            );
+            Ok(lines.join("\n"))
+        }
+        "add" => {
+            let (name, expansion) = match parse::parse_alias_add(args) {
+                Ok(parsed) => parsed,
+                Err(reason) => return Ok(reason),
+            };
+            if let Err(reason) = check(&name, &expansion, &model.aliases(user_id)?) {
+                return Ok(reason);
+            }
+            model.set_alias(user_id, &name, &expansion)?;
+            Ok(format!("/{} now stands for /{}.", name, expansion))
+        }
+        "del" if !rest.trim().is_empty() => {
+            let name = rest.trim().trim_start_matches('/').to_lowercase();
+            Ok(if model.delete_alias(user_id, &name)? {
+                match built_in(&name) {
+                    Some(command) => {
+                        format!("Deleted /{}, it stands for /{} again.", name, command)
+                    }
+                    None => format!("Deleted /{}.", name),
+                }
+            } else {
+                format!("You have no alias /{}.", name)
+            })
+        }
+        _ => Ok(ALIAS_USAGE.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    fn mine(name: &str) -> Option<String> {
+        match name {
+            "rl" => Some("report lastmonth".to_string()),
+            "b" => Some("balance".to_string()),
+            "r" => Some("report ytd".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands_aliases_once() {
+        let expand = |text| expand(text, mine);
+        assert_eq!(Some("/report lastmonth".to_string()), expand("/rl"));
+        assert_eq!(
+            Some("/report@tracker_bot lastmonth extra".to_string()),
+            expand("/RL@tracker_bot  extra")
+        );
+        // User aliases win over the built-in ones.
+        assert_eq!(Some("/report ytd".to_string()), expand("/r"));
+        assert_eq!(Some("/balance".to_string()), super::expand("/b", |_| None));
+        // Commands are never aliases, and only the first word is.
+        assert_eq!(None, expand("/report rl"));
+        assert_eq!(None, expand("/unknown"));
+        assert_eq!(None, expand("rl"));
+        assert_eq!(None, expand("12.5 rl"));
+    }
+
+    #[test]
+    fn rejects_aliases_that_could_loop_or_hide_commands() {
+        let aliases = vec![("rl".to_string(), "report lastmonth".to_string())];
+        assert_eq!(Ok(()), check("bal", "balance", &aliases));
+        assert_eq!(Ok(()), check("r", "report week", &aliases));
+        assert_eq!(
+            Err("An alias can't stand for itself.".to_string()),
+            check("x", "x", &aliases)
+        );
+        assert_eq!(
+            Err("/rl is an alias itself, aliases stand for commands only.".to_string()),
+            check("x", "rl", &aliases)
+        );
+        assert_eq!(
+            Err("/r is an alias itself, aliases stand for commands only.".to_string()),
+            check("x", "r lastmonth", &aliases)
+        );
+        assert_eq!(
+            Err("/report is a command already.".to_string()),
+            check("report", "balance", &aliases)
+        );
+        assert_eq!(
+            Err("There is no command /latest.".to_string()),
+            check("x", "latest", &aliases)
+        );
+        assert!(check("Rl-2", "report", &aliases).is_err());
+    }
+
+    #[test]
+    fn alias_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let run = |args: &str| handle_alias(&model, 1001, args).unwrap();
+
+        assert_eq!(
+            "/rl now stands for /report lastmonth.",
+            run(r#"add rl "report lastmonth""#)
+        );
+        assert_eq!("/r now stands for /report ytd.", run("add r report ytd"));
+        assert_eq!(
+            "/rl is an alias itself, aliases stand for commands only.",
+            run("add y rl")
+        );
+        assert_eq!(
+            "Your aliases:\n/r → /report ytd\n/rl → /report lastmonth\n/b → /balance (built in)",
+            run("list")
+        );
+        assert_eq!("Deleted /r, it stands for /report again.", run("del r"));
+        assert_eq!("You have no alias /r.", run("del /r"));
+        assert_eq!(ALIAS_USAGE, run("del"));
+        assert_eq!(
+            Some("report lastmonth".to_string()),
+            model.lock().unwrap().alias(1001, "rl").unwrap()
+        );
+        assert_eq!(None, model.lock().unwrap().alias(1002, "rl").unwrap());
+    }
+}
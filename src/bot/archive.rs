@@ -0,0 +1,102 @@
+//! `/archive before <date>`: moves old expenses of all households to a
+//! database file of their own, after confirmation. Reports still include
+//! them.
+
+use super::auth::{self, Access};
+use super::callback::CallbackData;
+use super::commands::SHARED_ONLY;
+use super::draft::DraftKey;
+use super::markdown::escape_md;
+use super::{keyboards, parse, Bot, HandlerResult, SharedModel};
+use crate::model::{archive_file, Error, Role, DEFAULT_HOUSEHOLD};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use std::path::Path;
+use teloxide::prelude::*;
+use teloxide::types::CallbackQuery;
+
+fn expenses(count: usize) -> String {
+    match count {
+        1 => "1 expense".to_string(),
+        n => format!("{} expenses", n),
+    }
+}
+
+/// `/archive`: asks to confirm moving the expenses before the cutoff.
+pub async fn handle_archive(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    (db, tz): (&Path, Tz),
+    args: &str,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let cutoff = match parse::parse_archive(args) {
+        Ok(cutoff) => cutoff,
+        Err(usage) => {
+            bot.send_message(chat_id, escape_md(&usage)).await?;
+            return Ok(());
+        }
+    };
+    let count = model.lock().unwrap().count_before(cutoff, tz)?;
+    if count == 0 {
+        let text = format!("There are no expenses before {}.", cutoff);
+        bot.send_message(chat_id, escape_md(&text)).await?;
+        return Ok(());
+    }
+    let text = format!(
+        "Move {} from before {} to {}? Reports, exports and balances still include them.",
+        expenses(count),
+        cutoff,
+        archive_file(db, cutoff).display()
+    );
+    let confirm = CallbackData::Archive(cutoff).encode();
+    bot.send_message(chat_id, escape_md(&text))
+        .reply_markup(keyboards::confirm_keyboard(confirm, CallbackData::Dismiss))
+        .await?;
+    Ok(())
+}
+
+/// The confirm button of `/archive`.
+pub async fn confirm_archive(
+    bot: &Bot,
+    q: &CallbackQuery,
+    model: &SharedModel,
+    key: DraftKey,
+    cutoff: NaiveDate,
+    (db, tz): (&Path, Tz),
+) -> HandlerResult {
+    let user = match auth::requires(model, &q.from, key.chat_id, Role::Admin)? {
+        Access::Granted(user) => user,
+        Access::Denied(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+            return Ok(());
+        }
+    };
+    if user.household != DEFAULT_HOUSEHOLD {
+        bot.answer_callback_query(q.id.clone())
+            .text(SHARED_ONLY)
+            .await?;
+        return Ok(());
+    }
+
+    let path = archive_file(db, cutoff);
+    let archived = model
+        .lock()
+        .unwrap()
+        .archive_before(user.telegram_id, cutoff, &path, tz);
+    let text = match archived {
+        Ok(count) => format!(
+            "Archived {} from before {} to {}.",
+            expenses(count),
+            cutoff,
+            path.display()
+        ),
+        Err(Error::Refused(reason)) => reason,
+        Err(e) => return Err(e.into()),
+    };
+    bot.edit_message_text(key.chat_id, key.message_id, escape_md(&text))
+        .await?;
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
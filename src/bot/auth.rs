@@ -0,0 +1,138 @@
+//! Role based permission checks. Every command and handler declares the
+//! role it needs and calls [`requires`] before doing any work. The granted
+//! user's household scopes everything the handler reads and changes.
+
+use super::{HandlerError, SharedModel};
+use crate::model::{Role, User};
+use teloxide::types;
+
+pub enum Access {
+    Granted(User),
+    Denied(String),
+}
+
+/// Checks that the sender has at least the `required` role, and belongs to
+/// the household of the chat if it is bound to one. Names and languages of
+/// known users are refreshed on the way, so they stay in sync with Telegram.
+pub fn requires(
+    model: &SharedModel,
+    from: &types::User,
+    chat_id: types::ChatId,
+    required: Role,
+) -> Result<Access, HandlerError> {
+    let model = model.lock().unwrap();
+    let telegram_id = from.id.0 as i64;
+    let telegram_name = from.username.clone().unwrap_or_default();
+    model.touch_user(
+        telegram_id,
+        (&telegram_name, &from.full_name()),
+        from.language_code.as_deref(),
+    )?;
+    let user = model.get_user(telegram_id)?;
+    let bound = model.chat_household(chat_id.0)?;
+
+    Ok(match refusal(user.as_ref(), telegram_id, bound, required) {
+        Some(text) => Access::Denied(text),
+        None => Access::Granted(user.unwrap()),
+    })
+}
+
+/// The text to refuse with, or `None` if the user may proceed. `bound` is
+/// the household of the chat, if any.
+pub fn refusal(
+    user: Option<&User>,
+    telegram_id: i64,
+    bound: Option<i64>,
+    required: Role,
+) -> Option<String> {
+    match user {
+        None => Some(format!(
+            "You are not allowed to use this bot. Ask the admin to allow your id {}.",
+            telegram_id
+        )),
+        Some(user) if bound.is_some_and(|h| h != user.household) => {
+            Some("⛔ This chat belongs to another household.".to_string())
+        }
+        Some(user) if !user.role.allows(required) => Some(format!(
+            "⛔ This needs the {} role, you are a {}.",
+            required, user.role
+        )),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(role: Role) -> User {
+        User {
+            telegram_id: 1,
+            telegram_name: "name".to_string(),
+            display_name: "Name".to_string(),
+            role,
+            household: 1,
+        }
+    }
+
+    #[test]
+    fn unknown_user_is_refused() {
+        let text = refusal(None, 77, None, Role::Viewer).unwrap();
+        assert!(text.contains("77"));
+    }
+
+    #[test]
+    fn refuses_above_own_role() {
+        let refusal = |role, required| refusal(Some(&user(role)), 1, None, required);
+        assert_eq!(None, refusal(Role::Viewer, Role::Viewer));
+        assert_eq!(
+            Some("⛔ This needs the member role, you are a viewer.".to_string()),
+            refusal(Role::Viewer, Role::Member)
+        );
+        assert_eq!(None, refusal(Role::Admin, Role::Member));
+        assert!(refusal(Role::Member, Role::Admin).is_some());
+    }
+
+    #[test]
+    fn names_follow_telegram() {
+        let model = crate::model::Model::new(true);
+        model.fill_test_data();
+        let model = std::sync::Arc::new(std::sync::Mutex::new(model));
+        let mut from = types::User {
+            id: types::UserId(1002),
+            is_bot: false,
+            first_name: "Hanna".to_string(),
+            last_name: None,
+            username: Some("hanna_bot".to_string()),
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        let name =
+            |from: &types::User| match requires(&model, from, types::ChatId(1002), Role::Viewer) {
+                Ok(Access::Granted(user)) => (user.telegram_name, user.display_name),
+                _ => panic!("refused"),
+            };
+        assert_eq!(("hanna_bot".to_string(), "Hanna".to_string()), name(&from));
+
+        // Renamed, and then without a username at all: still the same user.
+        from.username = Some("hanna_b".to_string());
+        from.last_name = Some("B".to_string());
+        assert_eq!(("hanna_b".to_string(), "Hanna B".to_string()), name(&from));
+        from.username = None;
+        assert_eq!((String::new(), "Hanna B".to_string()), name(&from));
+        let model = model.lock().unwrap();
+        assert_eq!(1002, model.resolve_user_id("hanna b").unwrap());
+        assert!(model.find_user_by_name("@hanna_b").unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_chats_of_other_households() {
+        let admin = user(Role::Admin);
+        assert_eq!(None, refusal(Some(&admin), 1, Some(1), Role::Admin));
+        assert_eq!(
+            Some("⛔ This chat belongs to another household.".to_string()),
+            refusal(Some(&admin), 1, Some(2), Role::Viewer)
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Several expenses pasted in one message, one per line. Each line is read
+//! like a message of its own; the summary lists what every line reads as,
+//! and its buttons save the expenses together or not at all.
+
+use super::draft::{ActiveTransaction, DraftKey, SharedDrafts};
+use super::expense::{new_draft, read_typed, source_of};
+use super::feed::{self, SharedFeeds};
+use super::markdown::escape_md;
+use super::{format, keyboards, notify, settings, Bot, HandlerResult, SharedModel};
+use crate::model::{Account, AmountLimits, Category, Error, Inserted, Locale, Model, User};
+use teloxide::prelude::*;
+use teloxide::types::CallbackQuery;
+
+/// The most expenses one message may carry.
+pub const MAX_LINES: usize = 20;
+
+/// A typed line with the draft it reads as, or why it can't be saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchLine {
+    /// Counted from 1, leaving out blank lines.
+    pub number: usize,
+    pub text: String,
+    pub draft: Result<ActiveTransaction, String>,
+}
+
+pub type Batch = Vec<BatchLine>;
+
+/// The user typing the batch with their strict commit setting, and the
+/// account and category a single message would start its draft in.
+pub type Defaults<'a> = (&'a User, bool, Account, Category);
+
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty())
+}
+
+/// Whether `text` has more than one expense to read.
+pub fn is_batch(text: &str) -> bool {
+    lines(text).nth(1).is_some()
+}
+
+/// Reads every line of `text` the way a message with just that line would
+/// be read, or refuses messages with more than `MAX_LINES` lines up front.
+/// A line can't be saved as is if it is in another currency, whose charged
+/// amount is asked for, if the category it lands in wants a comment it
+/// lacks, or if strict commit wants its category picked.
+pub fn read_batch(
+    model: &Model,
+    (user, strict_commit, account, category): Defaults,
+    text: &str,
+    (limits, locale): (&AmountLimits, Locale),
+) -> Result<Result<Batch, String>, Error> {
+    let count = lines(text).count();
+    if count > MAX_LINES {
+        return Ok(Err(format!(
+            "At most {} expenses fit in one message, this one has {} lines.",
+            MAX_LINES, count
+        )));
+    }
+    let mut batch = Vec::with_capacity(count);
+    for (i, line) in lines(text).enumerate() {
+        let typed = read_typed(model, user.household, &account, line, (limits, locale))?;
+        let draft = typed.and_then(|typed| {
+            if let Some(original) = &typed.original {
+                return Err(format!("{} needs a message of its own", original.currency));
+            }
+            let draft = new_draft(
+                (user, strict_commit),
+                account.clone(),
+                category.clone(),
+                typed,
+            );
+            if draft.category.lacks_comment(draft.comment.as_deref()) {
+                return Err(Error::CommentRequired(draft.category.name).to_string());
+            }
+            if draft.commit_locked(strict_commit) {
+                return Err("strict commit is on and no rule picks the category".to_string());
+            }
+            Ok(draft)
+        });
+        batch.push(BatchLine {
+            number: i + 1,
+            text: line.to_string(),
+            draft,
+        });
+    }
+    Ok(Ok(batch))
+}
+
+/// Key a line of the batch shown in the message at `key` is stored under,
+/// so that a second tap finds it saved.
+fn line_key(key: DraftKey, number: usize) -> String {
+    format!("batch:{}:{}:{}", key.chat_id, key.message_id.0, number)
+}
+
+/// Saves the lines of `batch` in one transaction: all of them, refused
+/// while any has an error, or with `valid_only` those that can be saved.
+/// Returns the ids of the expenses that are new, or why nothing was saved.
+pub fn commit_batch(
+    model: &Model,
+    key: DraftKey,
+    batch: &[BatchLine],
+    valid_only: bool,
+) -> Result<Result<Vec<i64>, String>, Error> {
+    let errors: Vec<String> = batch
+        .iter()
+        .filter(|line| line.draft.is_err())
+        .map(|line| line.number.to_string())
+        .collect();
+    if !valid_only && !errors.is_empty() {
+        let lines = match errors.as_slice() {
+            [number] => format!("Line {} has an error", number),
+            numbers => format!("Lines {} have errors", numbers.join(", ")),
+        };
+        return Ok(Err(format!(
+            "{}, nothing was saved. Commit the valid ones only, or cancel.",
+            lines
+        )));
+    }
+    let drafts: Vec<(usize, &ActiveTransaction)> = batch
+        .iter()
+        .filter_map(|line| Some((line.number, line.draft.as_ref().ok()?)))
+        .collect();
+    let Some((_, first)) = drafts.first() else {
+        return Ok(Err("No line can be saved.".to_string()));
+    };
+    let expenses: Vec<_> = drafts
+        .iter()
+        .map(|(number, draft)| (line_key(key, *number), draft.to_new_expense()))
+        .collect();
+    let saved = model
+        .insert_expenses_once(first.household, &expenses)
+        .and_then(|inserted| {
+            let mut ids = Vec::new();
+            for ((_, draft), inserted) in drafts.iter().zip(inserted) {
+                let Inserted::New(id) = inserted else {
+                    continue;
+                };
+                if let Some(source) = &draft.source {
+                    model.set_expense_source(first.household, id, source)?;
+                }
+                ids.push(id);
+            }
+            Ok(ids)
+        });
+    match saved {
+        Ok(ids) => Ok(Ok(ids)),
+        Err(
+            e @ (Error::CommentRequired(_)
+            | Error::TooLong { .. }
+            | Error::InvalidAmount(_)
+            | Error::NotFound(_)),
+        ) => Ok(Err(format!("{} Nothing was saved.", e))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Answers a message of several lines with the summary of its batch.
+pub async fn handle_batch(
+    bot: &Bot,
+    message: &Message,
+    (model, drafts): (&SharedModel, &SharedDrafts),
+    defaults: Defaults<'_>,
+    text: &str,
+    (limits, locale): (&AmountLimits, Locale),
+) -> HandlerResult {
+    let read = read_batch(&model.lock().unwrap(), defaults, text, (limits, locale))?;
+    let mut batch = match read {
+        Ok(batch) => batch,
+        Err(reason) => {
+            bot.send_message(message.chat.id, escape_md(&reason))
+                .await?;
+            return Ok(());
+        }
+    };
+    for draft in batch.iter_mut().filter_map(|line| line.draft.as_mut().ok()) {
+        draft.source = source_of(message);
+    }
+    let valid = batch.iter().filter(|line| line.draft.is_ok()).count();
+    let summary = bot.send_message(message.chat.id, format::render_batch(&batch));
+    if valid == 0 {
+        summary.await?;
+        return Ok(());
+    }
+    let sent = summary
+        .reply_markup(keyboards::batch_keyboard(valid, batch.len()))
+        .await?;
+    let key = DraftKey {
+        chat_id: sent.chat.id,
+        message_id: sent.id,
+    };
+    drafts.insert_batch(key, batch);
+    Ok(())
+}
+
+/// The commit buttons under a batch.
+pub async fn commit(
+    bot: &Bot,
+    q: &CallbackQuery,
+    (model, drafts, feeds): (&SharedModel, &SharedDrafts, &SharedFeeds),
+    key: DraftKey,
+    valid_only: bool,
+) -> HandlerResult {
+    let _guard = drafts.lock(key).await;
+    let Some(batch) = drafts.batch(key) else {
+        bot.answer_callback_query(q.id.clone())
+            .text("This batch is no longer active.")
+            .await?;
+        return Ok(());
+    };
+    let committed = commit_batch(&model.lock().unwrap(), key, &batch, valid_only)?;
+    let ids = match committed {
+        Ok(ids) => ids,
+        Err(reason) => {
+            bot.answer_callback_query(q.id.clone()).text(reason).await?;
+            return Ok(());
+        }
+    };
+    drafts.remove_batch(key);
+    if let Some(household) = batch
+        .iter()
+        .find_map(|line| line.draft.as_ref().ok())
+        .map(|draft| draft.household)
+    {
+        for id in ids {
+            notify::expense_logged(bot, model, feeds.reporter(), household, id);
+        }
+        feed::changed(bot, model, feeds, (key.chat_id, household))?;
+    }
+    let options = settings::render_options(&model.lock().unwrap(), key.chat_id)?;
+    bot.edit_message_text(
+        key.chat_id,
+        key.message_id,
+        format::render_batch_saved(&batch, options),
+    )
+    .await?;
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+pub async fn cancel(
+    bot: &Bot,
+    q: &CallbackQuery,
+    drafts: &SharedDrafts,
+    key: DraftKey,
+) -> HandlerResult {
+    let _guard = drafts.lock(key).await;
+    drafts.remove_batch(key);
+    bot.edit_message_text(key.chat_id, key.message_id, escape_md("✖️ Cancelled"))
+        .await?;
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::format::RenderOptions;
+    use crate::model::{Role, SourceMessage};
+    use teloxide::types::{ChatId, MessageId};
+
+    fn alex() -> User {
+        User {
+            telegram_id: 1001,
+            telegram_name: "alex".to_string(),
+            display_name: "Alex".to_string(),
+            role: Role::Member,
+            household: 1,
+        }
+    }
+
+    fn model() -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+    }
+
+    fn read(model: &Model, text: &str, strict: bool) -> Result<Batch, String> {
+        let account = model.accounts(1).unwrap().remove(0);
+        let category = model.categories(1).unwrap().remove(0);
+        read_batch(
+            model,
+            (&alex(), strict, account, category),
+            text,
+            (&AmountLimits::default(), Locale::DecimalPoint),
+        )
+        .unwrap()
+    }
+
+    const KEY: DraftKey = DraftKey {
+        chat_id: ChatId(1001),
+        message_id: MessageId(10),
+    };
+
+    fn count(model: &Model) -> usize {
+        let far = chrono::NaiveDate::from_ymd_opt(2100, 1, 1).unwrap();
+        model.count_before(far, chrono_tz::UTC).unwrap()
+    }
+
+    #[test]
+    fn lines_are_read_like_single_messages() {
+        let model = model();
+        let bus = model
+            .categories(1)
+            .unwrap()
+            .into_iter()
+            .find(|c| c.name == "Transportation")
+            .unwrap();
+        model.add_rule(1, "bus", bus.id).unwrap();
+        assert!(!is_batch("12.50 coffee\n\n"));
+        assert!(is_batch("12.50 coffee\n40 groceries"));
+
+        let batch = read(&model, "12.50 coffee\n 3*4.5 lunch \n\nabc\n8 bus", false).unwrap();
+        let numbers: Vec<usize> = batch.iter().map(|l| l.number).collect();
+        assert_eq!(vec![1, 2, 3, 4], numbers);
+        let coffee = batch[0].draft.as_ref().unwrap();
+        assert_eq!(
+            (12.5, Some("coffee"), "Alex Savings"),
+            (
+                coffee.amount,
+                coffee.comment.as_deref(),
+                coffee.account.name.as_str()
+            )
+        );
+        let lunch = batch[1].draft.as_ref().unwrap();
+        assert_eq!(13.5, lunch.amount);
+        assert_eq!(Some("3*4.5"), lunch.amount_calculation.as_deref());
+        // The same reason a single message gets.
+        assert_eq!(
+            Err("'abc' is not a valid amount.".to_string()),
+            batch[2].draft
+        );
+        let bus = batch[3].draft.as_ref().unwrap();
+        assert_eq!(
+            ("Transportation", true),
+            (bus.category.name.as_str(), bus.category_by_rule)
+        );
+
+        let batch = read(&model, "13.50 USD taxi\n5 snack", true).unwrap();
+        assert_eq!(
+            Err("USD needs a message of its own".to_string()),
+            batch[0].draft
+        );
+        assert_eq!(
+            Err("strict commit is on and no rule picks the category".to_string()),
+            batch[1].draft
+        );
+    }
+
+    #[test]
+    fn more_than_twenty_lines_are_refused() {
+        let model = model();
+        let text = vec!["1 x"; MAX_LINES + 1].join("\n");
+        assert_eq!(
+            Err("At most 20 expenses fit in one message, this one has 21 lines.".to_string()),
+            read(&model, &text, false)
+        );
+        assert_eq!(MAX_LINES, read(&model, &text[4..], false).unwrap().len());
+    }
+
+    #[test]
+    fn commit_all_or_valid_only() {
+        let model = model();
+        let batch = read(&model, "12.50 coffee\nabc\n8 bus", false).unwrap();
+        assert_eq!(
+            Err(
+                "Line 2 has an error, nothing was saved. Commit the valid ones only, or cancel."
+                    .to_string()
+            ),
+            commit_batch(&model, KEY, &batch, false).unwrap()
+        );
+        assert_eq!(4, count(&model));
+
+        let ids = commit_batch(&model, KEY, &batch, true).unwrap().unwrap();
+        assert_eq!(2, ids.len());
+        assert_eq!(6, count(&model));
+        // A second tap saves nothing more.
+        assert_eq!(Ok(vec![]), commit_batch(&model, KEY, &batch, true).unwrap());
+        assert_eq!(6, count(&model));
+        assert_eq!(
+            "✅ Saved 2 expenses from Alex Savings\n1\\. 12\\.50 EUR · 🛒 Groceries · coffee\n3\\. 8\\.00 EUR · 🛒 Groceries · bus\nSkipped line 2\\.",
+            format::render_batch_saved(&batch, RenderOptions::FULL)
+        );
+
+        let batch = read(&model, "1 a\n2 b", false).unwrap();
+        let key = DraftKey {
+            message_id: MessageId(11),
+            ..KEY
+        };
+        assert_eq!(
+            2,
+            commit_batch(&model, key, &batch, false)
+                .unwrap()
+                .unwrap()
+                .len()
+        );
+        assert_eq!(8, count(&model));
+    }
+
+    #[test]
+    fn lines_link_to_their_group_message() {
+        let model = model();
+        let mut batch = read(&model, "12.50 coffee\n8 bus", false).unwrap();
+        let source = SourceMessage {
+            chat_id: -1001234567890,
+            message_id: 5,
+            username: None,
+        };
+        for line in &mut batch {
+            line.draft.as_mut().unwrap().source = Some(source.clone());
+        }
+        let ids = commit_batch(&model, KEY, &batch, false).unwrap().unwrap();
+        for id in ids {
+            let expense = model.get_expense_details(1, id).unwrap().unwrap();
+            assert_eq!(Some(&source), expense.source_message.as_ref());
+        }
+    }
+
+    #[test]
+    fn a_refused_line_saves_nothing() {
+        let model = model();
+        let mut batch = read(&model, "12.50 coffee\n8 bus", false).unwrap();
+        // The category of the second line is gone by the time of the tap.
+        if let Ok(draft) = &mut batch[1].draft {
+            draft.category.id = 99;
+        }
+        assert!(commit_batch(&model, KEY, &batch, true).unwrap().is_err());
+        assert_eq!(4, count(&model));
+    }
+
+    #[test]
+    fn renders_summary() {
+        let model = model();
+        let batch = read(&model, "12.50 coffee\nabc", false).unwrap();
+        assert_eq!(
+            "📋 2 lines, 1 ready to save from Alex Savings\n✅ 1\\. 12\\.50 EUR · 🛒 Groceries · coffee\n❌ 2\\. abc — 'abc' is not a valid amount\\.",
+            format::render_batch(&batch)
+        );
+        let masked = RenderOptions {
+            mask_amounts: true,
+            ..RenderOptions::FULL
+        };
+        assert_eq!(
+            "✅ Saved 1 expense from Alex Savings\n1\\. 🔒 · 🛒 Groceries · coffee\nSkipped line 2\\.",
+            format::render_batch_saved(&batch, masked)
+        );
+    }
+}
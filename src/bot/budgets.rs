@@ -0,0 +1,128 @@
+//! `/budget`: monthly budgets of categories and what is left of them.
+
+use super::format::format_amount;
+use super::parse::{self, BudgetArgs};
+use super::resolve;
+use crate::model::{AmountLimits, BudgetRemaining, Error, Locale, Model, Period, User};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+fn amount(b: &BudgetRemaining, amount: f64) -> String {
+    format!("{} {}", format_amount(amount, b.exponent), b.currency)
+}
+
+/// The budgets with this month's spend, one line each.
+fn describe(
+    model: &Model,
+    household: i64,
+    (now, tz): (DateTime<Utc>, Tz),
+) -> Result<String, Error> {
+    let calendar = model.calendar(household)?;
+    let month = Period::ThisMonth.resolve(now, tz, calendar);
+    let budgets = model.budgets_with_remaining(household, month.start, tz)?;
+    if budgets.is_empty() {
+        return Ok("No budgets yet.".to_string());
+    }
+    let mut lines = vec![format!("Budgets of {}:", month.month_name())];
+    lines.extend(budgets.iter().map(|b| {
+        let remaining = b.remaining();
+        format!(
+            "{}: {} of {} spent, {} {}",
+            b.category,
+            format_amount(b.spent, b.exponent),
+            amount(b, b.budget),
+            format_amount(remaining.abs(), b.exponent),
+            if remaining < 0.0 {
+                "over ⚠️"
+            } else {
+                "left"
+            }
+        )
+    }));
+    Ok(lines.join("\n"))
+}
+
+/// `/budget`, in plain text.
+pub fn handle_budget(
+    model: &Model,
+    user: &User,
+    args: &str,
+    (limits, locale): (AmountLimits, Locale),
+    clock: (DateTime<Utc>, Tz),
+) -> Result<String, Error> {
+    let (category, amount, currency) = match parse::parse_budget(args) {
+        Ok(BudgetArgs::Show) => return describe(model, user.household, clock),
+        Ok(BudgetArgs::Set {
+            category,
+            amount,
+            currency,
+        }) => (category, amount, currency),
+        Err(usage) => return Ok(usage),
+    };
+    let category = match resolve::category(model, user.household, &category)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(reason),
+    };
+    let Some(exponent) = model.currency_exponent(&currency)? else {
+        return Ok(format!("Unknown currency '{}'.", currency));
+    };
+    let Some(amount) = amount else {
+        model.set_budget(user.household, category.id, &currency, None)?;
+        return Ok(format!("{} has no {} budget now.", category.name, currency));
+    };
+    let amount = match limits.for_exponent(exponent).parse(&amount, locale) {
+        Ok(parsed) => parsed.amount,
+        Err(e) => return Ok(e.to_string()),
+    };
+    model.set_budget(user.household, category.id, &currency, Some(amount))?;
+    Ok(format!(
+        "The budget of {} is {} {} a month now.",
+        category.name,
+        format_amount(amount, exponent),
+        currency
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let alex = model.get_user(1001).unwrap().unwrap();
+        let december = DateTime::from_timestamp(1_702_000_000, 0).unwrap();
+        let budget = |args| {
+            handle_budget(
+                &model,
+                &alex,
+                args,
+                (AmountLimits::default(), Locale::DecimalPoint),
+                (december, Tz::UTC),
+            )
+            .unwrap()
+        };
+
+        assert_eq!("No budgets yet.", budget(""));
+        assert_eq!(
+            "The budget of Groceries is 130.00 EUR a month now.",
+            budget("groc 130 eur")
+        );
+        assert_eq!(
+            "The budget of Utilities is 80.00 USD a month now.",
+            budget("Utilities 80 USD")
+        );
+        assert_eq!(
+            "Budgets of December 2023:\nGroceries: 50.75 of 130.00 EUR spent, 79.25 left\nUtilities: 100.00 of 80.00 USD spent, 20.00 over ⚠️",
+            budget("")
+        );
+        assert_eq!(
+            "Utilities has no USD budget now.",
+            budget("Utilities none usd")
+        );
+        assert_eq!("Unknown currency 'XYZ'.", budget("Groceries 5 xyz"));
+        assert!(budget("Other 5 EUR").starts_with("No category matches 'Other'."));
+        assert_eq!(parse::BUDGET_USAGE, budget("130 EUR"));
+    }
+}
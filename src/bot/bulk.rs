@@ -0,0 +1,272 @@
+//! Admin commands changing many expenses at once. They reply with a preview
+//! and only act once confirmed on its keyboard.
+
+use super::auth::{self, Access};
+use super::callback::{self, CallbackData};
+use super::draft::DraftKey;
+use super::markdown::escape_md;
+use super::{keyboards, parse, resolve, Bot, HandlerResult, SharedModel};
+use crate::model::{Category, DateRange, Error, MergeReport, Model, Role, User};
+use chrono::Utc;
+use chrono_tz::Tz;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, InlineKeyboardMarkup};
+
+fn label(category: &Category) -> String {
+    match &category.icon {
+        Some(icon) => format!("{} {}", icon, category.name),
+        None => category.name.clone(),
+    }
+}
+
+fn expenses(count: usize) -> String {
+    match count {
+        1 => "1 expense".to_string(),
+        n => format!("{} expenses", n),
+    }
+}
+
+/// The budgets and favorites a merge moved along with the expenses, if any.
+fn along(report: MergeReport) -> String {
+    let mut moved = Vec::new();
+    match report.budgets {
+        0 => {}
+        1 => moved.push("1 budget".to_string()),
+        n => moved.push(format!("{} budgets", n)),
+    }
+    match report.favorites {
+        0 => {}
+        1 => moved.push("1 favorite".to_string()),
+        n => moved.push(format!("{} favorites", n)),
+    }
+    if moved.is_empty() {
+        String::new()
+    } else {
+        format!(", {}", moved.join(" and "))
+    }
+}
+
+fn within(range: Option<DateRange>) -> String {
+    match range {
+        Some(range) => format!(" from {} to {}", range.start, range.last_day()),
+        None => String::new(),
+    }
+}
+
+/// The buttons under a `/recategorize` prompt.
+fn recategorize_keyboard(
+    model: &Model,
+    (from, to, range): (i64, i64, Option<DateRange>),
+) -> Result<InlineKeyboardMarkup, Error> {
+    let data = |dry_run| {
+        let data = CallbackData::Recategorize {
+            from,
+            to,
+            range,
+            dry_run,
+        };
+        callback::button_data(model, &data, Utc::now())
+    };
+    Ok(keyboards::bulk_confirm_keyboard(data(false)?, data(true)?))
+}
+
+/// `/recategorize`: previews how many expenses of the household would move.
+pub async fn handle_recategorize(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    household: i64,
+    tz: Tz,
+    args: &str,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let preview = match parse::parse_recategorize(args) {
+        Err(reason) => Err(reason),
+        Ok(args) => {
+            let model = model.lock().unwrap();
+            let from = resolve::category(&model, household, &args.from)?;
+            let to = resolve::category(&model, household, &args.to)?;
+            match from.and_then(|from| to.map(|to| (from, to))) {
+                Err(reason) => Err(reason),
+                Ok((from, to)) if from.id == to.id => {
+                    Err("Both categories are the same.".to_string())
+                }
+                Ok((from, to)) => {
+                    let count = model.count_in_category(household, from.id, args.range, tz)?;
+                    Ok((from, to, args.range, count))
+                }
+            }
+        }
+    };
+
+    match preview {
+        Err(reason) => {
+            bot.send_message(chat_id, escape_md(&reason)).await?;
+        }
+        Ok((from, _, range, 0)) => {
+            bot.send_message(
+                chat_id,
+                escape_md(&format!(
+                    "There are no expenses in {}{}.",
+                    label(&from),
+                    within(range)
+                )),
+            )
+            .await?;
+        }
+        Ok((from, to, range, count)) => {
+            let keyboard = recategorize_keyboard(&model.lock().unwrap(), (from.id, to.id, range))?;
+            bot.send_message(
+                chat_id,
+                escape_md(&format!(
+                    "Move {}{} from {} to {}?",
+                    expenses(count),
+                    within(range),
+                    label(&from),
+                    label(&to)
+                )),
+            )
+            .reply_markup(keyboard)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// `/category merge`: the reply to send.
+pub fn merge_categories(model: &SharedModel, user: &User, args: &str) -> Result<String, Error> {
+    let args = match parse::parse_category_merge(args) {
+        Ok(args) => args,
+        Err(reason) => return Ok(reason),
+    };
+    let model = model.lock().unwrap();
+    let source = match resolve::category(&model, user.household, &args.source)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(reason),
+    };
+    let target = match resolve::category(&model, user.household, &args.target)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(reason),
+    };
+    let ids = (source.id, target.id);
+    match model.merge_categories(user.household, user.telegram_id, ids, args.dry_run) {
+        Ok(report) if args.dry_run => Ok(format!(
+            "Dry run, nothing changed: merging {} into {} would move {}{} and archive {}.",
+            label(&source),
+            label(&target),
+            expenses(report.expenses),
+            along(report),
+            source.name
+        )),
+        Ok(report) => Ok(format!(
+            "Merged {} into {}: moved {}{}. {} is archived.",
+            label(&source),
+            label(&target),
+            expenses(report.expenses),
+            along(report),
+            source.name
+        )),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(e) => Err(e),
+    }
+}
+
+/// The confirmation and preview buttons of `/recategorize`. A preview
+/// keeps the buttons, so the move can follow it.
+pub async fn confirm_recategorize(
+    bot: &Bot,
+    q: &CallbackQuery,
+    model: &SharedModel,
+    key: DraftKey,
+    (from, to, range, dry_run): (i64, i64, Option<DateRange>, bool),
+    tz: Tz,
+) -> HandlerResult {
+    let user = match auth::requires(model, &q.from, key.chat_id, Role::Admin)? {
+        Access::Granted(user) => user,
+        Access::Denied(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+            return Ok(());
+        }
+    };
+
+    let (text, keyboard) = {
+        let model = model.lock().unwrap();
+        let household = user.household;
+        let categories = (
+            model.get_category(household, from)?,
+            model.get_category(household, to)?,
+        );
+        match categories {
+            (Some(from), Some(to)) => {
+                let ids = (from.id, to.id);
+                let moved = model.bulk_recategorize(
+                    household,
+                    user.telegram_id,
+                    ids,
+                    range,
+                    tz,
+                    dry_run,
+                )?;
+                let report = format!(
+                    "{}{} from {} to {}",
+                    expenses(moved),
+                    within(range),
+                    label(&from),
+                    label(&to)
+                );
+                if dry_run {
+                    let keyboard = recategorize_keyboard(&model, (ids.0, ids.1, range))?;
+                    (
+                        format!("Dry run, nothing changed: this would move {}.", report),
+                        Some(keyboard),
+                    )
+                } else {
+                    (format!("Moved {}.", report), None)
+                }
+            }
+            _ => (callback::NO_OPTION.to_string(), None),
+        }
+    };
+    let edit = bot.edit_message_text(key.chat_id, key.message_id, escape_md(&text));
+    match keyboard {
+        Some(keyboard) => edit.reply_markup(keyboard).await?,
+        None => edit.await?,
+    };
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn merge_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let admin = model.get_user(1001).unwrap().unwrap();
+        let model = Arc::new(Mutex::new(model));
+
+        assert_eq!(
+            "A category can't be merged into itself.",
+            merge_categories(&model, &admin, "merge gro into groceries").unwrap()
+        );
+        assert_eq!(
+            "Dry run, nothing changed: merging 🎬 Entertainment into 💡 Utilities would move 1 expense and archive Entertainment.",
+            merge_categories(&model, &admin, "merge dry Entertainment into Utilities").unwrap()
+        );
+        assert_eq!(0, model.lock().unwrap().audit_entries().unwrap().len());
+        assert_eq!(
+            "Merged 🎬 Entertainment into 💡 Utilities: moved 1 expense. Entertainment is archived.",
+            merge_categories(&model, &admin, r#"merge "Entertainment" into "Utilities""#).unwrap()
+        );
+        // Archived categories don't resolve any more.
+        assert!(
+            merge_categories(&model, &admin, "merge Utilities into Entertainment")
+                .unwrap()
+                .starts_with("No category matches 'Entertainment'.")
+        );
+    }
+}
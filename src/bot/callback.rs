@@ -0,0 +1,573 @@
+//! Typed callback data of inline keyboard buttons. Telegram limits callback
+//! data to 64 bytes, so the encoding is a short `name[:argument]` string.
+//! Data that doesn't fit is stored in the database and the button carries
+//! `cb:<shortId>` instead, see [`button_data`].
+
+use crate::model::{DateRange, Error, Model, Setting};
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// The most callback data Telegram accepts, in bytes.
+pub const MAX_DATA_LEN: usize = 64;
+
+/// Prefix of the short ids of stored payloads.
+const STORED: &str = "cb:";
+
+/// The answer to a button whose stored payload is gone.
+pub const EXPIRED: &str = "Expired, rerun the command.";
+
+/// The answer to a button naming something that isn't there, whether
+/// deleted since or never offered.
+pub const NO_OPTION: &str = "This option no longer exists.";
+
+/// The buttons of the last questions of `/setup`, which categories to start
+/// with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupAction {
+    /// Picks the preset at the index in [`crate::model::PRESETS`].
+    Preset(usize),
+    /// Leaves the category at the index of the preset out, or back in.
+    Toggle(usize),
+    /// Back to the presets.
+    Presets,
+    /// Creates everything, with the categories chosen.
+    Create,
+    /// Creates everything but categories.
+    Skip,
+}
+
+/// Draft fields edited by typing a new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Amount,
+    Timestamp,
+    Comment,
+    /// Shared as a location message, never typed.
+    Location,
+}
+
+impl Field {
+    fn as_str(self) -> &'static str {
+        match self {
+            Field::Amount => "amount",
+            Field::Timestamp => "timestamp",
+            Field::Comment => "comment",
+            Field::Location => "location",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Field> {
+        match s {
+            "amount" => Some(Field::Amount),
+            "timestamp" => Some(Field::Timestamp),
+            "comment" => Some(Field::Comment),
+            "location" => Some(Field::Location),
+            _ => None,
+        }
+    }
+}
+
+/// Pages of a draft's keyboard: the fields most expenses need, and More…
+/// with the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Page {
+    #[default]
+    Main,
+    More,
+}
+
+impl Page {
+    fn as_str(self) -> &'static str {
+        match self {
+            Page::Main => "main",
+            Page::More => "more",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Page> {
+        match s {
+            "main" => Some(Page::Main),
+            "more" => Some(Page::More),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallbackData {
+    Edit(Field),
+    PickCategory,
+    PickAccount,
+    SetCategory(i64),
+    SetAccount(i64),
+    /// Sets the comment to one of those the draft suggests.
+    UseComment(usize),
+    /// Asks for the comment to be typed rather than picked.
+    TypeComment,
+    /// Fills in one of the comment templates the draft offers.
+    UseTemplate(usize),
+    /// Moves the draft amount by the step, in minor units of its currency.
+    Nudge(i64),
+    /// Choose who shares the expense half and half.
+    PickSplit,
+    /// Split the draft with a user, or not at all for `None`.
+    SetSplit(Option<i64>),
+    /// Return from a picker to the draft keyboard.
+    Back,
+    /// Turns the draft keyboard to the page.
+    ShowPage(Page),
+    Commit,
+    /// Commits a draft whose amount was flagged as unusual.
+    CommitAnyway,
+    Cancel,
+    /// Delete a just committed expense.
+    Undo(i64),
+    /// Show a stored expense with its action buttons.
+    ViewExpense(i64),
+    /// Turn a stored expense into a draft, committing it updates the expense.
+    EditExpense(i64),
+    DeleteExpense(i64),
+    /// Confirms moving the expenses of a category to another one, or for a
+    /// dry run shows what the move would do.
+    Recategorize {
+        from: i64,
+        to: i64,
+        range: Option<DateRange>,
+        dry_run: bool,
+    },
+    /// Drops a confirmation prompt without doing anything.
+    Dismiss,
+    /// Changes a setting of the user pressing the button.
+    ChangeSetting(Setting),
+    /// Commits a favorite as a new expense.
+    UseFavorite(i64),
+    /// Confirms moving the expenses from before the date to an archive.
+    Archive(NaiveDate),
+    Setup(SetupAction),
+    /// Brings an account in line with the balance of a reconciliation.
+    RecordAdjustment(i64),
+    /// Saves the expenses of a batch: all of them, refused while any line
+    /// has an error, or the valid ones only.
+    CommitBatch {
+        valid_only: bool,
+    },
+    CancelBatch,
+    /// Opens a draft of the defaults that asks for the amount, under the
+    /// welcome of a new user.
+    FirstExpense,
+    /// Runs the command or alias a hint suggested, by its index in
+    /// [`super::intent::candidates`].
+    RunCommand(usize),
+}
+
+/// Dates are encoded as compact `YYYYMMDD`.
+const DATE: &str = "%Y%m%d";
+
+/// An index as [`CallbackData::encode`] writes it: digits only, without
+/// leading zeros, sign or spaces that `str::parse` would let through.
+fn parse_index(s: &str) -> Option<usize> {
+    let canonical =
+        !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) && (s == "0" || !s.starts_with('0'));
+    canonical.then(|| s.parse().ok()).flatten()
+}
+
+/// A database id: a canonical index that is positive and fits an `i64`.
+/// Rows never get 0 or negative ids, so data carrying one was forged.
+fn parse_id(s: &str) -> Option<i64> {
+    parse_index(s)
+        .and_then(|id| i64::try_from(id).ok())
+        .filter(|id| *id > 0)
+}
+
+fn encode_range(range: Option<DateRange>) -> String {
+    match range {
+        Some(range) => format!(":{}:{}", range.start.format(DATE), range.end.format(DATE)),
+        None => String::new(),
+    }
+}
+
+fn parse_recategorize(arg: &str, dry_run: bool) -> Option<CallbackData> {
+    let parts: Vec<&str> = arg.split(':').collect();
+    let (ids, range) = match parts.as_slice() {
+        [from, to] => ((from, to), None),
+        [from, to, start, end] => {
+            let start = NaiveDate::parse_from_str(start, DATE).ok()?;
+            let end = NaiveDate::parse_from_str(end, DATE).ok()?;
+            ((from, to), Some(DateRange { start, end }))
+        }
+        _ => return None,
+    };
+    Some(CallbackData::Recategorize {
+        from: parse_id(ids.0)?,
+        to: parse_id(ids.1)?,
+        range,
+        dry_run,
+    })
+}
+
+impl CallbackData {
+    pub fn encode(&self) -> String {
+        match self {
+            CallbackData::Edit(field) => format!("edit:{}", field.as_str()),
+            CallbackData::PickCategory => "pick:category".to_string(),
+            CallbackData::PickAccount => "pick:account".to_string(),
+            CallbackData::SetCategory(id) => format!("set_category:{}", id),
+            CallbackData::SetAccount(id) => format!("set_account:{}", id),
+            CallbackData::UseComment(i) => format!("comment:{}", i),
+            CallbackData::TypeComment => "comment:own".to_string(),
+            CallbackData::UseTemplate(i) => format!("template:{}", i),
+            CallbackData::Nudge(step) => format!("nudge:{}", step),
+            CallbackData::PickSplit => "pick:split".to_string(),
+            CallbackData::SetSplit(Some(id)) => format!("split:{}", id),
+            CallbackData::SetSplit(None) => "split:none".to_string(),
+            CallbackData::Back => "back".to_string(),
+            CallbackData::ShowPage(page) => format!("page:{}", page.as_str()),
+            CallbackData::Commit => "commit".to_string(),
+            CallbackData::CommitAnyway => "commit:anyway".to_string(),
+            CallbackData::Cancel => "cancel".to_string(),
+            CallbackData::Undo(id) => format!("undo:{}", id),
+            CallbackData::ViewExpense(id) => format!("view_expense:{}", id),
+            CallbackData::EditExpense(id) => format!("edit_expense:{}", id),
+            CallbackData::DeleteExpense(id) => format!("delete_expense:{}", id),
+            CallbackData::Recategorize {
+                from,
+                to,
+                range,
+                dry_run,
+            } => format!(
+                "{}:{}:{}{}",
+                if *dry_run { "dryrecat" } else { "recat" },
+                from,
+                to,
+                encode_range(*range)
+            ),
+            CallbackData::Dismiss => "dismiss".to_string(),
+            CallbackData::ChangeSetting(setting) => {
+                format!("setting:{}:{}", setting.key(), setting.value())
+            }
+            CallbackData::UseFavorite(id) => format!("fav:{}", id),
+            CallbackData::Archive(cutoff) => format!("archive:{}", cutoff.format(DATE)),
+            CallbackData::Setup(action) => match action {
+                SetupAction::Preset(i) => format!("setup:preset:{}", i),
+                SetupAction::Toggle(i) => format!("setup:toggle:{}", i),
+                SetupAction::Presets => "setup:presets".to_string(),
+                SetupAction::Create => "setup:add".to_string(),
+                SetupAction::Skip => "setup:skip".to_string(),
+            },
+            CallbackData::RecordAdjustment(id) => format!("adjust:{}", id),
+            CallbackData::CommitBatch { valid_only: false } => "batch:all".to_string(),
+            CallbackData::CommitBatch { valid_only: true } => "batch:valid".to_string(),
+            CallbackData::CancelBatch => "batch:cancel".to_string(),
+            CallbackData::FirstExpense => "first_expense".to_string(),
+            CallbackData::RunCommand(i) => format!("run:{}", i),
+        }
+    }
+
+    pub fn parse(data: &str) -> Option<CallbackData> {
+        let (name, arg) = match data.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (data, None),
+        };
+        match (name, arg) {
+            ("edit", Some(field)) => Field::parse(field).map(CallbackData::Edit),
+            ("pick", Some("category")) => Some(CallbackData::PickCategory),
+            ("pick", Some("account")) => Some(CallbackData::PickAccount),
+            ("set_category", Some(id)) => parse_id(id).map(CallbackData::SetCategory),
+            ("set_account", Some(id)) => parse_id(id).map(CallbackData::SetAccount),
+            ("comment", Some("own")) => Some(CallbackData::TypeComment),
+            ("comment", Some(i)) => parse_index(i).map(CallbackData::UseComment),
+            ("template", Some(i)) => parse_index(i).map(CallbackData::UseTemplate),
+            ("nudge", Some(step)) => step
+                .parse()
+                .ok()
+                .filter(|step| *step != 0)
+                .map(CallbackData::Nudge),
+            ("pick", Some("split")) => Some(CallbackData::PickSplit),
+            ("split", Some("none")) => Some(CallbackData::SetSplit(None)),
+            ("split", Some(id)) => parse_id(id).map(|id| CallbackData::SetSplit(Some(id))),
+            ("back", None) => Some(CallbackData::Back),
+            ("page", Some(page)) => Page::parse(page).map(CallbackData::ShowPage),
+            ("commit", None) => Some(CallbackData::Commit),
+            ("commit", Some("anyway")) => Some(CallbackData::CommitAnyway),
+            ("cancel", None) => Some(CallbackData::Cancel),
+            ("undo", Some(id)) => parse_id(id).map(CallbackData::Undo),
+            ("view_expense", Some(id)) => parse_id(id).map(CallbackData::ViewExpense),
+            ("edit_expense", Some(id)) => parse_id(id).map(CallbackData::EditExpense),
+            ("delete_expense", Some(id)) => parse_id(id).map(CallbackData::DeleteExpense),
+            ("recat", Some(arg)) => parse_recategorize(arg, false),
+            ("dryrecat", Some(arg)) => parse_recategorize(arg, true),
+            ("dismiss", None) => Some(CallbackData::Dismiss),
+            ("setting", Some(arg)) => {
+                let (key, value) = arg.split_once(':')?;
+                Setting::decode(key, value).map(CallbackData::ChangeSetting)
+            }
+            ("fav", Some(id)) => parse_id(id).map(CallbackData::UseFavorite),
+            ("archive", Some(date)) => NaiveDate::parse_from_str(date, DATE)
+                .ok()
+                .map(CallbackData::Archive),
+            ("setup", Some(arg)) => {
+                let action = match arg.split_once(':') {
+                    Some(("preset", i)) => SetupAction::Preset(parse_index(i)?),
+                    Some(("toggle", i)) => SetupAction::Toggle(parse_index(i)?),
+                    Some(_) => return None,
+                    None => match arg {
+                        "presets" => SetupAction::Presets,
+                        "add" => SetupAction::Create,
+                        "skip" => SetupAction::Skip,
+                        _ => return None,
+                    },
+                };
+                Some(CallbackData::Setup(action))
+            }
+            ("adjust", Some(id)) => parse_id(id).map(CallbackData::RecordAdjustment),
+            ("batch", Some("all")) => Some(CallbackData::CommitBatch { valid_only: false }),
+            ("batch", Some("valid")) => Some(CallbackData::CommitBatch { valid_only: true }),
+            ("batch", Some("cancel")) => Some(CallbackData::CancelBatch),
+            ("first_expense", None) => Some(CallbackData::FirstExpense),
+            ("run", Some(i)) => parse_index(i).map(CallbackData::RunCommand),
+            _ => None,
+        }
+    }
+}
+
+/// Callback data carrying `data`: its encoding when it fits, otherwise a
+/// short id of the encoding stored in the database.
+pub fn button_data(
+    model: &Model,
+    data: &CallbackData,
+    now: DateTime<Utc>,
+) -> Result<String, Error> {
+    fit(data.encode(), |payload| {
+        model.store_callback_payload(payload, now)
+    })
+}
+
+fn fit(
+    encoded: String,
+    store: impl FnOnce(&str) -> Result<String, Error>,
+) -> Result<String, Error> {
+    if encoded.len() <= MAX_DATA_LEN {
+        Ok(encoded)
+    } else {
+        Ok(format!("{}{}", STORED, store(&encoded)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolved {
+    Data(CallbackData),
+    /// A stored payload that was swept or is past its time.
+    Expired,
+    Unknown,
+}
+
+/// The callback data of a pressed button, looking up stored payloads.
+pub fn resolve(model: &Model, data: &str, now: DateTime<Utc>) -> Result<Resolved, Error> {
+    let payload = match data.strip_prefix(STORED) {
+        Some(id) => match model.callback_payload(id, now)? {
+            Some(payload) => payload,
+            None => return Ok(Resolved::Expired),
+        },
+        None => data.to_string(),
+    };
+    Ok(CallbackData::parse(&payload).map_or(Resolved::Unknown, Resolved::Data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn roundtrip() {
+        let all = [
+            CallbackData::Edit(Field::Amount),
+            CallbackData::Edit(Field::Timestamp),
+            CallbackData::Edit(Field::Comment),
+            CallbackData::Edit(Field::Location),
+            CallbackData::PickCategory,
+            CallbackData::PickAccount,
+            CallbackData::SetCategory(3),
+            CallbackData::SetAccount(12),
+            CallbackData::UseComment(4),
+            CallbackData::TypeComment,
+            CallbackData::UseTemplate(1),
+            CallbackData::Nudge(-100),
+            CallbackData::Nudge(10),
+            CallbackData::PickSplit,
+            CallbackData::SetSplit(Some(1002)),
+            CallbackData::SetSplit(None),
+            CallbackData::Back,
+            CallbackData::ShowPage(Page::Main),
+            CallbackData::ShowPage(Page::More),
+            CallbackData::Commit,
+            CallbackData::CommitAnyway,
+            CallbackData::Cancel,
+            CallbackData::Undo(42),
+            CallbackData::ViewExpense(7),
+            CallbackData::EditExpense(7),
+            CallbackData::DeleteExpense(7),
+            CallbackData::Recategorize {
+                from: 3,
+                to: 4,
+                range: None,
+                dry_run: false,
+            },
+            CallbackData::Recategorize {
+                from: 3,
+                to: 4,
+                range: DateRange::parse("2023-01..2023-12"),
+                dry_run: true,
+            },
+            CallbackData::Dismiss,
+            CallbackData::ChangeSetting(Setting::MonthToDate(false)),
+            CallbackData::ChangeSetting(Setting::NumberFormat(None)),
+            CallbackData::UseFavorite(5),
+            CallbackData::Archive(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap()),
+            CallbackData::Setup(SetupAction::Preset(2)),
+            CallbackData::Setup(SetupAction::Toggle(11)),
+            CallbackData::Setup(SetupAction::Presets),
+            CallbackData::Setup(SetupAction::Create),
+            CallbackData::Setup(SetupAction::Skip),
+            CallbackData::RecordAdjustment(9),
+            CallbackData::CommitBatch { valid_only: false },
+            CallbackData::CommitBatch { valid_only: true },
+            CallbackData::CancelBatch,
+            CallbackData::FirstExpense,
+            CallbackData::RunCommand(3),
+        ];
+        for data in all {
+            assert_eq!(Some(data), CallbackData::parse(&data.encode()));
+            assert!(data.encode().len() <= MAX_DATA_LEN);
+        }
+        assert_eq!(
+            "recat:3:4:20230101:20240101",
+            CallbackData::Recategorize {
+                from: 3,
+                to: 4,
+                range: DateRange::parse("2023-01..2023-12"),
+                dry_run: false,
+            }
+            .encode()
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(None, CallbackData::parse(""));
+        assert_eq!(None, CallbackData::parse("edit:currency"));
+        assert_eq!(None, CallbackData::parse("set_category:abc"));
+        assert_eq!(None, CallbackData::parse("split:alex"));
+        assert_eq!(None, CallbackData::parse("comment:-1"));
+        assert_eq!(None, CallbackData::parse("commit:1"));
+        assert_eq!(None, CallbackData::parse("nudge:0"));
+        assert_eq!(None, CallbackData::parse("nudge:0.1"));
+        assert_eq!(None, CallbackData::parse("recat:3"));
+        assert_eq!(None, CallbackData::parse("recat:3:4:2023:2024"));
+        assert_eq!(None, CallbackData::parse("setting:mtd"));
+        assert_eq!(None, CallbackData::parse("setting:num:roman"));
+        assert_eq!(None, CallbackData::parse("setup:toggle"));
+        assert_eq!(None, CallbackData::parse("setup:preset:x"));
+    }
+
+    #[test]
+    fn ids_are_positive_and_canonical() {
+        for id in [
+            "0",
+            "-1",
+            "+5",
+            "05",
+            " 5",
+            "5 ",
+            "9223372036854775808",
+            "1e3",
+        ] {
+            for name in [
+                "set_category",
+                "set_account",
+                "split",
+                "undo",
+                "fav",
+                "adjust",
+            ] {
+                let data = format!("{}:{}", name, id);
+                assert_eq!(None, CallbackData::parse(&data), "{}", data);
+            }
+            assert_eq!(None, CallbackData::parse(&format!("recat:{}:4", id)));
+            assert_eq!(None, CallbackData::parse(&format!("recat:3:{}", id)));
+        }
+        assert_eq!(
+            Some(CallbackData::Undo(i64::MAX)),
+            CallbackData::parse("undo:9223372036854775807")
+        );
+        // Indexes start at 0, but take no sign or padding either.
+        assert_eq!(
+            Some(CallbackData::UseComment(0)),
+            CallbackData::parse("comment:0")
+        );
+        for index in ["+1", "00", "01", "-0", "18446744073709551616"] {
+            assert_eq!(None, CallbackData::parse(&format!("comment:{}", index)));
+            assert_eq!(None, CallbackData::parse(&format!("run:{}", index)));
+            assert_eq!(
+                None,
+                CallbackData::parse(&format!("setup:toggle:{}", index))
+            );
+        }
+        // Steps may be negative, but not zero.
+        assert_eq!(
+            Some(CallbackData::Nudge(-100)),
+            CallbackData::parse("nudge:-100")
+        );
+    }
+
+    #[test]
+    fn long_data_is_stored() {
+        let mut stored = Vec::new();
+        let mut fit = |encoded: String| {
+            fit(encoded, |payload| {
+                stored.push(payload.to_string());
+                Ok("abc".to_string())
+            })
+            .unwrap()
+        };
+        let longest = "x".repeat(MAX_DATA_LEN);
+        assert_eq!(longest, fit(longest.clone()));
+        assert_eq!("cb:abc", fit(format!("{}y", longest)));
+        assert_eq!(vec![format!("{}y", longest)], stored);
+
+        let model = Model::new(true);
+        let now = Utc::now();
+        assert_eq!(
+            "undo:42",
+            button_data(&model, &CallbackData::Undo(42), now).unwrap()
+        );
+        assert_eq!(
+            Resolved::Data(CallbackData::Undo(42)),
+            resolve(&model, "undo:42", now).unwrap()
+        );
+        assert_eq!(Resolved::Unknown, resolve(&model, "undo", now).unwrap());
+    }
+
+    #[test]
+    fn stored_payloads_expire() {
+        let model = Model::new(true);
+        let now = Utc::now();
+        let recategorize = CallbackData::Recategorize {
+            from: 3,
+            to: 4,
+            range: None,
+            dry_run: false,
+        };
+        let fresh = model
+            .store_callback_payload(&recategorize.encode(), now)
+            .unwrap();
+        let stale = model
+            .store_callback_payload(&recategorize.encode(), now - TimeDelta::hours(48))
+            .unwrap();
+
+        let resolve = |id: &str| resolve(&model, &format!("{}{}", STORED, id), now).unwrap();
+        assert_eq!(Resolved::Data(recategorize), resolve(&fresh));
+        assert_eq!(Resolved::Expired, resolve(&stale));
+        assert_eq!(Resolved::Expired, resolve("gone"));
+        assert_eq!("Expired, rerun the command.", EXPIRED);
+    }
+}
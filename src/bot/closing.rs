@@ -0,0 +1,130 @@
+//! `/close` and `/reopen`: locking months the admin reconciled, so that
+//! nobody edits or back-dates expenses into them. The model refuses the
+//! changes, see [`super::read_only`] for how the refusal is answered.
+
+use crate::model::{parse_month, Error, Model, User};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+pub const CLOSE_USAGE: &str = "Usage: /close YYYY-MM";
+pub const REOPEN_USAGE: &str = "Usage: /reopen YYYY-MM";
+
+fn name(month: NaiveDate) -> String {
+    month.format("%B %Y").to_string()
+}
+
+/// `/close [YYYY-MM]`: the reply to send. Without a month, the closed
+/// ones.
+pub fn handle_close(
+    model: &Model,
+    user: &User,
+    args: &str,
+    clock: (DateTime<Utc>, Tz),
+) -> Result<String, Error> {
+    let args = args.trim();
+    if args.is_empty() {
+        let months = model.closed_months(user.household)?;
+        if months.is_empty() {
+            return Ok("No months are closed. Close a past one: /close YYYY-MM.".to_string());
+        }
+        let names: Vec<String> = months.into_iter().map(name).collect();
+        return Ok(format!("Closed months: {}.", names.join(", ")));
+    }
+    let Some(month) = parse_month(args) else {
+        return Ok(CLOSE_USAGE.to_string());
+    };
+    match model.close_month(user.household, user.telegram_id, month, clock) {
+        Ok(()) => Ok(format!(
+            "🔒 {} is closed: its expenses can't be added, edited or deleted until /reopen {}.",
+            name(month),
+            args
+        )),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(e) => Err(e),
+    }
+}
+
+/// `/reopen YYYY-MM`: the reply to send.
+pub fn handle_reopen(model: &Model, user: &User, args: &str) -> Result<String, Error> {
+    let Some(month) = parse_month(args.trim()) else {
+        return Ok(REOPEN_USAGE.to_string());
+    };
+    match model.reopen_month(user.household, user.telegram_id, month) {
+        Ok(()) => Ok(format!(
+            "🔓 {} is open again, its expenses can be changed.",
+            name(month)
+        )),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense, Role};
+
+    fn alex() -> User {
+        User {
+            telegram_id: 1001,
+            telegram_name: "alex".to_string(),
+            display_name: "Alex".to_string(),
+            role: Role::Admin,
+            household: 1,
+        }
+    }
+
+    #[test]
+    fn close_and_reopen() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = DateTime::parse_from_rfc3339("2024-01-10T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let close = |args| handle_close(&model, &alex(), args, (now, Tz::UTC)).unwrap();
+        let reopen = |args| handle_reopen(&model, &alex(), args).unwrap();
+
+        assert_eq!(
+            "No months are closed. Close a past one: /close YYYY-MM.",
+            close("")
+        );
+        assert_eq!(CLOSE_USAGE, close("november"));
+        assert_eq!(
+            "January 2024 isn't over yet, only past months can be closed.",
+            close("2024-01")
+        );
+        assert_eq!(
+            "🔒 December 2023 is closed: its expenses can't be added, edited or deleted until /reopen 2023-12.",
+            close("2023-12")
+        );
+        assert_eq!("December 2023 is closed already.", close("2023-12"));
+        close("2023-11");
+        assert_eq!("Closed months: December 2023, November 2023.", close(""));
+
+        let back_dated = NewExpense {
+            amount: 5.0,
+            account_id: 1,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: now - chrono::TimeDelta::days(30),
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        assert_eq!(
+            "December 2023 is closed — ask the admin to reopen.",
+            model
+                .insert_expense_once(1, "add:1", &back_dated)
+                .unwrap_err()
+                .to_string()
+        );
+
+        assert_eq!(REOPEN_USAGE, reopen(""));
+        assert_eq!(
+            "🔓 December 2023 is open again, its expenses can be changed.",
+            reopen("2023-12")
+        );
+        assert_eq!("December 2023 isn't closed.", reopen("2023-12"));
+        assert!(model.insert_expense_once(1, "add:1", &back_dated).is_ok());
+    }
+}
@@ -0,0 +1,1735 @@
+use super::auth::{self, Access};
+use super::feed::{self, SharedFeeds};
+use super::long::send_long;
+use super::markdown::escape_md;
+use super::parse::SettingsArgs;
+use super::{
+    accounts, aliases, archive, budgets, bulk, closing, expense, favorites, format, last, notify,
+    onboarding, parse, read_only, reconcile, resolve, review, rules, settings, setup, sources,
+    subscriptions, templates, Bot, HandlerResult, SharedModel,
+};
+use crate::config::Config;
+use crate::export::{self, Format};
+use crate::model::{
+    parse_month, Calendar, DateRange, Error, Period, ReportDate, Role, User, DEFAULT_HOUSEHOLD,
+};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
+use onboarding::Welcomed;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+use teloxide::utils::command::BotCommands;
+
+#[derive(BotCommands, Clone, Debug, PartialEq)]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
+pub enum Command {
+    #[command(description = "show this text.")]
+    Help,
+    #[command(description = "show your id and role.")]
+    Start,
+    #[command(
+        description = "set the role of a user: /role <user> <admin|member|viewer>.",
+        parse_with = "split"
+    )]
+    Role { user: String, role: String },
+    #[command(
+        description = "let a user in and welcome them: /allow <user> [admin|member|viewer], a member by default."
+    )]
+    Allow(String),
+    #[command(
+        description = "log and save an expense at once: /add <amount> <category> [\"comment\"] [acc:<account>]."
+    )]
+    Add(String),
+    #[command(
+        description = "log a favorite with one tap: /fav, /fav add <amount> <category> [\"label\"] [acc:<account>], /fav del <label>."
+    )]
+    Fav(String),
+    #[command(
+        description = "keep a pinned message with this month's totals in the chat: /feed enable|disable."
+    )]
+    Feed(String),
+    #[command(
+        description = "spending per category: /report [week|lastweek|month|lastmonth|ytd|YYYY-MM-DD..YYYY-MM-DD], as an image to share: /report img [period], where this month is heading: /report forecast, spend by weekday: /report heatmap [category], or the year in review: /report year [YYYY]. Leave categories out: /report [period] exclude <category>,<category>, or count by when expenses were logged: /report by logged [period]."
+    )]
+    Report(String),
+    #[command(
+        description = "light-hearted records of a month, like the longest streak of days with spending: /fun [lastmonth|YYYY-MM]."
+    )]
+    Fun(String),
+    #[command(description = "export a year of expenses to a file: /export [csv|xlsx] [YYYY].")]
+    Export(String),
+    #[command(description = "list recent expenses that could use a category or comment.")]
+    Review,
+    #[command(
+        description = "list the latest expenses: /last [<count>] [category <category>] [account \"<account>\"] [sort date|amount] [mine|all], your own in private and everyone's in groups by default."
+    )]
+    Last(String),
+    #[command(
+        description = "show and change your settings, turn the month to date after saving on or off: /settings mtd on|off, leave categories out of your reports: /settings report-exclude <category>,<category>|none, start weeks and months elsewhere: /settings week mon|sun, /settings month-start <day>, shorten commit confirmations: /settings confirm full|compact|silent, say which currency amounts typed in the chat are in: /settings currency <currency>|none, hold scheduled messages back at night: /settings quiet <from>-<to>|off, /settings timezone <zone>|none, or hide amounts in a group: /settings privacy on|off."
+    )]
+    Settings(String),
+    #[command(description = "show account balances grouped by currency.")]
+    Balance,
+    #[command(
+        description = "set today's exchange rate: /rate <currency> <value in base currency>, or see how old the latest rates are: /rate status."
+    )]
+    Rate(String),
+    #[command(
+        description = "change a currency: /currency set <currency> exponent <decimal places>."
+    )]
+    Currency(String),
+    #[command(
+        description = "move expenses to another category: /recategorize from <category> to <category> [YYYY-MM..YYYY-MM]."
+    )]
+    Recategorize(String),
+    #[command(
+        description = "change categories: /category merge \"<source>\" into \"<target>\", /category parent <category> under <parent>|none, /category require-comment <category> on|off, /category bind <category> <account>|unbind <category>, or see its spend: /category stats <category>."
+    )]
+    Category(String),
+    #[command(description = "check the database for damage and orphaned rows.")]
+    Integrity,
+    #[command(
+        description = "vacuum the database and report its size before and after, or delete old audit log entries and button payloads now: /maintenance prune."
+    )]
+    Maintenance(String),
+    #[command(
+        description = "pause all changes before a restore or migration, reports keep working: /readonly on|off."
+    )]
+    Readonly(String),
+    #[command(
+        description = "show the household of this chat: /household, bind the chat to yours: /household bind <name>, or start another: /household new <name> <user>."
+    )]
+    Household(String),
+    #[command(
+        description = "finish the move to minor units: /migrate verify-amounts, then /migrate finalize."
+    )]
+    Migrate(String),
+    #[command(
+        description = "shorthands for commands: /alias add <name> \"<command> [arguments]\", /alias del <name>, /alias list."
+    )]
+    Alias(String),
+    #[command(
+        description = "comments with blanks to fill in from a draft's Comment button: /template comment add <name> \"fuel {liters}L at {station}\", /template comment del <name>, /template comment list."
+    )]
+    Template(String),
+    #[command(
+        description = "show who owes whom for split expenses: /settle, or record that you settled up: /settle clear."
+    )]
+    Settle(String),
+    #[command(
+        description = "get a private message when others log expenses: /notify on|off, only from an amount: /notify over <amount> <currency>, or in a category: /notify category <category>."
+    )]
+    Notify(String),
+    #[command(
+        description = "get a private report of your own expenses: /subscribe weekly|monthly, /subscribe off, or see when the next comes: /subscribe status."
+    )]
+    Subscribe(String),
+    #[command(
+        description = "move old expenses to a separate file, reports still include them: /archive before <YYYY-MM-DD>."
+    )]
+    Archive(String),
+    #[command(
+        description = "create the first account and categories step by step: /setup [cancel]."
+    )]
+    Setup(String),
+    #[command(
+        description = "pick the category of drafts from their comment: /rule add \"<pattern>\" -> <category>, /rule del <id>, /rule test <text>, /rule list."
+    )]
+    Rule(String),
+    #[command(
+        description = "make forwards from a channel or bot drafts: /source add [chat:<id>|user:<id>] <category> -> <account> in reply to a forward, /source template <id> \"<pattern>\"|none, /source del <id>, /source list."
+    )]
+    Source(String),
+    #[command(
+        description = "compare an account with the bank: /reconcile \"<account>\" <balance>, or see past ones: /reconcile history."
+    )]
+    Reconcile(String),
+    #[command(
+        description = "lock a reconciled month against changes: /close YYYY-MM, or see the closed ones: /close."
+    )]
+    Close(String),
+    #[command(description = "allow changes in a closed month again: /reopen YYYY-MM.")]
+    Reopen(String),
+    #[command(
+        description = "delete an account created by mistake: /account delete \"<account>\", or move its expenses and favorites to another first: /account delete \"<account>\" into \"<other account>\". Limit what is spent from it a day: /account limit \"<account>\" <amount> <currency>|none."
+    )]
+    Account(String),
+    #[command(
+        description = "show what is left of the monthly budgets: /budget, or set one: /budget <category> <amount>|none <currency>."
+    )]
+    Budget(String),
+}
+
+impl Command {
+    /// The minimal role needed to run the command, `None` for commands
+    /// open to everyone (including users that are not allowed yet).
+    pub fn required_role(&self) -> Option<Role> {
+        match self {
+            Command::Help | Command::Start => None,
+            // Only looks, unlike the other subcommands.
+            Command::Category(args) if args.trim_start().starts_with("stats") => Some(Role::Viewer),
+            Command::Settings(args)
+                if args.trim_start().starts_with("mtd")
+                    || args.trim_start().starts_with("round")
+                    || args.trim_start().starts_with("report-exclude") =>
+            {
+                Some(Role::Viewer)
+            }
+            // A setting of the whole chat rather than of the user.
+            Command::Settings(args) if !args.trim().is_empty() => Some(Role::Admin),
+            Command::Budget(args) if !args.trim().is_empty() => Some(Role::Admin),
+            Command::Rate(args) if args.trim() == "status" => Some(Role::Viewer),
+            Command::Report(_)
+            | Command::Fun(_)
+            | Command::Last(_)
+            | Command::Export(_)
+            | Command::Balance
+            | Command::Settings(_)
+            | Command::Budget(_)
+            | Command::Alias(_)
+            | Command::Notify(_)
+            | Command::Subscribe(_) => Some(Role::Viewer),
+            Command::Add(_)
+            | Command::Fav(_)
+            | Command::Feed(_)
+            | Command::Review
+            | Command::Settle(_)
+            | Command::Reconcile(_)
+            | Command::Template(_) => Some(Role::Member),
+            Command::Role { .. }
+            | Command::Allow(_)
+            | Command::Rate(_)
+            | Command::Currency(_)
+            | Command::Recategorize(_)
+            | Command::Category(_)
+            | Command::Integrity
+            | Command::Maintenance(_)
+            | Command::Readonly(_)
+            | Command::Household(_)
+            | Command::Migrate(_)
+            | Command::Archive(_)
+            | Command::Setup(_)
+            | Command::Rule(_)
+            | Command::Source(_)
+            | Command::Close(_)
+            | Command::Reopen(_)
+            | Command::Account(_) => Some(Role::Admin),
+        }
+    }
+
+    /// Commands on what all households share, left to the admins of the
+    /// default household.
+    pub fn is_shared(&self) -> bool {
+        if let Command::Rate(args) = self {
+            // Rates are for everyone to see.
+            return args.trim() != "status";
+        }
+        matches!(
+            self,
+            Command::Rate(_)
+                | Command::Currency(_)
+                | Command::Integrity
+                | Command::Maintenance(_)
+                | Command::Readonly(_)
+                | Command::Migrate(_)
+                | Command::Archive(_)
+        )
+    }
+}
+
+pub(super) const SHARED_ONLY: &str = "⛔ Only the admins of the default household can do this.";
+
+/// Runs a command. One refused by read-only mode is answered as such.
+pub async fn handle_command(
+    bot: Bot,
+    message: Message,
+    command: Command,
+    model: SharedModel,
+    config: Arc<Config>,
+    feeds: SharedFeeds,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let result = run_command(bot.clone(), message, command, model, config, feeds).await;
+    read_only::answer_message(&bot, chat_id, result).await
+}
+
+async fn run_command(
+    bot: Bot,
+    message: Message,
+    command: Command,
+    model: SharedModel,
+    config: Arc<Config>,
+    feeds: SharedFeeds,
+) -> HandlerResult {
+    let Some(from) = message.from.as_ref() else {
+        return Ok(());
+    };
+
+    let user = match command.required_role() {
+        Some(required) => match auth::requires(&model, from, message.chat.id, required)? {
+            Access::Granted(user) => Some(user),
+            Access::Denied(text) => {
+                bot.send_message(message.chat.id, escape_md(&text)).await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if command.is_shared()
+        && user
+            .as_ref()
+            .is_some_and(|u| u.household != DEFAULT_HOUSEHOLD)
+    {
+        bot.send_message(message.chat.id, escape_md(SHARED_ONLY))
+            .await?;
+        return Ok(());
+    }
+
+    let chat_id = message.chat.id;
+    let max_messages = config.max_messages;
+    match command {
+        Command::Help => {
+            let text = escape_md(&Command::descriptions().to_string());
+            send_long(&bot, chat_id, text, max_messages).await?;
+        }
+        Command::Start => {
+            let text = start_text(&model, from)?;
+            // The welcome of a user allowed before they started the bot.
+            let allowed = model.lock().unwrap().get_user(from.id.0 as i64)?;
+            let welcomed = match allowed {
+                Some(user) if message.chat.is_private() => {
+                    onboarding::welcome(&model, &user, |w| onboarding::send(&bot, chat_id, w))
+                        .await?
+                }
+                _ => Welcomed::Already,
+            };
+            if welcomed != Welcomed::Sent {
+                bot.send_message(chat_id, escape_md(&text)).await?;
+            }
+            // A new household's admin goes straight to the setup.
+            if let Access::Granted(user) = auth::requires(&model, from, chat_id, Role::Admin)? {
+                let reply = setup::on_start(&model.lock().unwrap(), chat_id.0, &user)?;
+                if let Some(reply) = reply {
+                    reply.send(&bot, chat_id).await?;
+                }
+            }
+        }
+        Command::Role { user: name, role } => {
+            let household = user.expect("checked by required_role").household;
+            let text = set_role(&model, household, &name, &role)?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Allow(args) => {
+            let household = user.expect("checked by required_role").household;
+            onboarding::handle_allow(&bot, &model, household, chat_id, &args).await?;
+        }
+        Command::Add(args) => {
+            let user = user.expect("checked by required_role");
+            expense::handle_add(&bot, &message, (&model, &feeds), &user, &config, &args).await?;
+        }
+        Command::Fav(args) => {
+            let user = user.expect("checked by required_role");
+            favorites::handle_fav(&bot, &message, &model, &user, &config, &args).await?;
+        }
+        Command::Feed(args) => {
+            let user = user.expect("checked by required_role");
+            feed::handle_feed(&bot, &message, (&model, &feeds), &user, &args).await?;
+        }
+        Command::Report(args) if args.split_whitespace().next() == Some("img") => {
+            #[cfg(feature = "report-image")]
+            {
+                let household = user.expect("checked by required_role").household;
+                let period = args.trim_start().trim_start_matches("img");
+                match report_image(&model, household, period, Utc::now(), config.timezone)? {
+                    Ok(png) => {
+                        bot.send_photo(chat_id, InputFile::memory(png).file_name("report.png"))
+                            .await?;
+                    }
+                    Err(reason) => {
+                        bot.send_message(chat_id, escape_md(&reason)).await?;
+                    }
+                }
+            }
+            #[cfg(not(feature = "report-image"))]
+            bot.send_message(
+                chat_id,
+                escape_md("Report images are not available in this build."),
+            )
+            .await?;
+        }
+        Command::Report(args) if args.split_whitespace().next() == Some("heatmap") => {
+            let household = user.expect("checked by required_role").household;
+            let text = heatmap_report(&model, household, &args, Utc::now(), config.timezone)?;
+            bot.send_message(chat_id, text).await?;
+        }
+        Command::Report(period) if period.trim_start().starts_with("year") => {
+            let household = user.expect("checked by required_role").household;
+            let texts = year_report(&model, household, &period, Utc::now(), config.timezone)?;
+            for text in texts {
+                send_long(&bot, chat_id, text, max_messages).await?;
+            }
+        }
+        Command::Report(args) => {
+            let user = user.expect("checked by required_role");
+            let household = user.household;
+            let (period, exclude, date) = match parse::parse_report(&args) {
+                Ok(args) => args,
+                Err(usage) => {
+                    bot.send_message(chat_id, escape_md(&usage)).await?;
+                    return Ok(());
+                }
+            };
+            // Named categories replace the ones the user leaves out.
+            let exclude = {
+                let model = model.lock().unwrap();
+                match exclude {
+                    None => Ok(model.report_exclusions(user.telegram_id)?),
+                    Some(names) => resolve::categories(&model, household, &names)?
+                        .map(|categories| categories.iter().map(|c| c.id).collect()),
+                }
+            };
+            let exclude: Vec<i64> = match exclude {
+                Ok(exclude) => exclude,
+                Err(reason) => {
+                    bot.send_message(chat_id, escape_md(&reason)).await?;
+                    return Ok(());
+                }
+            };
+            let by_overrun = period.trim().eq_ignore_ascii_case("forecast");
+            let parsed = if by_overrun {
+                Some(Period::ThisMonth)
+            } else {
+                Period::parse(&period)
+            };
+            let now = Utc::now();
+            let text = match parsed {
+                Some(period) => {
+                    let (summary, budgets, calendar, rounding) = {
+                        let model = model.lock().unwrap();
+                        let calendar = model.calendar(household)?;
+                        let range = period.resolve(now, config.timezone, calendar);
+                        let settings = model.get_settings(user.telegram_id)?;
+                        let mut summary = match date {
+                            ReportDate::Spent => model.summarize_excluding(
+                                household,
+                                range,
+                                &exclude,
+                                config.timezone,
+                            )?,
+                            ReportDate::Logged => model.summarize_logged(
+                                household,
+                                range,
+                                &exclude,
+                                config.timezone,
+                            )?,
+                        };
+                        summary.completeness = Some(model.completeness_stats(
+                            household,
+                            range,
+                            &config.review_rules(),
+                            config.timezone,
+                        )?);
+                        summary.closed = model.is_closed(household, range)?;
+                        let budgets = if period == Period::ThisMonth {
+                            model.budgets_with_remaining(household, range.start, config.timezone)?
+                        } else {
+                            Vec::new()
+                        };
+                        let rounding = format::RoundingMode::of(&settings);
+                        (summary, budgets, calendar, rounding)
+                    };
+                    // Forecasts are of spend, whenever it was logged.
+                    if period == Period::ThisMonth && date == ReportDate::Spent {
+                        let context = (config.timezone, calendar);
+                        let budgets = (budgets.as_slice(), by_overrun);
+                        format::render_forecast(&summary, budgets, now, context, rounding)
+                    } else {
+                        format::render_report(&summary, rounding)
+                    }
+                }
+                None => escape_md(&unknown_period(&period)),
+            };
+            send_long(&bot, chat_id, text, max_messages).await?;
+        }
+        Command::Fun(args) => {
+            let household = user.expect("checked by required_role").household;
+            let text = fun_report(&model, household, &args, Utc::now(), config.timezone)?;
+            bot.send_message(chat_id, text).await?;
+        }
+        Command::Last(args) => {
+            let user = user.expect("checked by required_role");
+            let text =
+                last::handle_last(&model, &user, chat_id, &args, (Utc::now(), config.timezone))?;
+            bot.send_message(chat_id, text).await?;
+        }
+        Command::Review => {
+            let household = user.expect("checked by required_role").household;
+            review::handle_review(&bot, &message, &model, household, &config).await?;
+        }
+        Command::Settings(args) => {
+            let user = user.expect("checked by required_role");
+            match parse::parse_settings(&args) {
+                Ok(SettingsArgs::Show) => {
+                    settings::handle_settings(&bot, &message, &model, user.telegram_id).await?;
+                }
+                Ok(SettingsArgs::MonthToDate(on)) => {
+                    let text =
+                        settings::month_to_date(&model.lock().unwrap(), user.telegram_id, on)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::Round(on)) => {
+                    let text = settings::round(&model.lock().unwrap(), user.telegram_id, on)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::ReportExclude(names)) => {
+                    let text = settings::report_exclude(&model.lock().unwrap(), &user, &names)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::Privacy(on)) => {
+                    let text = settings::privacy(&model.lock().unwrap(), &user, chat_id, on)?;
+                    feed::changed(&bot, &model, &feeds, (chat_id, user.household))?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::Confirm(mode)) => {
+                    let text = settings::confirm(&model.lock().unwrap(), &user, chat_id, mode)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::Currency(currency)) => {
+                    let currency = currency.as_deref();
+                    let text =
+                        settings::currency(&model.lock().unwrap(), &user, chat_id, currency)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::Quiet(hours)) => {
+                    let text = settings::quiet(&model.lock().unwrap(), &user, chat_id, hours)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::TimeZone(zone)) => {
+                    let zone = zone.as_deref();
+                    let text = settings::time_zone(&model.lock().unwrap(), &user, chat_id, zone)?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::WeekStart(week_start)) => {
+                    let text = settings::calendar(&model.lock().unwrap(), &user, |c| Calendar {
+                        week_start,
+                        ..c
+                    })?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Ok(SettingsArgs::MonthStart(month_start)) => {
+                    let text = settings::calendar(&model.lock().unwrap(), &user, |c| Calendar {
+                        month_start,
+                        ..c
+                    })?;
+                    feed::changed(&bot, &model, &feeds, (chat_id, user.household))?;
+                    bot.send_message(chat_id, escape_md(&text)).await?;
+                }
+                Err(usage) => {
+                    bot.send_message(chat_id, escape_md(&usage)).await?;
+                }
+            }
+        }
+        Command::Balance => {
+            let household = user.expect("checked by required_role").household;
+            let (balances, daily) = {
+                let model = model.lock().unwrap();
+                (
+                    model.balances(household, &config.base_currency)?,
+                    model.daily_usages(household, (Utc::now(), config.timezone))?,
+                )
+            };
+            let today = Utc::now().with_timezone(&config.timezone).date_naive();
+            send_long(
+                &bot,
+                chat_id,
+                format::render_balance(&balances, &daily, (today, config.rate_stale_days)),
+                max_messages,
+            )
+            .await?;
+        }
+        Command::Rate(args) => {
+            let text = match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["status"] => {
+                    let today = Utc::now().with_timezone(&config.timezone).date_naive();
+                    let stale = (today, config.rate_stale_days);
+                    rate_status(&model, &config.base_currency, stale)?
+                }
+                [currency, rate] => set_rate(&model, currency, rate)?,
+                _ => RATE_USAGE.to_string(),
+            };
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Recategorize(args) => {
+            let household = user.expect("checked by required_role").household;
+            bulk::handle_recategorize(&bot, &message, &model, household, config.timezone, &args)
+                .await?;
+        }
+        Command::Category(args) => {
+            let user = user.expect("checked by required_role");
+            let text = if args.trim_start().starts_with("stats") {
+                category_stats(&model, user.household, &args, Utc::now(), config.timezone)?
+            } else if args.trim_start().starts_with("require-comment") {
+                escape_md(&require_comment(&model, user.household, &args)?)
+            } else if args.trim_start().starts_with("parent") {
+                escape_md(&category_parent(&model, user.household, &args)?)
+            } else if args.trim_start().starts_with("bind")
+                || args.trim_start().starts_with("unbind")
+            {
+                escape_md(&category_account(&model, user.household, &args)?)
+            } else {
+                escape_md(&bulk::merge_categories(&model, &user, &args)?)
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        Command::Export(args) => {
+            let household = user.expect("checked by required_role").household;
+            match export_file(&model, household, &args, Utc::now(), config.timezone)? {
+                Ok((name, bytes)) => {
+                    bot.send_document(chat_id, InputFile::memory(bytes).file_name(name))
+                        .await?;
+                }
+                Err(reason) => {
+                    bot.send_message(chat_id, escape_md(&reason)).await?;
+                }
+            }
+        }
+        Command::Integrity => {
+            let report = model.lock().unwrap().check_integrity()?;
+            send_long(
+                &bot,
+                chat_id,
+                format::render_integrity(&report),
+                max_messages,
+            )
+            .await?;
+        }
+        Command::Maintenance(args) => {
+            let user_id = from.id.0 as i64;
+            let shared = model.clone();
+            match args.trim() {
+                "" => {
+                    let report = tokio::task::spawn_blocking(move || {
+                        shared.lock().unwrap().maintenance(user_id)
+                    })
+                    .await??;
+                    bot.send_message(chat_id, format::render_maintenance(&report))
+                        .await?;
+                }
+                "prune" => {
+                    let retention = config.retention();
+                    let report = tokio::task::spawn_blocking(move || {
+                        let model = shared.lock().unwrap();
+                        model.prune_retention(&retention, user_id, Utc::now())
+                    })
+                    .await??;
+                    bot.send_message(chat_id, format::render_prune(&report))
+                        .await?;
+                }
+                _ => {
+                    bot.send_message(chat_id, escape_md(MAINTENANCE_USAGE))
+                        .await?;
+                }
+            }
+        }
+        Command::Readonly(args) => {
+            let user = user.expect("checked by required_role");
+            let text = read_only::handle_readonly(&model, &user, &args)?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Currency(args) => {
+            bot.send_message(chat_id, escape_md(&currency(&model, &args)?))
+                .await?;
+        }
+        Command::Household(args) => {
+            let user = user.expect("checked by required_role");
+            let text = household(&model, &user, chat_id.0, &args)?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Settle(args) => {
+            let user = user.expect("checked by required_role");
+            let text = settle(&model, &user, &args, Utc::now())?;
+            send_long(&bot, chat_id, text, max_messages).await?;
+        }
+        Command::Rule(args) => {
+            let household = user.expect("checked by required_role").household;
+            let text = rules::handle_rule(&model, household, &args)?;
+            send_long(&bot, chat_id, escape_md(&text), max_messages).await?;
+        }
+        Command::Source(args) => {
+            let household = user.expect("checked by required_role").household;
+            let forwarded = message.reply_to_message().and_then(|m| m.forward_origin());
+            let text = sources::handle_source(&model, household, &args, forwarded)?;
+            send_long(&bot, chat_id, escape_md(&text), max_messages).await?;
+        }
+        Command::Account(args) => {
+            let user = user.expect("checked by required_role");
+            let text = if args.trim_start().starts_with("limit") {
+                let locale = settings::locale(&model, from)?;
+                accounts::handle_limit(&model, &user, &args, (config.amount_limits(), locale))?
+            } else {
+                accounts::handle_account(&model, &user, &args)?
+            };
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Close(args) => {
+            let user = user.expect("checked by required_role");
+            let clock = (Utc::now(), config.timezone);
+            let text = closing::handle_close(&model.lock().unwrap(), &user, &args, clock)?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Reopen(args) => {
+            let user = user.expect("checked by required_role");
+            let text = closing::handle_reopen(&model.lock().unwrap(), &user, &args)?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Reconcile(args) => {
+            let user = user.expect("checked by required_role");
+            let locale = settings::locale(&model, from)?;
+            let (text, keyboard) = reconcile::handle_reconcile(
+                &model.lock().unwrap(),
+                &user,
+                &args,
+                locale,
+                (Utc::now(), config.timezone),
+            )?;
+            let request = bot.send_message(chat_id, escape_md(&text));
+            match keyboard {
+                Some(keyboard) => request.reply_markup(keyboard).await?,
+                None => request.await?,
+            };
+        }
+        Command::Budget(args) => {
+            let user = user.expect("checked by required_role");
+            let locale = settings::locale(&model, from)?;
+            let text = budgets::handle_budget(
+                &model.lock().unwrap(),
+                &user,
+                &args,
+                (config.amount_limits(), locale),
+                (Utc::now(), config.timezone),
+            )?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Alias(args) => {
+            let text = aliases::handle_alias(&model, from.id.0 as i64, &args)?;
+            send_long(&bot, chat_id, escape_md(&text), max_messages).await?;
+        }
+        Command::Template(args) => {
+            let text = templates::handle_template(&model, from.id.0 as i64, &args)?;
+            send_long(&bot, chat_id, escape_md(&text), max_messages).await?;
+        }
+        Command::Archive(args) => {
+            let at = (config.db_path.as_path(), config.timezone);
+            archive::handle_archive(&bot, &message, &model, at, &args).await?;
+        }
+        Command::Setup(args) => {
+            let user = user.expect("checked by required_role");
+            let reply = setup::handle_setup(&model.lock().unwrap(), chat_id.0, &user, &args)?;
+            reply.send(&bot, chat_id).await?;
+        }
+        Command::Notify(args) => {
+            let user = user.expect("checked by required_role");
+            let locale = settings::locale(&model, from)?;
+            let text =
+                notify::handle_notify(&model, &user, &args, (config.amount_limits(), locale))?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Subscribe(args) => {
+            let user = user.expect("checked by required_role");
+            let text =
+                subscriptions::handle_subscribe(&model, &user, &args, Utc::now(), config.timezone)?;
+            bot.send_message(chat_id, escape_md(&text)).await?;
+        }
+        Command::Migrate(args) => {
+            let user = user.expect("checked by required_role");
+            send_long(&bot, chat_id, migrate(&model, &user, &args)?, max_messages).await?;
+        }
+    }
+    Ok(())
+}
+
+fn start_text(model: &SharedModel, from: &teloxide::types::User) -> Result<String, Error> {
+    let telegram_id = from.id.0 as i64;
+    let model = model.lock().unwrap();
+    model.touch_user(
+        telegram_id,
+        (
+            &from.username.clone().unwrap_or_default(),
+            &from.full_name(),
+        ),
+        from.language_code.as_deref(),
+    )?;
+
+    Ok(match model.get_user(telegram_id)? {
+        Some(user) => format!(
+            "Hi {}! You are a {} of the household {}. Send an amount to log an expense, /help lists the commands.",
+            user.display_name,
+            user.role,
+            model.household_name(user.household)?
+        ),
+        None => format!(
+            "Hi! You are not allowed to use this bot yet. Ask the admin to allow your id {}.",
+            telegram_id
+        ),
+    })
+}
+
+/// `/report year [YYYY]`, the current local year by default.
+fn year_report(
+    model: &SharedModel,
+    household: i64,
+    args: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<Vec<String>, Error> {
+    let year = match parse_year(args.trim().trim_start_matches("year"), now, tz) {
+        Ok(year) => year,
+        Err(reason) => return Ok(vec![escape_md(&reason)]),
+    };
+    let summary = model.lock().unwrap().year_summary(household, year, tz)?;
+    Ok(format::render_year(&summary, tz))
+}
+
+/// How many weeks back `/report heatmap` averages over.
+const HEATMAP_WEEKS: u32 = 12;
+
+/// `/report heatmap [category]`, of every category without one.
+fn heatmap_report(
+    model: &SharedModel,
+    household: i64,
+    args: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    let name = args.trim().trim_start_matches("heatmap").trim();
+    let category = match name {
+        "" => None,
+        name => match resolve::category(&model, household, name)? {
+            Ok(category) => Some(category),
+            Err(reason) => return Ok(escape_md(&reason)),
+        },
+    };
+    let averages = model.weekday_averages(
+        household,
+        category.as_ref().map(|c| c.id),
+        HEATMAP_WEEKS,
+        (now, tz),
+    )?;
+    Ok(format::render_heatmap(
+        &averages,
+        HEATMAP_WEEKS,
+        category.as_ref(),
+    ))
+}
+
+/// `/fun [lastmonth|YYYY-MM]`, the current local month by default.
+fn fun_report(
+    model: &SharedModel,
+    household: i64,
+    args: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<String, Error> {
+    // Records are of calendar months, whenever the household's start.
+    let calendar = Calendar::default();
+    let month = match args.trim() {
+        "" => Period::ThisMonth.resolve(now, tz, calendar).start,
+        "lastmonth" => Period::LastMonth.resolve(now, tz, calendar).start,
+        text => match parse_month(text) {
+            Some(month) => month,
+            None => {
+                return Ok(escape_md(&format!(
+                    "Unknown month '{}'. Use lastmonth or YYYY-MM.",
+                    text
+                )))
+            }
+        },
+    };
+    let stats = model.lock().unwrap().fun_stats(household, month, tz)?;
+    Ok(format::render_fun(&stats, tz))
+}
+
+/// A typed year, the current local year if empty.
+fn parse_year(text: &str, now: DateTime<Utc>, tz: Tz) -> Result<i32, String> {
+    match text.trim() {
+        "" => Ok(now.with_timezone(&tz).year()),
+        year => match year.parse::<i32>() {
+            Ok(year) if (1970..=9999).contains(&year) => Ok(year),
+            _ => Err(format!("Invalid year '{}'.", year)),
+        },
+    }
+}
+
+fn unknown_period(period: &str) -> String {
+    format!(
+        "Unknown period '{}'. Use week, lastweek, month, lastmonth, ytd, forecast or YYYY-MM-DD..YYYY-MM-DD.",
+        period
+    )
+}
+
+/// `/report img [period]`: the report of a period, this month if none is
+/// given, as a PNG, or why there is none.
+#[cfg(feature = "report-image")]
+fn report_image(
+    model: &SharedModel,
+    household: i64,
+    period: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<Result<Vec<u8>, String>, Error> {
+    let period = match period.trim() {
+        "" => Period::ThisMonth,
+        text => match Period::parse(text) {
+            Some(period) => period,
+            None => return Ok(Err(unknown_period(text))),
+        },
+    };
+    let summary = {
+        let model = model.lock().unwrap();
+        let range = period.resolve(now, tz, model.calendar(household)?);
+        model.summarize(household, range, tz)?
+    };
+    Ok(Ok(export::image::render_report(&summary)))
+}
+
+/// `/export [format] [YYYY]`: the expenses of a local year as a file name
+/// and its contents, or why there is none.
+fn export_file(
+    model: &SharedModel,
+    household: i64,
+    args: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<Result<(String, Vec<u8>), String>, export::Error> {
+    let args = args.trim();
+    let (format, year) = match args.split_once(' ') {
+        Some((format, year)) => (format, year),
+        None if args.starts_with(|c: char| c.is_ascii_alphabetic()) => (args, ""),
+        None => ("", args),
+    };
+    let format = match format {
+        "" => Format::ALL[0],
+        name => match Format::parse(name) {
+            Some(format) => format,
+            None => {
+                let names: Vec<&str> = Format::ALL.iter().map(|f| f.extension()).collect();
+                return Ok(Err(format!(
+                    "Unknown format '{}'. Use {}.",
+                    name,
+                    names.join(" or ")
+                )));
+            }
+        },
+    };
+    let year = match parse_year(year, now, tz) {
+        Ok(year) => year,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    let range = DateRange::inclusive(
+        NaiveDate::from_ymd_opt(year, 1, 1).expect("checked by parse_year"),
+        NaiveDate::from_ymd_opt(year, 12, 31).expect("checked by parse_year"),
+    );
+    let bytes = export::write(
+        &model.lock().unwrap(),
+        household,
+        range,
+        tz,
+        format,
+        Vec::new(),
+    )?;
+    Ok(Ok((
+        format!("expenses-{}.{}", year, format.extension()),
+        bytes,
+    )))
+}
+
+fn set_role(model: &SharedModel, household: i64, user: &str, role: &str) -> Result<String, Error> {
+    let Some(role) = Role::parse(role) else {
+        return Ok(format!(
+            "Unknown role '{}'. Use admin, member or viewer.",
+            role
+        ));
+    };
+
+    let model = model.lock().unwrap();
+    let telegram_id = match model.resolve_user_id(user) {
+        Ok(id) => id,
+        Err(Error::NotFound(_)) => {
+            return Ok(format!("Unknown user {}. Use their numeric id.", user))
+        }
+        Err(Error::Refused(reason)) => return Ok(reason),
+        Err(e) => return Err(e),
+    };
+    match model.set_role(household, telegram_id, role) {
+        Ok(()) => Ok(format!("User {} is now a {}.", user, role)),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(e) => Err(e),
+    }
+}
+
+const RATE_USAGE: &str = "Usage: /rate <currency> <value in base currency> or /rate status";
+
+/// `/rate status`: each currency's latest rate and how old it is on
+/// `today`, flagged past `stale_after` days.
+fn rate_status(
+    model: &SharedModel,
+    base_currency: &str,
+    (today, stale_after): (NaiveDate, u32),
+) -> Result<String, Error> {
+    let rates = model.lock().unwrap().latest_rates(base_currency)?;
+    if rates.is_empty() {
+        return Ok(format!(
+            "There are no currencies besides {}.",
+            base_currency
+        ));
+    }
+    let lines: Vec<String> = rates
+        .iter()
+        .map(|(currency, rate)| match rate {
+            None => format!("{}: no rate", currency),
+            Some(rate) => {
+                let age = match rate.age(today) {
+                    0 => "today".to_string(),
+                    1 => "1 day old".to_string(),
+                    days => format!("{} days old", days),
+                };
+                let stale = match rate.is_stale(today, stale_after) {
+                    true => ", may be stale",
+                    false => "",
+                };
+                format!(
+                    "{}: {} from {}, {}{}",
+                    currency, rate.rate, rate.date, age, stale
+                )
+            }
+        })
+        .collect();
+    Ok(format!(
+        "Latest rates in {}:\n{}",
+        base_currency,
+        lines.join("\n")
+    ))
+}
+
+fn set_rate(model: &SharedModel, currency: &str, rate: &str) -> Result<String, Error> {
+    let rate = match rate.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => rate,
+        _ => return Ok(format!("Invalid rate '{}'.", rate)),
+    };
+
+    let today = Utc::now().date_naive();
+    match model.lock().unwrap().set_rate(currency, today, rate) {
+        Ok(()) => Ok(format!(
+            "Rate of {} on {} set to {}.",
+            currency, today, rate
+        )),
+        Err(Error::NotFound(what)) => Ok(format!("Unknown {}.", what)),
+        Err(e) => Err(e),
+    }
+}
+
+fn require_comment(model: &SharedModel, household: i64, args: &str) -> Result<String, Error> {
+    let (name, required) = match parse::parse_require_comment(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let category = match resolve::category(&model, household, &name)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(reason),
+    };
+    model.set_require_comment(household, category.id, required)?;
+    Ok(if required {
+        format!("{} expenses now need a comment.", category.name)
+    } else {
+        format!("{} expenses no longer need a comment.", category.name)
+    })
+}
+
+/// `/category stats`, in MarkdownV2.
+fn category_stats(
+    model: &SharedModel,
+    household: i64,
+    args: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<String, Error> {
+    let name = match parse::parse_category_stats(args) {
+        Ok(name) => name,
+        Err(usage) => return Ok(escape_md(&usage)),
+    };
+    let model = model.lock().unwrap();
+    let category = match resolve::category(&model, household, &name)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(escape_md(&reason)),
+    };
+    let detail = model.category_detail_stats(household, category.id, now, tz)?;
+    Ok(format::render_category_detail(&detail, tz))
+}
+
+fn category_parent(model: &SharedModel, household: i64, args: &str) -> Result<String, Error> {
+    let (name, parent) = match parse::parse_category_parent(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let category = match resolve::category(&model, household, &name)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(reason),
+    };
+    let parent = match parent {
+        Some(parent) => match resolve::category(&model, household, &parent)? {
+            Ok(parent) => Some(parent),
+            Err(reason) => return Ok(reason),
+        },
+        None => None,
+    };
+    match model.set_category_parent(household, category.id, parent.as_ref().map(|p| p.id)) {
+        Ok(()) => Ok(match parent {
+            Some(parent) => format!("{} is now under {}.", category.name, parent.name),
+            None => format!("{} is a top-level category now.", category.name),
+        }),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(e) => Err(e),
+    }
+}
+
+/// `/category bind|unbind`: the reply to send.
+fn category_account(model: &SharedModel, household: i64, args: &str) -> Result<String, Error> {
+    let (name, account) = match parse::parse_category_bind(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let category = match resolve::category(&model, household, &name)? {
+        Ok(category) => category,
+        Err(reason) => return Ok(reason),
+    };
+    let account = match account {
+        Some(account) => match resolve::account(&model, household, &account)? {
+            Ok(account) => Some(account),
+            Err(reason) => return Ok(reason),
+        },
+        None => None,
+    };
+    model.set_category_account(household, category.id, account.as_ref().map(|a| a.id))?;
+    Ok(match account {
+        Some(account) => format!(
+            "Picking {} now moves drafts to {}, unless their account was picked already.",
+            category.name, account.name
+        ),
+        None => format!("{} is no longer bound to an account.", category.name),
+    })
+}
+
+const HOUSEHOLD_USAGE: &str =
+    "Usage: /household, /household bind <name> or /household new <name> <user>";
+
+/// `/household`: the reply to send. Admins bind chats to their own household
+/// only, and new households are started from the default one.
+fn household(model: &SharedModel, user: &User, chat_id: i64, args: &str) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    let args = args.trim();
+    let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    match action {
+        "" => {
+            let name = model.household_name(user.household)?;
+            Ok(match model.chat_household(chat_id)? {
+                Some(_) => format!("This chat belongs to the household {}.", name),
+                None => format!(
+                    "This chat is not bound to a household, everyone sees their own here. Yours is {}.",
+                    name
+                ),
+            })
+        }
+        "bind" if !rest.is_empty() => match model.find_household(rest)? {
+            None => Ok(format!("There is no household called '{}'.", rest)),
+            Some(household) if household != user.household => {
+                Ok(format!("You are not an admin of the household {}.", rest))
+            }
+            Some(household) => match model.bind_chat(chat_id, household) {
+                Ok(()) => Ok(format!(
+                    "This chat now belongs to the household {}.",
+                    model.household_name(household)?
+                )),
+                Err(Error::Refused(reason)) => Ok(reason),
+                Err(e) => Err(e),
+            },
+        },
+        "new" if user.household != DEFAULT_HOUSEHOLD => Ok(SHARED_ONLY.to_string()),
+        "new" => {
+            let Some((name, admin)) = rest.rsplit_once(' ') else {
+                return Ok(HOUSEHOLD_USAGE.to_string());
+            };
+            let admin_id = match model.resolve_user_id(admin) {
+                Ok(id) => id,
+                Err(Error::NotFound(_)) => {
+                    return Ok(format!("Unknown user {}. Use their numeric id.", admin))
+                }
+                Err(Error::Refused(reason)) => return Ok(reason),
+                Err(e) => return Err(e),
+            };
+            let name = name.trim();
+            match model.create_household(name, admin_id) {
+                Ok(_) => Ok(format!(
+                    "Created the household {} with {} as its admin. They bind their chats to it with /household bind {}.",
+                    name, admin, name
+                )),
+                Err(Error::Refused(reason)) => Ok(reason),
+                Err(e @ Error::TooLong { .. }) => Ok(e.to_string()),
+                Err(e) => Err(e),
+            }
+        }
+        _ => Ok(HOUSEHOLD_USAGE.to_string()),
+    }
+}
+
+const MAINTENANCE_USAGE: &str = "Usage: /maintenance or /maintenance prune";
+
+const CURRENCY_USAGE: &str = "Usage: /currency set <currency> exponent <decimal places>";
+
+fn currency(model: &SharedModel, args: &str) -> Result<String, Error> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let (currency, exponent) = match args.as_slice() {
+        ["set", currency, "exponent", exponent] => match exponent.parse::<u32>() {
+            Ok(exponent) => (*currency, exponent),
+            Err(_) => return Ok(format!("Invalid exponent '{}'.", exponent)),
+        },
+        _ => return Ok(CURRENCY_USAGE.to_string()),
+    };
+
+    match model
+        .lock()
+        .unwrap()
+        .set_currency_exponent(currency, exponent)
+    {
+        Ok(()) => Ok(format!("{} now has {} decimal places.", currency, exponent)),
+        Err(Error::NotFound(what)) => Ok(format!("Unknown {}.", what)),
+        Err(Error::Refused(reason)) => Ok(format!("Can't change {}: {}", currency, reason)),
+        Err(e) => Err(e),
+    }
+}
+
+const SETTLE_USAGE: &str = "Usage: /settle or /settle clear";
+
+/// `/settle`: the reply to send, in MarkdownV2. Clearing settles the debts
+/// of the user asking, with whoever they are owed by or owe.
+fn settle(
+    model: &SharedModel,
+    user: &User,
+    args: &str,
+    now: DateTime<Utc>,
+) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    match args.trim() {
+        "" => Ok(format::render_debts(&model.debts(user.household)?)),
+        "clear" => Ok(escape_md(
+            &match model.settle(user.household, user.telegram_id, now)? {
+                0 => "You have no debts to settle.".to_string(),
+                1 => "Settled 1 debt, the expenses stay as they are.".to_string(),
+                n => format!("Settled {} debts, the expenses stay as they are.", n),
+            },
+        )),
+        _ => Ok(escape_md(SETTLE_USAGE)),
+    }
+}
+
+const MIGRATE_USAGE: &str = "Usage: /migrate verify-amounts or /migrate finalize";
+
+/// `/migrate`: the reply to send, in MarkdownV2. The legacy amounts are only
+/// dropped if they all agree with the converted ones.
+fn migrate(model: &SharedModel, user: &User, args: &str) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    match args.trim() {
+        "verify-amounts" => Ok(format::render_amount_migration(
+            &model.verify_amount_migration()?,
+        )),
+        "finalize" => Ok(escape_md(
+            &match model.finalize_amount_migration(user.telegram_id) {
+                Ok(()) => {
+                    "The legacy amounts are dropped, amounts are in minor units only.".to_string()
+                }
+                Err(Error::Refused(reason)) => {
+                    format!("Can't finalize: {} See /migrate verify-amounts.", reason)
+                }
+                Err(e) => return Err(e),
+            },
+        )),
+        _ => Ok(escape_md(MIGRATE_USAGE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    fn parse(text: &str) -> Command {
+        Command::parse(text, "bot").unwrap()
+    }
+
+    #[test]
+    fn required_roles() {
+        assert_eq!(None, parse("/help").required_role());
+        assert_eq!(None, parse("/start").required_role());
+        assert_eq!(
+            Some(Role::Admin),
+            parse("/role 1001 viewer").required_role()
+        );
+        assert_eq!(Some(Role::Viewer), parse("/balance").required_role());
+        assert_eq!(Some(Role::Viewer), parse("/report").required_role());
+        assert_eq!(Some(Role::Viewer), parse("/report ytd").required_role());
+        assert_eq!(Some(Role::Member), parse("/add 5 food").required_role());
+        assert_eq!(Some(Role::Member), parse("/review").required_role());
+        assert_eq!(Some(Role::Member), parse("/fav").required_role());
+        assert_eq!(Some(Role::Viewer), parse("/settings").required_role());
+        assert_eq!(
+            Some(Role::Admin),
+            parse("/settings privacy on").required_role()
+        );
+        assert_eq!(
+            Some(Role::Viewer),
+            parse("/settings report-exclude Housing").required_role()
+        );
+        assert_eq!(
+            Some(Role::Viewer),
+            parse("/settings mtd off").required_role()
+        );
+        assert_eq!(Some(Role::Viewer), parse("/budget").required_role());
+        assert_eq!(
+            Some(Role::Admin),
+            parse("/budget Groceries 100 EUR").required_role()
+        );
+        assert_eq!(Some(Role::Admin), parse("/rate USD 0.9").required_role());
+        assert_eq!(Some(Role::Viewer), parse("/rate status").required_role());
+        assert_eq!(
+            Some(Role::Admin),
+            parse("/currency set BYN exponent 2").required_role()
+        );
+        assert_eq!(
+            Some(Role::Admin),
+            parse("/recategorize from a to b").required_role()
+        );
+        assert_eq!(
+            Some(Role::Admin),
+            parse("/category merge a into b").required_role()
+        );
+        assert_eq!(
+            Some(Role::Viewer),
+            parse("/category stats Groceries").required_role()
+        );
+        assert_eq!(Some(Role::Admin), parse("/integrity").required_role());
+        assert_eq!(Some(Role::Admin), parse("/household").required_role());
+    }
+
+    #[test]
+    fn shared_commands() {
+        assert!(parse("/rate USD 0.9").is_shared());
+        assert!(!parse("/rate status").is_shared());
+        assert!(parse("/integrity").is_shared());
+        assert!(parse("/maintenance").is_shared());
+        assert!(parse("/readonly on").is_shared());
+        assert!(parse("/migrate finalize").is_shared());
+        assert!(!parse("/report").is_shared());
+        assert!(!parse("/household new Smiths 42").is_shared());
+    }
+
+    #[test]
+    fn role_command_sets_role() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+
+        assert_eq!(
+            "User @alex_bot is now a viewer.",
+            set_role(&model, 1, "@alex_bot", "viewer").unwrap()
+        );
+        assert_eq!(
+            Role::Viewer,
+            model.lock().unwrap().get_user(1001).unwrap().unwrap().role
+        );
+
+        assert!(set_role(&model, 1, "1001", "owner")
+            .unwrap()
+            .starts_with("Unknown role"));
+        assert!(set_role(&model, 1, "@ghost", "member")
+            .unwrap()
+            .starts_with("Unknown user"));
+        assert_eq!(
+            "User 1001 belongs to another household.",
+            set_role(&model, 2, "1001", "admin").unwrap()
+        );
+    }
+
+    #[test]
+    fn rate_status_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let day = |d| NaiveDate::from_ymd_opt(2023, 12, d).unwrap();
+        model.set_rate("USD", day(1), 0.9).unwrap();
+        let model = Arc::new(Mutex::new(model));
+        assert_eq!(
+            "Latest rates in EUR:\nBYN: no rate\nUSD: 0.9 from 2023-12-01, today",
+            rate_status(&model, "EUR", (day(1), 14)).unwrap()
+        );
+        model
+            .lock()
+            .unwrap()
+            .set_rate("BYN", day(20), 0.25)
+            .unwrap();
+
+        assert_eq!(
+            "Latest rates in EUR:\nBYN: 0.25 from 2023-12-20, today\nUSD: 0.9 from 2023-12-01, 19 days old, may be stale",
+            rate_status(&model, "EUR", (day(20), 14)).unwrap()
+        );
+        assert_eq!(
+            "Latest rates in EUR:\nBYN: 0.25 from 2023-12-20, 1 day old\nUSD: 0.9 from 2023-12-01, 20 days old",
+            rate_status(&model, "EUR", (day(21), 30)).unwrap()
+        );
+    }
+
+    #[test]
+    fn rate_command_validates() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+
+        assert!(set_rate(&model, "USD", "0.9")
+            .unwrap()
+            .starts_with("Rate of USD"));
+        assert_eq!("Invalid rate '-1'.", set_rate(&model, "USD", "-1").unwrap());
+        assert_eq!(
+            "Unknown currency XYZ.",
+            set_rate(&model, "XYZ", "1").unwrap()
+        );
+    }
+
+    #[test]
+    fn require_comment_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let required = |id| {
+            model
+                .lock()
+                .unwrap()
+                .get_category(1, id)
+                .unwrap()
+                .unwrap()
+                .require_comment
+        };
+
+        assert_eq!(
+            "Utilities expenses now need a comment.",
+            require_comment(&model, 1, "require-comment util on").unwrap()
+        );
+        assert!(required(4));
+        assert_eq!(
+            "Utilities expenses no longer need a comment.",
+            require_comment(&model, 1, "require-comment Utilities off").unwrap()
+        );
+        assert!(!required(4));
+        assert!(require_comment(&model, 1, "require-comment Other on")
+            .unwrap()
+            .starts_with("No category matches 'Other'."));
+        assert_eq!(
+            parse::CATEGORY_USAGE,
+            require_comment(&model, 1, "require-comment Utilities").unwrap()
+        );
+    }
+
+    #[test]
+    fn category_bind_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let bind = |args| category_account(&model, 1, args).unwrap();
+        let bound = || model.lock().unwrap().category_account(1, 4).unwrap();
+
+        assert_eq!(
+            "Picking Utilities now moves drafts to Family BYN, unless their account was picked already.",
+            bind(r#"bind util "family byn""#)
+        );
+        assert_eq!(Some(3), bound().map(|a| a.id));
+        assert!(bind("bind Utilities Nowhere").starts_with("No account matches 'Nowhere'."));
+        assert_eq!(
+            "Utilities is no longer bound to an account.",
+            bind("unbind Utilities")
+        );
+        assert_eq!(None, bound());
+        assert_eq!(parse::CATEGORY_USAGE, bind("bind Utilities"));
+    }
+
+    #[test]
+    fn category_stats_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let now = DateTime::from_timestamp(1_705_320_000, 0).unwrap(); // 2024-01-15 12:00 UTC
+        let stats = |args| category_stats(&model, 1, args, now, Tz::UTC).unwrap();
+
+        assert!(stats("stats groc").starts_with("*Groceries*\n"));
+        assert_eq!(escape_md(parse::CATEGORY_USAGE), stats("stats"));
+        assert!(
+            stats("stats Garden").starts_with("No category"),
+            "{}",
+            stats("stats Garden")
+        );
+    }
+
+    #[test]
+    fn category_parent_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+
+        assert_eq!(
+            "Entertainment is now under Utilities.",
+            category_parent(&model, 1, "parent ent under util").unwrap()
+        );
+        assert_eq!(
+            "Utilities has subcategories, categories nest only one level deep.",
+            category_parent(&model, 1, "parent Utilities under Groceries").unwrap()
+        );
+        assert_eq!(
+            "Entertainment is a top-level category now.",
+            category_parent(&model, 1, "parent Entertainment none").unwrap()
+        );
+        assert!(category_parent(&model, 1, "parent Other under Groceries")
+            .unwrap()
+            .starts_with("No category matches 'Other'."));
+    }
+
+    #[test]
+    fn year_report_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let now = DateTime::from_timestamp(1_704_067_200, 0).unwrap(); // 2024-01-01 00:00 UTC
+        let report = |args| year_report(&model, 1, args, now, Tz::UTC).unwrap();
+
+        assert_eq!(vec!["No expenses in 2024\\.".to_string()], report("year"));
+        assert_eq!(
+            vec!["Invalid year 'next'\\.".to_string()],
+            report("year next")
+        );
+        let texts = report("year 2023");
+        assert_eq!(1, texts.len());
+        assert!(
+            texts[0].starts_with("*2023 in review*\n📅 Busiest day: 2023\\-12\\-01 with 1 expense")
+        );
+        // It is still 2023 in New York.
+        assert_eq!(
+            report("year 2023"),
+            year_report(&model, 1, "year", now, Tz::America__New_York).unwrap()
+        );
+    }
+
+    #[test]
+    fn heatmap_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        // Friday 22 December 2023.
+        let now = DateTime::from_timestamp(1_703_246_400, 0).unwrap();
+        let report = |args| heatmap_report(&model, 1, args, now, Tz::UTC).unwrap();
+
+        let all = report("heatmap");
+        assert!(all.starts_with("*Average spend by weekday, last 12 weeks*\n\nBYN\n"));
+        // The 100 USD of electricity on Monday 4 December, 20 of taxi on
+        // Saturday the 2nd.
+        assert!(all.ends_with("USD\n🟥 Mon 8\\.33\n⬜ Tue 0\\.00\n⬜ Wed 0\\.00\n⬜ Thu 0\\.00\n⬜ Fri 0\\.00\n🟨 Sat 1\\.67\n⬜ Sun 0\\.00"));
+        assert!(report("heatmap util").starts_with("*Average spend on Utilities by weekday"));
+        assert!(report("heatmap nonsense").contains("nonsense"));
+    }
+
+    #[test]
+    fn fun_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let now = DateTime::from_timestamp(1_704_067_200, 0).unwrap(); // 2024-01-01 00:00 UTC
+        let fun = |args| fun_report(&model, 1, args, now, Tz::UTC).unwrap();
+
+        assert_eq!("No expenses in January 2024\\.", fun(""));
+        assert_eq!(
+            "Unknown month 'december'\\. Use lastmonth or YYYY\\-MM\\.",
+            fun("december")
+        );
+        assert_eq!(fun("2023-12"), fun("lastmonth"));
+        assert_eq!(
+            [
+                "*December 2023 for fun*",
+                "📝 Most logged: Alex with 2 expenses",
+                "💸 Largest: 30\\.00 BYN by Alex · Cinema tickets for family",
+                "💸 Largest: 50\\.75 EUR by Alex · Bought groceries for the week",
+                "💸 Largest: 100\\.00 USD by Hanna · Paid electricity bill",
+                "🏷️ Most used: Entertainment with 1 expense",
+                "🔥 Longest streak: 4 days from 2023\\-12\\-01",
+                "🌅 Earliest: 10:00 by Alex, Groceries",
+                "🌙 Latest: 20:00 by Hanna, Utilities",
+            ]
+            .join("\n"),
+            fun("2023-12")
+        );
+    }
+
+    #[cfg(feature = "report-image")]
+    #[test]
+    fn report_image_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let now = DateTime::from_timestamp(1_702_000_000, 0).unwrap(); // 2023-12-08
+        let image = |args| report_image(&model, 1, args, now, Tz::UTC).unwrap();
+
+        let png = image(" ").unwrap();
+        assert_eq!(b"\x89PNG", &png[..4]);
+        assert_eq!(&1080u32.to_be_bytes(), &png[16..20]);
+        assert!(image("lastmonth").is_ok());
+        assert_eq!(
+            Err("Unknown period 'soon'. Use week, lastweek, month, lastmonth, ytd, forecast or YYYY-MM-DD..YYYY-MM-DD.".to_string()),
+            image(" soon")
+        );
+    }
+
+    #[test]
+    fn export_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let now = DateTime::from_timestamp(1_704_067_200, 0).unwrap(); // 2024-01-01 00:00 UTC
+        let export = |args| export_file(&model, 1, args, now, Tz::UTC).unwrap();
+
+        let (name, csv) = export("2023").unwrap();
+        assert_eq!("expenses-2023.csv", name);
+        assert_eq!(5, String::from_utf8(csv).unwrap().lines().count());
+        assert_eq!(
+            Ok((
+                "expenses-2024.csv".to_string(),
+                b"Date,Amount,Currency,Account,Category,User,Comment\n".to_vec()
+            )),
+            export("csv")
+        );
+        assert_eq!(Err("Invalid year 'last'.".to_string()), export("csv last"));
+        assert!(export("ods 2023")
+            .unwrap_err()
+            .starts_with("Unknown format 'ods'. Use csv"));
+        #[cfg(feature = "xlsx")]
+        assert_eq!("expenses-2023.xlsx", export("XLSX 2023").unwrap().0);
+    }
+
+    #[test]
+    fn settle_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.split_expense(1, 1, Some(1002)).unwrap();
+        let model = Arc::new(Mutex::new(model));
+        let hanna = model.lock().unwrap().get_user(1002).unwrap().unwrap();
+        let now = Utc::now();
+
+        assert_eq!(
+            "Hanna owes Alex 25\\.37 EUR",
+            settle(&model, &hanna, "", now).unwrap()
+        );
+        assert_eq!(
+            escape_md("Settled 1 debt, the expenses stay as they are."),
+            settle(&model, &hanna, "clear", now).unwrap()
+        );
+        assert_eq!(
+            escape_md("Nobody owes anybody anything."),
+            settle(&model, &hanna, " ", now).unwrap()
+        );
+        assert_eq!(
+            escape_md("You have no debts to settle."),
+            settle(&model, &hanna, "clear", now).unwrap()
+        );
+        assert_eq!(
+            escape_md(SETTLE_USAGE),
+            settle(&model, &hanna, "all", now).unwrap()
+        );
+    }
+
+    #[test]
+    fn migrate_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let admin = model.lock().unwrap().get_user(1001).unwrap().unwrap();
+
+        assert_eq!(
+            escape_md(MIGRATE_USAGE),
+            migrate(&model, &admin, "").unwrap()
+        );
+        assert!(migrate(&model, &admin, "verify-amounts")
+            .unwrap()
+            .contains("All 0 amounts agree"));
+        assert_eq!(
+            escape_md("The legacy amounts are dropped, amounts are in minor units only."),
+            migrate(&model, &admin, "finalize").unwrap()
+        );
+        assert_eq!(
+            escape_md(
+                "Can't finalize: The legacy amounts are gone already. See /migrate verify-amounts."
+            ),
+            migrate(&model, &admin, "finalize").unwrap()
+        );
+    }
+
+    #[test]
+    fn settings_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let settings_of = |args: &str| match parse::parse_settings(args) {
+            Ok(SettingsArgs::MonthToDate(on)) => settings::month_to_date(&model, 1001, on).unwrap(),
+            Ok(other) => panic!("{:?}", other),
+            Err(usage) => usage,
+        };
+
+        assert!(model.get_settings(1001).unwrap().month_to_date);
+        assert_eq!(
+            "Month-to-date total after saving: off.",
+            settings_of("mtd off")
+        );
+        assert!(!model.get_settings(1001).unwrap().month_to_date);
+        assert_eq!(
+            "Month-to-date total after saving: on.",
+            settings_of("mtd on")
+        );
+        assert!(model.get_settings(1001).unwrap().month_to_date);
+        assert_eq!(parse::SETTINGS_USAGE, settings_of("mtd maybe"));
+    }
+
+    #[test]
+    fn currency_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+
+        assert_eq!(CURRENCY_USAGE, currency(&model, "").unwrap());
+        assert_eq!(CURRENCY_USAGE, currency(&model, "set BYN 2").unwrap());
+        assert_eq!(
+            "Invalid exponent 'two'.",
+            currency(&model, "set BYN exponent two").unwrap()
+        );
+        assert_eq!(
+            "Unknown currency XYZ.",
+            currency(&model, "set XYZ exponent 2").unwrap()
+        );
+        assert_eq!(
+            "Can't change BYN: BYN already has expenses stored with 2 decimal places.",
+            currency(&model, "set BYN exponent 3").unwrap()
+        );
+        assert_eq!(
+            "BYN now has 2 decimal places.",
+            currency(&model, "set BYN exponent 2").unwrap()
+        );
+    }
+}
@@ -0,0 +1,1010 @@
+//! Expenses being composed: a draft lives in memory, attached to the bot
+//! message carrying its edit keyboard, until it is committed or cancelled.
+
+use super::batch::Batch;
+use super::callback::{Field, Page};
+use super::templates::{FillStep, TemplateFill};
+use super::SharedModel;
+use crate::model::{
+    check_comment, from_minor, to_minor, Account, AmountLimits, Category, CommentTemplate,
+    ExpenseSource, Locale, NewExpense, OriginalAmount, SourceMessage, User,
+};
+use crate::time;
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use log::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use teloxide::types::{ChatId, MessageId, UserId};
+use tokio::sync::OwnedMutexGuard;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveTransaction {
+    /// The stored expense being edited, `None` for a new one.
+    pub expense_id: Option<i64>,
+    pub amount: f64,
+    /// The other way to read the typed amount when its separators were
+    /// ambiguous, shown so that a misreading is noticed before committing.
+    pub amount_alternative: Option<f64>,
+    /// The calculation the amount was typed as, shown with its result.
+    pub amount_calculation: Option<String>,
+    /// What was paid in another currency than the account's. `amount` is
+    /// what the account was charged for it, 0 until that is typed.
+    pub original: Option<OriginalAmount>,
+    pub account: Account,
+    /// Whether the account was picked for this draft, which keeps the
+    /// account a category is bound to from replacing it.
+    pub account_explicit: bool,
+    /// The currency the chat assumes amounts are in when none of the
+    /// household's accounts is in it, flagged until an account is picked.
+    pub missing_account: Option<String>,
+    pub category: Category,
+    /// The household of the account and category, which the draft is
+    /// committed to.
+    pub household: i64,
+    pub user_id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub comment: Option<String>,
+    /// Whether the category was chosen rather than left at the default.
+    pub category_confirmed: bool,
+    /// Whether a category rule picked the category from the comment.
+    pub category_by_rule: bool,
+    /// Whether the user's strict commit setting was on when the draft was
+    /// last shown, which locks its Commit button.
+    pub strict_commit: bool,
+    /// Who shares the expense half and half with its payer, if anybody.
+    pub split_with: Option<User>,
+    /// Latitude and longitude of where it was made, if shared.
+    pub location: Option<(f64, f64)>,
+    /// Comments offered as buttons when the comment was last edited.
+    pub comment_suggestions: Vec<String>,
+    /// The user's templates offered along with them.
+    pub comment_templates: Vec<CommentTemplate>,
+    /// The template whose blanks are being asked for, if any.
+    pub template_fill: Option<TemplateFill>,
+    /// The group message it was typed in, linked from the saved expense.
+    pub source: Option<SourceMessage>,
+    /// How it was logged, stored with the expense. Edits keep what it was.
+    pub origin: ExpenseSource,
+    /// The page of the keyboard shown, kept when the draft is shown again.
+    pub page: Page,
+}
+
+impl ActiveTransaction {
+    /// Whether the amount charged for the original one is still unknown.
+    pub fn awaits_charge(&self) -> bool {
+        self.original.is_some() && self.amount == 0.0
+    }
+
+    /// Whether the draft has no amount yet, like one awaiting the charge or
+    /// a forward whose amount couldn't be read. Typed amounts are never 0.
+    pub fn awaits_amount(&self) -> bool {
+        self.amount == 0.0
+    }
+
+    /// Moves the draft to `account`. Paying from an account in the original
+    /// currency, the original amount is what it is charged.
+    pub fn set_account(&mut self, account: Account) {
+        self.account = account;
+        if let Some(original) = self
+            .original
+            .take_if(|o| o.currency.eq_ignore_ascii_case(&self.account.currency))
+        {
+            self.amount = original.amount;
+            self.amount_alternative = None;
+            self.amount_calculation = None;
+        }
+    }
+
+    /// Picks `category`, moving the draft to `bound`, the account the
+    /// category is bound to, unless an account was picked for it already.
+    pub fn pick_category(&mut self, category: Category, bound: Option<Account>) {
+        self.category = category;
+        self.category_by_rule = false;
+        if let Some(account) = bound.filter(|_| !self.account_explicit) {
+            self.set_account(account);
+        }
+    }
+
+    /// Picks `account`, which bindings of categories leave alone from then
+    /// on.
+    pub fn pick_account(&mut self, account: Account) {
+        self.set_account(account);
+        self.account_explicit = true;
+        self.missing_account = None;
+    }
+
+    /// Whether Commit has to wait for the category to be picked, with the
+    /// strict commit setting `strict`.
+    pub fn commit_locked(&self, strict: bool) -> bool {
+        strict && !self.category_confirmed
+    }
+
+    /// Starts filling in `template`, returning the prompt for its first
+    /// blank. One without blanks is the comment right away.
+    pub fn start_template(&mut self, template: CommentTemplate) -> Option<String> {
+        let (fill, step) = TemplateFill::start(template);
+        self.take_fill_step(fill, step)
+    }
+
+    /// Takes `text` for the blank asked for, returning the prompt for the
+    /// next one, or the reason it can't be used. The comment only changes
+    /// once the last blank is answered.
+    pub fn answer_template(&mut self, text: &str) -> Result<Option<String>, String> {
+        let mut fill = self
+            .template_fill
+            .take()
+            .ok_or_else(|| "No template is being filled in.".to_string())?;
+        match fill.answer(text) {
+            Ok(step) => Ok(self.take_fill_step(fill, step)),
+            Err(reason) => {
+                self.template_fill = Some(fill);
+                Err(reason)
+            }
+        }
+    }
+
+    fn take_fill_step(&mut self, fill: TemplateFill, step: FillStep) -> Option<String> {
+        match step {
+            FillStep::Ask(prompt) => {
+                self.template_fill = Some(fill);
+                Some(prompt)
+            }
+            FillStep::Done(comment) => {
+                self.comment = Some(comment);
+                None
+            }
+        }
+    }
+
+    /// Moves the amount by `step` minor units, to no less than one. Counting
+    /// in minor units, taps add up without float drift.
+    pub fn nudge(&mut self, step: i64, limits: &AmountLimits) -> Result<(), String> {
+        let exponent = self.account.exponent;
+        let minor = to_minor(self.amount, exponent).saturating_add(step).max(1);
+        self.amount = limits
+            .for_exponent(exponent)
+            .validate(from_minor(minor, exponent))
+            .map_err(|e| e.to_string())?;
+        // Neither is what the amount is now.
+        self.amount_alternative = None;
+        self.amount_calculation = None;
+        Ok(())
+    }
+
+    pub fn to_new_expense(&self) -> NewExpense {
+        NewExpense {
+            account_id: self.account.id,
+            category_id: self.category.id,
+            user_id: self.user_id,
+            timestamp: self.timestamp,
+            amount: self.amount,
+            comment: self.comment.clone(),
+            category_confirmed: self.category_confirmed,
+            source: self.origin,
+        }
+    }
+
+    /// Applies a typed value to `field`, returning the reason when the value
+    /// can't be used. Amounts are typed the way `locale` writes numbers,
+    /// dates as local time in `tz`.
+    pub fn apply_edit(
+        &mut self,
+        field: Field,
+        text: &str,
+        limits: &AmountLimits,
+        (locale, tz): (Locale, Tz),
+    ) -> Result<(), String> {
+        match field {
+            Field::Amount => {
+                let parsed = limits
+                    .for_exponent(self.account.exponent)
+                    .parse(text, locale)
+                    .map_err(|e| e.to_string())?;
+                self.amount = parsed.amount;
+                self.amount_alternative = parsed.alternative;
+                self.amount_calculation = parsed.calculation.map(|c| c.text);
+            }
+            Field::Timestamp => {
+                self.timestamp = time::parse_local(text, tz).ok_or_else(|| {
+                    format!("'{}' is not a valid date. Use YYYY-MM-DD HH:MM.", text)
+                })?;
+            }
+            Field::Comment => {
+                let comment = text.trim();
+                check_comment(Some(comment)).map_err(|e| e.to_string())?;
+                // A single dash clears the comment.
+                self.comment = match comment {
+                    "" | "-" => None,
+                    _ => Some(comment.to_string()),
+                };
+            }
+            // Locations are shared rather than typed, only removing one is.
+            Field::Location => match text.trim() {
+                "-" => self.location = None,
+                _ => {
+                    return Err(
+                        "Share a location with 📎 → Location, or send - to remove it.".to_string(),
+                    )
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// The bot message a draft is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DraftKey {
+    pub chat_id: ChatId,
+    pub message_id: MessageId,
+}
+
+impl DraftKey {
+    /// Key the committed expense is stored under. Drafts only live in
+    /// memory, so after a restart this is what tells a stale Commit button
+    /// apart from a draft that was never saved.
+    pub fn idempotency_key(&self) -> String {
+        format!("draft:{}:{}", self.chat_id, self.message_id.0)
+    }
+}
+
+/// How long a prompt in a private chat takes the next message as its value.
+pub const PENDING_EDIT_TTL: TimeDelta = TimeDelta::minutes(5);
+
+/// A prompt waiting for the user to type a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingEdit {
+    pub draft: DraftKey,
+    pub field: Field,
+    /// The bot message asking for the value.
+    pub prompt: MessageId,
+    pub asked_at: DateTime<Utc>,
+}
+
+/// What is known about a message that may answer a prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Answer {
+    pub reply_to: Option<MessageId>,
+    pub private: bool,
+    pub sent_at: DateTime<Utc>,
+    /// Whether it shares a location rather than typing a value.
+    pub location: bool,
+}
+
+impl PendingEdit {
+    /// A reply to the prompt always answers it. In private chats so does
+    /// any message sent soon enough; in groups other messages are unrelated.
+    fn answered_by(&self, answer: Answer) -> bool {
+        answer.reply_to == Some(self.prompt)
+            || (answer.private && answer.sent_at - self.asked_at <= PENDING_EDIT_TTL)
+    }
+}
+
+#[derive(Default)]
+pub struct Drafts {
+    drafts: Mutex<HashMap<DraftKey, ActiveTransaction>>,
+    pending: Mutex<HashMap<(ChatId, UserId), PendingEdit>>,
+    locks: Mutex<HashMap<DraftKey, Arc<tokio::sync::Mutex<()>>>>,
+    /// Expenses typed several in a message, waiting to be committed under
+    /// their summary.
+    batches: Mutex<HashMap<DraftKey, Batch>>,
+    /// Where the messages of open drafts and batches are recorded, for the
+    /// next start to expire them if the bot stops first.
+    journal: Option<SharedModel>,
+}
+
+pub type SharedDrafts = Arc<Drafts>;
+
+impl Drafts {
+    /// Drafts whose messages are recorded in `model` while they are open.
+    pub fn journaled(model: SharedModel) -> Drafts {
+        Drafts {
+            journal: Some(model),
+            ..Drafts::default()
+        }
+    }
+
+    /// Records the message at `key` as showing an open draft, or no longer.
+    /// A failure only leaves the message out of the cleanup on the next
+    /// start, so it is logged rather than passed on.
+    fn journal(&self, key: DraftKey, open: bool) {
+        let Some(model) = &self.journal else {
+            return;
+        };
+        let model = model.lock().unwrap();
+        let (chat_id, message_id) = (key.chat_id.0, key.message_id.0);
+        let recorded = match open {
+            true => model.record_draft_message(chat_id, message_id, Utc::now()),
+            false => model.forget_draft_message(chat_id, message_id),
+        };
+        if let Err(e) = recorded {
+            warn!("Couldn't record the draft message {:?}: {}", key, e);
+        }
+    }
+
+    /// Serializes the handling of updates to the draft at `key`. Handlers
+    /// hold the guard from reading the draft until its message shows the
+    /// change, so two quick taps can't render or save a mix of both.
+    pub async fn lock(&self, key: DraftKey) -> OwnedMutexGuard<()> {
+        let lock = self.locks.lock().unwrap().entry(key).or_default().clone();
+        lock.lock_owned().await
+    }
+
+    pub fn insert(&self, key: DraftKey, draft: ActiveTransaction) {
+        self.drafts.lock().unwrap().insert(key, draft);
+        self.journal(key, true);
+    }
+
+    pub fn get(&self, key: DraftKey) -> Option<ActiveTransaction> {
+        self.drafts.lock().unwrap().get(&key).cloned()
+    }
+
+    /// Mutates a draft in place and returns its new state.
+    pub fn update<R>(
+        &self,
+        key: DraftKey,
+        f: impl FnOnce(&mut ActiveTransaction) -> R,
+    ) -> Option<(R, ActiveTransaction)> {
+        let mut drafts = self.drafts.lock().unwrap();
+        let draft = drafts.get_mut(&key)?;
+        let result = f(draft);
+        Some((result, draft.clone()))
+    }
+
+    pub fn remove(&self, key: DraftKey) -> Option<ActiveTransaction> {
+        self.pending.lock().unwrap().retain(|_, p| p.draft != key);
+        self.locks.lock().unwrap().remove(&key);
+        self.journal(key, false);
+        self.drafts.lock().unwrap().remove(&key)
+    }
+
+    pub fn insert_batch(&self, key: DraftKey, batch: Batch) {
+        self.batches.lock().unwrap().insert(key, batch);
+        self.journal(key, true);
+    }
+
+    pub fn batch(&self, key: DraftKey) -> Option<Batch> {
+        self.batches.lock().unwrap().get(&key).cloned()
+    }
+
+    pub fn remove_batch(&self, key: DraftKey) -> Option<Batch> {
+        self.locks.lock().unwrap().remove(&key);
+        self.journal(key, false);
+        self.batches.lock().unwrap().remove(&key)
+    }
+
+    /// Makes `edit` the prompt of the user, returning the one it replaces.
+    pub fn set_pending(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        edit: PendingEdit,
+    ) -> Option<PendingEdit> {
+        self.pending
+            .lock()
+            .unwrap()
+            .insert((chat_id, user_id), edit)
+    }
+
+    /// The edit `answer` is the value for, if any. An expired prompt in a
+    /// private chat is dropped; in groups it waits for a reply.
+    ///
+    /// Only location prompts take shared locations. The prompt for the
+    /// location of a committed expense, whose draft is gone, takes nothing
+    /// else: any other message means the user moved on, and drops it.
+    pub fn take_pending(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        answer: Answer,
+    ) -> Option<PendingEdit> {
+        let mut pending = self.pending.lock().unwrap();
+        let edit = *pending.get(&(chat_id, user_id))?;
+        let location_prompt = edit.field == Field::Location;
+        if answer.location && !location_prompt {
+            return None;
+        }
+        if !answer.location
+            && location_prompt
+            && !self.drafts.lock().unwrap().contains_key(&edit.draft)
+        {
+            pending.remove(&(chat_id, user_id));
+            return None;
+        }
+        if edit.answered_by(answer) || answer.private {
+            pending.remove(&(chat_id, user_id));
+        }
+        Some(edit).filter(|e| e.answered_by(answer))
+    }
+
+    /// Drops the prompt of the user, e.g. when they moved on to another
+    /// draft action.
+    pub fn clear_pending(&self, chat_id: ChatId, user_id: UserId) -> Option<PendingEdit> {
+        self.pending.lock().unwrap().remove(&(chat_id, user_id))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn draft() -> ActiveTransaction {
+        ActiveTransaction {
+            expense_id: None,
+            amount: 50.75,
+            amount_alternative: None,
+            amount_calculation: None,
+            original: None,
+            account: Account {
+                id: 1,
+                name: "Alex Savings".to_string(),
+                currency: "EUR".to_string(),
+                exponent: 2,
+            },
+            account_explicit: false,
+            missing_account: None,
+            category: Category {
+                id: 1,
+                name: "Groceries".to_string(),
+                icon: Some("🛒".to_string()),
+                require_comment: false,
+                parent_id: None,
+            },
+            household: 1,
+            user_id: 1001,
+            timestamp: DateTime::parse_from_rfc3339("2023-12-01T10:00:00Z")
+                .unwrap()
+                .to_utc(),
+            comment: Some("groceries for the week".to_string()),
+            category_confirmed: false,
+            category_by_rule: false,
+            strict_commit: false,
+            split_with: None,
+            location: None,
+            comment_suggestions: Vec::new(),
+            comment_templates: Vec::new(),
+            template_fill: None,
+            source: None,
+            origin: ExpenseSource::Manual,
+            page: Page::Main,
+        }
+    }
+
+    #[test]
+    fn strict_commit_follows_the_setting() {
+        let model = crate::model::Model::new(true);
+        model.fill_test_data();
+        let strict = || model.get_settings(1001).unwrap().strict_commit;
+        let mut d = draft();
+        assert!(!d.commit_locked(strict()));
+
+        // Turned on while the draft is open.
+        model
+            .set_setting(1001, crate::model::Setting::StrictCommit(true))
+            .unwrap();
+        assert!(d.commit_locked(strict()));
+        d.category_confirmed = true;
+        assert!(!d.commit_locked(strict()));
+
+        // And off again, even though the draft was shown locked.
+        d.category_confirmed = false;
+        d.strict_commit = true;
+        model
+            .set_setting(1001, crate::model::Setting::StrictCommit(false))
+            .unwrap();
+        assert!(!d.commit_locked(strict()));
+    }
+
+    #[test]
+    fn paying_from_an_account_in_the_original_currency() {
+        let mut d = draft();
+        d.amount = 0.0;
+        d.original = Some(crate::model::OriginalAmount {
+            amount: 13.5,
+            currency: "USD".to_string(),
+            exponent: 2,
+        });
+        assert!(d.awaits_charge());
+
+        let usd = Account {
+            id: 2,
+            name: "Hanna Card".to_string(),
+            currency: "usd".to_string(),
+            exponent: 2,
+        };
+        d.set_account(usd);
+        assert!(!d.awaits_charge());
+        assert_eq!((13.5, None), (d.amount, d.original.clone()));
+
+        // Without an original amount only the account changes.
+        d.set_account(draft().account);
+        assert_eq!((13.5, "EUR"), (d.amount, d.account.currency.as_str()));
+    }
+
+    fn account(id: i64, name: &str, currency: &str) -> Account {
+        Account {
+            id,
+            name: name.to_string(),
+            currency: currency.to_string(),
+            exponent: 2,
+        }
+    }
+
+    fn utilities() -> Category {
+        Category {
+            id: 4,
+            name: "Utilities".to_string(),
+            icon: None,
+            require_comment: false,
+            parent_id: None,
+        }
+    }
+
+    #[test]
+    fn category_picks_move_to_the_bound_account() {
+        let family = account(3, "Family Fund", "BYN");
+        let mut d = draft();
+        d.pick_category(utilities(), Some(family.clone()));
+        assert_eq!((4, 3), (d.category.id, d.account.id));
+        assert!(!d.account_explicit);
+
+        // Categories without a binding keep the account they found.
+        d.pick_category(draft().category, None);
+        assert_eq!((1, 3), (d.category.id, d.account.id));
+        // Another bound category moves it on.
+        d.pick_category(utilities(), Some(account(2, "Hanna Daily", "USD")));
+        assert_eq!(2, d.account.id);
+    }
+
+    #[test]
+    fn picked_accounts_win_over_bindings() {
+        let family = account(3, "Family Fund", "BYN");
+        // The account first, then the category.
+        let mut d = draft();
+        d.pick_account(account(2, "Hanna Daily", "USD"));
+        d.pick_category(utilities(), Some(family.clone()));
+        assert_eq!((4, 2), (d.category.id, d.account.id));
+
+        // The category first, then the account: the account stays picked,
+        // also through later category picks.
+        let mut d = draft();
+        d.pick_category(utilities(), Some(family.clone()));
+        assert_eq!(3, d.account.id);
+        d.pick_account(draft().account);
+        assert_eq!((4, 1), (d.category.id, d.account.id));
+        d.pick_category(utilities(), Some(family.clone()));
+        assert_eq!(1, d.account.id);
+
+        // Picking the bound account itself counts as picking it.
+        let mut d = draft();
+        d.pick_account(family.clone());
+        d.pick_category(utilities(), Some(account(2, "Hanna Daily", "USD")));
+        assert_eq!(3, d.account.id);
+
+        // A stored expense being edited keeps its account.
+        let mut d = draft();
+        d.expense_id = Some(1);
+        d.account_explicit = true;
+        d.pick_category(utilities(), Some(family));
+        assert_eq!(1, d.account.id);
+    }
+
+    #[test]
+    fn bound_accounts_take_the_original_amount() {
+        let mut d = draft();
+        d.amount = 0.0;
+        d.original = Some(crate::model::OriginalAmount {
+            amount: 40.0,
+            currency: "BYN".to_string(),
+            exponent: 2,
+        });
+        d.pick_category(utilities(), Some(account(3, "Family Fund", "BYN")));
+        assert_eq!((40.0, None), (d.amount, d.original.clone()));
+    }
+
+    #[test]
+    fn nudged_amounts() {
+        let limits = AmountLimits::default();
+        let mut d = draft();
+        d.amount = 12.3;
+        d.amount_calculation = Some("10+2.3".to_string());
+        // A tenth at a time, thirty times, lands exactly.
+        for _ in 0..30 {
+            d.nudge(10, &limits).unwrap();
+        }
+        assert_eq!((15.3, None), (d.amount, d.amount_calculation.clone()));
+        d.nudge(-100, &limits).unwrap();
+        assert_eq!(14.3, d.amount);
+
+        // Down to a cent and no further.
+        d.nudge(-10_000, &limits).unwrap();
+        assert_eq!(0.01, d.amount);
+        d.nudge(-10, &limits).unwrap();
+        assert_eq!(0.01, d.amount);
+        d.nudge(10, &limits).unwrap();
+        assert_eq!(0.11, d.amount);
+
+        d.amount = limits.max;
+        assert_eq!(
+            Err(crate::model::AmountError::TooLarge(limits.max).to_string()),
+            d.nudge(100, &limits)
+        );
+        assert_eq!(limits.max, d.amount);
+
+        d.account.exponent = 0;
+        d.amount = 5.0;
+        d.nudge(-10, &limits).unwrap();
+        assert_eq!(1.0, d.amount);
+    }
+
+    #[test]
+    fn apply_edits() {
+        const UTC: (Locale, Tz) = (Locale::DecimalPoint, Tz::UTC);
+        let limits = AmountLimits::default();
+        let mut d = draft();
+        d.apply_edit(Field::Amount, "12.5", &limits, UTC).unwrap();
+        assert_eq!(12.5, d.amount);
+        assert!(d.apply_edit(Field::Amount, "twelve", &limits, UTC).is_err());
+        assert_eq!(
+            Err("The amount must be a finite number.".to_string()),
+            d.apply_edit(Field::Amount, "inf", &limits, UTC)
+        );
+        assert_eq!(12.5, d.amount);
+
+        d.apply_edit(
+            Field::Amount,
+            "1.234",
+            &limits,
+            (Locale::DecimalComma, Tz::UTC),
+        )
+        .unwrap();
+        assert_eq!((1234.0, Some(1.234)), (d.amount, d.amount_alternative));
+        d.apply_edit(
+            Field::Amount,
+            "12,5",
+            &limits,
+            (Locale::DecimalComma, Tz::UTC),
+        )
+        .unwrap();
+        assert_eq!((12.5, None), (d.amount, d.amount_alternative));
+        d.apply_edit(Field::Amount, "3*4.5", &limits, UTC).unwrap();
+        assert_eq!(
+            (13.5, Some("3*4.5")),
+            (d.amount, d.amount_calculation.as_deref())
+        );
+        d.apply_edit(Field::Amount, "13", &limits, UTC).unwrap();
+        assert_eq!(None, d.amount_calculation);
+
+        d.apply_edit(Field::Timestamp, "2024-01-02 03:04", &limits, UTC)
+            .unwrap();
+        assert_eq!("2024-01-02T03:04:00+00:00", d.timestamp.to_rfc3339());
+        d.apply_edit(
+            Field::Timestamp,
+            "2024-01-02",
+            &limits,
+            (Locale::DecimalPoint, Tz::Europe__Berlin),
+        )
+        .unwrap();
+        assert_eq!("2024-01-01T23:00:00+00:00", d.timestamp.to_rfc3339());
+        assert!(d
+            .apply_edit(Field::Timestamp, "tomorrow", &limits, UTC)
+            .is_err());
+
+        d.apply_edit(Field::Comment, " lunch ", &limits, UTC)
+            .unwrap();
+        assert_eq!(Some("lunch".to_string()), d.comment);
+        d.apply_edit(Field::Comment, "-", &limits, UTC).unwrap();
+        assert_eq!(None, d.comment);
+        assert_eq!(
+            Err("Comment too long (1001/1000 chars).".to_string()),
+            d.apply_edit(Field::Comment, &"x".repeat(1001), &limits, UTC)
+        );
+        assert_eq!(None, d.comment);
+
+        d.location = Some((52.52, 13.405));
+        assert_eq!(
+            Err("Share a location with 📎 → Location, or send - to remove it.".to_string()),
+            d.apply_edit(Field::Location, "Berlin", &limits, UTC)
+        );
+        assert_eq!(Some((52.52, 13.405)), d.location);
+        d.apply_edit(Field::Location, "-", &limits, UTC).unwrap();
+        assert_eq!(None, d.location);
+    }
+
+    #[test]
+    fn templates_change_the_comment_once_filled() {
+        let fuel = CommentTemplate::parse("fuel", "fuel {liters}L at {station}").unwrap();
+        let mut d = draft();
+        d.comment = Some("old".to_string());
+        assert_eq!(
+            Some("liters? (1 of 2, /cancel to keep the comment as it was)".to_string()),
+            d.start_template(fuel.clone())
+        );
+        assert_eq!(
+            Ok(Some(
+                "station? (2 of 2, /cancel to keep the comment as it was)".to_string()
+            )),
+            d.answer_template("40")
+        );
+        assert!(d.answer_template(" ").is_err());
+        // Still waiting for the station, the comment as it was.
+        assert!(d.template_fill.is_some());
+        assert_eq!(Some("old"), d.comment.as_deref());
+        assert_eq!(Ok(None), d.answer_template("Shell"));
+        assert_eq!(Some("fuel 40L at Shell"), d.comment.as_deref());
+        assert_eq!(None, d.template_fill);
+        assert!(d.answer_template("more").is_err());
+
+        // Cancelling midway drops the fill only.
+        d.start_template(fuel);
+        d.answer_template("55").unwrap();
+        d.template_fill = None;
+        assert_eq!(Some("fuel 40L at Shell"), d.comment.as_deref());
+
+        let plain = CommentTemplate::parse("bus", "bus").unwrap();
+        assert_eq!(None, d.start_template(plain));
+        assert_eq!(Some("bus"), d.comment.as_deref());
+    }
+
+    #[test]
+    fn journaled_drafts_record_their_messages() {
+        let model = Arc::new(Mutex::new(crate::model::Model::new(true)));
+        let drafts = Drafts::journaled(model.clone());
+        let key = |message_id| DraftKey {
+            chat_id: ChatId(-100),
+            message_id: MessageId(message_id),
+        };
+        drafts.insert(key(10), draft());
+        drafts.insert(key(11), draft());
+        drafts.insert_batch(key(12), Vec::new());
+        drafts.remove(key(10));
+        drafts.remove_batch(key(12));
+
+        let open = model
+            .lock()
+            .unwrap()
+            .take_draft_messages(Utc::now() + TimeDelta::seconds(1))
+            .unwrap();
+        assert_eq!(vec![(-100, 11)], open);
+    }
+
+    #[test]
+    fn removing_draft_drops_its_pending_edits() {
+        let drafts = Drafts::default();
+        let key = DraftKey {
+            chat_id: ChatId(1),
+            message_id: MessageId(10),
+        };
+        drafts.insert(key, draft());
+        drafts.set_pending(ChatId(1), UserId(1001), pending(key));
+
+        assert!(drafts.remove(key).is_some());
+        assert_eq!(
+            None,
+            drafts.take_pending(ChatId(1), UserId(1001), answer(None, true, 0))
+        );
+    }
+
+    #[test]
+    fn new_prompt_replaces_the_outstanding_one() {
+        let drafts = Drafts::default();
+        let first = pending(key());
+        let second = PendingEdit {
+            field: Field::Comment,
+            prompt: MessageId(12),
+            ..first
+        };
+        assert_eq!(None, drafts.set_pending(ChatId(1), UserId(1001), first));
+        assert_eq!(
+            Some(first),
+            drafts.set_pending(ChatId(1), UserId(1001), second)
+        );
+        assert_eq!(
+            Some(second),
+            drafts.take_pending(ChatId(1), UserId(1001), answer(Some(12), false, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_changes_are_serialized() {
+        let drafts = Drafts::default();
+        drafts.insert(key(), draft());
+        let renders = Mutex::new(Vec::new());
+        let category = |id, name: &str| Category {
+            id,
+            name: name.to_string(),
+            icon: None,
+            require_comment: false,
+            parent_id: None,
+        };
+
+        // Like the SetCategory handler: the lookups and the re-render are
+        // awaited, and another tap on the keyboard can come in meanwhile.
+        // The first re-render is slower, as requests to Telegram can be.
+        let set_category = |category: Category, render_delay: usize| {
+            let (drafts, renders) = (&drafts, &renders);
+            async move {
+                let _guard = drafts.lock(key()).await;
+                tokio::task::yield_now().await;
+                let (_, draft) = drafts.update(key(), |d| d.category = category).unwrap();
+                for _ in 0..render_delay {
+                    tokio::task::yield_now().await;
+                }
+                renders.lock().unwrap().push(draft);
+            }
+        };
+        tokio::join!(
+            set_category(category(2, "Transport"), 3),
+            set_category(category(3, "Eating out"), 1),
+        );
+
+        let renders = renders.into_inner().unwrap();
+        let shown: Vec<&str> = renders.iter().map(|d| d.category.name.as_str()).collect();
+        assert_eq!(vec!["Transport", "Eating out"], shown);
+        assert_eq!(Some(&renders[1]), drafts.get(key()).as_ref());
+    }
+
+    fn key() -> DraftKey {
+        DraftKey {
+            chat_id: ChatId(1),
+            message_id: MessageId(10),
+        }
+    }
+
+    fn pending(key: DraftKey) -> PendingEdit {
+        PendingEdit {
+            draft: key,
+            field: Field::Amount,
+            prompt: MessageId(11),
+            asked_at: draft().timestamp,
+        }
+    }
+
+    /// A message sent `minutes` after the prompt.
+    fn answer(reply_to: Option<i32>, private: bool, minutes: i64) -> Answer {
+        Answer {
+            reply_to: reply_to.map(MessageId),
+            private,
+            sent_at: pending(key()).asked_at + TimeDelta::minutes(minutes),
+            location: false,
+        }
+    }
+
+    /// A location shared `minutes` after the prompt.
+    fn located(reply_to: Option<i32>, private: bool, minutes: i64) -> Answer {
+        Answer {
+            location: true,
+            ..answer(reply_to, private, minutes)
+        }
+    }
+
+    #[test]
+    fn location_prompts_only_take_locations() {
+        let drafts = Drafts::default();
+        let (chat, user) = (ChatId(1), UserId(1001));
+        let location = PendingEdit {
+            field: Field::Location,
+            ..pending(key())
+        };
+
+        // Text for the 📍 button of a draft is answered with how to share.
+        drafts.insert(key(), draft());
+        drafts.set_pending(chat, user, location);
+        assert_eq!(
+            Some(location),
+            drafts.take_pending(chat, user, answer(None, true, 1))
+        );
+        drafts.set_pending(chat, user, location);
+        assert_eq!(
+            Some(location),
+            drafts.take_pending(chat, user, located(None, true, 1))
+        );
+
+        // Other prompts don't take locations, and keep waiting.
+        drafts.set_pending(chat, user, pending(key()));
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, located(None, true, 1))
+        );
+        assert_eq!(
+            Some(pending(key())),
+            drafts.take_pending(chat, user, answer(None, true, 1))
+        );
+    }
+
+    #[test]
+    fn committed_expenses_take_a_location_replying_to_them() {
+        let drafts = Drafts::default();
+        let (chat, user) = (ChatId(1), UserId(1001));
+        // Asked by the confirmation of the committed draft itself.
+        let location = PendingEdit {
+            field: Field::Location,
+            prompt: key().message_id,
+            ..pending(key())
+        };
+
+        drafts.set_pending(chat, user, location);
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, located(Some(5), false, 1))
+        );
+        assert_eq!(
+            Some(location),
+            drafts.take_pending(chat, user, located(Some(10), false, 30))
+        );
+
+        // Typing the next expense instead drops the prompt.
+        drafts.set_pending(chat, user, location);
+        assert_eq!(None, drafts.take_pending(chat, user, answer(None, true, 1)));
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, located(None, true, 2))
+        );
+    }
+
+    #[test]
+    fn group_chats_need_a_reply_to_the_prompt() {
+        let drafts = Drafts::default();
+        let (chat, user) = (ChatId(1), UserId(1001));
+        drafts.set_pending(chat, user, pending(key()));
+
+        // Unrelated messages leave the prompt waiting, however late.
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, answer(None, false, 1))
+        );
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, answer(Some(5), false, 1))
+        );
+        assert_eq!(
+            Some(pending(key())),
+            drafts.take_pending(chat, user, answer(Some(11), false, 60))
+        );
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, answer(Some(11), false, 61))
+        );
+    }
+
+    #[test]
+    fn private_chats_take_the_next_message_until_it_expires() {
+        let drafts = Drafts::default();
+        let (chat, user) = (ChatId(1), UserId(1001));
+
+        drafts.set_pending(chat, user, pending(key()));
+        assert_eq!(
+            Some(pending(key())),
+            drafts.take_pending(chat, user, answer(None, true, 5))
+        );
+
+        drafts.set_pending(chat, user, pending(key()));
+        assert_eq!(None, drafts.take_pending(chat, user, answer(None, true, 6)));
+        // Expired prompts are gone, even for a late reply.
+        assert_eq!(
+            None,
+            drafts.take_pending(chat, user, answer(Some(11), true, 7))
+        );
+
+        drafts.set_pending(chat, user, pending(key()));
+        assert_eq!(
+            Some(pending(key())),
+            drafts.take_pending(chat, user, answer(Some(11), true, 30))
+        );
+
+        drafts.set_pending(chat, user, pending(key()));
+        drafts.clear_pending(chat, user);
+        assert_eq!(None, drafts.take_pending(chat, user, answer(None, true, 0)));
+    }
+
+    #[test]
+    fn idempotency_key_names_the_message() {
+        assert_eq!("draft:1:10", key().idempotency_key());
+        let group = DraftKey {
+            chat_id: ChatId(-100123),
+            message_id: MessageId(7),
+        };
+        assert_eq!("draft:-100123:7", group.idempotency_key());
+    }
+}
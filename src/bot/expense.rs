@@ -0,0 +1,1902 @@
+use super::auth::{self, Access};
+use super::callback::{self, CallbackData, Field, Page, Resolved};
+use super::draft::{ActiveTransaction, Answer, DraftKey, PendingEdit, SharedDrafts};
+use super::feed::{self, SharedFeeds};
+use super::format::RenderOptions;
+use super::in_flight::{self, InFlightGuard};
+use super::incoming::{self, SharedHints};
+use super::markdown::escape_md;
+use super::{
+    archive, batch, bulk, favorites, format, intent, keyboards, notify, onboarding, parse,
+    read_only, reconcile, resolve, settings, setup, sources, templates, Bot, HandlerError,
+    HandlerResult, SharedModel,
+};
+use crate::config::Config;
+use crate::model::{
+    check_comment, Account, AmountLimits, Category, ConfirmMode, DailyUsage, Error, ExpenseSource,
+    Inserted, Locale, Model, OriginalAmount, ParsedAmount, Period, Role, SourceMessage, User,
+    MAX_EXPONENT, STATS_WINDOW,
+};
+use chrono::Utc;
+use chrono_tz::Tz;
+use log::*;
+use std::mem::{discriminant, Discriminant};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, Chat, ForceReply, InlineKeyboardMarkup, MessageId};
+use teloxide::{ApiError, RequestError};
+
+/// Buttons of a message being handled, by what they do: a repeated tap is
+/// dropped until the first is done.
+pub type SharedCallbacks = Arc<InFlightGuard<(DraftKey, Discriminant<CallbackData>)>>;
+
+/// Handles a message that isn't a command. A change refused by read-only
+/// mode is answered as such.
+pub async fn handle_message(
+    bot: Bot,
+    message: Message,
+    model: SharedModel,
+    config: Arc<Config>,
+    drafts: SharedDrafts,
+    hints: SharedHints,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let result = run_message(bot.clone(), message, model, config, drafts, hints).await;
+    read_only::answer_message(&bot, chat_id, result).await
+}
+
+async fn run_message(
+    bot: Bot,
+    message: Message,
+    model: SharedModel,
+    config: Arc<Config>,
+    drafts: SharedDrafts,
+    hints: SharedHints,
+) -> HandlerResult {
+    let Some(from) = message.from.as_ref() else {
+        return Ok(());
+    };
+    let tz = config.timezone;
+    let locale = settings::locale(&model, from)?;
+    let Some(text) = message.text() else {
+        if let Some(location) = message.location() {
+            let point = (location.latitude, location.longitude);
+            if share_location(&bot, &message, (&model, &drafts), point, tz).await? {
+                return Ok(());
+            }
+        }
+        return incoming::handle(&bot, &message, &model, &hints).await;
+    };
+
+    let user = match auth::requires(&model, from, message.chat.id, Role::Member)? {
+        Access::Granted(user) => user,
+        Access::Denied(text) => {
+            bot.send_message(message.chat.id, escape_md(&text)).await?;
+            return Ok(());
+        }
+    };
+
+    // A message to an admin's setup in progress answers its question.
+    let reply = setup::answer(
+        &model.lock().unwrap(),
+        message.chat.id.0,
+        user.telegram_id,
+        text,
+    )?;
+    if let Some(reply) = reply {
+        return reply.send(&bot, message.chat.id).await;
+    }
+
+    // A forward from a trusted source is a draft of its defaults; other
+    // forwards are read like typed text.
+    let forwarded = {
+        let model = model.lock().unwrap();
+        let limits = config.amount_limits();
+        match sources::trusted_origin(&model, user.household, &message)? {
+            Some(source) => {
+                let strict_commit = model.get_settings(user.telegram_id)?.strict_commit;
+                let user = (&user, strict_commit);
+                Some(sources::forwarded_draft(
+                    &model,
+                    user,
+                    &source,
+                    text,
+                    (&limits, locale),
+                )?)
+            }
+            None => None,
+        }
+    };
+    if let Some(mut draft) = forwarded {
+        draft.source = source_of(&message);
+        return create_draft(&bot, &drafts, &message.chat, draft, tz).await;
+    }
+
+    let answer = Answer {
+        reply_to: message.reply_to_message().map(|m| m.id),
+        private: message.chat.is_private(),
+        sent_at: message.date,
+        location: false,
+    };
+    if let Some(pending) = drafts.take_pending(message.chat.id, from.id, answer) {
+        let limits = config.amount_limits();
+        let retry = PendingEdit {
+            asked_at: message.date,
+            ..pending
+        };
+        return apply_pending_edit(&bot, &drafts, (retry, from.id), text, &limits, (locale, tz))
+            .await;
+    }
+
+    let limits = config.amount_limits();
+    let (defaults, strict_commit) = {
+        let model = model.lock().unwrap();
+        // The lines of a batch type their currencies each.
+        let typed = match batch::is_batch(text) {
+            true => None,
+            false => typed_code(&model, text, (&limits, locale))?,
+        };
+        let account = preselect_account(
+            &model.accounts(user.household)?,
+            typed.as_deref(),
+            model.chat_assumed_currency(message.chat.id.0)?.as_deref(),
+            model.last_account(user.household, user.telegram_id)?,
+        );
+        let category = model.categories(user.household)?.into_iter().next();
+        let settings = model.get_settings(user.telegram_id)?;
+        (account.zip(category), settings.strict_commit)
+    };
+    let Some(((account, missing_account), category)) = defaults else {
+        bot.send_message(
+            message.chat.id,
+            escape_md("There are no accounts or categories yet, ask the admin to create them."),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    if batch::is_batch(text) {
+        let defaults = (&user, strict_commit, account, category);
+        let deps = (&model, &drafts);
+        return batch::handle_batch(&bot, &message, deps, defaults, text, (&limits, locale)).await;
+    }
+    let typed = read_typed(
+        &model.lock().unwrap(),
+        user.household,
+        &account,
+        text,
+        (&limits, locale),
+    )?;
+    match typed {
+        Ok(typed) => {
+            let mut draft = new_draft((&user, strict_commit), account, category, typed);
+            draft.source = source_of(&message);
+            draft.missing_account = missing_account;
+            create_draft(&bot, &drafts, &message.chat, draft, tz).await
+        }
+        Err(reason) if intent::is_unrecognized(text, &limits, locale) => {
+            let texts = (text, reason.as_str());
+            intent::handle_unrecognized(&bot, &message, &model, &user, texts, (&limits, locale))
+                .await
+        }
+        Err(reason) => {
+            bot.send_message(message.chat.id, escape_md(&reason))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// An expense message read for an account, before it becomes a draft.
+pub(super) struct Typed {
+    pub amount: ParsedAmount,
+    pub comment: Option<String>,
+    /// What was paid in another currency than the account's.
+    pub original: Option<OriginalAmount>,
+    /// The category a rule picked from the comment.
+    pub ruled: Option<Category>,
+}
+
+/// Reads `text` as an amount with a comment for `account`, or the reason it
+/// can't be used. A currency code after the amount makes it the original
+/// amount in that currency.
+pub(super) fn read_typed(
+    model: &Model,
+    household: i64,
+    account: &Account,
+    text: &str,
+    (limits, locale): (&AmountLimits, Locale),
+) -> Result<Result<Typed, String>, Error> {
+    // Decimal places are checked once the currency is known.
+    let (amount, comment) =
+        match parse::parse_expense_message(text, &limits.for_exponent(MAX_EXPONENT), locale) {
+            Ok(parsed) => parsed,
+            Err(reason) => return Ok(Err(reason.to_string())),
+        };
+    if let Err(e) = check_comment(comment.as_deref()) {
+        return Ok(Err(e.to_string()));
+    }
+    let (currency, comment) = typed_currency(model, comment)?;
+    let currency = currency.filter(|(c, _)| !c.eq_ignore_ascii_case(&account.currency));
+    let exponent = currency.as_ref().map_or(account.exponent, |(_, e)| *e);
+    let amount = match limits.for_exponent(exponent).validate_parsed(&amount) {
+        Ok(validated) => ParsedAmount {
+            amount: validated,
+            ..amount
+        },
+        Err(reason) => return Ok(Err(reason.to_string())),
+    };
+    let original = currency.map(|(currency, exponent)| OriginalAmount {
+        amount: amount.amount,
+        currency,
+        exponent,
+    });
+    let ruled = rule_category(model, household, comment.as_deref())?;
+    Ok(Ok(Typed {
+        amount,
+        comment,
+        original,
+        ruled,
+    }))
+}
+
+/// The category of the first rule matching the comment.
+fn rule_category(
+    model: &Model,
+    household: i64,
+    comment: Option<&str>,
+) -> Result<Option<Category>, Error> {
+    let Some(comment) = comment else {
+        return Ok(None);
+    };
+    match model.matching_rule(household, comment)? {
+        Some(rule) => model.get_category(household, rule.category_id),
+        None => Ok(None),
+    }
+}
+
+/// A currency code with its decimal places.
+type TypedCurrency = (String, u32);
+
+/// Splits a leading currency code off the comment, like `USD` of
+/// `13.50 USD taxi`, if it names a known currency.
+fn typed_currency(
+    model: &Model,
+    comment: Option<String>,
+) -> Result<(Option<TypedCurrency>, Option<String>), Error> {
+    let Some(comment) = comment else {
+        return Ok((None, None));
+    };
+    let (word, rest) = match comment.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, Some(rest.trim()).filter(|r| !r.is_empty())),
+        None => (comment.as_str(), None),
+    };
+    if word.len() != 3 || !word.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok((None, Some(comment)));
+    }
+    Ok(match model.currency_exponent(word)? {
+        Some(exponent) => (
+            Some((word.to_ascii_uppercase(), exponent)),
+            rest.map(str::to_string),
+        ),
+        None => (None, Some(comment)),
+    })
+}
+
+/// The currency code typed after the amount of `text`, if it names a known
+/// currency.
+fn typed_code(
+    model: &Model,
+    text: &str,
+    (limits, locale): (&AmountLimits, Locale),
+) -> Result<Option<String>, Error> {
+    let parsed = parse::parse_expense_message(text, &limits.for_exponent(MAX_EXPONENT), locale);
+    let Ok((_, comment)) = parsed else {
+        return Ok(None);
+    };
+    Ok(typed_currency(model, comment)?.0.map(|(code, _)| code))
+}
+
+/// The account a typed expense is drafted on, with the currency the chat
+/// assumes if none of `accounts` is in it. `None` without accounts. In
+/// order of precedence:
+///
+/// 1. A currency typed after the amount is what was paid. Unless it is the
+///    one the chat assumes, the chat's is ignored, and the amount is kept
+///    as the original one on the account picked below.
+/// 2. The currency the chat assumes bare amounts are in picks the first
+///    account in it. Without one the draft is flagged, and the account is
+///    picked as if the chat assumed nothing.
+/// 3. The account the user last logged an expense on, `last_used`.
+/// 4. The household's first account, the default of every chat.
+fn preselect_account(
+    accounts: &[Account],
+    typed: Option<&str>,
+    assumed: Option<&str>,
+    last_used: Option<i64>,
+) -> Option<(Account, Option<String>)> {
+    let assumed = assumed.filter(|a| typed.is_none_or(|t| t.eq_ignore_ascii_case(a)));
+    let mut missing = None;
+    if let Some(assumed) = assumed {
+        match accounts
+            .iter()
+            .find(|a| a.currency.eq_ignore_ascii_case(assumed))
+        {
+            Some(account) => return Some((account.clone(), None)),
+            None => missing = Some(assumed.to_ascii_uppercase()),
+        }
+    }
+    let last = last_used.and_then(|id| accounts.iter().find(|a| a.id == id));
+    last.or(accounts.first()).map(|a| (a.clone(), missing))
+}
+
+/// A new draft of `typed`, in its ruled category or else `category`. An
+/// amount in another currency than the account's becomes the original one,
+/// and the amount charged is 0 until asked for.
+pub(super) fn new_draft(
+    (user, strict_commit): (&User, bool),
+    account: Account,
+    category: Category,
+    typed: Typed,
+) -> ActiveTransaction {
+    let Typed {
+        amount,
+        comment,
+        original,
+        ruled,
+    } = typed;
+    let (amount, original) = match original {
+        Some(original) => (ParsedAmount::default(), Some(original)),
+        None => (amount, None),
+    };
+    let category_by_rule = ruled.is_some();
+    let category = ruled.unwrap_or(category);
+    ActiveTransaction {
+        expense_id: None,
+        amount: amount.amount,
+        amount_alternative: amount.alternative,
+        amount_calculation: amount.calculation.map(|c| c.text),
+        original,
+        account,
+        account_explicit: false,
+        missing_account: None,
+        category,
+        household: user.household,
+        user_id: user.telegram_id,
+        timestamp: Utc::now(),
+        comment,
+        // The first category, until the user picks one. A rule's pick is
+        // on purpose too.
+        category_confirmed: category_by_rule,
+        category_by_rule,
+        strict_commit,
+        split_with: None,
+        location: None,
+        comment_suggestions: Vec::new(),
+        comment_templates: Vec::new(),
+        template_fill: None,
+        source: None,
+        origin: ExpenseSource::Manual,
+        page: Page::Main,
+    }
+}
+
+/// The group message `message` is, for the expense logged from it to link
+/// back to. Private chats have nothing to link.
+pub(super) fn source_of(message: &Message) -> Option<SourceMessage> {
+    (!message.chat.is_private()).then(|| SourceMessage {
+        chat_id: message.chat.id.0,
+        message_id: message.id.0,
+        username: message.chat.username().map(str::to_string),
+    })
+}
+
+/// Shows a new draft, asking for the amount, or the amount charged, if it
+/// awaits one.
+pub(super) async fn create_draft(
+    bot: &Bot,
+    drafts: &SharedDrafts,
+    chat: &Chat,
+    draft: ActiveTransaction,
+    tz: Tz,
+) -> HandlerResult {
+    let sent = bot
+        .send_message(
+            chat.id,
+            format::render_draft(&draft, tz, RenderOptions::FULL),
+        )
+        .reply_markup(keyboards::draft_keyboard(&draft))
+        .await?;
+    let key = DraftKey {
+        chat_id: sent.chat.id,
+        message_id: sent.id,
+    };
+    let awaits_amount = draft.awaits_amount();
+    let draft_user = draft.user_id;
+    let text = prompt(Field::Amount, &draft);
+    drafts.insert(key, draft);
+    if awaits_amount {
+        let from = UserId(draft_user as u64);
+        ask(
+            bot,
+            drafts,
+            key,
+            (from, chat.is_private()),
+            Field::Amount,
+            &text,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Asks `user_id` to type the value of `field`, which their next message
+/// in the chat is then taken for.
+async fn ask(
+    bot: &Bot,
+    drafts: &SharedDrafts,
+    key: DraftKey,
+    (user_id, private): (UserId, bool),
+    field: Field,
+    text: &str,
+) -> HandlerResult {
+    let mut request = bot.send_message(key.chat_id, escape_md(text));
+    if !private {
+        // Group members answer by replying, which this preselects. Not
+        // selectively: that only reaches users the prompt replies to or
+        // mentions, and it does neither. Replies of others aren't taken.
+        request = request.reply_markup(ForceReply::new());
+    }
+    let prompt = request.await?;
+    drafts.set_pending(
+        key.chat_id,
+        user_id,
+        PendingEdit {
+            draft: key,
+            field,
+            prompt: prompt.id,
+            asked_at: prompt.date,
+        },
+    );
+    Ok(())
+}
+
+/// `/add`: creates and commits an expense in one go, no keyboard involved.
+pub async fn handle_add(
+    bot: &Bot,
+    message: &Message,
+    (model, feeds): (&SharedModel, &SharedFeeds),
+    user: &User,
+    config: &Config,
+    args: &str,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let limits = config.amount_limits();
+    let locale = match &message.from {
+        Some(from) => settings::locale(model, from)?,
+        None => Locale::default(),
+    };
+    // Decimal places are checked once the account is known.
+    let args = match parse::parse_add(args, &limits.for_exponent(MAX_EXPONENT), locale) {
+        Ok(args) => args,
+        Err(reason) => {
+            bot.send_message(chat_id, escape_md(&reason)).await?;
+            return Ok(());
+        }
+    };
+
+    let resolved = resolve_add(&model.lock().unwrap(), user.household, &args, &limits)?;
+    let (category, account, amount) = match resolved {
+        Ok(resolved) => resolved,
+        Err(reason) => {
+            bot.send_message(chat_id, escape_md(&reason)).await?;
+            return Ok(());
+        }
+    };
+
+    let draft = ActiveTransaction {
+        expense_id: None,
+        amount,
+        amount_alternative: args.amount.alternative,
+        amount_calculation: args.amount.calculation.map(|c| c.text),
+        original: None,
+        account_explicit: args.account.is_some(),
+        missing_account: None,
+        account,
+        category,
+        household: user.household,
+        user_id: user.telegram_id,
+        timestamp: Utc::now(),
+        comment: args.comment,
+        category_confirmed: true,
+        category_by_rule: false,
+        strict_commit: false,
+        split_with: None,
+        location: None,
+        comment_suggestions: Vec::new(),
+        comment_templates: Vec::new(),
+        template_fill: None,
+        source: source_of(message),
+        origin: ExpenseSource::Manual,
+        page: Page::Main,
+    };
+    // Telegram delivers the command again if the bot died before
+    // acknowledging it, which then finds the expense saved the first time.
+    let key = format!("add:{}:{}", chat_id, message.id.0);
+    let saved = {
+        let model = model.lock().unwrap();
+        model
+            .insert_expense_once(draft.household, &key, &draft.to_new_expense())
+            .and_then(|inserted| match (&draft.source, inserted) {
+                (Some(source), Inserted::New(id)) => model
+                    .set_expense_source(draft.household, id, source)
+                    .map(|()| inserted),
+                _ => Ok(inserted),
+            })
+    };
+    let id = match saved {
+        Ok(Inserted::New(id)) => {
+            notify::expense_logged(bot, model, feeds.reporter(), draft.household, id);
+            id
+        }
+        Ok(Inserted::Existing(id)) => id,
+        Err(e @ (Error::CommentRequired(_) | Error::TooLong { .. })) => {
+            bot.send_message(chat_id, escape_md(&e.to_string())).await?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    feed::changed(bot, model, feeds, (chat_id, draft.household))?;
+    confirm_saved(bot, (chat_id, None), model, (id, &draft), config.timezone).await
+}
+
+/// The category and account of the household named in `/add` arguments,
+/// and the amount checked against the account's currency, calculations
+/// rounded to its decimal places. Without an account the first one is used.
+pub(super) fn resolve_add(
+    model: &Model,
+    household: i64,
+    args: &parse::AddArgs,
+    limits: &AmountLimits,
+) -> Result<resolve::Picked<(Category, Account, f64)>, Error> {
+    let category = resolve::category(model, household, &args.category)?;
+    let bound = match &category {
+        Ok(category) => model.category_account(household, category.id)?,
+        Err(_) => None,
+    };
+    let account = match (&args.account, bound) {
+        (Some(name), _) => resolve::account(model, household, name)?,
+        (None, Some(bound)) => Ok(bound),
+        (None, None) => model
+            .accounts(household)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| "There are no accounts yet.".to_string()),
+    };
+    Ok(category
+        .and_then(|c| account.map(|a| (c, a)))
+        .and_then(|(c, a)| {
+            let amount = limits
+                .for_exponent(a.exponent)
+                .validate_parsed(&args.amount)
+                .map_err(|e| e.to_string())?;
+            Ok((c, a, amount))
+        }))
+}
+
+/// Confirms a committed expense with a button to undo it, in its draft's
+/// message if it had one, or else a new one. Commits are confirmed here
+/// only, so that every one follows the chat's `/settings confirm`.
+pub(super) async fn confirm_saved(
+    bot: &Bot,
+    (chat_id, draft_message): (ChatId, Option<MessageId>),
+    model: &SharedModel,
+    (id, draft): (i64, &ActiveTransaction),
+    tz: Tz,
+) -> HandlerResult {
+    let options = settings::render_options(&model.lock().unwrap(), chat_id)?;
+    // Left out of the chat, not computed at all. Notifications of the
+    // expense go out privately either way.
+    let add_ons: Vec<String> = match options.confirm {
+        ConfirmMode::Full => month_to_date(model, draft, tz, options)?
+            .into_iter()
+            .chain(daily_limit(model, draft, tz, options)?)
+            .collect(),
+        ConfirmMode::Compact | ConfirmMode::Silent => Vec::new(),
+    };
+    let text = format::render_confirmation(draft, &add_ons, draft_message.is_some(), (tz, options));
+    let undo = keyboards::undo_keyboard(id);
+    let message_id = match draft_message {
+        Some(message_id) => {
+            bot.edit_message_text(chat_id, message_id, text)
+                .reply_markup(undo)
+                .await?;
+            message_id
+        }
+        None => bot.send_message(chat_id, text).reply_markup(undo).await?.id,
+    };
+    model
+        .lock()
+        .unwrap()
+        .record_confirmation(chat_id.0, message_id.0, id)?;
+    Ok(())
+}
+
+/// The month-to-date line shown after saving `draft`, unless its user
+/// turned it off.
+fn month_to_date(
+    model: &SharedModel,
+    draft: &ActiveTransaction,
+    tz: Tz,
+    options: RenderOptions,
+) -> Result<Option<String>, Error> {
+    let model = model.lock().unwrap();
+    if !model.get_settings(draft.user_id)?.month_to_date {
+        return Ok(None);
+    }
+    let mtd = model.month_to_date(
+        draft.household,
+        &draft.account.currency,
+        None,
+        Utc::now(),
+        tz,
+    )?;
+    Ok(Some(format::render_month_to_date(
+        &mtd,
+        &draft.category,
+        options,
+    )))
+}
+
+/// The line with the day's new total on the account of the saved `draft`,
+/// if the account has a daily limit.
+fn daily_limit(
+    model: &SharedModel,
+    draft: &ActiveTransaction,
+    tz: Tz,
+    options: RenderOptions,
+) -> Result<Option<String>, Error> {
+    let day = model.lock().unwrap().daily_usage(
+        draft.household,
+        draft.account.id,
+        (draft.timestamp, tz),
+        None,
+    )?;
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    Ok(day.and_then(|day| format::render_daily_limit(draft, &day, today, options)))
+}
+
+/// Takes a location shared by the sender for the prompt it answers: that
+/// of a draft's 📍 button, or of the expense they just committed. `false`
+/// if it answers none.
+async fn share_location(
+    bot: &Bot,
+    message: &Message,
+    (model, drafts): (&SharedModel, &SharedDrafts),
+    point: (f64, f64),
+    tz: Tz,
+) -> Result<bool, HandlerError> {
+    let Some(from) = message.from.as_ref() else {
+        return Ok(false);
+    };
+    let user = match auth::requires(model, from, message.chat.id, Role::Member)? {
+        Access::Granted(user) => user,
+        Access::Denied(_) => return Ok(false),
+    };
+    let answer = Answer {
+        reply_to: message.reply_to_message().map(|m| m.id),
+        private: message.chat.is_private(),
+        sent_at: message.date,
+        location: true,
+    };
+    let Some(pending) = drafts.take_pending(message.chat.id, from.id, answer) else {
+        return Ok(false);
+    };
+    let key = pending.draft;
+    if drafts.get(key).is_some() {
+        let _guard = drafts.lock(key).await;
+        if let Some(((), draft)) = drafts.update(key, |d| d.location = Some(point)) {
+            reshow_draft(bot, drafts, key, &draft, tz).await?;
+            return Ok(true);
+        }
+    }
+    // The draft was committed meanwhile, its expense takes the location.
+    let text = {
+        let model = model.lock().unwrap();
+        let stored = match model.expense_by_key(&key.idempotency_key())? {
+            Some(id) => model.set_expense_location(user.household, id, Some(point)),
+            None => Err(Error::NotFound(key.idempotency_key())),
+        };
+        match stored {
+            Ok(()) => "📍 Added the location to the expense.".to_string(),
+            Err(Error::NotFound(_)) => GONE.to_string(),
+            Err(Error::Refused(reason)) => reason,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    bot.send_message(message.chat.id, escape_md(&text)).await?;
+    Ok(true)
+}
+
+async fn apply_pending_edit(
+    bot: &Bot,
+    drafts: &SharedDrafts,
+    (pending, user_id): (PendingEdit, UserId),
+    text: &str,
+    limits: &AmountLimits,
+    (locale, tz): (Locale, Tz),
+) -> HandlerResult {
+    let key = pending.draft;
+    let _guard = drafts.lock(key).await;
+    let filling = drafts
+        .get(key)
+        .is_some_and(|d| d.template_fill.is_some() && pending.field == Field::Comment);
+    if filling {
+        return answer_template(bot, drafts, (pending, user_id), text, tz).await;
+    }
+    match drafts.update(key, |draft| {
+        draft.apply_edit(pending.field, text, limits, (locale, tz))
+    }) {
+        None => {
+            bot.send_message(key.chat_id, escape_md("This draft is no longer active."))
+                .await?;
+        }
+        Some((Err(reason), _)) => {
+            // Keep waiting for a usable value.
+            drafts.set_pending(key.chat_id, user_id, pending);
+            bot.send_message(key.chat_id, escape_md(&reason)).await?;
+        }
+        Some((Ok(()), draft)) => {
+            reshow_draft(bot, drafts, key, &draft, tz).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Takes `text` as the value of the template blank asked for, asking for
+/// the next one until the comment is complete. `/cancel` stops, leaving the
+/// comment as it was.
+async fn answer_template(
+    bot: &Bot,
+    drafts: &SharedDrafts,
+    (pending, user_id): (PendingEdit, UserId),
+    text: &str,
+    tz: Tz,
+) -> HandlerResult {
+    let key = pending.draft;
+    if text.trim() == templates::CANCEL {
+        if let Some(((), draft)) = drafts.update(key, |d| d.template_fill = None) {
+            reshow_draft(bot, drafts, key, &draft, tz).await?;
+        }
+        return Ok(());
+    }
+    match drafts.update(key, |draft| draft.answer_template(text)) {
+        None => {
+            bot.send_message(key.chat_id, escape_md("This draft is no longer active."))
+                .await?;
+        }
+        Some((Err(reason), _)) => {
+            drafts.set_pending(key.chat_id, user_id, pending);
+            bot.send_message(key.chat_id, escape_md(&reason)).await?;
+        }
+        Some((Ok(Some(prompt)), _)) => {
+            let from = (user_id, key.chat_id.is_user());
+            ask(bot, drafts, key, from, Field::Comment, &prompt).await?;
+        }
+        Some((Ok(None), draft)) => {
+            reshow_draft(bot, drafts, key, &draft, tz).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn show_draft(
+    bot: &Bot,
+    key: DraftKey,
+    draft: &ActiveTransaction,
+    tz: Tz,
+    keyboard: InlineKeyboardMarkup,
+) -> HandlerResult {
+    bot.edit_message_text(
+        key.chat_id,
+        key.message_id,
+        format::render_draft(draft, tz, RenderOptions::FULL),
+    )
+    .reply_markup(keyboard)
+    .await?;
+    Ok(())
+}
+
+/// Shows the draft after an answer to one of its questions. Its message may
+/// be gone by then, deleted in the chat, and the draft with it.
+async fn reshow_draft(
+    bot: &Bot,
+    drafts: &SharedDrafts,
+    key: DraftKey,
+    draft: &ActiveTransaction,
+    tz: Tz,
+) -> HandlerResult {
+    match show_draft(bot, key, draft, tz, keyboards::draft_keyboard(draft)).await {
+        Err(e) if is_message_gone(&e) => {
+            info!("The message of draft {:?} is gone, dropping it", key);
+            drafts.remove(key);
+            bot.send_message(key.chat_id, escape_md("This draft is no longer active."))
+                .await?;
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn is_message_gone(e: &HandlerError) -> bool {
+    matches!(
+        e.downcast_ref::<RequestError>(),
+        Some(RequestError::Api(ApiError::MessageToEditNotFound))
+    )
+}
+
+/// How many comments tapping Comment offers at most.
+const COMMENT_SUGGESTIONS: usize = 5;
+
+fn prompt(field: Field, draft: &ActiveTransaction) -> String {
+    match field {
+        Field::Amount if draft.original.is_some() => {
+            format!("Charged amount in {}?", draft.account.currency)
+        }
+        Field::Amount => "Send the new amount:".to_string(),
+        Field::Timestamp => "Send the new date (YYYY-MM-DD HH:MM):".to_string(),
+        Field::Comment => "Send the new comment (- to clear):".to_string(),
+        Field::Location => "Share the location with 📎 → Location (- to remove it):".to_string(),
+    }
+}
+
+/// Handles a button. A change refused by read-only mode is answered as
+/// such.
+pub async fn handle_callback_query(
+    bot: Bot,
+    q: CallbackQuery,
+    model: SharedModel,
+    config: Arc<Config>,
+    drafts: SharedDrafts,
+    feeds: SharedFeeds,
+    callbacks: SharedCallbacks,
+) -> HandlerResult {
+    let result = run_callback_query(
+        bot.clone(),
+        q.clone(),
+        model,
+        config,
+        drafts,
+        feeds,
+        callbacks,
+    )
+    .await;
+    read_only::answer_callback(&bot, &q, result).await
+}
+
+async fn run_callback_query(
+    bot: Bot,
+    q: CallbackQuery,
+    model: SharedModel,
+    config: Arc<Config>,
+    drafts: SharedDrafts,
+    feeds: SharedFeeds,
+    callbacks: SharedCallbacks,
+) -> HandlerResult {
+    let tz = config.timezone;
+    let data = match q.data.as_deref() {
+        Some(data) => callback::resolve(&model.lock().unwrap(), data, Utc::now())?,
+        None => Resolved::Unknown,
+    };
+    let data = match data {
+        Resolved::Data(data) => Some(data),
+        Resolved::Expired => {
+            bot.answer_callback_query(q.id.clone())
+                .text(callback::EXPIRED)
+                .await?;
+            return Ok(());
+        }
+        Resolved::Unknown => None,
+    };
+    // Settings are open to viewers, everything else changes expenses.
+    let required = match data {
+        // Commands check the role they need themselves.
+        Some(CallbackData::ChangeSetting(_)) | Some(CallbackData::RunCommand(_)) => Role::Viewer,
+        _ => Role::Member,
+    };
+    let chat_id = match &q.message {
+        Some(message) => message.chat().id,
+        None => q.from.id.into(),
+    };
+    let user = match auth::requires(&model, &q.from, chat_id, required)? {
+        Access::Granted(user) => user,
+        Access::Denied(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+            return Ok(());
+        }
+    };
+    if let Some(CallbackData::ChangeSetting(setting)) = data {
+        return settings::handle_change(&bot, &q, &model, setting).await;
+    }
+
+    let (Some(data), Some(message)) = (data, q.regular_message()) else {
+        bot.answer_callback_query(q.id.clone())
+            .text("Unknown action.")
+            .await?;
+        return Ok(());
+    };
+    let key = DraftKey {
+        chat_id: message.chat.id,
+        message_id: message.id,
+    };
+    let Some(_in_flight) = callbacks.start((key, discriminant(&data))) else {
+        bot.answer_callback_query(q.id.clone())
+            .text(in_flight::WORKING)
+            .await?;
+        return Ok(());
+    };
+    match data {
+        CallbackData::Undo(id) => {
+            return delete(&bot, &q, (&model, &feeds), &user, key, id, "↩️ Undone").await
+        }
+        CallbackData::DeleteExpense(id) => {
+            return delete(&bot, &q, (&model, &feeds), &user, key, id, "🗑 Deleted").await
+        }
+        CallbackData::ViewExpense(id) => {
+            if !show_expense(&bot, &model, user.household, key, id, tz).await? {
+                bot.answer_callback_query(q.id.clone()).text(GONE).await?;
+                return Ok(());
+            }
+            bot.answer_callback_query(q.id.clone()).await?;
+            return Ok(());
+        }
+        CallbackData::EditExpense(id) => {
+            return edit_expense(&bot, &q, (&model, &drafts), &user, key, id, tz).await
+        }
+        CallbackData::Recategorize {
+            from,
+            to,
+            range,
+            dry_run,
+        } => {
+            return bulk::confirm_recategorize(
+                &bot,
+                &q,
+                &model,
+                key,
+                (from, to, range, dry_run),
+                tz,
+            )
+            .await
+        }
+        CallbackData::Archive(cutoff) => {
+            let at = (config.db_path.as_path(), tz);
+            return archive::confirm_archive(&bot, &q, &model, key, cutoff, at).await;
+        }
+        CallbackData::RecordAdjustment(id) => {
+            return reconcile::record_adjustment(&bot, &q, &model, key, id).await
+        }
+        CallbackData::Setup(action) => {
+            let user_id = user.telegram_id;
+            return setup::handle_button(&bot, &q, &model, key, user_id, action).await;
+        }
+        CallbackData::UseFavorite(id) => {
+            return favorites::commit(&bot, &q, (&model, &feeds), &user, key.chat_id, id, tz).await
+        }
+        CallbackData::CommitBatch { valid_only } => {
+            let deps = (&model, &drafts, &feeds);
+            return batch::commit(&bot, &q, deps, key, valid_only).await;
+        }
+        CallbackData::CancelBatch => return batch::cancel(&bot, &q, &drafts, key).await,
+        CallbackData::FirstExpense => {
+            let draft = onboarding::first_draft(&model.lock().unwrap(), &user)?;
+            let Some(draft) = draft else {
+                bot.answer_callback_query(q.id.clone())
+                    .text("There are no accounts or categories yet, ask the admin to create them.")
+                    .await?;
+                return Ok(());
+            };
+            create_draft(&bot, &drafts, &message.chat, draft, tz).await?;
+            bot.answer_callback_query(q.id.clone()).await?;
+            return Ok(());
+        }
+        CallbackData::RunCommand(index) => {
+            let deps = (&model, &config, &feeds);
+            return intent::run_suggestion(&bot, &q, message, deps, &user, index).await;
+        }
+        CallbackData::Dismiss => {
+            bot.edit_message_text(key.chat_id, key.message_id, escape_md("✖️ Cancelled"))
+                .await?;
+            bot.answer_callback_query(q.id.clone()).await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    let _guard = drafts.lock(key).await;
+    let Some(draft) = drafts.get(key) else {
+        // The draft may be gone because it was saved and the bot died before
+        // the confirmation went out; the stored key says so.
+        let saved = model
+            .lock()
+            .unwrap()
+            .expense_by_key(&key.idempotency_key())?;
+        let text = match saved {
+            Some(id) => {
+                show_saved(&bot, &model, user.household, key, id, tz).await?;
+                "This expense is already saved."
+            }
+            None => "This draft is no longer active.",
+        };
+        bot.answer_callback_query(q.id.clone()).text(text).await?;
+        return Ok(());
+    };
+
+    // Turning the page of its keyboard leaves the draft as it was, the
+    // value being asked for included.
+    if let CallbackData::ShowPage(page) = data {
+        if let Some(((), draft)) = drafts.update(key, |d| d.page = page) {
+            bot.edit_message_reply_markup(key.chat_id, key.message_id)
+                .reply_markup(keyboards::draft_keyboard(&draft))
+                .await?;
+        }
+        bot.answer_callback_query(q.id.clone()).await?;
+        return Ok(());
+    }
+    // Any other action on a draft abandons the value being asked for, and
+    // the template being filled in with it.
+    let abandoned = drafts.clear_pending(key.chat_id, q.from.id);
+    drafts.update(key, |d| d.template_fill = None);
+    // Tapping Comment offers what the user often writes and their
+    // templates, if anything.
+    let (suggestions, templates) = match data {
+        CallbackData::Edit(Field::Comment) => {
+            let model = model.lock().unwrap();
+            let user_id = q.from.id.0 as i64;
+            (
+                model.frequent_comments(user_id, Some(draft.category.id), COMMENT_SUGGESTIONS)?,
+                model.comment_templates(user_id)?,
+            )
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+    match data {
+        CallbackData::Edit(Field::Comment) if !suggestions.is_empty() || !templates.is_empty() => {
+            let keyboard = keyboards::comment_picker(&suggestions, &templates);
+            drafts.update(key, |d| {
+                d.comment_suggestions = suggestions;
+                d.comment_templates = templates;
+            });
+            bot.edit_message_reply_markup(key.chat_id, key.message_id)
+                .reply_markup(keyboard)
+                .await?;
+        }
+        CallbackData::UseTemplate(i) => {
+            let template = draft.comment_templates.get(i).cloned();
+            let started = template.and_then(|t| drafts.update(key, |d| d.start_template(t)));
+            match started {
+                Some((Some(prompt), _)) => {
+                    let from = (q.from.id, message.chat.is_private());
+                    ask(&bot, &drafts, key, from, Field::Comment, &prompt).await?;
+                }
+                Some((None, draft)) => {
+                    show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+                }
+                None => return no_option(&bot, &q).await,
+            }
+        }
+        CallbackData::Nudge(step) => {
+            let limits = config.amount_limits();
+            match drafts.update(key, |d| d.nudge(step, &limits)) {
+                Some((Ok(()), draft)) => {
+                    show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+                }
+                Some((Err(reason), _)) => {
+                    bot.answer_callback_query(q.id.clone()).text(reason).await?;
+                    return Ok(());
+                }
+                None => {}
+            }
+        }
+        CallbackData::UseComment(i) => {
+            let comment = draft.comment_suggestions.get(i).cloned();
+            let updated = comment.and_then(|c| drafts.update(key, |d| d.comment = Some(c)));
+            let Some((_, draft)) = updated else {
+                return no_option(&bot, &q).await;
+            };
+            show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+        }
+        CallbackData::Edit(_) | CallbackData::TypeComment => {
+            let field = match data {
+                CallbackData::Edit(field) => field,
+                _ => Field::Comment,
+            };
+            if let Some(old) = abandoned {
+                // Otherwise both prompts look like they wait for a value.
+                let notice = escape_md("↪️ Replaced by a newer prompt.");
+                if let Err(e) = bot.edit_message_text(key.chat_id, old.prompt, notice).await {
+                    warn!("Can't mark prompt {} as replaced: {}", old.prompt, e);
+                }
+            }
+            let from = (q.from.id, message.chat.is_private());
+            ask(&bot, &drafts, key, from, field, &prompt(field, &draft)).await?;
+        }
+        CallbackData::PickCategory => {
+            // Going back keeps the category, but now on purpose.
+            drafts.update(key, |d| {
+                d.category_confirmed = true;
+                d.category_by_rule = false;
+            });
+            let (categories, budgets) = {
+                let model = model.lock().unwrap();
+                let calendar = model.calendar(draft.household)?;
+                let month = Period::ThisMonth.resolve(Utc::now(), tz, calendar).start;
+                let mut budgets = model.budgets_with_remaining(draft.household, month, tz)?;
+                budgets.retain(|b| b.currency == draft.account.currency);
+                (model.categories(draft.household)?, budgets)
+            };
+            bot.edit_message_reply_markup(key.chat_id, key.message_id)
+                .reply_markup(keyboards::category_picker(&categories, &budgets))
+                .await?;
+        }
+        CallbackData::PickAccount => {
+            let (accounts, recent) = {
+                let model = model.lock().unwrap();
+                (
+                    model.accounts(draft.household)?,
+                    model.last_expense_per_account(draft.household, 1)?,
+                )
+            };
+            bot.edit_message_reply_markup(key.chat_id, key.message_id)
+                .reply_markup(keyboards::account_picker(&accounts, &recent, tz))
+                .await?;
+        }
+        CallbackData::SetCategory(id) => {
+            let (category, bound) = {
+                let model = model.lock().unwrap();
+                (
+                    model.get_category(draft.household, id)?,
+                    model.category_account(draft.household, id)?,
+                )
+            };
+            let updated = category.and_then(|c| drafts.update(key, |d| d.pick_category(c, bound)));
+            let Some((_, draft)) = updated else {
+                return no_option(&bot, &q).await;
+            };
+            show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+        }
+        CallbackData::SetAccount(id) => {
+            let account = model.lock().unwrap().get_account(draft.household, id)?;
+            let updated = account.and_then(|a| drafts.update(key, |d| d.pick_account(a)));
+            let Some((_, draft)) = updated else {
+                return no_option(&bot, &q).await;
+            };
+            show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+        }
+        CallbackData::PickSplit => {
+            let users: Vec<User> = model
+                .lock()
+                .unwrap()
+                .users(draft.household)?
+                .into_iter()
+                .filter(|u| u.telegram_id != draft.user_id)
+                .collect();
+            if users.is_empty() {
+                bot.answer_callback_query(q.id.clone())
+                    .text("There is nobody to split with.")
+                    .await?;
+                return Ok(());
+            }
+            bot.edit_message_reply_markup(key.chat_id, key.message_id)
+                .reply_markup(keyboards::split_picker(&users))
+                .await?;
+        }
+        CallbackData::SetSplit(id) => {
+            let with =
+                match id {
+                    Some(id) => model.lock().unwrap().get_user(id)?.filter(|u| {
+                        u.household == draft.household && u.telegram_id != draft.user_id
+                    }),
+                    None => None,
+                };
+            if id.is_some() && with.is_none() {
+                return no_option(&bot, &q).await;
+            }
+            if let Some((_, draft)) = drafts.update(key, |d| d.split_with = with) {
+                show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+            }
+        }
+        CallbackData::Back => {
+            // Coming back from a question, the text changes too.
+            show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+        }
+        CallbackData::Commit | CallbackData::CommitAnyway => {
+            if draft.awaits_charge() {
+                let text = format!(
+                    "Send the amount charged in {} first.",
+                    draft.account.currency
+                );
+                bot.answer_callback_query(q.id.clone()).text(text).await?;
+                return Ok(());
+            }
+            if draft.awaits_amount() {
+                bot.answer_callback_query(q.id.clone())
+                    .text("Send the amount first.")
+                    .await?;
+                return Ok(());
+            }
+            // The setting may have changed since the draft was shown.
+            let strict = model
+                .lock()
+                .unwrap()
+                .get_settings(draft.user_id)?
+                .strict_commit;
+            if draft.commit_locked(strict) {
+                if let Some((_, draft)) = drafts
+                    .update(key, |d| d.strict_commit = true)
+                    .filter(|_| !draft.strict_commit)
+                {
+                    show_draft(&bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+                }
+                bot.answer_callback_query(q.id.clone())
+                    .text("Pick a category first.")
+                    .await?;
+                return Ok(());
+            }
+            if data == CallbackData::Commit {
+                // The day as it would be, an edited expense counted with its
+                // new amount only.
+                let day = model.lock().unwrap().daily_usage(
+                    draft.household,
+                    draft.account.id,
+                    (draft.timestamp, tz),
+                    draft.expense_id,
+                )?;
+                if let Some(day) = day
+                    .map(|d| d.with(draft.amount))
+                    .filter(DailyUsage::is_over)
+                {
+                    let confirm = CallbackData::CommitAnyway.encode();
+                    bot.edit_message_text(
+                        key.chat_id,
+                        key.message_id,
+                        format::render_over_limit(&draft, &day, tz),
+                    )
+                    .reply_markup(keyboards::confirm_keyboard(confirm, CallbackData::Back))
+                    .await?;
+                    bot.answer_callback_query(q.id.clone()).await?;
+                    return Ok(());
+                }
+                let stats = model.lock().unwrap().category_stats(
+                    draft.user_id,
+                    draft.category.id,
+                    &draft.account.currency,
+                    STATS_WINDOW,
+                    Utc::now(),
+                )?;
+                if stats.is_unusual(draft.amount) {
+                    let confirm = CallbackData::CommitAnyway.encode();
+                    bot.edit_message_text(
+                        key.chat_id,
+                        key.message_id,
+                        format::render_unusual(&draft, &stats, tz),
+                    )
+                    .reply_markup(keyboards::confirm_keyboard(confirm, CallbackData::Back))
+                    .await?;
+                    bot.answer_callback_query(q.id.clone()).await?;
+                    return Ok(());
+                }
+            }
+            let expense = draft.to_new_expense();
+            let saved = {
+                let model = model.lock().unwrap();
+                // With whether the expense is new, not edited or committed
+                // before.
+                let saved = match draft.expense_id {
+                    Some(id) => model
+                        .update_expense(draft.household, q.from.id.0 as i64, id, &expense)
+                        .map(|()| (id, false)),
+                    None => model
+                        .insert_expense_once(draft.household, &key.idempotency_key(), &expense)
+                        .map(|inserted| match inserted {
+                            Inserted::New(id) => (id, true),
+                            Inserted::Existing(id) => (id, false),
+                        }),
+                };
+                // New expenses are only split on request, edited ones may
+                // stop being split.
+                let split_with = draft.split_with.as_ref().map(|u| u.telegram_id);
+                let saved = saved.and_then(|(id, new)| match (split_with, draft.expense_id) {
+                    (None, None) => Ok((id, new)),
+                    _ => model
+                        .split_expense(draft.household, id, split_with)
+                        .map(|()| (id, new)),
+                });
+                // Likewise for an amount in another currency.
+                let original = draft
+                    .original
+                    .as_ref()
+                    .map(|o| (o.amount, o.currency.as_str()));
+                let saved = saved.and_then(|(id, new)| match (original, draft.expense_id) {
+                    (None, None) => Ok((id, new)),
+                    _ => model
+                        .set_original_amount(draft.household, id, original)
+                        .map(|()| (id, new)),
+                });
+                // And for where it was made.
+                let saved = saved.and_then(|(id, new)| match (draft.location, draft.expense_id) {
+                    (None, None) => Ok((id, new)),
+                    _ => model
+                        .set_expense_location(draft.household, id, draft.location)
+                        .map(|()| (id, new)),
+                });
+                // The message it was typed in is only recorded once.
+                saved.and_then(|(id, new)| match (&draft.source, new) {
+                    (Some(source), true) => model
+                        .set_expense_source(draft.household, id, source)
+                        .map(|()| (id, new)),
+                    _ => Ok((id, new)),
+                })
+            };
+            match saved {
+                // The draft stays open, so that the amount or account can be fixed.
+                Err(Error::InvalidAmount(e)) => {
+                    bot.answer_callback_query(q.id.clone())
+                        .text(e.to_string())
+                        .await?;
+                    return Ok(());
+                }
+                Err(e @ (Error::CommentRequired(_) | Error::TooLong { .. })) => {
+                    bot.answer_callback_query(q.id.clone())
+                        .text(e.to_string())
+                        .await?;
+                    return Ok(());
+                }
+                Err(Error::Refused(reason)) => {
+                    bot.answer_callback_query(q.id.clone()).text(reason).await?;
+                    return Ok(());
+                }
+                Err(Error::NotFound(_)) => {
+                    drafts.remove(key);
+                    bot.edit_message_text(key.chat_id, key.message_id, escape_md(GONE))
+                        .await?;
+                }
+                Err(e) => return Err(e.into()),
+                Ok((id, new)) => {
+                    drafts.remove(key);
+                    if new {
+                        notify::expense_logged(&bot, &model, feeds.reporter(), draft.household, id);
+                    }
+                    feed::changed(&bot, &model, &feeds, (key.chat_id, draft.household))?;
+                    if draft.expense_id.is_some() {
+                        show_expense(&bot, &model, draft.household, key, id, tz).await?;
+                    } else {
+                        if draft.location.is_none() {
+                            // A location shared right after, or in reply to
+                            // the confirmation, is where it was made.
+                            let location = PendingEdit {
+                                draft: key,
+                                field: Field::Location,
+                                prompt: key.message_id,
+                                asked_at: Utc::now(),
+                            };
+                            drafts.set_pending(key.chat_id, q.from.id, location);
+                        }
+                        let message = (key.chat_id, Some(key.message_id));
+                        confirm_saved(&bot, message, &model, (id, &draft), tz).await?;
+                    }
+                }
+            }
+        }
+        CallbackData::Cancel => {
+            drafts.remove(key);
+            // Cancelling an edit goes back to the expense as it is stored.
+            let shown = match draft.expense_id {
+                Some(id) => show_expense(&bot, &model, draft.household, key, id, tz).await?,
+                None => false,
+            };
+            if !shown {
+                bot.edit_message_text(key.chat_id, key.message_id, escape_md("✖️ Cancelled"))
+                    .await?;
+            }
+        }
+        CallbackData::Undo(_)
+        | CallbackData::ViewExpense(_)
+        | CallbackData::EditExpense(_)
+        | CallbackData::DeleteExpense(_)
+        | CallbackData::Recategorize { .. }
+        | CallbackData::Dismiss
+        | CallbackData::ChangeSetting(_)
+        | CallbackData::UseFavorite(_)
+        | CallbackData::Archive(_)
+        | CallbackData::Setup(_)
+        | CallbackData::RecordAdjustment(_)
+        | CallbackData::CommitBatch { .. }
+        | CallbackData::CancelBatch
+        | CallbackData::FirstExpense
+        | CallbackData::ShowPage(_)
+        | CallbackData::RunCommand(_) => unreachable!("handled above"),
+    }
+
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+/// Answers a button naming an option the draft doesn't have, or no
+/// longer, leaving the draft as it is.
+async fn no_option(bot: &Bot, q: &CallbackQuery) -> HandlerResult {
+    bot.answer_callback_query(q.id.clone())
+        .text(callback::NO_OPTION)
+        .await?;
+    Ok(())
+}
+
+/// Replaces a stale draft with the confirmation of the expense it was
+/// saved as.
+async fn show_saved(
+    bot: &Bot,
+    model: &SharedModel,
+    household: i64,
+    key: DraftKey,
+    id: i64,
+    tz: Tz,
+) -> HandlerResult {
+    let (expense, options) = {
+        let model = model.lock().unwrap();
+        (
+            model.get_expense_details(household, id)?,
+            settings::render_options(&model, key.chat_id)?,
+        )
+    };
+    let Some(expense) = expense else {
+        return Ok(());
+    };
+    bot.edit_message_text(
+        key.chat_id,
+        key.message_id,
+        format!(
+            "✅ Saved\n{}",
+            format::render_expense(&expense, tz, options)
+        ),
+    )
+    .reply_markup(keyboards::undo_keyboard(id))
+    .await?;
+    Ok(())
+}
+
+/// Answer to actions on an expense that was deleted meanwhile.
+const GONE: &str = "This expense no longer exists.";
+
+/// Only the user who logged an expense or an admin of its household may
+/// change it.
+fn may_modify(user: &User, owner: i64) -> bool {
+    owner == user.telegram_id || user.role == Role::Admin
+}
+
+/// Replaces the message with the view of an expense, `false` if the
+/// expense is gone. Chats that mask amounts mask it too.
+async fn show_expense(
+    bot: &Bot,
+    model: &SharedModel,
+    household: i64,
+    key: DraftKey,
+    id: i64,
+    tz: Tz,
+) -> Result<bool, HandlerError> {
+    let (expense, options) = {
+        let model = model.lock().unwrap();
+        (
+            model.get_expense_details(household, id)?,
+            settings::render_options(&model, key.chat_id)?,
+        )
+    };
+    let Some(expense) = expense else {
+        return Ok(false);
+    };
+    bot.edit_message_text(
+        key.chat_id,
+        key.message_id,
+        format::render_expense(&expense, tz, options),
+    )
+    .reply_markup(keyboards::expense_keyboard(id, expense.location))
+    .await?;
+    Ok(true)
+}
+
+/// Turns the message into a draft of a stored expense.
+async fn edit_expense(
+    bot: &Bot,
+    q: &CallbackQuery,
+    (model, drafts): (&SharedModel, &SharedDrafts),
+    user: &User,
+    key: DraftKey,
+    id: i64,
+    tz: Tz,
+) -> HandlerResult {
+    let _guard = drafts.lock(key).await;
+    let household = user.household;
+    let draft = {
+        let model = model.lock().unwrap();
+        match model.get_expense_details(household, id)? {
+            None => Err(GONE),
+            Some(e) if !may_modify(user, e.user_id) => {
+                Err("Only the author can change this expense.")
+            }
+            Some(e) => {
+                let account = model.get_account(household, e.account_id)?;
+                let category = model.get_category(household, e.category_id)?;
+                let split_with = match e.split_user_id {
+                    Some(id) => model.get_user(id)?,
+                    None => None,
+                };
+                let strict_commit = model.get_settings(e.user_id)?.strict_commit;
+                match account.zip(category) {
+                    Some((account, category)) => Ok(ActiveTransaction {
+                        expense_id: Some(id),
+                        amount: e.amount,
+                        amount_alternative: None,
+                        amount_calculation: None,
+                        original: e.original,
+                        account,
+                        // Saved from it on purpose.
+                        account_explicit: true,
+                        missing_account: None,
+                        category,
+                        household,
+                        user_id: e.user_id,
+                        timestamp: e.timestamp,
+                        comment: e.comment,
+                        category_confirmed: e.category_confirmed,
+                        category_by_rule: false,
+                        strict_commit,
+                        split_with,
+                        location: e.location,
+                        comment_suggestions: Vec::new(),
+                        comment_templates: Vec::new(),
+                        template_fill: None,
+                        // Edits never move where it was logged, nor
+                        // change how.
+                        source: None,
+                        origin: ExpenseSource::Manual,
+                        page: Page::Main,
+                    }),
+                    None => Err("The account or category of this expense no longer exists."),
+                }
+            }
+        }
+    };
+
+    match draft {
+        Ok(draft) => {
+            show_draft(bot, key, &draft, tz, keyboards::draft_keyboard(&draft)).await?;
+            drafts.insert(key, draft);
+            bot.answer_callback_query(q.id.clone()).await?;
+        }
+        Err(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes an expense from its confirmation or detail message, replacing
+/// the message with `done`.
+async fn delete(
+    bot: &Bot,
+    q: &CallbackQuery,
+    (model, feeds): (&SharedModel, &SharedFeeds),
+    user: &User,
+    key: DraftKey,
+    id: i64,
+    done: &str,
+) -> HandlerResult {
+    let answer = {
+        let model = model.lock().unwrap();
+        match model.expense_user_id(user.household, id)? {
+            None => Some(GONE),
+            Some(owner) if !may_modify(user, owner) => {
+                Some("Only the author can change this expense.")
+            }
+            Some(_) => {
+                model.delete_expense(user.household, id)?;
+                None
+            }
+        }
+    };
+
+    match answer {
+        Some(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+        }
+        None => {
+            feed::changed(bot, model, feeds, (key.chat_id, user.household))?;
+            bot.edit_message_text(key.chat_id, key.message_id, escape_md(done))
+                .await?;
+            bot.answer_callback_query(q.id.clone()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::recording::{self, Call, RecordingBot};
+    use crate::bot::reporter::ErrorReporter;
+    use crate::bot::Feeds;
+    use crate::model::{ExpenseDetails, IntegrityReport, Model};
+    use chrono_tz::Tz;
+    use std::sync::Mutex;
+
+    /// Callback data no keyboard of the bot makes: ids out of range, in
+    /// other spellings, of rows that don't exist, or no data at all.
+    const HOSTILE: &[&str] = &[
+        "",
+        ":",
+        "::::",
+        "set_category:0",
+        "set_category:-1",
+        "set_category:+1",
+        "set_category:01",
+        "set_category:99999",
+        "set_account:-3",
+        "set_account:9223372036854775808",
+        "set_account:9223372036854775807",
+        "split:-1002",
+        "split:1001",
+        "split:99999",
+        "comment:99",
+        "comment:-1",
+        "template:18446744073709551615",
+        "nudge:-9223372036854775808",
+        "nudge:9223372036854775807",
+        "undo:0",
+        "undo:-1",
+        "undo:99999",
+        "view_expense:-4",
+        "edit_expense:0",
+        "edit_expense:99999",
+        "delete_expense:-4",
+        "delete_expense:+4",
+        "delete_expense: 4",
+        "delete_expense:４",
+        "delete_expense:99999",
+        "recat:1:-2",
+        "recat:-1:2",
+        "recat:1:99999",
+        "dryrecat:0:1",
+        "fav:-1",
+        "fav:99999",
+        "adjust:-1",
+        "adjust:99999",
+        "archive:99999999",
+        "setup:preset:99",
+        "setup:toggle:-1",
+        "run:99",
+        "run:-1",
+        "setting:mtd:maybe",
+        "cb:",
+        "cb:nothing",
+        "batch:all",
+        "💥",
+        "undo:1\0",
+    ];
+
+    /// What a press mustn't change: every table's rows, the expenses and
+    /// their categories and accounts.
+    fn state(model: &Model) -> (IntegrityReport, Vec<Option<ExpenseDetails>>) {
+        let expenses = (1..=4)
+            .map(|id| model.get_expense_details(1, id).unwrap())
+            .collect();
+        (model.check_integrity().unwrap(), expenses)
+    }
+
+    /// The callback handler with what it needs, and a draft under message
+    /// 7 of Alex's chat.
+    struct Harness {
+        model: SharedModel,
+        config: Arc<Config>,
+        drafts: SharedDrafts,
+        feeds: Arc<Feeds>,
+        callbacks: SharedCallbacks,
+        recording: RecordingBot,
+        alex: teloxide::types::User,
+        message: Message,
+        key: DraftKey,
+    }
+
+    impl Harness {
+        fn new(name: &str) -> Harness {
+            let model = Model::new(true);
+            model.fill_test_data();
+            let config = Arc::new(Config {
+                admin_id: None,
+                base_currency: "EUR".to_string(),
+                timezone: Tz::UTC,
+                max_amount: 1_000_000.0,
+                review_comment_over: 100.0,
+                max_messages: 3,
+                rate_stale_days: 14,
+                audit_retention_days: 365,
+                payload_retention_days: 2,
+                db_path: std::env::temp_dir().join(format!("{}.db", name)),
+                db_migrate_from: None,
+            });
+            let (reporter, _reports) = ErrorReporter::new();
+            let recording = RecordingBot::start();
+            let alex = recording::user(1001);
+            let message = recording::message(&alex, 7);
+            let key = DraftKey {
+                chat_id: message.chat.id,
+                message_id: message.id,
+            };
+            let drafts: SharedDrafts = Arc::default();
+            drafts.insert(key, crate::bot::draft::tests::draft());
+            Harness {
+                model: Arc::new(Mutex::new(model)),
+                config,
+                drafts,
+                feeds: Arc::new(Feeds::new(Tz::UTC, reporter)),
+                callbacks: Arc::default(),
+                recording,
+                alex,
+                message,
+                key,
+            }
+        }
+
+        /// Presses a button with `data` under the draft, and returns the
+        /// requests that made.
+        async fn press(&self, data: &str) -> Vec<Call> {
+            let q = recording::press(&self.alex, &self.message, data);
+            handle_callback_query(
+                self.recording.bot.clone(),
+                q,
+                self.model.clone(),
+                self.config.clone(),
+                self.drafts.clone(),
+                self.feeds.clone(),
+                self.callbacks.clone(),
+            )
+            .await
+            .unwrap_or_else(|e| panic!("{}: {}", data, e));
+            self.recording.take()
+        }
+    }
+
+    #[tokio::test]
+    async fn hostile_callback_data() {
+        // A draft under the message, so that its pickers are reached too.
+        let h = Harness::new("hostile-callbacks");
+        let before = state(&h.model.lock().unwrap());
+
+        for data in HOSTILE {
+            let calls = h.press(data).await;
+            let answers: Vec<&str> = calls
+                .iter()
+                .filter(|c| c.method == "answerCallbackQuery")
+                .map(|c| c.body.as_str())
+                .collect();
+            assert_eq!(1, answers.len(), "{}: {:?}", data, calls);
+            assert_eq!(before, state(&h.model.lock().unwrap()), "{}", data);
+        }
+
+        for data in [
+            "set_category:99999",
+            "split:1001",
+            "comment:99",
+            "fav:99999",
+        ] {
+            let calls = h.press(data).await;
+            assert_eq!(1, calls.len(), "{}: {:?}", data, calls);
+            assert!(calls[0].body.contains(callback::NO_OPTION), "{}", data);
+        }
+        // Nudged, but still on its category and account.
+        let draft = h.drafts.get(h.key).unwrap();
+        assert_eq!(
+            (1, 1, None),
+            (draft.category.id, draft.account.id, draft.split_with)
+        );
+    }
+
+    /// Typed currency, assumed one, last used account: the account the
+    /// draft starts on and the currency it lacks an account in.
+    type Row = (
+        Option<&'static str>,
+        Option<&'static str>,
+        Option<i64>,
+        i64,
+        Option<&'static str>,
+    );
+
+    #[test]
+    fn account_precedence() {
+        let account = |id, currency: &str| Account {
+            id,
+            name: format!("Account {}", id),
+            currency: currency.to_string(),
+            exponent: 2,
+        };
+        let accounts = [account(1, "EUR"), account(2, "USD"), account(3, "BYN")];
+        let table: &[Row] = &[
+            // The household's first account, by default.
+            (None, None, None, 1, None),
+            // The last used one beats it, unless it is gone.
+            (None, None, Some(3), 3, None),
+            (None, None, Some(99), 1, None),
+            // The assumed currency beats both.
+            (None, Some("USD"), Some(3), 2, None),
+            (None, Some("usd"), None, 2, None),
+            // Without an account in it, it is flagged.
+            (None, Some("GBP"), Some(3), 3, Some("GBP")),
+            (None, Some("GBP"), None, 1, Some("GBP")),
+            // A typed currency beats the assumed one, unless it is the same.
+            (Some("EUR"), Some("USD"), Some(3), 3, None),
+            (Some("BYN"), Some("USD"), None, 1, None),
+            (Some("USD"), Some("USD"), Some(3), 2, None),
+            (Some("GBP"), Some("GBP"), None, 1, Some("GBP")),
+            (Some("GBP"), None, Some(2), 2, None),
+        ];
+        for &(typed, assumed, last_used, id, missing) in table {
+            let picked = preselect_account(&accounts, typed, assumed, last_used)
+                .map(|(account, missing)| (account.id, missing));
+            assert_eq!(
+                Some((id, missing.map(str::to_string))),
+                picked,
+                "{:?}",
+                (typed, assumed, last_used)
+            );
+        }
+        assert_eq!(None, preselect_account(&[], None, Some("USD"), Some(1)));
+
+        let model = Model::new(true);
+        model.fill_test_data();
+        let limits = AmountLimits {
+            max: 1_000_000.0,
+            decimals: 2,
+        };
+        let typed = |text| typed_code(&model, text, (&limits, Locale::DecimalPoint)).unwrap();
+        assert_eq!(Some("USD".to_string()), typed("13.50 usd taxi"));
+        assert_eq!(None, typed("13.50 taxi"));
+        assert_eq!(None, typed("13.50 xyz"));
+        assert_eq!(None, typed("taxi USD"));
+    }
+
+    #[tokio::test]
+    async fn pages_survive_edits() {
+        let h = Harness::new("draft-pages");
+        // The keyboard sent last, told apart by the button turning its page.
+        let page = |calls: &[Call]| {
+            let markup = calls
+                .iter()
+                .rev()
+                .find(|c| c.method.starts_with("editMessage"))
+                .map(|c| c.body.as_str())
+                .unwrap_or_default();
+            match (markup.contains("page:more"), markup.contains("page:main")) {
+                (true, false) => Some(Page::Main),
+                (false, true) => Some(Page::More),
+                _ => None,
+            }
+        };
+
+        let asked = h.press("edit:comment").await;
+        assert!(asked.iter().any(|c| c.method == "sendMessage"));
+        assert_eq!(Some(Page::More), page(&h.press("page:more").await));
+        // Turning the page doesn't abandon the comment being asked for.
+        assert!(h.drafts.clear_pending(h.key.chat_id, h.alex.id).is_some());
+
+        h.press("pick:split").await;
+        assert_eq!(Some(Page::More), page(&h.press("split:1002").await));
+        h.press("pick:account").await;
+        assert_eq!(Some(Page::More), page(&h.press("back").await));
+        assert_eq!(Some(Page::More), page(&h.press("set_account:2").await));
+
+        assert_eq!(Some(Page::Main), page(&h.press("page:main").await));
+        assert_eq!(Some(Page::Main), page(&h.press("nudge:100").await));
+        let draft = h.drafts.get(h.key).unwrap();
+        assert_eq!(
+            (2, Some(1002)),
+            (draft.account.id, draft.split_with.map(|u| u.telegram_id))
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! `/fav`: expenses kept to be logged again with one tap on a keyboard.
+
+use super::callback::{self, Page};
+use super::draft::ActiveTransaction;
+use super::expense::{confirm_saved, resolve_add};
+use super::feed::{self, SharedFeeds};
+use super::markdown::escape_md;
+use super::{format, keyboards, notify, parse, settings, Bot, HandlerResult, SharedModel};
+use crate::config::Config;
+use crate::model::{
+    AmountLimits, Error, ExpenseSource, Favorite, Inserted, Locale, NewFavorite, User, MAX_EXPONENT,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::prelude::*;
+use teloxide::types::CallbackQuery;
+
+pub const FAV_USAGE: &str =
+    "Usage: /fav, /fav add <amount> <category> [\"label\"] [acc:<account>] or /fav del <label>";
+
+pub async fn handle_fav(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    user: &User,
+    config: &Config,
+    args: &str,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let args = args.trim();
+    let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let text = match action {
+        "" => {
+            let favorites = model.lock().unwrap().favorites(user.telegram_id)?;
+            if favorites.is_empty() {
+                bot.send_message(
+                    chat_id,
+                    escape_md("You have no favorites yet. Add one with /fav add 2.50 Groceries \"morning coffee\"."),
+                )
+                .await?;
+            } else {
+                bot.send_message(chat_id, format::render_favorites(&favorites))
+                    .reply_markup(keyboards::favorites_keyboard(&favorites))
+                    .await?;
+            }
+            return Ok(());
+        }
+        "add" => {
+            let locale = match &message.from {
+                Some(from) => settings::locale(model, from)?,
+                None => Locale::default(),
+            };
+            add(model, user, &config.amount_limits(), locale, rest)?
+        }
+        "del" => delete(model, user, rest)?,
+        _ => FAV_USAGE.to_string(),
+    };
+    bot.send_message(chat_id, escape_md(&text)).await?;
+    Ok(())
+}
+
+/// `/fav add`: takes the arguments of `/add`, the quoted comment doubling as
+/// the label. Without one the category names the favorite.
+fn add(
+    model: &SharedModel,
+    user: &User,
+    limits: &AmountLimits,
+    locale: Locale,
+    args: &str,
+) -> Result<String, Error> {
+    let args = match parse::parse_add(args, &limits.for_exponent(MAX_EXPONENT), locale) {
+        Ok(args) => args,
+        Err(reason) if reason == parse::ADD_USAGE => return Ok(FAV_USAGE.to_string()),
+        Err(reason) => return Ok(reason),
+    };
+    let model = model.lock().unwrap();
+    let (category, account, amount) = match resolve_add(&model, user.household, &args, limits)? {
+        Ok(resolved) => resolved,
+        Err(reason) => return Ok(reason),
+    };
+    let label = args
+        .comment
+        .clone()
+        .unwrap_or_else(|| category.name.clone());
+    let favorite = NewFavorite {
+        user_id: user.telegram_id,
+        label: label.clone(),
+        amount,
+        account_id: account.id,
+        category_id: category.id,
+        comment: args.comment,
+    };
+    match model.add_favorite(user.household, &favorite) {
+        Ok(_) => Ok(format!(
+            "Saved favorite '{}': {} {}, {}, {}. Tap it under /fav to log it.",
+            label,
+            format::format_amount(favorite.amount, account.exponent),
+            account.currency,
+            category.name,
+            account.name
+        )),
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(Error::InvalidAmount(e)) => Ok(e.to_string()),
+        Err(e @ (Error::CommentRequired(_) | Error::TooLong { .. })) => Ok(e.to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+fn delete(model: &SharedModel, user: &User, label: &str) -> Result<String, Error> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Ok(FAV_USAGE.to_string());
+    }
+    let deleted = model
+        .lock()
+        .unwrap()
+        .delete_favorite(user.telegram_id, label)?;
+    Ok(if deleted {
+        format!("Deleted favorite '{}'.", label)
+    } else {
+        format!("You have no favorite called '{}'.", label)
+    })
+}
+
+/// The expense a tap on the favorite logs, or why it can't.
+fn favorite_expense(
+    favorite: Favorite,
+    tapped_by: &User,
+    now: DateTime<Utc>,
+) -> Result<ActiveTransaction, String> {
+    if favorite.user_id != tapped_by.telegram_id {
+        return Err("This favorite belongs to someone else.".to_string());
+    }
+    if let Some(reason) = favorite.blocked_reason() {
+        return Err(format!(
+            "{} Delete it with /fav del {} and add it again.",
+            reason, favorite.label
+        ));
+    }
+    Ok(ActiveTransaction {
+        expense_id: None,
+        amount: favorite.amount,
+        amount_alternative: None,
+        amount_calculation: None,
+        original: None,
+        account: favorite.account,
+        account_explicit: true,
+        missing_account: None,
+        category: favorite.category,
+        household: tapped_by.household,
+        user_id: favorite.user_id,
+        timestamp: now,
+        comment: favorite.comment,
+        category_confirmed: true,
+        category_by_rule: false,
+        strict_commit: false,
+        split_with: None,
+        location: None,
+        comment_suggestions: Vec::new(),
+        comment_templates: Vec::new(),
+        template_fill: None,
+        source: None,
+        origin: ExpenseSource::Favorite,
+        page: Page::Main,
+    })
+}
+
+/// A tap on a favorite: commits it right away and confirms in a new
+/// message, so that the keyboard stays for the next time.
+pub async fn commit(
+    bot: &Bot,
+    q: &CallbackQuery,
+    (model, feeds): (&SharedModel, &SharedFeeds),
+    user: &User,
+    chat_id: ChatId,
+    id: i64,
+    tz: Tz,
+) -> HandlerResult {
+    let favorite = model.lock().unwrap().get_favorite(user.household, id)?;
+    let draft = favorite
+        .ok_or_else(|| callback::NO_OPTION.to_string())
+        .and_then(|f| favorite_expense(f, user, Utc::now()));
+    let draft = match draft {
+        Ok(draft) => draft,
+        Err(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+            return Ok(());
+        }
+    };
+
+    // A redelivered tap carries the same query id and finds the first save.
+    let key = format!("fav:{}", q.id);
+    let saved =
+        model
+            .lock()
+            .unwrap()
+            .insert_expense_once(draft.household, &key, &draft.to_new_expense());
+    let id = match saved {
+        Ok(Inserted::New(id)) => {
+            notify::expense_logged(bot, model, feeds.reporter(), draft.household, id);
+            id
+        }
+        Ok(Inserted::Existing(id)) => id,
+        Err(Error::InvalidAmount(e)) => {
+            bot.answer_callback_query(q.id.clone())
+                .text(e.to_string())
+                .await?;
+            return Ok(());
+        }
+        Err(e @ (Error::CommentRequired(_) | Error::TooLong { .. })) => {
+            bot.answer_callback_query(q.id.clone())
+                .text(e.to_string())
+                .await?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    feed::changed(bot, model, feeds, (chat_id, draft.household))?;
+    confirm_saved(bot, (chat_id, None), model, (id, &draft), tz).await?;
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    fn setup() -> (SharedModel, User, AmountLimits) {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let user = model.get_user(1001).unwrap().unwrap();
+        (Arc::new(Mutex::new(model)), user, AmountLimits::default())
+    }
+
+    #[test]
+    fn add_and_delete() {
+        let (model, user, limits) = setup();
+        let add = |args| add(&model, &user, &limits, Locale::DecimalPoint, args).unwrap();
+
+        assert_eq!(
+            "Saved favorite 'morning coffee': 2.50 EUR, Groceries, Alex Savings. Tap it under /fav to log it.",
+            add(r#"2.50 Groceries "morning coffee""#)
+        );
+        assert_eq!(
+            "Saved favorite 'Transportation': 1.20 BYN, Transportation, Family BYN. Tap it under /fav to log it.",
+            add("1.2 transp acc:family")
+        );
+        assert_eq!(
+            "There already is a favorite called 'Morning coffee'.",
+            add(r#"3 Groceries "Morning coffee""#)
+        );
+        assert_eq!(FAV_USAGE, add("Groceries 2.50"));
+        assert!(add("2.50 Nonsense").starts_with("No category matches"));
+
+        assert_eq!(
+            "You have no favorite called 'tea'.",
+            delete(&model, &user, "tea").unwrap()
+        );
+        assert_eq!(
+            "Deleted favorite 'MORNING COFFEE'.",
+            delete(&model, &user, "MORNING COFFEE").unwrap()
+        );
+        assert_eq!(1, model.lock().unwrap().favorites(1001).unwrap().len());
+    }
+
+    #[test]
+    fn required_comments() {
+        let (model, user, limits) = setup();
+        let add = |args| add(&model, &user, &limits, Locale::DecimalPoint, args).unwrap();
+        add("1.2 Transportation");
+        {
+            let model = model.lock().unwrap();
+            model.set_require_comment(1, 2, true).unwrap();
+            model.set_require_comment(1, 4, true).unwrap();
+        }
+
+        assert_eq!(
+            "Add a comment first, Utilities expenses need one.",
+            add("80 Utilities")
+        );
+        assert!(add(r#"80 Utilities "power""#).starts_with("Saved favorite 'power'"));
+
+        let favorites = model.lock().unwrap().favorites(1001).unwrap();
+        assert_eq!(
+            vec![
+                None,
+                Some("Transportation expenses need a comment, this favorite has none.".to_string()),
+            ],
+            favorites
+                .iter()
+                .map(Favorite::blocked_reason)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn tapping_a_favorite() {
+        let (model, user, limits) = setup();
+        add(
+            &model,
+            &user,
+            &limits,
+            Locale::DecimalPoint,
+            r#"2.50 Groceries "morning coffee""#,
+        )
+        .unwrap();
+        let favorite = || model.lock().unwrap().favorites(1001).unwrap().remove(0);
+        let hanna = model.lock().unwrap().get_user(1002).unwrap().unwrap();
+        let now = Utc::now();
+
+        let draft = favorite_expense(favorite(), &user, now).unwrap();
+        assert_eq!(
+            (2.5, "Groceries", Some("morning coffee"), now),
+            (
+                draft.amount,
+                draft.category.name.as_str(),
+                draft.comment.as_deref(),
+                draft.timestamp
+            )
+        );
+        assert_eq!(
+            Err("This favorite belongs to someone else.".to_string()),
+            favorite_expense(favorite(), &hanna, now)
+        );
+
+        model
+            .lock()
+            .unwrap()
+            .merge_categories(1, 1001, (1, 2), false)
+            .unwrap();
+        // The favorite follows its category into the merge.
+        assert_eq!(
+            "Transportation",
+            favorite_expense(favorite(), &user, now)
+                .unwrap()
+                .category
+                .name
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! `/feed`: one pinned message per chat with the month's totals, edited as
+//! expenses come and go instead of posting new messages.
+
+use super::markdown::escape_md;
+use super::reporter::{self, ErrorReporter};
+use super::{format, settings, Bot, HandlerResult, SharedModel};
+use crate::model::{Error, Model, Period, User};
+use chrono::Utc;
+use chrono_tz::Tz;
+use log::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use teloxide::{ApiError, RequestError};
+
+pub const FEED_USAGE: &str = "Usage: /feed enable or /feed disable";
+
+/// Changes this soon after the first one are shown by the same edit.
+pub const DEBOUNCE: Duration = Duration::from_secs(10);
+
+/// Coalesces bursts of changes: the first change of a key schedules an
+/// update `delay` later, the ones until then join it. Times are passed in so
+/// that tests don't have to wait.
+pub struct Debouncer<K> {
+    delay: Duration,
+    due: HashMap<K, Instant>,
+}
+
+impl<K: Copy + Eq + Hash> Debouncer<K> {
+    pub fn new(delay: Duration) -> Debouncer<K> {
+        Debouncer {
+            delay,
+            due: HashMap::new(),
+        }
+    }
+
+    /// Records a change of `key` at `now`. Returns when the update is due if
+    /// this change scheduled it, `None` if an update is pending already.
+    pub fn schedule(&mut self, key: K, now: Instant) -> Option<Instant> {
+        if self.due.contains_key(&key) {
+            return None;
+        }
+        let due = now + self.delay;
+        self.due.insert(key, due);
+        Some(due)
+    }
+
+    /// The keys whose update is due at `now`, no longer pending.
+    pub fn take_due(&mut self, now: Instant) -> Vec<K> {
+        let due: Vec<K> = self
+            .due
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &due {
+            self.due.remove(key);
+        }
+        due
+    }
+}
+
+pub struct Feeds {
+    tz: Tz,
+    pending: Mutex<Debouncer<ChatId>>,
+    reporter: ErrorReporter,
+}
+
+pub type SharedFeeds = Arc<Feeds>;
+
+impl Feeds {
+    pub fn new(tz: Tz, reporter: ErrorReporter) -> Feeds {
+        Feeds {
+            tz,
+            pending: Mutex::new(Debouncer::new(DEBOUNCE)),
+            reporter,
+        }
+    }
+
+    /// Where the updates done in the background report their failures, and
+    /// other tasks spawned by handlers along with them.
+    pub fn reporter(&self) -> &ErrorReporter {
+        &self.reporter
+    }
+}
+
+pub async fn handle_feed(
+    bot: &Bot,
+    message: &Message,
+    (model, feeds): (&SharedModel, &SharedFeeds),
+    user: &User,
+    args: &str,
+) -> HandlerResult {
+    let chat_id = message.chat.id;
+    let current = model.lock().unwrap().feed_message(chat_id.0)?;
+    let text = match args.trim() {
+        "enable" => {
+            // A new feed replaces the old one, which may have been lost.
+            if let Some(old) = current {
+                unpin(bot, chat_id, old).await;
+            }
+            if post(bot, model, (chat_id, user.household), feeds.tz).await? {
+                return Ok(());
+            }
+            "The feed is on, but I can't pin it here. Make me an admin allowed to pin messages."
+        }
+        "disable" => match current {
+            Some(id) => {
+                model
+                    .lock()
+                    .unwrap()
+                    .set_feed_message(chat_id.0, user.household, None)?;
+                unpin(bot, chat_id, id).await;
+                "The feed is off."
+            }
+            None => "The feed is not enabled in this chat.",
+        },
+        _ => FEED_USAGE,
+    };
+    bot.send_message(chat_id, escape_md(text)).await?;
+    Ok(())
+}
+
+/// The feeds to update after something of `household` changed in the
+/// chat: every feed of the household, and the chat's own if it shows
+/// another one.
+fn feeds_to_refresh(model: &Model, chat_id: ChatId, household: i64) -> Result<Vec<ChatId>, Error> {
+    let mut chats: Vec<ChatId> = model
+        .feed_chats(household)?
+        .into_iter()
+        .map(ChatId)
+        .collect();
+    if !chats.contains(&chat_id) && model.feed_message(chat_id.0)?.is_some() {
+        chats.push(chat_id);
+    }
+    Ok(chats)
+}
+
+/// Schedules updates of the feeds after an expense of `household` changed
+/// in the chat, see [`feeds_to_refresh`].
+pub fn changed(
+    bot: &Bot,
+    model: &SharedModel,
+    feeds: &SharedFeeds,
+    (chat_id, household): (ChatId, i64),
+) -> Result<(), Error> {
+    let chats = feeds_to_refresh(&model.lock().unwrap(), chat_id, household)?;
+    let now = Instant::now();
+    let due = {
+        let mut pending = feeds.pending.lock().unwrap();
+        // All scheduled now are due together. Those pending already are
+        // updated by the task that scheduled them.
+        chats
+            .into_iter()
+            .filter_map(|chat_id| pending.schedule(chat_id, now))
+            .min()
+    };
+    let Some(due) = due else {
+        return Ok(());
+    };
+    let (bot, model, pending) = (bot.clone(), model.clone(), feeds.clone());
+    reporter::spawn(&feeds.reporter, "feed update", async move {
+        let feeds = pending;
+        tokio::time::sleep_until(due.into()).await;
+        let chats = feeds.pending.lock().unwrap().take_due(Instant::now());
+        for chat_id in chats {
+            if let Err(e) = refresh(&bot, &model, chat_id, feeds.tz).await {
+                let message = format!("chat {}: {}", chat_id, e);
+                feeds.reporter.report(Level::Warn, "feed update", message);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn feed_text(
+    model: &SharedModel,
+    (chat_id, household): (ChatId, i64),
+    tz: Tz,
+) -> Result<String, Error> {
+    let now = Utc::now();
+    let model = model.lock().unwrap();
+    let month = Period::ThisMonth.resolve(now, tz, model.calendar(household)?);
+    let summary = model.summarize(household, month, tz)?;
+    let options = settings::render_options(&model, chat_id)?;
+    Ok(format::render_feed(&summary, now, tz, options))
+}
+
+/// Edits the feed message, posting a new one if it was deleted. The feed
+/// shows the household the chat was bound to when it was enabled.
+async fn refresh(bot: &Bot, model: &SharedModel, chat_id: ChatId, tz: Tz) -> HandlerResult {
+    let feed = {
+        let model = model.lock().unwrap();
+        model
+            .feed_message(chat_id.0)?
+            .zip(model.chat_household(chat_id.0)?)
+    };
+    let Some((id, household)) = feed else {
+        return Ok(());
+    };
+    let text = feed_text(model, (chat_id, household), tz)?;
+    match bot.edit_message_text(chat_id, MessageId(id), text).await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(RequestError::Api(ApiError::MessageToEditNotFound)) => {
+            info!("The feed of chat {} is gone, posting it again", chat_id);
+            post(bot, model, (chat_id, household), tz).await?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Posts the feed of the household, records it and pins it. Returns whether
+/// it is pinned.
+async fn post(
+    bot: &Bot,
+    model: &SharedModel,
+    (chat_id, household): (ChatId, i64),
+    tz: Tz,
+) -> Result<bool, super::HandlerError> {
+    let text = feed_text(model, (chat_id, household), tz)?;
+    let message = bot.send_message(chat_id, text).await?;
+    model
+        .lock()
+        .unwrap()
+        .set_feed_message(chat_id.0, household, Some(message.id.0))?;
+    match bot
+        .pin_chat_message(chat_id, message.id)
+        .disable_notification(true)
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            warn!("Pinning the feed in chat {} failed: {}", chat_id, e);
+            Ok(false)
+        }
+    }
+}
+
+/// Unpins a feed message, which may well be gone already.
+async fn unpin(bot: &Bot, chat_id: ChatId, id: i32) {
+    if let Err(e) = bot
+        .unpin_chat_message(chat_id)
+        .message_id(MessageId(id))
+        .await
+    {
+        info!("Unpinning message {} in chat {} failed: {}", id, chat_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_are_coalesced() {
+        let mut debouncer = Debouncer::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        assert_eq!(Some(at(10)), debouncer.schedule(1, at(0)));
+        assert_eq!(None, debouncer.schedule(1, at(3)));
+        assert_eq!(None, debouncer.schedule(1, at(9)));
+        assert_eq!(Some(at(15)), debouncer.schedule(2, at(5)));
+
+        assert!(debouncer.take_due(at(9)).is_empty());
+        assert_eq!(vec![1], debouncer.take_due(at(10)));
+        assert!(debouncer.take_due(at(12)).is_empty());
+        // A change after the update schedules the next one.
+        assert_eq!(Some(at(21)), debouncer.schedule(1, at(11)));
+
+        let mut due = debouncer.take_due(at(30));
+        due.sort();
+        assert_eq!(vec![1, 2], due);
+        assert!(debouncer.take_due(at(60)).is_empty());
+    }
+
+    #[test]
+    fn every_feed_of_the_household_is_refreshed() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        model.set_feed_message(-100, 1, Some(42)).unwrap();
+        model.set_feed_message(-200, 1, Some(7)).unwrap();
+        model.set_feed_message(-300, 2, Some(8)).unwrap();
+
+        // A private chat without a feed of its own.
+        assert_eq!(
+            vec![ChatId(-200), ChatId(-100)],
+            feeds_to_refresh(&model, ChatId(1001), 1).unwrap()
+        );
+        assert_eq!(
+            vec![ChatId(-200), ChatId(-100)],
+            feeds_to_refresh(&model, ChatId(-100), 1).unwrap()
+        );
+        // A chat showing another household keeps its own feed current.
+        assert_eq!(
+            vec![ChatId(-200), ChatId(-100), ChatId(-300)],
+            feeds_to_refresh(&model, ChatId(-300), 1).unwrap()
+        );
+        assert!(feeds_to_refresh(&model, ChatId(1001), 7)
+            .unwrap()
+            .is_empty());
+    }
+}
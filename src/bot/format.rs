@@ -0,0 +1,2803 @@
+//! Pure rendering of model data into message texts (MarkdownV2).
+
+use super::batch::BatchLine;
+use super::draft::ActiveTransaction;
+use super::long::CHUNK_LIMIT;
+use super::markdown::{self, escape_code, escape_md};
+use crate::model::{
+    forecast, from_minor, to_minor, AmountMigrationReport, AmountMismatch, Balances,
+    BudgetRemaining, Calendar, Category, CategoryDetail, CategoryGroup, CategoryStats, Checkpoint,
+    Completeness, ConfirmMode, DailyUsage, Debt, ExpenseDetails, Favorite, FunStats, GrandTotal,
+    IntegrityReport, Locale, MaintenanceReport, Modification, MonthToDate, OriginalAmount,
+    PruneReport, ReportDate, Retained, RetentionPolicy, ReviewReason, Settings, SourceMessage,
+    Summary, WeekdayAverages, YearSummary, MAX_DRIFT,
+};
+use crate::time::{self, DateStyle, Lang, Style};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+/// The amount with as many decimals as its currency has.
+pub fn format_amount(amount: f64, exponent: u32) -> String {
+    format!("{:.*}", exponent as usize, amount)
+}
+
+/// How reports show their amounts, by the setting of whoever asked. Only
+/// reports round, what is stored, exported or shown per expense doesn't.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// With the decimal places of the currency.
+    #[default]
+    Exact,
+    /// To whole units, `/settings round on`.
+    Whole,
+}
+
+impl RoundingMode {
+    pub fn of(settings: &Settings) -> RoundingMode {
+        match settings.round_totals {
+            true => RoundingMode::Whole,
+            false => RoundingMode::Exact,
+        }
+    }
+
+    /// The amount as a report shows it. Totals are rounded themselves rather
+    /// than summed from rounded parts, so they may differ from that sum by a
+    /// unit or so.
+    fn format(self, amount: f64, exponent: u32) -> String {
+        match self {
+            RoundingMode::Exact => format_amount(amount, exponent),
+            RoundingMode::Whole => format_amount(amount.round(), 0),
+        }
+    }
+
+    /// The line under a report with rounded amounts.
+    fn footer(self) -> Option<String> {
+        match self {
+            RoundingMode::Exact => None,
+            RoundingMode::Whole => Some(format!("_{}_", escape_md("amounts rounded"))),
+        }
+    }
+}
+
+/// Shown in place of amounts in chats that mask them.
+const MASKED: &str = "🔒";
+
+/// What messages may show in the chat they are sent to. Every formatter of
+/// commit confirmations and the feed takes one, so that none shows an
+/// amount where it shouldn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Amounts are shown as 🔒, in groups with `/settings privacy on`.
+    pub mask_amounts: bool,
+    /// How much commit confirmations show, see `/settings confirm`.
+    pub confirm: ConfirmMode,
+    /// The language dates are written in.
+    pub lang: Lang,
+}
+
+impl RenderOptions {
+    /// Everything shown, as in private chats.
+    pub const FULL: RenderOptions = RenderOptions {
+        mask_amounts: false,
+        confirm: ConfirmMode::Full,
+        lang: Lang::En,
+    };
+
+    /// The amount with its currency, unless amounts are masked.
+    fn amount(self, amount: f64, exponent: u32, currency: &str) -> String {
+        match self.mask_amounts {
+            true => MASKED.to_string(),
+            false => format!("{} {}", format_amount(amount, exponent), currency),
+        }
+    }
+}
+
+/// Lays out `(label, value)` rows as two columns: labels padded on the
+/// right, values aligned on the right edge. Rows with an empty value are
+/// headers and are printed as is.
+fn align_columns(rows: &[(String, String)]) -> String {
+    let label_width = rows
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let value_width = rows
+        .iter()
+        .map(|(_, value)| value.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    rows.iter()
+        .map(|(label, value)| {
+            if value.is_empty() {
+                label.clone()
+            } else {
+                format!("{:<label_width$}  {:>value_width$}", label, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The balances, with what was spent today on the accounts with a `daily`
+/// limit below each of them. Rates more than `stale_after` days old on
+/// `today` are flagged under their currency.
+pub fn render_balance(
+    balances: &Balances,
+    daily: &[DailyUsage],
+    (today, stale_after): (NaiveDate, u32),
+) -> String {
+    let mut stale = false;
+    let mut rows = Vec::new();
+    for currency in &balances.currencies {
+        rows.push((currency.currency.clone(), String::new()));
+        for account in balances
+            .accounts
+            .iter()
+            .filter(|a| a.currency == currency.currency)
+        {
+            rows.push((
+                format!("  {}", account.name),
+                format_amount(account.balance, account.exponent),
+            ));
+            if let Some(day) = daily.iter().find(|d| d.account_id == account.id) {
+                rows.push((
+                    format!(
+                        "    today: {} / {}{}",
+                        format_amount(day.spent, day.exponent),
+                        format_amount(day.limit, day.exponent),
+                        if day.is_over() { ", over" } else { "" }
+                    ),
+                    String::new(),
+                ));
+            }
+        }
+        rows.push((
+            "  Subtotal".to_string(),
+            format_amount(currency.subtotal, currency.exponent),
+        ));
+        if let Some(rate) = currency.rate.filter(|r| r.is_stale(today, stale_after)) {
+            rows.push((
+                format!("  (rate from {}, may be stale)", rate.date),
+                String::new(),
+            ));
+            stale = true;
+        }
+    }
+
+    let footer = match balances.grand_total() {
+        GrandTotal::Total {
+            amount,
+            rates_as_of,
+        } => {
+            rows.push((
+                format!("Total {}", balances.base_currency),
+                format_amount(amount, balances.base_exponent),
+            ));
+            rates_as_of.map(|date| match stale {
+                true => format!("Rates as of {}, some may be stale: /rate status", date),
+                false => format!("Rates as of {}", date),
+            })
+        }
+        GrandTotal::MissingRates(missing) => Some(format!(
+            "No total in {}: missing rates for {}",
+            balances.base_currency,
+            missing.join(", ")
+        )),
+    };
+
+    let mut text = align_columns(&rows);
+    if let Some(footer) = footer {
+        text.push_str("\n\n");
+        text.push_str(&footer);
+    }
+    format!("```\n{}\n```", escape_code(&text))
+}
+
+pub fn render_report(summary: &Summary, rounding: RoundingMode) -> String {
+    let first = summary.range.start;
+    let last = summary.range.last_day();
+    let (logged, by) = match summary.date {
+        ReportDate::Spent => ("", ""),
+        ReportDate::Logged => (" logged", ", by date logged"),
+    };
+    if summary.categories.is_empty() {
+        let mut text = escape_md(&format!(
+            "No expenses{} from {} to {}.",
+            logged, first, last
+        ));
+        if let Some(footer) = exclusion_footer(summary, rounding) {
+            text.push('\n');
+            text.push_str(&footer);
+        }
+        return text;
+    }
+
+    let mut text = format!(
+        "{}\n```\n{}\n```",
+        report_heading(&format!("Report {} – {}{}", first, last, by), summary),
+        escape_code(&align_columns(&report_rows(summary, rounding)))
+    );
+    push_footers(&mut text, summary, rounding);
+    text
+}
+
+/// The bold heading of a report, 🔒 for closed months, with how far its
+/// expenses can be trusted below when the summary knows.
+fn report_heading(heading: &str, summary: &Summary) -> String {
+    let lock = if summary.closed { "🔒 " } else { "" };
+    let mut text = format!("*{}{}*", lock, escape_md(heading));
+    if let Some(line) = summary.completeness.as_ref().and_then(completeness_line) {
+        text.push('\n');
+        text.push_str(&line);
+    }
+    text
+}
+
+/// Like "87 expenses (12 imported, 5 need review)", pointing at `/review`
+/// while any need it.
+fn completeness_line(completeness: &Completeness) -> Option<String> {
+    let Completeness {
+        expenses,
+        imported,
+        needs_review,
+    } = *completeness;
+    if expenses == 0 {
+        return None;
+    }
+    let mut line = match expenses {
+        1 => "1 expense".to_string(),
+        n => format!("{} expenses", n),
+    };
+    let mut details = Vec::new();
+    if imported > 0 {
+        details.push(format!("{} imported", imported));
+    }
+    match needs_review {
+        0 => {}
+        1 => details.push("1 needs review".to_string()),
+        n => details.push(format!("{} need review", n)),
+    }
+    if !details.is_empty() {
+        line.push_str(&format!(" ({})", details.join(", ")));
+    }
+    if needs_review > 0 {
+        line.push_str(", see /review");
+    }
+    Some(escape_md(&line))
+}
+
+/// The line naming the categories a report leaves out, with what was spent
+/// on them in each currency.
+fn exclusion_footer(summary: &Summary, rounding: RoundingMode) -> Option<String> {
+    if summary.excluded.is_empty() {
+        return None;
+    }
+    let totals: Vec<String> = summary
+        .excluded_totals()
+        .iter()
+        .map(|(currency, total)| {
+            let exponent = summary
+                .excluded_spend
+                .iter()
+                .find(|c| c.currency == *currency)
+                .map_or(2, |c| c.exponent);
+            format!("{} {}", rounding.format(*total, exponent), currency)
+        })
+        .collect();
+    let spent = match totals.is_empty() {
+        true => "nothing spent".to_string(),
+        false => totals.join(", "),
+    };
+    Some(format!(
+        "_{}_",
+        escape_md(&format!(
+            "excluded {}: {}",
+            summary.excluded.join(", "),
+            spent
+        ))
+    ))
+}
+
+/// The lines under a report, each on its own.
+fn push_footers(text: &mut String, summary: &Summary, rounding: RoundingMode) {
+    for footer in [rounding.footer(), exclusion_footer(summary, rounding)]
+        .into_iter()
+        .flatten()
+    {
+        text.push('\n');
+        text.push_str(&footer);
+    }
+}
+
+/// `/report` of the current month: spend per category so far and where it
+/// is heading, next to the budget of the month where there is one, largest
+/// first. With `by_overrun`, for `/report forecast`, the categories heading
+/// furthest over their budgets come first instead, then those without one.
+/// A month starting elsewhere than on the 1st is named by its days.
+pub fn render_forecast(
+    summary: &Summary,
+    (budgets, by_overrun): (&[BudgetRemaining], bool),
+    now: DateTime<Utc>,
+    (tz, calendar): (Tz, Calendar),
+    rounding: RoundingMode,
+) -> String {
+    let heading = report_heading(
+        &format!(
+            "Forecast for {}, day {} of {}",
+            summary.range.month_name(),
+            (now.with_timezone(&tz).date_naive() - summary.range.start).num_days() + 1,
+            (summary.range.end - summary.range.start).num_days()
+        ),
+        summary,
+    );
+    if summary.categories.is_empty() {
+        let mut text = format!("{}\n{}", heading, escape_md("No expenses yet."));
+        if let Some(footer) = exclusion_footer(summary, rounding) {
+            text.push('\n');
+            text.push_str(&footer);
+        }
+        return text;
+    }
+
+    // The budget of each row goes after the aligned columns.
+    let mut rows = vec![(String::new(), "spent".to_string(), "forecast".to_string())];
+    let mut budget_notes = vec![String::new()];
+    for (currency, total) in summary.currency_totals() {
+        rows.push((currency.clone(), String::new(), String::new()));
+        budget_notes.push(String::new());
+        let exponent = currency_exponent(summary, &currency);
+        let project = |spent: f64| forecast(to_minor(spent, exponent), now, tz, calendar);
+        let budget_of = |category: &str| {
+            budgets
+                .iter()
+                .find(|b| b.category == category && b.currency == currency)
+                .map(|b| to_minor(b.budget, exponent))
+        };
+        let mut groups: Vec<_> = summary
+            .groups(&currency)
+            .into_iter()
+            .map(|group| {
+                let overrun = budget_of(group.category).map(|b| project(group.total) - b);
+                (overrun, group)
+            })
+            .collect();
+        if by_overrun {
+            // Stable, so categories without a budget stay largest first.
+            groups.sort_by_key(|(overrun, _)| std::cmp::Reverse(*overrun));
+        }
+        for (_, group) in &groups {
+            for (label, (spent, projected, note)) in group_rows(group, |category, spent| {
+                let projected = project(spent);
+                let note = match category.and_then(budget_of) {
+                    Some(budget) => format!(
+                        " of {}{}",
+                        rounding.format(from_minor(budget, exponent), exponent),
+                        if projected > budget { " ⚠️" } else { "" }
+                    ),
+                    None => String::new(),
+                };
+                (
+                    rounding.format(spent, exponent),
+                    rounding.format(from_minor(projected, exponent), exponent),
+                    note,
+                )
+            }) {
+                rows.push((label, spent, projected));
+                budget_notes.push(note);
+            }
+        }
+        rows.push((
+            "  Total".to_string(),
+            rounding.format(total, exponent),
+            rounding.format(from_minor(project(total), exponent), exponent),
+        ));
+        budget_notes.push(String::new());
+    }
+    let lines: Vec<String> = align_two_values(&rows)
+        .lines()
+        .zip(&budget_notes)
+        .map(|(line, note)| format!("{}{}", line, note))
+        .collect();
+    let mut text = format!("{}\n```\n{}\n```", heading, escape_code(&lines.join("\n")));
+    push_footers(&mut text, summary, rounding);
+    text
+}
+
+/// Like [`align_columns`], with two values right-aligned after each label.
+fn align_two_values(rows: &[(String, String, String)]) -> String {
+    let width = |column: fn(&(String, String, String)) -> &String| {
+        rows.iter()
+            .filter(|row| !row.1.is_empty())
+            .map(|row| column(row).chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (labels, firsts, seconds) = (width(|r| &r.0), width(|r| &r.1), width(|r| &r.2));
+    rows.iter()
+        .map(|(label, first, second)| {
+            if first.is_empty() {
+                label.clone()
+            } else {
+                format!(
+                    "{:<labels$}  {:>firsts$}  {:>seconds$}",
+                    label, first, second
+                )
+                .trim_end()
+                .to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The pinned feed of a chat: the totals of the month so far.
+pub fn render_feed(
+    summary: &Summary,
+    now: DateTime<Utc>,
+    tz: Tz,
+    options: RenderOptions,
+) -> String {
+    let heading = format!(
+        "*{}*",
+        escape_md(&format!("📌 {} so far", summary.range.month_name()))
+    );
+    let body = if summary.categories.is_empty() {
+        escape_md("No expenses yet.")
+    } else {
+        let mut rows = report_rows(summary, RoundingMode::Exact);
+        if options.mask_amounts {
+            for (_, value) in rows.iter_mut().filter(|(_, value)| !value.is_empty()) {
+                *value = MASKED.to_string();
+            }
+        }
+        format!("```\n{}\n```", escape_code(&align_columns(&rows)))
+    };
+    format!(
+        "{}\n{}\n{}",
+        heading,
+        body,
+        escape_md(&format!(
+            "Updated {}",
+            time::render(now, tz, Style::DateTime)
+        ))
+    )
+}
+
+fn currency_exponent(summary: &Summary, currency: &str) -> u32 {
+    summary
+        .categories
+        .iter()
+        .find(|c| c.currency == currency)
+        .map_or(2, |c| c.exponent)
+}
+
+/// A row per top-level category spent in, with what `values` makes of its
+/// rolled-up spend. Subcategories follow indented, along with the spend in
+/// the parent itself as "(own)", largest first.
+fn category_rows<T>(
+    summary: &Summary,
+    currency: &str,
+    values: impl Fn(f64, u32) -> T,
+) -> Vec<(String, T)> {
+    summary
+        .groups(currency)
+        .iter()
+        .flat_map(|group| group_rows(group, |_, total| values(total, group.exponent)))
+        .collect()
+}
+
+/// The row of a top-level category, followed by those of its subcategories
+/// and its own spend, largest first, if it has subcategories. `values` gets
+/// the category of a row, `None` for its own spend, and the amount.
+fn group_rows<T>(
+    group: &CategoryGroup<'_>,
+    values: impl Fn(Option<&str>, f64) -> T,
+) -> Vec<(String, T)> {
+    let mut rows = vec![(
+        format!("  {}", group.category),
+        values(Some(group.category), group.total),
+    )];
+    if group.children.is_empty() {
+        return rows;
+    }
+    let mut parts: Vec<(Option<&str>, f64)> = group
+        .children
+        .iter()
+        .map(|c| (Some(c.category.as_str()), c.total))
+        .collect();
+    if let Some(own) = group.own {
+        parts.push((None, own.total));
+    }
+    parts.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (category, total) in parts {
+        let label = format!("    {}", category.unwrap_or("(own)"));
+        rows.push((label, values(category, total)));
+    }
+    rows
+}
+
+/// Spend per category under each currency, with its total.
+fn report_rows(summary: &Summary, rounding: RoundingMode) -> Vec<(String, String)> {
+    let mut rows = Vec::new();
+    for (currency, total) in summary.currency_totals() {
+        rows.push((currency.clone(), String::new()));
+        rows.extend(category_rows(summary, &currency, |amount, exponent| {
+            rounding.format(amount, exponent)
+        }));
+        let exponent = currency_exponent(summary, &currency);
+        rows.push(("  Total".to_string(), rounding.format(total, exponent)));
+    }
+    rows
+}
+
+/// Heading of the favorites keyboard, explaining the flagged ones.
+pub fn render_favorites(favorites: &[Favorite]) -> String {
+    let mut lines = vec![escape_md("⭐ Tap a favorite to log it now.")];
+    for f in favorites {
+        if let Some(reason) = f.blocked_reason() {
+            lines.push(escape_md(&format!("⚠️ {}: {}", f.label, reason)));
+        }
+    }
+    lines.join("\n")
+}
+
+/// What the integrity check found, followed by the size of every table.
+pub fn render_integrity(report: &IntegrityReport) -> String {
+    let mut lines = vec!["*Integrity check*".to_string()];
+    if report.is_ok() {
+        lines.push(escape_md("✅ No problems found."));
+    }
+    lines.extend(
+        report
+            .problems
+            .iter()
+            .map(|p| escape_md(&format!("⚠️ {}", p))),
+    );
+    lines.extend(
+        report
+            .orphans
+            .iter()
+            .map(|o| escape_md(&format!("🔗 {}", o))),
+    );
+    let rows: Vec<(String, String)> = report
+        .row_counts
+        .iter()
+        .map(|(table, count)| (table.clone(), count.to_string()))
+        .collect();
+    lines.push(format!("```\n{}\n```", escape_code(&align_columns(&rows))));
+    lines.join("\n")
+}
+
+/// A size in bytes the way people read it, like `1.5 MB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    if size < 1024.0 {
+        return format!("{} B", bytes);
+    }
+    let mut unit = "";
+    for next in UNITS {
+        size /= 1024.0;
+        unit = next;
+        if size < 1024.0 {
+            break;
+        }
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// The line on the write-ahead log, after a line break.
+fn checkpoint_line(checkpoint: Checkpoint) -> String {
+    match checkpoint.busy {
+        true => format!(
+            "\nLog: {} of {} pages checkpointed, the rest held back by another connection",
+            checkpoint.checkpointed, checkpoint.log_pages
+        ),
+        false => format!("\nLog: {} pages checkpointed", checkpoint.checkpointed),
+    }
+}
+
+/// What `/maintenance` did, with the size of the database before and after.
+pub fn render_maintenance(report: &MaintenanceReport) -> String {
+    let vacuumed = if report.vacuumed {
+        format!("{} free pages vacuumed", report.free_pages)
+    } else {
+        format!("{} free pages, too few to vacuum", report.free_pages)
+    };
+    let checkpoint = report.checkpoint.map(checkpoint_line).unwrap_or_default();
+    format!(
+        "*Maintenance*\n{}",
+        escape_md(&format!(
+            "Size: {} → {}\n{}{}\nTook {:.1} s.",
+            format_size(report.size_before),
+            format_size(report.size_after),
+            vacuumed,
+            checkpoint,
+            report.took.as_secs_f64()
+        ))
+    )
+}
+
+/// Which rows of a pruned table were deleted, like `Audit log: 12 entries
+/// older than 365 days`.
+fn pruned_line(policy: &RetentionPolicy, deleted: usize) -> String {
+    let (table, rows) = match policy.table {
+        Retained::AuditLog => ("Audit log", "entries"),
+        Retained::CallbackPayload => ("Button payloads", "payloads"),
+    };
+    format!(
+        "{}: {} {} older than {} days",
+        table, deleted, rows, policy.days
+    )
+}
+
+pub fn render_prune(report: &PruneReport) -> String {
+    let mut lines: Vec<String> = report
+        .pruned
+        .iter()
+        .map(|(policy, deleted)| pruned_line(policy, *deleted))
+        .collect();
+    lines.push(format!("Took {:.1} s.", report.took.as_secs_f64()));
+    format!("*Pruning*\n{}", escape_md(&lines.join("\n")))
+}
+
+/// Who owes whom, one line per pair of users and currency.
+pub fn render_debts(debts: &[Debt]) -> String {
+    if debts.is_empty() {
+        return escape_md("Nobody owes anybody anything.");
+    }
+    debts
+        .iter()
+        .map(|d| {
+            escape_md(&format!(
+                "{} owes {} {} {}",
+                d.debtor,
+                d.creditor,
+                format_amount(d.amount, d.exponent),
+                d.currency
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Mismatches listed by `render_amount_migration`, the rest are counted.
+const MISMATCHES_SHOWN: usize = 20;
+
+fn mismatch_line(m: &AmountMismatch) -> String {
+    format!(
+        "expense {}: {} stored as {}, {} expected",
+        m.expense_id,
+        m.legacy,
+        format_amount(from_minor(m.minor, m.exponent), m.exponent),
+        format_amount(from_minor(m.rederived, m.exponent), m.exponent)
+    )
+}
+
+/// What checking the converted amounts against the legacy ones found.
+pub fn render_amount_migration(report: &AmountMigrationReport) -> String {
+    let mut lines = vec!["*Amount migration*".to_string()];
+    if report.finalized {
+        lines.push(escape_md(
+            "✅ The legacy amounts are dropped already, there is nothing to verify.",
+        ));
+    } else if report.is_ok() {
+        lines.push(escape_md(&format!(
+            "✅ All {} amounts agree with their legacy value. /migrate finalize drops it.",
+            report.checked
+        )));
+    } else {
+        lines.push(escape_md(&format!(
+            "⚠️ {} of {} amounts differ from their legacy value by more than {}:",
+            report.mismatches.len(),
+            report.checked,
+            MAX_DRIFT
+        )));
+        lines.extend(
+            report
+                .mismatches
+                .iter()
+                .take(MISMATCHES_SHOWN)
+                .map(|m| escape_md(&mismatch_line(m))),
+        );
+        if report.mismatches.len() > MISMATCHES_SHOWN {
+            lines.push(escape_md(&format!(
+                "… and {} more.",
+                report.mismatches.len() - MISMATCHES_SHOWN
+            )));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Joins `sections` into as few messages as fit the limit, never splitting
+/// a section.
+fn split_messages(sections: Vec<String>) -> Vec<String> {
+    let mut messages: Vec<String> = Vec::new();
+    for section in sections {
+        match messages.last_mut() {
+            Some(last) if last.chars().count() + 2 + section.chars().count() <= CHUNK_LIMIT => {
+                last.push_str("\n\n");
+                last.push_str(&section);
+            }
+            _ => messages.push(section),
+        }
+    }
+    messages
+}
+
+/// The year in review, one section per currency. Long reviews are split
+/// into several messages between currencies.
+pub fn render_year(summary: &YearSummary, tz: Tz) -> Vec<String> {
+    if summary.currencies.is_empty() {
+        return vec![escape_md(&format!("No expenses in {}.", summary.year))];
+    }
+
+    let mut heading = format!("*{}*", escape_md(&format!("{} in review", summary.year)));
+    if let Some((day, count)) = summary.busiest_day {
+        let expenses = match count {
+            1 => "1 expense".to_string(),
+            n => format!("{} expenses", n),
+        };
+        heading.push_str(&escape_md(&format!(
+            "\n📅 Busiest day: {} with {}",
+            day, expenses
+        )));
+    }
+    let mut sections = vec![heading];
+
+    for c in &summary.currencies {
+        let amount = |a: f64| format_amount(a, c.exponent);
+        let mut rows = vec![("Per month".to_string(), String::new())];
+        for (month, total) in c.months.iter().enumerate() {
+            let name = chrono::Month::try_from(month as u8 + 1).expect("12 months");
+            rows.push((format!("  {}", &name.name()[..3]), amount(*total)));
+        }
+        rows.push(("  Total".to_string(), amount(c.total)));
+        rows.push(("Top categories".to_string(), String::new()));
+        for (category, total) in &c.top_categories {
+            rows.push((
+                format!("  {} ({:.0}%)", category, c.share(*total)),
+                amount(*total),
+            ));
+        }
+        rows.push(("Per user".to_string(), String::new()));
+        for (user, total) in &c.users {
+            rows.push((format!("  {}", user), amount(*total)));
+        }
+
+        let mut section = format!(
+            "*{}*\n```\n{}\n```",
+            escape_md(&c.currency),
+            escape_code(&align_columns(&rows))
+        );
+        if let Some(b) = &c.biggest {
+            section.push_str(&escape_md(&format!(
+                "\n🏆 Biggest: {} {}, {} on {}",
+                amount(b.amount),
+                c.currency,
+                b.category,
+                time::render(b.timestamp, tz, Style::Date)
+            )));
+            if let Some(comment) = &b.comment {
+                section.push_str(" · ");
+                section.push_str(&markdown::comment(comment));
+            }
+        }
+        sections.push(section);
+    }
+    split_messages(sections)
+}
+
+/// `/category stats`. Windows without expenses show dashes, so that they
+/// don't read as real zeros.
+/// Levels of a sparkline, lowest first.
+const SPARKS: [char; 7] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇'];
+
+/// The values as a sparkline scaled to the largest, with that largest, or
+/// `None` if all are 0. Zeros are left blank, so that any spend shows.
+fn sparkline(values: &[f64]) -> Option<(String, f64)> {
+    let max = values.iter().copied().fold(0.0, f64::max);
+    if max <= 0.0 {
+        return None;
+    }
+    let line = values
+        .iter()
+        .map(|&v| {
+            if v <= 0.0 {
+                return ' ';
+            }
+            let level = (v / max * SPARKS.len() as f64).ceil() as usize;
+            SPARKS[level.clamp(1, SPARKS.len()) - 1]
+        })
+        .collect();
+    Some((line, max))
+}
+
+pub fn render_category_detail(detail: &CategoryDetail, tz: Tz) -> String {
+    if detail.currencies.is_empty() {
+        return escape_md(&format!(
+            "No expenses in {} in the last 12 months.",
+            detail.category
+        ));
+    }
+    let mut sections = vec![format!("*{}*", escape_md(&detail.category))];
+    for c in &detail.currencies {
+        let amount = |a: f64| format_amount(a, c.exponent);
+        let dated = |(a, at): (f64, DateTime<Utc>)| {
+            format!(
+                "{} on {}",
+                amount(a),
+                time::render(at, tz, Style::ShortDate)
+            )
+        };
+        let mut rows = Vec::new();
+        if let Some((line, max)) = sparkline(&c.weekly) {
+            let trend = format!("{} weeks {}  max {}", c.weekly.len(), line, amount(max));
+            rows.push((trend, String::new()));
+        }
+        for w in &c.windows {
+            rows.push((
+                match w.months {
+                    1 => "Last month".to_string(),
+                    n => format!("Last {} months", n),
+                },
+                String::new(),
+            ));
+            let values = match &w.spend {
+                Some(s) => [
+                    amount(s.total),
+                    s.count.to_string(),
+                    w.average().map(amount).unwrap_or_default(),
+                    w.per_week().map(amount).unwrap_or_default(),
+                    dated(s.min),
+                    dated(s.max),
+                ],
+                None => std::array::from_fn(|_| "—".to_string()),
+            };
+            for (label, value) in ["Total", "Expenses", "Per expense", "Per week", "Min", "Max"]
+                .into_iter()
+                .zip(values)
+            {
+                rows.push((format!("  {}", label), value));
+            }
+        }
+        sections.push(format!(
+            "*{}*\n```\n{}\n```",
+            escape_md(&c.currency),
+            escape_code(&align_columns(&rows))
+        ));
+    }
+    if !detail.top_comments.is_empty() {
+        let mut lines = vec!["*Top comments*".to_string()];
+        for (comment, count) in &detail.top_comments {
+            lines.push(format!("{} × {}", markdown::comment(comment), count));
+        }
+        sections.push(lines.join("\n"));
+    }
+    sections.join("\n\n")
+}
+
+/// `/fun`: the month's records, one line each, leaving out those without
+/// data.
+pub fn render_fun(stats: &FunStats, tz: Tz) -> String {
+    let month = stats.month.format("%B %Y").to_string();
+    let Some((logger, logged)) = &stats.top_logger else {
+        return escape_md(&format!("No expenses in {}.", month));
+    };
+    let expenses = |n: i64| match n {
+        1 => "1 expense".to_string(),
+        n => format!("{} expenses", n),
+    };
+    let mut lines = vec![
+        format!("*{}*", escape_md(&format!("{} for fun", month))),
+        escape_md(&format!(
+            "📝 Most logged: {} with {}",
+            logger,
+            expenses(*logged)
+        )),
+    ];
+    for l in &stats.largest {
+        let mut line = escape_md(&format!(
+            "💸 Largest: {} {} by {}",
+            format_amount(l.amount, l.exponent),
+            l.currency,
+            l.user
+        ));
+        if let Some(comment) = &l.comment {
+            line.push_str(" · ");
+            line.push_str(&markdown::comment(comment));
+        }
+        lines.push(line);
+    }
+    if let Some((category, count)) = &stats.top_category {
+        lines.push(escape_md(&format!(
+            "🏷️ Most used: {} with {}",
+            category,
+            expenses(*count)
+        )));
+    }
+    if let Some(streak) = stats.streak {
+        lines.push(escape_md(&match streak.days {
+            1 => format!("🔥 Longest streak: 1 day, {}", streak.first),
+            days => format!("🔥 Longest streak: {} days from {}", days, streak.first),
+        }));
+    }
+    for (icon, label, expense) in [
+        ("🌅", "Earliest", &stats.earliest),
+        ("🌙", "Latest", &stats.latest),
+    ] {
+        if let Some(e) = expense {
+            lines.push(escape_md(&format!(
+                "{} {}: {} by {}, {}",
+                icon,
+                label,
+                time::render(e.timestamp, tz, Style::Time),
+                e.user,
+                e.category
+            )));
+        }
+    }
+    lines.join("\n")
+}
+
+/// The intensity emoji of the heatmap, from no spend to the most.
+const INTENSITIES: [&str; 4] = ["⬜", "🟨", "🟧", "🟥"];
+
+/// The value at fraction `p` of the way through the sorted `values`,
+/// interpolated between neighbours.
+fn quantile(sorted: &[f64], p: f64) -> f64 {
+    let at = (sorted.len() - 1) as f64 * p;
+    let (below, above) = (at.floor() as usize, at.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (at - below as f64)
+}
+
+/// The intensity of each of `values`, an index into [`INTENSITIES`]: 0 for
+/// nothing spent, otherwise 1 up to the median of the days with spend, 2 up
+/// to their upper quartile and 3 above it. Equal values share one, so days
+/// spent alike are never told apart.
+fn intensities(values: &[f64]) -> Vec<usize> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|&v| v > 0.0).collect();
+    sorted.sort_by(f64::total_cmp);
+    if sorted.is_empty() {
+        return vec![0; values.len()];
+    }
+    let (median, upper) = (quantile(&sorted, 0.5), quantile(&sorted, 0.75));
+    values
+        .iter()
+        .map(|&v| match v {
+            v if v <= 0.0 => 0,
+            v if v > upper => 3,
+            v if v > median => 2,
+            _ => 1,
+        })
+        .collect()
+}
+
+/// `/report heatmap`: the average spend of each weekday over the last
+/// `weeks`, one section per currency.
+pub fn render_heatmap(
+    currencies: &[WeekdayAverages],
+    weeks: u32,
+    category: Option<&Category>,
+) -> String {
+    let scope = match category {
+        Some(category) => format!(" on {}", category.name),
+        None => String::new(),
+    };
+    if currencies.is_empty() {
+        return escape_md(&format!(
+            "No expenses{} over the last {} weeks.",
+            scope, weeks
+        ));
+    }
+    let mut lines = vec![format!(
+        "*{}*",
+        escape_md(&format!(
+            "Average spend{} by weekday, last {} weeks",
+            scope, weeks
+        ))
+    )];
+    for currency in currencies {
+        lines.push(String::new());
+        lines.push(escape_md(&currency.currency));
+        let weekdays = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+        for ((weekday, average), intensity) in weekdays
+            .iter()
+            .zip(currency.averages)
+            .zip(intensities(&currency.averages))
+        {
+            lines.push(escape_md(&format!(
+                "{} {} {}",
+                INTENSITIES[intensity],
+                weekday,
+                format_amount(average, currency.exponent)
+            )));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Preview of a draft. It is regenerated after every change, so it always
+/// matches what would be saved.
+pub fn render_draft(draft: &ActiveTransaction, tz: Tz, options: RenderOptions) -> String {
+    let icon = draft.category.icon.as_deref().unwrap_or("🏷️");
+    let charged = match draft.awaits_charge() {
+        true => "?".to_string(),
+        false => format_amount(draft.amount, draft.account.exponent),
+    };
+    let mut lines = vec![amount_heading(
+        format!("{} {}", charged, draft.account.currency),
+        draft.original.as_ref(),
+        options,
+    )];
+    if !options.mask_amounts {
+        lines.extend(amount_note(draft));
+    }
+    lines.extend([
+        format!(
+            "{} {}{}",
+            icon,
+            escape_md(&draft.category.name),
+            if draft.category_by_rule {
+                " \\(rule\\)"
+            } else {
+                ""
+            }
+        ),
+        format!("🏦 {}", escape_md(&draft.account.name)),
+        format!(
+            "🕒 {}",
+            escape_md(&time::render_datetime(
+                draft.timestamp,
+                tz,
+                options.lang,
+                DateStyle::Short
+            ))
+        ),
+    ]);
+    if let Some(comment) = &draft.comment {
+        lines.push(format!("💬 {}", markdown::comment(comment)));
+    }
+    if let Some(user) = &draft.split_with {
+        lines.push(split_line(&user.display_name));
+    }
+    lines.extend(draft.location.map(location_line));
+    lines.join("\n")
+}
+
+fn location_line((latitude, longitude): (f64, f64)) -> String {
+    format!(
+        "📍 {}",
+        escape_md(&format!("{:.5}, {:.5}", latitude, longitude))
+    )
+}
+
+/// Supergroup ids are the channel id behind `-100`, which `t.me/c/` links
+/// take without it.
+const SUPERGROUP_PREFIX: i64 = -1_000_000_000_000;
+
+/// A `t.me` link to the message: by username in public groups, by channel
+/// id in other supergroups, for their members. Basic groups have no links.
+fn message_link(source: &SourceMessage) -> Option<String> {
+    if let Some(username) = &source.username {
+        return Some(format!("https://t.me/{}/{}", username, source.message_id));
+    }
+    (source.chat_id < SUPERGROUP_PREFIX).then(|| {
+        format!(
+            "https://t.me/c/{}/{}",
+            SUPERGROUP_PREFIX - source.chat_id,
+            source.message_id
+        )
+    })
+}
+
+fn source_line(source: &SourceMessage) -> Option<String> {
+    // Links hold no character that needs escaping inside the parentheses.
+    message_link(source).map(|link| format!("🔗 [Original message]({})", link))
+}
+
+/// When `expense` was logged, if that was over an hour away from when it
+/// was made, like cash spend back-filled later.
+fn logged_line(expense: &ExpenseDetails, tz: Tz, lang: Lang) -> Option<String> {
+    let apart = (expense.created_at - expense.timestamp).abs();
+    (apart > chrono::TimeDelta::hours(1)).then(|| {
+        format!(
+            "📝 {}",
+            escape_md(&format!(
+                "Logged {}",
+                time::render_datetime(expense.created_at, tz, lang, DateStyle::Long)
+            ))
+        )
+    })
+}
+
+/// Who changed an expense after it was saved, like `Edited by Alex on Dec 5`.
+fn modified_line(modification: &Modification, tz: Tz) -> String {
+    let user = modification
+        .user
+        .clone()
+        .unwrap_or_else(|| modification.user_id.to_string());
+    format!(
+        "✏️ {}",
+        escape_md(&format!(
+            "Edited by {} on {}",
+            user,
+            time::render(modification.at, tz, Style::ShortDate)
+        ))
+    )
+}
+
+fn split_line(with: &str) -> String {
+    format!("👥 {}", escape_md(&format!("Split 50/50 with {}", with)))
+}
+
+/// The draft with a question whether its unusually large amount is meant.
+/// Like any draft it shows the amount its user just typed.
+pub fn render_unusual(draft: &ActiveTransaction, stats: &CategoryStats, tz: Tz) -> String {
+    format!(
+        "{}\n\n⚠️ {}",
+        render_draft(draft, tz, RenderOptions::FULL),
+        escape_md(&format!(
+            "This is much larger than your usual {} (avg {}) — save anyway?",
+            draft.category.name,
+            format_amount(stats.mean, draft.account.exponent)
+        ))
+    )
+}
+
+/// The draft with a question whether to go over the daily limit of its
+/// account, `day` counting the draft in.
+pub fn render_over_limit(draft: &ActiveTransaction, day: &DailyUsage, tz: Tz) -> String {
+    format!(
+        "{}\n\n⚠️ {}",
+        render_draft(draft, tz, RenderOptions::FULL),
+        escape_md(&format!(
+            "This brings {} to {} / {} {} on {}, over its daily limit — save anyway?",
+            draft.account.name,
+            format_amount(day.spent, day.exponent),
+            format_amount(day.limit, day.exponent),
+            draft.account.currency,
+            day.date
+        ))
+    )
+}
+
+/// The line under a saved expense with the new total of the day on its
+/// account against the limit, `None` in chats that mask amounts.
+pub fn render_daily_limit(
+    draft: &ActiveTransaction,
+    day: &DailyUsage,
+    today: NaiveDate,
+    options: RenderOptions,
+) -> Option<String> {
+    if options.mask_amounts {
+        return None;
+    }
+    let when = match day.date == today {
+        true => "today".to_string(),
+        false => format!("on {}", day.date),
+    };
+    let mut line = format!(
+        "🎯 {} {}: {} / {} {}",
+        draft.account.name,
+        when,
+        format_amount(day.spent, day.exponent),
+        format_amount(day.limit, day.exponent),
+        draft.account.currency
+    );
+    if day.is_over() {
+        line.push_str(", over the daily limit ⚠️");
+    }
+    Some(escape_md(&line))
+}
+
+/// Shows the calculation the amount was typed as, or else points out how
+/// the typed amount could also have been meant.
+fn amount_note(draft: &ActiveTransaction) -> Option<String> {
+    let amount = format_amount(draft.amount, draft.account.exponent);
+    let note = match (&draft.amount_calculation, draft.amount_alternative) {
+        (Some(calculation), _) => format!("🧮 {} = {}", calculation, amount),
+        (None, Some(alternative)) => format!("❔ Read as {}, not {}", amount, alternative),
+        (None, None) => return None,
+    };
+    Some(escape_md(&note))
+}
+
+/// The amount in bold, or for one paid in another currency the original
+/// amount in bold with what the account was `charged` after it.
+fn amount_heading(
+    charged: String,
+    original: Option<&OriginalAmount>,
+    options: RenderOptions,
+) -> String {
+    if options.mask_amounts {
+        return format!("💶 {}", MASKED);
+    }
+    match original {
+        None => format!("💶 *{}*", escape_md(&charged)),
+        Some(original) => format!(
+            "💶 *{} {}* {}",
+            escape_md(&format_amount(original.amount, original.exponent)),
+            escape_md(&original.currency),
+            escape_md(&format!("({})", charged))
+        ),
+    }
+}
+
+/// Everything known about a stored expense. Parts whose rows are gone are
+/// shown as unknown.
+pub fn render_expense(expense: &ExpenseDetails, tz: Tz, options: RenderOptions) -> String {
+    let unknown = || "?".to_string();
+    let icon = expense.category_icon.as_deref().unwrap_or("🏷️");
+    let mut lines = vec![
+        amount_heading(
+            format!(
+                "{} {}",
+                format_amount(expense.amount, expense.exponent),
+                expense.currency.clone().unwrap_or_else(unknown)
+            ),
+            expense.original.as_ref(),
+            options,
+        ),
+        format!(
+            "{} {}",
+            icon,
+            escape_md(&expense.category.clone().unwrap_or_else(unknown))
+        ),
+        format!(
+            "🏦 {}",
+            escape_md(&expense.account.clone().unwrap_or_else(unknown))
+        ),
+        format!(
+            "👤 {}",
+            escape_md(
+                &expense
+                    .user
+                    .clone()
+                    .unwrap_or_else(|| expense.user_id.to_string())
+            )
+        ),
+        format!(
+            "🕒 {}",
+            escape_md(&time::render_datetime(
+                expense.timestamp,
+                tz,
+                options.lang,
+                DateStyle::Long
+            ))
+        ),
+    ];
+    if let Some(comment) = &expense.comment {
+        lines.push(format!("💬 {}", markdown::comment(comment)));
+    }
+    if let Some(id) = expense.split_user_id {
+        let name = expense.split_user.clone().unwrap_or_else(|| id.to_string());
+        lines.push(split_line(&name));
+    }
+    lines.extend(logged_line(expense, tz, options.lang));
+    lines.extend(expense.location.map(location_line));
+    lines.extend(expense.modified.as_ref().map(|m| modified_line(m, tz)));
+    lines.extend(expense.source_message.as_ref().and_then(source_line));
+    lines.join("\n")
+}
+
+/// One line telling the household that someone logged `expense`.
+pub fn render_notification(expense: &ExpenseDetails) -> String {
+    let unknown = || "?".to_string();
+    let user = expense
+        .user
+        .clone()
+        .unwrap_or_else(|| expense.user_id.to_string());
+    let mut line = escape_md(&format!(
+        "🔔 {} logged {} {} · {} {}",
+        user,
+        format_amount(expense.amount, expense.exponent),
+        expense.currency.clone().unwrap_or_else(unknown),
+        expense.category_icon.as_deref().unwrap_or("🏷️"),
+        expense.category.clone().unwrap_or_else(unknown)
+    ));
+    if let Some(comment) = &expense.comment {
+        line.push_str(" · ");
+        line.push_str(&markdown::comment(comment));
+    }
+    line
+}
+
+/// One line of a `/last` listing, with who logged it if `with_user`. A
+/// masked amount goes after the account, as in [`render_saved_line`].
+/// Expenses of the last week say how long ago they were made at `now`.
+pub fn render_expense_line(
+    expense: &ExpenseDetails,
+    (now, tz): (DateTime<Utc>, Tz),
+    options: RenderOptions,
+    with_user: bool,
+) -> String {
+    let unknown = || "?".to_string();
+    let amount = options.amount(
+        expense.amount,
+        expense.exponent,
+        &expense.currency.clone().unwrap_or_else(unknown),
+    );
+    let category = format!(
+        "{} {}",
+        expense.category_icon.as_deref().unwrap_or("🏷️"),
+        expense.category.clone().unwrap_or_else(unknown)
+    );
+    let account = expense.account.clone().unwrap_or_else(unknown);
+    let mut parts = vec![time::render_datetime(
+        expense.timestamp,
+        tz,
+        options.lang,
+        DateStyle::Relative(now),
+    )];
+    match options.mask_amounts {
+        true => parts.extend([category, account, amount]),
+        false => parts.extend([amount, category, account]),
+    }
+    if with_user {
+        let user = expense.user.clone();
+        parts.push(user.unwrap_or_else(|| expense.user_id.to_string()));
+    }
+    let mut line = escape_md(&parts.join(" · "));
+    if let Some(comment) = &expense.comment {
+        line.push_str(" · ");
+        line.push_str(&markdown::comment(comment));
+    }
+    line
+}
+
+/// Spend of the month so far, shown under a saved expense of `category`.
+pub fn render_month_to_date(
+    mtd: &MonthToDate,
+    category: &Category,
+    options: RenderOptions,
+) -> String {
+    let count = match mtd.count {
+        1 => "1 expense".to_string(),
+        n => format!("{} expenses", n),
+    };
+    let mut lines = vec![escape_md(&format!(
+        "📅 This month: {} across {}",
+        options.amount(mtd.total, mtd.exponent, &mtd.currency),
+        count
+    ))];
+    if let Some(total) = mtd.category(&category.name) {
+        lines.push(escape_md(&format!(
+            "{} {}: {}",
+            category.icon.as_deref().unwrap_or("🏷️"),
+            category.name,
+            options.amount(total.total, total.exponent, &mtd.currency)
+        )));
+    }
+    lines.join("\n")
+}
+
+pub fn render_settings(settings: &Settings) -> String {
+    let on_off = |on| if on { "on" } else { "off" };
+    let format = match settings.number_format {
+        None => "from your Telegram language",
+        Some(Locale::DecimalPoint) => "1,234.56",
+        Some(Locale::DecimalComma) => "1.234,56",
+    };
+    [
+        "*Settings*".to_string(),
+        escape_md(&format!(
+            "📅 Month-to-date total after saving: {}",
+            on_off(settings.month_to_date)
+        )),
+        escape_md(&format!("🔢 Number format: {}", format)),
+        escape_md(&format!(
+            "🔒 Commit only after picking a category: {}",
+            on_off(settings.strict_commit)
+        )),
+        escape_md(&format!(
+            "🧮 Report totals rounded to whole units: {}",
+            on_off(settings.round_totals)
+        )),
+    ]
+    .join("\n")
+}
+
+/// Why `/review` lists an expense.
+pub fn render_review_reasons(reasons: &[ReviewReason]) -> String {
+    let reasons: Vec<&str> = reasons
+        .iter()
+        .map(|r| match r {
+            ReviewReason::DefaultCategory => "category never picked",
+            ReviewReason::MissingComment => "no comment",
+        })
+        .collect();
+    format!("⚠️ {}", escape_md(&reasons.join(", ")))
+}
+
+/// One line confirmation of a committed expense. A masked amount goes
+/// after the account rather than first.
+pub fn render_saved_line(draft: &ActiveTransaction, options: RenderOptions) -> String {
+    let icon = draft.category.icon.as_deref().unwrap_or("🏷️");
+    let amount = options.amount(
+        draft.amount,
+        draft.account.exponent,
+        &draft.account.currency,
+    );
+    let category = format!("{} {}", icon, draft.category.name);
+    let parts = match options.mask_amounts {
+        true => [category, draft.account.name.clone(), amount],
+        false => [amount, category, draft.account.name.clone()],
+    };
+    let mut line = escape_md(&parts.join(" · "));
+    if let Some(comment) = &draft.comment {
+        line.push_str(" · ");
+        line.push_str(&markdown::comment(comment));
+    }
+    let note = match options.mask_amounts {
+        true => None,
+        false => amount_note(draft),
+    };
+    match note {
+        Some(note) => format!("✅ {}\n{}", line, note),
+        None => format!("✅ {}", line),
+    }
+}
+
+/// The confirmation of a committed `draft`, as much of it as the chat's
+/// `/settings confirm` shows. It replaces the draft's message if
+/// `replaces_draft`, or else is sent on its own. The `add_ons`, like the
+/// month to date, only go with full confirmations.
+pub fn render_confirmation(
+    draft: &ActiveTransaction,
+    add_ons: &[String],
+    replaces_draft: bool,
+    (tz, options): (Tz, RenderOptions),
+) -> String {
+    let (text, separator) = match (options.confirm, replaces_draft) {
+        (ConfirmMode::Silent, _) => return "✅".to_string(),
+        (ConfirmMode::Compact, _) => return render_saved_line(draft, options),
+        (ConfirmMode::Full, true) => (
+            format!("✅ Saved\n{}", render_draft(draft, tz, options)),
+            "\n\n",
+        ),
+        (ConfirmMode::Full, false) => (render_saved_line(draft, options), "\n"),
+    };
+    std::iter::once(text)
+        .chain(add_ons.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn lines_of(count: usize) -> String {
+    match count {
+        1 => "1 line".to_string(),
+        n => format!("{} lines", n),
+    }
+}
+
+/// A line of a batch that can be saved, numbered as typed.
+fn batch_expense(number: usize, draft: &ActiveTransaction, options: RenderOptions) -> String {
+    let mut line = escape_md(&format!(
+        "{}. {} · {} {}",
+        number,
+        options.amount(
+            draft.amount,
+            draft.account.exponent,
+            &draft.account.currency
+        ),
+        draft.category.icon.as_deref().unwrap_or("🏷️"),
+        draft.category.name
+    ));
+    if let Some(comment) = &draft.comment {
+        line.push_str(" · ");
+        line.push_str(&markdown::comment(comment));
+    }
+    line
+}
+
+/// The account of the lines that can be saved, all on the same one.
+fn batch_account(batch: &[BatchLine]) -> Option<&str> {
+    batch
+        .iter()
+        .find_map(|line| line.draft.as_ref().ok())
+        .map(|draft| draft.account.name.as_str())
+}
+
+/// Summary of a batch before it is committed, every line with what it reads
+/// as or why it can't be saved. Like drafts it shows amounts.
+pub fn render_batch(batch: &[BatchLine]) -> String {
+    let valid = batch.iter().filter(|line| line.draft.is_ok()).count();
+    let mut heading = format!("📋 {}, {} ready to save", lines_of(batch.len()), valid);
+    if let Some(account) = batch_account(batch) {
+        heading.push_str(&format!(" from {}", account));
+    }
+    let mut lines = vec![escape_md(&heading)];
+    for line in batch {
+        lines.push(match &line.draft {
+            Ok(draft) => format!(
+                "✅ {}",
+                batch_expense(line.number, draft, RenderOptions::FULL)
+            ),
+            Err(reason) => format!(
+                "❌ {} {}",
+                escape_md(&format!("{}.", line.number)),
+                escape_md(&format!("{} — {}", line.text, reason))
+            ),
+        });
+    }
+    lines.join("\n")
+}
+
+/// Confirmation of a committed batch: the saved lines and the skipped ones.
+pub fn render_batch_saved(batch: &[BatchLine], options: RenderOptions) -> String {
+    let saved: Vec<String> = batch
+        .iter()
+        .filter_map(|line| {
+            let draft = line.draft.as_ref().ok()?;
+            Some(batch_expense(line.number, draft, options))
+        })
+        .collect();
+    let count = match saved.len() {
+        1 => "1 expense".to_string(),
+        n => format!("{} expenses", n),
+    };
+    let mut heading = format!("✅ Saved {}", count);
+    if let Some(account) = batch_account(batch) {
+        heading.push_str(&format!(" from {}", account));
+    }
+    let mut lines = vec![escape_md(&heading)];
+    lines.extend(saved);
+    let skipped: Vec<String> = batch
+        .iter()
+        .filter(|line| line.draft.is_err())
+        .map(|line| line.number.to_string())
+        .collect();
+    match skipped.as_slice() {
+        [] => {}
+        [number] => lines.push(escape_md(&format!("Skipped line {}.", number))),
+        numbers => lines.push(escape_md(&format!("Skipped lines {}.", numbers.join(", ")))),
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::draft;
+    use crate::model::Model;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn renders_debts() {
+        assert_eq!("Nobody owes anybody anything\\.", render_debts(&[]));
+        let debt = |debtor: &str, amount, currency: &str, exponent| Debt {
+            debtor: debtor.to_string(),
+            creditor: "Alex".to_string(),
+            currency: currency.to_string(),
+            exponent,
+            amount,
+        };
+        assert_eq!(
+            "Hanna owes Alex 123\\.45 EUR\nHanna owes Alex 1500 JPY",
+            render_debts(&[
+                debt("Hanna", 123.45, "EUR", 2),
+                debt("Hanna", 1500.0, "JPY", 0)
+            ])
+        );
+    }
+
+    #[test]
+    fn renders_amount_migration_report() {
+        let mut report = AmountMigrationReport {
+            finalized: false,
+            checked: 3,
+            mismatches: Vec::new(),
+        };
+        assert_eq!(
+            "*Amount migration*\n✅ All 3 amounts agree with their legacy value\\. /migrate finalize drops it\\.",
+            render_amount_migration(&report)
+        );
+        report.mismatches.push(AmountMismatch {
+            expense_id: 2,
+            legacy: 0.1 + 0.2,
+            minor: 31,
+            rederived: 30,
+            exponent: 2,
+        });
+        assert_eq!(
+            "*Amount migration*\n⚠️ 1 of 3 amounts differ from their legacy value by more than 0\\.005:\nexpense 2: 0\\.30000000000000004 stored as 0\\.31, 0\\.30 expected",
+            render_amount_migration(&report)
+        );
+    }
+
+    #[test]
+    fn renders_integrity_report() {
+        let mut report = IntegrityReport {
+            problems: Vec::new(),
+            orphans: Vec::new(),
+            row_counts: vec![("Currency".to_string(), 3), ("Expense".to_string(), 120)],
+        };
+        assert_eq!(
+            "*Integrity check*\n✅ No problems found\\.\n```\nCurrency    3\nExpense   120\n```",
+            render_integrity(&report)
+        );
+
+        report.problems.push("row 3 missing from index".to_string());
+        assert!(render_integrity(&report)
+            .starts_with("*Integrity check*\n⚠️ row 3 missing from index\n```"));
+    }
+
+    #[test]
+    fn renders_maintenance_report() {
+        let mut report = MaintenanceReport {
+            size_before: 12 * 1024 * 1024 + 300 * 1024,
+            size_after: 4 * 1024 * 1024,
+            free_pages: 2100,
+            vacuumed: true,
+            checkpoint: None,
+            took: std::time::Duration::from_millis(1300),
+        };
+        assert_eq!(
+            "*Maintenance*\nSize: 12\\.3 MB → 4\\.0 MB\n2100 free pages vacuumed\nTook 1\\.3 s\\.",
+            render_maintenance(&report)
+        );
+        let mut checkpoint = Checkpoint {
+            busy: false,
+            log_pages: 40,
+            checkpointed: 40,
+        };
+        report.checkpoint = Some(checkpoint);
+        assert!(render_maintenance(&report).contains("vacuumed\nLog: 40 pages checkpointed\nTook"));
+        checkpoint.busy = true;
+        checkpoint.checkpointed = 25;
+        report.checkpoint = Some(checkpoint);
+        assert!(render_maintenance(&report).contains(
+            "Log: 25 of 40 pages checkpointed, the rest held back by another connection\n"
+        ));
+        report.vacuumed = false;
+        report.free_pages = 3;
+        report.size_before = 900;
+        report.size_after = 900;
+        assert!(render_maintenance(&report)
+            .contains("Size: 900 B → 900 B\n3 free pages, too few to vacuum"));
+        assert_eq!("1.0 KB", format_size(1024));
+        assert_eq!("2.0 GB", format_size(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn renders_prune_report() {
+        let policy = |table, days| RetentionPolicy { table, days };
+        let report = PruneReport {
+            pruned: vec![
+                (policy(Retained::AuditLog, 365), 1200),
+                (policy(Retained::CallbackPayload, 2), 0),
+            ],
+            took: std::time::Duration::from_millis(250),
+        };
+        assert_eq!(
+            "*Pruning*\nAudit log: 1200 entries older than 365 days\nButton payloads: 0 payloads older than 2 days\nTook 0\\.2 s\\.",
+            render_prune(&report)
+        );
+    }
+
+    #[test]
+    fn intensities_by_quartile() {
+        // A weekend spike.
+        assert_eq!(
+            vec![1, 1, 0, 1, 2, 3, 3],
+            intensities(&[5.0, 4.0, 0.0, 6.0, 8.0, 30.0, 25.0])
+        );
+        assert_eq!(vec![0; 7], intensities(&[0.0; 7]));
+        assert_eq!(vec![1; 7], intensities(&[12.5; 7]));
+        // A single day with spend has nothing to stand out from.
+        assert_eq!(
+            vec![0, 0, 0, 0, 0, 1, 0],
+            intensities(&[0.0, 0.0, 0.0, 0.0, 0.0, 9.0, 0.0])
+        );
+        assert!(intensities(&[]).is_empty());
+    }
+
+    #[test]
+    fn renders_heatmap() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let groceries = model.get_category(1, 1).unwrap().unwrap();
+        let eur = WeekdayAverages {
+            currency: "EUR".to_string(),
+            exponent: 2,
+            averages: [0.0, 2.0, 0.0, 0.0, 0.0, 20.0, 5.0],
+        };
+        assert_eq!(
+            "*Average spend on Groceries by weekday, last 12 weeks*\n\nEUR\n⬜ Mon 0\\.00\n🟨 Tue 2\\.00\n⬜ Wed 0\\.00\n⬜ Thu 0\\.00\n⬜ Fri 0\\.00\n🟥 Sat 20\\.00\n🟨 Sun 5\\.00",
+            render_heatmap(&[eur], 12, Some(&groceries))
+        );
+        assert_eq!(
+            "No expenses over the last 12 weeks\\.",
+            render_heatmap(&[], 12, None)
+        );
+    }
+
+    #[test]
+    fn renders_year_in_review() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let texts = render_year(&model.year_summary(1, 2023, Tz::UTC).unwrap(), Tz::UTC);
+        assert_eq!(1, texts.len());
+        let text = &texts[0];
+        assert!(text.contains(
+            "*EUR*
+```
+Per month
+  Jan                0.00
+  Feb                0.00"
+        ));
+        assert!(text.contains(
+            "  Dec               50.75
+  Total             50.75
+Top categories
+  Groceries (100%)  50.75
+Per user
+  Alex              50.75
+```
+🏆 Biggest: 50\\.75 EUR, Groceries on 2023\\-12\\-01 · Bought groceries for the week"
+        ));
+        assert_eq!(
+            vec!["No expenses in 2020\\.".to_string()],
+            render_year(&model.year_summary(1, 2020, Tz::UTC).unwrap(), Tz::UTC)
+        );
+    }
+
+    #[test]
+    fn splits_long_messages_between_sections() {
+        let section = |c: char| c.to_string().repeat(1500);
+        let messages = split_messages(vec![section('a'), section('b'), section('c')]);
+        assert_eq!(
+            vec![
+                format!("{}\n\n{}", section('a'), section('b')),
+                section('c')
+            ],
+            messages
+        );
+        assert_eq!(vec!["x".to_string()], split_messages(vec!["x".to_string()]));
+    }
+
+    #[test]
+    fn renders_aligned_balance() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let date = NaiveDate::from_ymd_opt(2023, 12, 1).unwrap();
+        model.set_rate("USD", date, 0.9).unwrap();
+        model.set_rate("BYN", date, 0.25).unwrap();
+
+        let text = render_balance(&model.balances(1, "EUR").unwrap(), &[], (date, 14));
+        assert_eq!(
+            "```
+BYN
+  Family BYN     270.00
+  Subtotal       270.00
+EUR
+  Alex Savings   949.25
+  Subtotal       949.25
+USD
+  Hanna Daily    380.00
+  Subtotal       380.00
+Total EUR       1358.75
+
+Rates as of 2023-12-01
+```",
+            text
+        );
+    }
+
+    #[test]
+    fn renders_stale_rates() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .set_rate("USD", NaiveDate::from_ymd_opt(2023, 11, 2).unwrap(), 0.9)
+            .unwrap();
+        model
+            .set_rate("BYN", NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(), 0.25)
+            .unwrap();
+        let balances = model.balances(1, "EUR").unwrap();
+
+        let today = NaiveDate::from_ymd_opt(2023, 12, 5).unwrap();
+        assert_eq!(
+            "```
+BYN
+  Family BYN     270.00
+  Subtotal       270.00
+EUR
+  Alex Savings   949.25
+  Subtotal       949.25
+USD
+  Hanna Daily    380.00
+  Subtotal       380.00
+  (rate from 2023-11-02, may be stale)
+Total EUR       1358.75
+
+Rates as of 2023-11-02, some may be stale: /rate status
+```",
+            render_balance(&balances, &[], (today, 14))
+        );
+        // A rate of the same age is fine with a longer threshold.
+        let text = render_balance(&balances, &[], (today, 60));
+        assert!(!text.contains("stale"));
+    }
+
+    #[test]
+    fn renders_missing_rates_note() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .set_rate("USD", NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(), 0.9)
+            .unwrap();
+
+        let today = NaiveDate::from_ymd_opt(2023, 12, 10).unwrap();
+        let text = render_balance(&model.balances(1, "EUR").unwrap(), &[], (today, 14));
+        assert!(!text.contains("Total EUR"));
+        assert!(text.contains("No total in EUR: missing rates for BYN"));
+    }
+
+    #[test]
+    fn renders_daily_limits_in_balance() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_daily_limit(1, 1, Some(60.0)).unwrap();
+        model.set_daily_limit(1, 2, Some(80.0)).unwrap();
+        // The days of Alex's groceries and Hanna's electricity.
+        let day = |account, day| {
+            let noon = NaiveDate::from_ymd_opt(2023, 12, day)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+                .and_utc();
+            model
+                .daily_usage(1, account, (noon, Tz::UTC), None)
+                .unwrap()
+                .unwrap()
+        };
+        let daily = [day(1, 1), day(2, 4)];
+
+        let today = NaiveDate::from_ymd_opt(2023, 12, 4).unwrap();
+        let text = render_balance(&model.balances(1, "EUR").unwrap(), &daily, (today, 14));
+        assert_eq!(
+            "```
+BYN
+  Family BYN    270.00
+  Subtotal      270.00
+EUR
+  Alex Savings  949.25
+    today: 50.75 / 60.00
+  Subtotal      949.25
+USD
+  Hanna Daily   380.00
+    today: 100.00 / 80.00, over
+  Subtotal      380.00
+
+No total in EUR: missing rates for BYN, USD
+```",
+            text
+        );
+    }
+
+    #[test]
+    fn renders_draft() {
+        assert_eq!(
+            "💶 *50\\.75 EUR*\n🛒 Groceries\n🏦 Alex Savings\n🕒 Dec 1, 10:00\n💬 groceries for the week",
+            render_draft(&draft::tests::draft(), Tz::UTC, RenderOptions::FULL)
+        );
+    }
+
+    #[test]
+    fn renders_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(
+            "💶 *50\\.75 EUR*\n🛒 Groceries\n🏦 Alex Savings\n👤 Alex\n🕒 December 1, 2023, 11:00\n💬 Bought groceries for the week",
+            render_expense(&expense, Tz::Europe__Berlin, RenderOptions::FULL)
+        );
+
+        let orphan = ExpenseDetails {
+            account: None,
+            currency: None,
+            category: None,
+            category_icon: None,
+            user: None,
+            comment: None,
+            ..expense
+        };
+        assert_eq!(
+            "💶 *50\\.75 ?*\n🏷️ ?\n🏦 ?\n👤 1001\n🕒 December 1, 2023, 10:00",
+            render_expense(&orphan, Tz::UTC, RenderOptions::FULL)
+        );
+    }
+
+    #[test]
+    fn renders_when_back_filled_expenses_were_logged() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let mut expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        let rendered =
+            |expense: &ExpenseDetails| render_expense(expense, Tz::UTC, RenderOptions::FULL);
+        assert!(!rendered(&expense).contains("Logged"));
+
+        // Logged within the hour, as when typed in after paying.
+        expense.created_at = expense.timestamp + chrono::TimeDelta::minutes(60);
+        assert!(!rendered(&expense).contains("Logged"));
+        expense.created_at = expense.timestamp + chrono::TimeDelta::days(6);
+        assert!(rendered(&expense)
+            .contains("🕒 December 1, 2023, 10:00\n💬 Bought groceries for the week\n📝 Logged December 7, 2023, 10:00"));
+    }
+
+    #[test]
+    fn renders_who_edited_an_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let mut expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        expense.modified = Some(Modification {
+            user_id: 1002,
+            user: Some("Hanna".to_string()),
+            at: DateTime::parse_from_rfc3339("2023-12-05T10:00:00Z")
+                .unwrap()
+                .to_utc(),
+        });
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .ends_with("💬 Bought groceries for the week\n✏️ Edited by Hanna on Dec 5"));
+        expense.modified.as_mut().unwrap().user = None;
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .ends_with("✏️ Edited by 1002 on Dec 5"));
+    }
+
+    #[test]
+    fn renders_expense_lines() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let expense = model.get_expense_details(1, 2).unwrap().unwrap();
+        let at = |s| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        let later = (at("2023-12-12T12:00:00Z"), Tz::UTC);
+        assert_eq!(
+            "Dec 2, 12:30 · 20\\.00 USD · 🚌 Transportation · Hanna Daily · Taxi ride to the office",
+            render_expense_line(&expense, later, RenderOptions::FULL, false)
+        );
+        assert_eq!(
+            "Dec 2, 12:30 · 🚌 Transportation · Hanna Daily · 🔒 · Hanna · Taxi ride to the office",
+            render_expense_line(&expense, later, MASKED_OPTIONS, true)
+        );
+        let soon = (at("2023-12-02T15:00:00Z"), Tz::UTC);
+        let russian = RenderOptions {
+            lang: Lang::Ru,
+            ..RenderOptions::FULL
+        };
+        assert_eq!(
+            "2 часа назад · 20\\.00 USD · 🚌 Transportation · Hanna Daily · Taxi ride to the office",
+            render_expense_line(&expense, soon, russian, false)
+        );
+    }
+
+    #[test]
+    fn message_links() {
+        let link = |chat_id, username: Option<&str>| {
+            message_link(&SourceMessage {
+                chat_id,
+                message_id: 42,
+                username: username.map(str::to_string),
+            })
+        };
+        assert_eq!(
+            Some("https://t.me/c/1234567890/42".to_string()),
+            link(-1001234567890, None)
+        );
+        assert_eq!(
+            Some("https://t.me/flat_expenses/42".to_string()),
+            link(-1001234567890, Some("flat_expenses"))
+        );
+        // Basic groups and private chats can't be linked to.
+        assert_eq!(None, link(-123456789, None));
+        assert_eq!(None, link(1001, None));
+        assert_eq!(None, link(-1000000000000, None));
+    }
+
+    #[test]
+    fn renders_the_original_message() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let mut expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        expense.source_message = Some(SourceMessage {
+            chat_id: -1001234567890,
+            message_id: 7,
+            username: None,
+        });
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .ends_with("💬 Bought groceries for the week\n🔗 [Original message](https://t.me/c/1234567890/7)"));
+        expense.source_message.as_mut().unwrap().chat_id = -4001;
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .ends_with("💬 Bought groceries for the week"));
+    }
+
+    #[test]
+    fn renders_notification() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let expense = model.get_expense_details(1, 2).unwrap().unwrap();
+        assert_eq!(
+            "🔔 Hanna logged 20\\.00 USD · 🚌 Transportation · Taxi ride to the office",
+            render_notification(&expense)
+        );
+    }
+
+    #[test]
+    fn amounts_use_currency_decimals() {
+        assert_eq!("1500", format_amount(1500.0, 0));
+        assert_eq!("50.75", format_amount(50.75, 2));
+        assert_eq!("1.235", format_amount(1.235, 3));
+
+        let mut d = draft::tests::draft();
+        d.account.exponent = 3;
+        d.amount = 1.234;
+        assert!(render_draft(&d, Tz::UTC, RenderOptions::FULL).starts_with("💶 *1\\.234 EUR*"));
+        d.account.exponent = 0;
+        d.amount = 1500.0;
+        assert!(render_saved_line(&d, RenderOptions::FULL).starts_with("✅ 1500 EUR"));
+    }
+
+    #[test]
+    fn points_out_ambiguous_amounts() {
+        let mut d = draft::tests::draft();
+        d.amount = 1234.0;
+        d.amount_alternative = Some(1.234);
+        let text = render_draft(&d, Tz::UTC, RenderOptions::FULL);
+        assert!(
+            text.starts_with("💶 *1234\\.00 EUR*\n❔ Read as 1234\\.00, not 1\\.234\n🛒"),
+            "{}",
+            text
+        );
+        assert!(render_saved_line(&d, RenderOptions::FULL)
+            .ends_with("\n❔ Read as 1234\\.00, not 1\\.234"));
+    }
+
+    #[test]
+    fn shows_calculations_with_their_result() {
+        let mut d = draft::tests::draft();
+        d.amount = 16.0;
+        d.amount_calculation = Some("12.40+3.60".to_string());
+        let text = render_draft(&d, Tz::UTC, RenderOptions::FULL);
+        assert!(
+            text.starts_with("💶 *16\\.00 EUR*\n🧮 12\\.40\\+3\\.60 \\= 16\\.00\n🛒"),
+            "{}",
+            text
+        );
+        assert!(render_saved_line(&d, RenderOptions::FULL)
+            .ends_with("\n🧮 12\\.40\\+3\\.60 \\= 16\\.00"));
+    }
+
+    #[test]
+    fn marks_categories_picked_by_a_rule() {
+        let mut d = draft::tests::draft();
+        d.category_by_rule = true;
+        assert!(
+            render_draft(&d, Tz::UTC, RenderOptions::FULL).contains("\n🛒 Groceries \\(rule\\)\n")
+        );
+    }
+
+    #[test]
+    fn category_detail_shows_dashes_for_empty_windows() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = DateTime::from_timestamp(1_705_320_000, 0).unwrap();
+        let detail = model.category_detail_stats(1, 1, now, Tz::UTC).unwrap();
+        let text = render_category_detail(&detail, Tz::UTC);
+        assert!(
+            text.starts_with(
+                "*Groceries*\n\n*EUR*\n```\n12 weeks     ▇         max 50.75\nLast month\n"
+            ),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("\n  Total                     —\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("\nLast 3 months\n  Total                 50.75\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("\n  Min          50.75 on Dec 1\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.ends_with("\n\n*Top comments*\nbought groceries for the week × 1"),
+            "{}",
+            text
+        );
+
+        let pets = model.add_category(1, "Pets", None).unwrap();
+        let detail = model.category_detail_stats(1, pets, now, Tz::UTC).unwrap();
+        assert_eq!(
+            "No expenses in Pets in the last 12 months\\.",
+            render_category_detail(&detail, Tz::UTC)
+        );
+    }
+
+    #[test]
+    fn sparklines_scale_to_the_largest_week() {
+        assert_eq!(
+            Some(("▁▂ ▄▇▇".to_string(), 70.0)),
+            sparkline(&[5.0, 20.0, 0.0, 40.0, 70.0, 61.0])
+        );
+        assert_eq!(Some(("▇".to_string(), 0.5)), sparkline(&[0.5]));
+        assert_eq!(None, sparkline(&[0.0; 12]));
+        assert_eq!(None, sparkline(&[]));
+    }
+
+    #[test]
+    fn shows_the_original_amount() {
+        let mut d = draft::tests::draft();
+        d.amount = 0.0;
+        d.original = Some(OriginalAmount {
+            amount: 13.5,
+            currency: "USD".to_string(),
+            exponent: 2,
+        });
+        assert!(render_draft(&d, Tz::UTC, RenderOptions::FULL)
+            .starts_with("💶 *13\\.50 USD* \\(? EUR\\)\n🛒"));
+        d.amount = 12.43;
+        assert!(render_draft(&d, Tz::UTC, RenderOptions::FULL)
+            .starts_with("💶 *13\\.50 USD* \\(12\\.43 EUR\\)\n🛒"));
+
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .set_original_amount(1, 1, Some((13.5, "USD")))
+            .unwrap();
+        let expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .starts_with("💶 *13\\.50 USD* \\(50\\.75 EUR\\)\n🛒"));
+    }
+
+    #[test]
+    fn renders_splits() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.split_expense(1, 1, Some(1002)).unwrap();
+
+        let expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .ends_with("\n👥 Split 50/50 with Hanna"));
+        let mut d = draft::tests::draft();
+        d.split_with = model.get_user(1002).unwrap();
+        assert!(
+            render_draft(&d, Tz::UTC, RenderOptions::FULL).ends_with("\n👥 Split 50/50 with Hanna")
+        );
+    }
+
+    #[test]
+    fn renders_locations() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .set_expense_location(1, 1, Some((52.52, -13.405)))
+            .unwrap();
+
+        let expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert!(render_expense(&expense, Tz::UTC, RenderOptions::FULL)
+            .ends_with("\n📍 52\\.52000, \\-13\\.40500"));
+        let mut d = draft::tests::draft();
+        d.location = expense.location;
+        assert!(render_draft(&d, Tz::UTC, RenderOptions::FULL)
+            .ends_with("\n📍 52\\.52000, \\-13\\.40500"));
+    }
+
+    #[test]
+    fn renders_unusual_amounts() {
+        let stats = CategoryStats {
+            count: 5,
+            mean: 42.1,
+            std_dev: 3.0,
+        };
+        let text = render_unusual(&draft::tests::draft(), &stats, Tz::UTC);
+        assert!(
+            text.ends_with("\n\n⚠️ This is much larger than your usual Groceries \\(avg 42\\.10\\) — save anyway?"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn renders_daily_limits() {
+        let draft = draft::tests::draft();
+        let day = DailyUsage {
+            account_id: 1,
+            date: NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            exponent: 2,
+            limit: 50.0,
+            spent: 50.75,
+        };
+        let text = render_over_limit(&draft, &day, Tz::UTC);
+        assert!(
+            text.ends_with("\n\n⚠️ This brings Alex Savings to 50\\.75 / 50\\.00 EUR on 2023\\-12\\-01, over its daily limit — save anyway?"),
+            "{}",
+            text
+        );
+
+        let today = day.date;
+        assert_eq!(
+            Some(
+                "🎯 Alex Savings today: 50\\.75 / 50\\.00 EUR, over the daily limit ⚠️".to_string()
+            ),
+            render_daily_limit(&draft, &day, today, RenderOptions::FULL)
+        );
+        let under = DailyUsage {
+            limit: 60.0,
+            ..day.clone()
+        };
+        assert_eq!(
+            Some("🎯 Alex Savings on 2023\\-12\\-01: 50\\.75 / 60\\.00 EUR".to_string()),
+            render_daily_limit(
+                &draft,
+                &under,
+                today.succ_opt().unwrap(),
+                RenderOptions::FULL
+            )
+        );
+        let masked = RenderOptions {
+            mask_amounts: true,
+            ..RenderOptions::FULL
+        };
+        assert_eq!(None, render_daily_limit(&draft, &day, today, masked));
+    }
+
+    #[test]
+    fn renders_draft_in_local_time() {
+        let text = render_draft(
+            &draft::tests::draft(),
+            Tz::Europe__Berlin,
+            RenderOptions::FULL,
+        );
+        assert!(text.contains("🕒 Dec 1, 11:00"), "{}", text);
+        let russian = RenderOptions {
+            lang: Lang::Ru,
+            ..RenderOptions::FULL
+        };
+        let text = render_draft(&draft::tests::draft(), Tz::Europe__Berlin, russian);
+        assert!(text.contains("🕒 1 дек, 11:00"), "{}", text);
+    }
+
+    #[test]
+    fn renders_draft_without_comment_or_icon() {
+        let mut d = draft::tests::draft();
+        d.comment = None;
+        d.category.icon = None;
+        let text = render_draft(&d, Tz::UTC, RenderOptions::FULL);
+        assert!(text.contains("🏷️ Groceries"));
+        assert!(!text.contains("💬"));
+    }
+
+    #[test]
+    fn escapes_comment_in_draft() {
+        let mut d = draft::tests::draft();
+        for (comment, expected) in [
+            ("*bold*", "💬 \\*bold\\*"),
+            ("snake_case_name", "💬 snake\\_case\\_name"),
+            ("[link](http://x.y)", "💬 \\[link\\]\\(http://x\\.y\\)"),
+            ("`code`", "💬 \\`code\\`"),
+            (
+                "*_[]()~>#+-=|{}.!",
+                "💬 \\*\\_\\[\\]\\(\\)\\~\\>\\#\\+\\-\\=\\|\\{\\}\\.\\!",
+            ),
+        ] {
+            d.comment = Some(comment.to_string());
+            assert!(
+                render_draft(&d, Tz::UTC, RenderOptions::FULL).ends_with(expected),
+                "{} rendered as {}",
+                comment,
+                render_draft(&d, Tz::UTC, RenderOptions::FULL)
+            );
+        }
+    }
+
+    #[test]
+    fn renders_report() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let range = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+
+        let summary = model.summarize(1, range, chrono_tz::Tz::UTC).unwrap();
+        assert_eq!(
+            "*Report 2023\\-12\\-01 – 2023\\-12\\-31*
+```
+BYN
+  Entertainment    30.00
+  Total            30.00
+EUR
+  Groceries        50.75
+  Total            50.75
+USD
+  Utilities       100.00
+  Transportation   20.00
+  Total           120.00
+```",
+            render_report(&summary, RoundingMode::Exact)
+        );
+        let mut closed = summary;
+        closed.closed = true;
+        assert!(render_report(&closed, RoundingMode::Exact)
+            .starts_with("*🔒 Report 2023\\-12\\-01 – 2023\\-12\\-31*\n```\nBYN"));
+
+        let empty = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+        );
+        let summary = model.summarize(1, empty, chrono_tz::Tz::UTC).unwrap();
+        assert_eq!(
+            "No expenses from 2024\\-01\\-01 to 2024\\-01\\-31\\.",
+            render_report(&summary, RoundingMode::Exact)
+        );
+
+        let logged = model
+            .summarize_logged(1, range, &[], chrono_tz::Tz::UTC)
+            .unwrap();
+        assert!(render_report(&logged, RoundingMode::Exact)
+            .starts_with("*Report 2023\\-12\\-01 – 2023\\-12\\-31, by date logged*\n```\nBYN"));
+        let logged = model
+            .summarize_logged(1, empty, &[], chrono_tz::Tz::UTC)
+            .unwrap();
+        assert_eq!(
+            "No expenses logged from 2024\\-01\\-01 to 2024\\-01\\-31\\.",
+            render_report(&logged, RoundingMode::Exact)
+        );
+    }
+
+    #[test]
+    fn renders_subcategories_under_their_parent() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let bills = model.add_test_category(1, "Bills");
+        model.set_category_parent(1, 2, Some(bills)).unwrap();
+        model.set_category_parent(1, 4, Some(bills)).unwrap();
+        model
+            .insert_expense(&crate::model::NewExpense {
+                account_id: 2,
+                category_id: bills,
+                user_id: 1002,
+                timestamp: chrono::DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+                amount: 5.0,
+                comment: None,
+                category_confirmed: true,
+                source: crate::model::ExpenseSource::Manual,
+            })
+            .unwrap();
+        let range = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+
+        let summary = model.summarize(1, range, chrono_tz::Tz::UTC).unwrap();
+        assert_eq!(
+            "*Report 2023\\-12\\-01 – 2023\\-12\\-31*
+```
+BYN
+  Entertainment      30.00
+  Total              30.00
+EUR
+  Groceries          50.75
+  Total              50.75
+USD
+  Bills             125.00
+    Utilities       100.00
+    Transportation   20.00
+    (own)             5.00
+  Total             125.00
+```",
+            render_report(&summary, RoundingMode::Exact)
+        );
+    }
+
+    #[test]
+    fn renders_rounded_report() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        for category_id in [2, 4] {
+            model
+                .insert_expense(&crate::model::NewExpense {
+                    account_id: 1,
+                    category_id,
+                    user_id: 1001,
+                    timestamp: chrono::DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+                    amount: 10.40,
+                    comment: None,
+                    category_confirmed: true,
+                    source: crate::model::ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+        let range = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+
+        let summary = model.summarize(1, range, chrono_tz::Tz::UTC).unwrap();
+        // 71.55 EUR rounds to more than its rounded parts.
+        assert_eq!(
+            "*Report 2023\\-12\\-01 – 2023\\-12\\-31*
+```
+BYN
+  Entertainment    30
+  Total            30
+EUR
+  Groceries        51
+  Transportation   10
+  Utilities        10
+  Total            72
+USD
+  Utilities       100
+  Transportation   20
+  Total           120
+```
+_amounts rounded_",
+            render_report(&summary, RoundingMode::Whole)
+        );
+    }
+
+    #[test]
+    fn renders_report_exclusions() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let range = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+        let report = |exclude: &[i64], rounding| {
+            let summary = model
+                .summarize_excluding(1, range, exclude, chrono_tz::Tz::UTC)
+                .unwrap();
+            render_report(&summary, rounding)
+        };
+
+        let text = report(&[4], RoundingMode::Exact);
+        assert!(!text.contains("Utilities  "), "{}", text);
+        assert!(
+            text.ends_with("```\n_excluded Utilities: 100\\.00 USD_"),
+            "{}",
+            text
+        );
+        assert!(report(&[4], RoundingMode::Whole)
+            .ends_with("```\n_amounts rounded_\n_excluded Utilities: 100 USD_"));
+        assert_eq!(
+            "No expenses from 2023\\-12\\-01 to 2023\\-12\\-31\\.\n\
+             _excluded Groceries, Transportation, Entertainment, Utilities: 30\\.00 BYN, 50\\.75 EUR, 120\\.00 USD_",
+            report(&[1, 2, 3, 4], RoundingMode::Exact)
+        );
+    }
+
+    #[test]
+    fn renders_report_completeness() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let range = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+        let mut summary = model.summarize(1, range, Tz::UTC).unwrap();
+        let heading = |summary: &Summary| {
+            let text = render_report(summary, RoundingMode::Exact);
+            text.split("\n```").next().unwrap().to_string()
+        };
+        assert_eq!(
+            "*Report 2023\\-12\\-01 – 2023\\-12\\-31*",
+            heading(&summary)
+        );
+
+        let mut complete = |expenses, imported, needs_review| {
+            summary.completeness = Some(Completeness {
+                expenses,
+                imported,
+                needs_review,
+            });
+            heading(&summary)
+        };
+        assert_eq!(
+            "*Report 2023\\-12\\-01 – 2023\\-12\\-31*\n87 expenses \\(12 imported, 5 need review\\), see /review",
+            complete(87, 12, 5)
+        );
+        assert!(complete(4, 0, 0).ends_with("*\n4 expenses"));
+        assert!(complete(1, 1, 0).ends_with("*\n1 expense \\(1 imported\\)"));
+        assert!(complete(3, 0, 1).ends_with("*\n3 expenses \\(1 needs review\\), see /review"));
+        assert!(complete(0, 0, 0).ends_with("31*"));
+    }
+
+    #[test]
+    fn renders_forecast() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-10T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let month = crate::model::Period::ThisMonth.resolve(now, Tz::UTC, Calendar::default());
+
+        let summary = model.summarize(1, month, Tz::UTC).unwrap();
+        assert_eq!(
+            "*Forecast for December 2023, day 10 of 31*
+```
+                   spent  forecast
+BYN
+  Entertainment    30.00     93.00
+  Total            30.00     93.00
+EUR
+  Groceries        50.75    157.33
+  Total            50.75    157.33
+USD
+  Utilities       100.00    310.00
+  Transportation   20.00     62.00
+  Total           120.00    372.00
+```",
+            render_forecast(
+                &summary,
+                (&[], false),
+                now,
+                (Tz::UTC, Calendar::default()),
+                RoundingMode::Exact
+            )
+        );
+
+        // Budgets follow the forecasts, and `/report forecast` puts the
+        // furthest over them first.
+        model.set_budget(1, 2, "USD", Some(50.0)).unwrap();
+        model.set_budget(1, 4, "USD", Some(400.0)).unwrap();
+        let budgets = model
+            .budgets_with_remaining(1, month.start, Tz::UTC)
+            .unwrap();
+        let usd = |by_overrun| {
+            let text = render_forecast(
+                &summary,
+                (&budgets, by_overrun),
+                now,
+                (Tz::UTC, Calendar::default()),
+                RoundingMode::Exact,
+            );
+            text.split_once("USD\n").unwrap().1.to_string()
+        };
+        assert_eq!(
+            "  Utilities       100.00    310.00 of 400.00
+  Transportation   20.00     62.00 of 50.00 ⚠️
+  Total           120.00    372.00
+```",
+            usd(false)
+        );
+        assert_eq!(
+            "  Transportation   20.00     62.00 of 50.00 ⚠️
+  Utilities       100.00    310.00 of 400.00
+  Total           120.00    372.00
+```",
+            usd(true)
+        );
+
+        let january = crate::model::DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+        );
+        let summary = model.summarize(1, january, Tz::UTC).unwrap();
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-01T08:00:00Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            "*Forecast for January 2024, day 1 of 31*\nNo expenses yet\\.",
+            render_forecast(
+                &summary,
+                (&[], false),
+                now,
+                (Tz::UTC, Calendar::default()),
+                RoundingMode::Exact
+            )
+        );
+
+        // Months starting on the 25th are named by their days.
+        let salary = Calendar {
+            week_start: chrono::Weekday::Sun,
+            month_start: 25,
+        };
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-09T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let month = crate::model::Period::ThisMonth.resolve(now, Tz::UTC, salary);
+        let summary = model.summarize(1, month, Tz::UTC).unwrap();
+        assert!(render_forecast(
+            &summary,
+            (&[], false),
+            now,
+            (Tz::UTC, salary),
+            RoundingMode::Exact
+        )
+        .starts_with("*Forecast for 25 Nov – 24 Dec, day 15 of 30*"));
+    }
+
+    #[test]
+    fn renders_feed() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-03T09:30:00Z")
+            .unwrap()
+            .to_utc();
+        let month = crate::model::Period::ThisMonth.resolve(now, Tz::UTC, Calendar::default());
+
+        let summary = model.summarize(1, month, Tz::UTC).unwrap();
+        assert_eq!(
+            "*📌 December 2023 so far*
+```
+BYN
+  Entertainment    30.00
+  Total            30.00
+EUR
+  Groceries        50.75
+  Total            50.75
+USD
+  Utilities       100.00
+  Transportation   20.00
+  Total           120.00
+```
+Updated 2023\\-12\\-03 09:30",
+            render_feed(&summary, now, Tz::UTC, RenderOptions::FULL)
+        );
+
+        let summary = model
+            .summarize(
+                1,
+                crate::model::Period::ThisMonth.resolve(Utc::now(), Tz::UTC, Calendar::default()),
+                Tz::UTC,
+            )
+            .unwrap();
+        assert!(
+            render_feed(&summary, Utc::now(), Tz::UTC, RenderOptions::FULL)
+                .contains("\nNo expenses yet\\.\n")
+        );
+    }
+
+    #[test]
+    fn renders_month_to_date() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-15T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let usd = model.month_to_date(1, "USD", None, now, Tz::UTC).unwrap();
+        let utilities = model.get_category(1, 4).unwrap().unwrap();
+        assert_eq!(
+            "📅 This month: 120\\.00 USD across 2 expenses\n💡 Utilities: 100\\.00 USD",
+            render_month_to_date(&usd, &utilities, RenderOptions::FULL)
+        );
+
+        let eur = model.month_to_date(1, "EUR", None, now, Tz::UTC).unwrap();
+        assert_eq!(
+            "📅 This month: 50\\.75 EUR across 1 expense",
+            render_month_to_date(&eur, &utilities, RenderOptions::FULL)
+        );
+    }
+
+    #[test]
+    fn renders_review_reasons() {
+        assert_eq!(
+            "⚠️ category never picked, no comment",
+            render_review_reasons(&[ReviewReason::DefaultCategory, ReviewReason::MissingComment])
+        );
+    }
+
+    #[test]
+    fn renders_saved_line() {
+        let mut d = draft::tests::draft();
+        d.comment = Some("weekly (big) shop".to_string());
+        assert_eq!(
+            "✅ 50\\.75 EUR · 🛒 Groceries · Alex Savings · weekly \\(big\\) shop",
+            render_saved_line(&d, RenderOptions::FULL)
+        );
+    }
+
+    #[test]
+    fn confirmations_follow_the_chat() {
+        let mut d = draft::tests::draft();
+        d.comment = None;
+        let add_ons = ["📅 month to date".to_string(), "🎯 daily limit".to_string()];
+        let confirm = |mode, replaces_draft| {
+            let options = RenderOptions {
+                confirm: mode,
+                ..RenderOptions::FULL
+            };
+            render_confirmation(&d, &add_ons, replaces_draft, (Tz::UTC, options))
+        };
+        let line = "✅ 50\\.75 EUR · 🛒 Groceries · Alex Savings";
+
+        let full = confirm(ConfirmMode::Full, true);
+        assert!(full.starts_with(&format!(
+            "✅ Saved\n{}",
+            render_draft(&d, Tz::UTC, RenderOptions::FULL)
+        )));
+        assert!(full.ends_with("\n\n📅 month to date\n\n🎯 daily limit"));
+        assert_eq!(
+            format!("{}\n📅 month to date\n🎯 daily limit", line),
+            confirm(ConfirmMode::Full, false)
+        );
+        // A single line, whether it replaces the draft or not.
+        for replaces_draft in [true, false] {
+            assert_eq!(line, confirm(ConfirmMode::Compact, replaces_draft));
+            assert_eq!("✅", confirm(ConfirmMode::Silent, replaces_draft));
+        }
+    }
+
+    const MASKED_OPTIONS: RenderOptions = RenderOptions {
+        mask_amounts: true,
+        ..RenderOptions::FULL
+    };
+
+    #[test]
+    fn masks_amounts_of_confirmations() {
+        let mut d = draft::tests::draft();
+        d.comment = None;
+        d.amount_calculation = Some("40+10.75".to_string());
+        assert_eq!(
+            "✅ 50\\.75 EUR · 🛒 Groceries · Alex Savings\n🧮 40\\+10\\.75 \\= 50\\.75",
+            render_saved_line(&d, RenderOptions::FULL)
+        );
+        assert_eq!(
+            "✅ 🛒 Groceries · Alex Savings · 🔒",
+            render_saved_line(&d, MASKED_OPTIONS)
+        );
+        assert_eq!(
+            "💶 🔒\n🛒 Groceries\n🏦 Alex Savings\n🕒 Dec 1, 10:00",
+            render_draft(&d, Tz::UTC, MASKED_OPTIONS)
+        );
+
+        let model = Model::new(true);
+        model.fill_test_data();
+        let expense = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(
+            "💶 🔒\n🛒 Groceries\n🏦 Alex Savings\n👤 Alex\n🕒 December 1, 2023, 10:00\n💬 Bought groceries for the week",
+            render_expense(&expense, Tz::UTC, MASKED_OPTIONS)
+        );
+
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-15T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let usd = model.month_to_date(1, "USD", None, now, Tz::UTC).unwrap();
+        let utilities = model.get_category(1, 4).unwrap().unwrap();
+        assert_eq!(
+            "📅 This month: 🔒 across 2 expenses\n💡 Utilities: 🔒",
+            render_month_to_date(&usd, &utilities, MASKED_OPTIONS)
+        );
+    }
+
+    #[test]
+    fn masks_amounts_of_the_feed() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-03T09:30:00Z")
+            .unwrap()
+            .to_utc();
+        let month = crate::model::Period::ThisMonth.resolve(now, Tz::UTC, Calendar::default());
+        let summary = model.summarize(1, month, Tz::UTC).unwrap();
+        assert_eq!(
+            "*📌 December 2023 so far*
+```
+BYN
+  Entertainment   🔒
+  Total           🔒
+EUR
+  Groceries       🔒
+  Total           🔒
+USD
+  Utilities       🔒
+  Transportation  🔒
+  Total           🔒
+```
+Updated 2023\\-12\\-03 09:30",
+            render_feed(&summary, now, Tz::UTC, MASKED_OPTIONS)
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Guards against taps on a button arriving again before the first one is
+//! handled, as clients on a flaky connection let them. Even when the result
+//! is the same, each repeat edits the message and asks the database again.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// After this a key counts as free again, in case its handler got stuck.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The answer to a tap dropped as a repeat.
+pub const WORKING: &str = "Working…";
+
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Keys being worked on, with when they were taken.
+pub struct InFlightGuard<K> {
+    started: Mutex<HashMap<K, Instant>>,
+    clock: Clock,
+}
+
+impl<K> Default for InFlightGuard<K> {
+    fn default() -> Self {
+        InFlightGuard::new(Arc::new(Instant::now))
+    }
+}
+
+/// Holds a key of an [`InFlightGuard`] until dropped.
+pub struct InFlight<'g, K: Eq + Hash> {
+    guard: &'g InFlightGuard<K>,
+    key: K,
+    started: Instant,
+}
+
+impl<K> InFlightGuard<K> {
+    pub fn new(clock: Clock) -> Self {
+        InFlightGuard {
+            started: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> InFlightGuard<K> {
+    /// Takes `key`, unless it is taken and not for [`TIMEOUT`] yet.
+    pub fn start(&self, key: K) -> Option<InFlight<'_, K>> {
+        let now = (self.clock)();
+        let mut started = self.started.lock().unwrap();
+        if let Some(since) = started.get(&key) {
+            if now.saturating_duration_since(*since) < TIMEOUT {
+                return None;
+            }
+        }
+        started.insert(key.clone(), now);
+        Some(InFlight {
+            guard: self,
+            key,
+            started: now,
+        })
+    }
+}
+
+impl<K: Eq + Hash> Drop for InFlight<'_, K> {
+    fn drop(&mut self) {
+        let mut started = self.guard.started.lock().unwrap();
+        // Past the timeout the key may be taken by another tap since.
+        if started.get(&self.key) == Some(&self.started) {
+            started.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A clock that moves only when told to.
+    fn manual_clock() -> (Clock, Arc<Mutex<Instant>>) {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        (Arc::new(move || *clock.lock().unwrap()), now)
+    }
+
+    #[test]
+    fn repeats_wait_for_the_first() {
+        let (clock, _) = manual_clock();
+        let guard = InFlightGuard::new(clock);
+        let first = guard.start((1, "commit")).unwrap();
+        assert!(guard.start((1, "commit")).is_none());
+        // Other actions and messages go on.
+        assert!(guard.start((1, "category")).is_some());
+        assert!(guard.start((2, "commit")).is_some());
+        drop(first);
+        assert!(guard.start((1, "commit")).is_some());
+    }
+
+    #[test]
+    fn stuck_keys_are_freed() {
+        let (clock, now) = manual_clock();
+        let guard = InFlightGuard::new(clock);
+        let stuck = guard.start(1).unwrap();
+        *now.lock().unwrap() += TIMEOUT - Duration::from_millis(1);
+        assert!(guard.start(1).is_none());
+        *now.lock().unwrap() += Duration::from_millis(1);
+        let next = guard.start(1).unwrap();
+        // The stuck one finishing doesn't free the key of the next.
+        drop(stuck);
+        assert!(guard.start(1).is_none());
+        drop(next);
+        assert!(guard.start(1).is_some());
+    }
+
+    /// Like a callback handler: answers the repeats, works on the rest.
+    async fn tap(
+        guard: &InFlightGuard<(i32, &'static str)>,
+        key: (i32, &'static str),
+        runs: &AtomicUsize,
+    ) -> &'static str {
+        let Some(_in_flight) = guard.start(key) else {
+            return WORKING;
+        };
+        runs.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        "Saved"
+    }
+
+    #[tokio::test]
+    async fn duplicate_taps_run_once() {
+        let guard = InFlightGuard::default();
+        let runs = AtomicUsize::new(0);
+        let commit = (1, "commit");
+        let answers = tokio::join!(
+            tap(&guard, commit, &runs),
+            tap(&guard, commit, &runs),
+            tap(&guard, commit, &runs),
+        );
+        assert_eq!(("Saved", WORKING, WORKING), answers);
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+        // Done, so a later tap runs again.
+        assert_eq!("Saved", tap(&guard, commit, &runs).await);
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn different_taps_run_together() {
+        let guard = InFlightGuard::default();
+        let runs = AtomicUsize::new(0);
+        let answers = tokio::join!(
+            tap(&guard, (1, "commit"), &runs),
+            tap(&guard, (1, "category"), &runs),
+            tap(&guard, (2, "commit"), &runs),
+        );
+        assert_eq!(("Saved", "Saved", "Saved"), answers);
+        assert_eq!(3, runs.load(Ordering::SeqCst));
+    }
+}
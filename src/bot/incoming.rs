@@ -0,0 +1,211 @@
+//! Messages without text: photos, documents, voice notes, stickers and the
+//! like. In private chats the bot answers them, so that it doesn't look
+//! dead; in groups it only answers those meant for it.
+
+use super::markdown::escape_md;
+use super::{Bot, HandlerResult, SharedModel};
+use crate::model::Role;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::{MediaKind, MessageKind};
+
+/// A user gets at most one hint this often, however many stickers they send.
+pub const HINT_EVERY: Duration = Duration::from_secs(10 * 60);
+
+/// What a message carries, as far as the choice of an answer goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Photo,
+    Document,
+    /// Voice notes and audio files.
+    Voice,
+    /// Stickers, locations, contacts and other media.
+    Other,
+    /// Joins, pins and other service messages, never answered.
+    Service,
+}
+
+/// Where a message was sent and by whom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub private: bool,
+    /// It replies to one of the bot's messages, like an expense.
+    pub replies_to_bot: bool,
+    pub admin: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Handled as a typed expense or answer.
+    Text,
+    AttachReceipt,
+    Import,
+    CantTranscribe,
+    /// A hint on what the bot understands, rate limited per user.
+    Hint,
+    Ignore,
+}
+
+pub fn kind(message: &Message) -> Kind {
+    match &message.kind {
+        MessageKind::Common(common) => match common.media_kind {
+            MediaKind::Text(_) => Kind::Text,
+            MediaKind::Photo(_) => Kind::Photo,
+            MediaKind::Document(_) => Kind::Document,
+            MediaKind::Voice(_) | MediaKind::Audio(_) => Kind::Voice,
+            _ => Kind::Other,
+        },
+        _ => Kind::Service,
+    }
+}
+
+/// What to do with a message of `kind`. In groups only messages that reply
+/// to the bot are answered, so that people can share photos among
+/// themselves.
+pub fn action(kind: Kind, context: Context) -> Action {
+    let addressed = context.private || context.replies_to_bot;
+    match kind {
+        Kind::Text => Action::Text,
+        Kind::Service => Action::Ignore,
+        Kind::Photo if context.replies_to_bot => Action::AttachReceipt,
+        Kind::Document if context.private && context.admin => Action::Import,
+        Kind::Voice if addressed => Action::CantTranscribe,
+        _ if context.private => Action::Hint,
+        _ => Action::Ignore,
+    }
+}
+
+/// When each user was last given a hint.
+#[derive(Default)]
+pub struct Hints {
+    last: Mutex<HashMap<UserId, Instant>>,
+}
+
+pub type SharedHints = Arc<Hints>;
+
+impl Hints {
+    /// Whether `user` may get a hint at `now`, which then counts as given.
+    pub fn allow(&self, user: UserId, now: Instant) -> bool {
+        let mut last = self.last.lock().unwrap();
+        match last.get(&user) {
+            Some(at) if now.duration_since(*at) < HINT_EVERY => false,
+            _ => {
+                last.insert(user, now);
+                true
+            }
+        }
+    }
+}
+
+/// The answer to an `action` other than `Text`, if any.
+fn reply(action: Action) -> Option<&'static str> {
+    match action {
+        Action::Text | Action::Ignore => None,
+        Action::AttachReceipt => Some("Receipts can't be attached to expenses yet."),
+        Action::Import => Some("Importing files isn't supported yet."),
+        Action::CantTranscribe => Some("I can't transcribe audio yet — type the amount."),
+        Action::Hint => Some("I only read text. Send an amount like 12.50 coffee, or /help."),
+    }
+}
+
+/// Answers a message without text.
+pub async fn handle(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    hints: &Hints,
+) -> HandlerResult {
+    let Some(from) = message.from.as_ref() else {
+        return Ok(());
+    };
+    let admin = model
+        .lock()
+        .unwrap()
+        .get_user(from.id.0 as i64)?
+        .is_some_and(|u| u.role.allows(Role::Admin));
+    let context = Context {
+        private: message.chat.is_private(),
+        replies_to_bot: message
+            .reply_to_message()
+            .and_then(|m| m.from.as_ref())
+            .is_some_and(|u| u.is_bot),
+        admin,
+    };
+    let action = action(kind(message), context);
+    if action == Action::Hint && !hints.allow(from.id, Instant::now()) {
+        return Ok(());
+    }
+    if let Some(text) = reply(action) {
+        bot.send_message(message.chat.id, escape_md(text)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE: Context = Context {
+        private: true,
+        replies_to_bot: false,
+        admin: false,
+    };
+    const GROUP: Context = Context {
+        private: false,
+        ..PRIVATE
+    };
+
+    #[test]
+    fn private_chats_always_get_an_answer() {
+        assert_eq!(Action::Text, action(Kind::Text, PRIVATE));
+        assert_eq!(Action::CantTranscribe, action(Kind::Voice, PRIVATE));
+        assert_eq!(Action::Hint, action(Kind::Other, PRIVATE));
+        assert_eq!(Action::Hint, action(Kind::Photo, PRIVATE));
+        assert_eq!(Action::Hint, action(Kind::Document, PRIVATE));
+        let admin = Context {
+            admin: true,
+            ..PRIVATE
+        };
+        assert_eq!(Action::Import, action(Kind::Document, admin));
+        let reply = Context {
+            replies_to_bot: true,
+            ..PRIVATE
+        };
+        assert_eq!(Action::AttachReceipt, action(Kind::Photo, reply));
+        assert_eq!(Action::Ignore, action(Kind::Service, PRIVATE));
+    }
+
+    #[test]
+    fn groups_only_answer_replies_to_the_bot() {
+        for kind in [Kind::Photo, Kind::Document, Kind::Voice, Kind::Other] {
+            assert_eq!(Action::Ignore, action(kind, GROUP), "{:?}", kind);
+        }
+        let admin = Context {
+            admin: true,
+            ..GROUP
+        };
+        assert_eq!(Action::Ignore, action(Kind::Document, admin));
+        let reply = Context {
+            replies_to_bot: true,
+            ..GROUP
+        };
+        assert_eq!(Action::AttachReceipt, action(Kind::Photo, reply));
+        assert_eq!(Action::CantTranscribe, action(Kind::Voice, reply));
+        assert_eq!(Action::Ignore, action(Kind::Other, reply));
+        assert_eq!(Action::Text, action(Kind::Text, GROUP));
+    }
+
+    #[test]
+    fn hints_are_rate_limited_per_user() {
+        let hints = Hints::default();
+        let start = Instant::now();
+        assert!(hints.allow(UserId(1), start));
+        assert!(!hints.allow(UserId(1), start + Duration::from_secs(60)));
+        assert!(hints.allow(UserId(2), start + Duration::from_secs(60)));
+        assert!(!hints.allow(UserId(1), start + HINT_EVERY - Duration::from_secs(1)));
+        assert!(hints.allow(UserId(1), start + HINT_EVERY));
+    }
+}
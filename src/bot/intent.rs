@@ -0,0 +1,357 @@
+//! Hints for text in private chats that is neither a command nor starts
+//! with an amount, like `report` or `taxi 12,5`: the command it looks like,
+//! with a button running it, or how to write the expense it seems to be.
+
+use super::callback;
+use super::commands::{self, Command};
+use super::feed::SharedFeeds;
+use super::markdown::escape_md;
+use super::{aliases, keyboards, menu, parse, Bot, HandlerResult, SharedModel};
+use crate::config::Config;
+use crate::model::{AmountError, AmountLimits, Error, Locale, Model, User, MAX_EXPONENT};
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, MediaKind, MediaText, MessageCommon, MessageKind};
+use teloxide::utils::command::BotCommands;
+
+/// Longer texts are sentences rather than a mistyped command.
+const MAX_WORDS: usize = 3;
+
+/// Shorter words only match commands exactly.
+const MIN_PREFIX: usize = 3;
+
+/// Edits a word may be away from a command name, and at most one per
+/// three letters of it, so that short words don't match everything.
+const MAX_DISTANCE: usize = 2;
+
+/// How a word matches a name, lower is closer.
+fn closeness(word: &str, name: &str) -> Option<usize> {
+    let length = word.chars().count();
+    if word == name {
+        return Some(0);
+    }
+    if length >= MIN_PREFIX && name.starts_with(word) {
+        return Some(1);
+    }
+    let distance = levenshtein(word, name);
+    (distance <= MAX_DISTANCE && distance * 3 <= length).then_some(1 + distance)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The index of the one name in `names` the text looks like a command of,
+/// `None` if there is none or several are as close. Texts with digits are
+/// expenses gone wrong rather than commands.
+pub fn guess(text: &str, names: &[String]) -> Option<usize> {
+    if text.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|w| {
+            w.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.len() > MAX_WORDS {
+        return None;
+    }
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for word in &words {
+        for (index, name) in names.iter().enumerate() {
+            let Some(score) = closeness(word, name) else {
+                continue;
+            };
+            match &mut best {
+                Some((best_score, _)) if score > *best_score => {}
+                Some((best_score, indexes)) if score == *best_score => {
+                    if !indexes.contains(&index) {
+                        indexes.push(index);
+                    }
+                }
+                _ => best = Some((score, vec![index])),
+            }
+        }
+    }
+    match best?.1.as_slice() {
+        [index] => Some(*index),
+        _ => None,
+    }
+}
+
+/// The first number in the text, like `12,5` of `taxi 12,5`, with where it
+/// starts and ends.
+fn find_number(text: &str) -> Option<(usize, usize)> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let bytes = text.as_bytes();
+    let mut end = start;
+    while end < bytes.len() {
+        let is_digit = bytes[end].is_ascii_digit();
+        let joins_digits =
+            matches!(bytes[end], b'.' | b',') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit);
+        if !is_digit && !joins_digits {
+            break;
+        }
+        end += 1;
+    }
+    Some((start, end))
+}
+
+/// What the text would be as an expense, when it has a number somewhere but
+/// doesn't start with it: the number as typed, if it is an amount, followed
+/// by the rest.
+pub fn number_hint(text: &str, limits: &AmountLimits, locale: Locale) -> Option<String> {
+    let (start, end) = find_number(text)?;
+    let number = &text[start..end];
+    limits.parse(number, locale).ok()?;
+    let rest = format!("{} {}", &text[..start], &text[end..]);
+    let rest: Vec<&str> = rest.split_whitespace().collect();
+    let suggestion = match rest.as_slice() {
+        [] => number.to_string(),
+        rest => format!("{} {}", number, rest.join(" ")),
+    };
+    Some(format!(
+        "I saw '{}' but the rest wasn't recognized — try '{}'.",
+        number, suggestion
+    ))
+}
+
+/// Whether the text doesn't start with an amount at all, as opposed to one
+/// that is out of bounds.
+pub fn is_unrecognized(text: &str, limits: &AmountLimits, locale: Locale) -> bool {
+    matches!(
+        parse::parse_expense_message(text, &limits.for_exponent(MAX_EXPONENT), locale),
+        Err(AmountError::NotANumber(_))
+    )
+}
+
+/// What a hint may suggest to the user: the commands their role runs, then
+/// the built-in aliases and their own.
+pub fn candidates(model: &Model, user: &User) -> Result<Vec<String>, Error> {
+    let mut names = menu::commands_for(user.role);
+    let aliases = model.aliases(user.telegram_id)?;
+    let built_in = aliases::BUILT_IN.iter().map(|(name, _)| name.to_string());
+    for name in built_in.chain(aliases.into_iter().map(|(name, _)| name)) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Answers a text that doesn't start with an amount. Groups stay quiet,
+/// people talk there; private chats get a hint, or the plain reason.
+pub async fn handle_unrecognized(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    user: &User,
+    (text, reason): (&str, &str),
+    (limits, locale): (&AmountLimits, Locale),
+) -> HandlerResult {
+    if !message.chat.is_private() {
+        return Ok(());
+    }
+    let names = candidates(&model.lock().unwrap(), user)?;
+    if let Some(index) = guess(text, &names) {
+        bot.send_message(
+            message.chat.id,
+            escape_md(&format!("Did you mean /{}?", names[index])),
+        )
+        .reply_markup(keyboards::run_command_keyboard(&names[index], index))
+        .await?;
+        return Ok(());
+    }
+    let hint = number_hint(text, &limits.for_exponent(MAX_EXPONENT), locale);
+    let text = hint.as_deref().unwrap_or(reason);
+    bot.send_message(message.chat.id, escape_md(text)).await?;
+    Ok(())
+}
+
+/// Runs the suggestion the tapped button of `message` stands for, as if the
+/// user had sent it.
+pub async fn run_suggestion(
+    bot: &Bot,
+    q: &CallbackQuery,
+    message: &Message,
+    (model, config, feeds): (&SharedModel, &std::sync::Arc<Config>, &SharedFeeds),
+    user: &User,
+    index: usize,
+) -> HandlerResult {
+    let names = candidates(&model.lock().unwrap(), user)?;
+    let Some(name) = names.get(index) else {
+        bot.answer_callback_query(q.id.clone())
+            .text(callback::NO_OPTION)
+            .await?;
+        return Ok(());
+    };
+    let mut command_message = message.clone();
+    command_message.from = Some(q.from.clone());
+    if let MessageKind::Common(MessageCommon {
+        media_kind: MediaKind::Text(media_text),
+        ..
+    }) = &mut command_message.kind
+    {
+        *media_text = MediaText {
+            text: format!("/{}", name),
+            entities: Vec::new(),
+            link_preview_options: None,
+        };
+    }
+    let command_message = aliases::expand_message(command_message, model.clone());
+    let command = command_message
+        .text()
+        .and_then(|text| Command::parse(text, "").ok());
+    bot.answer_callback_query(q.id.clone()).await?;
+    let Some(command) = command else {
+        return Ok(());
+    };
+    commands::handle_command(
+        bot.clone(),
+        command_message,
+        command,
+        model.clone(),
+        config.clone(),
+        feeds.clone(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        [
+            "help",
+            "start",
+            "add",
+            "report",
+            "balance",
+            "export",
+            "settings",
+            "settle",
+            "setup",
+            "review",
+            "reconcile",
+            "recategorize",
+            "fav",
+            "feed",
+            "fun",
+            "notify",
+            "r",
+            "b",
+            "rl",
+        ]
+        .iter()
+        .map(|n| n.to_string())
+        .collect()
+    }
+
+    fn guessed(text: &str) -> Option<String> {
+        let names = names();
+        guess(text, &names).map(|i| names[i].clone())
+    }
+
+    #[test]
+    fn typos_of_commands() {
+        let table = [
+            ("report", Some("report")),
+            ("Report", Some("report")),
+            ("balance?", Some("balance")),
+            ("/balance", Some("balance")),
+            ("reprot", Some("report")),
+            ("repotr", Some("report")),
+            ("balnce", Some("balance")),
+            ("ballance", Some("balance")),
+            ("halp", Some("help")),
+            ("exprot", Some("export")),
+            ("settigns", Some("settings")),
+            ("notfy", Some("notify")),
+            ("rep", Some("report")),
+            ("bal", Some("balance")),
+            ("show report", Some("report")),
+            ("my balance please", Some("balance")),
+            ("rl", Some("rl")),
+            // Prefixes of several commands, and as close to several.
+            ("set", None),
+            ("rec", None),
+            ("re", None),
+            // Words, not commands.
+            ("taxi", None),
+            ("bus", None),
+            ("fuel", None),
+            ("coffee", None),
+            ("hello there", None),
+            ("what is my balance now", None),
+            ("report 2023", None),
+            ("", None),
+        ];
+        for (text, expected) in table {
+            assert_eq!(expected.map(str::to_string), guessed(text), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(0, levenshtein("report", "report"));
+        assert_eq!(2, levenshtein("reprot", "report"));
+        assert_eq!(1, levenshtein("balnce", "balance"));
+        assert_eq!(3, levenshtein("", "fun"));
+        assert_eq!(1, levenshtein("кофе", "кафе"));
+    }
+
+    #[test]
+    fn numbers_out_of_place() {
+        let limits = AmountLimits {
+            decimals: MAX_EXPONENT,
+            ..AmountLimits::default()
+        };
+        let hint = |text| number_hint(text, &limits, Locale::DecimalPoint);
+        assert_eq!(
+            Some("I saw '12,5' but the rest wasn't recognized — try '12,5 taxi'.".to_string()),
+            hint("taxi 12,5")
+        );
+        assert_eq!(
+            Some("I saw '12.50' but the rest wasn't recognized — try '12.50 coffee'.".to_string()),
+            hint("coffee12.50")
+        );
+        assert_eq!(
+            Some("I saw '3' but the rest wasn't recognized — try '3 bread milk'.".to_string()),
+            hint("bread 3 milk")
+        );
+        assert_eq!(
+            Some(
+                "I saw '1.250,5' but the rest wasn't recognized — try '1.250,5 rent'.".to_string()
+            ),
+            number_hint("rent 1.250,5", &limits, Locale::DecimalComma)
+        );
+        assert_eq!(None, hint("coffee"));
+        // Not an amount, nothing to suggest.
+        assert_eq!(None, hint("taxi 0"));
+    }
+
+    #[test]
+    fn only_texts_without_an_amount_are_unrecognized() {
+        let limits = AmountLimits::default();
+        let unrecognized = |text| is_unrecognized(text, &limits, Locale::DecimalPoint);
+        assert!(unrecognized("report"));
+        assert!(unrecognized("taxi 12"));
+        assert!(!unrecognized("12 taxi"));
+        // Read as an amount, just not a valid one.
+        assert!(!unrecognized("0 taxi"));
+        assert!(!unrecognized("-5 taxi"));
+    }
+}
@@ -0,0 +1,702 @@
+use super::callback::{CallbackData, Field, Page};
+use super::draft::ActiveTransaction;
+use super::format::format_amount;
+use super::markdown::truncate;
+use crate::model::from_minor;
+use crate::model::{
+    Account, BudgetRemaining, Category, CommentTemplate, Favorite, Locale, RecentExpense, Setting,
+    Settings, User,
+};
+use crate::time::{self, Style};
+use chrono_tz::Tz;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+fn button(label: impl Into<String>, data: CallbackData) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(label, data.encode())
+}
+
+/// The small and the large step of the amount buttons of a draft, in minor
+/// units of a currency with `exponent` decimal places: a tenth and a whole
+/// unit, or one and ten units of currencies without a tenth.
+pub fn nudge_steps(exponent: u32) -> (i64, i64) {
+    let small = 10i64.pow(exponent.saturating_sub(1));
+    (small, small * 10)
+}
+
+/// Like `−0.1` or `+1`.
+fn nudge_label(step: i64, exponent: u32) -> String {
+    let sign = if step < 0 { "−" } else { "+" };
+    format!("{}{}", sign, from_minor(step.abs(), exponent))
+}
+
+/// Buttons of a draft, on the page it is turned to. The values are shown
+/// in the message text, so the labels stay short to fit narrow screens.
+/// The main page has what most expenses need: around Amount are the steps
+/// it can be nudged by, a comment the category requires but the draft
+/// lacks is flagged on its button, and so is a Commit that waits for the
+/// category to be picked. More… has the rest; an account missing in the
+/// currency the chat assumes is flagged on both.
+pub fn draft_keyboard(draft: &ActiveTransaction) -> InlineKeyboardMarkup {
+    if draft.page == Page::More {
+        let account = match &draft.missing_account {
+            Some(currency) => format!("⚠️ no {} account", currency),
+            None => "Account".to_string(),
+        };
+        return InlineKeyboardMarkup::new(vec![
+            vec![
+                button("Date", CallbackData::Edit(Field::Timestamp)),
+                button(account, CallbackData::PickAccount),
+            ],
+            vec![
+                button("Split 50/50", CallbackData::PickSplit),
+                button("📍 Location", CallbackData::Edit(Field::Location)),
+            ],
+            vec![button("« Back", CallbackData::ShowPage(Page::Main))],
+        ]);
+    }
+    let comment = if draft.category.lacks_comment(draft.comment.as_deref()) {
+        "Comment ❗"
+    } else {
+        "Comment"
+    };
+    let commit = if draft.commit_locked(draft.strict_commit) {
+        "Commit 🔒"
+    } else {
+        "✅ Commit"
+    };
+    let more = match draft.missing_account {
+        Some(_) => "More… ⚠️",
+        None => "More…",
+    };
+    let exponent = draft.account.exponent;
+    let (small, large) = nudge_steps(exponent);
+    let nudge = |step| button(nudge_label(step, exponent), CallbackData::Nudge(step));
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            nudge(-large),
+            nudge(-small),
+            button("Amount", CallbackData::Edit(Field::Amount)),
+            nudge(small),
+            nudge(large),
+        ],
+        vec![
+            button("Category", CallbackData::PickCategory),
+            button(comment, CallbackData::Edit(Field::Comment)),
+        ],
+        vec![
+            button(commit, CallbackData::Commit),
+            button("✖️ Cancel", CallbackData::Cancel),
+        ],
+        vec![button(more, CallbackData::ShowPage(Page::More))],
+    ])
+}
+
+/// Longest category button label. They are two to a row, so that names
+/// get shortened before what is left of the budget.
+const MAX_CATEGORY_LABEL: usize = 32;
+
+/// Like `↳ 🛒 Groceries · 79 left`, with what is left of the category's
+/// budget in whole units if it has one.
+fn category_label(category: &Category, prefix: &str, budget: Option<&BudgetRemaining>) -> String {
+    let mut start = prefix.to_string();
+    if let Some(icon) = &category.icon {
+        start.push_str(icon);
+        start.push(' ');
+    }
+    let suffix = match budget {
+        Some(b) if b.remaining() < 0.0 => format!(" · ⚠️ {:.0} left", b.remaining()),
+        Some(b) => format!(" · {:.0} left", b.remaining()),
+        None => String::new(),
+    };
+    let room = MAX_CATEGORY_LABEL.saturating_sub(start.chars().count() + suffix.chars().count());
+    format!("{}{}{}", start, truncate(&category.name, room), suffix)
+}
+
+/// Categories two to a row. A category with subcategories gets a row of
+/// its own as their heading, tapping it picks the category itself; its
+/// subcategories follow. `budgets` are those in the currency of the
+/// draft's account, shown with what is left of them this month.
+pub fn category_picker(
+    categories: &[Category],
+    budgets: &[BudgetRemaining],
+) -> InlineKeyboardMarkup {
+    let category_button = |c: &Category, prefix: &str| {
+        let budget = budgets.iter().find(|b| b.category_id == c.id);
+        button(
+            category_label(c, prefix, budget),
+            CallbackData::SetCategory(c.id),
+        )
+    };
+    // A subcategory of an archived category is shown as a top-level one.
+    let top_level = |c: &&Category| {
+        c.parent_id
+            .is_none_or(|parent| categories.iter().all(|p| p.id != parent))
+    };
+
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = Vec::new();
+    let mut pending: Vec<InlineKeyboardButton> = Vec::new();
+    let pair = |rows: &mut Vec<_>, pending: &mut Vec<_>| {
+        rows.extend(pending.chunks(2).map(<[_]>::to_vec));
+        pending.clear();
+    };
+    for parent in categories.iter().filter(top_level) {
+        let children: Vec<&Category> = categories
+            .iter()
+            .filter(|c| c.parent_id == Some(parent.id))
+            .collect();
+        if children.is_empty() {
+            pending.push(category_button(parent, ""));
+            continue;
+        }
+        pair(&mut rows, &mut pending);
+        rows.push(vec![category_button(parent, "")]);
+        pending.extend(children.into_iter().map(|c| category_button(c, "↳ ")));
+        pair(&mut rows, &mut pending);
+    }
+    pair(&mut rows, &mut pending);
+    rows.push(vec![button("« Back", CallbackData::Back)]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Longest comment button label, a row each.
+const MAX_COMMENT_LABEL: usize = 40;
+
+/// Comments to tap instead of typing, one per row, then the user's
+/// templates to fill in and a button to type another one.
+pub fn comment_picker(
+    suggestions: &[String],
+    templates: &[CommentTemplate],
+) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = suggestions
+        .iter()
+        .enumerate()
+        .map(|(i, comment)| {
+            vec![button(
+                truncate(comment, MAX_COMMENT_LABEL),
+                CallbackData::UseComment(i),
+            )]
+        })
+        .collect();
+    rows.extend(templates.iter().enumerate().map(|(i, template)| {
+        vec![button(
+            truncate(&format!("📝 {}", template.text), MAX_COMMENT_LABEL),
+            CallbackData::UseTemplate(i),
+        )]
+    }));
+    rows.push(vec![
+        button("✏️ Type my own", CallbackData::TypeComment),
+        button("« Back", CallbackData::Back),
+    ]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Users to split an expense with, and a button to not split it.
+pub fn split_picker(users: &[User]) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = users
+        .iter()
+        .map(|u| {
+            vec![button(
+                format!("👥 {}", u.display_name),
+                CallbackData::SetSplit(Some(u.telegram_id)),
+            )]
+        })
+        .collect();
+    rows.push(vec![
+        button("Not split", CallbackData::SetSplit(None)),
+        button("« Back", CallbackData::Back),
+    ]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// One favorite per row. Those that can't be committed are flagged, tapping
+/// them explains why.
+pub fn favorites_keyboard(favorites: &[Favorite]) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(favorites.iter().map(|f| {
+        let flag = if f.blocked_reason().is_some() {
+            "⚠️ "
+        } else {
+            ""
+        };
+        vec![button(
+            format!(
+                "{}{} · {} {}",
+                flag,
+                f.label,
+                format_amount(f.amount, f.exponent),
+                f.currency
+            ),
+            CallbackData::UseFavorite(f.id),
+        )]
+    }))
+}
+
+/// Longest account button label. Longer ones get cut off by Telegram
+/// clients anyway, losing the part after the name.
+const MAX_ACCOUNT_LABEL: usize = 60;
+
+/// Like `Family BYN (BYN) · last 30.00 on Dec 3`, shortening the name if
+/// needed so that the last expense stays readable.
+fn account_label(account: &Account, last: Option<&RecentExpense>, tz: Tz) -> String {
+    let suffix = match last {
+        Some(e) => format!(
+            " ({}) · last {} on {}",
+            account.currency,
+            format_amount(e.amount, account.exponent),
+            time::render(e.timestamp, tz, Style::ShortDate)
+        ),
+        None => format!(" ({})", account.currency),
+    };
+    let room = MAX_ACCOUNT_LABEL.saturating_sub(suffix.chars().count());
+    let name = if account.name.chars().count() > room {
+        let kept: String = account.name.chars().take(room.saturating_sub(1)).collect();
+        format!("{}…", kept)
+    } else {
+        account.name.clone()
+    };
+    format!("{}{}", name, suffix)
+}
+
+/// Accounts with their most recent expense from `recent`, if any.
+pub fn account_picker(
+    accounts: &[Account],
+    recent: &[RecentExpense],
+    tz: Tz,
+) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = accounts
+        .iter()
+        .map(|a| {
+            let last = recent.iter().find(|e| e.account_id == a.id);
+            vec![button(
+                account_label(a, last, tz),
+                CallbackData::SetAccount(a.id),
+            )]
+        })
+        .collect();
+    rows.push(vec![button("« Back", CallbackData::Back)]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+pub fn undo_keyboard(expense_id: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        button("↩️ Undo", CallbackData::Undo(expense_id)),
+        button("🔎 Details", CallbackData::ViewExpense(expense_id)),
+    ]])
+}
+
+/// Buttons under a batch of `total` expenses, `valid` of which can be
+/// saved. Committing all is refused while any line has an error, so saving
+/// only the valid ones is a button of its own.
+pub fn batch_keyboard(valid: usize, total: usize) -> InlineKeyboardMarkup {
+    let mut row = vec![button(
+        format!("✅ Commit all {}", total),
+        CallbackData::CommitBatch { valid_only: false },
+    )];
+    if valid < total {
+        row.push(button(
+            format!("Commit valid only ({})", valid),
+            CallbackData::CommitBatch { valid_only: true },
+        ));
+    }
+    InlineKeyboardMarkup::new(vec![
+        row,
+        vec![button("✖️ Cancel", CallbackData::CancelBatch)],
+    ])
+}
+
+/// The button under the welcome of a newly allowed user.
+pub fn welcome_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![button(
+        "📝 Log my first expense",
+        CallbackData::FirstExpense,
+    )]])
+}
+
+/// The button under a hint, running the command it suggests.
+pub fn run_command_keyboard(command: &str, index: usize) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![button(
+        format!("▶️ /{}", command),
+        CallbackData::RunCommand(index),
+    )]])
+}
+
+/// Confirmation of a change that is only previewed so far. `confirm` is
+/// made by [`super::callback::button_data`], as confirmations can carry a lot.
+/// `cancel` is usually [`CallbackData::Dismiss`].
+pub fn confirm_keyboard(confirm: String, cancel: CallbackData) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("✅ Confirm", confirm),
+        button("✖️ Cancel", cancel),
+    ]])
+}
+
+/// The confirmation of a bulk change, which can also be previewed.
+pub fn bulk_confirm_keyboard(confirm: String, preview: String) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("✅ Confirm", confirm),
+        InlineKeyboardButton::callback("🔍 Preview", preview),
+        button("✖️ Cancel", CallbackData::Dismiss),
+    ]])
+}
+
+/// Actions on a stored expense shown in full.
+/// The button under a notification of someone else's expense.
+pub fn view_keyboard(expense_id: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![button(
+        "🔎 View",
+        CallbackData::ViewExpense(expense_id),
+    )]])
+}
+
+/// Where Google Maps shows a location.
+fn map_url(latitude: f64, longitude: f64) -> String {
+    format!("https://maps.google.com/?q={},{}", latitude, longitude)
+}
+
+/// Actions on a stored expense, and its map if it has a location.
+pub fn expense_keyboard(expense_id: i64, location: Option<(f64, f64)>) -> InlineKeyboardMarkup {
+    let mut rows = vec![vec![
+        button("✏️ Edit", CallbackData::EditExpense(expense_id)),
+        button("🗑 Delete", CallbackData::DeleteExpense(expense_id)),
+    ]];
+    if let Some((latitude, longitude)) = location {
+        rows.push(vec![InlineKeyboardButton::url(
+            "📍 open map",
+            map_url(latitude, longitude)
+                .parse()
+                .expect("coordinates make a valid URL"),
+        )]);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Buttons of `/settings`: toggles show the current state, value buttons
+/// mark the current choice.
+pub fn settings_keyboard(settings: &Settings) -> InlineKeyboardMarkup {
+    let on_off = |on| if on { "on" } else { "off" };
+    let formats = [
+        (None, "Auto"),
+        (Some(Locale::DecimalPoint), "1,234.56"),
+        (Some(Locale::DecimalComma), "1.234,56"),
+    ];
+    InlineKeyboardMarkup::new(vec![
+        vec![button(
+            format!("📅 Month-to-date: {}", on_off(settings.month_to_date)),
+            CallbackData::ChangeSetting(Setting::MonthToDate(!settings.month_to_date)),
+        )],
+        vec![button(
+            format!("🔒 Strict commit: {}", on_off(settings.strict_commit)),
+            CallbackData::ChangeSetting(Setting::StrictCommit(!settings.strict_commit)),
+        )],
+        vec![button(
+            format!("🧮 Round report totals: {}", on_off(settings.round_totals)),
+            CallbackData::ChangeSetting(Setting::RoundTotals(!settings.round_totals)),
+        )],
+        formats
+            .into_iter()
+            .map(|(locale, label)| {
+                let label = if settings.number_format == locale {
+                    format!("• {}", label)
+                } else {
+                    label.to_string()
+                };
+                button(
+                    label,
+                    CallbackData::ChangeSetting(Setting::NumberFormat(locale)),
+                )
+            })
+            .collect(),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn account(name: &str) -> Account {
+        Account {
+            id: 3,
+            name: name.to_string(),
+            currency: "BYN".to_string(),
+            exponent: 2,
+        }
+    }
+
+    fn comment_label(draft: &ActiveTransaction) -> String {
+        draft_keyboard(draft).inline_keyboard[1][1].text.clone()
+    }
+
+    /// Rows of `label data` pairs.
+    fn snapshot(keyboard: &InlineKeyboardMarkup) -> Vec<Vec<String>> {
+        keyboard
+            .inline_keyboard
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|b| match &b.kind {
+                        teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => {
+                            format!("{} {}", b.text, data)
+                        }
+                        other => panic!("unexpected {:?}", other),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draft_pages() {
+        let mut d = crate::bot::draft::tests::draft();
+        assert_eq!(
+            vec![
+                vec![
+                    "−1 nudge:-100",
+                    "−0.1 nudge:-10",
+                    "Amount edit:amount",
+                    "+0.1 nudge:10",
+                    "+1 nudge:100",
+                ],
+                vec!["Category pick:category", "Comment edit:comment"],
+                vec!["✅ Commit commit", "✖️ Cancel cancel"],
+                vec!["More… page:more"],
+            ],
+            snapshot(&draft_keyboard(&d))
+        );
+        d.page = Page::More;
+        assert_eq!(
+            vec![
+                vec!["Date edit:timestamp", "Account pick:account"],
+                vec!["Split 50/50 pick:split", "📍 Location edit:location"],
+                vec!["« Back page:main"],
+            ],
+            snapshot(&draft_keyboard(&d))
+        );
+    }
+
+    #[test]
+    fn flags_a_missing_assumed_account() {
+        let mut d = crate::bot::draft::tests::draft();
+        d.missing_account = Some("USD".to_string());
+        assert_eq!("More… ⚠️ page:more", snapshot(&draft_keyboard(&d))[3][0]);
+        d.page = Page::More;
+        assert_eq!(
+            "⚠️ no USD account pick:account",
+            snapshot(&draft_keyboard(&d))[0][1]
+        );
+        d.pick_account(account("Family"));
+        assert_eq!("Account pick:account", snapshot(&draft_keyboard(&d))[0][1]);
+    }
+
+    #[test]
+    fn edits_keep_the_page() {
+        let mut d = crate::bot::draft::tests::draft();
+        d.page = Page::More;
+        let more = snapshot(&draft_keyboard(&d));
+        let limits = crate::model::AmountLimits {
+            max: 1_000_000.0,
+            decimals: 2,
+        };
+        d.nudge(100, &limits).unwrap();
+        d.pick_category(d.category.clone(), None);
+        d.comment = Some("lunch".to_string());
+        d.timestamp += chrono::TimeDelta::days(-1);
+        d.location = Some((53.9, 27.56));
+        assert_eq!(more, snapshot(&draft_keyboard(&d)));
+        d.page = Page::Main;
+        assert_eq!("More… page:more", snapshot(&draft_keyboard(&d))[3][0]);
+    }
+
+    #[test]
+    fn flags_a_missing_required_comment() {
+        let mut d = crate::bot::draft::tests::draft();
+        d.comment = None;
+        assert_eq!("Comment", comment_label(&d));
+        d.category.require_comment = true;
+        assert_eq!("Comment ❗", comment_label(&d));
+        d.comment = Some("lunch".to_string());
+        assert_eq!("Comment", comment_label(&d));
+    }
+
+    #[test]
+    fn nudges_around_the_amount() {
+        let labels = |d: &ActiveTransaction| -> Vec<String> {
+            draft_keyboard(d).inline_keyboard[0]
+                .iter()
+                .map(|b| b.text.clone())
+                .collect()
+        };
+        let mut d = crate::bot::draft::tests::draft();
+        assert_eq!(vec!["−1", "−0.1", "Amount", "+0.1", "+1"], labels(&d));
+        let keyboard = draft_keyboard(&d);
+        let data = |i: usize| match &keyboard.inline_keyboard[0][i].kind {
+            teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => data.clone(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            ("nudge:-100", "nudge:10"),
+            (data(0).as_str(), data(3).as_str())
+        );
+
+        d.account.exponent = 0;
+        assert_eq!(vec!["−10", "−1", "Amount", "+1", "+10"], labels(&d));
+        d.account.exponent = 3;
+        assert_eq!(vec!["−1", "−0.1", "Amount", "+0.1", "+1"], labels(&d));
+        assert_eq!((100, 1000), nudge_steps(3));
+        assert_eq!((1, 10), nudge_steps(1));
+    }
+
+    #[test]
+    fn locks_commit_until_the_category_is_picked() {
+        let commit_label =
+            |d: &ActiveTransaction| draft_keyboard(d).inline_keyboard[2][0].text.clone();
+        let mut d = crate::bot::draft::tests::draft();
+        assert_eq!("✅ Commit", commit_label(&d));
+        d.strict_commit = true;
+        assert_eq!("Commit 🔒", commit_label(&d));
+        d.category_confirmed = true;
+        assert_eq!("✅ Commit", commit_label(&d));
+    }
+
+    #[test]
+    fn groups_subcategories_under_their_parent() {
+        let category = |id, name: &str, parent_id| Category {
+            id,
+            name: name.to_string(),
+            icon: None,
+            require_comment: false,
+            parent_id,
+        };
+        let categories = [
+            category(1, "Groceries", Some(5)),
+            category(2, "Transport", None),
+            category(3, "Fun", None),
+            category(4, "Cafes", Some(5)),
+            category(5, "Food", None),
+            category(6, "Other", None),
+            // Its parent is archived.
+            category(7, "Taxi", Some(9)),
+        ];
+        let labels: Vec<Vec<String>> = category_picker(&categories, &[])
+            .inline_keyboard
+            .iter()
+            .map(|row| row.iter().map(|b| b.text.clone()).collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec!["Transport", "Fun"],
+                vec!["Food"],
+                vec!["↳ Groceries", "↳ Cafes"],
+                vec!["Other", "Taxi"],
+                vec!["« Back"],
+            ],
+            labels
+        );
+    }
+
+    #[test]
+    fn comments_to_pick() {
+        let suggestions = ["weekly shop".to_string(), "x".repeat(50)];
+        let templates = [CommentTemplate::parse("fuel", "fuel {liters}L at {station}").unwrap()];
+        let keyboard = comment_picker(&suggestions, &templates).inline_keyboard;
+        let labels: Vec<Vec<&str>> = keyboard
+            .iter()
+            .map(|row| row.iter().map(|b| b.text.as_str()).collect())
+            .collect();
+        let long = format!("{}…", "x".repeat(MAX_COMMENT_LABEL - 1));
+        assert_eq!(
+            vec![
+                vec!["weekly shop"],
+                vec![long.as_str()],
+                vec!["📝 fuel {liters}L at {station}"],
+                vec!["✏️ Type my own", "« Back"],
+            ],
+            labels
+        );
+    }
+
+    #[test]
+    fn category_labels_show_the_budget_left() {
+        let groceries = Category {
+            id: 1,
+            name: "Groceries".to_string(),
+            icon: Some("🛒".to_string()),
+            require_comment: false,
+            parent_id: None,
+        };
+        let budget = |budget, spent| BudgetRemaining {
+            category_id: 1,
+            category: "Groceries".to_string(),
+            currency: "EUR".to_string(),
+            exponent: 2,
+            budget,
+            spent,
+        };
+        // A budget without spend yet, and spend without a budget.
+        assert_eq!(
+            "🛒 Groceries · 130 left",
+            category_label(&groceries, "", Some(&budget(130.0, 0.0)))
+        );
+        assert_eq!("🛒 Groceries", category_label(&groceries, "", None));
+        assert_eq!(
+            "↳ 🛒 Groceries · 79 left",
+            category_label(&groceries, "↳ ", Some(&budget(130.0, 50.75)))
+        );
+        assert_eq!(
+            "🛒 Groceries · ⚠️ -12 left",
+            category_label(&groceries, "", Some(&budget(100.0, 112.4)))
+        );
+
+        let long = Category {
+            name: "Household supplies and repairs".to_string(),
+            ..groceries
+        };
+        let label = category_label(&long, "", Some(&budget(1500.0, 20.0)));
+        assert_eq!("🛒 Household supplie… · 1480 left", label);
+        assert_eq!(MAX_CATEGORY_LABEL, label.chars().count());
+    }
+
+    #[test]
+    fn expenses_with_a_location_link_their_map() {
+        assert_eq!(1, expense_keyboard(1, None).inline_keyboard.len());
+        let keyboard = expense_keyboard(1, Some((52.52, -13.405)));
+        let map = &keyboard.inline_keyboard[1][0];
+        assert_eq!("📍 open map", map.text);
+        assert!(matches!(
+            &map.kind,
+            teloxide::types::InlineKeyboardButtonKind::Url(url)
+                if url.as_str() == "https://maps.google.com/?q=52.52,-13.405"
+        ));
+    }
+
+    #[test]
+    fn account_labels() {
+        let last = RecentExpense {
+            account_id: 3,
+            amount: 30.0,
+            timestamp: DateTime::from_timestamp(1_701_626_400, 0).unwrap(),
+        };
+        assert_eq!(
+            "Family BYN (BYN) · last 30.00 on Dec 3",
+            account_label(&account("Family BYN"), Some(&last), Tz::UTC)
+        );
+        // Local time decides the day.
+        assert_eq!(
+            "Family BYN (BYN) · last 30.00 on Dec 4",
+            account_label(&account("Family BYN"), Some(&last), Tz::Asia__Tokyo)
+        );
+        assert_eq!(
+            "Family BYN (BYN)",
+            account_label(&account("Family BYN"), None, Tz::UTC)
+        );
+
+        let long = "Joint household account for groceries and bills";
+        let label = account_label(&account(long), Some(&last), Tz::UTC);
+        assert_eq!(
+            "Joint household account for gro… (BYN) · last 30.00 on Dec 3",
+            label
+        );
+        assert_eq!(MAX_ACCOUNT_LABEL, label.chars().count());
+        let label = account_label(&account(&long.repeat(2)), None, Tz::UTC);
+        assert_eq!(MAX_ACCOUNT_LABEL, label.chars().count());
+    }
+}
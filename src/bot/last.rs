@@ -0,0 +1,193 @@
+//! `/last`: the latest expenses, one line each, narrowed down by category,
+//! account or who logged them.
+
+use super::markdown::escape_md;
+use super::parse::{self, LastArgs, LastScope};
+use super::{format, resolve, settings, SharedModel};
+use crate::model::{Error, ExpenseFilter, ExpenseSort, User};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::types::ChatId;
+
+/// Expenses listed when no count is given.
+const DEFAULT_COUNT: usize = 10;
+
+/// The filters in effect, as the listing names them.
+struct Described {
+    scope: LastScope,
+    category: Option<String>,
+    account: Option<String>,
+    sort: ExpenseSort,
+}
+
+impl Described {
+    /// Like " in Groceries on Family BYN".
+    fn filters(&self) -> String {
+        let mut text = String::new();
+        if let Some(category) = &self.category {
+            text.push_str(&format!(" in {}", category));
+        }
+        if let Some(account) = &self.account {
+            text.push_str(&format!(" on {}", account));
+        }
+        text
+    }
+
+    /// The heading of a listing of `count` expenses, echoing the filters so
+    /// that a forwarded listing says what it shows.
+    fn header(&self, count: usize) -> String {
+        let filters = self.filters();
+        if count == 0 {
+            return match self.scope {
+                LastScope::Mine => format!("You have no expenses{}.", filters),
+                LastScope::All => format!("The household has no expenses{}.", filters),
+            };
+        }
+        let expenses = match (self.sort, count) {
+            (ExpenseSort::Date, 1) => "last expense".to_string(),
+            (ExpenseSort::Date, n) => format!("last {} expenses", n),
+            (ExpenseSort::Amount, 1) => "largest expense".to_string(),
+            (ExpenseSort::Amount, n) => format!("{} largest expenses", n),
+        };
+        match self.scope {
+            LastScope::Mine => format!("Your {}{}:", expenses, filters),
+            LastScope::All => format!("The household's {}{}:", expenses, filters),
+        }
+    }
+}
+
+/// `/last`: the listing to send, MarkdownV2. Private chats list the user's
+/// own expenses unless told `all`, groups everyone's unless told `mine`.
+pub fn handle_last(
+    model: &SharedModel,
+    user: &User,
+    chat_id: ChatId,
+    args: &str,
+    (now, tz): (DateTime<Utc>, Tz),
+) -> Result<String, Error> {
+    let LastArgs {
+        count,
+        category,
+        account,
+        sort,
+        scope,
+    } = match parse::parse_last(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(escape_md(&usage)),
+    };
+    let model = model.lock().unwrap();
+    let household = user.household;
+    let category = match category {
+        None => None,
+        Some(name) => match resolve::category(&model, household, &name)? {
+            Ok(category) => Some(category),
+            Err(reason) => return Ok(escape_md(&reason)),
+        },
+    };
+    let account = match account {
+        None => None,
+        Some(name) => match resolve::account(&model, household, &name)? {
+            Ok(account) => Some(account),
+            Err(reason) => return Ok(escape_md(&reason)),
+        },
+    };
+    let scope = scope.unwrap_or(match chat_id.is_user() {
+        true => LastScope::Mine,
+        false => LastScope::All,
+    });
+    let filter = ExpenseFilter {
+        category_id: category.as_ref().map(|c| c.id),
+        account_id: account.as_ref().map(|a| a.id),
+        user_id: (scope == LastScope::Mine).then_some(user.telegram_id),
+    };
+    let sort = sort.unwrap_or_default();
+    let expenses = model.last_expenses(household, &filter, sort, count.unwrap_or(DEFAULT_COUNT))?;
+    let options = settings::render_options(&model, chat_id)?;
+
+    let described = Described {
+        scope,
+        category: category.map(|c| c.name),
+        account: account.map(|a| a.name),
+        sort,
+    };
+    let mut lines = vec![escape_md(&described.header(expenses.len()))];
+    let with_user = scope == LastScope::All;
+    for expense in &expenses {
+        lines.push(format::render_expense_line(
+            expense,
+            (now, tz),
+            options,
+            with_user,
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn last_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let alex = model.get_user(1001).unwrap().unwrap();
+        let model = Arc::new(Mutex::new(model));
+        let private = ChatId(1001);
+        // Late on Monday 4 December.
+        let now = DateTime::parse_from_rfc3339("2023-12-04T22:00:00Z")
+            .unwrap()
+            .to_utc();
+        let last =
+            |chat_id, args| handle_last(&model, &alex, chat_id, args, (now, Tz::UTC)).unwrap();
+
+        assert_eq!(
+            "Your last 2 expenses:\n\
+             1 day ago · 30\\.00 BYN · 🎬 Entertainment · Family BYN · Cinema tickets for family\n\
+             3 days ago · 50\\.75 EUR · 🛒 Groceries · Alex Savings · Bought groceries for the week",
+            last(private, "")
+        );
+        assert_eq!(
+            "Your last expense in Groceries:\n\
+             3 days ago · 50\\.75 EUR · 🛒 Groceries · Alex Savings · Bought groceries for the week",
+            last(private, "category groc")
+        );
+        assert_eq!(
+            "You have no expenses in Groceries on Family BYN\\.",
+            last(private, "category Groceries account \"family byn\"")
+        );
+        // Groups list everyone's, with who logged them.
+        assert_eq!(
+            "The household's 2 largest expenses:\n\
+             2 hours ago · 100\\.00 USD · 💡 Utilities · Hanna Daily · Hanna · Paid electricity bill\n\
+             3 days ago · 50\\.75 EUR · 🛒 Groceries · Alex Savings · Alex · Bought groceries for the week",
+            last(ChatId(-100), "2 sort amount")
+        );
+        assert_eq!(
+            "Your last expense on Alex Savings:\n\
+             3 days ago · 50\\.75 EUR · 🛒 Groceries · Alex Savings · Bought groceries for the week",
+            last(ChatId(-100), "mine account alex")
+        );
+        assert_eq!(
+            "The household's last expense on Hanna Daily:\n\
+             2 hours ago · 100\\.00 USD · 💡 Utilities · Hanna Daily · Hanna · Paid electricity bill",
+            last(private, "1 all account hanna")
+        );
+        assert_eq!(
+            "No category matches 'rent'\\. Did you mean: Groceries, Utilities, Entertainment?",
+            last(private, "category rent")
+        );
+        assert_eq!(escape_md(parse::LAST_USAGE), last(private, "mine all"));
+
+        // In Alex's Telegram language privately, English in groups.
+        model
+            .lock()
+            .unwrap()
+            .touch_user(1001, ("alex_bot", "Alex"), Some("ru"))
+            .unwrap();
+        assert!(last(private, "1").ends_with("\n1 день назад · 30\\.00 BYN · 🎬 Entertainment · Family BYN · Cinema tickets for family"));
+        assert!(last(ChatId(-100), "1").contains("\n2 hours ago · "));
+    }
+}
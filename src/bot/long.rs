@@ -0,0 +1,314 @@
+//! Messages longer than Telegram accepts, split into several or sent as a
+//! file.
+
+use super::{Bot, HandlerResult};
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+
+/// Most characters put in one message, a little under Telegram's 4096.
+pub const CHUNK_LIMIT: usize = 4000;
+
+/// What text too long for `max_messages` messages is sent as.
+const FILE_NAME: &str = "message.txt";
+
+/// Markup opening and closing an entity with the same delimiter, longest
+/// first so that `__` isn't read as two `_`.
+const DELIMITERS: [&str; 7] = ["```", "`", "||", "__", "*", "_", "~"];
+
+/// A piece of MarkdownV2 text that is never cut.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Piece<'a> {
+    /// A character, or an escape sequence.
+    Text(&'a str),
+    Newline,
+    Open(&'static str),
+    Close(&'static str),
+    /// `[text](url)`, entities inside included.
+    Link {
+        text: &'a str,
+        url: &'a str,
+    },
+}
+
+impl Piece<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Piece::Text(s) => s.chars().count(),
+            Piece::Newline => 1,
+            Piece::Open(d) | Piece::Close(d) => d.len(),
+            Piece::Link { text, url } => text.chars().count() + url.chars().count() + 4,
+        }
+    }
+
+    fn push_to(&self, out: &mut String) {
+        match self {
+            Piece::Text(s) => out.push_str(s),
+            Piece::Newline => out.push('\n'),
+            Piece::Open(d) | Piece::Close(d) => out.push_str(d),
+            Piece::Link { text, url } => {
+                out.push('[');
+                out.push_str(text);
+                out.push_str("](");
+                out.push_str(url);
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// The link starting at `rest`, as its text and url, and its length.
+fn link(rest: &str) -> Option<(&str, &str, usize)> {
+    let (mut escaped, mut after_text) = (false, false);
+    let mut url_start = None;
+    for (i, c) in rest.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+            after_text = false;
+            continue;
+        }
+        match (c, url_start) {
+            ('\n', _) => return None,
+            ('\\', _) => escaped = true,
+            (')', Some(start)) => return Some((&rest[1..start - 2], &rest[start..i], i + 1)),
+            ('(', None) if after_text => url_start = Some(i + 1),
+            _ => {}
+        }
+        after_text = c == ']' && url_start.is_none();
+    }
+    None
+}
+
+fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // Inside code only its own delimiter ends it.
+        let top = open.last().copied();
+        let delimiter = match top {
+            Some(code @ ("`" | "```")) => rest.starts_with(code).then_some(code),
+            _ => DELIMITERS.into_iter().find(|d| rest.starts_with(d)),
+        };
+        let (piece, len) = if c == '\\' {
+            let len = 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+            (Piece::Text(&rest[..len]), len)
+        } else if c == '\n' {
+            (Piece::Newline, 1)
+        } else if let Some(d) = delimiter {
+            if top == Some(d) {
+                open.pop();
+                (Piece::Close(d), d.len())
+            } else {
+                open.push(d);
+                (Piece::Open(d), d.len())
+            }
+        } else if let Some((text, url, len)) = (c == '[' && !matches!(top, Some("`" | "```")))
+            .then(|| link(rest))
+            .flatten()
+        {
+            (Piece::Link { text, url }, len)
+        } else {
+            (Piece::Text(&rest[..c.len_utf8()]), c.len_utf8())
+        };
+        pieces.push(piece);
+        rest = &rest[len..];
+    }
+    pieces
+}
+
+/// What opens `open` again at the start of the next message. A `pre` block
+/// needs its line break, or the first line would be read as its language.
+fn reopen(open: &[&'static str]) -> String {
+    open.iter()
+        .map(|d| if *d == "```" { "```\n" } else { d })
+        .collect()
+}
+
+fn close(open: &[&'static str]) -> String {
+    open.iter().rev().copied().collect()
+}
+
+fn apply(open: &mut Vec<&'static str>, piece: Piece) {
+    match piece {
+        Piece::Open(d) => open.push(d),
+        Piece::Close(_) => {
+            open.pop();
+        }
+        _ => {}
+    }
+}
+
+/// Splits MarkdownV2 `text` into messages of at most `limit` characters.
+/// Each cut goes at the last line break outside of any entity that fits.
+/// Where there is none, it goes at the last line break, or, in a line longer
+/// than the limit, between any two characters; the entities still open are
+/// closed there and opened again in the next message. Escape sequences and
+/// links are never cut.
+pub fn split_markdown(text: &str, limit: usize) -> Vec<String> {
+    let pieces = pieces(text);
+    let mut messages = Vec::new();
+    let (mut start, mut open_at_start) = (0, Vec::new());
+    'messages: loop {
+        let mut open = open_at_start.clone();
+        let mut len = reopen(&open).chars().count();
+        // The last line break outside of entities, and the last one at all
+        // with what is open there.
+        let mut clean = None;
+        let mut newline: Option<(usize, Vec<&'static str>)> = None;
+        for (i, piece) in pieces.iter().enumerate().skip(start) {
+            if *piece == Piece::Newline && i > start {
+                if open.is_empty() {
+                    clean = Some(i);
+                }
+                newline = Some((i, open.clone()));
+            }
+            let mut after = open.clone();
+            apply(&mut after, *piece);
+            if len + piece.len() + close(&after).len() > limit && i > start {
+                let (cut, still_open, next) = match (clean, newline) {
+                    (Some(cut), _) => (cut, Vec::new(), cut + 1),
+                    (None, Some((cut, still_open))) => (cut, still_open, cut + 1),
+                    (None, None) => (i, open, i),
+                };
+                let mut message = reopen(&open_at_start);
+                pieces[start..cut]
+                    .iter()
+                    .for_each(|p| p.push_to(&mut message));
+                message.push_str(&close(&still_open));
+                messages.push(message);
+                (start, open_at_start) = (next, still_open);
+                continue 'messages;
+            }
+            len += piece.len();
+            open = after;
+        }
+        if start < pieces.len() || messages.is_empty() {
+            let mut message = reopen(&open_at_start);
+            pieces[start..].iter().for_each(|p| p.push_to(&mut message));
+            messages.push(message);
+        }
+        return messages;
+    }
+}
+
+/// `text` without its markup, as read in the chat.
+pub fn plain_text(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    for piece in pieces(text) {
+        match piece {
+            Piece::Text(s) => plain.push_str(s.strip_prefix('\\').unwrap_or(s)),
+            Piece::Newline => plain.push('\n'),
+            Piece::Open(_) | Piece::Close(_) => {}
+            Piece::Link { text, url } => {
+                plain.push_str(&plain_text(text));
+                plain.push_str(" (");
+                plain.push_str(&plain_text(url));
+                plain.push(')');
+            }
+        }
+    }
+    plain
+}
+
+/// Sends MarkdownV2 `text` in as many messages as it takes. Text needing
+/// more than `max_messages` of them is sent as a plain text file instead.
+pub async fn send_long(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: String,
+    max_messages: usize,
+) -> HandlerResult {
+    let messages = split_markdown(&text, CHUNK_LIMIT);
+    if messages.len() > max_messages {
+        let file = InputFile::memory(plain_text(&text).into_bytes()).file_name(FILE_NAME);
+        bot.send_document(chat_id, file).await?;
+        return Ok(());
+    }
+    for message in messages {
+        bot.send_message(chat_id, message).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(n: usize, width: usize) -> Vec<String> {
+        (0..n).map(|i| format!("{:width$}", i)).collect()
+    }
+
+    #[test]
+    fn short_text_is_one_message() {
+        assert_eq!(vec!["hello\nworld"], split_markdown("hello\nworld", 20));
+        assert_eq!(vec![""], split_markdown("", 20));
+    }
+
+    #[test]
+    fn splits_exactly_at_the_limit() {
+        // Two lines of 9 and the line break between them make 19.
+        let text = lines(4, 9).join("\n");
+        let messages = split_markdown(&text, 19);
+        assert_eq!(
+            vec![lines(2, 9)[..].join("\n"), lines(4, 9)[2..].join("\n")],
+            messages
+        );
+
+        // One character less and every line is on its own.
+        let messages = split_markdown(&text, 18);
+        assert_eq!(lines(4, 9), messages);
+        assert!(messages.iter().all(|m| m.chars().count() <= 18));
+    }
+
+    #[test]
+    fn cuts_long_lines_anywhere_but_escapes() {
+        let messages = split_markdown("abcdefghij", 4);
+        assert_eq!(vec!["abcd", "efgh", "ij"], messages);
+
+        let messages = split_markdown("ab\\.cd\\.ef", 3);
+        assert_eq!(vec!["ab", "\\.c", "d\\.", "ef"], messages);
+
+        // Counted in characters, not bytes.
+        assert_eq!(vec!["ééé", "é"], split_markdown("éééé", 3));
+    }
+
+    #[test]
+    fn keeps_entities_whole() {
+        // A pre block spanning the line break the limit would cut at.
+        let text = "Report\n```\nrow 1\nrow 2\n```\nend";
+        assert_eq!(
+            vec!["Report", "```\nrow 1\nrow 2\n```\nend"],
+            split_markdown(text, 24)
+        );
+        assert_eq!(
+            vec!["*bold\nstill bold*", "plain"],
+            split_markdown("*bold\nstill bold*\nplain", 20)
+        );
+        // Links are never cut, and escapes inside code are no delimiters.
+        assert_eq!(
+            vec!["see", "[a *b*](tg://x)", "`\\`*`"],
+            split_markdown("see\n[a *b*](tg://x)\n`\\`*`", 15)
+        );
+    }
+
+    #[test]
+    fn reopens_entities_too_long_for_one_message() {
+        let text = "```\nrow 1\nrow 2\nrow 3\n```";
+        let messages = split_markdown(text, 18);
+        assert_eq!(vec!["```\nrow 1\nrow 2```", "```\nrow 3\n```"], messages);
+        assert!(messages.iter().all(|m| m.chars().count() <= 18));
+
+        assert_eq!(
+            vec!["*__abcd__*", "*__efg__*"],
+            split_markdown("*__abcdefg__*", 10)
+        );
+    }
+
+    #[test]
+    fn strips_markup() {
+        assert_eq!(
+            "Total: 1.50 (see docs (https://x.y/z))\n\nrow `1`\n",
+            plain_text("*Total:* 1\\.50 \\(see [docs](https://x.y/z)\\)\n```\nrow \\`1\\`\n```")
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! All outgoing messages use the MarkdownV2 parse mode (set once on the bot
+//! with `DefaultParseMode`). Any user provided or otherwise dynamic text
+//! must pass through one of the escape functions here before being sent.
+
+/// Longest comment shown in messages. Stored comments are never shortened.
+pub const MAX_DISPLAYED_COMMENT: usize = 200;
+
+/// Escapes text placed in a message outside of entities.
+pub fn escape_md(text: &str) -> String {
+    const SPECIAL: &str = "_*[]()~`>#+-=|{}.!\\";
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes text placed inside a `pre` or `code` entity.
+pub fn escape_code(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '`' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Shortens `text` to at most `max_chars` characters, marking the cut with
+/// an ellipsis. Apply before escaping, so escape sequences are never split.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Truncated and escaped comment, ready to be interpolated.
+pub fn comment(text: &str) -> String {
+    escape_md(&truncate(text, MAX_DISPLAYED_COMMENT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_markdown() {
+        assert_eq!("a\\*b\\_c\\[d\\]\\`e\\\\", escape_md("a*b_c[d]`e\\"));
+        assert_eq!("plain text", escape_md("plain text"));
+    }
+
+    #[test]
+    fn escapes_every_special_character() {
+        let special = "*_[]()~>#+-=|{}.!";
+        let escaped = escape_md(special);
+        assert_eq!(special.len() * 2, escaped.len());
+        // Dropping the escapes gives back the literal text.
+        assert_eq!(special, escaped.replace('\\', ""));
+    }
+
+    #[test]
+    fn escapes_code() {
+        assert_eq!("a\\`b\\\\c*", escape_code("a`b\\c*"));
+    }
+
+    #[test]
+    fn truncates_with_ellipsis() {
+        assert_eq!("short", truncate("short", 5));
+        assert_eq!("shor…", truncate("shorter", 5));
+        assert_eq!("éé…", truncate("éééé", 3));
+    }
+
+    #[test]
+    fn long_comment_is_capped() {
+        let long = "a.".repeat(MAX_DISPLAYED_COMMENT);
+        let rendered = comment(&long);
+        assert!(rendered.ends_with('…'));
+        assert_eq!(
+            MAX_DISPLAYED_COMMENT,
+            rendered.replace('\\', "").chars().count()
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! The command menu Telegram clients show next to the input field. It is
+//! generated from [`Command`], so it can't drift from what the bot parses.
+
+use super::commands::Command;
+use super::Bot;
+use crate::model::Role;
+use log::*;
+use teloxide::prelude::*;
+use teloxide::types::{BotCommand, BotCommandScope, Recipient};
+use teloxide::utils::command::BotCommands;
+
+/// Languages with translated descriptions. Everyone else gets English.
+const LANGUAGES: &[&str] = &["ru"];
+
+fn translated(language: &str, command: &str) -> Option<&'static str> {
+    let description = match (language, command) {
+        ("ru", "help") => "показать этот текст.",
+        ("ru", "start") => "показать ваш id и роль.",
+        ("ru", "role") => "задать роль пользователя: /role <пользователь> <admin|member|viewer>.",
+        ("ru", "allow") => {
+            "впустить пользователя и поприветствовать его: /allow <пользователь> [admin|member|viewer], по умолчанию участником."
+        }
+        ("ru", "add") => {
+            "сразу сохранить расход: /add <сумма> <категория> [\"комментарий\"] [acc:<счёт>]."
+        }
+        ("ru", "fav") => {
+            "избранное в одно касание: /fav, /fav add <сумма> <категория> [\"название\"] [acc:<счёт>], /fav del <название>."
+        }
+        ("ru", "feed") => {
+            "закреплённое сообщение с итогами месяца в чате: /feed enable|disable."
+        }
+        ("ru", "report") => {
+            "расходы по категориям: /report [week|lastweek|month|lastmonth|ytd|ГГГГ-ММ-ДД..ГГГГ-ММ-ДД], картинкой: /report img [период], прогноз на месяц: /report forecast, расходы по дням недели: /report heatmap [категория] или итоги года: /report year [ГГГГ]. По дате записи: /report by logged [период]."
+        }
+        ("ru", "export") => "выгрузить расходы за год в файл: /export [csv|xlsx] [ГГГГ].",
+        ("ru", "review") => "недавние расходы без категории или комментария.",
+        ("ru", "settings") => {
+            "показать и изменить ваши настройки, включить или выключить итог месяца после сохранения: /settings mtd on|off, начинать недели и месяцы с другого дня: /settings week mon|sun, /settings month-start <день>, сократить подтверждения: /settings confirm full|compact|silent, указать валюту сумм в чате: /settings currency <валюта>|none, не присылать плановые сообщения ночью: /settings quiet <с>-<до>|off, /settings timezone <зона>|none, или скрыть суммы в группе: /settings privacy on|off."
+        }
+        ("ru", "balance") => "остатки на счетах по валютам.",
+        ("ru", "rate") => {
+            "задать курс на сегодня: /rate <валюта> <курс в базовой валюте>, или посмотреть, насколько устарели курсы: /rate status."
+        }
+        ("ru", "currency") => {
+            "изменить валюту: /currency set <валюта> exponent <знаков после запятой>."
+        }
+        ("ru", "recategorize") => {
+            "перенести расходы в другую категорию: /recategorize from <категория> to <категория> [ГГГГ-ММ..ГГГГ-ММ]."
+        }
+        ("ru", "category") => {
+            "изменить категории: /category merge \"<откуда>\" into \"<куда>\", /category parent <категория> under <родитель>|none, /category require-comment <категория> on|off, или посмотреть расходы: /category stats <категория>."
+        }
+        ("ru", "integrity") => "проверить базу данных на повреждения и потерянные ссылки.",
+        ("ru", "maintenance") => {
+            "сжать базу данных и показать её размер до и после, или сразу удалить старые записи журнала и данные кнопок: /maintenance prune."
+        }
+        ("ru", "readonly") => {
+            "приостановить все изменения перед восстановлением или миграцией, отчёты продолжают работать: /readonly on|off."
+        }
+        ("ru", "household") => {
+            "семья этого чата: /household, привязать чат к вашей: /household bind <название>, создать новую: /household new <название> <пользователь>."
+        }
+        ("ru", "migrate") => {
+            "завершить переход на целые суммы: /migrate verify-amounts, затем /migrate finalize."
+        }
+        ("ru", "alias") => {
+            "сокращения команд: /alias add <имя> \"<команда> [аргументы]\", /alias del <имя>, /alias list."
+        }
+        ("ru", "template") => {
+            "комментарии с пропусками для кнопки Comment черновика: /template comment add <имя> \"бензин {литры}L на {заправка}\", /template comment del <имя>, /template comment list."
+        }
+        ("ru", "last") => {
+            "последние расходы: /last [<число>] [category <категория>] [account \"<счёт>\"] [sort date|amount] [mine|all], в личном чате ваши, в группе всех."
+        }
+        ("ru", "fun") => {
+            "забавные рекорды месяца, например самая длинная серия дней с тратами: /fun [lastmonth|ГГГГ-ММ]."
+        }
+        ("ru", "settle") => {
+            "кто кому должен за разделённые расходы: /settle, отметить, что вы рассчитались: /settle clear."
+        }
+        ("ru", "notify") => {
+            "личное сообщение, когда другие записывают расходы: /notify on|off, только от суммы: /notify over <сумма> <валюта>, или в категории: /notify category <категория>."
+        }
+        ("ru", "subscribe") => {
+            "личный отчёт о ваших расходах: /subscribe weekly|monthly, /subscribe off, или когда придёт следующий: /subscribe status."
+        }
+        ("ru", "archive") => {
+            "перенести старые расходы в отдельный файл, отчёты их по-прежнему учитывают: /archive before <ГГГГ-ММ-ДД>."
+        }
+        ("ru", "rule") => {
+            "выбирать категорию черновика по комментарию: /rule add \"<шаблон>\" -> <категория>, /rule del <id>, /rule test <текст>, /rule list."
+        }
+        ("ru", "source") => {
+            "делать черновики из пересланных сообщений канала или бота: /source add [chat:<id>|user:<id>] <категория> -> <счёт> в ответ на пересланное, /source template <id> \"<шаблон>\"|none, /source del <id>, /source list."
+        }
+        ("ru", "account") => {
+            "удалить счёт, созданный по ошибке: /account delete \"<счёт>\", или сначала перенести его расходы и избранное на другой: /account delete \"<счёт>\" into \"<другой счёт>\"."
+        }
+        ("ru", "reconcile") => {
+            "сверить счёт с банком: /reconcile \"<счёт>\" <остаток>, или посмотреть прошлые сверки: /reconcile history."
+        }
+        ("ru", "close") => {
+            "закрыть сверенный месяц для изменений: /close ГГГГ-ММ, или посмотреть закрытые: /close."
+        }
+        ("ru", "reopen") => "снова разрешить изменения в закрытом месяце: /reopen ГГГГ-ММ.",
+        ("ru", "budget") => {
+            "сколько осталось от месячных бюджетов: /budget, или задать бюджет: /budget <категория> <сумма>|none <валюта>."
+        }
+        ("ru", "setup") => {
+            "создать первый счёт и категории по шагам: /setup [cancel]."
+        }
+        _ => return None,
+    };
+    Some(description)
+}
+
+/// A command parsed from its bare name, to look up its required role.
+fn sample(name: &str) -> Option<Command> {
+    Command::parse(&format!("/{}", name), "")
+        .or_else(|_| Command::parse(&format!("/{} x x", name), ""))
+        .ok()
+}
+
+/// The names of the commands `role` may run, in menu order.
+pub fn commands_for(role: Role) -> Vec<String> {
+    Command::bot_commands()
+        .into_iter()
+        .map(|c| c.command.trim_start_matches('/').to_string())
+        .filter(|name| {
+            sample(name)
+                .and_then(|c| c.required_role())
+                .is_none_or(|required| role.allows(required))
+        })
+        .collect()
+}
+
+/// The menu for admins or everybody else, in `language` if translated.
+pub fn menu(admin: bool, language: Option<&str>) -> Vec<BotCommand> {
+    Command::bot_commands()
+        .into_iter()
+        .filter_map(|c| {
+            let name = c.command.trim_start_matches('/').to_string();
+            let is_admin_only = sample(&name)?.required_role() == Some(Role::Admin);
+            if is_admin_only && !admin {
+                return None;
+            }
+            let description = language
+                .and_then(|l| translated(l, &name))
+                .map_or(c.description, str::to_string);
+            Some(BotCommand::new(name, description))
+        })
+        .collect()
+}
+
+/// Registers the menus: the admin commands only in the chat with the admin.
+/// Setting the commands replaces the previous ones, so running this on every
+/// start is safe; failures are logged and don't stop the bot.
+pub async fn register(bot: &Bot, admin_id: Option<i64>) {
+    let scopes = [(false, BotCommandScope::Default)]
+        .into_iter()
+        .chain(admin_id.map(|id| {
+            (
+                true,
+                BotCommandScope::Chat {
+                    chat_id: Recipient::Id(ChatId(id)),
+                },
+            )
+        }));
+    for (admin, scope) in scopes {
+        for language in [None]
+            .into_iter()
+            .chain(LANGUAGES.iter().copied().map(Some))
+        {
+            let mut request = bot
+                .set_my_commands(menu(admin, language))
+                .scope(scope.clone());
+            if let Some(language) = language {
+                request = request.language_code(language);
+            }
+            if let Err(e) = request.await {
+                warn!(
+                    "Failed to register the {} command menu ({}): {}",
+                    if admin { "admin" } else { "default" },
+                    language.unwrap_or("default language"),
+                    e
+                );
+            }
+        }
+    }
+    info!("Registered the command menus");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(menu: &[BotCommand]) -> Vec<&str> {
+        menu.iter().map(|c| c.command.as_str()).collect()
+    }
+
+    #[test]
+    fn admin_menu_lists_every_command() {
+        let all: Vec<String> = Command::bot_commands()
+            .into_iter()
+            .map(|c| c.command.trim_start_matches('/').to_string())
+            .collect();
+        assert_eq!(all, names(&menu(true, None)));
+        for language in LANGUAGES {
+            assert_eq!(all, names(&menu(true, Some(language))));
+        }
+    }
+
+    #[test]
+    fn default_menu_hides_admin_commands() {
+        let menu = menu(false, None);
+        let names = names(&menu);
+        assert!(names.contains(&"add"));
+        assert!(names.contains(&"report"));
+        for admin_only in ["role", "rate", "currency", "recategorize", "category"] {
+            assert!(!names.contains(&admin_only), "{}", admin_only);
+        }
+    }
+
+    #[test]
+    fn every_command_is_translated() {
+        let english = menu(true, None);
+        for language in LANGUAGES {
+            for (command, translated) in english.iter().zip(menu(true, Some(language))) {
+                assert_ne!(
+                    command.description, translated.description,
+                    "{}",
+                    command.command
+                );
+            }
+        }
+        // Unknown languages fall back to English.
+        assert_eq!(english, menu(true, Some("xx")));
+    }
+}
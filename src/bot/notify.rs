@@ -0,0 +1,191 @@
+//! `/notify`: a private message whenever someone else in the household logs
+//! an expense, optionally only above an amount or in a category.
+
+use super::parse::{self, NotifyArgs};
+use super::reporter::{self, ErrorReporter};
+use super::{format, keyboards, resolve, Bot, SharedModel};
+use crate::model::{from_minor, to_minor, AmountLimits, Error, Locale, Model, Subscription, User};
+use log::*;
+use teloxide::prelude::*;
+
+fn describe(
+    model: &Model,
+    household: i64,
+    subscription: Option<&Subscription>,
+) -> Result<String, Error> {
+    let Some(subscription) = subscription else {
+        return Ok("Notifications are off.".to_string());
+    };
+    let mut text = "You are notified of the expenses others in your household log".to_string();
+    if let Some((currency, minor)) = &subscription.over {
+        let exponent = model.currency_exponent(currency)?.unwrap_or(2);
+        text.push_str(&format!(
+            " of at least {} {}",
+            format::format_amount(from_minor(*minor, exponent), exponent),
+            currency
+        ));
+    }
+    if let Some(id) = subscription.category_id {
+        match model.get_category(household, id)? {
+            Some(category) => text.push_str(&format!(" in {}", category.name)),
+            None => text.push_str(" in a category that is gone"),
+        }
+    }
+    text.push('.');
+    Ok(text)
+}
+
+/// `/notify`: the reply to send. Filters are added to the ones already set,
+/// `/notify on` clears them.
+pub fn handle_notify(
+    model: &SharedModel,
+    user: &User,
+    args: &str,
+    (limits, locale): (AmountLimits, Locale),
+) -> Result<String, Error> {
+    let args = match parse::parse_notify(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let user_id = user.telegram_id;
+    let current = model.subscription(user_id)?;
+    let subscription = match args {
+        NotifyArgs::Show => return describe(&model, user.household, current.as_ref()),
+        NotifyArgs::Off => {
+            model.unsubscribe(user_id)?;
+            return describe(&model, user.household, None);
+        }
+        NotifyArgs::On => Subscription::default(),
+        NotifyArgs::Over { amount, currency } => {
+            let Some(exponent) = model.currency_exponent(&currency)? else {
+                return Ok(format!("Unknown currency '{}'.", currency));
+            };
+            let amount = match limits.for_exponent(exponent).parse(&amount, locale) {
+                Ok(parsed) => parsed.amount,
+                Err(e) => return Ok(e.to_string()),
+            };
+            Subscription {
+                over: Some((currency, to_minor(amount, exponent))),
+                ..current.unwrap_or_default()
+            }
+        }
+        NotifyArgs::Category(name) => {
+            let category = match resolve::category(&model, user.household, &name)? {
+                Ok(category) => category,
+                Err(reason) => return Ok(reason),
+            };
+            Subscription {
+                category_id: Some(category.id),
+                ..current.unwrap_or_default()
+            }
+        }
+    };
+    model.subscribe(user_id, &subscription)?;
+    describe(&model, user.household, Some(&subscription))
+}
+
+/// Tells the household's subscribers about the new expense `id` in the
+/// background, so that whoever logged it doesn't wait for the messages.
+pub fn expense_logged(
+    bot: &Bot,
+    model: &SharedModel,
+    reporter: &ErrorReporter,
+    household: i64,
+    id: i64,
+) {
+    let (bot, model, errors) = (bot.clone(), model.clone(), reporter.clone());
+    reporter::spawn(reporter, "notification", async move {
+        let recipients = {
+            let model = model.lock().unwrap();
+            model
+                .get_expense_details(household, id)
+                .and_then(|expense| match expense {
+                    Some(expense) => Ok(Some((model.subscribers(household, &expense)?, expense))),
+                    None => Ok(None),
+                })
+        };
+        let (subscribers, expense) = match recipients {
+            Ok(Some(recipients)) => recipients,
+            Ok(None) => return,
+            Err(e) => {
+                errors.report(
+                    Level::Warn,
+                    "notification",
+                    format!("expense {}: {}", id, e),
+                );
+                return;
+            }
+        };
+        let text = format::render_notification(&expense);
+        for user_id in subscribers {
+            // Private chats have the id of the user.
+            if let Err(e) = bot
+                .send_message(ChatId(user_id), text.clone())
+                .reply_markup(keyboards::view_keyboard(id))
+                .await
+            {
+                let message = format!("user {} of expense {}: {}", user_id, id, e);
+                errors.report(Level::Info, "notification", message);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn notify_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let hanna = model.get_user(1002).unwrap().unwrap();
+        let model = Arc::new(Mutex::new(model));
+        let notify = |args| {
+            handle_notify(
+                &model,
+                &hanna,
+                args,
+                (AmountLimits::default(), Locale::DecimalPoint),
+            )
+            .unwrap()
+        };
+
+        assert_eq!("Notifications are off.", notify(""));
+        assert_eq!(
+            "You are notified of the expenses others in your household log.",
+            notify("on")
+        );
+        assert_eq!(
+            "You are notified of the expenses others in your household log of at least 50.00 EUR.",
+            notify("over 50 eur")
+        );
+        assert_eq!(
+            "You are notified of the expenses others in your household log of at least 50.00 EUR in Groceries.",
+            notify("category groc")
+        );
+        assert_eq!(
+            "You are notified of the expenses others in your household log of at least 50.00 EUR in Groceries.",
+            notify("")
+        );
+        assert_eq!("Unknown currency 'XYZ'.", notify("over 5 xyz"));
+        assert!(notify("category Other").starts_with("No category matches 'Other'."));
+
+        // Alex's groceries pass both filters.
+        let expense = model
+            .lock()
+            .unwrap()
+            .get_expense_details(1, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![1002],
+            model.lock().unwrap().subscribers(1, &expense).unwrap()
+        );
+
+        assert_eq!("Notifications are off.", notify("off"));
+        assert_eq!(None, model.lock().unwrap().subscription(1002).unwrap());
+    }
+}
@@ -0,0 +1,383 @@
+//! `/allow`: letting a user in and welcoming them with their role, what the
+//! household has, and a button to log their first expense. The bot can't
+//! message users who never started it, so their welcome waits for their
+//! first `/start`. It goes out once either way.
+
+use super::draft::ActiveTransaction;
+use super::expense::{new_draft, Typed};
+use super::markdown::escape_md;
+use super::parse;
+use super::{keyboards, Bot, HandlerError, SharedModel};
+use crate::model::{Error, Model, ParsedAmount, Role, User};
+use std::future::Future;
+use teloxide::prelude::*;
+use teloxide::types::InlineKeyboardMarkup;
+use teloxide::{ApiError, RequestError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Welcome {
+    /// Plain text, escaped when sent.
+    pub text: String,
+    /// The button to log a first expense, for those who can.
+    pub keyboard: Option<InlineKeyboardMarkup>,
+}
+
+/// What became of a user's welcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Welcomed {
+    Sent,
+    /// They got it before.
+    Already,
+    /// The bot can't message them until they start it.
+    Unreachable,
+}
+
+fn welcome_of(model: &Model, user: &User) -> Result<Welcome, Error> {
+    let mut lines = vec![format!(
+        "👋 Welcome, {}! You are a {} of the household {}.",
+        user.display_name,
+        user.role,
+        model.household_name(user.household)?
+    )];
+    let accounts = model.accounts(user.household)?;
+    let categories = model.categories(user.household)?;
+    if accounts.is_empty() || categories.is_empty() {
+        lines.push(
+            "There are no accounts or categories yet, the admin is still setting up.".to_string(),
+        );
+        return Ok(Welcome {
+            text: lines.join("\n"),
+            keyboard: None,
+        });
+    }
+    let accounts: Vec<String> = accounts
+        .iter()
+        .map(|a| format!("{} ({})", a.name, a.currency))
+        .collect();
+    lines.push(format!("Accounts: {}", accounts.join(", ")));
+    let categories: Vec<String> = categories
+        .iter()
+        .map(|c| match &c.icon {
+            Some(icon) => format!("{} {}", icon, c.name),
+            None => c.name.clone(),
+        })
+        .collect();
+    lines.push(format!("Categories: {}", categories.join(", ")));
+    let can_log = user.role.allows(Role::Member);
+    lines.push(if can_log {
+        "Send an amount with a comment, like 12.50 lunch, to log an expense, or tap below to be walked through the first one. /help lists the commands.".to_string()
+    } else {
+        "As a viewer you can see /report and /balance, but not log expenses. /help lists the commands.".to_string()
+    });
+    Ok(Welcome {
+        text: lines.join("\n"),
+        keyboard: can_log.then(keyboards::welcome_keyboard),
+    })
+}
+
+/// Whether an error sending to a user means they never started the bot,
+/// or stopped it.
+fn unreachable(error: &RequestError) -> bool {
+    matches!(
+        error,
+        RequestError::Api(
+            ApiError::BotBlocked | ApiError::CantInitiateConversation | ApiError::ChatNotFound
+        )
+    )
+}
+
+/// Sends `user` their welcome with `send`, unless they got it already. A
+/// welcome that can't be sent is left for the next try.
+pub async fn welcome<F, Fut>(
+    model: &SharedModel,
+    user: &User,
+    send: F,
+) -> Result<Welcomed, HandlerError>
+where
+    F: FnOnce(Welcome) -> Fut,
+    Fut: Future<Output = Result<(), RequestError>>,
+{
+    let welcome = {
+        let model = model.lock().unwrap();
+        let welcome = welcome_of(&model, user)?;
+        if !model.set_welcomed(user.telegram_id, true)? {
+            return Ok(Welcomed::Already);
+        }
+        welcome
+    };
+    match send(welcome).await {
+        Ok(()) => Ok(Welcomed::Sent),
+        Err(e) => {
+            model
+                .lock()
+                .unwrap()
+                .set_welcomed(user.telegram_id, false)?;
+            if unreachable(&e) {
+                Ok(Welcomed::Unreachable)
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Sends a welcome to `chat_id`.
+pub async fn send(bot: &Bot, chat_id: ChatId, welcome: Welcome) -> Result<(), RequestError> {
+    let mut request = bot.send_message(chat_id, escape_md(&welcome.text));
+    if let Some(keyboard) = welcome.keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    request.await?;
+    Ok(())
+}
+
+/// Allows `name` in the household, returns the user or why they weren't.
+fn allow(
+    model: &Model,
+    household: i64,
+    name: &str,
+    role: Role,
+) -> Result<Result<User, String>, Error> {
+    let telegram_id = match model.resolve_user_id(name) {
+        Ok(id) => id,
+        Err(Error::NotFound(_)) => {
+            return Ok(Err(format!("Unknown user {}. Use their numeric id.", name)))
+        }
+        Err(Error::Refused(reason)) => return Ok(Err(reason)),
+        Err(e) => return Err(e),
+    };
+    if let Some(user) = model.get_user(telegram_id)? {
+        if user.household == household {
+            return Ok(Err(format!(
+                "User {} is allowed already, as a {}. /role changes that.",
+                name, user.role
+            )));
+        }
+    }
+    match model.set_role(household, telegram_id, role) {
+        Ok(()) => {}
+        Err(Error::Refused(reason)) => return Ok(Err(reason)),
+        Err(e) => return Err(e),
+    }
+    let user = model
+        .get_user(telegram_id)?
+        .ok_or_else(|| Error::NotFound(format!("user {}", telegram_id)))?;
+    Ok(Ok(user))
+}
+
+/// `/allow <user> [role]`, answered in `chat_id`.
+pub async fn handle_allow(
+    bot: &Bot,
+    model: &SharedModel,
+    household: i64,
+    chat_id: ChatId,
+    args: &str,
+) -> Result<(), HandlerError> {
+    let (name, role) = match parse::parse_allow(args) {
+        Ok(args) => args,
+        Err(usage) => {
+            bot.send_message(chat_id, escape_md(&usage)).await?;
+            return Ok(());
+        }
+    };
+    let allowed = allow(&model.lock().unwrap(), household, &name, role)?;
+    let user = match allowed {
+        Ok(user) => user,
+        Err(reason) => {
+            bot.send_message(chat_id, escape_md(&reason)).await?;
+            return Ok(());
+        }
+    };
+    // Private chats have the id of the user.
+    let private = ChatId(user.telegram_id);
+    let text = match welcome(model, &user, |w| send(bot, private, w)).await? {
+        Welcomed::Sent => format!("Allowed {} as a {} and sent them a welcome.", name, role),
+        Welcomed::Already => format!("Allowed {} as a {}.", name, role),
+        Welcomed::Unreachable => format!(
+            "Allowed {} as a {}. The bot can't message them before they start it, so share {} with them: their welcome waits for their first /start.",
+            name,
+            role,
+            bot.get_me().await?.tme_url()
+        ),
+    };
+    bot.send_message(chat_id, escape_md(&text)).await?;
+    Ok(())
+}
+
+/// The draft "log my first expense" opens: the household's first account
+/// and category, asking for the amount. `None` while there are none.
+pub fn first_draft(model: &Model, user: &User) -> Result<Option<ActiveTransaction>, Error> {
+    let account = model.accounts(user.household)?.into_iter().next();
+    let category = model.categories(user.household)?.into_iter().next();
+    let Some((account, category)) = account.zip(category) else {
+        return Ok(None);
+    };
+    let strict_commit = model.get_settings(user.telegram_id)?.strict_commit;
+    let typed = Typed {
+        amount: ParsedAmount::default(),
+        comment: None,
+        original: None,
+        ruled: None,
+    };
+    Ok(Some(new_draft(
+        (user, strict_commit),
+        account,
+        category,
+        typed,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn model() -> SharedModel {
+        let model = Model::new(true);
+        model.fill_test_data();
+        Arc::new(Mutex::new(model))
+    }
+
+    fn newcomer(model: &SharedModel, id: i64, role: Role) -> User {
+        let model = model.lock().unwrap();
+        allow(&model, 1, &id.to_string(), role).unwrap().unwrap()
+    }
+
+    #[test]
+    fn allowing() {
+        let model = model();
+        let user = newcomer(&model, 42, Role::Member);
+        assert_eq!(
+            (42, Role::Member, 1),
+            (user.telegram_id, user.role, user.household)
+        );
+
+        let model = model.lock().unwrap();
+        assert_eq!(
+            Err("User 42 is allowed already, as a member. /role changes that.".to_string()),
+            allow(&model, 1, "42", Role::Admin).unwrap()
+        );
+        assert_eq!(
+            Err("Unknown user @nobody. Use their numeric id.".to_string()),
+            allow(&model, 1, "@nobody", Role::Member).unwrap()
+        );
+        model.fill_second_household();
+        assert_eq!(
+            Err("User 42 belongs to another household.".to_string()),
+            allow(&model, 2, "42", Role::Member).unwrap()
+        );
+    }
+
+    #[test]
+    fn welcome_texts() {
+        let model = model();
+        let member = newcomer(&model, 42, Role::Member);
+        let welcome = welcome_of(&model.lock().unwrap(), &member).unwrap();
+        let household = model.lock().unwrap().household_name(1).unwrap();
+        assert_eq!(
+            format!(
+                "👋 Welcome, 42! You are a member of the household {}.\n\
+                 Accounts: Alex Savings (EUR), Hanna Daily (USD), Family BYN (BYN)\n\
+                 Categories: 🛒 Groceries, 🚌 Transportation, 🎬 Entertainment, 💡 Utilities\n\
+                 Send an amount with a comment, like 12.50 lunch, to log an expense, or tap below to be walked through the first one. /help lists the commands.",
+                household
+            ),
+            welcome.text
+        );
+        assert_eq!(Some(keyboards::welcome_keyboard()), welcome.keyboard);
+
+        let viewer = User {
+            role: Role::Viewer,
+            ..member.clone()
+        };
+        let welcome = welcome_of(&model.lock().unwrap(), &viewer).unwrap();
+        assert!(welcome.text.ends_with("As a viewer you can see /report and /balance, but not log expenses. /help lists the commands."));
+        assert_eq!(None, welcome.keyboard);
+
+        let empty = Model::new(true);
+        let welcome = welcome_of(&empty, &member).unwrap();
+        assert!(welcome.text.ends_with("the admin is still setting up."));
+        assert_eq!(None, welcome.keyboard);
+    }
+
+    /// Records welcomes as sent, or fails like a user who never started
+    /// the bot.
+    fn sender<'a>(
+        sent: &'a Mutex<Vec<String>>,
+        started: bool,
+    ) -> impl FnOnce(Welcome) -> std::future::Ready<Result<(), RequestError>> + 'a {
+        move |welcome| {
+            std::future::ready(if started {
+                sent.lock().unwrap().push(welcome.text);
+                Ok(())
+            } else {
+                Err(RequestError::Api(ApiError::CantInitiateConversation))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn welcomed_at_once() {
+        let model = model();
+        let user = newcomer(&model, 42, Role::Member);
+        let sent = Mutex::new(Vec::new());
+
+        assert_eq!(
+            Welcomed::Sent,
+            welcome(&model, &user, sender(&sent, true)).await.unwrap()
+        );
+        // Their first /start doesn't welcome them again.
+        assert_eq!(
+            Welcomed::Already,
+            welcome(&model, &user, sender(&sent, true)).await.unwrap()
+        );
+        assert_eq!(1, sent.lock().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn welcomed_on_the_first_start() {
+        let model = model();
+        let user = newcomer(&model, 42, Role::Member);
+        let sent = Mutex::new(Vec::new());
+
+        assert_eq!(
+            Welcomed::Unreachable,
+            welcome(&model, &user, sender(&sent, false)).await.unwrap()
+        );
+        assert!(sent.lock().unwrap().is_empty());
+        // Once they start the bot.
+        assert_eq!(
+            Welcomed::Sent,
+            welcome(&model, &user, sender(&sent, true)).await.unwrap()
+        );
+        assert_eq!(
+            Welcomed::Already,
+            welcome(&model, &user, sender(&sent, true)).await.unwrap()
+        );
+        assert_eq!(1, sent.lock().unwrap().len());
+
+        // Other errors are errors, and the welcome is kept for later.
+        let other = newcomer(&model, 43, Role::Member);
+        let failing = |_| std::future::ready(Err(RequestError::Api(ApiError::MessageTextIsEmpty)));
+        assert!(welcome(&model, &other, failing).await.is_err());
+        assert!(model.lock().unwrap().set_welcomed(43, true).unwrap());
+    }
+
+    #[test]
+    fn first_drafts_ask_for_the_amount() {
+        let model = model();
+        let user = newcomer(&model, 42, Role::Member);
+        let draft = first_draft(&model.lock().unwrap(), &user).unwrap().unwrap();
+        assert!(draft.awaits_amount());
+        assert_eq!(
+            (42, "Alex Savings", "Groceries", false),
+            (
+                draft.user_id,
+                draft.account.name.as_str(),
+                draft.category.name.as_str(),
+                draft.category_confirmed
+            )
+        );
+        assert_eq!(None, first_draft(&Model::new(true), &user).unwrap());
+    }
+}
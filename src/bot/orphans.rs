@@ -0,0 +1,129 @@
+//! Drafts live in memory, so a restart leaves the keyboards under their
+//! messages leading nowhere. The messages of open drafts are recorded, and
+//! on the next start marked expired, a batch at a time to stay clear of
+//! Telegram's flood limits.
+
+use super::markdown::escape_md;
+use super::Bot;
+use log::*;
+use std::future::Future;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use teloxide::RequestError;
+
+const EXPIRED: &str = "⌛ expired";
+
+/// Messages edited before pausing.
+const BATCH: usize = 50;
+const PAUSE: Duration = Duration::from_secs(3);
+
+/// How the messages of expired drafts fared.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Outcome {
+    edited: usize,
+    /// Deleted since, or in chats the bot can no longer edit in.
+    failed: usize,
+}
+
+/// The messages in the batches they are edited in.
+fn batches(messages: &[(i64, i32)]) -> std::slice::Chunks<'_, (i64, i32)> {
+    messages.chunks(BATCH)
+}
+
+/// Edits every message with `edit`, pausing between batches. Failures are
+/// counted and otherwise ignored, the message may well be gone.
+async fn expire_with<F, Fut>(messages: &[(i64, i32)], pause: Duration, mut edit: F) -> Outcome
+where
+    F: FnMut(ChatId, MessageId) -> Fut,
+    Fut: Future<Output = Result<(), RequestError>>,
+{
+    let mut outcome = Outcome::default();
+    for (i, batch) in batches(messages).enumerate() {
+        if i > 0 {
+            tokio::time::sleep(pause).await;
+        }
+        for &(chat_id, message_id) in batch {
+            match edit(ChatId(chat_id), MessageId(message_id)).await {
+                Ok(()) => outcome.edited += 1,
+                Err(e) => {
+                    debug!("Can't expire draft {} in {}: {}", message_id, chat_id, e);
+                    outcome.failed += 1;
+                }
+            }
+        }
+    }
+    outcome
+}
+
+/// Marks the messages of drafts left open by the last run as expired,
+/// removing their keyboards.
+pub async fn expire(bot: Bot, messages: Vec<(i64, i32)>) {
+    if messages.is_empty() {
+        return;
+    }
+    let outcome = expire_with(&messages, PAUSE, |chat_id, message_id| {
+        let request = bot.edit_message_text(chat_id, message_id, escape_md(EXPIRED));
+        async move { request.await.map(|_| ()) }
+    })
+    .await;
+    info!(
+        "Expired {} drafts left open, {} of their messages couldn't be edited",
+        messages.len(),
+        outcome.failed
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use teloxide::ApiError;
+
+    fn messages(count: i32) -> Vec<(i64, i32)> {
+        (1..=count).map(|id| (-100, id)).collect()
+    }
+
+    #[test]
+    fn batches_of_fifty() {
+        let sizes: Vec<usize> = batches(&messages(120)).map(|b| b.len()).collect();
+        assert_eq!(vec![50, 50, 20], sizes);
+        assert_eq!(1, batches(&messages(50)).count());
+        assert_eq!(0, batches(&[]).count());
+    }
+
+    #[tokio::test]
+    async fn failed_edits_are_counted() {
+        let edited = RefCell::new(Vec::new());
+        let outcome = expire_with(&messages(60), Duration::ZERO, |_, message_id| {
+            edited.borrow_mut().push(message_id.0);
+            // Every tenth message was deleted meanwhile.
+            let result = match message_id.0 % 10 {
+                0 => Err(RequestError::Api(ApiError::MessageToEditNotFound)),
+                _ => Ok(()),
+            };
+            std::future::ready(result)
+        })
+        .await;
+        assert_eq!(
+            Outcome {
+                edited: 54,
+                failed: 6
+            },
+            outcome
+        );
+        // Each once, in order, also after a failure.
+        assert_eq!((1..=60).collect::<Vec<_>>(), edited.into_inner());
+    }
+
+    #[tokio::test]
+    async fn batches_pause_in_between() {
+        let started = std::time::Instant::now();
+        let pause = Duration::from_millis(20);
+        let ok = |_, _| std::future::ready(Ok(()));
+        expire_with(&messages(50), pause, ok).await;
+        assert!(started.elapsed() < pause);
+        expire_with(&messages(101), pause, ok).await;
+        assert!(started.elapsed() >= pause * 2);
+    }
+}
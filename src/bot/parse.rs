@@ -0,0 +1,1537 @@
+//! Parsing of user typed values.
+
+use crate::model::{
+    check_comment, AmountError, AmountLimits, Cadence, ConfirmMode, DateRange, ExpenseSort, Locale,
+    ParsedAmount, QuietHours, ReportDate, Role, SourceId, GROUP_SPACES,
+};
+use chrono::{NaiveDate, Weekday};
+
+/// Whitespace between words, as opposed to spaces grouping digits.
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() && !GROUP_SPACES.contains(&c)
+}
+
+/// Splits an expense message like `50.75 groceries for the week` into the
+/// amount and an optional comment.
+pub fn parse_expense_message(
+    text: &str,
+    limits: &AmountLimits,
+    locale: Locale,
+) -> Result<(ParsedAmount, Option<String>), AmountError> {
+    let text = text.trim();
+    let (amount, comment) = match text.split_once(is_separator) {
+        Some((amount, comment)) => (amount, Some(comment.trim().to_string())),
+        None => (text, None),
+    };
+    let amount = limits.parse(amount, locale)?;
+    Ok((amount, comment.filter(|c| !c.is_empty())))
+}
+
+/// Arguments of `/add <amount> <category words> ["comment"] [acc:<account>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddArgs {
+    pub amount: ParsedAmount,
+    pub category: String,
+    pub comment: Option<String>,
+    pub account: Option<String>,
+}
+
+pub const ADD_USAGE: &str = "Usage: /add <amount> <category> [\"comment\"] [acc:<account>]";
+
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// Splits on whitespace, keeping double quoted parts together. The quotes
+/// themselves are dropped; `quoted` tells whether the token started with one.
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if is_separator(c) {
+            chars.next();
+            continue;
+        }
+        let quoted = c == '"';
+        let mut token = String::new();
+        let mut in_quotes = false;
+        while let Some(&c) = chars.peek() {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            } else if is_separator(c) && !in_quotes {
+                break;
+            } else {
+                token.push(c);
+            }
+            chars.next();
+        }
+        if in_quotes {
+            return Err("Unclosed quote.".to_string());
+        }
+        tokens.push(Token {
+            text: token,
+            quoted,
+        });
+    }
+    Ok(tokens)
+}
+
+pub fn parse_add(text: &str, limits: &AmountLimits, locale: Locale) -> Result<AddArgs, String> {
+    let mut tokens = tokenize(text)?.into_iter();
+    let first = tokens.next().ok_or_else(|| ADD_USAGE.to_string())?;
+    let amount = match limits.parse(&first.text, locale) {
+        Ok(amount) => amount,
+        // Most likely the arguments are in the wrong order.
+        Err(AmountError::NotANumber(_)) => return Err(ADD_USAGE.to_string()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut category = Vec::new();
+    let mut comment = None;
+    let mut account = None;
+    for token in tokens {
+        if token.quoted {
+            if comment.replace(token.text).is_some() {
+                return Err("Only one quoted comment is allowed.".to_string());
+            }
+        } else if let Some(name) = token.text.strip_prefix("acc:") {
+            if account.replace(name.to_string()).is_some() {
+                return Err("Only one account is allowed.".to_string());
+            }
+        } else {
+            category.push(token.text);
+        }
+    }
+
+    if category.is_empty() {
+        return Err(ADD_USAGE.to_string());
+    }
+    check_comment(comment.as_deref()).map_err(|e| e.to_string())?;
+    Ok(AddArgs {
+        amount,
+        category: category.join(" "),
+        comment: comment.filter(|c| !c.trim().is_empty()),
+        account: account.filter(|a| !a.trim().is_empty()),
+    })
+}
+
+/// Arguments of `/recategorize from <category> to <category> [range]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecategorizeArgs {
+    pub from: String,
+    pub to: String,
+    /// `None` for all time.
+    pub range: Option<DateRange>,
+}
+
+pub const RECATEGORIZE_USAGE: &str =
+    "Usage: /recategorize from <category> to <category> [YYYY-MM..YYYY-MM]";
+
+pub fn parse_recategorize(text: &str) -> Result<RecategorizeArgs, String> {
+    let text = text.trim();
+    let rest = text
+        .strip_prefix("from ")
+        .ok_or_else(|| RECATEGORIZE_USAGE.to_string())?;
+    let (rest, range) = match rest.rsplit_once(char::is_whitespace) {
+        Some((head, last)) if last.contains("..") => {
+            let range = DateRange::parse(last).ok_or_else(|| {
+                format!(
+                    "Invalid range '{}'. Use YYYY-MM..YYYY-MM or YYYY-MM-DD..YYYY-MM-DD.",
+                    last
+                )
+            })?;
+            (head, Some(range))
+        }
+        _ => (rest, None),
+    };
+    let (from, to) = rest
+        .split_once(" to ")
+        .ok_or_else(|| RECATEGORIZE_USAGE.to_string())?;
+    let (from, to) = (from.trim(), to.trim());
+    if from.is_empty() || to.is_empty() {
+        return Err(RECATEGORIZE_USAGE.to_string());
+    }
+    Ok(RecategorizeArgs {
+        from: from.to_string(),
+        to: to.to_string(),
+        range,
+    })
+}
+
+pub const CATEGORY_USAGE: &str = "Usage: /category merge [dry] \"<source>\" into \"<target>\", /category parent \"<category>\" under \"<parent>\"|none or /category require-comment <category> on|off, /category bind <category> <account>, /category unbind <category> or /category stats <category>";
+
+/// The texts of `tokens`, joined by spaces.
+fn join(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|t| t.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Arguments of `/category merge [dry] <source> into <target>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeArgs {
+    pub source: String,
+    pub target: String,
+    /// Only report what the merge would do.
+    pub dry_run: bool,
+}
+
+/// Names with spaces are either quoted or all words up to `into`. A source
+/// whose name starts with the word "dry" has to be quoted.
+pub fn parse_category_merge(text: &str) -> Result<MergeArgs, String> {
+    let usage = || CATEGORY_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let (first, rest) = tokens.split_first().ok_or_else(usage)?;
+    if first.quoted || first.text != "merge" {
+        return Err(usage());
+    }
+    let (dry_run, rest) = match rest.split_first() {
+        Some((dry, rest)) if !dry.quoted && dry.text == "dry" => (true, rest),
+        _ => (false, rest),
+    };
+    let into = rest
+        .iter()
+        .position(|t| !t.quoted && t.text == "into")
+        .ok_or_else(usage)?;
+    let (source, target) = (join(&rest[..into]), join(&rest[into + 1..]));
+    if source.trim().is_empty() || target.trim().is_empty() {
+        return Err(usage());
+    }
+    Ok(MergeArgs {
+        source,
+        target,
+        dry_run,
+    })
+}
+
+pub const ACCOUNT_USAGE: &str = "Usage: /account delete \"<account>\" [into \"<other account>\"], or /account limit \"<account>\" <amount> <currency>|none";
+
+/// Arguments of `/account delete <account> [into <other account>]`: the
+/// account, and the one to move what refers to it to first.
+pub fn parse_account_delete(text: &str) -> Result<(String, Option<String>), String> {
+    let usage = || ACCOUNT_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let (first, rest) = tokens.split_first().ok_or_else(usage)?;
+    if first.quoted || first.text != "delete" {
+        return Err(usage());
+    }
+    let (account, target) = match rest.iter().position(|t| !t.quoted && t.text == "into") {
+        Some(into) => (join(&rest[..into]), Some(join(&rest[into + 1..]))),
+        None => (join(rest), None),
+    };
+    if account.trim().is_empty() || target.as_ref().is_some_and(|t| t.trim().is_empty()) {
+        return Err(usage());
+    }
+    Ok((account, target))
+}
+
+/// Arguments of `/account limit <account> <amount> <currency>`: the account,
+/// and the amount as typed with its currency, `None` for `none` removing the
+/// limit.
+pub fn parse_account_limit(text: &str) -> Result<(String, Option<(String, String)>), String> {
+    let usage = || ACCOUNT_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let (first, rest) = tokens.split_first().ok_or_else(usage)?;
+    if first.quoted || first.text != "limit" {
+        return Err(usage());
+    }
+    match rest {
+        [account @ .., none] if !account.is_empty() && !none.quoted && none.text == "none" => {
+            Ok((join(account), None))
+        }
+        [account @ .., amount, currency]
+            if !account.is_empty() && !amount.quoted && !currency.quoted =>
+        {
+            let limit = (amount.text.clone(), currency.text.to_uppercase());
+            Ok((join(account), Some(limit)))
+        }
+        _ => Err(usage()),
+    }
+}
+
+/// Arguments of `/category parent <category> under <parent>`, or `none`
+/// instead of `under <parent>` to make it a top-level category again.
+pub fn parse_category_parent(text: &str) -> Result<(String, Option<String>), String> {
+    let usage = || CATEGORY_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let (first, rest) = tokens.split_first().ok_or_else(usage)?;
+    if first.quoted || first.text != "parent" {
+        return Err(usage());
+    }
+    let (category, parent) = match rest.iter().position(|t| !t.quoted && t.text == "under") {
+        Some(under) => (join(&rest[..under]), Some(join(&rest[under + 1..]))),
+        None => match rest.split_last() {
+            Some((none, category)) if !none.quoted && none.text == "none" => (join(category), None),
+            _ => return Err(usage()),
+        },
+    };
+    if category.trim().is_empty() || parent.as_ref().is_some_and(|p| p.trim().is_empty()) {
+        return Err(usage());
+    }
+    Ok((category, parent))
+}
+
+/// Arguments of `/category bind <category> <account>`, or of
+/// `/category unbind <category>` with no account. Names with spaces are
+/// quoted, or bound with `to` between them.
+pub fn parse_category_bind(text: &str) -> Result<(String, Option<String>), String> {
+    let usage = || CATEGORY_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let (first, rest) = tokens.split_first().ok_or_else(usage)?;
+    let (category, account) = match first.text.as_str() {
+        _ if first.quoted => return Err(usage()),
+        "unbind" => (join(rest), None),
+        "bind" => match rest.iter().position(|t| !t.quoted && t.text == "to") {
+            Some(to) => (join(&rest[..to]), Some(join(&rest[to + 1..]))),
+            None => match rest {
+                [category, account] => (category.text.clone(), Some(account.text.clone())),
+                _ => return Err(usage()),
+            },
+        },
+        _ => return Err(usage()),
+    };
+    if category.trim().is_empty() || account.as_ref().is_some_and(|a| a.trim().is_empty()) {
+        return Err(usage());
+    }
+    Ok((category, account))
+}
+
+/// Arguments of `/category require-comment <category> on|off`, as the
+/// category name and whether comments are required.
+pub fn parse_require_comment(text: &str) -> Result<(String, bool), String> {
+    let usage = || CATEGORY_USAGE.to_string();
+    let rest = text
+        .trim()
+        .strip_prefix("require-comment")
+        .ok_or_else(usage)?;
+    let (name, switch) = rest
+        .trim()
+        .rsplit_once(char::is_whitespace)
+        .ok_or_else(usage)?;
+    let required = match switch {
+        "on" => true,
+        "off" => false,
+        _ => return Err(usage()),
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(usage());
+    }
+    Ok((name.to_string(), required))
+}
+
+/// The category of `/category stats <category>`, quoted or not.
+pub fn parse_category_stats(text: &str) -> Result<String, String> {
+    let usage = || CATEGORY_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    match tokens.split_first() {
+        Some((first, rest)) if !first.quoted && first.text == "stats" && !rest.is_empty() => {
+            Ok(join(rest))
+        }
+        _ => Err(usage()),
+    }
+}
+
+pub const RECONCILE_USAGE: &str = "Usage: /reconcile \"<account>\" <balance> or /reconcile history";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReconcileArgs {
+    /// The account, quoted or not, and the balance the bank shows as typed.
+    Check {
+        account: String,
+        balance: String,
+    },
+    History,
+}
+
+pub fn parse_reconcile(text: &str) -> Result<ReconcileArgs, String> {
+    let usage = || RECONCILE_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    match tokens.as_slice() {
+        [history] if !history.quoted && history.text == "history" => Ok(ReconcileArgs::History),
+        [account @ .., balance] if !account.is_empty() && !balance.quoted => {
+            Ok(ReconcileArgs::Check {
+                account: join(account),
+                balance: balance.text.clone(),
+            })
+        }
+        _ => Err(usage()),
+    }
+}
+
+pub const NOTIFY_USAGE: &str =
+    "Usage: /notify on|off, /notify over <amount> <currency> or /notify category <category>";
+
+/// Arguments of `/notify`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifyArgs {
+    Show,
+    On,
+    Off,
+    /// The amount as typed, read once its currency is known.
+    Over {
+        amount: String,
+        currency: String,
+    },
+    Category(String),
+}
+
+pub fn parse_notify(text: &str) -> Result<NotifyArgs, String> {
+    let usage = || NOTIFY_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let Some((first, rest)) = tokens.split_first() else {
+        return Ok(NotifyArgs::Show);
+    };
+    match (first.text.as_str(), rest) {
+        ("on", []) => Ok(NotifyArgs::On),
+        ("off", []) => Ok(NotifyArgs::Off),
+        ("over", [amount, currency]) => Ok(NotifyArgs::Over {
+            amount: amount.text.clone(),
+            currency: currency.text.to_uppercase(),
+        }),
+        ("category", [_, ..]) => Ok(NotifyArgs::Category(join(rest))),
+        _ => Err(usage()),
+    }
+}
+
+pub const SUBSCRIBE_USAGE: &str = "Usage: /subscribe weekly|monthly|off|status";
+
+/// Arguments of `/subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscribeArgs {
+    Status,
+    Subscribe(Cadence),
+    Off,
+}
+
+pub fn parse_subscribe(text: &str) -> Result<SubscribeArgs, String> {
+    match text.trim().to_lowercase().as_str() {
+        "" | "status" => Ok(SubscribeArgs::Status),
+        "off" => Ok(SubscribeArgs::Off),
+        other => Cadence::parse(other)
+            .map(SubscribeArgs::Subscribe)
+            .ok_or_else(|| SUBSCRIBE_USAGE.to_string()),
+    }
+}
+
+pub const BUDGET_USAGE: &str =
+    "Usage: /budget, or /budget <category> <amount>|none <currency> to set or remove one";
+
+/// Arguments of `/budget`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetArgs {
+    Show,
+    /// The amount as typed, `None` removing the budget.
+    Set {
+        category: String,
+        amount: Option<String>,
+        currency: String,
+    },
+}
+
+pub fn parse_budget(text: &str) -> Result<BudgetArgs, String> {
+    let tokens = tokenize(text)?;
+    match tokens.as_slice() {
+        [] => Ok(BudgetArgs::Show),
+        [category @ .., amount, currency]
+            if !category.is_empty() && !amount.quoted && !currency.quoted =>
+        {
+            Ok(BudgetArgs::Set {
+                category: join(category),
+                amount: (amount.text != "none").then(|| amount.text.clone()),
+                currency: currency.text.to_uppercase(),
+            })
+        }
+        _ => Err(BUDGET_USAGE.to_string()),
+    }
+}
+
+pub const REPORT_USAGE: &str =
+    "Usage: /report [by logged] [period] [exclude <category>,<category>|none]";
+
+/// Categories typed as a comma separated list, `none` for none. Names are
+/// kept as typed, for the caller to resolve.
+fn category_list(text: &str) -> Option<Vec<String>> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("none") {
+        return Some(Vec::new());
+    }
+    let names: Vec<String> = text
+        .split(',')
+        .map(|name| name.trim().trim_matches('"').trim().to_string())
+        .collect();
+    names.iter().all(|name| !name.is_empty()).then_some(names)
+}
+
+/// Arguments of `/report [by logged] [period] [exclude <categories>]`: the
+/// period as typed, the categories to leave out instead of the user's
+/// default, empty for `none`, and which time of expenses counts.
+pub fn parse_report(text: &str) -> Result<(String, Option<Vec<String>>, ReportDate), String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (period, exclude) = match words.iter().position(|w| w.eq_ignore_ascii_case("exclude")) {
+        None => (&words[..], None),
+        Some(at) => {
+            let names = category_list(&words[at + 1..].join(" "));
+            let names = names.ok_or_else(|| REPORT_USAGE.to_string())?;
+            (&words[..at], Some(names))
+        }
+    };
+    let mut period = period.to_vec();
+    let by_logged = period
+        .windows(2)
+        .position(|w| w[0].eq_ignore_ascii_case("by") && w[1].eq_ignore_ascii_case("logged"));
+    let date = match by_logged {
+        Some(at) => {
+            period.drain(at..at + 2);
+            ReportDate::Logged
+        }
+        None => ReportDate::Spent,
+    };
+    Ok((period.join(" "), exclude, date))
+}
+
+pub const SETTINGS_USAGE: &str = "Usage: /settings, /settings mtd on|off, /settings round on|off, /settings report-exclude <category>,<category>|none, /settings week mon|sun, /settings month-start <day>, /settings confirm full|compact|silent, /settings currency <currency>|none, /settings quiet <from>-<to>|off, /settings timezone <zone>|none, or in a group /settings privacy on|off";
+
+/// Arguments of `/settings`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsArgs {
+    Show,
+    /// Whether the chat masks amounts.
+    Privacy(bool),
+    /// How much commits are confirmed with in the chat.
+    Confirm(ConfirmMode),
+    /// The currency bare amounts in the chat are in, uppercased and not
+    /// checked yet; `None` for none.
+    Currency(Option<String>),
+    /// The quiet hours of the chat, equal hours for none.
+    Quiet(QuietHours),
+    /// The time zone of the chat, not checked yet; `None` for the bot's.
+    TimeZone(Option<String>),
+    /// Whether the user's commits are confirmed with the month to date.
+    MonthToDate(bool),
+    /// Whether the user's reports round their amounts.
+    Round(bool),
+    /// The categories the user's reports leave out, none for none.
+    ReportExclude(Vec<String>),
+    /// The day the household's weeks start on.
+    WeekStart(Weekday),
+    /// The day of the month the household's months start on, checked by
+    /// the model.
+    MonthStart(u32),
+}
+
+pub fn parse_settings(text: &str) -> Result<SettingsArgs, String> {
+    if let Some(rest) = text.trim().strip_prefix("report-exclude") {
+        return match category_list(rest) {
+            Some(names) => Ok(SettingsArgs::ReportExclude(names)),
+            None => Err(SETTINGS_USAGE.to_string()),
+        };
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Ok(SettingsArgs::Show),
+        ["privacy", "on"] => Ok(SettingsArgs::Privacy(true)),
+        ["privacy", "off"] => Ok(SettingsArgs::Privacy(false)),
+        ["confirm", mode] => ConfirmMode::parse(mode)
+            .map(SettingsArgs::Confirm)
+            .ok_or_else(|| SETTINGS_USAGE.to_string()),
+        ["currency", "none"] => Ok(SettingsArgs::Currency(None)),
+        ["currency", code] if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) => {
+            Ok(SettingsArgs::Currency(Some(code.to_ascii_uppercase())))
+        }
+        ["quiet", "off"] => Ok(SettingsArgs::Quiet(QuietHours { start: 0, end: 0 })),
+        ["quiet", hours] => quiet_hours(hours)
+            .map(SettingsArgs::Quiet)
+            .ok_or_else(|| SETTINGS_USAGE.to_string()),
+        ["timezone", "none"] => Ok(SettingsArgs::TimeZone(None)),
+        ["timezone", zone] => Ok(SettingsArgs::TimeZone(Some(zone.to_string()))),
+        ["mtd", "on"] => Ok(SettingsArgs::MonthToDate(true)),
+        ["mtd", "off"] => Ok(SettingsArgs::MonthToDate(false)),
+        ["round", "on"] => Ok(SettingsArgs::Round(true)),
+        ["round", "off"] => Ok(SettingsArgs::Round(false)),
+        ["week", "mon"] => Ok(SettingsArgs::WeekStart(Weekday::Mon)),
+        ["week", "sun"] => Ok(SettingsArgs::WeekStart(Weekday::Sun)),
+        ["month-start", day] => day
+            .parse()
+            .map(SettingsArgs::MonthStart)
+            .map_err(|_| SETTINGS_USAGE.to_string()),
+        _ => Err(SETTINGS_USAGE.to_string()),
+    }
+}
+
+/// Quiet hours as in `22-8` or `22:00-08:00`, whole hours of the day.
+fn quiet_hours(text: &str) -> Option<QuietHours> {
+    let hour = |text: &str| {
+        let hour: u32 = text.strip_suffix(":00").unwrap_or(text).parse().ok()?;
+        (hour < 24).then_some(hour)
+    };
+    let (start, end) = text.split_once('-')?;
+    Some(QuietHours {
+        start: hour(start)?,
+        end: hour(end)?,
+    })
+}
+
+pub const ALLOW_USAGE: &str = "Usage: /allow <user> [admin|member|viewer]";
+
+/// Arguments of `/allow <user> [role]`, members by default.
+pub fn parse_allow(text: &str) -> Result<(String, Role), String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (user, role) = match words.as_slice() {
+        [user] => (user, Some(Role::Member)),
+        [user, role] => (user, Role::parse(role)),
+        _ => return Err(ALLOW_USAGE.to_string()),
+    };
+    role.map(|role| (user.to_string(), role))
+        .ok_or_else(|| ALLOW_USAGE.to_string())
+}
+
+pub const ARCHIVE_USAGE: &str = "Usage: /archive before YYYY-MM-DD";
+
+/// The cutoff of `/archive before <date>`.
+pub fn parse_archive(text: &str) -> Result<NaiveDate, String> {
+    text.trim()
+        .strip_prefix("before")
+        .and_then(|date| NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok())
+        .ok_or_else(|| ARCHIVE_USAGE.to_string())
+}
+
+pub const RULE_USAGE: &str =
+    "Usage: /rule add \"<pattern>\" -> <category>, /rule del <id>, /rule test <text> or /rule list";
+
+/// Arguments of `/rule add "<pattern>" -> <category>`, as the pattern and
+/// the category name.
+pub fn parse_rule_add(text: &str) -> Result<(String, String), String> {
+    let usage = || RULE_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    match tokens.as_slice() {
+        [add, pattern, arrow, category @ ..]
+            if !add.quoted
+                && add.text == "add"
+                && pattern.quoted
+                && !pattern.text.is_empty()
+                && arrow.text == "->"
+                && !category.is_empty() =>
+        {
+            let category: Vec<&str> = category.iter().map(|t| t.text.as_str()).collect();
+            Ok((pattern.text.clone(), category.join(" ")))
+        }
+        _ => Err(usage()),
+    }
+}
+
+pub const SOURCE_USAGE: &str = "Usage: /source add [chat:<id>|user:<id>] <category> -> <account> in reply to a forward from the source unless it is named, /source template <id> \"<pattern>\"|none, /source del <id> or /source list";
+
+/// Arguments of `/source`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceArgs {
+    List,
+    /// The source if named, else the origin of the forward replied to.
+    Add {
+        source: Option<SourceId>,
+        category: String,
+        account: String,
+    },
+    /// The pattern reading the amount, `None` removing it.
+    Template {
+        id: i64,
+        pattern: Option<String>,
+    },
+    Del(i64),
+}
+
+pub fn parse_source(text: &str) -> Result<SourceArgs, String> {
+    let usage = || SOURCE_USAGE.to_string();
+    let id = |token: &Token| token.text.trim_start_matches('#').parse::<i64>().ok();
+    let tokens = tokenize(text)?;
+    let Some((action, rest)) = tokens.split_first() else {
+        return Ok(SourceArgs::List);
+    };
+    if action.quoted {
+        return Err(usage());
+    }
+    match (action.text.as_str(), rest) {
+        ("list", []) => Ok(SourceArgs::List),
+        ("add", rest) => {
+            let source = rest
+                .first()
+                .filter(|t| !t.quoted)
+                .and_then(|t| SourceId::parse(&t.text));
+            let rest = if source.is_some() { &rest[1..] } else { rest };
+            let arrow = rest
+                .iter()
+                .position(|t| !t.quoted && t.text == "->")
+                .ok_or_else(usage)?;
+            let (category, account) = (join(&rest[..arrow]), join(&rest[arrow + 1..]));
+            if category.trim().is_empty() || account.trim().is_empty() {
+                return Err(usage());
+            }
+            Ok(SourceArgs::Add {
+                source,
+                category,
+                account,
+            })
+        }
+        ("template", [source, pattern]) => {
+            let id = id(source).ok_or_else(usage)?;
+            let pattern = match pattern {
+                none if !none.quoted && none.text == "none" => None,
+                pattern if pattern.quoted && !pattern.text.is_empty() => Some(pattern.text.clone()),
+                _ => return Err(usage()),
+            };
+            Ok(SourceArgs::Template { id, pattern })
+        }
+        ("del", [source]) => id(source).map(SourceArgs::Del).ok_or_else(usage),
+        _ => Err(usage()),
+    }
+}
+
+pub const TEMPLATE_USAGE: &str = "Usage: /template comment add <name> \"<comment with {placeholders}>\", /template comment del <name> or /template comment list";
+
+/// Arguments of `/template comment`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateArgs {
+    List,
+    Add { name: String, text: String },
+    Del(String),
+}
+
+pub fn parse_template(text: &str) -> Result<TemplateArgs, String> {
+    let usage = || TEMPLATE_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    let plain = |token: &Token, word: &str| !token.quoted && token.text == word;
+    match tokens.as_slice() {
+        [] => Ok(TemplateArgs::List),
+        [comment] if plain(comment, "comment") => Ok(TemplateArgs::List),
+        [comment, list] if plain(comment, "comment") && plain(list, "list") => {
+            Ok(TemplateArgs::List)
+        }
+        [comment, add, name, text]
+            if plain(comment, "comment")
+                && plain(add, "add")
+                && !name.text.trim().is_empty()
+                && text.quoted
+                && !text.text.trim().is_empty() =>
+        {
+            Ok(TemplateArgs::Add {
+                name: name.text.trim().to_lowercase(),
+                text: text.text.trim().to_string(),
+            })
+        }
+        [comment, del, name] if plain(comment, "comment") && plain(del, "del") => {
+            Ok(TemplateArgs::Del(name.text.trim().to_lowercase()))
+        }
+        _ => Err(usage()),
+    }
+}
+
+pub const LAST_USAGE: &str = "Usage: /last [<count>] [category <category>] [account \"<account>\"] [sort date|amount] [mine|all]";
+
+/// Expenses `/last` lists at most.
+pub const MAX_LAST: usize = 50;
+
+/// Whose expenses `/last` lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LastScope {
+    Mine,
+    All,
+}
+
+/// Arguments of `/last`, `None` for what the chat decides.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LastArgs {
+    pub count: Option<usize>,
+    pub category: Option<String>,
+    pub account: Option<String>,
+    pub sort: Option<ExpenseSort>,
+    pub scope: Option<LastScope>,
+}
+
+/// Arguments of `/last`, in any order. A modifier given twice, even with
+/// the same value, is refused rather than one of them picked.
+pub fn parse_last(text: &str) -> Result<LastArgs, String> {
+    let usage = || LAST_USAGE.to_string();
+    fn set<T>(slot: &mut Option<T>, value: T) -> Result<(), String> {
+        match slot.replace(value) {
+            Some(_) => Err(LAST_USAGE.to_string()),
+            None => Ok(()),
+        }
+    }
+    let tokens = tokenize(text)?;
+    let mut tokens = tokens.iter();
+    let mut args = LastArgs::default();
+    while let Some(token) = tokens.next() {
+        let word = match token.quoted {
+            true => return Err(usage()),
+            false => token.text.to_lowercase(),
+        };
+        let mut value = || {
+            tokens
+                .next()
+                .map(|t| t.text.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .ok_or_else(usage)
+        };
+        match word.as_str() {
+            "category" => set(&mut args.category, value()?)?,
+            "account" => set(&mut args.account, value()?)?,
+            "sort" => {
+                let sort = ExpenseSort::parse(&value()?).ok_or_else(usage)?;
+                set(&mut args.sort, sort)?
+            }
+            "mine" => set(&mut args.scope, LastScope::Mine)?,
+            "all" => set(&mut args.scope, LastScope::All)?,
+            count => match count.parse() {
+                Ok(count @ 1..=MAX_LAST) => set(&mut args.count, count)?,
+                _ => return Err(usage()),
+            },
+        }
+    }
+    Ok(args)
+}
+
+pub const ALIAS_USAGE: &str =
+    "Usage: /alias add <name> \"<command> [arguments]\", /alias del <name> or /alias list";
+
+/// Arguments of `/alias add <name> <command> [arguments]`, as the name and
+/// the command it stands for without its slash. The command may be quoted.
+pub fn parse_alias_add(text: &str) -> Result<(String, String), String> {
+    let usage = || ALIAS_USAGE.to_string();
+    let tokens = tokenize(text)?;
+    match tokens.as_slice() {
+        [add, name, expansion @ ..]
+            if !add.quoted && add.text == "add" && !expansion.is_empty() =>
+        {
+            let expansion = expansion
+                .iter()
+                .map(|t| t.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let expansion = expansion.trim().trim_start_matches('/').trim();
+            if expansion.is_empty() {
+                return Err(usage());
+            }
+            Ok((name.text.to_lowercase(), expansion.to_string()))
+        }
+        _ => Err(usage()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(text: &str) -> Result<AddArgs, String> {
+        parse_add(text, &AmountLimits::default(), Locale::DecimalPoint)
+    }
+
+    fn plain(amount: f64) -> ParsedAmount {
+        ParsedAmount {
+            amount,
+            alternative: None,
+            calculation: None,
+        }
+    }
+
+    #[test]
+    fn expense_message() {
+        let limits = AmountLimits::default();
+        let message = |text| parse_expense_message(text, &limits, Locale::DecimalPoint);
+        assert_eq!(Ok((plain(50.75), None)), message("50.75"));
+        assert_eq!(
+            Ok((plain(50.75), Some("groceries for the week".to_string()))),
+            message(" 50.75  groceries for the week ")
+        );
+        assert_eq!(
+            Err(AmountError::NotANumber("groceries".to_string())),
+            message("groceries 50")
+        );
+        assert_eq!(Err(AmountError::NotFinite), message("NaN pizza"));
+        assert_eq!(Err(AmountError::Negative), message("-5 refund"));
+
+        let (amount, comment) = message("12.40+3.60 lunch for two").unwrap();
+        assert_eq!(16.0, amount.amount);
+        assert_eq!("12.40+3.60", amount.calculation.unwrap().text);
+        assert_eq!(Some("lunch for two".to_string()), comment);
+        assert_eq!(Err(AmountError::DivisionByZero), message("5/0 pizza"));
+    }
+
+    #[test]
+    fn expense_message_with_grouped_digits() {
+        let limits = AmountLimits::default();
+        assert_eq!(
+            Ok((plain(1234.56), Some("rent".to_string()))),
+            parse_expense_message("1\u{a0}234,56 rent", &limits, Locale::DecimalComma)
+        );
+        assert_eq!(
+            Ok((plain(1234.56), None)),
+            parse_expense_message("1\u{202f}234.56", &limits, Locale::DecimalPoint)
+        );
+        assert_eq!(
+            Ok((
+                ParsedAmount {
+                    amount: 1234.0,
+                    alternative: Some(1.234),
+                    calculation: None,
+                },
+                Some("bike".to_string())
+            )),
+            parse_expense_message("1,234 bike", &limits, Locale::DecimalPoint)
+        );
+        // A plain space ends the amount.
+        assert_eq!(
+            Ok((plain(1.0), Some("234,56".to_string()))),
+            parse_expense_message("1 234,56", &limits, Locale::DecimalComma)
+        );
+    }
+
+    #[test]
+    fn add_arguments() {
+        assert_eq!(
+            Ok(AddArgs {
+                amount: plain(23.4),
+                category: "groceries".to_string(),
+                comment: Some("weekly shop".to_string()),
+                account: Some("family".to_string()),
+            }),
+            add(r#"23.40 groceries "weekly shop" acc:family"#)
+        );
+        assert_eq!(
+            Ok(AddArgs {
+                amount: plain(5.0),
+                category: "eating out".to_string(),
+                comment: None,
+                account: Some("Family BYN".to_string()),
+            }),
+            add(r#"5 acc:"Family BYN" eating out"#)
+        );
+        assert_eq!(
+            Ok(plain(1234.5)),
+            parse_add(
+                "1\u{a0}234,50 rent",
+                &AmountLimits::default(),
+                Locale::DecimalComma
+            )
+            .map(|args| args.amount)
+        );
+    }
+
+    #[test]
+    fn add_errors() {
+        assert_eq!(Err(ADD_USAGE.to_string()), add(""));
+        assert_eq!(Err(ADD_USAGE.to_string()), add("groceries 5"));
+        assert_eq!(Err(ADD_USAGE.to_string()), add(r#"5 "only comment""#));
+        assert!(add(r#"5 food "unclosed"#).is_err());
+        assert!(add(r#"5 food "a" "b""#).is_err());
+        assert!(add("5 food acc:a acc:b").is_err());
+        assert_eq!(Err("The amount can't be zero.".to_string()), add("0 food"));
+        assert_eq!(
+            Err("The amount can have at most 2 decimal places.".to_string()),
+            add("1.999 food")
+        );
+        assert!(add(&format!("5 food \"{}\"", "x".repeat(1000))).is_ok());
+        assert_eq!(
+            Err("Comment too long (1001/1000 chars).".to_string()),
+            add(&format!("5 food \"{}\"", "x".repeat(1001)))
+        );
+    }
+
+    #[test]
+    fn recategorize_arguments() {
+        assert_eq!(
+            Ok(RecategorizeArgs {
+                from: "Entertainment".to_string(),
+                to: "Eating out".to_string(),
+                range: DateRange::parse("2023-01..2023-12"),
+            }),
+            parse_recategorize("from Entertainment to Eating out 2023-01..2023-12")
+        );
+        assert_eq!(
+            Ok(RecategorizeArgs {
+                from: "Entertainment".to_string(),
+                to: "Leisure".to_string(),
+                range: None,
+            }),
+            parse_recategorize(" from Entertainment  to Leisure ")
+        );
+        assert_eq!(
+            Err(RECATEGORIZE_USAGE.to_string()),
+            parse_recategorize("Entertainment Leisure")
+        );
+        assert_eq!(
+            Err(RECATEGORIZE_USAGE.to_string()),
+            parse_recategorize("from Entertainment")
+        );
+        assert!(parse_recategorize("from a to b 2023-12..2023-01")
+            .unwrap_err()
+            .starts_with("Invalid range"));
+    }
+
+    #[test]
+    fn account_delete_arguments() {
+        assert_eq!(
+            Ok(("Typo".to_string(), None)),
+            parse_account_delete("delete Typo")
+        );
+        assert_eq!(
+            Ok(("Old wallet".to_string(), Some("Alex Savings".to_string()))),
+            parse_account_delete(r#"delete "Old wallet" into "Alex Savings""#)
+        );
+        assert_eq!(
+            Ok(("Old wallet".to_string(), Some("Cash".to_string()))),
+            parse_account_delete("delete Old wallet into Cash")
+        );
+        let usage = Err(ACCOUNT_USAGE.to_string());
+        assert_eq!(usage, parse_account_delete(""));
+        assert_eq!(usage, parse_account_delete("delete"));
+        assert_eq!(usage, parse_account_delete("delete Typo into"));
+        assert_eq!(usage, parse_account_delete("rename Typo"));
+    }
+
+    #[test]
+    fn account_limit_arguments() {
+        let limit = |amount: &str, currency: &str| Some((amount.to_string(), currency.to_string()));
+        assert_eq!(
+            Ok(("Kid pocket".to_string(), limit("20", "EUR"))),
+            parse_account_limit(r#"limit "Kid pocket" 20 eur"#)
+        );
+        assert_eq!(
+            Ok(("Kid pocket".to_string(), limit("12,50", "EUR"))),
+            parse_account_limit("limit Kid pocket 12,50 EUR")
+        );
+        assert_eq!(
+            Ok(("Kid pocket".to_string(), None)),
+            parse_account_limit("limit Kid pocket none")
+        );
+        let usage = Err(ACCOUNT_USAGE.to_string());
+        assert_eq!(usage, parse_account_limit("limit"));
+        assert_eq!(usage, parse_account_limit("limit none"));
+        assert_eq!(usage, parse_account_limit("limit 20 EUR"));
+        assert_eq!(usage, parse_account_limit(r#"limit Cash "20" EUR"#));
+        assert_eq!(usage, parse_account_limit("delete Cash"));
+    }
+
+    #[test]
+    fn category_merge_arguments() {
+        let names = |s: &str, t: &str| {
+            Ok(MergeArgs {
+                source: s.to_string(),
+                target: t.to_string(),
+                dry_run: false,
+            })
+        };
+        assert_eq!(
+            names("Eating out", "Restaurants"),
+            parse_category_merge(r#"merge "Eating out" into "Restaurants""#)
+        );
+        assert_eq!(
+            names("Eating out", "Restaurants"),
+            parse_category_merge("merge Eating out into Restaurants")
+        );
+        assert_eq!(
+            names("Into the wild", "Travel"),
+            parse_category_merge(r#"merge "Into the wild" into Travel"#)
+        );
+        assert_eq!(
+            Ok(MergeArgs {
+                dry_run: true,
+                ..names("Eating out", "Restaurants").unwrap()
+            }),
+            parse_category_merge("merge dry Eating out into Restaurants")
+        );
+        assert_eq!(
+            names("dry", "Restaurants"),
+            parse_category_merge(r#"merge "dry" into Restaurants"#)
+        );
+        assert_eq!(
+            Err(CATEGORY_USAGE.to_string()),
+            parse_category_merge("merge a")
+        );
+        assert_eq!(
+            Err(CATEGORY_USAGE.to_string()),
+            parse_category_merge("rename a into b")
+        );
+        assert_eq!(
+            Err(CATEGORY_USAGE.to_string()),
+            parse_category_merge("merge into b")
+        );
+    }
+
+    #[test]
+    fn category_parent_arguments() {
+        assert_eq!(
+            Ok(("Eating out".to_string(), Some("Food".to_string()))),
+            parse_category_parent("parent Eating out under Food")
+        );
+        assert_eq!(
+            Ok(("Under the sea".to_string(), Some("Travel".to_string()))),
+            parse_category_parent(r#"parent "Under the sea" under "Travel""#)
+        );
+        assert_eq!(
+            Ok(("Eating out".to_string(), None)),
+            parse_category_parent("parent Eating out none")
+        );
+        for invalid in [
+            "parent",
+            "parent a",
+            "parent under b",
+            "parent a under",
+            "parent none",
+        ] {
+            assert_eq!(
+                Err(CATEGORY_USAGE.to_string()),
+                parse_category_parent(invalid),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn alias_arguments() {
+        let alias = |n: &str, e: &str| Ok((n.to_string(), e.to_string()));
+        assert_eq!(
+            alias("rl", "report lastmonth"),
+            parse_alias_add(r#"add rl "report lastmonth""#)
+        );
+        assert_eq!(
+            alias("rl", "report lastmonth"),
+            parse_alias_add("add RL /report lastmonth")
+        );
+        let usage = Err(ALIAS_USAGE.to_string());
+        assert_eq!(usage, parse_alias_add("add rl"));
+        assert_eq!(usage, parse_alias_add(r#"add rl "/""#));
+        assert_eq!(usage, parse_alias_add("new rl report"));
+    }
+
+    #[test]
+    fn rule_arguments() {
+        assert_eq!(
+            Ok(("uber|bolt|taxi".to_string(), "Transportation".to_string())),
+            parse_rule_add(r#"add "uber|bolt|taxi" -> Transportation"#)
+        );
+        assert_eq!(
+            Ok((r"\bgas station\b".to_string(), "Car fuel".to_string())),
+            parse_rule_add(r#"add "\bgas station\b" -> Car fuel"#)
+        );
+        let usage = Err(RULE_USAGE.to_string());
+        assert_eq!(usage, parse_rule_add("add uber -> Transportation"));
+        assert_eq!(usage, parse_rule_add(r#"add "uber" Transportation"#));
+        assert_eq!(usage, parse_rule_add(r#"add "uber" ->"#));
+        assert_eq!(usage, parse_rule_add(r#"add "" -> Transportation"#));
+    }
+
+    #[test]
+    fn template_arguments() {
+        assert_eq!(
+            Ok(TemplateArgs::Add {
+                name: "fuel".to_string(),
+                text: "fuel {liters}L at {station}".to_string(),
+            }),
+            parse_template(r#"comment add Fuel "fuel {liters}L at {station}""#)
+        );
+        assert_eq!(
+            Ok(TemplateArgs::Del("fuel".to_string())),
+            parse_template("comment del fuel")
+        );
+        assert_eq!(Ok(TemplateArgs::List), parse_template(""));
+        assert_eq!(Ok(TemplateArgs::List), parse_template("comment list"));
+        let usage = Err(TEMPLATE_USAGE.to_string());
+        assert_eq!(usage, parse_template("comment add fuel fuel {liters}L"));
+        assert_eq!(usage, parse_template(r#"comment add fuel """#));
+        assert_eq!(usage, parse_template(r#"add fuel "fuel {liters}L""#));
+        assert_eq!(usage, parse_template("comment del"));
+    }
+
+    #[test]
+    fn last_arguments() {
+        assert_eq!(Ok(LastArgs::default()), parse_last(""));
+        assert_eq!(
+            Ok(LastArgs {
+                count: Some(20),
+                category: Some("Groceries".to_string()),
+                ..LastArgs::default()
+            }),
+            parse_last("20 category Groceries")
+        );
+        assert_eq!(
+            Ok(LastArgs {
+                account: Some("Family BYN".to_string()),
+                sort: Some(ExpenseSort::Amount),
+                scope: Some(LastScope::All),
+                ..LastArgs::default()
+            }),
+            parse_last(r#"ALL sort Amount account "Family BYN""#)
+        );
+        assert_eq!(
+            Ok(LastArgs {
+                scope: Some(LastScope::Mine),
+                sort: Some(ExpenseSort::Date),
+                ..LastArgs::default()
+            }),
+            parse_last("mine sort date")
+        );
+        let usage = Err(LAST_USAGE.to_string());
+        // Conflicting, repeated and unknown modifiers.
+        assert_eq!(usage, parse_last("mine all"));
+        assert_eq!(usage, parse_last("5 10"));
+        assert_eq!(usage, parse_last("category Groceries category Utilities"));
+        assert_eq!(usage, parse_last("sort comment"));
+        assert_eq!(usage, parse_last("sort"));
+        assert_eq!(usage, parse_last("category"));
+        assert_eq!(usage, parse_last("yesterday"));
+        assert_eq!(usage, parse_last(r#""mine""#));
+        assert_eq!(usage, parse_last("0"));
+        assert_eq!(usage, parse_last("51"));
+    }
+
+    #[test]
+    fn category_bind_arguments() {
+        assert_eq!(
+            Ok(("Utilities".to_string(), Some("Family Fund".to_string()))),
+            parse_category_bind(r#"bind Utilities "Family Fund""#)
+        );
+        assert_eq!(
+            Ok(("Eating out".to_string(), Some("Family Fund".to_string()))),
+            parse_category_bind("bind Eating out to Family Fund")
+        );
+        assert_eq!(
+            Ok(("Eating out".to_string(), None)),
+            parse_category_bind("unbind Eating out")
+        );
+        for invalid in [
+            "bind",
+            "bind Utilities",
+            "bind Eating out Family Fund",
+            "bind Utilities to",
+            "unbind",
+            r#""bind" a b"#,
+        ] {
+            assert_eq!(
+                Err(CATEGORY_USAGE.to_string()),
+                parse_category_bind(invalid),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn require_comment_arguments() {
+        assert_eq!(
+            Ok(("Other".to_string(), true)),
+            parse_require_comment("require-comment Other on")
+        );
+        assert_eq!(
+            Ok(("Eating out".to_string(), false)),
+            parse_require_comment(" require-comment  Eating out off ")
+        );
+        let usage = Err(CATEGORY_USAGE.to_string());
+        assert_eq!(usage, parse_require_comment("require-comment Other"));
+        assert_eq!(usage, parse_require_comment("require-comment Other yes"));
+        assert_eq!(usage, parse_require_comment("require-comment on"));
+    }
+
+    #[test]
+    fn reconcile_arguments() {
+        assert_eq!(
+            Ok(ReconcileArgs::Check {
+                account: "Alex Savings".to_string(),
+                balance: "1234.56".to_string()
+            }),
+            parse_reconcile(r#""Alex Savings" 1234.56"#)
+        );
+        assert_eq!(
+            Ok(ReconcileArgs::Check {
+                account: "Alex Savings".to_string(),
+                balance: "-5".to_string()
+            }),
+            parse_reconcile("Alex Savings -5")
+        );
+        assert_eq!(Ok(ReconcileArgs::History), parse_reconcile(" history "));
+        let usage = Err(RECONCILE_USAGE.to_string());
+        assert_eq!(usage, parse_reconcile(""));
+        assert_eq!(usage, parse_reconcile("1234.56"));
+        assert_eq!(usage, parse_reconcile(r#"Savings "12""#));
+    }
+
+    #[test]
+    fn budget_arguments() {
+        assert_eq!(Ok(BudgetArgs::Show), parse_budget(" "));
+        assert_eq!(
+            Ok(BudgetArgs::Set {
+                category: "Eating out".to_string(),
+                amount: Some("150".to_string()),
+                currency: "EUR".to_string()
+            }),
+            parse_budget(r#""Eating out" 150 eur"#)
+        );
+        assert_eq!(
+            Ok(BudgetArgs::Set {
+                category: "Groceries".to_string(),
+                amount: None,
+                currency: "USD".to_string()
+            }),
+            parse_budget("Groceries none USD")
+        );
+        let usage = Err(BUDGET_USAGE.to_string());
+        assert_eq!(usage, parse_budget("150 EUR"));
+        assert_eq!(usage, parse_budget("Groceries"));
+    }
+
+    #[test]
+    fn category_stats_arguments() {
+        assert_eq!(
+            Ok("Eating out".to_string()),
+            parse_category_stats(r#"stats "Eating out""#)
+        );
+        assert_eq!(
+            Ok("Eating out".to_string()),
+            parse_category_stats("stats Eating out")
+        );
+        let usage = Err(CATEGORY_USAGE.to_string());
+        assert_eq!(usage, parse_category_stats("stats"));
+        assert_eq!(usage, parse_category_stats(r#""stats" Other"#));
+    }
+
+    #[test]
+    fn settings_arguments() {
+        assert_eq!(Ok(SettingsArgs::Show), parse_settings(" "));
+        assert_eq!(
+            Ok(SettingsArgs::Privacy(true)),
+            parse_settings("privacy on")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::Privacy(false)),
+            parse_settings(" privacy  off ")
+        );
+        assert_eq!(Ok(SettingsArgs::Round(true)), parse_settings("round on"));
+        assert_eq!(
+            Ok(SettingsArgs::MonthToDate(false)),
+            parse_settings("mtd off")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::Confirm(ConfirmMode::Silent)),
+            parse_settings("confirm Silent")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::ReportExclude(vec![
+                "Housing".to_string(),
+                "Eating out".to_string()
+            ])),
+            parse_settings("report-exclude Housing, \"Eating out\"")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::ReportExclude(Vec::new())),
+            parse_settings("report-exclude none")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::WeekStart(Weekday::Sun)),
+            parse_settings("week sun")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::MonthStart(25)),
+            parse_settings("month-start 25")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::Currency(Some("USD".to_string()))),
+            parse_settings("currency usd")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::Currency(None)),
+            parse_settings("currency none")
+        );
+        let usage = Err(SETTINGS_USAGE.to_string());
+        assert_eq!(
+            Ok(SettingsArgs::Quiet(QuietHours { start: 23, end: 7 })),
+            parse_settings("quiet 23:00-07:00")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::Quiet(QuietHours { start: 13, end: 15 })),
+            parse_settings("quiet 13-15")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::Quiet(QuietHours { start: 0, end: 0 })),
+            parse_settings("quiet off")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::TimeZone(Some("Europe/Berlin".to_string()))),
+            parse_settings("timezone Europe/Berlin")
+        );
+        assert_eq!(
+            Ok(SettingsArgs::TimeZone(None)),
+            parse_settings("timezone none")
+        );
+        assert_eq!(usage, parse_settings("quiet 22-24"));
+        assert_eq!(usage, parse_settings("quiet 22:30-08:00"));
+        assert_eq!(usage, parse_settings("quiet 22"));
+        assert_eq!(usage, parse_settings("timezone"));
+        assert_eq!(usage, parse_settings("currency"));
+        assert_eq!(usage, parse_settings("currency dollars"));
+        assert_eq!(usage, parse_settings("currency U5D"));
+        assert_eq!(usage, parse_settings("week tue"));
+        assert_eq!(usage, parse_settings("month-start 25th"));
+        assert_eq!(usage, parse_settings("privacy"));
+        assert_eq!(usage, parse_settings("privacy maybe"));
+        assert_eq!(usage, parse_settings("confirm"));
+        assert_eq!(usage, parse_settings("confirm loud"));
+        assert_eq!(usage, parse_settings("strict on"));
+        assert_eq!(usage, parse_settings("mtd maybe"));
+        assert_eq!(usage, parse_settings("report-exclude"));
+        assert_eq!(usage, parse_settings("report-exclude Housing,,Rent"));
+    }
+
+    #[test]
+    fn report_arguments() {
+        let names = |names: &[&str]| Some(names.iter().map(|n| n.to_string()).collect());
+        let spent = ReportDate::Spent;
+        assert_eq!(
+            Ok(("lastmonth".to_string(), None, spent)),
+            parse_report(" lastmonth ")
+        );
+        assert_eq!(
+            Ok((String::new(), names(&["Housing", "Utilities"]), spent)),
+            parse_report("exclude Housing,Utilities")
+        );
+        assert_eq!(
+            Ok((
+                "2023-12-01..2023-12-31".to_string(),
+                names(&["Eating out"]),
+                spent
+            )),
+            parse_report("2023-12-01..2023-12-31 EXCLUDE Eating out")
+        );
+        assert_eq!(
+            Ok(("week".to_string(), names(&[]), spent)),
+            parse_report("week exclude none")
+        );
+        let logged = ReportDate::Logged;
+        assert_eq!(Ok((String::new(), None, logged)), parse_report("by logged"));
+        assert_eq!(
+            Ok(("lastmonth".to_string(), names(&["Rent"]), logged)),
+            parse_report("by Logged lastmonth exclude Rent")
+        );
+        assert_eq!(
+            Ok(("lastmonth".to_string(), None, logged)),
+            parse_report("lastmonth by logged")
+        );
+        // Only before the categories.
+        assert_eq!(
+            Ok((String::new(), names(&["by logged"]), spent)),
+            parse_report("exclude by logged")
+        );
+        assert_eq!(Err(REPORT_USAGE.to_string()), parse_report("exclude"));
+        assert_eq!(Err(REPORT_USAGE.to_string()), parse_report("exclude Rent,"));
+    }
+
+    #[test]
+    fn allow_arguments() {
+        assert_eq!(Ok(("42".to_string(), Role::Member)), parse_allow(" 42 "));
+        assert_eq!(
+            Ok(("@hanna".to_string(), Role::Viewer)),
+            parse_allow("@hanna viewer")
+        );
+        let usage = Err(ALLOW_USAGE.to_string());
+        assert_eq!(usage, parse_allow(""));
+        assert_eq!(usage, parse_allow("42 owner"));
+        assert_eq!(usage, parse_allow("42 member now"));
+    }
+
+    #[test]
+    fn archive_arguments() {
+        assert_eq!(
+            NaiveDate::from_ymd_opt(2023, 1, 1).ok_or_else(String::new),
+            parse_archive(" before 2023-01-01 ")
+        );
+        let usage = Err(ARCHIVE_USAGE.to_string());
+        assert_eq!(usage, parse_archive("2023-01-01"));
+        assert_eq!(usage, parse_archive("before 2023-13-01"));
+        assert_eq!(usage, parse_archive("before"));
+    }
+
+    #[test]
+    fn source_arguments() {
+        assert_eq!(Ok(SourceArgs::List), parse_source(""));
+        assert_eq!(Ok(SourceArgs::List), parse_source("list"));
+        assert_eq!(
+            Ok(SourceArgs::Add {
+                source: None,
+                category: "Utilities".to_string(),
+                account: "Family BYN".to_string()
+            }),
+            parse_source("add Utilities -> Family BYN")
+        );
+        assert_eq!(
+            Ok(SourceArgs::Add {
+                source: Some(SourceId::Chat(-100_123)),
+                category: "Eating out".to_string(),
+                account: "Alex Savings".to_string()
+            }),
+            parse_source(r#"add chat:-100123 "Eating out" -> "Alex Savings""#)
+        );
+        assert_eq!(
+            Ok(SourceArgs::Template {
+                id: 2,
+                pattern: Some(r"to pay: (\d+)".to_string())
+            }),
+            parse_source(r#"template #2 "to pay: (\d+)""#)
+        );
+        assert_eq!(
+            Ok(SourceArgs::Template {
+                id: 2,
+                pattern: None
+            }),
+            parse_source("template 2 none")
+        );
+        assert_eq!(Ok(SourceArgs::Del(3)), parse_source("del #3"));
+        let usage = Err(SOURCE_USAGE.to_string());
+        assert_eq!(usage, parse_source("add Utilities"));
+        assert_eq!(usage, parse_source("add chat:-1 -> Family BYN"));
+        assert_eq!(usage, parse_source("template 2 to pay"));
+        assert_eq!(usage, parse_source("del one"));
+        assert_eq!(usage, parse_source("list all"));
+    }
+
+    #[test]
+    fn notify_arguments() {
+        assert_eq!(Ok(NotifyArgs::Show), parse_notify(" "));
+        assert_eq!(Ok(NotifyArgs::On), parse_notify("on"));
+        assert_eq!(Ok(NotifyArgs::Off), parse_notify("off"));
+        assert_eq!(
+            Ok(NotifyArgs::Over {
+                amount: "50".to_string(),
+                currency: "EUR".to_string()
+            }),
+            parse_notify("over 50 eur")
+        );
+        assert_eq!(
+            Ok(NotifyArgs::Category("Eating out".to_string())),
+            parse_notify("category Eating out")
+        );
+        let usage = Err(NOTIFY_USAGE.to_string());
+        assert_eq!(usage, parse_notify("over 50"));
+        assert_eq!(usage, parse_notify("category"));
+        assert_eq!(usage, parse_notify("on please"));
+    }
+
+    #[test]
+    fn subscribe_arguments() {
+        assert_eq!(Ok(SubscribeArgs::Status), parse_subscribe(""));
+        assert_eq!(Ok(SubscribeArgs::Status), parse_subscribe("status"));
+        assert_eq!(Ok(SubscribeArgs::Off), parse_subscribe(" OFF "));
+        assert_eq!(
+            Ok(SubscribeArgs::Subscribe(Cadence::Weekly)),
+            parse_subscribe("weekly")
+        );
+        assert_eq!(
+            Ok(SubscribeArgs::Subscribe(Cadence::Monthly)),
+            parse_subscribe("Month")
+        );
+        let usage = Err(SUBSCRIBE_USAGE.to_string());
+        assert_eq!(usage, parse_subscribe("daily"));
+        assert_eq!(usage, parse_subscribe("weekly monthly"));
+    }
+}
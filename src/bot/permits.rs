@@ -0,0 +1,116 @@
+//! Every update is handled holding a permit of the [`Coordinator`]: a
+//! shared one, or an exclusive one for `/maintenance` and the confirmation
+//! of an archive. The dispatcher keeps the permit until the handler is
+//! done, so handlers don't deal with it themselves.
+
+use super::callback::CallbackData;
+use super::markdown::escape_md;
+use super::Bot;
+use crate::model::{Coordinator, Error, ExclusivePermit, SharedPermit};
+use std::sync::Arc;
+use teloxide::prelude::*;
+
+pub type SharedCoordinator = Arc<Coordinator>;
+
+/// Held while an update is handled.
+pub enum Permit {
+    Shared { _permit: SharedPermit },
+    Exclusive { _permit: ExclusivePermit },
+}
+
+/// The long operation a message starts, if any: only `/maintenance`,
+/// possibly addressed to the bot.
+fn message_operation(text: &str) -> Option<&'static str> {
+    let command = text.split_whitespace().next()?;
+    let command = command.split('@').next()?;
+    command
+        .eq_ignore_ascii_case("/maintenance")
+        .then_some("Maintenance")
+}
+
+/// The long operation a button starts, if any. Archive confirmations are
+/// short enough to be sent inline, never stored.
+fn callback_operation(data: &str) -> Option<&'static str> {
+    match CallbackData::parse(data) {
+        Some(CallbackData::Archive(_)) => Some("Archiving"),
+        _ => None,
+    }
+}
+
+async fn acquire(
+    coordinator: &Coordinator,
+    operation: Option<&'static str>,
+) -> Result<Permit, Error> {
+    Ok(match operation {
+        Some(operation) => Permit::Exclusive {
+            _permit: coordinator.exclusive(operation).await?,
+        },
+        None => Permit::Shared {
+            _permit: coordinator.shared().await?,
+        },
+    })
+}
+
+/// The permit for a message, `None` having told the user the database is
+/// busy. Failing to tell them only leaves the message unanswered.
+pub async fn for_message(
+    bot: Bot,
+    message: Message,
+    coordinator: SharedCoordinator,
+) -> Option<Permit> {
+    let operation = message.text().and_then(message_operation);
+    match acquire(&coordinator, operation).await {
+        Ok(permit) => Some(permit),
+        Err(e) => {
+            let _ = bot
+                .send_message(message.chat.id, escape_md(&e.to_string()))
+                .await;
+            None
+        }
+    }
+}
+
+/// The permit for a button, `None` having told the user the database is
+/// busy.
+pub async fn for_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    coordinator: SharedCoordinator,
+) -> Option<Permit> {
+    let operation = q.data.as_deref().and_then(callback_operation);
+    match acquire(&coordinator, operation).await {
+        Ok(permit) => Some(permit),
+        Err(e) => {
+            let _ = bot.answer_callback_query(q.id).text(e.to_string()).await;
+            None
+        }
+    }
+}
+
+/// The permit for a reaction, `None` ignoring it while the database is
+/// busy: reactions aren't answered.
+pub async fn for_reaction(coordinator: SharedCoordinator) -> Option<Permit> {
+    acquire(&coordinator, None).await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn long_operations_of_updates() {
+        assert_eq!(Some("Maintenance"), message_operation("/maintenance"));
+        assert_eq!(
+            Some("Maintenance"),
+            message_operation("/maintenance@spending_bot now")
+        );
+        assert_eq!(None, message_operation("/maintenances"));
+        assert_eq!(None, message_operation("12 maintenance"));
+        assert_eq!(None, message_operation(""));
+
+        let archive = CallbackData::Archive(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap());
+        assert_eq!(Some("Archiving"), callback_operation(&archive.encode()));
+        assert_eq!(None, callback_operation("pick:category"));
+    }
+}
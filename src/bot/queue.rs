@@ -0,0 +1,293 @@
+//! Scheduled messages, like subscribed reports, go out through a queue kept
+//! in the database: they wait out the quiet hours of their chat, a send that
+//! fails is tried again, and a restart loses neither. Replies to users don't
+//! go through it.
+
+use super::reporter::ErrorReporter;
+use super::{Bot, SharedModel};
+use crate::model::{Error, Model};
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use log::*;
+use teloxide::prelude::*;
+
+/// How often the queue is drained.
+pub const DRAIN_EVERY: TimeDelta = TimeDelta::minutes(1);
+
+/// How long a message that failed to send waits to be tried again.
+const RETRY_AFTER: TimeDelta = TimeDelta::minutes(15);
+
+/// Sends tried before a message is given up on.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The time zone of the chat: its own, else the bot's.
+pub fn chat_tz(model: &Model, chat_id: ChatId, tz: Tz) -> Result<Tz, Error> {
+    Ok(model.chat_time_zone(chat_id.0)?.unwrap_or(tz))
+}
+
+/// When a message scheduled for the chat at `now` may go out.
+fn release(
+    model: &Model,
+    chat_id: ChatId,
+    (now, tz): (DateTime<Utc>, Tz),
+) -> Result<DateTime<Utc>, Error> {
+    let hours = model.chat_quiet_hours(chat_id.0)?;
+    Ok(hours.release(now, chat_tz(model, chat_id, tz)?))
+}
+
+/// Queues `text`, in MarkdownV2, for the chat: for the next drain, or the
+/// end of its quiet hours if it is quiet at `now`.
+pub fn schedule(
+    model: &Model,
+    chat_id: ChatId,
+    text: &str,
+    (now, tz): (DateTime<Utc>, Tz),
+) -> Result<(), Error> {
+    let due = release(model, chat_id, (now, tz))?;
+    model.queue_notification(chat_id.0, text, due, now)?;
+    Ok(())
+}
+
+/// Sends the queued messages due at `now`. One whose chat has turned quiet
+/// since it was queued waits again; one that fails to send is tried again
+/// later, up to `MAX_ATTEMPTS` times.
+pub async fn drain(
+    bot: Bot,
+    model: SharedModel,
+    (now, tz): (DateTime<Utc>, Tz),
+    reporter: ErrorReporter,
+) -> Result<(), Error> {
+    let due = model.lock().unwrap().due_notifications(now)?;
+    for notification in due {
+        let chat_id = ChatId(notification.chat_id);
+        let release = release(&model.lock().unwrap(), chat_id, (now, tz))?;
+        if release > now {
+            model
+                .lock()
+                .unwrap()
+                .postpone_notification(notification.id, release, false)?;
+            continue;
+        }
+        let sent = bot.send_message(chat_id, notification.text.clone()).await;
+        let attempts = notification.attempts + 1;
+        let result = match sent {
+            Ok(_) => model.lock().unwrap().remove_notification(notification.id),
+            Err(e) if attempts >= MAX_ATTEMPTS => {
+                let message = format!(
+                    "chat {}: given up after {} attempts: {}",
+                    chat_id, attempts, e
+                );
+                reporter.report(Level::Warn, "notification queue", message);
+                model.lock().unwrap().remove_notification(notification.id)
+            }
+            Err(e) => {
+                let message = format!("chat {}: {}", chat_id, e);
+                reporter.report(Level::Info, "notification queue", message);
+                model.lock().unwrap().postpone_notification(
+                    notification.id,
+                    now + RETRY_AFTER,
+                    true,
+                )
+            }
+        };
+        result?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::recording::{self, RecordingBot};
+    use crate::model::QuietHours;
+    use std::sync::{Arc, Mutex};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn shared() -> SharedModel {
+        let model = Model::new(true);
+        model.fill_test_data();
+        Arc::new(Mutex::new(model))
+    }
+
+    fn queued(model: &SharedModel) -> Vec<(i64, DateTime<Utc>, u32)> {
+        let model = model.lock().unwrap();
+        let far = at("2100-01-01T00:00:00Z");
+        model
+            .due_notifications(far)
+            .unwrap()
+            .iter()
+            .map(|n| (n.chat_id, n.due_at, n.attempts))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sent_right_away_outside_quiet_hours() {
+        let model = shared();
+        let recording = RecordingBot::start();
+        let (reporter, _receiver) = ErrorReporter::new();
+        let noon = at("2023-12-04T12:00:00Z");
+        schedule(
+            &model.lock().unwrap(),
+            ChatId(1001),
+            "Report",
+            (noon, Tz::UTC),
+        )
+        .unwrap();
+
+        drain(
+            recording.bot.clone(),
+            model.clone(),
+            (noon, Tz::UTC),
+            reporter,
+        )
+        .await
+        .unwrap();
+        let calls = recording.take();
+        assert_eq!(1, calls.len());
+        assert_eq!("sendMessage", calls[0].method);
+        assert!(calls[0].body.contains("\"chat_id\":1001"));
+        assert!(queued(&model).is_empty());
+    }
+
+    #[tokio::test]
+    async fn deferred_across_midnight() {
+        let model = shared();
+        let recording = RecordingBot::start();
+        let (reporter, _receiver) = ErrorReporter::new();
+        let night = at("2023-12-04T23:30:00Z");
+        let morning = at("2023-12-05T08:00:00Z");
+        schedule(
+            &model.lock().unwrap(),
+            ChatId(1001),
+            "Report",
+            (night, Tz::UTC),
+        )
+        .unwrap();
+        assert_eq!(vec![(1001, morning, 0)], queued(&model));
+
+        // Past midnight it still waits, and it outlives the drains.
+        for now in [
+            night,
+            at("2023-12-05T00:30:00Z"),
+            at("2023-12-05T07:59:00Z"),
+        ] {
+            drain(
+                recording.bot.clone(),
+                model.clone(),
+                (now, Tz::UTC),
+                reporter.clone(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(recording.take().is_empty());
+
+        drain(
+            recording.bot.clone(),
+            model.clone(),
+            (morning, Tz::UTC),
+            reporter,
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, recording.take().len());
+        assert!(queued(&model).is_empty());
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_of_the_chat() {
+        let model = shared();
+        let recording = RecordingBot::start();
+        let (reporter, _receiver) = ErrorReporter::new();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        {
+            let model = model.lock().unwrap();
+            model.set_chat_time_zone(1001, 1, Some(berlin)).unwrap();
+            model
+                .set_chat_quiet_hours(1002, 1, QuietHours { start: 0, end: 0 })
+                .unwrap();
+        }
+        // 22:30 UTC is 23:30 in Berlin, quiet until 07:00 UTC; Hanna has no
+        // quiet hours.
+        let now = at("2023-12-04T22:30:00Z");
+        for user in [1001, 1002] {
+            schedule(
+                &model.lock().unwrap(),
+                ChatId(user),
+                "Report",
+                (now, Tz::UTC),
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            vec![(1001, at("2023-12-05T07:00:00Z"), 0), (1002, now, 0)],
+            queued(&model)
+        );
+
+        // Quiet hours set after queuing hold a message back, too.
+        {
+            let model = model.lock().unwrap();
+            model.set_chat_time_zone(1002, 1, Some(berlin)).unwrap();
+            model
+                .set_chat_quiet_hours(1002, 1, QuietHours::DEFAULT)
+                .unwrap();
+        }
+        drain(
+            recording.bot.clone(),
+            model.clone(),
+            (now, Tz::UTC),
+            reporter,
+        )
+        .await
+        .unwrap();
+        assert!(recording.take().is_empty());
+        assert_eq!(
+            vec![
+                (1001, at("2023-12-05T07:00:00Z"), 0),
+                (1002, at("2023-12-05T07:00:00Z"), 0)
+            ],
+            queued(&model)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_sends_are_retried_then_given_up() {
+        let model = shared();
+        let (reporter, mut receiver) = ErrorReporter::new();
+        let mut now = at("2023-12-04T12:00:00Z");
+        schedule(
+            &model.lock().unwrap(),
+            ChatId(1001),
+            "Report",
+            (now, Tz::UTC),
+        )
+        .unwrap();
+
+        for attempts in 1..MAX_ATTEMPTS {
+            drain(
+                recording::unreachable(),
+                model.clone(),
+                (now, Tz::UTC),
+                reporter.clone(),
+            )
+            .await
+            .unwrap();
+            now += RETRY_AFTER;
+            assert_eq!(vec![(1001, now, attempts)], queued(&model));
+        }
+        drain(
+            recording::unreachable(),
+            model.clone(),
+            (now, Tz::UTC),
+            reporter,
+        )
+        .await
+        .unwrap();
+        assert!(queued(&model).is_empty());
+        let report = receiver.try_recv().unwrap();
+        assert!(report.message.contains("given up after 5 attempts"));
+    }
+}
@@ -0,0 +1,109 @@
+//! `/readonly`: freezes changes bot-wide while an admin restores a backup
+//! or migrates. The model refuses the changes wherever they come from;
+//! handlers pass the refusal on, and it is answered here. Changes of
+//! expenses in a month closed by `/close` are refused and answered alike.
+
+use super::markdown::escape_md;
+use super::{Bot, HandlerError, HandlerResult, SharedModel};
+use crate::model::{Error, User};
+use teloxide::prelude::*;
+
+pub const READONLY_USAGE: &str = "Usage: /readonly on|off";
+
+/// `/readonly`: the reply to send.
+pub fn handle_readonly(model: &SharedModel, user: &User, args: &str) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    let on = match args.trim() {
+        "" => {
+            return Ok(match model.read_only()? {
+                true => "The bot is read-only, changes are paused.".to_string(),
+                false => "The bot isn't read-only.".to_string(),
+            })
+        }
+        "on" => true,
+        "off" => false,
+        _ => return Ok(READONLY_USAGE.to_string()),
+    };
+    let changed = model.set_read_only(user.telegram_id, on)?;
+    Ok(match (on, changed) {
+        (true, true) => "The bot is read-only now: changes are refused until /readonly off, reports and exports keep working.",
+        (true, false) => "The bot is read-only already.",
+        (false, true) => "Read-only mode is off, changes are saved again.",
+        (false, false) => "The bot isn't read-only.",
+    }
+    .to_string())
+}
+
+fn is_paused(e: &HandlerError) -> bool {
+    matches!(
+        e.downcast_ref::<Error>(),
+        Some(Error::ReadOnly | Error::Closed(_))
+    )
+}
+
+/// `result` of handling a message, a change refused by read-only mode or
+/// a closed month told to the chat instead.
+pub async fn answer_message(bot: &Bot, chat_id: ChatId, result: HandlerResult) -> HandlerResult {
+    match result {
+        Err(e) if is_paused(&e) => {
+            bot.send_message(chat_id, escape_md(&e.to_string())).await?;
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// `result` of handling a button, a change refused by read-only mode or a
+/// closed month told to whoever tapped it instead.
+pub async fn answer_callback(bot: &Bot, q: &CallbackQuery, result: HandlerResult) -> HandlerResult {
+    match result {
+        Err(e) if is_paused(&e) => {
+            bot.answer_callback_query(q.id.clone())
+                .text(e.to_string())
+                .await?;
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn readonly_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let alex = model.get_user(1001).unwrap().unwrap();
+        let model = Arc::new(Mutex::new(model));
+        let readonly = |args| handle_readonly(&model, &alex, args).unwrap();
+
+        assert_eq!("The bot isn't read-only.", readonly(""));
+        assert_eq!(
+            "The bot is read-only now: changes are refused until /readonly off, reports and exports keep working.",
+            readonly("on")
+        );
+        assert_eq!("The bot is read-only already.", readonly("on"));
+        assert_eq!("The bot is read-only, changes are paused.", readonly(""));
+        assert_eq!(READONLY_USAGE, readonly("maybe"));
+        assert_eq!(
+            "Read-only mode is off, changes are saved again.",
+            readonly("off")
+        );
+        assert_eq!("The bot isn't read-only.", readonly("off"));
+    }
+
+    #[test]
+    fn only_paused_changes_are_answered() {
+        let refused: HandlerError = Error::ReadOnly.into();
+        assert!(is_paused(&refused));
+        let november = chrono::NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let closed: HandlerError = Error::Closed(november).into();
+        assert!(is_paused(&closed));
+        let other: HandlerError = Error::Busy("Maintenance".to_string()).into();
+        assert!(!is_paused(&other));
+    }
+}
@@ -0,0 +1,214 @@
+//! `/reconcile`: checks an account against the balance the bank shows and
+//! offers to record the difference as an adjustment.
+
+use super::auth::{self, Access};
+use super::callback::{self, CallbackData};
+use super::draft::DraftKey;
+use super::format::format_amount;
+use super::markdown::escape_md;
+use super::parse::{self, ReconcileArgs};
+use super::{resolve, Bot, HandlerResult, SharedModel};
+use crate::model::{from_minor, parse_balance, Error, Locale, Model, Reconciliation, Role, User};
+use crate::time::{self, Style};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// How many reconciliations `/reconcile history` lists.
+const HISTORY: usize = 20;
+
+fn amount(r: &Reconciliation, minor: i64) -> String {
+    let amount = from_minor(minor, r.exponent);
+    format!("{} {}", format_amount(amount, r.exponent), r.currency)
+}
+
+/// `/reconcile`, in plain text, with the button recording an adjustment
+/// when the balances differ.
+pub fn handle_reconcile(
+    model: &Model,
+    user: &User,
+    args: &str,
+    locale: Locale,
+    (now, tz): (DateTime<Utc>, Tz),
+) -> Result<(String, Option<InlineKeyboardMarkup>), Error> {
+    let (account, balance) = match parse::parse_reconcile(args) {
+        Ok(ReconcileArgs::Check { account, balance }) => (account, balance),
+        Ok(ReconcileArgs::History) => return Ok((history(model, user.household, tz)?, None)),
+        Err(usage) => return Ok((usage, None)),
+    };
+    let account = match resolve::account(model, user.household, &account)? {
+        Ok(account) => account,
+        Err(reason) => return Ok((reason, None)),
+    };
+    let Some(actual) = parse_balance(&balance, locale, account.exponent) else {
+        return Ok((format!("'{}' is not a balance.", balance), None));
+    };
+    let r = model.reconcile(user.household, account.id, actual, now)?;
+    let delta = r.delta();
+    if delta == 0 {
+        return Ok((
+            format!(
+                "✅ {} matches the bank: {}.",
+                r.account,
+                amount(&r, r.actual)
+            ),
+            None,
+        ));
+    }
+    let text = format!(
+        "{}: the bank shows {}, the bot {}. The bank has {} {}.",
+        r.account,
+        amount(&r, r.actual),
+        amount(&r, r.computed),
+        amount(&r, delta.abs()),
+        if delta > 0 { "more" } else { "less" }
+    );
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            "⚖️ Record adjustment",
+            CallbackData::RecordAdjustment(r.id).encode(),
+        ),
+        InlineKeyboardButton::callback("✖️ Cancel", CallbackData::Dismiss.encode()),
+    ]]);
+    Ok((text, Some(keyboard)))
+}
+
+/// The latest reconciliations, one line each.
+fn history(model: &Model, household: i64, tz: Tz) -> Result<String, Error> {
+    let reconciliations = model.reconciliations(household, HISTORY)?;
+    if reconciliations.is_empty() {
+        return Ok("No reconciliations yet.".to_string());
+    }
+    let lines: Vec<String> = reconciliations
+        .iter()
+        .map(|r| {
+            let delta = r.delta();
+            let mut line = format!(
+                "{} {}: {}{}",
+                time::render(r.timestamp, tz, Style::Date),
+                r.account,
+                if delta > 0 { "+" } else { "" },
+                amount(r, delta)
+            );
+            if r.adjusted {
+                line.push_str(", adjusted");
+            }
+            line
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+/// The button recording an adjustment, in plain text.
+pub fn adjust_text(
+    model: &Model,
+    user: &User,
+    reconciliation_id: i64,
+    now: DateTime<Utc>,
+) -> Result<String, Error> {
+    match model.record_adjustment(user.household, reconciliation_id, user.telegram_id, now) {
+        Ok(id) => {
+            let expense = model
+                .get_expense_details(user.household, id)?
+                .ok_or_else(|| Error::NotFound(format!("expense {}", id)))?;
+            let r = model
+                .reconciliation(user.household, reconciliation_id)?
+                .ok_or_else(|| Error::NotFound(format!("reconciliation {}", reconciliation_id)))?;
+            Ok(format!(
+                "⚖️ Recorded an adjustment of {} {} in {}, {} is at {} now.",
+                format_amount(expense.amount, r.exponent),
+                r.currency,
+                expense.category.as_deref().unwrap_or("?"),
+                r.account,
+                amount(&r, r.actual)
+            ))
+        }
+        Err(Error::Refused(reason)) => Ok(reason),
+        Err(Error::NotFound(_)) => Ok(callback::NO_OPTION.to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn record_adjustment(
+    bot: &Bot,
+    q: &CallbackQuery,
+    model: &SharedModel,
+    key: DraftKey,
+    reconciliation_id: i64,
+) -> HandlerResult {
+    let user = match auth::requires(model, &q.from, key.chat_id, Role::Member)? {
+        Access::Granted(user) => user,
+        Access::Denied(text) => {
+            bot.answer_callback_query(q.id.clone()).text(text).await?;
+            return Ok(());
+        }
+    };
+    let text = adjust_text(&model.lock().unwrap(), &user, reconciliation_id, Utc::now())?;
+    bot.edit_message_text(key.chat_id, key.message_id, escape_md(&text))
+        .await?;
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alex() -> User {
+        User {
+            telegram_id: 1001,
+            telegram_name: "alex".to_string(),
+            display_name: "Alex".to_string(),
+            role: Role::Member,
+            household: 1,
+        }
+    }
+
+    #[test]
+    fn reconcile_and_adjust() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = DateTime::from_timestamp(1_705_320_000, 0).unwrap();
+        let reconcile = |args| {
+            handle_reconcile(&model, &alex(), args, Locale::DecimalPoint, (now, Tz::UTC)).unwrap()
+        };
+
+        assert_eq!(
+            ("No reconciliations yet.".to_string(), None),
+            reconcile("history")
+        );
+        let (text, keyboard) = reconcile(r#""Alex Savings" 940"#);
+        assert_eq!(
+            "Alex Savings: the bank shows 940.00 EUR, the bot 949.25 EUR. The bank has 9.25 EUR less.",
+            text
+        );
+        assert!(keyboard.is_some());
+        let id = model.reconciliations(1, 1).unwrap()[0].id;
+        assert_eq!(
+            "⚖️ Recorded an adjustment of 9.25 EUR in Adjustments, Alex Savings is at 940.00 EUR now.",
+            adjust_text(&model, &alex(), id, now).unwrap()
+        );
+        assert_eq!(
+            "The adjustment has been recorded already.",
+            adjust_text(&model, &alex(), id, now).unwrap()
+        );
+        assert_eq!(
+            (
+                "✅ Alex Savings matches the bank: 940.00 EUR.".to_string(),
+                None
+            ),
+            reconcile("alex 940")
+        );
+        assert_eq!(
+            "2024-01-15 Alex Savings: 0.00 EUR\n2024-01-15 Alex Savings: -9.25 EUR, adjusted",
+            reconcile("history").0
+        );
+        assert_eq!("'12a' is not a balance.", reconcile("alex 12a").0);
+        assert_eq!("'9.999' is not a balance.", reconcile("alex 9.999").0);
+        assert_eq!(
+            "This option no longer exists.",
+            adjust_text(&model, &alex(), id + 100, now).unwrap()
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! A bot for tests driving whole handlers: its requests go to a local
+//! server answering the way Telegram does, which records the methods called.
+
+use super::Bot;
+use chrono::DateTime;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use teloxide::prelude::*;
+use teloxide::types::{
+    CallbackQuery, Chat, ChatFullInfo, ChatKind, ChatPrivate, MaybeInaccessibleMessage, MediaKind,
+    MediaText, Message, MessageCommon, MessageId, MessageKind, ParseMode,
+};
+
+/// A request the bot made: the method, as in `answerCallbackQuery`, and
+/// its JSON body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub method: String,
+    pub body: String,
+}
+
+pub struct RecordingBot {
+    pub bot: Bot,
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl RecordingBot {
+    pub fn start() -> RecordingBot {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                serve(stream, &recorded);
+            }
+        });
+
+        let bot = teloxide::Bot::new("123:test");
+        let mut url = bot.api_url();
+        url.set_scheme("http").unwrap();
+        url.set_host(Some("127.0.0.1")).unwrap();
+        url.set_port(Some(port)).unwrap();
+        RecordingBot {
+            bot: bot.set_api_url(url).parse_mode(ParseMode::MarkdownV2),
+            calls,
+        }
+    }
+
+    /// The requests made so far, and forgets them.
+    pub fn take(&self) -> Vec<Call> {
+        std::mem::take(&mut self.calls.lock().unwrap())
+    }
+}
+
+/// A bot whose requests find nobody listening, so that every one fails.
+pub fn unreachable() -> Bot {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let bot = teloxide::Bot::new("123:test");
+    let mut url = bot.api_url();
+    url.set_scheme("http").unwrap();
+    url.set_host(Some("127.0.0.1")).unwrap();
+    url.set_port(Some(port)).unwrap();
+    bot.set_api_url(url).parse_mode(ParseMode::MarkdownV2)
+}
+
+/// Records one request and answers it, closing the connection after it.
+/// The call is recorded first, so that it is there once the bot has its
+/// answer.
+fn serve(stream: TcpStream, recorded: &Mutex<Vec<Call>>) -> Option<()> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let method = request_line.split_whitespace().nth(1)?.rsplit('/').next()?;
+    // Teloxide spells methods with a capital, Telegram doesn't mind.
+    let mut chars = method.chars();
+    let method = match chars.next() {
+        Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+        None => String::new(),
+    };
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    let result = match method.as_str() {
+        "answerCallbackQuery" | "deleteMessage" => "true",
+        _ => r#"{"message_id":1,"date":0,"chat":{"id":1001,"type":"private"},"text":"ok"}"#,
+    };
+    recorded.lock().unwrap().push(Call {
+        method,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+
+    let response = format!(r#"{{"ok":true,"result":{}}}"#, result);
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    )
+    .ok()
+}
+
+/// A Telegram user with a private chat with the bot.
+pub fn user(id: u64) -> teloxide::types::User {
+    teloxide::types::User {
+        id: UserId(id),
+        is_bot: false,
+        first_name: "Alex".to_string(),
+        last_name: None,
+        username: Some("alex_bot".to_string()),
+        language_code: None,
+        is_premium: false,
+        added_to_attachment_menu: false,
+    }
+}
+
+/// The bot's message `message_id` in the private chat of `user`.
+pub fn message(user: &teloxide::types::User, message_id: i32) -> Message {
+    Message {
+        id: MessageId(message_id),
+        thread_id: None,
+        from: None,
+        sender_chat: None,
+        date: DateTime::UNIX_EPOCH,
+        chat: Chat {
+            id: user.id.into(),
+            kind: ChatKind::Private(ChatPrivate {
+                username: user.username.clone(),
+                first_name: Some(user.first_name.clone()),
+                last_name: None,
+                bio: None,
+                has_private_forwards: None,
+                has_restricted_voice_and_video_messages: None,
+            }),
+            photo: None,
+            available_reactions: None,
+            pinned_message: None,
+            message_auto_delete_time: None,
+            has_hidden_members: false,
+            has_aggressive_anti_spam_enabled: false,
+            chat_full_info: ChatFullInfo::default(),
+        },
+        is_topic_message: false,
+        via_bot: None,
+        kind: MessageKind::Common(MessageCommon {
+            author_signature: None,
+            forward_origin: None,
+            reply_to_message: None,
+            external_reply: None,
+            quote: None,
+            edit_date: None,
+            media_kind: MediaKind::Text(MediaText {
+                text: "A draft".to_string(),
+                entities: Vec::new(),
+                link_preview_options: None,
+            }),
+            reply_markup: None,
+            is_automatic_forward: false,
+            has_protected_content: false,
+        }),
+    }
+}
+
+/// A press of a button carrying `data` under `message`.
+pub fn press(from: &teloxide::types::User, message: &Message, data: &str) -> CallbackQuery {
+    CallbackQuery {
+        id: format!("press:{}", data),
+        from: from.clone(),
+        message: Some(MaybeInaccessibleMessage::Regular(message.clone())),
+        inline_message_id: None,
+        chat_instance: "1".to_string(),
+        data: Some(data.to_string()),
+        game_short_name: None,
+    }
+}
@@ -0,0 +1,172 @@
+//! 🔁 on the confirmation of a saved expense: a new draft of the same
+//! expense, dated now, to tweak before committing it.
+
+use super::auth::{self, Access};
+use super::callback::Page;
+use super::draft::{ActiveTransaction, SharedDrafts};
+use super::expense::create_draft;
+use super::{Bot, HandlerResult, SharedModel};
+use crate::config::Config;
+use crate::model::{Account, Category, ExpenseDetails, ExpenseSource, Role, User};
+use chrono::{DateTime, Utc};
+use log::*;
+use std::sync::Arc;
+use teloxide::types::{MessageReactionUpdated, ReactionType};
+
+/// The reaction that logs an expense again.
+const REPEAT: &str = "🔁";
+
+fn has_repeat(reactions: &[ReactionType]) -> bool {
+    reactions
+        .iter()
+        .any(|r| r.emoji().is_some_and(|e| e == REPEAT))
+}
+
+/// Whether the user just reacted with 🔁, rather than kept or took it back
+/// while changing other reactions.
+fn added_repeat(old: &[ReactionType], new: &[ReactionType]) -> bool {
+    has_repeat(new) && !has_repeat(old)
+}
+
+/// A draft of the same amount, category, account and comment as `expense`,
+/// by `user` at `now`. Splits, locations, amounts in another currency and
+/// the message it was logged from stay with the original.
+fn repeat_draft(
+    expense: &ExpenseDetails,
+    (account, category): (Account, Category),
+    (user, strict_commit): (&User, bool),
+    now: DateTime<Utc>,
+) -> ActiveTransaction {
+    ActiveTransaction {
+        expense_id: None,
+        amount: expense.amount,
+        amount_alternative: None,
+        amount_calculation: None,
+        original: None,
+        account,
+        // Both were picked when the expense was saved.
+        account_explicit: true,
+        missing_account: None,
+        category,
+        household: user.household,
+        user_id: user.telegram_id,
+        timestamp: now,
+        comment: expense.comment.clone(),
+        category_confirmed: true,
+        category_by_rule: false,
+        strict_commit,
+        split_with: None,
+        location: None,
+        comment_suggestions: Vec::new(),
+        comment_templates: Vec::new(),
+        template_fill: None,
+        source: None,
+        origin: ExpenseSource::Repeat,
+        page: Page::Main,
+    }
+}
+
+/// A change of reactions: 🔁 added to a confirmation by a member of its
+/// household posts a draft of the expense. Anything else is ignored,
+/// quietly, as reactions aren't addressed to the bot.
+pub async fn handle_reaction(
+    bot: Bot,
+    reaction: MessageReactionUpdated,
+    model: SharedModel,
+    config: Arc<Config>,
+    drafts: SharedDrafts,
+) -> HandlerResult {
+    if !added_repeat(&reaction.old_reaction, &reaction.new_reaction) {
+        return Ok(());
+    }
+    // Anonymous admins react as the chat.
+    let Some(from) = reaction.user() else {
+        return Ok(());
+    };
+    let user = match auth::requires(&model, from, reaction.chat.id, Role::Member)? {
+        Access::Granted(user) => user,
+        Access::Denied(text) => {
+            debug!("Ignoring 🔁 of {}: {}", from.id, text);
+            return Ok(());
+        }
+    };
+    let draft = {
+        let model = model.lock().unwrap();
+        let chat_id = reaction.chat.id.0;
+        let message_id = reaction.message_id.0;
+        let Some(expense) = model.confirmed_expense(user.household, chat_id, message_id)? else {
+            return Ok(());
+        };
+        let account = model.get_account(user.household, expense.account_id)?;
+        let category = model.get_category(user.household, expense.category_id)?;
+        let Some(chosen) = account.zip(category) else {
+            return Ok(());
+        };
+        let strict_commit = model.get_settings(user.telegram_id)?.strict_commit;
+        repeat_draft(&expense, chosen, (&user, strict_commit), Utc::now())
+    };
+    create_draft(&bot, &drafts, &reaction.chat, draft, config.timezone).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    fn emoji(emoji: &str) -> ReactionType {
+        ReactionType::Emoji {
+            emoji: emoji.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_an_added_repeat_counts() {
+        let added = |old: Vec<ReactionType>, new: Vec<ReactionType>| added_repeat(&old, &new);
+        assert!(added(vec![], vec![emoji("🔁")]));
+        assert!(added(vec![emoji("👍")], vec![emoji("👍"), emoji("🔁")]));
+        // Kept while adding another, or taken back.
+        assert!(!added(vec![emoji("🔁")], vec![emoji("🔁"), emoji("👍")]));
+        assert!(!added(vec![emoji("🔁")], vec![]));
+        assert!(!added(vec![], vec![emoji("👍")]));
+        let custom = ReactionType::CustomEmoji {
+            custom_emoji_id: "123".to_string(),
+        };
+        assert!(!added(vec![], vec![custom]));
+    }
+
+    #[test]
+    fn drafts_copy_the_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.record_confirmation(-100, 7, 4).unwrap();
+        model.split_expense(1, 4, Some(1001)).unwrap();
+        let alex = model.get_user(1001).unwrap().unwrap();
+        let expense = model.confirmed_expense(1, -100, 7).unwrap().unwrap();
+        let chosen = (
+            model.get_account(1, expense.account_id).unwrap().unwrap(),
+            model.get_category(1, expense.category_id).unwrap().unwrap(),
+        );
+        let now = DateTime::from_timestamp(1_705_320_000, 0).unwrap();
+
+        // Hanna's 100 USD for the electricity, logged again by Alex.
+        let draft = repeat_draft(&expense, chosen, (&alex, true), now);
+        assert_eq!(
+            (100.0, "Hanna Daily", "Utilities"),
+            (
+                draft.amount,
+                draft.account.name.as_str(),
+                draft.category.name.as_str()
+            )
+        );
+        assert_eq!(Some("Paid electricity bill"), draft.comment.as_deref());
+        assert_eq!(
+            (1001, 1, now),
+            (draft.user_id, draft.household, draft.timestamp)
+        );
+        assert_eq!(None, draft.expense_id);
+        assert_eq!(None, draft.split_with);
+        assert_eq!(ExpenseSource::Repeat, draft.origin);
+        assert!(draft.category_confirmed && draft.account_explicit);
+        assert!(!draft.commit_locked(draft.strict_commit));
+    }
+}
@@ -0,0 +1,304 @@
+//! Errors of background tasks, which have nobody to answer, told to the
+//! admin privately. Repeats of a failure within an hour are counted into one
+//! message rather than sent each, a task failing every few minutes would
+//! drown the chat otherwise.
+
+use super::markdown::escape_md;
+use super::Bot;
+use log::*;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Reports less severe than this are only logged, like users who blocked the
+/// bot.
+const REPORT_LEVEL: Level = Level::Warn;
+
+/// How long a class of failure goes between messages.
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// How often repeats whose hour is over are sent.
+const FLUSH_EVERY: Duration = Duration::from_secs(60);
+
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// A failure of a background task. `class` names what failed, like
+/// `feed update`, and repeats are counted by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub class: &'static str,
+    pub message: String,
+}
+
+/// Where background tasks send their failures, cheap to clone into each.
+#[derive(Clone)]
+pub struct ErrorReporter {
+    sender: UnboundedSender<Report>,
+}
+
+impl ErrorReporter {
+    /// A reporter and the end [`deliver`] reads its reports from.
+    pub fn new() -> (ErrorReporter, UnboundedReceiver<Report>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (ErrorReporter { sender }, receiver)
+    }
+
+    /// Logs the failure, and queues it for the admin if it is at least as
+    /// severe as [`REPORT_LEVEL`].
+    pub fn report(&self, level: Level, class: &'static str, message: impl ToString) {
+        let message = message.to_string();
+        log!(level, "{} failed: {}", class, message);
+        if level <= REPORT_LEVEL {
+            // Nobody delivers after shutdown, the log has it.
+            let _ = self.sender.send(Report { class, message });
+        }
+    }
+}
+
+/// What the panic said, if it said it with a string.
+pub(super) fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "no message".to_string(),
+        },
+    }
+}
+
+/// Spawns a background task, reporting it as `class` if it panics.
+pub fn spawn<F>(reporter: &ErrorReporter, class: &'static str, task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let reporter = reporter.clone();
+    let handle = tokio::spawn(task);
+    tokio::spawn(async move {
+        if let Err(e) = handle.await {
+            if e.is_panic() {
+                let message = format!("panicked: {}", panic_message(e.into_panic()));
+                reporter.report(Level::Error, class, message);
+            }
+        }
+    });
+}
+
+/// The message for `count` failures of `class`, the last saying `message`.
+fn render(class: &str, count: usize, message: &str) -> String {
+    match count {
+        1 => format!("⚠️ {} failed: {}", class, message),
+        n => format!("⚠️ {} failed {}× in the last hour: {}", class, n, message),
+    }
+}
+
+/// A class sent within the window, with the repeats since.
+struct Sent {
+    at: Instant,
+    repeats: usize,
+    last: String,
+}
+
+/// Decides which reports become messages: the first of a class is sent
+/// right away, its repeats within [`WINDOW`] once the window is over.
+pub struct Coalescer {
+    sent: HashMap<&'static str, Sent>,
+    clock: Clock,
+}
+
+impl Default for Coalescer {
+    fn default() -> Self {
+        Coalescer::new(Arc::new(Instant::now))
+    }
+}
+
+impl Coalescer {
+    pub fn new(clock: Clock) -> Self {
+        Coalescer {
+            sent: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// The message to send for the report now, `None` if it is a repeat.
+    pub fn record(&mut self, report: Report) -> Option<String> {
+        let now = (self.clock)();
+        if let Some(sent) = self.sent.get_mut(report.class) {
+            if now.saturating_duration_since(sent.at) < WINDOW {
+                sent.repeats += 1;
+                sent.last = report.message;
+                return None;
+            }
+        }
+        let repeats = self.sent.remove(report.class).map_or(0, |s| s.repeats);
+        let text = render(report.class, repeats + 1, &report.message);
+        self.sent.insert(
+            report.class,
+            Sent {
+                at: now,
+                repeats: 0,
+                last: report.message,
+            },
+        );
+        Some(text)
+    }
+
+    /// The messages for the repeats whose window is over, starting a new
+    /// window for each. Classes that stayed quiet are forgotten.
+    pub fn flush(&mut self) -> Vec<String> {
+        let now = (self.clock)();
+        let mut texts = Vec::new();
+        self.sent.retain(|class, sent| {
+            if now.saturating_duration_since(sent.at) < WINDOW {
+                return true;
+            }
+            if sent.repeats == 0 {
+                return false;
+            }
+            texts.push(render(class, sent.repeats, &sent.last));
+            sent.at = now;
+            sent.repeats = 0;
+            true
+        });
+        texts.sort();
+        texts
+    }
+}
+
+/// Sends the reports to the admin's private chat as the [`Coalescer`] lets
+/// them through, for as long as the bot runs. Without an admin they are
+/// only logged.
+pub async fn deliver(bot: Bot, admin_id: Option<i64>, mut receiver: UnboundedReceiver<Report>) {
+    let mut coalescer = Coalescer::default();
+    let mut interval = tokio::time::interval(FLUSH_EVERY);
+    loop {
+        let texts = tokio::select! {
+            report = receiver.recv() => match report {
+                Some(report) => coalescer.record(report).into_iter().collect(),
+                None => return,
+            },
+            _ = interval.tick() => coalescer.flush(),
+        };
+        let Some(admin_id) = admin_id else {
+            continue;
+        };
+        for text in texts {
+            // Reporting this would only fail again.
+            if let Err(e) = bot.send_message(ChatId(admin_id), escape_md(&text)).await {
+                info!("Sending the admin an error report failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn report(class: &'static str, message: &str) -> Report {
+        Report {
+            class,
+            message: message.to_string(),
+        }
+    }
+
+    /// A coalescer on a clock the test moves.
+    fn coalescer() -> (Coalescer, Arc<Mutex<Instant>>) {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        (
+            Coalescer::new(Arc::new(move || *clock.lock().unwrap())),
+            now,
+        )
+    }
+
+    fn wait(now: &Mutex<Instant>, minutes: u64) {
+        *now.lock().unwrap() += Duration::from_secs(minutes * 60);
+    }
+
+    #[test]
+    fn repeats_are_counted_into_one_message() {
+        let (mut coalescer, now) = coalescer();
+        assert_eq!(
+            Some("⚠️ backup failed: disk full".to_string()),
+            coalescer.record(report("backup", "disk full"))
+        );
+        for _ in 0..5 {
+            wait(&now, 10);
+            assert_eq!(None, coalescer.record(report("backup", "disk full")));
+        }
+        // Other classes have their own window.
+        assert_eq!(
+            Some("⚠️ feed update failed: chat not found".to_string()),
+            coalescer.record(report("feed update", "chat not found"))
+        );
+        assert!(coalescer.flush().is_empty());
+
+        wait(&now, 10);
+        assert_eq!(
+            vec!["⚠️ backup failed 5× in the last hour: disk full".to_string()],
+            coalescer.flush()
+        );
+        // A new window started with that message.
+        wait(&now, 30);
+        assert_eq!(None, coalescer.record(report("backup", "disk full")));
+        assert!(coalescer.flush().is_empty());
+        wait(&now, 30);
+        assert_eq!(
+            vec!["⚠️ backup failed: disk full".to_string()],
+            coalescer.flush()
+        );
+    }
+
+    #[test]
+    fn quiet_classes_are_sent_right_away_again() {
+        let (mut coalescer, now) = coalescer();
+        coalescer.record(report("maintenance", "database is locked"));
+        wait(&now, 61);
+        // Nothing repeated, nothing to send and the class is forgotten.
+        assert!(coalescer.flush().is_empty());
+        assert_eq!(
+            Some("⚠️ maintenance failed: disk I/O error".to_string()),
+            coalescer.record(report("maintenance", "disk I/O error"))
+        );
+        // Repeats not flushed yet go with the next message.
+        wait(&now, 5);
+        coalescer.record(report("maintenance", "disk I/O error"));
+        wait(&now, 60);
+        assert_eq!(
+            Some("⚠️ maintenance failed 2× in the last hour: out of memory".to_string()),
+            coalescer.record(report("maintenance", "out of memory"))
+        );
+    }
+
+    #[test]
+    fn only_severe_reports_are_queued() {
+        let (reporter, mut receiver) = ErrorReporter::new();
+        reporter.report(Level::Info, "notification", "bot was blocked by the user");
+        reporter.report(Level::Warn, "feed update", "chat not found");
+        assert_eq!(
+            report("feed update", "chat not found"),
+            receiver.try_recv().unwrap()
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn panics_are_reported() {
+        let (reporter, mut receiver) = ErrorReporter::new();
+        spawn(&reporter, "maintenance", async {
+            panic!("the database is gone");
+        });
+        assert_eq!(
+            Some(report("maintenance", "panicked: the database is gone")),
+            receiver.recv().await
+        );
+        spawn(&reporter, "maintenance", async {});
+        drop(reporter);
+        // The task ended fine, the reporter clone it held is dropped unused.
+        assert_eq!(None, receiver.recv().await);
+    }
+}
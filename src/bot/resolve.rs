@@ -0,0 +1,149 @@
+//! Consistent replies for user typed account and category names.
+
+use crate::model::{self, Account, Category, Model, Resolution};
+
+/// The resolved item, or the prompt telling the user what to type instead.
+pub type Picked<T> = Result<T, String>;
+
+/// `what` names the kind of item, singular and plural, as `("category", "categories")`.
+pub fn pick<T>(
+    (what, plural): (&str, &str),
+    query: &str,
+    resolution: Resolution<T>,
+    label: impl Fn(&T) -> String,
+) -> Picked<T> {
+    let list = |items: &[T]| items.iter().map(&label).collect::<Vec<_>>().join(", ");
+    match resolution {
+        Resolution::Exact(item) | Resolution::UniquePrefix(item) => Ok(item),
+        Resolution::Ambiguous(candidates) => Err(format!(
+            "'{}' matches several {}: {}. Be more specific.",
+            query,
+            plural,
+            list(&candidates)
+        )),
+        Resolution::NotFound(suggestions) if suggestions.is_empty() => {
+            Err(format!("No {} matches '{}'.", what, query))
+        }
+        Resolution::NotFound(suggestions) => Err(format!(
+            "No {} matches '{}'. Did you mean: {}?",
+            what,
+            query,
+            list(&suggestions)
+        )),
+    }
+}
+
+pub fn category(model: &Model, household: i64, name: &str) -> model::Result<Picked<Category>> {
+    Ok(pick(
+        ("category", "categories"),
+        name,
+        model.resolve_category(household, name)?,
+        |c| c.name.clone(),
+    ))
+}
+
+/// Every category of `names`, or the prompt for the first that doesn't
+/// resolve.
+pub fn categories(
+    model: &Model,
+    household: i64,
+    names: &[String],
+) -> model::Result<Picked<Vec<Category>>> {
+    let mut categories = Vec::new();
+    for name in names {
+        match category(model, household, name)? {
+            Ok(category) => categories.push(category),
+            Err(reason) => return Ok(Err(reason)),
+        }
+    }
+    Ok(Ok(categories))
+}
+
+pub fn account(model: &Model, household: i64, name: &str) -> model::Result<Picked<Account>> {
+    Ok(pick(
+        ("account", "accounts"),
+        name,
+        model.resolve_account(household, name)?,
+        |a| a.name.clone(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(s: &&str) -> String {
+        s.to_string()
+    }
+
+    #[test]
+    fn prompts() {
+        assert_eq!(
+            Ok("a"),
+            pick(
+                ("category", "categories"),
+                "a",
+                Resolution::Exact("a"),
+                label
+            )
+        );
+        assert_eq!(
+            Ok("abc"),
+            pick(
+                ("category", "categories"),
+                "ab",
+                Resolution::UniquePrefix("abc"),
+                label
+            )
+        );
+        assert_eq!(
+            Err(
+                "'eat' matches several categories: Eating out, Eating in. Be more specific."
+                    .to_string()
+            ),
+            pick(
+                ("category", "categories"),
+                "eat",
+                Resolution::Ambiguous(vec!["Eating out", "Eating in"]),
+                label
+            )
+        );
+        assert_eq!(
+            Err("No account matches 'bank'. Did you mean: Bunk?".to_string()),
+            pick(
+                ("account", "accounts"),
+                "bank",
+                Resolution::NotFound(vec!["Bunk"]),
+                label
+            )
+        );
+        assert_eq!(
+            Err("No account matches 'bank'.".to_string()),
+            pick(
+                ("account", "accounts"),
+                "bank",
+                Resolution::NotFound(vec![]),
+                label
+            )
+        );
+    }
+
+    #[test]
+    fn resolves_through_model() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(
+            "Groceries",
+            category(&model, 1, "gro").unwrap().unwrap().name
+        );
+        assert_eq!(
+            Err("No category matches 'grocries'. Did you mean: Groceries, Utilities, Entertainment?".to_string()),
+            category(&model, 1, "grocries").unwrap().map(|c| c.name)
+        );
+        assert_eq!(
+            "Family BYN",
+            account(&model, 1, "family").unwrap().unwrap().name
+        );
+    }
+}
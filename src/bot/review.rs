@@ -0,0 +1,64 @@
+//! `/review`: recent expenses that were likely logged in a hurry, each sent
+//! as its own message so that it can be edited in place.
+
+use super::markdown::escape_md;
+use super::{format, keyboards, settings, Bot, HandlerResult, SharedModel};
+use crate::config::Config;
+use chrono::{TimeDelta, Utc};
+use teloxide::prelude::*;
+
+/// How far back `/review` looks.
+const REVIEW_DAYS: i64 = 30;
+
+/// Expenses sent at most per `/review`, to keep the chat readable.
+const MAX_SHOWN: usize = 10;
+
+pub async fn handle_review(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    household: i64,
+    config: &Config,
+) -> HandlerResult {
+    let since = Utc::now() - TimeDelta::days(REVIEW_DAYS);
+    let chat_id = message.chat.id;
+    let (candidates, options) = {
+        let model = model.lock().unwrap();
+        (
+            model.find_review_candidates(household, since, &config.review_rules())?,
+            settings::render_options(&model, chat_id)?,
+        )
+    };
+
+    let header = match candidates.len() {
+        0 => format!("Nothing to review in the last {} days.", REVIEW_DAYS),
+        n if n > MAX_SHOWN => format!(
+            "{} expenses from the last {} days could use a look, here are the oldest {}:",
+            n, REVIEW_DAYS, MAX_SHOWN
+        ),
+        1 => format!(
+            "1 expense from the last {} days could use a look:",
+            REVIEW_DAYS
+        ),
+        n => format!(
+            "{} expenses from the last {} days could use a look:",
+            n, REVIEW_DAYS
+        ),
+    };
+    bot.send_message(chat_id, escape_md(&header)).await?;
+
+    for candidate in candidates.iter().take(MAX_SHOWN) {
+        let text = format!(
+            "{}\n{}",
+            format::render_expense(&candidate.expense, config.timezone, options),
+            format::render_review_reasons(&candidate.reasons)
+        );
+        bot.send_message(chat_id, text)
+            .reply_markup(keyboards::expense_keyboard(
+                candidate.expense.id,
+                candidate.expense.location,
+            ))
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! `/rule`: category rules of the household. A new draft whose comment
+//! matches one gets its category, marked "(rule)".
+
+use super::parse::{self, RULE_USAGE};
+use super::resolve;
+use super::SharedModel;
+use crate::model::{Error, Rule};
+
+fn describe(rule: &Rule) -> String {
+    format!("#{} {} → {}", rule.id, rule.pattern, rule.category)
+}
+
+pub fn handle_rule(model: &SharedModel, household: i64, args: &str) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    let args = args.trim();
+    let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    match action {
+        "" | "list" => {
+            let rules = model.rules(household)?;
+            if rules.is_empty() {
+                return Ok(format!("There are no rules yet. {}", RULE_USAGE));
+            }
+            let mut lines = vec!["Rules, the first match wins:".to_string()];
+            lines.extend(rules.iter().map(describe));
+            Ok(lines.join("\n"))
+        }
+        "add" => {
+            let (pattern, category) = match parse::parse_rule_add(args) {
+                Ok(parsed) => parsed,
+                Err(reason) => return Ok(reason),
+            };
+            let category = match resolve::category(&model, household, &category)? {
+                Ok(category) => category,
+                Err(reason) => return Ok(reason),
+            };
+            match model.add_rule(household, &pattern, category.id) {
+                Ok(id) => Ok(format!(
+                    "Added rule #{}: comments matching {} go to {}.",
+                    id, pattern, category.name
+                )),
+                Err(Error::Refused(reason)) => Ok(reason),
+                Err(e @ Error::TooLong { .. }) => Ok(e.to_string()),
+                Err(e) => Err(e),
+            }
+        }
+        "del" => match rest.trim_start_matches('#').parse::<i64>() {
+            Ok(id) if model.delete_rule(household, id)? => Ok(format!("Deleted rule #{}.", id)),
+            Ok(id) => Ok(format!("There is no rule #{}.", id)),
+            Err(_) => Ok(RULE_USAGE.to_string()),
+        },
+        "test" if !rest.is_empty() => Ok(match model.matching_rule(household, rest)? {
+            Some(rule) => format!(
+                "Rule #{} ({}) picks {}.",
+                rule.id, rule.pattern, rule.category
+            ),
+            None => "No rule matches, the draft keeps the first category.".to_string(),
+        }),
+        _ => Ok(RULE_USAGE.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn rule_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let rule = |args| handle_rule(&model, 1, args).unwrap();
+
+        assert_eq!(format!("There are no rules yet. {}", RULE_USAGE), rule(""));
+        assert_eq!(
+            "Added rule #1: comments matching uber|bolt|taxi go to Transportation.",
+            rule(r#"add "uber|bolt|taxi" -> transp"#)
+        );
+        assert_eq!(
+            "Added rule #2: comments matching bill go to Utilities.",
+            rule(r#"add "bill" -> Utilities"#)
+        );
+        assert!(rule(r#"add "taxi(" -> Utilities"#).starts_with("Invalid pattern: "));
+        assert!(rule(r#"add "dog" -> Pets"#).starts_with("No category matches 'Pets'."));
+        assert_eq!(
+            "Rules, the first match wins:\n#1 uber|bolt|taxi → Transportation\n#2 bill → Utilities",
+            rule("list")
+        );
+        assert_eq!(
+            "Rule #1 (uber|bolt|taxi) picks Transportation.",
+            rule("test Taxi bill")
+        );
+        assert_eq!("Rule #2 (bill) picks Utilities.", rule("test water bill"));
+        assert_eq!(
+            "No rule matches, the draft keeps the first category.",
+            rule("test groceries")
+        );
+        assert_eq!(RULE_USAGE, rule("test"));
+        assert_eq!("Deleted rule #1.", rule("del #1"));
+        assert_eq!("There is no rule #1.", rule("del 1"));
+        assert_eq!(RULE_USAGE, rule("del one"));
+        assert_eq!("Rule #2 (bill) picks Utilities.", rule("test Taxi bill"));
+    }
+}
@@ -0,0 +1,270 @@
+//! The one loop running periodic background work. Tasks register under a
+//! unique name with how often they run; when each last ran is stored, so a
+//! restart picks up where the last run left off rather than running
+//! everything again, and registering a name twice is refused rather than
+//! running a task twice as often.
+
+use super::reporter::{panic_message, ErrorReporter};
+use super::SharedModel;
+use crate::model::Error;
+use chrono::{DateTime, TimeDelta, Utc};
+use log::*;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// How often the scheduler looks for due tasks.
+const TICK: Duration = Duration::from_secs(60);
+
+type Run = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+type Handler = Box<dyn Fn(DateTime<Utc>) -> Run + Send + Sync>;
+
+struct Task {
+    name: &'static str,
+    every: TimeDelta,
+    handler: Handler,
+}
+
+pub struct Scheduler {
+    model: SharedModel,
+    reporter: ErrorReporter,
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    pub fn new(model: SharedModel, reporter: ErrorReporter) -> Scheduler {
+        Scheduler {
+            model,
+            reporter,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Adds a task to run every `every`, given the time it runs at. Its
+    /// failures are reported under `name`.
+    pub fn register<F, R>(
+        &mut self,
+        name: &'static str,
+        every: TimeDelta,
+        handler: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(DateTime<Utc>) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        if self.tasks.iter().any(|task| task.name == name) {
+            return Err(Error::Refused(format!(
+                "A task named {} is registered already.",
+                name
+            )));
+        }
+        self.tasks.push(Task {
+            name,
+            every,
+            handler: Box::new(move |now| Box::pin(handler(now))),
+        });
+        Ok(())
+    }
+
+    /// Runs the tasks due at `now` one after another, in the order they were
+    /// registered, and returns their names. A task that missed several runs
+    /// while the bot was down runs once; catching up on what it missed is up
+    /// to the task. Failing or panicking counts as having run, the task is
+    /// tried again when it is next due.
+    pub async fn run_due(&self, now: DateTime<Utc>) -> Vec<&'static str> {
+        let mut ran = Vec::new();
+        for task in &self.tasks {
+            let last = self.model.lock().unwrap().task_last_run(task.name);
+            match last {
+                Ok(Some(last)) if now - last < task.every => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    self.reporter.report(Level::Warn, task.name, e);
+                    continue;
+                }
+            }
+            debug!("Running {}", task.name);
+            // On a task of its own, so that a panic ends only this run.
+            match tokio::spawn((task.handler)(now)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => self.reporter.report(Level::Warn, task.name, e),
+                Err(e) if e.is_panic() => {
+                    let message = format!("panicked: {}", panic_message(e.into_panic()));
+                    self.reporter.report(Level::Error, task.name, message);
+                }
+                Err(e) => self.reporter.report(Level::Error, task.name, e),
+            }
+            if let Err(e) = self.model.lock().unwrap().set_task_last_run(task.name, now) {
+                self.reporter.report(Level::Warn, task.name, e);
+            }
+            ran.push(task.name);
+        }
+        ran
+    }
+
+    /// Runs the tasks as they fall due, for as long as the bot runs.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            self.run_due(Utc::now()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::sweep::{self, SWEEP_EVERY};
+    use crate::bot::SharedCoordinator;
+    use crate::model::Model;
+    use chrono_tz::Tz;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn shared() -> SharedModel {
+        let model = Model::new(true);
+        model.fill_test_data();
+        Arc::new(Mutex::new(model))
+    }
+
+    /// A task counting its runs, failing from the second on if `fails`.
+    fn counting(
+        scheduler: &mut Scheduler,
+        name: &'static str,
+        every: TimeDelta,
+        fails: bool,
+    ) -> Arc<AtomicUsize> {
+        let runs = Arc::new(AtomicUsize::default());
+        let counter = runs.clone();
+        scheduler
+            .register(name, every, move |_| {
+                let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    match fails && count > 1 {
+                        true => Err(Error::Refused("no luck".to_string())),
+                        false => Ok(()),
+                    }
+                }
+            })
+            .unwrap();
+        runs
+    }
+
+    #[tokio::test]
+    async fn tasks_run_when_due() {
+        let model = shared();
+        let (reporter, mut reports) = ErrorReporter::new();
+        let mut scheduler = Scheduler::new(model.clone(), reporter.clone());
+        let hourly = counting(&mut scheduler, "hourly", TimeDelta::hours(1), false);
+        let often = counting(&mut scheduler, "often", TimeDelta::minutes(5), true);
+
+        let start = at("2023-12-10T12:00:00Z");
+        assert_eq!(vec!["hourly", "often"], scheduler.run_due(start).await);
+        let minute = |n| start + TimeDelta::minutes(n);
+        assert!(scheduler.run_due(minute(1)).await.is_empty());
+        // Failing from its second run on, and run again when next due.
+        assert_eq!(vec!["often"], scheduler.run_due(minute(5)).await);
+        assert_eq!("often", reports.recv().await.unwrap().class);
+        assert!(scheduler.run_due(minute(9)).await.is_empty());
+        assert_eq!(vec!["often"], scheduler.run_due(minute(10)).await);
+        assert_eq!(vec!["hourly", "often"], scheduler.run_due(minute(60)).await);
+        assert_eq!(
+            (2, 4),
+            (hourly.load(Ordering::SeqCst), often.load(Ordering::SeqCst))
+        );
+
+        // Down for three days, then restarted: each task runs once, and the
+        // last runs carry over.
+        let mut restarted = Scheduler::new(model, reporter);
+        let hourly = counting(&mut restarted, "hourly", TimeDelta::hours(1), false);
+        let often = counting(&mut restarted, "often", TimeDelta::minutes(5), false);
+        assert!(restarted.run_due(minute(61)).await.is_empty());
+        let back = start + TimeDelta::days(3);
+        assert_eq!(vec!["hourly", "often"], restarted.run_due(back).await);
+        assert!(restarted
+            .run_due(back + TimeDelta::minutes(1))
+            .await
+            .is_empty());
+        assert_eq!(
+            (1, 1),
+            (hourly.load(Ordering::SeqCst), often.load(Ordering::SeqCst))
+        );
+    }
+
+    #[tokio::test]
+    async fn names_are_registered_once() {
+        let (reporter, _reports) = ErrorReporter::new();
+        let mut scheduler = Scheduler::new(shared(), reporter);
+        counting(&mut scheduler, "callback sweep", TimeDelta::hours(1), false);
+        let twice = scheduler.register("callback sweep", TimeDelta::minutes(1), |_| async {
+            Ok(())
+        });
+        assert!(matches!(twice, Err(Error::Refused(_))));
+        assert_eq!(1, scheduler.tasks.len());
+    }
+
+    #[tokio::test]
+    async fn panics_end_only_their_run() {
+        let (reporter, mut reports) = ErrorReporter::new();
+        let mut scheduler = Scheduler::new(shared(), reporter);
+        scheduler
+            .register("broken", TimeDelta::hours(1), |_| async {
+                panic!("out of coffee");
+            })
+            .unwrap();
+        let after = counting(&mut scheduler, "after", TimeDelta::hours(1), false);
+
+        let now = at("2023-12-10T12:00:00Z");
+        assert_eq!(vec!["broken", "after"], scheduler.run_due(now).await);
+        let report = reports.recv().await.unwrap();
+        assert_eq!(
+            ("broken", "panicked: out of coffee"),
+            (report.class, report.message.as_str())
+        );
+        assert_eq!(1, after.load(Ordering::SeqCst));
+        assert!(scheduler
+            .run_due(now + TimeDelta::minutes(30))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn maintenance_waits_for_the_night() {
+        let model = shared();
+        let (reporter, _reports) = ErrorReporter::new();
+        let mut scheduler = Scheduler::new(model.clone(), reporter.clone());
+        let coordinator = SharedCoordinator::default();
+        scheduler
+            .register("maintenance", SWEEP_EVERY, {
+                let model = model.clone();
+                move |now| {
+                    sweep::maintain(
+                        model.clone(),
+                        coordinator.clone(),
+                        (now, Tz::UTC),
+                        Vec::new(),
+                        reporter.clone(),
+                    )
+                }
+            })
+            .unwrap();
+        let last = || model.lock().unwrap().last_maintenance().unwrap();
+
+        // Checked hourly, never maintained yet, but not in the day.
+        let noon = at("2023-12-10T12:00:00Z");
+        assert_eq!(vec!["maintenance"], scheduler.run_due(noon).await);
+        assert_eq!(None, last());
+        assert!(scheduler
+            .run_due(noon + TimeDelta::minutes(30))
+            .await
+            .is_empty());
+        let night = noon + TimeDelta::hours(11);
+        assert_eq!(vec!["maintenance"], scheduler.run_due(night).await);
+        assert!(last().is_some());
+    }
+}
@@ -0,0 +1,502 @@
+//! `/settings`: the user's preferences as buttons. Pressing one changes the
+//! setting and re-renders the same message. `/settings privacy`, `confirm`,
+//! `currency`, `quiet` and `timezone` are settings of the chat instead, and
+//! the week and month starts are settings of the household.
+
+use super::format::RenderOptions;
+use super::{format, keyboards, resolve, Bot, HandlerResult, SharedModel};
+use crate::model::{
+    Calendar, ConfirmMode, Error, Locale, Model, QuietHours, Setting, Settings, User,
+};
+use crate::time::Lang;
+use chrono::Weekday;
+use chrono_tz::Tz;
+use teloxide::prelude::*;
+use teloxide::types::CallbackQuery;
+
+/// How messages to the chat are rendered. Groups with privacy on mask
+/// amounts, private chats never do. Private chats get dates in the
+/// Telegram language of their user, groups, read by several, in English.
+pub fn render_options(model: &Model, chat_id: ChatId) -> Result<RenderOptions, Error> {
+    let lang = match chat_id.is_user() {
+        true => Lang::from_language(model.user_language(chat_id.0)?.as_deref()),
+        false => Lang::En,
+    };
+    Ok(RenderOptions {
+        mask_amounts: !chat_id.is_user() && model.chat_privacy(chat_id.0)?,
+        confirm: model.chat_confirm_mode(chat_id.0)?,
+        lang,
+    })
+}
+
+/// Stores `setting` for the user and returns all their settings.
+fn change(model: &Model, user_id: i64, setting: Setting) -> Result<Settings, Error> {
+    model.set_setting(user_id, setting)?;
+    model.get_settings(user_id)
+}
+
+/// How `user` writes amounts: their choice, or else their Telegram language.
+pub fn locale(model: &SharedModel, user: &teloxide::types::User) -> Result<Locale, Error> {
+    let settings = model.lock().unwrap().get_settings(user.id.0 as i64)?;
+    Ok(settings
+        .number_format
+        .unwrap_or_else(|| Locale::from_language(user.language_code.as_deref())))
+}
+
+/// `/settings privacy on|off`, in plain text. Turning it on binds the chat
+/// to the household of `user`, like the feed.
+pub fn privacy(model: &Model, user: &User, chat_id: ChatId, on: bool) -> Result<String, Error> {
+    if chat_id.is_user() {
+        return Ok("Privacy is for groups, private chats always show amounts.".to_string());
+    }
+    model.set_chat_privacy(chat_id.0, user.household, on)?;
+    Ok(match on {
+        true => "🔒 Confirmations and the feed in this chat hide amounts now.",
+        false => "Confirmations and the feed in this chat show amounts again.",
+    }
+    .to_string())
+}
+
+/// `/settings confirm full|compact|silent`, in plain text. Binds the chat
+/// to the household of `user`, like privacy.
+pub fn confirm(
+    model: &Model,
+    user: &User,
+    chat_id: ChatId,
+    mode: ConfirmMode,
+) -> Result<String, Error> {
+    model.set_chat_confirm_mode(chat_id.0, user.household, mode)?;
+    Ok(match mode {
+        ConfirmMode::Full => {
+            "Commits in this chat are confirmed in full again, with the month to date and daily limits."
+        }
+        ConfirmMode::Compact => "Commits in this chat are confirmed on a single line now.",
+        ConfirmMode::Silent => {
+            "Commits in this chat are only confirmed with a ✅ now. Notifications still go out privately."
+        }
+    }
+    .to_string())
+}
+
+/// `/settings currency <currency>|none`, in plain text: which currency
+/// bare amounts typed in the chat are in, and so which account drafts start
+/// on. Binds the chat to the household of `user`, like privacy.
+pub fn currency(
+    model: &Model,
+    user: &User,
+    chat_id: ChatId,
+    currency: Option<&str>,
+) -> Result<String, Error> {
+    let Some(currency) = currency else {
+        model.set_chat_assumed_currency(chat_id.0, user.household, None)?;
+        return Ok("Drafts in this chat start on the account you last used again.".to_string());
+    };
+    if model.currency_exponent(currency)?.is_none() {
+        return Ok(format!("Unknown currency '{}'.", currency));
+    }
+    model.set_chat_assumed_currency(chat_id.0, user.household, Some(currency))?;
+    let accounts = model.accounts(user.household)?;
+    Ok(match accounts.iter().find(|a| a.currency == currency) {
+        Some(account) => format!(
+            "Amounts typed in this chat are {} now, drafts start on {}.",
+            currency, account.name
+        ),
+        None => format!(
+            "Amounts typed in this chat are {} now, but there is no {} account: drafts flag it until you pick one.",
+            currency, currency
+        ),
+    })
+}
+
+/// `/settings quiet <from>-<to>|off`, in plain text: the hours scheduled
+/// messages to the chat, like subscribed reports, wait out. Binds the chat
+/// to the household of `user`, like privacy.
+pub fn quiet(
+    model: &Model,
+    user: &User,
+    chat_id: ChatId,
+    hours: QuietHours,
+) -> Result<String, Error> {
+    model.set_chat_quiet_hours(chat_id.0, user.household, hours)?;
+    Ok(match hours.is_off() {
+        true => "Scheduled messages come to this chat at any hour now.".to_string(),
+        false => format!(
+            "Scheduled messages to this chat wait from {:02}:00 to {:02}:00 now.",
+            hours.start, hours.end
+        ),
+    })
+}
+
+/// `/settings timezone <zone>|none`, in plain text: the time zone the quiet
+/// hours of the chat are in, and in a private chat subscribed reports.
+/// Binds the chat to the household of `user`, like privacy.
+pub fn time_zone(
+    model: &Model,
+    user: &User,
+    chat_id: ChatId,
+    zone: Option<&str>,
+) -> Result<String, Error> {
+    let Some(zone) = zone else {
+        model.set_chat_time_zone(chat_id.0, user.household, None)?;
+        return Ok("This chat is on the time of the bot again.".to_string());
+    };
+    let Ok(tz) = zone.parse::<Tz>() else {
+        return Ok(format!(
+            "Unknown time zone '{}', try one like Europe/Berlin.",
+            zone
+        ));
+    };
+    model.set_chat_time_zone(chat_id.0, user.household, Some(tz))?;
+    Ok(format!("This chat is on {} time now.", tz.name()))
+}
+
+/// `/settings mtd on|off`, in plain text. A setting of the user, like the
+/// month-to-date button of `/settings`.
+pub fn month_to_date(model: &Model, user_id: i64, on: bool) -> Result<String, Error> {
+    let current = change(model, user_id, Setting::MonthToDate(on))?;
+    let on_off = if current.month_to_date { "on" } else { "off" };
+    Ok(format!("Month-to-date total after saving: {}.", on_off))
+}
+
+/// `/settings round on|off`, in plain text. A setting of the user, like
+/// the buttons of `/settings`.
+pub fn round(model: &Model, user_id: i64, on: bool) -> Result<String, Error> {
+    change(model, user_id, Setting::RoundTotals(on))?;
+    Ok(match on {
+        true => "Your reports show amounts rounded to whole units now.",
+        false => "Your reports show exact amounts again.",
+    }
+    .to_string())
+}
+
+/// `/settings report-exclude`, in plain text: the categories the user's
+/// reports leave out from now on, replacing those left out before.
+pub fn report_exclude(model: &Model, user: &User, names: &[String]) -> Result<String, Error> {
+    let categories = match resolve::categories(model, user.household, names)? {
+        Ok(categories) => categories,
+        Err(reason) => return Ok(reason),
+    };
+    let ids: Vec<i64> = categories.iter().map(|c| c.id).collect();
+    model.set_report_exclusions(user.telegram_id, &ids)?;
+    if categories.is_empty() {
+        return Ok("Your reports include every category again.".to_string());
+    }
+    let names: Vec<&str> = categories.iter().map(|c| c.name.as_str()).collect();
+    Ok(format!(
+        "Your reports leave out {} now. /report exclude none shows everything once.",
+        names.join(", ")
+    ))
+}
+
+/// `/settings week|month-start`, in plain text. `change` makes the new
+/// calendar from the household's current one.
+pub fn calendar(
+    model: &Model,
+    user: &User,
+    change: impl FnOnce(Calendar) -> Calendar,
+) -> Result<String, Error> {
+    let calendar = change(model.calendar(user.household)?);
+    match model.set_calendar(user.household, calendar) {
+        Ok(()) => {}
+        Err(Error::Refused(reason)) => return Ok(reason),
+        Err(e) => return Err(e),
+    }
+    let week_start = match calendar.week_start {
+        Weekday::Sun => "Sunday",
+        _ => "Monday",
+    };
+    Ok(format!(
+        "Weeks start on {} and months on day {} now, for reports and budgets.",
+        week_start, calendar.month_start
+    ))
+}
+
+pub async fn handle_settings(
+    bot: &Bot,
+    message: &Message,
+    model: &SharedModel,
+    user_id: i64,
+) -> HandlerResult {
+    let settings = model.lock().unwrap().get_settings(user_id)?;
+    bot.send_message(message.chat.id, format::render_settings(&settings))
+        .reply_markup(keyboards::settings_keyboard(&settings))
+        .await?;
+    Ok(())
+}
+
+/// A settings button. Everyone changes their own settings, whoever sent
+/// the message.
+pub async fn handle_change(
+    bot: &Bot,
+    q: &CallbackQuery,
+    model: &SharedModel,
+    setting: Setting,
+) -> HandlerResult {
+    let Some(message) = q.regular_message() else {
+        bot.answer_callback_query(q.id.clone()).await?;
+        return Ok(());
+    };
+    let settings = change(&model.lock().unwrap(), q.from.id.0 as i64, setting)?;
+    bot.edit_message_text(
+        message.chat.id,
+        message.id,
+        format::render_settings(&settings),
+    )
+    .reply_markup(keyboards::settings_keyboard(&settings))
+    .await?;
+    bot.answer_callback_query(q.id.clone())
+        .text("Saved")
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::callback::CallbackData;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    /// The settings every button of the keyboard leads to.
+    fn presses(settings: &Settings) -> Vec<Setting> {
+        keyboards::settings_keyboard(settings)
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .map(|button| match &button.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => match CallbackData::parse(data) {
+                    Some(CallbackData::ChangeSetting(setting)) => setting,
+                    other => panic!("unexpected button {:?}", other),
+                },
+                other => panic!("unexpected button {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn month_to_date_toggle_roundtrip() {
+        let model = Model::new(true);
+        let before = model.get_settings(1).unwrap();
+        assert!(render(&before).contains("after saving: on"));
+
+        let toggle = presses(&before)[0];
+        assert_eq!(Setting::MonthToDate(false), toggle);
+        let after = change(&model, 1, toggle).unwrap();
+        assert!(!after.month_to_date);
+        assert!(render(&after).contains("after saving: off"));
+
+        // The re-rendered keyboard toggles back.
+        let toggle = presses(&after)[0];
+        assert_eq!(Setting::MonthToDate(true), toggle);
+        assert_eq!(before, change(&model, 1, toggle).unwrap());
+    }
+
+    #[test]
+    fn number_format_buttons_roundtrip() {
+        let model = Model::new(true);
+        let formats: Vec<Setting> = presses(&Settings::default())
+            .into_iter()
+            .filter(|s| matches!(s, Setting::NumberFormat(_)))
+            .collect();
+        assert_eq!(3, formats.len());
+        // Ends on the first button, back to automatic.
+        for setting in formats.iter().rev().copied() {
+            let Setting::NumberFormat(locale) = setting else {
+                unreachable!()
+            };
+            let after = change(&model, 1, setting).unwrap();
+            assert_eq!(locale, after.number_format);
+            assert!(presses(&after).contains(&setting));
+        }
+        assert_eq!(None, model.get_settings(1).unwrap().number_format);
+    }
+
+    fn render(settings: &Settings) -> String {
+        format::render_settings(settings)
+    }
+
+    #[test]
+    fn currency_of_the_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.add_currency("GBP").unwrap();
+        let admin = model.get_user(1001).unwrap().unwrap();
+        let group = ChatId(-100);
+        let currency = |code| currency(&model, &admin, group, code).unwrap();
+
+        assert_eq!("Unknown currency 'XYZ'.", currency(Some("XYZ")));
+        assert_eq!(None, model.chat_assumed_currency(group.0).unwrap());
+        assert_eq!(
+            "Amounts typed in this chat are USD now, drafts start on Hanna Daily.",
+            currency(Some("USD"))
+        );
+        assert_eq!(
+            "Amounts typed in this chat are GBP now, but there is no GBP account: drafts flag it until you pick one.",
+            currency(Some("GBP"))
+        );
+        assert_eq!(
+            (Some("GBP".to_string()), Some(1)),
+            (
+                model.chat_assumed_currency(group.0).unwrap(),
+                model.chat_household(group.0).unwrap()
+            )
+        );
+        assert_eq!(
+            "Drafts in this chat start on the account you last used again.",
+            currency(None)
+        );
+        assert_eq!(None, model.chat_assumed_currency(group.0).unwrap());
+    }
+
+    #[test]
+    fn quiet_hours_and_time_zone_of_the_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let admin = model.get_user(1001).unwrap().unwrap();
+        let group = ChatId(-100);
+
+        assert_eq!(
+            "Scheduled messages to this chat wait from 23:00 to 07:00 now.",
+            quiet(&model, &admin, group, QuietHours { start: 23, end: 7 }).unwrap()
+        );
+        assert_eq!(
+            "Scheduled messages come to this chat at any hour now.",
+            quiet(&model, &admin, group, QuietHours { start: 0, end: 0 }).unwrap()
+        );
+        assert_eq!(Some(1), model.chat_household(group.0).unwrap());
+
+        assert_eq!(
+            "Unknown time zone 'Mars/Olympus', try one like Europe/Berlin.",
+            time_zone(&model, &admin, group, Some("Mars/Olympus")).unwrap()
+        );
+        assert_eq!(None, model.chat_time_zone(group.0).unwrap());
+        assert_eq!(
+            "This chat is on Europe/Berlin time now.",
+            time_zone(&model, &admin, group, Some("Europe/Berlin")).unwrap()
+        );
+        assert_eq!(
+            Some("Europe/Berlin".parse().unwrap()),
+            model.chat_time_zone(group.0).unwrap()
+        );
+        assert_eq!(
+            "This chat is on the time of the bot again.",
+            time_zone(&model, &admin, group, None).unwrap()
+        );
+        assert_eq!(None, model.chat_time_zone(group.0).unwrap());
+    }
+
+    #[test]
+    fn privacy_masks_groups_only() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let admin = model.get_user(1001).unwrap().unwrap();
+        let (group, private) = (ChatId(-100), ChatId(1001));
+        assert_eq!(RenderOptions::FULL, render_options(&model, group).unwrap());
+
+        privacy(&model, &admin, group, true).unwrap();
+        assert!(render_options(&model, group).unwrap().mask_amounts);
+        assert_eq!(
+            "Privacy is for groups, private chats always show amounts.",
+            privacy(&model, &admin, private, true).unwrap()
+        );
+        assert_eq!(
+            RenderOptions::FULL,
+            render_options(&model, private).unwrap()
+        );
+
+        privacy(&model, &admin, group, false).unwrap();
+        assert_eq!(RenderOptions::FULL, render_options(&model, group).unwrap());
+    }
+
+    #[test]
+    fn confirm_mode_of_the_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let hanna = model.get_user(1002).unwrap().unwrap();
+        let (group, private) = (ChatId(-100), ChatId(1002));
+        assert_eq!(
+            "Commits in this chat are only confirmed with a ✅ now. Notifications still go out privately.",
+            confirm(&model, &hanna, group, ConfirmMode::Silent).unwrap()
+        );
+        confirm(&model, &hanna, private, ConfirmMode::Compact).unwrap();
+        let options = render_options(&model, group).unwrap();
+        assert_eq!(
+            (ConfirmMode::Silent, false),
+            (options.confirm, options.mask_amounts)
+        );
+        assert_eq!(
+            ConfirmMode::Compact,
+            render_options(&model, private).unwrap().confirm
+        );
+
+        confirm(&model, &hanna, group, ConfirmMode::Full).unwrap();
+        assert_eq!(RenderOptions::FULL, render_options(&model, group).unwrap());
+    }
+
+    #[test]
+    fn dates_in_the_language_of_private_chats() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .touch_user(1002, ("hanna_bot", "Hanna"), Some("ru-RU"))
+            .unwrap();
+        assert_eq!(Lang::Ru, render_options(&model, ChatId(1002)).unwrap().lang);
+        assert_eq!(Lang::En, render_options(&model, ChatId(-100)).unwrap().lang);
+        assert_eq!(Lang::En, render_options(&model, ChatId(1001)).unwrap().lang);
+    }
+
+    #[test]
+    fn report_exclusions_of_the_user() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let hanna = model.get_user(1002).unwrap().unwrap();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            "Your reports leave out Utilities, Groceries now. /report exclude none shows everything once.",
+            report_exclude(&model, &hanna, &names(&["util", "groceries"])).unwrap()
+        );
+        assert_eq!(vec![4, 1], model.report_exclusions(1002).unwrap());
+        // A typo leaves them as they were.
+        assert!(report_exclude(&model, &hanna, &names(&["rent"]))
+            .unwrap()
+            .starts_with("No category matches 'rent'"));
+        assert_eq!(vec![4, 1], model.report_exclusions(1002).unwrap());
+        assert_eq!(
+            "Your reports include every category again.",
+            report_exclude(&model, &hanna, &[]).unwrap()
+        );
+        assert!(model.report_exclusions(1002).unwrap().is_empty());
+    }
+
+    #[test]
+    fn calendar_of_the_household() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let admin = model.get_user(1001).unwrap().unwrap();
+        assert_eq!(
+            "Weeks start on Monday and months on day 25 now, for reports and budgets.",
+            calendar(&model, &admin, |c| Calendar {
+                month_start: 25,
+                ..c
+            })
+            .unwrap()
+        );
+        assert_eq!(
+            "Weeks start on Sunday and months on day 25 now, for reports and budgets.",
+            calendar(&model, &admin, |c| Calendar {
+                week_start: Weekday::Sun,
+                ..c
+            })
+            .unwrap()
+        );
+        assert_eq!(
+            "Months can start on a day from 1 to 28, which every month has.",
+            calendar(&model, &admin, |c| Calendar {
+                month_start: 31,
+                ..c
+            })
+            .unwrap()
+        );
+        assert_eq!(25, model.calendar(1).unwrap().month_start);
+    }
+}
@@ -0,0 +1,558 @@
+//! `/setup`: a few questions that create the first account and categories,
+//! offered on `/start` while the household has neither. The answers are
+//! stored as they come, so the wizard picks up where it was after a restart.
+
+use super::callback::{self, CallbackData, SetupAction};
+use super::draft::DraftKey;
+use super::markdown::escape_md;
+use super::{Bot, HandlerResult, SharedModel};
+use crate::model::{
+    preset, Error, Model, Preset, Role, Setup, SetupStep, User, MAX_NAME_LEN, PRESETS,
+};
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+
+pub const SETUP_USAGE: &str = "Usage: /setup [cancel]";
+
+/// A message of the wizard, in plain text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub keyboard: Option<InlineKeyboardMarkup>,
+}
+
+impl Reply {
+    fn text(text: impl Into<String>) -> Reply {
+        Reply {
+            text: text.into(),
+            keyboard: None,
+        }
+    }
+
+    pub async fn send(self, bot: &Bot, chat_id: ChatId) -> HandlerResult {
+        let request = bot.send_message(chat_id, escape_md(&self.text));
+        match self.keyboard {
+            Some(keyboard) => request.reply_markup(keyboard).await?,
+            None => request.await?,
+        };
+        Ok(())
+    }
+}
+
+fn button(text: impl Into<String>, action: SetupAction) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text, CallbackData::Setup(action).encode())
+}
+
+/// The presets to pick from, each with its categories.
+fn presets_question() -> Reply {
+    let mut text = "Which categories to start with? You can leave some out next.".to_string();
+    for preset in &PRESETS {
+        let names: Vec<&str> = preset.categories.iter().map(|c| c.name).collect();
+        text.push_str(&format!("\n{}: {}", preset.name, names.join(", ")));
+    }
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = PRESETS
+        .iter()
+        .enumerate()
+        .map(|(i, preset)| {
+            let label = format!("{} ({})", preset.name, preset.categories.len());
+            vec![button(label, SetupAction::Preset(i))]
+        })
+        .collect();
+    rows.push(vec![button("Skip", SetupAction::Skip)]);
+    Reply {
+        text,
+        keyboard: Some(InlineKeyboardMarkup::new(rows)),
+    }
+}
+
+/// The categories of the preset as checkboxes, those in `left_out`
+/// unchecked.
+fn customize_question(preset: &Preset, left_out: &[String]) -> Reply {
+    let mut text = format!(
+        "{}: tap a category to leave it out or back in.",
+        preset.name
+    );
+    if preset.categories.iter().any(|c| c.require_comment) {
+        text.push_str(" Expenses in those marked ✍️ need a comment.");
+    }
+    let chosen = preset.chosen(left_out);
+    let boxes: Vec<InlineKeyboardButton> = preset
+        .categories
+        .iter()
+        .enumerate()
+        .map(|(i, category)| {
+            let check = if chosen.contains(category) {
+                "✅"
+            } else {
+                "⬜"
+            };
+            let mut label = format!("{} {} {}", check, category.icon, category.name);
+            if category.require_comment {
+                label.push_str(" ✍️");
+            }
+            button(label, SetupAction::Toggle(i))
+        })
+        .collect();
+    let mut rows: Vec<Vec<InlineKeyboardButton>> =
+        boxes.chunks(2).map(|row| row.to_vec()).collect();
+    rows.push(vec![
+        button("« Back", SetupAction::Presets),
+        button(format!("✅ Add {}", chosen.len()), SetupAction::Create),
+    ]);
+    Reply {
+        text,
+        keyboard: Some(InlineKeyboardMarkup::new(rows)),
+    }
+}
+
+/// The question `setup` waits for an answer to.
+fn question(setup: &Setup) -> Reply {
+    match setup.step {
+        SetupStep::AccountName => Reply::text(
+            "Let's set things up. Name the first account, like Cash or Main card. /setup cancel stops.",
+        ),
+        SetupStep::AccountCurrency => Reply::text(format!(
+            "Which currency is {} in? Send its code, like EUR.",
+            setup.account_name.as_deref().unwrap_or("the account")
+        )),
+        SetupStep::Categories => presets_question(),
+        SetupStep::Customize => match setup.preset.as_deref().and_then(preset) {
+            Some(preset) => customize_question(preset, &setup.left_out),
+            None => presets_question(),
+        },
+    }
+}
+
+fn currency_code(text: &str) -> Option<String> {
+    let code = text.trim();
+    (code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()))
+        .then(|| code.to_ascii_uppercase())
+}
+
+/// Starts the wizard over for `user` in the chat.
+fn start(model: &Model, chat_id: i64, user: &User) -> Result<Reply, Error> {
+    let setup = Setup::new(user.telegram_id, user.household);
+    model.save_setup(chat_id, &setup)?;
+    Ok(question(&setup))
+}
+
+/// `/setup`: starts the wizard, or over if one is in progress.
+pub fn handle_setup(model: &Model, chat_id: i64, user: &User, args: &str) -> Result<Reply, Error> {
+    match args.trim() {
+        "" => start(model, chat_id, user),
+        "cancel" => Ok(Reply::text(if model.cancel_setup(chat_id)? {
+            "Setup cancelled, nothing was created."
+        } else {
+            "There is no setup in progress."
+        })),
+        _ => Ok(Reply::text(SETUP_USAGE)),
+    }
+}
+
+/// After `/start`: the question an admin's wizard is at, starting one if
+/// the household has nothing to log expenses with yet.
+pub fn on_start(model: &Model, chat_id: i64, user: &User) -> Result<Option<Reply>, Error> {
+    if !user.role.allows(Role::Admin) {
+        return Ok(None);
+    }
+    match model.setup(chat_id)? {
+        Some(setup) if setup.user_id == user.telegram_id => Ok(Some(question(&setup))),
+        Some(_) => Ok(None),
+        None if model.is_unconfigured(user.household)? => start(model, chat_id, user).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// A typed message: the answer to the wizard's question if `user_id` has
+/// one waiting in the chat, `None` otherwise.
+pub fn answer(
+    model: &Model,
+    chat_id: i64,
+    user_id: i64,
+    text: &str,
+) -> Result<Option<Reply>, Error> {
+    let Some(mut setup) = model.setup(chat_id)?.filter(|s| s.user_id == user_id) else {
+        return Ok(None);
+    };
+    let text = text.trim();
+    match setup.step {
+        SetupStep::AccountName => {
+            if text.is_empty() || text.chars().count() > MAX_NAME_LEN {
+                return Ok(Some(Reply::text(format!(
+                    "Send a name of at most {} characters.",
+                    MAX_NAME_LEN
+                ))));
+            }
+            setup.account_name = Some(text.to_string());
+            setup.step = SetupStep::AccountCurrency;
+        }
+        SetupStep::AccountCurrency => match currency_code(text) {
+            Some(code) => {
+                setup.account_currency = Some(code);
+                setup.step = SetupStep::Categories;
+            }
+            None => return Ok(Some(not_a_currency(text))),
+        },
+        SetupStep::Categories | SetupStep::Customize => {
+            let mut reply = question(&setup);
+            reply.text = format!("Tap a button: {}", reply.text);
+            return Ok(Some(reply));
+        }
+    }
+    model.save_setup(chat_id, &setup)?;
+    Ok(Some(question(&setup)))
+}
+
+fn not_a_currency(text: &str) -> Reply {
+    Reply::text(format!(
+        "'{}' is not a currency code, send three letters like EUR.",
+        text
+    ))
+}
+
+/// The wizard of the chat if it is `user_id`'s and at the categories,
+/// otherwise why a button of it does nothing.
+fn at_categories(model: &Model, chat_id: i64, user_id: i64) -> Result<Result<Setup, Reply>, Error> {
+    Ok(match model.setup(chat_id)? {
+        Some(setup) if setup.user_id != user_id => {
+            Err(Reply::text("This setup belongs to someone else."))
+        }
+        Some(setup) if matches!(setup.step, SetupStep::Categories | SetupStep::Customize) => {
+            Ok(setup)
+        }
+        _ => Err(Reply::text("There is no setup waiting for this.")),
+    })
+}
+
+/// The buttons picking a preset and its categories: the question to show
+/// instead, or, without a keyboard, why nothing changes.
+pub fn customize(
+    model: &Model,
+    chat_id: i64,
+    user_id: i64,
+    action: SetupAction,
+) -> Result<Reply, Error> {
+    let mut setup = match at_categories(model, chat_id, user_id)? {
+        Ok(setup) => setup,
+        Err(reply) => return Ok(reply),
+    };
+    match action {
+        SetupAction::Preset(i) => {
+            let Some(preset) = PRESETS.get(i) else {
+                return Ok(Reply::text(callback::NO_OPTION));
+            };
+            setup.step = SetupStep::Customize;
+            setup.preset = Some(preset.key.to_string());
+            setup.left_out.clear();
+        }
+        SetupAction::Toggle(i) => {
+            let category = setup
+                .preset
+                .as_deref()
+                .and_then(preset)
+                .filter(|_| setup.step == SetupStep::Customize)
+                .and_then(|preset| preset.categories.get(i));
+            let Some(category) = category else {
+                return Ok(Reply::text("Pick a set of categories first."));
+            };
+            let name = category.name.to_string();
+            match setup.left_out.iter().position(|n| *n == name) {
+                Some(at) => {
+                    setup.left_out.remove(at);
+                }
+                None => setup.left_out.push(name),
+            }
+        }
+        SetupAction::Presets | SetupAction::Create | SetupAction::Skip => {
+            setup.step = SetupStep::Categories;
+            setup.preset = None;
+            setup.left_out.clear();
+        }
+    }
+    model.save_setup(chat_id, &setup)?;
+    Ok(question(&setup))
+}
+
+/// The buttons that create everything, with the categories chosen or
+/// without any.
+pub fn choose_categories(
+    model: &Model,
+    chat_id: i64,
+    user_id: i64,
+    accept: bool,
+) -> Result<Reply, Error> {
+    let setup = match at_categories(model, chat_id, user_id)? {
+        Ok(setup) => setup,
+        Err(reply) => return Ok(reply),
+    };
+    let categories = match accept {
+        // Without a preset picked, the button of a wizard started before
+        // there were presets: the minimal one.
+        true => setup
+            .preset
+            .as_deref()
+            .and_then(preset)
+            .unwrap_or(&PRESETS[0])
+            .chosen(&setup.left_out),
+        false => Vec::new(),
+    };
+    let result = model.finish_setup(chat_id, &setup, &categories)?;
+    let account = model
+        .get_account(setup.household, result.account_id)?
+        .ok_or_else(|| Error::NotFound(format!("account {}", result.account_id)))?;
+    let mut text = format!("All set: {} in {}", account.name, account.currency);
+    if !result.categories.is_empty() {
+        text.push_str(&format!(
+            " and the categories {}",
+            result.categories.join(", ")
+        ));
+    }
+    text.push('.');
+    if model.categories(setup.household)?.is_empty() {
+        text.push_str(" There are no categories yet, /setup offers them again.");
+    } else {
+        text.push_str(" Send an amount to log an expense.");
+    }
+    Ok(Reply::text(text))
+}
+
+/// A button of the last questions: picking and customizing a preset edits
+/// the question in place, creating removes the buttons and answers below.
+pub async fn handle_button(
+    bot: &Bot,
+    q: &CallbackQuery,
+    model: &SharedModel,
+    key: DraftKey,
+    user_id: i64,
+    action: SetupAction,
+) -> HandlerResult {
+    let chat_id = key.chat_id;
+    if let SetupAction::Create | SetupAction::Skip = action {
+        let accept = action == SetupAction::Create;
+        let reply = choose_categories(&model.lock().unwrap(), chat_id.0, user_id, accept)?;
+        bot.edit_message_reply_markup(chat_id, key.message_id)
+            .await?;
+        reply.send(bot, chat_id).await?;
+        bot.answer_callback_query(q.id.clone()).await?;
+        return Ok(());
+    }
+    let reply = customize(&model.lock().unwrap(), chat_id.0, user_id, action)?;
+    match reply.keyboard {
+        Some(keyboard) => {
+            bot.edit_message_text(chat_id, key.message_id, escape_md(&reply.text))
+                .reply_markup(keyboard)
+                .await?;
+            bot.answer_callback_query(q.id.clone()).await?;
+        }
+        None => {
+            bot.answer_callback_query(q.id.clone())
+                .text(reply.text)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    fn admin() -> User {
+        User {
+            telegram_id: 1,
+            telegram_name: "owner".to_string(),
+            display_name: "Owner".to_string(),
+            role: Role::Admin,
+            household: 1,
+        }
+    }
+
+    #[test]
+    fn wizard_end_to_end() {
+        let model = Model::new(true);
+        let admin = admin();
+        let chat = -10;
+        let say = |text| answer(&model, chat, 1, text).unwrap().unwrap().text;
+
+        // Members aren't asked, nothing happens without a wizard.
+        let member = User {
+            role: Role::Member,
+            ..admin.clone()
+        };
+        assert_eq!(None, on_start(&model, chat, &member).unwrap());
+        assert_eq!(None, answer(&model, chat, 1, "Cash").unwrap());
+
+        let first = on_start(&model, chat, &admin).unwrap().unwrap();
+        assert_eq!(
+            "Let's set things up. Name the first account, like Cash or Main card. /setup cancel stops.",
+            first.text
+        );
+        // Someone else's messages aren't answers.
+        assert_eq!(None, answer(&model, chat, 2, "Card").unwrap());
+        assert_eq!(
+            "Which currency is Cash in? Send its code, like EUR.",
+            say(" Cash ")
+        );
+
+        // The state is in the database, so a restart resumes at the question.
+        let resumed = on_start(&model, chat, &admin).unwrap().unwrap();
+        assert_eq!(
+            "Which currency is Cash in? Send its code, like EUR.",
+            resumed.text
+        );
+        assert_eq!(
+            "'euro' is not a currency code, send three letters like EUR.",
+            say("euro")
+        );
+
+        let categories = answer(&model, chat, 1, "eur").unwrap().unwrap();
+        assert!(categories.text.starts_with(
+            "Which categories to start with? You can leave some out next.\nMinimal: Groceries, Transport, Utilities, Entertainment, Other\nFamily detailed: "
+        ), "{}", categories.text);
+        assert_eq!(
+            vec!["Minimal (5)", "Family detailed (12)", "Student (8)", "Skip"],
+            labels(&categories).concat()
+        );
+        // Tapping Add without picking a set, as before there were sets.
+        assert!(say("yes").starts_with("Tap a button: "));
+        assert_eq!(
+            "This setup belongs to someone else.",
+            choose_categories(&model, chat, 2, true).unwrap().text
+        );
+        assert_eq!(
+            "All set: Cash in EUR and the categories Groceries, Transport, Utilities, Entertainment, Other. Send an amount to log an expense.",
+            choose_categories(&model, chat, 1, true).unwrap().text
+        );
+        assert_eq!(None, answer(&model, chat, 1, "12").unwrap());
+        assert!(!model.is_unconfigured(1).unwrap());
+        // Configured households aren't asked again on /start.
+        assert_eq!(None, on_start(&model, chat, &admin).unwrap());
+        assert_eq!(
+            "There is no setup waiting for this.",
+            choose_categories(&model, chat, 1, true).unwrap().text
+        );
+
+        // /setup runs it again, here skipping the categories.
+        handle_setup(&model, chat, &admin, "").unwrap();
+        say("Card");
+        say("byn");
+        assert_eq!(
+            "All set: Card in BYN. Send an amount to log an expense.",
+            choose_categories(&model, chat, 1, false).unwrap().text
+        );
+        assert_eq!(2, model.accounts(1).unwrap().len());
+        assert_eq!(5, model.categories(1).unwrap().len());
+    }
+
+    fn labels(reply: &Reply) -> Vec<Vec<String>> {
+        let keyboard = reply.keyboard.as_ref().expect("a keyboard");
+        keyboard
+            .inline_keyboard
+            .iter()
+            .map(|row| row.iter().map(|b| b.text.clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn customizing_a_preset() {
+        let model = Model::new(true);
+        let chat = -10;
+        handle_setup(&model, chat, &admin(), "").unwrap();
+        for text in ["Cash", "EUR"] {
+            answer(&model, chat, 1, text).unwrap();
+        }
+        let tap = |action| customize(&model, chat, 1, action).unwrap();
+
+        assert_eq!(
+            "Pick a set of categories first.",
+            tap(SetupAction::Toggle(0)).text
+        );
+        let student = tap(SetupAction::Preset(2));
+        assert_eq!(
+            "Student: tap a category to leave it out or back in. Expenses in those marked ✍️ need a comment.",
+            student.text
+        );
+        let rows = labels(&student);
+        assert_eq!(vec!["✅ 🛒 Groceries", "✅ 🍽 Eating out"], rows[0]);
+        assert_eq!(vec!["✅ 🎬 Entertainment", "✅ 📦 Other ✍️"], rows[3]);
+        assert_eq!(vec!["« Back", "✅ Add 8"], rows[4]);
+
+        tap(SetupAction::Toggle(2));
+        let toggled = tap(SetupAction::Toggle(5));
+        assert_eq!(vec!["⬜ 🏠 Rent", "✅ 🚌 Transport"], labels(&toggled)[1]);
+        assert_eq!(vec!["« Back", "✅ Add 6"], labels(&toggled)[4]);
+        // Back in, and out of range.
+        assert_eq!(
+            vec!["« Back", "✅ Add 7"],
+            labels(&tap(SetupAction::Toggle(2)))[4]
+        );
+        assert_eq!(None, tap(SetupAction::Toggle(8)).keyboard);
+        assert_eq!(
+            "This setup belongs to someone else.",
+            customize(&model, chat, 2, SetupAction::Toggle(1))
+                .unwrap()
+                .text
+        );
+        // Stored as it goes, like the answers.
+        let resumed = on_start(&model, chat, &admin()).unwrap().unwrap();
+        assert_eq!(
+            vec!["✅ 📚 Study", "⬜ 📱 Phone and internet"],
+            labels(&resumed)[2]
+        );
+
+        // Going back forgets what was left out.
+        assert!(tap(SetupAction::Presets)
+            .text
+            .starts_with("Which categories"));
+        assert_eq!(
+            vec!["« Back", "✅ Add 12"],
+            labels(&tap(SetupAction::Preset(1))).pop().unwrap()
+        );
+        assert_eq!(None, tap(SetupAction::Preset(3)).keyboard);
+        tap(SetupAction::Preset(2));
+        tap(SetupAction::Toggle(5));
+        assert_eq!(
+            "All set: Cash in EUR and the categories Groceries, Eating out, Rent, Transport, Study, Entertainment, Other. Send an amount to log an expense.",
+            choose_categories(&model, chat, 1, true).unwrap().text
+        );
+        let categories = model.categories(1).unwrap();
+        assert_eq!(7, categories.len());
+        assert!(
+            categories
+                .iter()
+                .find(|c| c.name == "Other")
+                .unwrap()
+                .require_comment
+        );
+    }
+
+    #[test]
+    fn setup_command() {
+        let model = Model::new(true);
+        let admin = admin();
+        assert_eq!(
+            "There is no setup in progress.",
+            handle_setup(&model, 5, &admin, "cancel").unwrap().text
+        );
+        handle_setup(&model, 5, &admin, "").unwrap();
+        assert_eq!(
+            "Setup cancelled, nothing was created.",
+            handle_setup(&model, 5, &admin, "cancel").unwrap().text
+        );
+        assert_eq!(None, model.setup(5).unwrap());
+        assert_eq!(
+            SETUP_USAGE,
+            handle_setup(&model, 5, &admin, "now").unwrap().text
+        );
+        // Skipping the categories of an empty household leaves it without.
+        handle_setup(&model, 5, &admin, "").unwrap();
+        for text in ["Cash", "EUR"] {
+            answer(&model, 5, 1, text).unwrap();
+        }
+        assert_eq!(
+            "All set: Cash in EUR. There are no categories yet, /setup offers them again.",
+            choose_categories(&model, 5, 1, false).unwrap().text
+        );
+    }
+}
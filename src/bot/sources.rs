@@ -0,0 +1,368 @@
+//! `/source`: trusted sources of forwards, and the drafts their forwards
+//! become. A message forwarded from a trusted channel or user is a draft in
+//! the source's category and account, with the amount its template reads;
+//! without one, or when it doesn't match, the amount is asked for.
+
+use super::draft::ActiveTransaction;
+use super::expense::{new_draft, read_typed, Typed};
+use super::parse::{self, SourceArgs, SOURCE_USAGE};
+use super::{resolve, SharedModel};
+use crate::model::{
+    AmountLimits, Error, ExpenseSource, Locale, Model, ParsedAmount, SourceId, TrustedSource, User,
+};
+use teloxide::types::{Message, MessageOrigin};
+
+/// Who a forward is from, if they can be told apart from others: users who
+/// hide their account can't.
+pub fn source_of(origin: &MessageOrigin) -> Option<SourceId> {
+    match origin {
+        MessageOrigin::User { sender_user, .. } => Some(SourceId::User(sender_user.id.0 as i64)),
+        MessageOrigin::HiddenUser { .. } => None,
+        MessageOrigin::Chat { sender_chat, .. } => Some(SourceId::Chat(sender_chat.id.0)),
+        MessageOrigin::Channel { chat, .. } => Some(SourceId::Chat(chat.id.0)),
+    }
+}
+
+/// The trusted source `message` was forwarded from, if it is a forward
+/// from one.
+pub fn trusted_origin(
+    model: &Model,
+    household: i64,
+    message: &Message,
+) -> Result<Option<TrustedSource>, Error> {
+    match message.forward_origin().and_then(source_of) {
+        Some(source) => model.trusted_source(household, source),
+        None => Ok(None),
+    }
+}
+
+/// A draft of `text` forwarded from `source`, in its category and account.
+/// The amount is what the source's template reads, or else 0 until asked
+/// for.
+pub fn forwarded_draft(
+    model: &Model,
+    (user, strict_commit): (&User, bool),
+    source: &TrustedSource,
+    text: &str,
+    (limits, locale): (&AmountLimits, Locale),
+) -> Result<ActiveTransaction, Error> {
+    let account = model
+        .get_account(user.household, source.account_id)?
+        .ok_or_else(|| Error::NotFound(format!("account {}", source.account_id)))?;
+    let category = model
+        .get_category(user.household, source.category_id)?
+        .ok_or_else(|| Error::NotFound(format!("category {}", source.category_id)))?;
+    let typed = match source.read_amount(text) {
+        Some(amount) => read_typed(model, user.household, &account, amount, (limits, locale))?.ok(),
+        None => None,
+    };
+    let typed = typed.unwrap_or(Typed {
+        amount: ParsedAmount::default(),
+        comment: None,
+        original: None,
+        ruled: None,
+    });
+    let mut draft = new_draft((user, strict_commit), account, category, typed);
+    // The admin picked it for the source.
+    draft.category_confirmed = true;
+    draft.origin = ExpenseSource::Forward;
+    Ok(draft)
+}
+
+fn describe(source: &TrustedSource) -> String {
+    let mut text = format!(
+        "#{} {} → {} on {}",
+        source.id, source.source, source.category, source.account
+    );
+    if let Some(template) = &source.template {
+        text.push_str(&format!(", amount by {}", template));
+    }
+    text
+}
+
+/// `/source`, in plain text. `forwarded` is the origin of the message the
+/// command replies to, if that is a forward.
+pub fn handle_source(
+    model: &SharedModel,
+    household: i64,
+    args: &str,
+    forwarded: Option<&MessageOrigin>,
+) -> Result<String, Error> {
+    let model = model.lock().unwrap();
+    let args = match parse::parse_source(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    match args {
+        SourceArgs::List => {
+            let sources = model.trusted_sources(household)?;
+            if sources.is_empty() {
+                return Ok(format!(
+                    "There are no trusted sources yet. {}",
+                    SOURCE_USAGE
+                ));
+            }
+            let mut lines = vec!["Trusted sources, their forwards become drafts:".to_string()];
+            lines.extend(sources.iter().map(describe));
+            Ok(lines.join("\n"))
+        }
+        SourceArgs::Add {
+            source,
+            category,
+            account,
+        } => {
+            let source = match (source, forwarded) {
+                (Some(source), _) => source,
+                (None, Some(origin)) => match source_of(origin) {
+                    Some(source) => source,
+                    None => {
+                        return Ok("Its sender hides their account, so their forwards can't be told apart from others.".to_string())
+                    }
+                },
+                (None, None) => {
+                    return Ok(
+                        "Reply to a message forwarded from the source, or name it as chat:<id> or user:<id>."
+                            .to_string(),
+                    )
+                }
+            };
+            let category = match resolve::category(&model, household, &category)? {
+                Ok(category) => category,
+                Err(reason) => return Ok(reason),
+            };
+            let account = match resolve::account(&model, household, &account)? {
+                Ok(account) => account,
+                Err(reason) => return Ok(reason),
+            };
+            match model.add_trusted_source(household, source, category.id, account.id) {
+                Ok(id) => Ok(format!(
+                    "Trusted source #{}: forwards from {} become drafts in {} on {}.",
+                    id, source, category.name, account.name
+                )),
+                Err(Error::Refused(reason)) => Ok(reason),
+                Err(e) => Err(e),
+            }
+        }
+        SourceArgs::Template { id, pattern } => {
+            match model.set_source_template(household, id, pattern.as_deref()) {
+                Ok(true) => Ok(match pattern {
+                    Some(pattern) => {
+                        format!("Source #{} reads the amount with {} now.", id, pattern)
+                    }
+                    None => format!("Source #{} asks for the amount now.", id),
+                }),
+                Ok(false) => Ok(format!("There is no source #{}.", id)),
+                Err(Error::Refused(reason)) => Ok(reason),
+                Err(e @ Error::TooLong { .. }) => Ok(e.to_string()),
+                Err(e) => Err(e),
+            }
+        }
+        SourceArgs::Del(id) => Ok(if model.delete_trusted_source(household, id)? {
+            format!("Deleted source #{}.", id)
+        } else {
+            format!("There is no source #{}.", id)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Role;
+    use chrono::DateTime;
+    use std::sync::{Arc, Mutex};
+    use teloxide::types::{
+        Chat, ChatFullInfo, ChatId, ChatKind, ChatPublic, MessageId, PublicChatChannel,
+        PublicChatKind, UserId,
+    };
+
+    fn channel(id: i64) -> Chat {
+        Chat {
+            id: ChatId(id),
+            kind: ChatKind::Public(ChatPublic {
+                title: Some("Building bills".to_string()),
+                kind: PublicChatKind::Channel(PublicChatChannel {
+                    username: None,
+                    linked_chat_id: None,
+                }),
+                description: None,
+                invite_link: None,
+                has_protected_content: None,
+            }),
+            photo: None,
+            available_reactions: None,
+            pinned_message: None,
+            message_auto_delete_time: None,
+            has_hidden_members: false,
+            has_aggressive_anti_spam_enabled: false,
+            chat_full_info: ChatFullInfo::default(),
+        }
+    }
+
+    fn date() -> DateTime<chrono::Utc> {
+        DateTime::from_timestamp(1_701_388_800, 0).unwrap()
+    }
+
+    fn from_channel(id: i64) -> MessageOrigin {
+        MessageOrigin::Channel {
+            date: date(),
+            chat: channel(id),
+            message_id: MessageId(7),
+            author_signature: None,
+        }
+    }
+
+    fn from_user(id: u64) -> MessageOrigin {
+        MessageOrigin::User {
+            date: date(),
+            sender_user: teloxide::types::User {
+                id: UserId(id),
+                is_bot: true,
+                first_name: "Utilities bot".to_string(),
+                last_name: None,
+                username: None,
+                language_code: None,
+                is_premium: false,
+                added_to_attachment_menu: false,
+            },
+        }
+    }
+
+    fn alex() -> User {
+        User {
+            telegram_id: 1001,
+            telegram_name: "alex".to_string(),
+            display_name: "Alex".to_string(),
+            role: Role::Admin,
+            household: 1,
+        }
+    }
+
+    #[test]
+    fn origins() {
+        assert_eq!(
+            Some(SourceId::Chat(-100_123)),
+            source_of(&from_channel(-100_123))
+        );
+        assert_eq!(Some(SourceId::User(42)), source_of(&from_user(42)));
+        // Anonymous admins post as their group.
+        let group = MessageOrigin::Chat {
+            date: date(),
+            sender_chat: channel(-100_456),
+            author_signature: Some("Admin".to_string()),
+        };
+        assert_eq!(Some(SourceId::Chat(-100_456)), source_of(&group));
+        let hidden = MessageOrigin::HiddenUser {
+            date: date(),
+            sender_user_name: "Someone".to_string(),
+        };
+        assert_eq!(None, source_of(&hidden));
+    }
+
+    #[test]
+    fn source_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let source = |args, forwarded: Option<MessageOrigin>| {
+            handle_source(&model, 1, args, forwarded.as_ref()).unwrap()
+        };
+
+        assert_eq!(
+            format!("There are no trusted sources yet. {}", SOURCE_USAGE),
+            source("", None)
+        );
+        assert_eq!(
+            "Trusted source #1: forwards from chat:-100123 become drafts in Utilities on Family BYN.",
+            source("add utilities -> family", Some(from_channel(-100_123)))
+        );
+        assert_eq!(
+            "chat:-100123 is trusted already, as source #1.",
+            source("add groceries -> alex", Some(from_channel(-100_123)))
+        );
+        assert_eq!(
+            "Trusted source #2: forwards from user:42 become drafts in Groceries on Alex Savings.",
+            source("add user:42 groceries -> alex", None)
+        );
+        assert_eq!(
+            "Reply to a message forwarded from the source, or name it as chat:<id> or user:<id>.",
+            source("add groceries -> alex", None)
+        );
+        let hidden = MessageOrigin::HiddenUser {
+            date: date(),
+            sender_user_name: "Someone".to_string(),
+        };
+        assert!(source("add groceries -> alex", Some(hidden)).starts_with("Its sender hides"));
+        assert!(source("add user:7 pets -> alex", None).starts_with("No category matches"));
+
+        assert_eq!(
+            r"Source #1 reads the amount with to pay: (\d+[.,]\d\d) now.",
+            source(r#"template 1 "to pay: (\d+[.,]\d\d)""#, None)
+        );
+        assert_eq!("There is no source #9.", source("template 9 none", None));
+        assert_eq!(
+            "Trusted sources, their forwards become drafts:\n\
+             #1 chat:-100123 → Utilities on Family BYN, amount by to pay: (\\d+[.,]\\d\\d)\n\
+             #2 user:42 → Groceries on Alex Savings",
+            source("list", None)
+        );
+        assert_eq!(
+            "Source #1 asks for the amount now.",
+            source("template 1 none", None)
+        );
+        assert_eq!("Deleted source #2.", source("del 2", None));
+        assert_eq!("There is no source #2.", source("del #2", None));
+    }
+
+    #[test]
+    fn forwards_become_drafts_of_the_source() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let id = model
+            .add_trusted_source(1, SourceId::Chat(-100_123), 4, 3)
+            .unwrap();
+        let limits = AmountLimits::default();
+        let bill = "November bill\nTo pay: 84,50";
+        let draft = |model: &Model| {
+            let source = model
+                .trusted_source(1, SourceId::Chat(-100_123))
+                .unwrap()
+                .unwrap();
+            forwarded_draft(
+                model,
+                (&alex(), false),
+                &source,
+                bill,
+                (&limits, Locale::DecimalComma),
+            )
+            .unwrap()
+        };
+
+        // Without a template only the amount is left to send.
+        let d = draft(&model);
+        assert!(d.awaits_amount());
+        assert_eq!(
+            ("Family BYN", "Utilities", true, None),
+            (
+                d.account.name.as_str(),
+                d.category.name.as_str(),
+                d.category_confirmed,
+                d.comment.as_deref()
+            )
+        );
+
+        model
+            .set_source_template(1, id, Some(r"to pay: ([\d,]+)"))
+            .unwrap();
+        let d = draft(&model);
+        assert_eq!(84.5, d.amount);
+        assert!(!d.category_by_rule);
+        assert_eq!(ExpenseSource::Forward, d.to_new_expense().source);
+
+        // An amount the template reads that isn't one is asked for too.
+        model
+            .set_source_template(1, id, Some(r"(\w+) bill"))
+            .unwrap();
+        assert!(draft(&model).awaits_amount());
+    }
+}
@@ -0,0 +1,288 @@
+//! `/subscribe`: a private report of the user's own expenses every week or
+//! month, delivered in the background.
+
+use super::format::RoundingMode;
+use super::markdown::escape_md;
+use super::parse::{self, SubscribeArgs};
+use super::{format, queue, SharedModel};
+use crate::model::{Error, ReportSubscription, User};
+use crate::time::{self, DateStyle, Lang};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::prelude::*;
+
+/// How often due reports are looked for.
+pub const DELIVER_EVERY: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
+
+fn describe(subscription: &ReportSubscription, tz: Tz, lang: Lang) -> String {
+    format!(
+        "{} reports, the next on {}",
+        subscription.cadence.as_str(),
+        time::render_datetime(subscription.next_run, tz, lang, DateStyle::Short)
+    )
+}
+
+/// `/subscribe`: the reply to send.
+pub fn handle_subscribe(
+    model: &SharedModel,
+    user: &User,
+    args: &str,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<String, Error> {
+    let args = match parse::parse_subscribe(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    let user_id = user.telegram_id;
+    // Reports go to the private chat, as do these replies mostly, and keep
+    // its time.
+    let lang = Lang::from_language(model.user_language(user_id)?.as_deref());
+    let tz = queue::chat_tz(&model, ChatId(user_id), tz)?;
+    match args {
+        SubscribeArgs::Subscribe(cadence) => {
+            let calendar = model.calendar(user.household)?;
+            let next_run = cadence.next_run(now, tz, calendar);
+            if !model.subscribe_report(user_id, cadence, next_run)? {
+                return Ok(format!(
+                    "You are subscribed to {} reports already.",
+                    cadence.as_str()
+                ));
+            }
+            Ok(format!(
+                "You'll get a {} report of your expenses privately, the first on {}.",
+                cadence.as_str(),
+                time::render_datetime(next_run, tz, lang, DateStyle::Short)
+            ))
+        }
+        SubscribeArgs::Off => match model.unsubscribe_reports(user_id)? {
+            0 => Ok("You have no report subscriptions.".to_string()),
+            _ => Ok("Unsubscribed from your reports.".to_string()),
+        },
+        SubscribeArgs::Status => {
+            let subscriptions = model.report_subscriptions(user_id)?;
+            if subscriptions.is_empty() {
+                return Ok(
+                    "You have no report subscriptions. Subscribe with /subscribe weekly or /subscribe monthly."
+                        .to_string(),
+                );
+            }
+            let lines: Vec<String> = subscriptions
+                .iter()
+                .map(|s| describe(s, tz, lang))
+                .collect();
+            Ok(format!("You are subscribed to:\n{}", lines.join("\n")))
+        }
+    }
+}
+
+/// Queues the reports users subscribed to that are due at `now`, for their
+/// private chats, moving each subscription on to its next report. Periods
+/// and report times are those of the chat's time zone. The queue sends them
+/// when the chat's quiet hours allow and tries again if sending fails, so
+/// moving on loses no report. Downtime of several periods sends a single report, of the
+/// period finished last.
+pub fn deliver(model: &SharedModel, (now, tz): (DateTime<Utc>, Tz)) -> Result<(), Error> {
+    let model = model.lock().unwrap();
+    for subscription in model.due_report_subscriptions(now)? {
+        // Private chats have the id of the user.
+        let chat_id = ChatId(subscription.user_id);
+        let tz = queue::chat_tz(&model, chat_id, tz)?;
+        let calendar = model.calendar(subscription.household)?;
+        let cadence = subscription.cadence;
+        let range = cadence.period().resolve(now, tz, calendar);
+        let exclude = model.report_exclusions(subscription.user_id)?;
+        let summary = model.summarize_user(
+            subscription.household,
+            subscription.user_id,
+            range,
+            &exclude,
+            tz,
+        )?;
+        let heading = escape_md(&format!("Your {} report:", cadence.as_str()));
+        let rounding = RoundingMode::of(&model.get_settings(subscription.user_id)?);
+        let text = format!("{}\n{}", heading, format::render_report(&summary, rounding));
+        queue::schedule(&model, chat_id, &text, (now, tz))?;
+        model.set_report_next_run(
+            subscription.user_id,
+            cadence,
+            cadence.next_run(now, tz, calendar),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::recording::{self, RecordingBot};
+    use crate::bot::reporter::ErrorReporter;
+    use crate::model::{Cadence, Model};
+    use std::sync::{Arc, Mutex};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn shared() -> SharedModel {
+        let model = Model::new(true);
+        model.fill_test_data();
+        Arc::new(Mutex::new(model))
+    }
+
+    #[test]
+    fn subscribe_command() {
+        let model = shared();
+        let hanna = model.lock().unwrap().get_user(1002).unwrap().unwrap();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        // Sunday 10 December 2023.
+        let now = at("2023-12-10T12:00:00Z");
+        let subscribe = |args| handle_subscribe(&model, &hanna, args, now, berlin).unwrap();
+
+        assert_eq!(
+            "You have no report subscriptions. Subscribe with /subscribe weekly or /subscribe monthly.",
+            subscribe("")
+        );
+        assert_eq!(
+            "You'll get a weekly report of your expenses privately, the first on Dec 11, 09:00.",
+            subscribe("weekly")
+        );
+        assert_eq!(
+            "You are subscribed to weekly reports already.",
+            subscribe("weekly")
+        );
+        subscribe("monthly");
+        assert_eq!(
+            "You are subscribed to:\nweekly reports, the next on Dec 11, 09:00\nmonthly reports, the next on Jan 1, 09:00",
+            subscribe("status")
+        );
+        assert_eq!(parse::SUBSCRIBE_USAGE, subscribe("daily"));
+        assert_eq!("Unsubscribed from your reports.", subscribe("off"));
+        assert_eq!("You have no report subscriptions.", subscribe("off"));
+    }
+
+    /// The queued messages, with their chats and when they go out.
+    fn queued(model: &SharedModel) -> Vec<(i64, DateTime<Utc>, String)> {
+        let model = model.lock().unwrap();
+        let far = at("2100-01-01T00:00:00Z");
+        model
+            .due_notifications(far)
+            .unwrap()
+            .into_iter()
+            .map(|n| (n.chat_id, n.due_at, n.text))
+            .collect()
+    }
+
+    #[test]
+    fn one_report_after_downtime() {
+        let model = shared();
+        // Due since November, the bot was down until January.
+        model
+            .lock()
+            .unwrap()
+            .subscribe_report(1002, Cadence::Monthly, at("2023-11-01T09:00:00Z"))
+            .unwrap();
+        let now = at("2024-01-02T10:00:00Z");
+
+        deliver(&model, (now, Tz::UTC)).unwrap();
+        let reports = queued(&model);
+        assert_eq!(1, reports.len());
+        let (chat_id, due_at, text) = &reports[0];
+        assert_eq!((1002, now), (*chat_id, *due_at));
+        assert!(text.starts_with("Your monthly report:\n*Report 2023\\-12\\-01 – 2023\\-12\\-31*"));
+        assert!(text.contains("Utilities"));
+        // Only Hanna's own expenses.
+        assert!(!text.contains("Groceries"));
+
+        deliver(&model, (now, Tz::UTC)).unwrap();
+        assert_eq!(1, queued(&model).len());
+        let next = model.lock().unwrap().report_subscriptions(1002).unwrap()[0].next_run;
+        assert_eq!(at("2024-02-01T09:00:00Z"), next);
+    }
+
+    #[test]
+    fn reports_in_the_time_of_the_chat() {
+        let model = shared();
+        let hanna = model.lock().unwrap().get_user(1002).unwrap().unwrap();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        model
+            .lock()
+            .unwrap()
+            .set_chat_time_zone(1002, 1, Some(berlin))
+            .unwrap();
+        // Sunday 10 December 2023, the bot on UTC.
+        let now = at("2023-12-10T12:00:00Z");
+        assert_eq!(
+            "You'll get a weekly report of your expenses privately, the first on Dec 11, 09:00.",
+            handle_subscribe(&model, &hanna, "weekly", now, Tz::UTC).unwrap()
+        );
+        let next = model.lock().unwrap().report_subscriptions(1002).unwrap()[0].next_run;
+        assert_eq!(at("2023-12-11T08:00:00Z"), next);
+
+        // 09:00 in Berlin, the report covers the week to Sunday there.
+        let monday = at("2023-12-11T08:00:00Z");
+        deliver(&model, (monday, Tz::UTC)).unwrap();
+        assert_eq!(monday, queued(&model)[0].1);
+        let next = model.lock().unwrap().report_subscriptions(1002).unwrap()[0].next_run;
+        assert_eq!(at("2023-12-18T08:00:00Z"), next);
+    }
+
+    #[tokio::test]
+    async fn failed_send_keeps_the_report() {
+        let model = shared();
+        let (reporter, _receiver) = ErrorReporter::new();
+        model
+            .lock()
+            .unwrap()
+            .subscribe_report(1001, Cadence::Weekly, at("2023-12-04T09:00:00Z"))
+            .unwrap();
+        let now = at("2023-12-04T09:00:00Z");
+        deliver(&model, (now, Tz::UTC)).unwrap();
+        queue::drain(
+            recording::unreachable(),
+            model.clone(),
+            (now, Tz::UTC),
+            reporter.clone(),
+        )
+        .await
+        .unwrap();
+        // Moved on to the next week, the report still waits.
+        assert_eq!(1, queued(&model).len());
+
+        let recording = RecordingBot::start();
+        let later = now + chrono::TimeDelta::hours(1);
+        queue::drain(
+            recording.bot.clone(),
+            model.clone(),
+            (later, Tz::UTC),
+            reporter,
+        )
+        .await
+        .unwrap();
+        let calls = recording.take();
+        assert_eq!(1, calls.len());
+        assert!(calls[0].body.contains("Your weekly report"));
+        assert!(queued(&model).is_empty());
+    }
+
+    #[test]
+    fn quiet_hours_defer_reports() {
+        let model = shared();
+        model
+            .lock()
+            .unwrap()
+            .subscribe_report(1001, Cadence::Weekly, at("2023-12-04T09:00:00Z"))
+            .unwrap();
+        // Due while the bot was down, found at night: it waits for the
+        // morning, in the queue.
+        deliver(&model, (at("2023-12-04T23:30:00Z"), Tz::UTC)).unwrap();
+        deliver(&model, (at("2023-12-05T07:59:00Z"), Tz::UTC)).unwrap();
+        let reports = queued(&model);
+        assert_eq!(1, reports.len());
+        assert_eq!(
+            (1001, at("2023-12-05T08:00:00Z")),
+            (reports[0].0, reports[0].1)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Housekeeping done in the background while the bot runs.
+
+use super::reporter::ErrorReporter;
+use super::{SharedCoordinator, SharedModel};
+use crate::model::{Model, Result, RetentionPolicy};
+use chrono::{DateTime, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
+use log::*;
+
+/// How often expired callback payloads are deleted, and how often the
+/// maintenance checks whether it is due.
+pub const SWEEP_EVERY: TimeDelta = TimeDelta::hours(1);
+
+/// Local hours of little activity, from 22:00 to 08:00, that the maintenance,
+/// holding the database, waits for. Messages to users wait out the quiet
+/// hours of their chat instead.
+const QUIET_HOURS: (u32, u32) = (22, 8);
+
+/// How long the database goes between scheduled maintenances.
+const MAINTAIN_EVERY: TimeDelta = TimeDelta::weeks(1);
+
+fn is_quiet(now: DateTime<Utc>, tz: Tz) -> bool {
+    let hour = now.with_timezone(&tz).hour();
+    hour >= QUIET_HOURS.0 || hour < QUIET_HOURS.1
+}
+
+/// Deletes the callback payloads expired at `now`.
+pub fn sweep(model: &SharedModel, now: DateTime<Utc>) -> Result<()> {
+    let swept = model.lock().unwrap().sweep_callback_payloads(now)?;
+    if swept > 0 {
+        info!("Swept {} expired callback payloads", swept);
+    }
+    Ok(())
+}
+
+/// Whether the database is due for maintenance at `now`, a week after the
+/// last one and only in the quiet hours.
+fn maintenance_due(last: Option<DateTime<Utc>>, now: DateTime<Utc>, tz: Tz) -> bool {
+    is_quiet(now, tz) && last.is_none_or(|last| now - last >= MAINTAIN_EVERY)
+}
+
+/// Whether to run the scheduled maintenance at `now`. Read-only mode holds
+/// it back; the last maintenance stays where it was, so it catches up on the
+/// first quiet hour after the mode ends.
+fn scheduled_maintenance_due(model: &Model, now: DateTime<Utc>, tz: Tz) -> Result<bool> {
+    if model.read_only()? {
+        return Ok(false);
+    }
+    Ok(maintenance_due(model.last_maintenance()?, now, tz))
+}
+
+/// Maintains the database if it is due at `now`, the weekly one: prunes
+/// the rows `retention` no longer keeps, then vacuums what that freed. It
+/// runs on a blocking thread with the database to itself, users are told to
+/// try again meanwhile.
+pub async fn maintain(
+    model: SharedModel,
+    coordinator: SharedCoordinator,
+    (now, tz): (DateTime<Utc>, Tz),
+    retention: Vec<RetentionPolicy>,
+    reporter: ErrorReporter,
+) -> Result<()> {
+    let due = scheduled_maintenance_due(&model.lock().unwrap(), now, tz)?;
+    if !due {
+        return Ok(());
+    }
+    // Running by hand right now, it is tried again in an hour.
+    let _permit = match coordinator.exclusive("Maintenance").await {
+        Ok(permit) => permit,
+        Err(e) => {
+            info!("Scheduled maintenance skipped: {}", e);
+            return Ok(());
+        }
+    };
+    let run = move || {
+        let model = model.lock().unwrap();
+        model.prune_retention(&retention, 0, now)?;
+        model.maintenance(0)
+    };
+    match tokio::task::spawn_blocking(run).await {
+        Ok(result) => result.map(|_| ()),
+        Err(e) => {
+            reporter.report(Level::Error, "maintenance", e);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn weekly_maintenance_in_quiet_hours() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let night = at("2023-12-10T02:00:00Z");
+        assert!(maintenance_due(None, night, berlin));
+        // Noon in Berlin.
+        assert!(!maintenance_due(None, at("2023-12-10T11:00:00Z"), berlin));
+        assert!(!maintenance_due(
+            Some(at("2023-12-05T02:00:00Z")),
+            night,
+            berlin
+        ));
+        assert!(maintenance_due(
+            Some(at("2023-12-03T02:00:00Z")),
+            night,
+            berlin
+        ));
+        // 23:00 in Berlin is quiet already.
+        assert!(maintenance_due(
+            Some(at("2023-12-01T12:00:00Z")),
+            at("2023-12-10T22:00:00Z"),
+            berlin
+        ));
+    }
+
+    #[test]
+    fn maintenance_catches_up_after_read_only_mode() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let night = at("2023-12-10T02:00:00Z");
+        model.set_read_only(1001, true).unwrap();
+        assert!(!scheduled_maintenance_due(&model, night, Tz::UTC).unwrap());
+        // Weeks later, once the mode ends, the maintenance is due at once.
+        model.set_read_only(1001, false).unwrap();
+        let later = at("2024-01-07T02:00:00Z");
+        assert!(scheduled_maintenance_due(&model, later, Tz::UTC).unwrap());
+        model.maintenance(0).unwrap();
+        assert!(!scheduled_maintenance_due(&model, later, Tz::UTC).unwrap());
+    }
+}
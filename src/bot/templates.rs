@@ -0,0 +1,185 @@
+//! `/template comment`: comments with blanks, like `fuel {liters}L at
+//! {station}`. Picking one for a draft asks for each blank in turn, and the
+//! filled in comment replaces the draft's once the last one is answered.
+
+use super::parse::{self, TemplateArgs};
+use super::SharedModel;
+use crate::model::{check_comment, CommentTemplate, Error};
+
+/// What answering the prompt of a blank cancels the fill with.
+pub const CANCEL: &str = "/cancel";
+
+/// A template being filled in for a draft.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateFill {
+    template: CommentTemplate,
+    /// The values of the blanks answered so far, in order.
+    values: Vec<String>,
+}
+
+/// Where a fill is after an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FillStep {
+    /// The prompt for the next blank.
+    Ask(String),
+    /// The filled in comment.
+    Done(String),
+}
+
+impl TemplateFill {
+    /// Starts filling `template`, which is done already without blanks.
+    pub fn start(template: CommentTemplate) -> (TemplateFill, FillStep) {
+        let fill = TemplateFill {
+            template,
+            values: Vec::new(),
+        };
+        let step = fill.step();
+        (fill, step)
+    }
+
+    fn step(&self) -> FillStep {
+        let blanks = &self.template.placeholders;
+        match blanks.get(self.values.len()) {
+            Some(name) => FillStep::Ask(format!(
+                "{}? ({} of {}, {} to keep the comment as it was)",
+                name,
+                self.values.len() + 1,
+                blanks.len(),
+                CANCEL
+            )),
+            None => FillStep::Done(self.template.fill(&self.values)),
+        }
+    }
+
+    /// Takes `value` for the blank asked for, or returns why it can't.
+    pub fn answer(&mut self, value: &str) -> Result<FillStep, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Send a value for the blank.".to_string());
+        }
+        self.values.push(value.to_string());
+        let step = self.step();
+        if let FillStep::Done(comment) = &step {
+            if let Err(e) = check_comment(Some(comment)) {
+                self.values.pop();
+                return Err(e.to_string());
+            }
+        }
+        Ok(step)
+    }
+}
+
+/// `/template`: the reply to send.
+pub fn handle_template(model: &SharedModel, user_id: i64, args: &str) -> Result<String, Error> {
+    let args = match parse::parse_template(args) {
+        Ok(args) => args,
+        Err(usage) => return Ok(usage),
+    };
+    let model = model.lock().unwrap();
+    match args {
+        TemplateArgs::List => {
+            let templates = model.comment_templates(user_id)?;
+            if templates.is_empty() {
+                return Ok(format!(
+                    "You have no comment templates. {}",
+                    parse::TEMPLATE_USAGE
+                ));
+            }
+            let mut lines = vec!["Your comment templates:".to_string()];
+            lines.extend(templates.iter().map(|t| format!("{}: {}", t.name, t.text)));
+            Ok(lines.join("\n"))
+        }
+        TemplateArgs::Add { name, text } => {
+            match model.set_comment_template(user_id, &name, &text) {
+                Ok(()) => {}
+                Err(Error::Refused(reason)) => return Ok(reason),
+                Err(e @ Error::TooLong { .. }) => return Ok(e.to_string()),
+                Err(e) => return Err(e),
+            }
+            Ok(format!(
+                "Saved the template {}, pick it under Comment on a draft.",
+                name
+            ))
+        }
+        TemplateArgs::Del(name) => Ok(if model.delete_comment_template(user_id, &name)? {
+            format!("Deleted the template {}.", name)
+        } else {
+            format!("You have no template {}.", name)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use std::sync::{Arc, Mutex};
+
+    fn fuel() -> CommentTemplate {
+        CommentTemplate::parse("fuel", "fuel {liters}L at {station}").unwrap()
+    }
+
+    #[test]
+    fn fills_blanks_in_turn() {
+        let (mut fill, step) = TemplateFill::start(fuel());
+        assert_eq!(
+            FillStep::Ask("liters? (1 of 2, /cancel to keep the comment as it was)".to_string()),
+            step
+        );
+        assert_eq!(
+            Err("Send a value for the blank.".to_string()),
+            fill.answer("  ")
+        );
+        assert_eq!(
+            Ok(FillStep::Ask(
+                "station? (2 of 2, /cancel to keep the comment as it was)".to_string()
+            )),
+            fill.answer(" 40 ")
+        );
+        assert_eq!(
+            Ok(FillStep::Done("fuel 40L at Shell Main St".to_string())),
+            fill.answer("Shell Main St")
+        );
+
+        let plain = CommentTemplate::parse("bus", "bus ticket").unwrap();
+        assert_eq!(
+            FillStep::Done("bus ticket".to_string()),
+            TemplateFill::start(plain).1
+        );
+    }
+
+    #[test]
+    fn too_long_comments_ask_again() {
+        let (mut fill, _) = TemplateFill::start(fuel());
+        fill.answer("40").unwrap();
+        assert!(fill.answer(&"x".repeat(1000)).is_err());
+        assert_eq!(
+            Ok(FillStep::Done("fuel 40L at Shell".to_string())),
+            fill.answer("Shell")
+        );
+    }
+
+    #[test]
+    fn template_command() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let model = Arc::new(Mutex::new(model));
+        let template = |args| handle_template(&model, 1001, args).unwrap();
+
+        assert!(template("").starts_with("You have no comment templates."));
+        assert_eq!(
+            "Saved the template fuel, pick it under Comment on a draft.",
+            template(r#"comment add fuel "fuel {liters}L at {station}""#)
+        );
+        assert_eq!(
+            "The { at 6 is never closed.",
+            template(r#"comment add bad "fuel {liters""#)
+        );
+        assert_eq!(
+            "Your comment templates:\nfuel: fuel {liters}L at {station}",
+            template("comment list")
+        );
+        assert_eq!("Deleted the template fuel.", template("comment del fuel"));
+        assert_eq!("You have no template fuel.", template("comment del fuel"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::model::{AmountLimits, Retained, RetentionPolicy, ReviewRules, DB_FILE};
+use chrono_tz::Tz;
+use log::*;
+use std::env;
+use std::path::{self, PathBuf};
+
+const DEFAULT_MAX_MESSAGES: usize = 3;
+const DEFAULT_RATE_STALE_DAYS: u32 = 14;
+const DEFAULT_AUDIT_RETENTION_DAYS: u32 = 365;
+/// As long as a stored payload answers its button.
+const DEFAULT_PAYLOAD_RETENTION_DAYS: u32 = 2;
+
+/// Days from `var`, positive, or else `default`.
+fn retention_days(var: &str, default: u32) -> u32 {
+    match env::var(var) {
+        Ok(value) => match value.trim().parse::<u32>() {
+            Ok(days) if days > 0 => days,
+            _ => {
+                warn!("Ignoring invalid {}: {}", var, value);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Runtime configuration read from `ST_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Telegram id of the bot owner. The user is granted the admin role on
+    /// startup, so a fresh database always has someone able to manage roles.
+    pub admin_id: Option<i64>,
+    /// Currency grand totals are converted to, `EUR` unless `ST_BASE_CURRENCY`
+    /// says otherwise. Exchange rates are stored relative to it.
+    pub base_currency: String,
+    /// Time zone that days, weeks and months are counted in (`ST_TIMEZONE`,
+    /// an IANA name such as `Europe/Berlin`), UTC by default.
+    pub timezone: Tz,
+    /// Largest amount a single expense may have (`ST_MAX_AMOUNT`), guarding
+    /// against typos like an extra few zeros.
+    pub max_amount: f64,
+    /// Amount above which `/review` asks for a comment
+    /// (`ST_REVIEW_COMMENT_OVER`), in the expense's currency.
+    pub review_comment_over: f64,
+    /// Most messages a long text is split into (`ST_MAX_MESSAGES`), 3 by
+    /// default. Longer texts are sent as a file.
+    pub max_messages: usize,
+    /// Age in days past which a rate is flagged as possibly stale where it
+    /// is used (`ST_RATE_STALE_DAYS`), 14 by default.
+    pub rate_stale_days: u32,
+    /// Days the audit log is kept for (`ST_AUDIT_RETENTION_DAYS`), 365 by
+    /// default. Older entries are pruned by the weekly maintenance.
+    pub audit_retention_days: u32,
+    /// Days stored callback payloads are kept for
+    /// (`ST_PAYLOAD_RETENTION_DAYS`), 2 by default.
+    pub payload_retention_days: u32,
+    /// The database file (`ST_DB_PATH`), `./spending-tracker.db` by default.
+    /// Archives are made next to it.
+    pub db_path: PathBuf,
+    /// The database to move to `db_path` on startup while nothing is there
+    /// (`ST_DB_MIGRATE_FROM`), usually the one in the working directory.
+    pub db_migrate_from: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let admin_id = match env::var("ST_ADMIN_ID") {
+            Ok(value) => match value.trim().parse() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    warn!("Ignoring invalid ST_ADMIN_ID: {}", value);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+
+        let base_currency = env::var("ST_BASE_CURRENCY").unwrap_or_else(|_| "EUR".to_string());
+
+        let timezone = match env::var("ST_TIMEZONE") {
+            Ok(value) => value.parse().unwrap_or_else(|_| {
+                warn!("Ignoring invalid ST_TIMEZONE: {}", value);
+                Tz::UTC
+            }),
+            Err(_) => Tz::UTC,
+        };
+
+        let default_max = AmountLimits::default().max;
+        let max_amount = match env::var("ST_MAX_AMOUNT") {
+            Ok(value) => match value.trim().parse::<f64>() {
+                Ok(max) if max.is_finite() && max > 0.0 => max,
+                _ => {
+                    warn!("Ignoring invalid ST_MAX_AMOUNT: {}", value);
+                    default_max
+                }
+            },
+            Err(_) => default_max,
+        };
+
+        let default_over = ReviewRules::default().comment_over;
+        let review_comment_over = match env::var("ST_REVIEW_COMMENT_OVER") {
+            Ok(value) => match value.trim().parse::<f64>() {
+                Ok(over) if over.is_finite() && over >= 0.0 => over,
+                _ => {
+                    warn!("Ignoring invalid ST_REVIEW_COMMENT_OVER: {}", value);
+                    default_over
+                }
+            },
+            Err(_) => default_over,
+        };
+
+        let max_messages = match env::var("ST_MAX_MESSAGES") {
+            Ok(value) => match value.trim().parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => {
+                    warn!("Ignoring invalid ST_MAX_MESSAGES: {}", value);
+                    DEFAULT_MAX_MESSAGES
+                }
+            },
+            Err(_) => DEFAULT_MAX_MESSAGES,
+        };
+
+        let rate_stale_days = match env::var("ST_RATE_STALE_DAYS") {
+            Ok(value) => value.trim().parse::<u32>().unwrap_or_else(|_| {
+                warn!("Ignoring invalid ST_RATE_STALE_DAYS: {}", value);
+                DEFAULT_RATE_STALE_DAYS
+            }),
+            Err(_) => DEFAULT_RATE_STALE_DAYS,
+        };
+
+        let audit_retention_days =
+            retention_days("ST_AUDIT_RETENTION_DAYS", DEFAULT_AUDIT_RETENTION_DAYS);
+        let payload_retention_days =
+            retention_days("ST_PAYLOAD_RETENTION_DAYS", DEFAULT_PAYLOAD_RETENTION_DAYS);
+
+        let db_path = env::var("ST_DB_PATH").unwrap_or_else(|_| DB_FILE.to_string());
+        let db_path = path::absolute(db_path).expect("the working directory is known");
+        let db_migrate_from = env::var("ST_DB_MIGRATE_FROM")
+            .ok()
+            .map(|from| path::absolute(from).expect("the working directory is known"));
+
+        Config {
+            admin_id,
+            base_currency,
+            timezone,
+            max_amount,
+            review_comment_over,
+            max_messages,
+            rate_stale_days,
+            audit_retention_days,
+            payload_retention_days,
+            db_path,
+            db_migrate_from,
+        }
+    }
+
+    pub fn amount_limits(&self) -> AmountLimits {
+        AmountLimits {
+            max: self.max_amount,
+            ..AmountLimits::default()
+        }
+    }
+
+    /// How long the tables pruned by maintenance keep their rows.
+    pub fn retention(&self) -> Vec<RetentionPolicy> {
+        vec![
+            RetentionPolicy {
+                table: Retained::AuditLog,
+                days: self.audit_retention_days,
+            },
+            RetentionPolicy {
+                table: Retained::CallbackPayload,
+                days: self.payload_retention_days,
+            },
+        ]
+    }
+
+    pub fn review_rules(&self) -> ReviewRules {
+        ReviewRules {
+            comment_over: self.review_comment_over,
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Expenses as files to open in a spreadsheet, written while the rows are
+//! read from the database.
+
+#[cfg(feature = "report-image")]
+mod crc;
+#[cfg(feature = "report-image")]
+mod font;
+#[cfg(feature = "report-image")]
+pub mod image;
+#[cfg(feature = "report-image")]
+mod png;
+#[cfg(feature = "xlsx")]
+mod xlsx;
+#[cfg(feature = "xlsx")]
+mod zip;
+
+use crate::model::{self, DateRange, Model};
+use crate::time::{self, Style};
+use chrono_tz::Tz;
+use std::io::{self, Write};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Model(#[from] model::Error),
+    #[error("writing the export failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// A workbook with the expenses and a pivot of them per category and
+    /// month.
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+}
+
+impl Format {
+    /// The formats of this build, the default first.
+    pub const ALL: &'static [Format] = &[
+        Format::Csv,
+        #[cfg(feature = "xlsx")]
+        Format::Xlsx,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            #[cfg(feature = "xlsx")]
+            Format::Xlsx => "xlsx",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|f| f.extension().eq_ignore_ascii_case(text))
+    }
+}
+
+const HEADER: [&str; 7] = [
+    "Date", "Amount", "Currency", "Account", "Category", "User", "Comment",
+];
+
+/// Writes the expenses of the household over the local days of `range` to
+/// `out`.
+pub fn write<W: Write>(
+    model: &Model,
+    household: i64,
+    range: DateRange,
+    tz: Tz,
+    format: Format,
+    out: W,
+) -> Result<W, Error> {
+    match format {
+        Format::Csv => write_csv((model, household), range, tz, out),
+        #[cfg(feature = "xlsx")]
+        Format::Xlsx => write_xlsx((model, household), range, tz, out),
+    }
+}
+
+fn write_csv<W: Write>(
+    (model, household): (&Model, i64),
+    range: DateRange,
+    tz: Tz,
+    mut out: W,
+) -> Result<W, Error> {
+    writeln!(out, "{}", HEADER.join(","))?;
+    model.export_expenses(household, range, tz, |row| -> Result<(), Error> {
+        let fields = [
+            time::render(row.timestamp, tz, Style::DateTime),
+            format!(
+                "{:.*}",
+                row.exponent as usize,
+                model::from_minor(row.amount_minor, row.exponent)
+            ),
+            row.currency,
+            row.account,
+            row.category,
+            row.user,
+            row.comment.unwrap_or_default(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        writeln!(out, "{}", fields.join(","))?;
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(out)
+}
+
+/// Quotes the field if it needs to, as RFC 4180 says.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(feature = "xlsx")]
+fn write_xlsx<W: Write>(
+    (model, household): (&Model, i64),
+    range: DateRange,
+    tz: Tz,
+    out: W,
+) -> Result<W, Error> {
+    use chrono::Datelike;
+    use std::collections::BTreeMap;
+    use xlsx::{Cell, Workbook};
+
+    let mut book = Workbook::new(out);
+    book.add_sheet("Expenses")?;
+    book.row(&HEADER.map(Cell::Text))?;
+    // Spend per month from the first one of the range, by currency and
+    // category, in minor units so that the sums stay exact.
+    let mut pivot: BTreeMap<(String, String), (u32, Vec<i64>)> = BTreeMap::new();
+    let months = month_index(range.start, range.last_day()) + 1;
+    model.export_expenses(household, range, tz, |row| -> Result<(), Error> {
+        let local = row.timestamp.with_timezone(&tz).naive_local();
+        book.row(&[
+            Cell::DateTime(local),
+            Cell::Amount(
+                model::from_minor(row.amount_minor, row.exponent),
+                row.exponent,
+            ),
+            Cell::Text(&row.currency),
+            Cell::Text(&row.account),
+            Cell::Text(&row.category),
+            Cell::Text(&row.user),
+            Cell::Text(row.comment.as_deref().unwrap_or_default()),
+        ])?;
+        let (_, spend) = pivot
+            .entry((row.currency, row.category))
+            .or_insert_with(|| (row.exponent, vec![0; months]));
+        spend[month_index(range.start, local.date())] += row.amount_minor;
+        Ok(())
+    })?;
+
+    book.add_sheet("By category")?;
+    let month_names: Vec<String> = (0..months)
+        .map(|m| {
+            (range.start.with_day(1).unwrap() + chrono::Months::new(m as u32))
+                .format("%Y-%m")
+                .to_string()
+        })
+        .collect();
+    let mut header = vec![Cell::Text("Currency"), Cell::Text("Category")];
+    header.extend(month_names.iter().map(|m| Cell::Text(m)));
+    header.push(Cell::Text("Total"));
+    book.row(&header)?;
+    for ((currency, category), (exponent, spend)) in &pivot {
+        let mut cells = vec![Cell::Text(currency), Cell::Text(category)];
+        let amount = |minor| Cell::Amount(model::from_minor(minor, *exponent), *exponent);
+        cells.extend(spend.iter().map(|s| amount(*s)));
+        cells.push(amount(spend.iter().sum()));
+        book.row(&cells)?;
+    }
+    Ok(book.finish()?)
+}
+
+/// Months from the one of `first` to the one of `date`.
+#[cfg(feature = "xlsx")]
+fn month_index(first: chrono::NaiveDate, date: chrono::NaiveDate) -> usize {
+    use chrono::Datelike;
+    ((date.year() - first.year()) * 12 + date.month() as i32 - first.month() as i32) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::NaiveDate;
+
+    fn year(year: i32) -> DateRange {
+        DateRange::inclusive(
+            NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(year, 12, 31).unwrap(),
+        )
+    }
+
+    fn test_model() -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id: 1,
+                user_id: 1002,
+                // 2023-12-05 08:00 UTC
+                timestamp: chrono::DateTime::from_timestamp(1_701_763_200, 0).unwrap(),
+                amount: 2.5,
+                comment: Some("Coffee, \"large\"".to_string()),
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+        model
+    }
+
+    #[test]
+    fn csv() {
+        let model = test_model();
+        let csv = write(&model, 1, year(2023), Tz::UTC, Format::Csv, Vec::new()).unwrap();
+        assert_eq!(
+            "Date,Amount,Currency,Account,Category,User,Comment
+2023-12-01 10:00,50.75,EUR,Alex Savings,Groceries,Alex,Bought groceries for the week
+2023-12-02 12:30,20.00,USD,Hanna Daily,Transportation,Hanna,Taxi ride to the office
+2023-12-03 18:00,30.00,BYN,Family BYN,Entertainment,Alex,Cinema tickets for family
+2023-12-04 20:00,100.00,USD,Hanna Daily,Utilities,Hanna,Paid electricity bill
+2023-12-05 08:00,2.50,EUR,Alex Savings,Groceries,Hanna,\"Coffee, \"\"large\"\"\"
+",
+            String::from_utf8(csv).unwrap()
+        );
+
+        let empty = write(&model, 1, year(2022), Tz::UTC, Format::Csv, Vec::new()).unwrap();
+        assert_eq!(
+            "Date,Amount,Currency,Account,Category,User,Comment\n",
+            String::from_utf8(empty).unwrap()
+        );
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(Some(Format::Csv), Format::parse("CSV"));
+        assert_eq!(None, Format::parse("ods"));
+        assert_eq!(Format::Csv, Format::ALL[0]);
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn xlsx() {
+        use super::zip::read_entries;
+
+        let model = test_model();
+        // Minsk is three hours ahead of UTC all year.
+        let bytes = write(
+            &model,
+            1,
+            year(2023),
+            Tz::Europe__Minsk,
+            Format::Xlsx,
+            Vec::new(),
+        )
+        .unwrap();
+        let entries = read_entries(&bytes);
+        let sheet = |n: usize| {
+            let name = format!("xl/worksheets/sheet{}.xml", n);
+            let (_, xml) = entries.iter().find(|(entry, _)| *entry == name).unwrap();
+            String::from_utf8(xml.clone()).unwrap()
+        };
+
+        let expenses = sheet(1);
+        assert_eq!(6, expenses.matches("<row ").count());
+        // 2023-12-03 21:00 in Minsk.
+        assert!(expenses
+            .contains(r#"<c r="A4" s="1"><v>45263.875</v></c><c r="B4" s="4"><v>30</v></c>"#));
+        assert!(expenses.contains("Coffee, &quot;large&quot;"));
+
+        let pivot = sheet(2);
+        // A header and one row per currency and category.
+        assert_eq!(5, pivot.matches("<row ").count());
+        assert!(pivot.contains(r#"<c r="D2" s="4"><v>0</v></c>"#));
+        assert!(pivot.contains(r#"<c r="N3" s="4"><v>53.25</v></c>"#));
+        assert!(pivot.contains(r#"<is><t xml:space="preserve">2023-12</t></is></c><c r="O1""#));
+        // USD Utilities, the last row: 100 in December and in total.
+        assert!(pivot.ends_with(concat!(
+            r#"<c r="N5" s="4"><v>100</v></c><c r="O5" s="4"><v>100</v></c></row>"#,
+            "</sheetData></worksheet>"
+        )));
+        assert!(entries.iter().any(|(name, xml)| name == "xl/workbook.xml"
+            && String::from_utf8_lossy(xml).contains(r#"<sheet name="By category""#)));
+    }
+}
@@ -0,0 +1,43 @@
+//! The CRC-32 of PNG chunks.
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// `crc` extended with `bytes`, starting from 0.
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |c, b| {
+        CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(0, crc32_update(0, b""));
+        assert_eq!(0xcbf4_3926, crc32_update(0, b"123456789"));
+        assert_eq!(
+            crc32_update(0, b"123456789"),
+            crc32_update(crc32_update(0, b"1234"), b"56789")
+        );
+    }
+}
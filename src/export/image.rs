@@ -0,0 +1,316 @@
+//! `/report img`: the report as a PNG, a table of the spend per category
+//! with a bar for each, for chats where text tables get mangled.
+
+use super::font::{self, ADVANCE};
+use super::png;
+use crate::model::Summary;
+
+/// The width of every image, whatever the number of categories.
+pub const WIDTH: u32 = 1080;
+/// Rows past these are summed up in one, so that the image stays within
+/// what Telegram accepts as a photo.
+const MAX_ROWS: usize = 100;
+
+const PADDING: u32 = 48;
+/// Pixels per dot of the font.
+const TITLE_SCALE: u32 = 5;
+const SCALE: u32 = 3;
+const TITLE_HEIGHT: u32 = font::HEIGHT * TITLE_SCALE + 32;
+const ROW_HEIGHT: u32 = 40;
+/// Characters of a label, longer ones are ellipsized.
+const LABEL_CHARS: usize = 20;
+const AMOUNT_CHARS: u32 = 12;
+const GAP: u32 = 24;
+const BAR_X: u32 = PADDING + LABEL_CHARS as u32 * ADVANCE * SCALE + GAP;
+const AMOUNT_X: u32 = WIDTH - PADDING - AMOUNT_CHARS * ADVANCE * SCALE;
+const BAR_WIDTH: u32 = AMOUNT_X - GAP - BAR_X;
+const BAR_HEIGHT: u32 = 24;
+
+type Color = [u8; 3];
+const BACKGROUND: Color = [0xff, 0xff, 0xff];
+const TEXT: Color = [0x21, 0x21, 0x21];
+const MUTED: Color = [0x75, 0x75, 0x75];
+const RULE: Color = [0xe0, 0xe0, 0xe0];
+const BAR: Color = [0x42, 0x85, 0xf4];
+const SUBCATEGORY_BAR: Color = [0xa1, 0xc2, 0xfa];
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            rgb: BACKGROUND.repeat((width * height) as usize),
+        }
+    }
+
+    /// Fills a rectangle, clipped to the canvas.
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: Color) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                let at = ((row * self.width + column) * 3) as usize;
+                self.rgb[at..at + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draws `text` with its top left corner at `x`, `y`.
+    fn text(&mut self, x: u32, y: u32, text: &str, scale: u32, color: Color) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as u32 * ADVANCE * scale;
+            for (column, bits) in font::glyph(c).into_iter().enumerate() {
+                for row in 0..font::HEIGHT {
+                    if bits & (1 << row) != 0 {
+                        let column = left + column as u32 * scale;
+                        self.fill(column, y + row * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws `text` ending at `right`.
+    fn text_right(&mut self, right: u32, y: u32, text: &str, scale: u32, color: Color) {
+        let width = text.chars().count() as u32 * ADVANCE * scale;
+        // The blank column after the last glyph isn't part of the text.
+        let x = (right + scale).saturating_sub(width);
+        self.text(x, y, text, scale, color);
+    }
+}
+
+enum Row {
+    Currency(String),
+    /// `share` is of the largest spend in the currency, for the bar.
+    Category {
+        label: String,
+        amount: String,
+        share: f64,
+        subcategory: bool,
+    },
+    Total(String),
+    Note(String),
+}
+
+fn amount(amount: f64, exponent: u32) -> String {
+    format!("{:.*}", exponent as usize, amount)
+}
+
+fn ellipsize(text: &str, chars: usize) -> String {
+    if text.chars().count() <= chars {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(chars - 1).collect();
+    short.push('…');
+    short
+}
+
+/// The rows of the report, as in the text one: currencies, the spend per
+/// category under each, largest first, with that of subcategories below.
+fn rows(summary: &Summary) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for (currency, total) in summary.currency_totals() {
+        let groups = summary.groups(&currency);
+        let largest = groups.iter().map(|g| g.total).fold(0.0, f64::max);
+        let share = |total: f64| {
+            if largest > 0.0 {
+                (total / largest).max(0.0)
+            } else {
+                0.0
+            }
+        };
+        rows.push(Row::Currency(currency.clone()));
+        for group in &groups {
+            rows.push(Row::Category {
+                label: group.category.to_string(),
+                amount: amount(group.total, group.exponent),
+                share: share(group.total),
+                subcategory: false,
+            });
+            if group.children.is_empty() {
+                continue;
+            }
+            let mut parts: Vec<(&str, f64)> = group
+                .children
+                .iter()
+                .map(|c| (c.category.as_str(), c.total))
+                .collect();
+            if let Some(own) = group.own {
+                parts.push(("(own)", own.total));
+            }
+            parts.sort_by(|a, b| b.1.total_cmp(&a.1));
+            rows.extend(parts.into_iter().map(|(label, total)| Row::Category {
+                label: label.to_string(),
+                amount: amount(total, group.exponent),
+                share: share(total),
+                subcategory: true,
+            }));
+        }
+        let exponent = groups.first().map_or(0, |g| g.exponent);
+        rows.push(Row::Total(amount(total, exponent)));
+    }
+    if rows.is_empty() {
+        rows.push(Row::Note("No expenses yet.".to_string()));
+    }
+    if rows.len() > MAX_ROWS {
+        let hidden = rows.len() - (MAX_ROWS - 1);
+        rows.truncate(MAX_ROWS - 1);
+        rows.push(Row::Note(format!("… and {} more rows", hidden)));
+    }
+    rows
+}
+
+/// The report of `summary` as a PNG, [`WIDTH`] pixels wide and as high as
+/// its rows take.
+pub fn render_report(summary: &Summary) -> Vec<u8> {
+    let rows = rows(summary);
+    let height = 2 * PADDING + TITLE_HEIGHT + rows.len() as u32 * ROW_HEIGHT;
+    let mut canvas = Canvas::new(WIDTH, height);
+    let title = format!("Report {}", summary.range.month_name());
+    let title_chars = ((WIDTH - 2 * PADDING) / (ADVANCE * TITLE_SCALE)) as usize;
+    canvas.text(
+        PADDING,
+        PADDING,
+        &ellipsize(&title, title_chars),
+        TITLE_SCALE,
+        TEXT,
+    );
+
+    let text_y = (ROW_HEIGHT - font::HEIGHT * SCALE) / 2;
+    let bar_y = (ROW_HEIGHT - BAR_HEIGHT) / 2;
+    for (i, row) in rows.iter().enumerate() {
+        let y = PADDING + TITLE_HEIGHT + i as u32 * ROW_HEIGHT;
+        let right = WIDTH - PADDING;
+        match row {
+            Row::Currency(currency) => canvas.text(PADDING, y + text_y, currency, SCALE, MUTED),
+            Row::Category {
+                label,
+                amount,
+                share,
+                subcategory,
+            } => {
+                let (label, color, bar) = match subcategory {
+                    false => (ellipsize(label, LABEL_CHARS), TEXT, BAR),
+                    true => (
+                        format!("  {}", ellipsize(label, LABEL_CHARS - 2)),
+                        MUTED,
+                        SUBCATEGORY_BAR,
+                    ),
+                };
+                canvas.text(PADDING, y + text_y, &label, SCALE, color);
+                let width = (BAR_WIDTH as f64 * share).round() as u32;
+                canvas.fill(BAR_X, y + bar_y, width, BAR_HEIGHT, bar);
+                canvas.text_right(right, y + text_y, amount, SCALE, color);
+            }
+            Row::Total(total) => {
+                canvas.fill(PADDING, y, WIDTH - 2 * PADDING, 2, RULE);
+                canvas.text(PADDING, y + text_y, "Total", SCALE, TEXT);
+                canvas.text_right(right, y + text_y, total, SCALE, TEXT);
+            }
+            Row::Note(note) => canvas.text(PADDING, y + text_y, note, SCALE, MUTED),
+        }
+    }
+    png::encode(canvas.width, canvas.height, &canvas.rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::png::tests::decode;
+    use crate::model::{DateRange, ExpenseSource, Model, NewExpense};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use chrono_tz::Tz;
+
+    fn december() -> DateRange {
+        DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        )
+    }
+
+    fn pixel(rgb: &[u8], x: u32, y: u32) -> Color {
+        let at = ((y * WIDTH + x) * 3) as usize;
+        rgb[at..at + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn renders_the_report() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Transportation under Utilities.
+        model.set_category_parent(1, 2, Some(4)).unwrap();
+        let summary = model.summarize(1, december(), Tz::UTC).unwrap();
+        let (width, height, rgb) = decode(&render_report(&summary));
+        // BYN, EUR and USD with a heading, a category and a total each, and
+        // the own spend of Utilities and Transportation under it.
+        let rows = 3 * 3 + 2;
+        assert_eq!(
+            (WIDTH, 2 * PADDING + TITLE_HEIGHT + rows * ROW_HEIGHT),
+            (width, height)
+        );
+        assert_eq!(BACKGROUND, pixel(&rgb, 0, 0));
+        assert!(rgb.chunks(3).any(|p| p == TEXT), "has text");
+        // The largest spend of a currency has the whole bar, the rest less.
+        let bar = |row: u32, x: u32| {
+            let y = PADDING + TITLE_HEIGHT + row * ROW_HEIGHT + ROW_HEIGHT / 2;
+            pixel(&rgb, x, y)
+        };
+        assert_eq!(BAR, bar(1, BAR_X + BAR_WIDTH - 1));
+        assert_eq!(BAR, bar(7, BAR_X + BAR_WIDTH - 1));
+        assert_eq!(SUBCATEGORY_BAR, bar(8, BAR_X + BAR_WIDTH / 2));
+        assert_eq!(BACKGROUND, bar(8, BAR_X + BAR_WIDTH - 1));
+        assert_eq!(SUBCATEGORY_BAR, bar(9, BAR_X));
+        assert_eq!(BACKGROUND, bar(9, BAR_X + BAR_WIDTH / 3));
+        assert_eq!(BACKGROUND, bar(10, BAR_X));
+
+        let summary = model.summarize(2, december(), Tz::UTC).unwrap();
+        let (width, height, _) = decode(&render_report(&summary));
+        assert_eq!(
+            (WIDTH, 2 * PADDING + TITLE_HEIGHT + ROW_HEIGHT),
+            (width, height)
+        );
+    }
+
+    #[test]
+    fn long_names_and_many_rows_are_cut() {
+        assert_eq!("Groceries", ellipsize("Groceries", 9));
+        assert_eq!("Grocer…", ellipsize("Groceries", 7));
+
+        let model = Model::new(true);
+        model.fill_test_data();
+        for i in 0..MAX_ROWS {
+            let name = format!("Category number {} of many", i);
+            let category_id = model.add_category(1, &name, None).unwrap();
+            model
+                .insert_expense(&NewExpense {
+                    account_id: 1,
+                    category_id,
+                    user_id: 1001,
+                    timestamp: Utc.with_ymd_and_hms(2023, 12, 5, 12, 0, 0).unwrap(),
+                    amount: 1.0,
+                    comment: None,
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+        let rows = rows(&model.summarize(1, december(), Tz::UTC).unwrap());
+        assert_eq!(MAX_ROWS, rows.len());
+        assert!(matches!(
+            &rows[MAX_ROWS - 1],
+            Row::Note(note) if note == "… and 11 more rows"
+        ));
+        let png = render_report(&model.summarize(1, december(), Tz::UTC).unwrap());
+        assert_eq!(
+            (
+                WIDTH,
+                2 * PADDING + TITLE_HEIGHT + MAX_ROWS as u32 * ROW_HEIGHT
+            ),
+            (decode(&png).0, decode(&png).1)
+        );
+    }
+}
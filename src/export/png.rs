@@ -0,0 +1,290 @@
+//! Just enough of PNG for drawn images: 8-bit RGB, rows filtered against
+//! the pixel on their left, so that flat areas become runs of zeros, and
+//! deflated with the fixed codes and nothing but runs for matches.
+
+use super::crc::crc32_update;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// 8 bits per channel of RGB.
+const BIT_DEPTH: u8 = 8;
+const COLOR_TYPE: u8 = 2;
+/// Each byte less the one of the pixel on its left.
+const FILTER_SUB: u8 = 1;
+const BYTES_PER_PIXEL: usize = 3;
+
+/// PNG of `width` by `height` pixels, `rgb` holding three bytes for each,
+/// row by row.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let stride = width as usize * BYTES_PER_PIXEL;
+    assert_eq!(stride * height as usize, rgb.len(), "pixels of the size");
+    let mut filtered = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgb.chunks(stride) {
+        filtered.push(FILTER_SUB);
+        filtered.extend_from_slice(&row[..BYTES_PER_PIXEL]);
+        filtered.extend(
+            row.windows(BYTES_PER_PIXEL + 1)
+                .map(|w| w[BYTES_PER_PIXEL].wrapping_sub(w[0])),
+        );
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // No compression, filter or interlace method other than the standard.
+    header.extend_from_slice(&[BIT_DEPTH, COLOR_TYPE, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib(&filtered));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32_update(crc32_update(0, kind), data);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Writes bits from the lowest up, as deflate wants them.
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    current: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn put(&mut self, value: u32, count: u32) {
+        self.current |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// A Huffman code, which starts from its highest bit.
+    fn put_code(&mut self, code: u32, count: u32) {
+        self.put(code.reverse_bits() >> (32 - count), count);
+    }
+
+    /// A literal, a length or the end of the block in the fixed codes.
+    fn put_symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const END_OF_BLOCK: u32 = 256;
+
+/// The first length of each length code from 257 up, and its extra bits.
+const LENGTHS: [(usize, u32); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+/// `data` as a zlib stream of a single fixed-code block, repeats of the
+/// byte before written as matches at a distance of 1.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut bits = Bits::default();
+    // Deflate with a 32 KiB window; the header is a multiple of 31.
+    bits.put(0x78, 8);
+    bits.put(0x01, 8);
+    // The final block, of fixed codes.
+    bits.put(1, 1);
+    bits.put(1, 2);
+    let mut at = 0;
+    while at < data.len() {
+        let run = match at {
+            0 => 0,
+            _ => data[at..]
+                .iter()
+                .take(MAX_MATCH)
+                .take_while(|b| **b == data[at - 1])
+                .count(),
+        };
+        if run < MIN_MATCH {
+            bits.put_symbol(data[at].into());
+            at += 1;
+            continue;
+        }
+        let code = LENGTHS
+            .iter()
+            .rposition(|(first, _)| *first <= run)
+            .expect("runs are 3 long at least");
+        let (first, extra) = LENGTHS[code];
+        bits.put_symbol(257 + code as u32);
+        bits.put((run - first) as u32, extra);
+        // Distance code 0, a distance of 1.
+        bits.put_code(0, 5);
+        at += run;
+    }
+    bits.put_symbol(END_OF_BLOCK);
+    let mut stream = bits.finish();
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65_521;
+    let (a, b) = data.iter().fold((1, 0), |(a, b), byte| {
+        let a = (a + *byte as u32) % MOD;
+        (a, (b + a) % MOD)
+    });
+    (b << 16) | a
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Inflates a stream of [`zlib`], which uses only fixed codes and
+    /// matches at a distance of 1.
+    fn inflate(stream: &[u8]) -> Vec<u8> {
+        let mut at = 16;
+        let mut bit = |count: u32| {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = stream[at / 8];
+                value |= (((byte >> (at % 8)) & 1) as u32) << i;
+                at += 1;
+            }
+            value
+        };
+        assert_eq!((1, 1), (bit(1), bit(2)), "a final block of fixed codes");
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let mut code = 0;
+            let mut count = 0;
+            let symbol = loop {
+                code = (code << 1) | bit(1);
+                count += 1;
+                match (count, code) {
+                    (7, 0..=0x17) => break code + 256,
+                    (8, 0x30..=0xbf) => break code - 0x30,
+                    (8, 0xc0..=0xc7) => break code - 0xc0 + 280,
+                    (9, 0x190..=0x1ff) => break code - 0x190 + 144,
+                    _ => assert!(count < 9, "no code {:b}", code),
+                }
+            };
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                END_OF_BLOCK => break,
+                _ => {
+                    let (first, extra) = LENGTHS[symbol as usize - 257];
+                    let length = first + bit(extra) as usize;
+                    assert_eq!(0, bit(5), "a distance of 1");
+                    let byte = *out.last().unwrap();
+                    out.extend(std::iter::repeat_n(byte, length));
+                }
+            }
+        }
+        out
+    }
+
+    /// The pixels of a PNG of [`encode`], with its width and height.
+    pub fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(SIGNATURE, png[..8]);
+        let mut chunks = Vec::new();
+        let mut at = 8;
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let kind = &png[at + 4..at + 8];
+            let data = &png[at + 8..at + 8 + len];
+            let crc = u32::from_be_bytes(png[at + 8 + len..at + 12 + len].try_into().unwrap());
+            assert_eq!(crc32_update(crc32_update(0, kind), data), crc);
+            chunks.push((kind, data));
+            at += 12 + len;
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(vec![&b"IHDR"[..], b"IDAT", b"IEND"], kinds);
+        let header = chunks[0].1;
+        let width = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        assert_eq!([BIT_DEPTH, COLOR_TYPE, 0, 0, 0], header[8..]);
+
+        let stream = chunks[1].1;
+        let filtered = inflate(stream);
+        let checksum = u32::from_be_bytes(stream[stream.len() - 4..].try_into().unwrap());
+        assert_eq!(adler32(&filtered), checksum);
+        let stride = width as usize * BYTES_PER_PIXEL;
+        let mut rgb = Vec::with_capacity(stride * height as usize);
+        for row in filtered.chunks(stride + 1) {
+            assert_eq!(FILTER_SUB, row[0]);
+            let start = rgb.len();
+            for (i, byte) in row[1..].iter().enumerate() {
+                let left = match i {
+                    0..BYTES_PER_PIXEL => 0,
+                    _ => rgb[start + i - BYTES_PER_PIXEL],
+                };
+                rgb.push(byte.wrapping_add(left));
+            }
+        }
+        (width, height, rgb)
+    }
+
+    #[test]
+    fn round_trip() {
+        // A flat area for runs, and noise for literals.
+        let (width, height) = (300, 4);
+        let rgb: Vec<u8> = (0..width * height * 3)
+            .map(|i| match i % 900 {
+                0..=599 => 200,
+                n => (n * 37 % 251) as u8,
+            })
+            .collect();
+        let png = encode(width, height, &rgb);
+        assert!(png.len() < rgb.len() / 2, "{} bytes", png.len());
+        assert_eq!((width, height, rgb), decode(&png));
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(1, adler32(b""));
+        assert_eq!(0x11e6_0398, adler32(b"Wikipedia"));
+    }
+}
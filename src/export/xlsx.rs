@@ -0,0 +1,333 @@
+//! A minimal Office Open XML workbook: sheets of text, numbers and dates,
+//! each written row by row straight into the zip file. Strings are stored
+//! inline rather than in a shared table so that nothing has to be kept until
+//! the end.
+
+use super::zip::ZipWriter;
+use crate::model::MAX_EXPONENT;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell<'a> {
+    Text(&'a str),
+    /// An amount shown with `exponent` decimals.
+    Amount(f64, u32),
+    DateTime(NaiveDateTime),
+}
+
+/// Style indices in `styles.xml`: the default, the date and time, then one
+/// per number of decimals.
+const DATE_TIME_STYLE: u32 = 1;
+const FIRST_AMOUNT_STYLE: u32 = 2;
+const FIRST_CUSTOM_FORMAT: u32 = 164;
+
+pub struct Workbook<W: Write> {
+    zip: ZipWriter<W>,
+    sheets: Vec<String>,
+    /// Rows written to the open sheet.
+    rows: u32,
+}
+
+impl<W: Write> Workbook<W> {
+    pub fn new(out: W) -> Workbook<W> {
+        Workbook {
+            zip: ZipWriter::new(out),
+            sheets: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    /// Starts a sheet, closing the previous one. Names must be unique.
+    pub fn add_sheet(&mut self, name: &str) -> io::Result<()> {
+        self.end_sheet()?;
+        self.sheets.push(name.to_string());
+        self.rows = 0;
+        self.zip
+            .start_file(&format!("xl/worksheets/sheet{}.xml", self.sheets.len()))?;
+        self.zip.write_all(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+                "<sheetData>"
+            )
+            .as_bytes(),
+        )
+    }
+
+    fn end_sheet(&mut self) -> io::Result<()> {
+        if !self.sheets.is_empty() {
+            self.zip.write_all(b"</sheetData></worksheet>")?;
+        }
+        Ok(())
+    }
+
+    pub fn row(&mut self, cells: &[Cell]) -> io::Result<()> {
+        self.rows += 1;
+        let mut xml = format!(r#"<row r="{}">"#, self.rows);
+        for (column, cell) in cells.iter().enumerate() {
+            let at = format!("{}{}", column_name(column), self.rows);
+            match cell {
+                Cell::Text(text) => xml.push_str(&format!(
+                    r#"<c r="{}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    at,
+                    escape(text)
+                )),
+                Cell::Amount(amount, exponent) => xml.push_str(&format!(
+                    r#"<c r="{}" s="{}"><v>{}</v></c>"#,
+                    at,
+                    FIRST_AMOUNT_STYLE + (*exponent).min(MAX_EXPONENT),
+                    amount
+                )),
+                Cell::DateTime(dt) => xml.push_str(&format!(
+                    r#"<c r="{}" s="{}"><v>{}</v></c>"#,
+                    at,
+                    DATE_TIME_STYLE,
+                    serial(*dt)
+                )),
+            }
+        }
+        xml.push_str("</row>");
+        self.zip.write_all(xml.as_bytes())
+    }
+
+    /// Writes the parts describing the sheets and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_sheet()?;
+        let mut content_types = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+            r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+            r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+            r#"<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+            r#"<Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#,
+        ));
+        let mut workbook = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+            r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+            r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">"#,
+            "<sheets>"
+        ));
+        let mut relationships = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+            r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+        ));
+        for (n, name) in self
+            .sheets
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (i + 1, name))
+        {
+            content_types.push_str(&format!(
+                r#"<Override PartName="/xl/worksheets/sheet{}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+                n
+            ));
+            workbook.push_str(&format!(
+                r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#,
+                escape(name),
+                n,
+                n
+            ));
+            relationships.push_str(&format!(
+                r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{}.xml"/>"#,
+                n, n
+            ));
+        }
+        content_types.push_str("</Types>");
+        workbook.push_str("</sheets></workbook>");
+        relationships.push_str(&format!(
+            r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#,
+            self.sheets.len() + 1
+        ));
+        relationships.push_str("</Relationships>");
+
+        let parts = [
+            ("[Content_Types].xml", content_types),
+            ("_rels/.rels", ROOT_RELATIONSHIPS.to_string()),
+            ("xl/workbook.xml", workbook),
+            ("xl/_rels/workbook.xml.rels", relationships),
+            ("xl/styles.xml", styles()),
+        ];
+        for (name, xml) in parts {
+            self.zip.start_file(name)?;
+            self.zip.write_all(xml.as_bytes())?;
+        }
+        self.zip.finish()
+    }
+}
+
+const ROOT_RELATIONSHIPS: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#,
+    "</Relationships>"
+);
+
+fn styles() -> String {
+    let amounts = 0..=MAX_EXPONENT;
+    let formats: String = amounts
+        .clone()
+        .map(|exponent| {
+            let pattern = match exponent {
+                0 => "0".to_string(),
+                e => format!("0.{}", "0".repeat(e as usize)),
+            };
+            format!(
+                r#"<numFmt numFmtId="{}" formatCode="{}"/>"#,
+                FIRST_CUSTOM_FORMAT + 1 + exponent,
+                pattern
+            )
+        })
+        .collect();
+    let cell_styles: String = amounts
+        .map(|exponent| {
+            format!(
+                r#"<xf numFmtId="{}" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/>"#,
+                FIRST_CUSTOM_FORMAT + 1 + exponent
+            )
+        })
+        .collect();
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+            r#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+            r#"<numFmts count="{count}"><numFmt numFmtId="{date}" formatCode="yyyy-mm-dd hh:mm"/>{formats}</numFmts>"#,
+            r#"<fonts count="1"><font><sz val="11"/><name val="Calibri"/></font></fonts>"#,
+            r#"<fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills>"#,
+            r#"<borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders>"#,
+            r#"<cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs>"#,
+            r#"<cellXfs count="{xfs}"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/>"#,
+            r#"<xf numFmtId="{date}" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/>{cell_styles}</cellXfs>"#,
+            r#"<cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles>"#,
+            "</styleSheet>"
+        ),
+        count = MAX_EXPONENT + 2,
+        date = FIRST_CUSTOM_FORMAT,
+        formats = formats,
+        xfs = FIRST_AMOUNT_STYLE + MAX_EXPONENT + 1,
+        cell_styles = cell_styles,
+    )
+}
+
+/// `A`, `B`, ... `Z`, `AA`, ... for the zero based column.
+fn column_name(column: usize) -> String {
+    let mut name = Vec::new();
+    let mut n = column + 1;
+    while n > 0 {
+        n -= 1;
+        name.push(b'A' + (n % 26) as u8);
+        n /= 26;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ASCII letters")
+}
+
+/// Days since 1899-12-30, the fraction being the time of day, as stored by
+/// spreadsheets.
+fn serial(dt: NaiveDateTime) -> f64 {
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    (dt - epoch).num_seconds() as f64 / 86_400.0
+}
+
+/// Text as XML character data, leaving out control characters XML 1.0 can't
+/// hold.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::zip::read_entries;
+
+    #[test]
+    fn columns_and_serials() {
+        assert_eq!(
+            vec!["A", "Z", "AA", "AZ", "BA"],
+            [0, 25, 26, 51, 52].map(column_name).to_vec()
+        );
+        let dt = NaiveDate::from_ymd_opt(2023, 12, 1)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap();
+        assert_eq!(45261.75, serial(dt));
+        assert_eq!(
+            "a &lt;b&gt; &amp; &quot;c&quot;",
+            escape("a <b> & \"c\"\u{7}")
+        );
+    }
+
+    #[test]
+    fn workbook_parts() {
+        let mut book = Workbook::new(Vec::new());
+        book.add_sheet("Expenses").unwrap();
+        book.row(&[Cell::Text("Date"), Cell::Text("Amount")])
+            .unwrap();
+        book.row(&[
+            Cell::DateTime(
+                NaiveDate::from_ymd_opt(2023, 12, 1)
+                    .unwrap()
+                    .and_hms_opt(6, 0, 0)
+                    .unwrap(),
+            ),
+            Cell::Amount(50.75, 2),
+        ])
+        .unwrap();
+        book.add_sheet("R&D").unwrap();
+        let bytes = book.finish().unwrap();
+
+        let entries = read_entries(&bytes);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            vec![
+                "xl/worksheets/sheet1.xml",
+                "xl/worksheets/sheet2.xml",
+                "[Content_Types].xml",
+                "_rels/.rels",
+                "xl/workbook.xml",
+                "xl/_rels/workbook.xml.rels",
+                "xl/styles.xml",
+            ],
+            names
+        );
+        let part = |name: &str| {
+            let (_, xml) = entries.iter().find(|(n, _)| n == name).unwrap();
+            String::from_utf8(xml.clone()).unwrap()
+        };
+        let sheet = part("xl/worksheets/sheet1.xml");
+        assert!(sheet.ends_with(concat!(
+            r#"<sheetData><row r="1"><c r="A1" t="inlineStr"><is><t xml:space="preserve">Date</t></is></c>"#,
+            r#"<c r="B1" t="inlineStr"><is><t xml:space="preserve">Amount</t></is></c></row>"#,
+            r#"<row r="2"><c r="A2" s="1"><v>45261.25</v></c><c r="B2" s="4"><v>50.75</v></c></row>"#,
+            "</sheetData></worksheet>"
+        )));
+        assert!(part("xl/worksheets/sheet2.xml").ends_with("<sheetData></sheetData></worksheet>"));
+        assert!(part("xl/workbook.xml").contains(concat!(
+            r#"<sheet name="Expenses" sheetId="1" r:id="rId1"/>"#,
+            r#"<sheet name="R&amp;D" sheetId="2" r:id="rId2"/>"#
+        )));
+        assert!(part("[Content_Types].xml").contains("/xl/worksheets/sheet2.xml"));
+        let styles = part("xl/styles.xml");
+        // Two decimals is the third amount style, the fourth one overall.
+        assert!(styles.contains(r#"<numFmt numFmtId="167" formatCode="0.00"/>"#));
+        assert_eq!(
+            (FIRST_AMOUNT_STYLE + MAX_EXPONENT + 1) as usize,
+            styles.matches("<xf ").count() - 1
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Just enough of the zip format for an xlsx file: entries are stored
+//! uncompressed, and each one is written as it comes, its checksum and size
+//! following in a data descriptor.
+
+use std::io::{self, Write};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+/// Sizes and checksum follow the data, names are UTF-8.
+const FLAGS: u16 = 0x0008 | 0x0800;
+const VERSION: u16 = 20;
+/// 1980-01-01 00:00, the earliest time zip can record.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |c, b| {
+        CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+struct Entry {
+    name: String,
+    offset: u32,
+    crc: u32,
+    size: u32,
+}
+
+pub struct ZipWriter<W: Write> {
+    out: W,
+    written: u32,
+    entries: Vec<Entry>,
+    /// The entry being written, if any.
+    open: Option<Entry>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> ZipWriter<W> {
+        ZipWriter {
+            out,
+            written: 0,
+            entries: Vec::new(),
+            open: None,
+        }
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written = u32::try_from(bytes.len())
+            .ok()
+            .and_then(|len| self.written.checked_add(len))
+            .ok_or_else(|| io::Error::other("zip file over 4 GiB"))?;
+        Ok(())
+    }
+
+    fn put_u16(&mut self, value: u16) -> io::Result<()> {
+        self.put(&value.to_le_bytes())
+    }
+
+    fn put_u32(&mut self, value: u32) -> io::Result<()> {
+        self.put(&value.to_le_bytes())
+    }
+
+    /// Starts a new entry, finishing the previous one.
+    pub fn start_file(&mut self, name: &str) -> io::Result<()> {
+        self.finish_file()?;
+        let offset = self.written;
+        self.put_u32(LOCAL_HEADER)?;
+        for value in [VERSION, FLAGS, 0, 0, DOS_DATE] {
+            self.put_u16(value)?;
+        }
+        // Checksum and sizes, in the data descriptor instead.
+        for _ in 0..3 {
+            self.put_u32(0)?;
+        }
+        self.put_u16(name.len() as u16)?;
+        self.put_u16(0)?;
+        self.put(name.as_bytes())?;
+        self.open = Some(Entry {
+            name: name.to_string(),
+            offset,
+            crc: 0,
+            size: 0,
+        });
+        Ok(())
+    }
+
+    fn finish_file(&mut self) -> io::Result<()> {
+        let Some(entry) = self.open.take() else {
+            return Ok(());
+        };
+        self.put_u32(DATA_DESCRIPTOR)?;
+        self.put_u32(entry.crc)?;
+        self.put_u32(entry.size)?;
+        self.put_u32(entry.size)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finish_file()?;
+        let start = self.written;
+        let entries = std::mem::take(&mut self.entries);
+        for entry in &entries {
+            self.put_u32(CENTRAL_HEADER)?;
+            for value in [VERSION, VERSION, FLAGS, 0, 0, DOS_DATE] {
+                self.put_u16(value)?;
+            }
+            self.put_u32(entry.crc)?;
+            self.put_u32(entry.size)?;
+            self.put_u32(entry.size)?;
+            self.put_u16(entry.name.len() as u16)?;
+            // Extra field, comment, disk, internal and external attributes.
+            for _ in 0..4 {
+                self.put_u16(0)?;
+            }
+            self.put_u32(0)?;
+            self.put_u32(entry.offset)?;
+            self.put(entry.name.as_bytes())?;
+        }
+        let size = self.written - start;
+        self.put_u32(END_OF_CENTRAL_DIRECTORY)?;
+        for value in [0, 0, entries.len() as u16, entries.len() as u16] {
+            self.put_u16(value)?;
+        }
+        self.put_u32(size)?;
+        self.put_u32(start)?;
+        self.put_u16(0)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for ZipWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.open.is_none() {
+            return Err(io::Error::other("no zip entry started"));
+        }
+        self.put(bytes)?;
+        let entry = self.open.as_mut().expect("checked above");
+        entry.crc = crc32_update(entry.crc, bytes);
+        entry.size += bytes.len() as u32;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The entries of a zip file written by `ZipWriter`, by name, read through
+/// its central directory with their checksums verified.
+#[cfg(test)]
+pub fn read_entries(zip: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let u16_at = |at: usize| u16::from_le_bytes([zip[at], zip[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes(zip[at..at + 4].try_into().unwrap());
+
+    let end = zip.len() - 22;
+    assert_eq!(END_OF_CENTRAL_DIRECTORY, u32_at(end));
+    let count = u16_at(end + 10);
+    let mut at = u32_at(end + 16) as usize;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        assert_eq!(CENTRAL_HEADER, u32_at(at));
+        let crc = u32_at(at + 16);
+        let size = u32_at(at + 24) as usize;
+        let name_len = u16_at(at + 28);
+        let offset = u32_at(at + 42) as usize;
+        let name = String::from_utf8(zip[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+        assert_eq!(LOCAL_HEADER, u32_at(offset));
+        let data = offset + 30 + u16_at(offset + 26) + u16_at(offset + 28);
+        let content = zip[data..data + size].to_vec();
+        assert_eq!(crc, crc32_update(0, &content), "checksum of {}", name);
+        assert_eq!(DATA_DESCRIPTOR, u32_at(data + size));
+
+        entries.push((name, content));
+        at += 46 + name_len;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(0, crc32_update(0, b""));
+        assert_eq!(0xcbf4_3926, crc32_update(0, b"123456789"));
+        assert_eq!(
+            crc32_update(0, b"123456789"),
+            crc32_update(crc32_update(0, b"1234"), b"56789")
+        );
+    }
+
+    #[test]
+    fn writes_and_reads_back() {
+        let mut zip = ZipWriter::new(Vec::new());
+        assert!(zip.write_all(b"orphan").is_err());
+        zip.start_file("a.txt").unwrap();
+        zip.write_all(b"hello ").unwrap();
+        zip.write_all(b"world").unwrap();
+        zip.start_file("dir/empty").unwrap();
+        zip.start_file("ü.xml").unwrap();
+        zip.write_all("<x>ü</x>".as_bytes()).unwrap();
+        let bytes = zip.finish().unwrap();
+
+        assert_eq!(
+            vec![
+                ("a.txt".to_string(), b"hello world".to_vec()),
+                ("dir/empty".to_string(), Vec::new()),
+                ("ü.xml".to_string(), "<x>ü</x>".as_bytes().to_vec()),
+            ],
+            read_entries(&bytes)
+        );
+    }
+}
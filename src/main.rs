@@ -1,23 +1,33 @@
+mod bot;
+mod config;
+mod export;
+mod model;
+mod time;
+
+use config::Config;
+use model::{Model, Role, DB_FILE, DEFAULT_HOUSEHOLD};
+use std::path::{self, PathBuf};
 use std::sync::{Arc, Mutex};
-use chrono::Utc;
 use teloxide::prelude::*;
-use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
-
-struct MyLogic {
-    x: i32
-}
-
-impl MyLogic {
-    pub fn new() -> Self {
-        MyLogic { x: 123 }
-    }
 
-    pub fn my_get(&self) -> i32 {
-        self.x
+/// `--restore <path>` and whether `--force` is given with it.
+fn restore_args(args: impl Iterator<Item = String>) -> Result<Option<(PathBuf, bool)>, String> {
+    let (mut backup, mut force) = (None, false);
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--restore" => match args.next() {
+                Some(path) => backup = Some(PathBuf::from(path)),
+                None => return Err("--restore needs the path of a backup".to_string()),
+            },
+            "--force" => force = true,
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
     }
-
-    pub fn my_change(&mut self) {
-        self.x += 1;
+    match backup {
+        Some(backup) => Ok(Some((backup, force))),
+        None if force => Err("--force only goes with --restore".to_string()),
+        None => Ok(None),
     }
 }
 
@@ -26,119 +36,130 @@ async fn main() {
     pretty_env_logger::init();
     log::info!("Starting Spending Tracker bot...");
 
-    let bot = Bot::from_env();
-    let business_logic = Arc::new(Mutex::new(MyLogic::new()));
-
-    Dispatcher::builder(
-        bot.clone(),
-        dptree::entry()
-            .branch(Update::filter_message().endpoint(handle_message))
-            .branch(Update::filter_callback_query().endpoint(handle_callback_query)),
-    )
-    .dependencies(dptree::deps![business_logic])
-    .enable_ctrlc_handler()
-    .build()
-    .dispatch()
-    .await;
-}
-
-async fn handle_message(
-    bot: Bot,
-    message: Message,
-    x: Arc<Mutex<MyLogic>>
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    {
-        let mut lock = x.lock().unwrap();
-        lock.my_change();
+    let config = Config::from_env();
+    match restore_args(std::env::args()) {
+        Ok(None) => {}
+        Ok(Some((backup, force))) => {
+            let db = &config.db_path;
+            if let Err(e) = model::restore(db, &backup, force, chrono::Utc::now()) {
+                log::error!("Restoring from {} failed: {}", backup.display(), e);
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
     }
 
-    if let Some(text) = message.text() {
-        if let Ok(amount) = text.parse::<f64>() {
-            let timestamp = Utc::now().to_rfc3339();
-            let user = message
-                .from()
-                .and_then(|u| u.username.clone())
-                .unwrap_or_else(|| "unknown".to_string());
-            let account = "default_account".to_string();
-            let currency = "USD".to_string();
-
-            // Inline keyboard for editing properties
-            let keyboard = InlineKeyboardMarkup::new(vec![
-                vec![InlineKeyboardButton::callback(
-                    format!("Amount: {}", amount),
-                    "edit_amount",
-                )],
-                vec![InlineKeyboardButton::callback(
-                    format!("Timestamp: {}", timestamp),
-                    "edit_timestamp",
-                )],
-                vec![InlineKeyboardButton::callback(
-                    format!("Account: {}", account),
-                    "edit_account",
-                )],
-                vec![InlineKeyboardButton::callback(
-                    format!("Currency: {}", currency),
-                    "edit_currency",
-                )],
-                vec![InlineKeyboardButton::callback("Commit", "commit")],
-            ]);
-
-            // Send message with inline keyboard
-            bot.send_message(
-                message.chat.id,
-                "Expense created! Edit properties if needed:",
-            )
-            .reply_markup(keyboard)
-            .await?;
-        } else {
-            bot.send_message(message.chat.id, "Please send a valid amount.")
-                .await?;
+    let legacy = path::absolute(DB_FILE).unwrap();
+    let migrate_from = config.db_migrate_from.as_deref();
+    if let Err(e) = model::locate(&config.db_path, &legacy, migrate_from) {
+        log::error!("Refusing to start: {}", e);
+        std::process::exit(1);
+    }
+    let mut model = Model::open(&config.db_path);
+    let problems = model.startup_problems().unwrap();
+    if !problems.is_empty() {
+        for problem in &problems {
+            log::error!("Integrity check: {}", problem);
         }
+        log::error!("The database is damaged, refusing to start. Restore it from a backup or fix the rows above.");
+        std::process::exit(1);
     }
-    Ok(())
-}
+    model.set_amount_limits(config.amount_limits());
+    match config.admin_id {
+        // The admin has the role from the last start, for /readonly off.
+        Some(_) if model.read_only().unwrap() => {
+            log::warn!("The bot is read-only, changes wait for /readonly off")
+        }
+        Some(admin_id) => model
+            .set_role(DEFAULT_HOUSEHOLD, admin_id, Role::Admin)
+            .unwrap(),
+        None => log::warn!("ST_ADMIN_ID is not set, nobody can manage roles"),
+    }
+    let model = Arc::new(Mutex::new(model));
+    let config = Arc::new(config);
+    // Drafts don't survive a restart, their messages are expired below.
+    let orphans = match model
+        .lock()
+        .unwrap()
+        .take_draft_messages(chrono::Utc::now())
+    {
+        Ok(orphans) => orphans,
+        Err(e) => {
+            log::warn!("Can't look up the drafts left open: {}", e);
+            Vec::new()
+        }
+    };
+    let drafts: bot::SharedDrafts = Arc::new(bot::Drafts::journaled(model.clone()));
+    let (reporter, reports) = bot::ErrorReporter::new();
+    let feeds: bot::SharedFeeds = Arc::new(bot::Feeds::new(config.timezone, reporter.clone()));
+    let hints: bot::SharedHints = Arc::new(bot::Hints::default());
+    let callbacks: bot::SharedCallbacks = Arc::default();
+    let coordinator: bot::SharedCoordinator = Arc::default();
 
-async fn handle_callback_query(
-    bot: Bot,
-    q: CallbackQuery,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if let Some(data) = q.data.clone() {
-        match data.as_str() {
-            "edit_amount" => {
-                bot.send_message(q.from.id, "Send the new amount:").await?;
-                // Implement logic to handle the next message as the new amount
-            }
-            "edit_timestamp" => {
-                bot.send_message(q.from.id, "Send the new timestamp:")
-                    .await?;
-                // Implement logic to handle the next message as the new timestamp
-            }
-            "edit_account" => {
-                bot.send_message(q.from.id, "Send the new account:").await?;
-                // Implement logic to handle the next message as the new account
-            }
-            "edit_currency" => {
-                bot.send_message(q.from.id, "Send the new currency:")
-                    .await?;
-                // Implement logic to handle the next message as the new currency
-            }
-            "commit" => {
-                // Save to DB (implement SQLite logic here)
-                bot.send_message(q.from.id, "Expense saved successfully!")
-                    .await?;
+    let bot = bot::create_bot();
+    tokio::spawn(bot::deliver_errors(bot.clone(), config.admin_id, reports));
+    bot::spawn(
+        &reporter,
+        "draft cleanup",
+        bot::expire_orphan_drafts(bot.clone(), orphans),
+    );
+
+    bot::register_commands(&bot, config.admin_id).await;
+    let mut scheduler = bot::Scheduler::new(model.clone(), reporter.clone());
+    let tz = config.timezone;
+    scheduler
+        .register("callback sweep", bot::SWEEP_EVERY, {
+            let model = model.clone();
+            move |now| std::future::ready(bot::sweep(&model, now))
+        })
+        .unwrap();
+    scheduler
+        .register("maintenance", bot::SWEEP_EVERY, {
+            let (model, coordinator, reporter) =
+                (model.clone(), coordinator.clone(), reporter.clone());
+            let retention = config.retention();
+            move |now| {
+                bot::maintain_database(
+                    model.clone(),
+                    coordinator.clone(),
+                    (now, tz),
+                    retention.clone(),
+                    reporter.clone(),
+                )
             }
-            _ => {
-                bot.send_message(q.from.id, "Unknown action.").await?;
+        })
+        .unwrap();
+    scheduler
+        .register("report delivery", bot::DELIVER_EVERY, {
+            let model = model.clone();
+            move |now| std::future::ready(bot::queue_reports(&model, (now, tz)))
+        })
+        .unwrap();
+    scheduler
+        .register("notification queue", bot::DRAIN_EVERY, {
+            let (bot, model, reporter) = (bot.clone(), model.clone(), reporter.clone());
+            move |now| {
+                bot::drain_notifications(bot.clone(), model.clone(), (now, tz), reporter.clone())
             }
-        }
+        })
+        .unwrap();
+    bot::spawn(&reporter, "scheduler", scheduler.run());
 
-        bot.answer_callback_query(&q.id.clone()).await?;
-
-        // Edit text of the message to which the buttons were attached
-        if let Some(message) = q.regular_message() {
-            bot.edit_message_text(message.chat.id, message.id, String::from("new text"))
-                .await?;
-        }
-    }
-    Ok(())
+    Dispatcher::builder(bot, bot::schema())
+        .dependencies(dptree::deps![
+            model,
+            config,
+            drafts,
+            feeds,
+            hints,
+            callbacks,
+            coordinator
+        ])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
 }
@@ -1,40 +1,196 @@
+mod account_deletion;
+mod accounts;
+mod aliases;
+mod amount;
+mod amount_migration;
+mod archive;
+mod audit;
+mod balance;
+mod budgets;
+mod bulk;
+mod categories;
+mod category_detail;
+mod chats;
+mod closing;
+mod comment_templates;
+mod comments;
+mod confirmations;
+mod connection;
+mod coordinator;
+mod currencies;
+mod daily_limits;
+mod draft_messages;
+mod error;
+mod expenses;
+mod export;
+mod expression;
+mod favorites;
+mod forecast;
+mod fun;
+mod households;
+mod integrity;
+mod lengths;
+mod listing;
+mod maintenance;
+mod notifications;
+mod payloads;
+mod pending_notifications;
+mod period;
+mod presets;
+mod rates;
+mod read_only;
+mod reconcile;
+mod relocation;
+mod report_exclusions;
+mod report_subscriptions;
+mod resolve;
+mod restore;
+mod retention;
+mod review;
+mod rules;
+mod schedule;
 mod schema;
+mod settings;
+mod setup;
+mod shares;
+mod sources;
+mod stats;
+mod summary;
+#[cfg(test)]
 mod test_data;
+mod users;
+mod weekdays;
+mod year;
+
+pub use account_deletion::DependencyReport;
+pub use accounts::{Account, RecentExpense};
+pub use amount::{
+    from_minor, parse_balance, to_minor, AmountError, AmountLimits, Locale, ParsedAmount,
+    GROUP_SPACES,
+};
+pub use amount_migration::{AmountMigrationReport, AmountMismatch, MAX_DRIFT};
+pub use archive::archive_file;
+pub use balance::{Balances, GrandTotal};
+pub use budgets::BudgetRemaining;
+pub use bulk::MergeReport;
+pub use categories::Category;
+pub use category_detail::CategoryDetail;
+pub use chats::{ConfirmMode, QuietHours};
+pub use comment_templates::CommentTemplate;
+pub use coordinator::{Coordinator, ExclusivePermit, SharedPermit};
+pub use currencies::MAX_EXPONENT;
+pub use daily_limits::DailyUsage;
+pub use error::{Error, Result};
+pub use expenses::{
+    ExpenseDetails, ExpenseSource, Inserted, Modification, NewExpense, OriginalAmount,
+    SourceMessage,
+};
+pub use favorites::{Favorite, NewFavorite};
+pub use forecast::forecast;
+pub use fun::FunStats;
+pub use households::DEFAULT_HOUSEHOLD;
+pub use integrity::IntegrityReport;
+pub use lengths::{check_comment, MAX_NAME_LEN};
+pub use listing::{ExpenseFilter, ExpenseSort};
+pub use maintenance::{Checkpoint, MaintenanceReport};
+pub use notifications::Subscription;
+pub use period::{parse_month, Calendar, DateRange, Period};
+pub use presets::{preset, Preset, PRESETS};
+pub use rates::Rate;
+pub use reconcile::Reconciliation;
+pub use relocation::locate;
+pub use report_subscriptions::{Cadence, ReportSubscription};
+pub use resolve::Resolution;
+pub use restore::restore;
+pub use retention::{PruneReport, Retained, RetentionPolicy};
+pub use review::{Completeness, ReviewReason, ReviewRules};
+pub use rules::Rule;
+pub use settings::{Setting, Settings};
+pub use setup::{Setup, SetupStep};
+pub use shares::Debt;
+pub use sources::{SourceId, TrustedSource};
+pub use stats::{CategoryStats, STATS_WINDOW};
+pub use summary::{CategoryGroup, MonthToDate, ReportDate, Summary};
+pub use users::{Role, User};
+pub use weekdays::WeekdayAverages;
+pub use year::YearSummary;
 
 use log::*;
-use rusqlite;
-use std::path;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Where the bot keeps its data, relative to the working directory.
+pub const DB_FILE: &str = "./spending-tracker.db";
 
 pub struct Model {
     connection: rusqlite::Connection,
+    /// Checked again on every insert, whatever the caller validated.
+    amount_limits: AmountLimits,
+    /// Compiled category rules per household, dropped when they change.
+    rule_sets: RefCell<HashMap<i64, rules::RuleSet>>,
 }
 
 impl Model {
+    /// The bot itself opens the file configured with `ST_DB_PATH`.
+    #[cfg(test)]
     pub fn new(in_memory: bool) -> Model {
         info!("Creating model...");
 
-        let conn = if in_memory {
-            info!("Use in memory connection");
-            rusqlite::Connection::open_in_memory().unwrap()
-        } else {
-            let path = path::absolute("./spending-tracker.db").unwrap();
-            info!("Use file: {}", path.display());
-            rusqlite::Connection::open(path).unwrap()
-        };
+        if !in_memory {
+            return Model::open(&std::path::absolute(DB_FILE).unwrap());
+        }
+        info!("Use in memory connection");
+        Model::with_connection(connection::open_connection(connection::Storage::InMemory).unwrap())
+    }
 
-        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+    /// Opens the database file at `path`, creating and migrating it as
+    /// needed.
+    pub fn open(path: &Path) -> Model {
+        info!("Use file: {}", path.display());
+        Model::with_connection(
+            connection::open_connection(connection::Storage::File(path)).unwrap(),
+        )
+    }
 
+    fn with_connection(conn: rusqlite::Connection) -> Model {
         schema::init_schema(&conn);
 
         info!("Model created successfully");
 
-        Model { connection: conn }
+        Model {
+            connection: conn,
+            amount_limits: AmountLimits::default(),
+            rule_sets: RefCell::default(),
+        }
     }
 
-    //#[cfg(test)]
+    pub fn set_amount_limits(&mut self, limits: AmountLimits) {
+        self.amount_limits = limits;
+    }
+
+    #[cfg(test)]
     pub fn fill_test_data(&self) {
         schema::fill_test_data(&self.connection);
     }
+
+    #[cfg(test)]
+    pub fn fill_second_household(&self) {
+        schema::fill_second_household(&self.connection);
+    }
+
+    /// Adds a category at the end of the household's ones, returns its id.
+    #[cfg(test)]
+    pub fn add_test_category(&self, household: i64, name: &str) -> i64 {
+        self.connection
+            .execute(
+                "INSERT INTO ExpenseCategory (name, householdId, sortingOrder)
+                 SELECT ?1, ?2, COALESCE(MAX(sortingOrder), 0) + 1 FROM ExpenseCategory",
+                rusqlite::params![name, household],
+            )
+            .unwrap();
+        self.connection.last_insert_rowid()
+    }
 }
 
 #[cfg(test)]
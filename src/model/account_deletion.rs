@@ -0,0 +1,296 @@
+//! Deleting accounts created by mistake. Expenses and favorites keep an
+//! account from being deleted, so what refers to it is either reported or
+//! moved to another account of the same currency first.
+
+use super::{audit, Account, Error, Model, Result};
+use log::*;
+
+/// What refers to an account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DependencyReport {
+    pub expenses: usize,
+    /// Expenses moved out to archives, which stay as they were archived.
+    pub archived_expenses: usize,
+    pub favorites: usize,
+    pub reconciliations: usize,
+    /// Trusted sources whose forwards default to the account.
+    pub sources: usize,
+}
+
+impl DependencyReport {
+    /// Whether nothing refers to the account.
+    pub fn is_empty(&self) -> bool {
+        *self == DependencyReport::default()
+    }
+}
+
+impl Model {
+    fn account_of(&self, household: i64, id: i64) -> Result<Account> {
+        self.get_account(household, id)?
+            .ok_or_else(|| Error::NotFound(format!("account {}", id)))
+    }
+
+    /// What refers to the account of the household.
+    pub fn account_dependencies(&self, household: i64, id: i64) -> Result<DependencyReport> {
+        self.account_of(household, id)?;
+        let count = |sql: &str| -> Result<usize> {
+            let count: i64 = self.connection.query_row(sql, [id], |row| row.get(0))?;
+            Ok(count as usize)
+        };
+        let expenses = count("SELECT COUNT(*) FROM Expense WHERE accountId = ?1")?;
+        let tables = self.expense_tables(None)?;
+        let all_expenses =
+            count(&tables.apply("SELECT COUNT(*) FROM {expenses} WHERE accountId = ?1"))?;
+        Ok(DependencyReport {
+            expenses,
+            archived_expenses: all_expenses - expenses,
+            favorites: count("SELECT COUNT(*) FROM Favorite WHERE accountId = ?1")?,
+            reconciliations: count("SELECT COUNT(*) FROM Reconciliation WHERE accountId = ?1")?,
+            sources: count("SELECT COUNT(*) FROM TrustedSource WHERE defaultAccountId = ?1")?,
+        })
+    }
+
+    /// Deletes the account, refused while anything refers to it.
+    pub fn delete_account(&self, household: i64, user_id: i64, id: i64) -> Result<()> {
+        self.check_writable()?;
+        if !self.account_dependencies(household, id)?.is_empty() {
+            return Err(Error::Refused(format!("Account {} is in use.", id)));
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute("DELETE FROM Account WHERE id = ?1", [id])?;
+        audit::record(&tx, user_id, "delete_account", &format!("account {}", id))?;
+        tx.commit()?;
+        info!("Deleted account {}", id);
+        Ok(())
+    }
+
+    /// Moves everything referring to `source_id` to `target_id`, adds its
+    /// initial balance to the target's and deletes it, all or nothing.
+    /// Both must be accounts of the household in the same currency, and the
+    /// source must have no archived expenses. Returns what was moved.
+    pub fn move_and_delete_account(
+        &self,
+        household: i64,
+        user_id: i64,
+        (source_id, target_id): (i64, i64),
+    ) -> Result<DependencyReport> {
+        self.check_writable()?;
+        if source_id == target_id {
+            return Err(Error::Refused(
+                "An account can't be moved into itself.".to_string(),
+            ));
+        }
+        let source = self.account_of(household, source_id)?;
+        let target = self.account_of(household, target_id)?;
+        if source.currency != target.currency {
+            return Err(Error::Refused(format!(
+                "{} is in {} and {} in {}, their expenses can't be moved between them.",
+                source.name, source.currency, target.name, target.currency
+            )));
+        }
+        let report = self.account_dependencies(household, source_id)?;
+        if report.archived_expenses > 0 {
+            return Err(Error::Refused(format!(
+                "{} has archived expenses, which can't be moved.",
+                source.name
+            )));
+        }
+        self.check_expenses_open(household, "e.accountId = ?1", source_id)?;
+
+        let tx = self.connection.unchecked_transaction()?;
+        let ids = [source_id, target_id];
+        let moved = DependencyReport {
+            expenses: tx.execute(
+                "UPDATE Expense SET accountId = ?2 WHERE accountId = ?1",
+                ids,
+            )?,
+            archived_expenses: 0,
+            favorites: tx.execute(
+                "UPDATE Favorite SET accountId = ?2 WHERE accountId = ?1",
+                ids,
+            )?,
+            reconciliations: tx.execute(
+                "UPDATE Reconciliation SET accountId = ?2 WHERE accountId = ?1",
+                ids,
+            )?,
+            sources: tx.execute(
+                "UPDATE TrustedSource SET defaultAccountId = ?2 WHERE defaultAccountId = ?1",
+                ids,
+            )?,
+        };
+        // Bindings of categories are no reason to keep an account, so they
+        // go along without being reported.
+        tx.execute(
+            "UPDATE ExpenseCategory SET defaultAccountId = ?2 WHERE defaultAccountId = ?1",
+            ids,
+        )?;
+        tx.execute(
+            "UPDATE Account SET initialBalanceMinor = initialBalanceMinor
+                 + (SELECT initialBalanceMinor FROM Account WHERE id = ?1)
+             WHERE id = ?2",
+            ids,
+        )?;
+        tx.execute("DELETE FROM Account WHERE id = ?1", [source_id])?;
+        audit::record(
+            &tx,
+            user_id,
+            "delete_account",
+            &format!(
+                "account {} into {}, {} expenses",
+                source_id, target_id, moved.expenses
+            ),
+        )?;
+        tx.commit()?;
+        info!(
+            "Deleted account {}, moving {:?} to account {}",
+            source_id, moved, target_id
+        );
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense, NewFavorite, SourceId};
+    use chrono::{TimeZone, Utc};
+
+    /// An account in EUR like account 1, with something of every kind
+    /// referring to it.
+    fn wired_account(model: &Model) -> i64 {
+        let id = model.add_account(1, "Wallet", 1).unwrap();
+        model
+            .connection
+            .execute(
+                "UPDATE Account SET initialBalanceMinor = 5000 WHERE id = ?1",
+                [id],
+            )
+            .unwrap();
+        for amount in [10.0, 2.5] {
+            model
+                .insert_expense(&NewExpense {
+                    account_id: id,
+                    category_id: 1,
+                    user_id: 1001,
+                    timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+                    amount,
+                    comment: None,
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+        model
+            .add_favorite(
+                1,
+                &NewFavorite {
+                    user_id: 1001,
+                    label: "coffee".to_string(),
+                    amount: 3.0,
+                    account_id: id,
+                    category_id: 1,
+                    comment: None,
+                },
+            )
+            .unwrap();
+        model.reconcile(1, id, 3750, Utc::now()).unwrap();
+        model
+            .add_trusted_source(1, SourceId::Chat(-1), 4, id)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn unused_accounts_are_deleted() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let id = model.add_account(1, "Typo", 1).unwrap();
+        assert!(model.account_dependencies(1, id).unwrap().is_empty());
+        assert!(matches!(
+            model.account_dependencies(2, id),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.delete_account(2, 1001, id),
+            Err(Error::NotFound(_))
+        ));
+        model.delete_account(1, 1001, id).unwrap();
+        assert_eq!(None, model.get_account(1, id).unwrap());
+    }
+
+    #[test]
+    fn used_accounts_are_kept() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let id = wired_account(&model);
+        assert_eq!(
+            DependencyReport {
+                expenses: 2,
+                archived_expenses: 0,
+                favorites: 1,
+                reconciliations: 1,
+                sources: 1,
+            },
+            model.account_dependencies(1, id).unwrap()
+        );
+        assert!(matches!(
+            model.delete_account(1, 1001, id),
+            Err(Error::Refused(_))
+        ));
+        assert!(model.get_account(1, id).unwrap().is_some());
+    }
+
+    #[test]
+    fn everything_moves_before_deleting() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let id = wired_account(&model);
+        let before = model.balances(1, "EUR").unwrap().currencies;
+
+        // Hanna Daily is in USD.
+        assert!(matches!(
+            model.move_and_delete_account(1, 1001, (id, 2)),
+            Err(Error::Refused(reason)) if reason.contains("USD")
+        ));
+        assert!(matches!(
+            model.move_and_delete_account(1, 1001, (id, id)),
+            Err(Error::Refused(_))
+        ));
+        let moved = model.move_and_delete_account(1, 1001, (id, 1)).unwrap();
+        assert_eq!(
+            DependencyReport {
+                expenses: 2,
+                archived_expenses: 0,
+                favorites: 1,
+                reconciliations: 1,
+                sources: 1,
+            },
+            moved
+        );
+        assert_eq!(None, model.get_account(1, id).unwrap());
+
+        let count =
+            |sql: &str| -> i64 { model.connection.query_row(sql, [], |r| r.get(0)).unwrap() };
+        assert_eq!(3, count("SELECT COUNT(*) FROM Expense WHERE accountId = 1"));
+        assert_eq!(
+            1,
+            count("SELECT COUNT(*) FROM Favorite WHERE accountId = 1")
+        );
+        assert_eq!(
+            1,
+            count("SELECT COUNT(*) FROM Reconciliation WHERE accountId = 1")
+        );
+        assert_eq!(
+            1,
+            model
+                .trusted_source(1, SourceId::Chat(-1))
+                .unwrap()
+                .unwrap()
+                .account_id
+        );
+        // The money is where it was, in one account instead of two.
+        assert_eq!(before, model.balances(1, "EUR").unwrap().currencies);
+        assert!(model.check_integrity().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+use super::amount::from_minor;
+use super::lengths::{check_length, MAX_NAME_LEN};
+use super::resolve::{self, Resolution};
+use super::{Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use rusqlite::OptionalExtension;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub id: i64,
+    /// Display name if set, the plain name otherwise.
+    pub name: String,
+    pub currency: String,
+    /// Decimal places of the currency.
+    pub exponent: u32,
+}
+
+/// A recent expense on an account, to remind which account was used lately.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentExpense {
+    pub account_id: i64,
+    pub amount: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+const SELECT_ACCOUNT: &str = "
+    SELECT a.id, COALESCE(a.displayName, a.name), c.name, c.exponent
+    FROM Account a
+    JOIN Currency c ON c.id = a.currencyId
+";
+
+impl Account {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+        Ok(Account {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            currency: row.get(2)?,
+            exponent: row.get(3)?,
+        })
+    }
+}
+
+impl Model {
+    /// Adds an account in the currency `currency_id`, returns its id.
+    pub fn add_account(&self, household: i64, name: &str, currency_id: i64) -> Result<i64> {
+        self.check_writable()?;
+        check_length("Account name", name, MAX_NAME_LEN)?;
+        self.connection.execute(
+            "INSERT INTO Account (name, currencyId, householdId) VALUES (?1, ?2, ?3)",
+            rusqlite::params![name, currency_id, household],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    pub fn accounts(&self, household: i64) -> Result<Vec<Account>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE a.householdId = ?1 ORDER BY a.id",
+            SELECT_ACCOUNT
+        ))?;
+        let accounts = stmt
+            .query_map([household], Account::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(accounts)
+    }
+
+    /// Resolves a typed name to an account, by its name or display name.
+    pub fn resolve_account(&self, household: i64, name: &str) -> Result<Resolution<Account>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT a.id, COALESCE(a.displayName, a.name), c.name, c.exponent, a.name
+             FROM Account a
+             JOIN Currency c ON c.id = a.currencyId
+             WHERE a.householdId = ?1
+             ORDER BY a.id",
+        )?;
+        let candidates = stmt
+            .query_map([household], |row| {
+                let account = Account::from_row(row)?;
+                let names = vec![row.get(4)?, account.name.clone()];
+                Ok((account, names))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(resolve::resolve(name, candidates))
+    }
+
+    /// The latest `limit_per_account` expenses of every account, newest
+    /// first within an account. Accounts without expenses are left out.
+    pub fn last_expense_per_account(
+        &self,
+        household: i64,
+        limit_per_account: usize,
+    ) -> Result<Vec<RecentExpense>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT accountId, amountMinor, exponent, timestamp FROM (
+                 SELECT e.accountId, e.amountMinor, c.exponent, e.timestamp,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY e.accountId ORDER BY e.timestamp DESC, e.id DESC
+                        ) AS n
+                 FROM Expense e
+                 JOIN Account a ON a.id = e.accountId
+                 JOIN Currency c ON c.id = a.currencyId
+                 WHERE a.householdId = ?2
+             )
+             WHERE n <= ?1
+             ORDER BY accountId, n",
+        )?;
+        let expenses = stmt
+            .query_map([limit_per_account as i64, household], |row| {
+                Ok(RecentExpense {
+                    account_id: row.get(0)?,
+                    amount: from_minor(row.get(1)?, row.get(2)?),
+                    timestamp: row.get::<_, DbTime>(3)?.0,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(expenses)
+    }
+    /// The account the user last logged an expense on in the household,
+    /// back-dated ones counting when they were logged.
+    pub fn last_account(&self, household: i64, user_id: i64) -> Result<Option<i64>> {
+        let id = self
+            .connection
+            .query_row(
+                "SELECT e.accountId FROM Expense e
+                 JOIN Account a ON a.id = e.accountId
+                 WHERE a.householdId = ?1 AND e.userId = ?2
+                 ORDER BY e.createdAt DESC, e.id DESC
+                 LIMIT 1",
+                [household, user_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// The account if it belongs to the household.
+    pub fn get_account(&self, household: i64, id: i64) -> Result<Option<Account>> {
+        let account = self
+            .connection
+            .query_row(
+                &format!("{} WHERE a.id = ?1 AND a.householdId = ?2", SELECT_ACCOUNT),
+                [id, household],
+                Account::from_row,
+            )
+            .optional()?;
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved_name(resolution: Resolution<Account>) -> Option<String> {
+        match resolution {
+            Resolution::Exact(a) | Resolution::UniquePrefix(a) => Some(a.name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn resolves_name_or_display_name() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let resolve = |name| resolved_name(model.resolve_account(1, name).unwrap());
+        assert_eq!(Some("Family BYN".to_string()), resolve("family"));
+        assert_eq!(Some("Family BYN".to_string()), resolve("family fund"));
+        assert_eq!(Some("Alex Savings".to_string()), resolve("SAVINGS"));
+        assert_eq!(None, resolve("bank"));
+    }
+
+    #[test]
+    fn last_expenses_per_account() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let last = |limit| -> Vec<(i64, f64)> {
+            model
+                .last_expense_per_account(1, limit)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.account_id, e.amount))
+                .collect()
+        };
+        assert_eq!(vec![(1, 50.75), (2, 100.0), (3, 30.0)], last(1));
+        assert_eq!(vec![(1, 50.75), (2, 100.0), (2, 20.0), (3, 30.0)], last(2));
+        assert!(last(0).is_empty());
+    }
+
+    #[test]
+    fn last_account_of_a_user() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let last = |user_id| model.last_account(1, user_id).unwrap();
+        assert_eq!(
+            (Some(3), Some(2), None),
+            (last(1001), last(1002), last(1003))
+        );
+
+        let back_dated = crate::model::NewExpense {
+            amount: 5.0,
+            account_id: 1,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            comment: None,
+            category_confirmed: true,
+            source: crate::model::ExpenseSource::Manual,
+        };
+        model.insert_expense_once(1, "add:1", &back_dated).unwrap();
+        assert_eq!(Some(1), last(1001));
+        assert_eq!(None, model.last_account(2, 1001).unwrap());
+    }
+}
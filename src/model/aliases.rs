@@ -0,0 +1,94 @@
+//! Per-user command aliases, stored with the settings under `alias:<name>`.
+
+use super::{Model, Result};
+use rusqlite::{params, OptionalExtension};
+
+const PREFIX: &str = "alias:";
+
+impl Model {
+    /// The user's aliases and what they expand to, by name.
+    pub fn aliases(&self, user_id: i64) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT substr(key, ?2), value FROM UserSetting
+             WHERE userId = ?1 AND key LIKE 'alias:%'
+             ORDER BY key",
+        )?;
+        let aliases = stmt
+            .query_map(params![user_id, PREFIX.len() + 1], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(aliases)
+    }
+
+    pub fn alias(&self, user_id: i64, name: &str) -> Result<Option<String>> {
+        let expansion = self
+            .connection
+            .query_row(
+                "SELECT value FROM UserSetting WHERE userId = ?1 AND key = ?2",
+                params![user_id, format!("{}{}", PREFIX, name)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(expansion)
+    }
+
+    /// Adds the alias or replaces what it expands to.
+    pub fn set_alias(&self, user_id: i64, name: &str, expansion: &str) -> Result<()> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT INTO UserSetting (userId, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (userId, key) DO UPDATE SET value = excluded.value",
+            params![user_id, format!("{}{}", PREFIX, name), expansion],
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether there was such an alias.
+    pub fn delete_alias(&self, user_id: i64, name: &str) -> Result<bool> {
+        self.check_writable()?;
+        let deleted = self.connection.execute(
+            "DELETE FROM UserSetting WHERE userId = ?1 AND key = ?2",
+            params![user_id, format!("{}{}", PREFIX, name)],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Setting, Settings};
+
+    #[test]
+    fn aliases_are_per_user_and_apart_from_settings() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        model.set_alias(1001, "rl", "report lastmonth").unwrap();
+        model.set_alias(1001, "bal", "balance").unwrap();
+        model.set_alias(1001, "rl", "report month").unwrap();
+        model
+            .set_setting(1001, Setting::MonthToDate(false))
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("bal".to_string(), "balance".to_string()),
+                ("rl".to_string(), "report month".to_string()),
+            ],
+            model.aliases(1001).unwrap()
+        );
+        assert_eq!(None, model.alias(1002, "rl").unwrap());
+        assert_eq!(
+            Settings {
+                month_to_date: false,
+                ..Settings::default()
+            },
+            model.get_settings(1001).unwrap()
+        );
+
+        assert!(model.delete_alias(1001, "rl").unwrap());
+        assert!(!model.delete_alias(1001, "rl").unwrap());
+        assert_eq!(None, model.alias(1001, "rl").unwrap());
+    }
+}
@@ -0,0 +1,487 @@
+//! Sanity checks for expense amounts, shared by user input parsing and the
+//! model so that nothing invalid reaches the database.
+
+use super::expression::{self, Calculation, Fraction};
+use thiserror::Error;
+
+/// Bounds an amount must stay within.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmountLimits {
+    /// Largest accepted amount, in major units.
+    pub max: f64,
+    /// Decimal places the currency allows.
+    pub decimals: u32,
+}
+
+impl Default for AmountLimits {
+    fn default() -> AmountLimits {
+        AmountLimits {
+            max: 1_000_000.0,
+            decimals: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum AmountError {
+    #[error("'{0}' is not a valid amount.")]
+    NotANumber(String),
+    #[error("The amount must be a finite number.")]
+    NotFinite,
+    #[error("The amount must be positive, income and refunds can't be logged yet.")]
+    Negative,
+    #[error("The amount can't be zero.")]
+    Zero,
+    #[error("The amount is over the limit of {0}.")]
+    TooLarge(f64),
+    #[error("The amount can have at most {0} decimal places.")]
+    TooPrecise(u32),
+    #[error("'{0}' is not a valid calculation, only numbers, + - * / and parentheses work.")]
+    InvalidCalculation(String),
+    #[error("The calculation divides by zero.")]
+    DivisionByZero,
+}
+
+/// How a user writes numbers, from their Telegram language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    /// `1,234.56`
+    #[default]
+    DecimalPoint,
+    /// `1.234,56`
+    DecimalComma,
+}
+
+/// Languages writing `1.234,56` or `1 234,56`.
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "az", "be", "bg", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "kk",
+    "lt", "lv", "nb", "nl", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "uz",
+];
+
+/// Spaces Telegram clients and keyboards put between groups of thousands.
+/// Plain spaces are not among them: they separate the amount from a comment.
+pub const GROUP_SPACES: [char; 3] = ['\u{a0}', '\u{202f}', '\u{2009}'];
+
+impl Locale {
+    /// The locale of an IETF language tag like `ru` or `pt-br`.
+    pub fn from_language(code: Option<&str>) -> Locale {
+        let language = code
+            .and_then(|c| c.split(['-', '_']).next())
+            .map(str::to_lowercase);
+        match language {
+            Some(l) if DECIMAL_COMMA_LANGUAGES.contains(&l.as_str()) => Locale::DecimalComma,
+            _ => Locale::DecimalPoint,
+        }
+    }
+
+    /// The decimal and the grouping separator.
+    pub(super) fn separators(self) -> (char, char) {
+        match self {
+            Locale::DecimalPoint => ('.', ','),
+            Locale::DecimalComma => (',', '.'),
+        }
+    }
+}
+
+/// A typed amount, with the other way to read it when the separators are
+/// ambiguous, like `1,234` being either 1234 or 1.234.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedAmount {
+    pub amount: f64,
+    pub alternative: Option<f64>,
+    /// The calculation the amount was typed as, if any.
+    pub calculation: Option<Calculation>,
+}
+
+/// Splits unsigned `digits` written with `decimal` before the decimals and
+/// `group` or a space between groups of thousands into the integer digits
+/// and the decimals, `None` if it doesn't fit.
+pub(super) fn number_parts(digits: &str, decimal: char, group: char) -> Option<(String, &str)> {
+    let (integer, fraction) = match digits.split_once(decimal) {
+        Some((integer, fraction)) => (integer, fraction),
+        None => (digits, ""),
+    };
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if !all_digits(fraction) || (digits.contains(decimal) && fraction.is_empty()) {
+        return None;
+    }
+
+    let groups: Vec<&str> = integer
+        .split(|c| c == group || GROUP_SPACES.contains(&c))
+        .collect();
+    let grouped = match groups.as_slice() {
+        [single] => all_digits(single) && !(single.is_empty() && fraction.is_empty()),
+        [first, rest @ ..] => {
+            (1..=3).contains(&first.len())
+                && !first.starts_with('0')
+                && all_digits(first)
+                && rest.iter().all(|g| g.len() == 3 && all_digits(g))
+        }
+        [] => false,
+    };
+    grouped.then(|| (groups.concat(), fraction))
+}
+
+/// Reads `text` as a number, `None` if it doesn't fit. See `number_parts`.
+fn read_number(text: &str, decimal: char, group: char) -> Option<f64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let (integer, fraction) = number_parts(digits, decimal, group)?;
+    format!("{}{}.{}0", sign, integer, fraction).parse().ok()
+}
+
+/// A typed account balance in minor units of a currency with `exponent`
+/// decimal places, which unlike an amount may be zero or negative:
+/// separators as `locale` writes them, or else the other way. `None` for
+/// more decimals than the currency has.
+pub fn parse_balance(text: &str, locale: Locale, exponent: u32) -> Option<i64> {
+    let (decimal, group) = locale.separators();
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (integer, fraction) =
+        number_parts(digits, decimal, group).or_else(|| number_parts(digits, group, decimal))?;
+    if fraction.len() > exponent as usize {
+        return None;
+    }
+    let digits = format!(
+        "{}{:0<width$}",
+        integer,
+        fraction,
+        width = exponent as usize
+    );
+    let minor: i64 = digits.parse().ok()?;
+    Some(if negative { -minor } else { minor })
+}
+
+/// `amount` in minor units of a currency with `exponent` decimal places.
+pub fn to_minor(amount: f64, exponent: u32) -> i64 {
+    (amount * 10f64.powi(exponent as i32)).round() as i64
+}
+
+pub fn from_minor(minor: i64, exponent: u32) -> f64 {
+    minor as f64 / 10f64.powi(exponent as i32)
+}
+
+impl AmountLimits {
+    /// The same limits for a currency with `exponent` decimal places.
+    pub fn for_exponent(self, exponent: u32) -> AmountLimits {
+        AmountLimits {
+            decimals: exponent,
+            ..self
+        }
+    }
+
+    pub fn validate(&self, amount: f64) -> Result<f64, AmountError> {
+        if !amount.is_finite() {
+            return Err(AmountError::NotFinite);
+        }
+        if amount < 0.0 {
+            return Err(AmountError::Negative);
+        }
+        if amount == 0.0 {
+            return Err(AmountError::Zero);
+        }
+        if amount > self.max {
+            return Err(AmountError::TooLarge(self.max));
+        }
+        let scaled = amount * 10f64.powi(self.decimals as i32);
+        // Tolerates the representation error of decimal fractions like 0.1.
+        if (scaled - scaled.round()).abs() > 1e-6 {
+            return Err(AmountError::TooPrecise(self.decimals));
+        }
+        Ok(amount)
+    }
+
+    /// `value` rounded to the decimal places, halves up, and validated.
+    fn round(&self, value: Fraction) -> Result<f64, AmountError> {
+        let minor = value
+            .to_minor(self.decimals)
+            .and_then(|minor| i64::try_from(minor).ok())
+            .ok_or(AmountError::TooLarge(self.max))?;
+        self.validate(from_minor(minor, self.decimals))
+    }
+
+    /// Validates an amount parsed with other limits, rounding a calculation
+    /// to these decimal places instead of refusing its extra ones.
+    pub fn validate_parsed(&self, parsed: &ParsedAmount) -> Result<f64, AmountError> {
+        match &parsed.calculation {
+            Some(calculation) => self.round(calculation.value),
+            None => self.validate(parsed.amount),
+        }
+    }
+
+    /// Parses and validates a typed amount. Separators are read the way
+    /// `locale` writes them, falling back to the other way when that's the
+    /// only one that fits. Text with operators is a calculation, whose
+    /// exact result is rounded to the decimal places.
+    pub fn parse(&self, text: &str, locale: Locale) -> Result<ParsedAmount, AmountError> {
+        let text = text.trim();
+        let (decimal, group) = locale.separators();
+        let (amount, alternative) = match (
+            read_number(text, decimal, group),
+            read_number(text, group, decimal),
+        ) {
+            (Some(amount), Some(other)) => (amount, Some(other).filter(|o| *o != amount)),
+            (Some(amount), None) | (None, Some(amount)) => (amount, None),
+            // Exponents, infinities and the like.
+            (None, None) => match text.parse::<f64>() {
+                Ok(amount) => (amount, None),
+                Err(_) if expression::is_calculation(text) => {
+                    let value = expression::evaluate(text, locale)?;
+                    return Ok(ParsedAmount {
+                        amount: self.round(value)?,
+                        alternative: None,
+                        calculation: Some(Calculation {
+                            text: text.to_string(),
+                            value,
+                        }),
+                    });
+                }
+                Err(_) => return Err(AmountError::NotANumber(text.to_string())),
+            },
+        };
+        Ok(ParsedAmount {
+            amount: self.validate(amount)?,
+            alternative,
+            calculation: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(limits: &AmountLimits, text: &str) -> Result<f64, AmountError> {
+        limits.parse(text, Locale::DecimalPoint).map(|p| p.amount)
+    }
+
+    #[test]
+    fn parse_matrix() {
+        let limits = AmountLimits::default();
+        let cases = [
+            ("50.75", Ok(50.75)),
+            (" 12 ", Ok(12.0)),
+            ("0.1", Ok(0.1)),
+            ("0.01", Ok(0.01)),
+            ("5.000", Ok(5.0)),
+            ("1e3", Ok(1000.0)),
+            ("1000000", Ok(1_000_000.0)),
+            ("", Err(AmountError::NotANumber(String::new()))),
+            ("abc", Err(AmountError::NotANumber("abc".to_string()))),
+            ("5,5,0", Err(AmountError::NotANumber("5,5,0".to_string()))),
+            ("NaN", Err(AmountError::NotFinite)),
+            ("inf", Err(AmountError::NotFinite)),
+            ("-inf", Err(AmountError::NotFinite)),
+            ("1e400", Err(AmountError::NotFinite)),
+            ("-5", Err(AmountError::Negative)),
+            ("-0.01", Err(AmountError::Negative)),
+            ("0", Err(AmountError::Zero)),
+            ("-0", Err(AmountError::Zero)),
+            ("0.00", Err(AmountError::Zero)),
+            ("1e300", Err(AmountError::TooLarge(1_000_000.0))),
+            ("1000000.01", Err(AmountError::TooLarge(1_000_000.0))),
+            ("1.005", Err(AmountError::TooPrecise(2))),
+            ("0.001", Err(AmountError::TooPrecise(2))),
+            ("1e-3", Err(AmountError::TooPrecise(2))),
+            ("12.40+3.60", Ok(16.0)),
+            ("10/3", Ok(3.33)),
+            ("(1+2)*4.5", Ok(13.5)),
+            ("2-5", Err(AmountError::Negative)),
+            ("2-2", Err(AmountError::Zero)),
+            ("1/0", Err(AmountError::DivisionByZero)),
+            ("999999*2", Err(AmountError::TooLarge(1_000_000.0))),
+            (
+                "2+x",
+                Err(AmountError::InvalidCalculation("2+x".to_string())),
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(expected, parse(&limits, text), "parsing {:?}", text);
+        }
+    }
+
+    /// `(input, amount, alternative)` read in `locale`, with 3 decimals
+    /// allowed so that both readings of `1,234` validate.
+    fn check_locale(locale: Locale, cases: &[(&str, Result<f64, AmountError>, Option<f64>)]) {
+        let limits = AmountLimits {
+            max: 1e9,
+            decimals: 3,
+        };
+        for (text, amount, alternative) in cases {
+            let parsed = limits.parse(text, locale);
+            assert_eq!(
+                *amount,
+                parsed.as_ref().map(|p| p.amount).map_err(Clone::clone),
+                "{:?} in {:?}",
+                text,
+                locale
+            );
+            if let Ok(parsed) = parsed {
+                assert_eq!(
+                    *alternative, parsed.alternative,
+                    "{:?} in {:?}",
+                    text, locale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decimal_point_locale() {
+        let not_a_number = |s: &str| Err(AmountError::NotANumber(s.to_string()));
+        check_locale(
+            Locale::DecimalPoint,
+            &[
+                ("1234.56", Ok(1234.56), None),
+                ("1,234.56", Ok(1234.56), None),
+                ("1,234,567.5", Ok(1_234_567.5), None),
+                ("1\u{a0}234.56", Ok(1234.56), None),
+                ("1\u{202f}234.56", Ok(1234.56), None),
+                ("1\u{2009}234", Ok(1234.0), None),
+                ("1,234", Ok(1234.0), Some(1.234)),
+                ("12,345", Ok(12345.0), Some(12.345)),
+                ("1.234", Ok(1.234), Some(1234.0)),
+                ("0.5", Ok(0.5), None),
+                (".5", Ok(0.5), None),
+                // Only one way to read these.
+                ("5,50", Ok(5.5), None),
+                ("1\u{a0}234,56", Ok(1234.56), None),
+                ("1.234,56", Ok(1234.56), None),
+                ("12", Ok(12.0), None),
+                ("1,2345", Err(AmountError::TooPrecise(3)), None),
+                ("1,23,456", not_a_number("1,23,456"), None),
+                ("01,234", Ok(1.234), None),
+                ("1,234.56.7", not_a_number("1,234.56.7"), None),
+                ("1 234", not_a_number("1 234"), None),
+                (",", not_a_number(","), None),
+                ("-1,234", Err(AmountError::Negative), None),
+            ],
+        );
+    }
+
+    #[test]
+    fn decimal_comma_locale() {
+        let not_a_number = |s: &str| Err(AmountError::NotANumber(s.to_string()));
+        check_locale(
+            Locale::DecimalComma,
+            &[
+                ("1234,56", Ok(1234.56), None),
+                ("1.234,56", Ok(1234.56), None),
+                ("1.234.567,5", Ok(1_234_567.5), None),
+                ("1\u{a0}234,56", Ok(1234.56), None),
+                ("1\u{202f}234,56", Ok(1234.56), None),
+                ("1\u{2009}234", Ok(1234.0), None),
+                ("1,234", Ok(1.234), Some(1234.0)),
+                ("1.234", Ok(1234.0), Some(1.234)),
+                ("0,5", Ok(0.5), None),
+                (",5", Ok(0.5), None),
+                // Only one way to read these.
+                ("50.75", Ok(50.75), None),
+                ("1,234.56", Ok(1234.56), None),
+                ("1\u{a0}234.56", Ok(1234.56), None),
+                ("12", Ok(12.0), None),
+                ("1.23.456", not_a_number("1.23.456"), None),
+                ("1,234,56", not_a_number("1,234,56"), None),
+                ("1 234,56", not_a_number("1 234,56"), None),
+                ("5,", not_a_number("5,"), None),
+                ("0,001", Ok(0.001), None),
+                ("-1,5", Err(AmountError::Negative), None),
+            ],
+        );
+    }
+
+    #[test]
+    fn calculations_round_once_the_currency_is_known() {
+        let parsed = AmountLimits::default()
+            .for_exponent(6)
+            .parse("10/3", Locale::DecimalComma)
+            .unwrap();
+        assert_eq!("10/3", parsed.calculation.as_ref().unwrap().text);
+        assert_eq!(3.333333, parsed.amount);
+        let limits = AmountLimits::default();
+        assert_eq!(Ok(3.33), limits.validate_parsed(&parsed));
+        assert_eq!(Ok(3.0), limits.for_exponent(0).validate_parsed(&parsed));
+
+        let plain = limits.for_exponent(6).parse("1,005", Locale::DecimalComma);
+        assert_eq!(
+            Err(AmountError::TooPrecise(2)),
+            limits.validate_parsed(&plain.unwrap())
+        );
+    }
+
+    #[test]
+    fn locale_from_language() {
+        assert_eq!(Locale::DecimalPoint, Locale::from_language(None));
+        assert_eq!(Locale::DecimalPoint, Locale::from_language(Some("en")));
+        assert_eq!(Locale::DecimalPoint, Locale::from_language(Some("en-GB")));
+        assert_eq!(Locale::DecimalComma, Locale::from_language(Some("ru")));
+        assert_eq!(Locale::DecimalComma, Locale::from_language(Some("pt-br")));
+        assert_eq!(Locale::DecimalComma, Locale::from_language(Some("DE")));
+    }
+
+    #[test]
+    fn balances_may_be_zero_or_negative() {
+        let point = |text| parse_balance(text, Locale::DecimalPoint, 2);
+        assert_eq!(Some(123456), point("1,234.56"));
+        assert_eq!(Some(0), point("0"));
+        assert_eq!(Some(-1250), point("-12.5"));
+        assert_eq!(Some(-1250), parse_balance("-12,5", Locale::DecimalComma, 2));
+        assert_eq!(None, point("12+3"));
+        assert_eq!(None, point("abc"));
+        // No more decimals than the currency has.
+        assert_eq!(None, point("12.345"));
+        assert_eq!(Some(1500), parse_balance("1500", Locale::DecimalPoint, 0));
+        assert_eq!(None, parse_balance("1500.5", Locale::DecimalPoint, 0));
+        assert_eq!(
+            Some(12345),
+            parse_balance("12.345", Locale::DecimalPoint, 3)
+        );
+    }
+
+    #[test]
+    fn minor_units() {
+        assert_eq!(5075, to_minor(50.75, 2));
+        assert_eq!(10, to_minor(0.1, 2));
+        assert_eq!(1999, to_minor(19.99, 2));
+        assert_eq!(1500, to_minor(1500.0, 0));
+        assert_eq!(1234, to_minor(1.234, 3));
+        assert_eq!(50.75, from_minor(5075, 2));
+        assert_eq!(1500.0, from_minor(1500, 0));
+        assert_eq!(1.234, from_minor(1234, 3));
+    }
+
+    #[test]
+    fn limits_are_configurable() {
+        let limits = AmountLimits {
+            max: 100.0,
+            decimals: 0,
+        };
+        assert_eq!(Ok(100.0), parse(&limits, "100"));
+        assert_eq!(Err(AmountError::TooLarge(100.0)), parse(&limits, "101"));
+        assert_eq!(Err(AmountError::TooPrecise(0)), parse(&limits, "1.5"));
+    }
+
+    #[test]
+    fn errors_have_distinct_messages() {
+        let errors = [
+            AmountError::NotANumber("x".to_string()),
+            AmountError::NotFinite,
+            AmountError::Negative,
+            AmountError::Zero,
+            AmountError::TooLarge(1.0),
+            AmountError::TooPrecise(2),
+            AmountError::InvalidCalculation("x".to_string()),
+            AmountError::DivisionByZero,
+        ];
+        let mut messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        messages.sort();
+        messages.dedup();
+        assert_eq!(errors.len(), messages.len());
+    }
+}
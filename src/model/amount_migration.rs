@@ -0,0 +1,271 @@
+//! The second half of the move to minor units: V5 kept every float amount as
+//! `amountLegacy`, and only once an admin has checked that the converted
+//! amounts agree with them is the column dropped.
+
+use super::amount::{from_minor, to_minor};
+use super::{audit, Error, Model, Result};
+use log::*;
+
+/// Converted amounts further than this from their float, in major units,
+/// changed in the conversion.
+pub const MAX_DRIFT: f64 = 0.005;
+
+/// Half a cent off is rounding, and floats land on either side of it.
+const FLOAT_NOISE: f64 = 1e-9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountMismatch {
+    pub expense_id: i64,
+    pub legacy: f64,
+    /// The amount stored, in minor units.
+    pub minor: i64,
+    /// The amount converted from `legacy` again, in minor units.
+    pub rederived: i64,
+    pub exponent: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountMigrationReport {
+    /// Whether the legacy column is gone, in which case nothing was checked.
+    pub finalized: bool,
+    /// Expenses with a legacy amount to compare with.
+    pub checked: usize,
+    pub mismatches: Vec<AmountMismatch>,
+}
+
+impl AmountMigrationReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl Model {
+    /// Compares every stored amount with the float it was converted from.
+    /// Only reads: whatever it finds is left for an admin to look at.
+    pub fn verify_amount_migration(&self) -> Result<AmountMigrationReport> {
+        if !self.has_legacy_amounts()? {
+            return Ok(AmountMigrationReport {
+                finalized: true,
+                checked: 0,
+                mismatches: Vec::new(),
+            });
+        }
+        let mut stmt = self.connection.prepare(
+            "SELECT e.id, e.amountLegacy, e.amountMinor, c.exponent
+             FROM Expense e
+             JOIN Account a ON a.id = e.accountId
+             JOIN Currency c ON c.id = a.currencyId
+             WHERE e.amountLegacy IS NOT NULL
+             ORDER BY e.id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(i64, f64, i64, u32)>>>()?;
+        let checked = rows.len();
+        let mismatches = rows
+            .into_iter()
+            .filter(|(_, legacy, minor, exponent)| {
+                (from_minor(*minor, *exponent) - legacy).abs() > MAX_DRIFT + FLOAT_NOISE
+            })
+            .map(|(expense_id, legacy, minor, exponent)| AmountMismatch {
+                expense_id,
+                legacy,
+                minor,
+                rederived: to_minor(legacy, exponent),
+                exponent,
+            })
+            .collect();
+        Ok(AmountMigrationReport {
+            finalized: false,
+            checked,
+            mismatches,
+        })
+    }
+
+    /// Drops the legacy amounts, refusing while any stored amount disagrees
+    /// with its float.
+    pub fn finalize_amount_migration(&self, user_id: i64) -> Result<()> {
+        self.check_writable()?;
+        let tx = self.connection.unchecked_transaction()?;
+        let report = self.verify_amount_migration()?;
+        if report.finalized {
+            return Err(Error::Refused(
+                "The legacy amounts are gone already.".to_string(),
+            ));
+        }
+        if !report.is_ok() {
+            return Err(Error::Refused(format!(
+                "{} of {} amounts disagree with their legacy value.",
+                report.mismatches.len(),
+                report.checked
+            )));
+        }
+        tx.execute_batch(super::schema::DROP_LEGACY_AMOUNTS)?;
+        audit::record(
+            &tx,
+            user_id,
+            "finalize-amounts",
+            &format!("{} amounts verified", report.checked),
+        )?;
+        tx.commit()?;
+        info!("Dropped the legacy amounts of {} expenses", report.checked);
+        Ok(())
+    }
+
+    fn has_legacy_amounts(&self) -> Result<bool> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('Expense') WHERE name = 'amountLegacy'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{schema, AmountLimits};
+
+    /// A database from before V5 with the given float amounts, in one EUR
+    /// account, migrated to the latest version.
+    fn migrated(amounts: &[f64]) -> Model {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        schema::migrate_to(&conn, 4);
+        conn.execute_batch(
+            "INSERT INTO Currency (name) VALUES ('EUR');
+             INSERT INTO User (telegramId, telegramName, displayName) VALUES (1, 'a', 'A');
+             INSERT INTO Account (name, currencyId) VALUES ('Cash', 1);
+             INSERT INTO ExpenseCategory (name) VALUES ('Food');",
+        )
+        .unwrap();
+        for amount in amounts {
+            conn.execute(
+                "INSERT INTO Expense (accountId, categoryId, userId, timestamp, amount)
+                 VALUES (1, 1, 1, 0, ?1)",
+                [amount],
+            )
+            .unwrap();
+        }
+        schema::init_schema(&conn);
+        Model {
+            connection: conn,
+            amount_limits: AmountLimits::default(),
+            rule_sets: Default::default(),
+        }
+    }
+
+    fn stored(model: &Model) -> Vec<i64> {
+        model
+            .connection
+            .prepare("SELECT amountMinor FROM Expense ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn float_artifacts_convert_cleanly() {
+        let model = migrated(&[
+            0.1 + 0.2,
+            19.99 + 1e-9,
+            20.0 - 1e-9,
+            1.005,
+            0.015,
+            1_000_000.0 - 0.01,
+            100.0 / 3.0,
+            2.675,
+        ]);
+        let report = model.verify_amount_migration().unwrap();
+        assert_eq!(8, report.checked);
+        assert!(report.is_ok(), "{:?}", report.mismatches);
+        assert_eq!(
+            vec![30, 1999, 2000, 100, 2, 99_999_999, 3333, 268],
+            stored(&model)
+        );
+    }
+
+    #[test]
+    fn reports_amounts_that_changed_and_keeps_them() {
+        let model = migrated(&[50.75, 0.1 + 0.2, 12.5]);
+        model
+            .connection
+            .execute_batch(
+                "UPDATE Expense SET amountMinor = 31 WHERE id = 2;
+                 UPDATE Expense SET amountMinor = 125 WHERE id = 3;",
+            )
+            .unwrap();
+
+        let report = model.verify_amount_migration().unwrap();
+        assert_eq!(3, report.checked);
+        assert_eq!(
+            vec![
+                AmountMismatch {
+                    expense_id: 2,
+                    legacy: 0.1 + 0.2,
+                    minor: 31,
+                    rederived: 30,
+                    exponent: 2,
+                },
+                AmountMismatch {
+                    expense_id: 3,
+                    legacy: 12.5,
+                    minor: 125,
+                    rederived: 1250,
+                    exponent: 2,
+                },
+            ],
+            report.mismatches
+        );
+        assert!(matches!(
+            model.finalize_amount_migration(1),
+            Err(Error::Refused(_))
+        ));
+        assert_eq!(vec![5075, 31, 125], stored(&model));
+        assert!(model.has_legacy_amounts().unwrap());
+    }
+
+    #[test]
+    fn finalizing_drops_the_legacy_column_once() {
+        let model = migrated(&[50.75, 0.1 + 0.2]);
+        model.finalize_amount_migration(1).unwrap();
+
+        assert!(!model.has_legacy_amounts().unwrap());
+        assert_eq!(vec![5075, 30], stored(&model));
+        let report = model.verify_amount_migration().unwrap();
+        assert!(report.finalized && report.is_ok());
+        assert!(matches!(
+            model.finalize_amount_migration(1),
+            Err(Error::Refused(_))
+        ));
+        assert_eq!("finalize-amounts", model.audit_entries().unwrap()[0].action);
+    }
+
+    #[test]
+    fn new_expenses_have_nothing_to_verify() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let report = model.verify_amount_migration().unwrap();
+        assert_eq!((false, 0), (report.finalized, report.checked));
+        model.finalize_amount_migration(1001).unwrap();
+        assert_eq!(
+            4,
+            model
+                .summarize(
+                    1,
+                    crate::model::DateRange::inclusive(
+                        chrono::NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+                        chrono::NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+                    ),
+                    chrono_tz::Tz::UTC
+                )
+                .unwrap()
+                .categories
+                .len()
+        );
+    }
+}
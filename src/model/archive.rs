@@ -0,0 +1,461 @@
+//! Old expenses moved out to database files of their own. An archive holds
+//! the expenses from before its cutoff and their shares; queries reaching
+//! back past a cutoff attach the archives and read through views adding
+//! their rows to the main tables.
+
+use super::{audit, Error, Model, Result};
+use crate::time::{self, DbTime};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use log::*;
+use rusqlite::OptionalExtension;
+use std::path::Path;
+
+/// What schema a new archive is attached as while it is written.
+const NEW_ARCHIVE: &str = "new_archive";
+
+/// The tables of an archive. Accounts, categories and users stay in the
+/// main database, so there are no foreign keys.
+const ARCHIVE_SCHEMA: &str = "
+CREATE TABLE new_archive.Expense (
+    id INTEGER PRIMARY KEY,
+    accountId INTEGER NOT NULL,
+    categoryId INTEGER NOT NULL,
+    userId INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    amountMinor INTEGER NOT NULL,
+    comments TEXT,
+    categoryConfirmed INTEGER NOT NULL,
+    originalAmountMinor INTEGER,
+    originalCurrencyId INTEGER,
+    kind TEXT NOT NULL DEFAULT 'expense',
+    latitude REAL,
+    longitude REAL,
+    source TEXT NOT NULL DEFAULT 'manual',
+    createdAt INTEGER,
+    sourceChatId INTEGER,
+    sourceMessageId INTEGER,
+    sourceChatUsername TEXT,
+    modifiedBy INTEGER,
+    modifiedAt INTEGER
+);
+CREATE TABLE new_archive.ExpenseShare (
+    expenseId INTEGER NOT NULL,
+    userId INTEGER NOT NULL,
+    shareMinor INTEGER NOT NULL,
+    PRIMARY KEY (expenseId, userId)
+);
+";
+
+/// The columns archived, and read through the views. Expense ids are never
+/// reused, so they stay unique across the archives.
+const EXPENSE_COLUMNS: &str =
+    "id, accountId, categoryId, userId, timestamp, amountMinor, comments, categoryConfirmed";
+const SHARE_COLUMNS: &str = "expenseId, userId, shareMinor";
+/// Kept in the archive besides those, but not read back: archives from
+/// before the original amounts, kinds, locations, sources, times logged,
+/// source messages and edits lack them. The views have archived expenses
+/// logged when they were made, and of the kind archived, or plain expenses
+/// for archives from before kinds.
+const ARCHIVED_COLUMNS: &str =
+    "id, accountId, categoryId, userId, timestamp, amountMinor, comments, \
+     categoryConfirmed, originalAmountMinor, originalCurrencyId, kind, latitude, longitude, \
+     source, createdAt, sourceChatId, sourceMessageId, sourceChatUsername, \
+     modifiedBy, modifiedAt";
+
+/// Where a query reads expenses and their shares from. Queries name them
+/// `{expenses}` and `{shares}`, see [`Tables::apply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Tables {
+    expenses: &'static str,
+    shares: &'static str,
+}
+
+const MAIN: Tables = Tables {
+    expenses: "Expense",
+    shares: "ExpenseShare",
+};
+
+const WITH_ARCHIVES: Tables = Tables {
+    expenses: "temp.ExpenseWithArchives",
+    shares: "temp.ExpenseShareWithArchives",
+};
+
+impl Tables {
+    pub(super) fn apply(self, sql: &str) -> String {
+        sql.replace("{expenses}", self.expenses)
+            .replace("{shares}", self.shares)
+    }
+}
+
+/// The file an archive of the expenses before `cutoff` goes to, next to the
+/// main database `db`.
+pub fn archive_file(db: &Path, cutoff: NaiveDate) -> std::path::PathBuf {
+    let name = format!("spending-tracker-before-{}.db", cutoff.format("%Y-%m-%d"));
+    std::path::absolute(db.with_file_name(name)).expect("the working directory is known")
+}
+
+impl Model {
+    /// How many expenses of all households are from before the local day
+    /// `cutoff`.
+    pub fn count_before(&self, cutoff: NaiveDate, tz: Tz) -> Result<usize> {
+        let count = self.connection.query_row(
+            "SELECT COUNT(*) FROM Expense WHERE timestamp < ?1",
+            [DbTime(time::start_of_day(cutoff, tz))],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    /// Moves the expenses of all households from before the local day
+    /// `cutoff`, with their shares, into a new database file at `path` and
+    /// records it in the catalog. Copying, deleting and recording are one
+    /// transaction; the file is removed again if it fails. Returns how many
+    /// expenses moved.
+    pub fn archive_before(
+        &self,
+        user_id: i64,
+        cutoff: NaiveDate,
+        path: &Path,
+        tz: Tz,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        if path.exists() {
+            return Err(Error::Refused(format!(
+                "{} exists already, archives go to new files.",
+                path.display()
+            )));
+        }
+        let Some(name) = path.to_str() else {
+            return Err(Error::Refused(format!(
+                "{} is no valid file name.",
+                path.display()
+            )));
+        };
+        let count = self.count_before(cutoff, tz)?;
+        if count == 0 {
+            return Err(Error::Refused(format!(
+                "There are no expenses before {}.",
+                cutoff
+            )));
+        }
+
+        let before = DbTime(time::start_of_day(cutoff, tz));
+        self.connection
+            .execute(&format!("ATTACH DATABASE ?1 AS {}", NEW_ARCHIVE), [name])?;
+        let moved = || -> Result<usize> {
+            let tx = self.connection.unchecked_transaction()?;
+            tx.execute_batch(ARCHIVE_SCHEMA)?;
+            let moved = tx.execute(
+                &format!(
+                    "INSERT INTO {db}.Expense ({columns}) SELECT {columns} FROM main.Expense
+                     WHERE timestamp < ?1",
+                    db = NEW_ARCHIVE,
+                    columns = ARCHIVED_COLUMNS
+                ),
+                [before],
+            )?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO {db}.ExpenseShare ({columns}) SELECT {columns} FROM main.ExpenseShare
+                     WHERE expenseId IN (SELECT id FROM {db}.Expense)",
+                    db = NEW_ARCHIVE,
+                    columns = SHARE_COLUMNS
+                ),
+                [],
+            )?;
+            // The shares go with them.
+            tx.execute("DELETE FROM main.Expense WHERE timestamp < ?1", [before])?;
+            tx.execute(
+                "INSERT INTO ArchiveCatalog (path, cutoff, expenses, createdAt)
+                 VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![name, before, moved, DbTime(Utc::now())],
+            )?;
+            audit::record(
+                &tx,
+                user_id,
+                "archive",
+                &format!("{} expenses before {} to {}", moved, cutoff, name),
+            )?;
+            tx.commit()?;
+            Ok(moved)
+        };
+        let moved = moved();
+        self.connection
+            .execute(&format!("DETACH DATABASE {}", NEW_ARCHIVE), [])?;
+        // The views are made again with the new archive when next needed.
+        self.connection.execute_batch(
+            "DROP VIEW IF EXISTS temp.ExpenseWithArchives;
+             DROP VIEW IF EXISTS temp.ExpenseShareWithArchives;",
+        )?;
+        match moved {
+            Ok(moved) => {
+                info!("Archived {} expenses before {} to {}", moved, cutoff, name);
+                Ok(moved)
+            }
+            Err(e) => {
+                if let Err(e) = std::fs::remove_file(path) {
+                    warn!("Removing the failed archive {} failed: {}", name, e);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// The tables holding the expenses from `since` on, or of all time for
+    /// `None`: the main ones unless archives go back that far, then views
+    /// adding the archives, which are attached as needed.
+    pub(super) fn expense_tables(&self, since: Option<DateTime<Utc>>) -> Result<Tables> {
+        let needed = self
+            .connection
+            .query_row(
+                "SELECT 1 FROM ArchiveCatalog WHERE ?1 IS NULL OR cutoff > ?1",
+                [since.map(DbTime)],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if needed.is_none() {
+            return Ok(MAIN);
+        }
+        let ready = self
+            .connection
+            .query_row(
+                "SELECT 1 FROM sqlite_temp_master WHERE name = 'ExpenseWithArchives'",
+                [],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if ready.is_some() {
+            return Ok(WITH_ARCHIVES);
+        }
+
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id, path FROM ArchiveCatalog ORDER BY id")?;
+        let archives = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut attached = Vec::new();
+        let mut stmt = self.connection.prepare("PRAGMA database_list")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            attached.push(row.get::<_, String>(1)?);
+        }
+        let (mut expenses, mut shares) = (
+            vec![format!(
+                "SELECT {}, createdAt, kind FROM main.Expense",
+                EXPENSE_COLUMNS
+            )],
+            vec![format!("SELECT {} FROM main.ExpenseShare", SHARE_COLUMNS)],
+        );
+        for (id, path) in archives {
+            let schema = format!("archive{}", id);
+            if !attached.contains(&schema) {
+                // Attaching a missing file would make an empty one.
+                if !Path::new(&path).exists() {
+                    return Err(Error::Refused(format!(
+                        "The archive {} is missing, reports reaching back that far can't be made.",
+                        path
+                    )));
+                }
+                self.connection
+                    .execute(&format!("ATTACH DATABASE ?1 AS {}", schema), [&path])?;
+            }
+            let has_kind = self
+                .connection
+                .query_row(
+                    "SELECT 1 FROM pragma_table_info('Expense', ?1) WHERE name = 'kind'",
+                    [&schema],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            expenses.push(format!(
+                "SELECT {}, timestamp AS createdAt, {} FROM {}.Expense",
+                EXPENSE_COLUMNS,
+                if has_kind {
+                    "kind"
+                } else {
+                    "'expense' AS kind"
+                },
+                schema
+            ));
+            shares.push(format!(
+                "SELECT {} FROM {}.ExpenseShare",
+                SHARE_COLUMNS, schema
+            ));
+        }
+        self.connection.execute_batch(&format!(
+            "CREATE TEMP VIEW ExpenseWithArchives AS {};
+             CREATE TEMP VIEW ExpenseShareWithArchives AS {};",
+            expenses.join(" UNION ALL "),
+            shares.join(" UNION ALL ")
+        ))?;
+        Ok(WITH_ARCHIVES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::restore::tests::TempDir;
+    use crate::model::{Calendar, DateRange, ExpenseSource, NewExpense, Period, SourceMessage};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// The test data of December 2023 plus an expense in January 2024.
+    fn model() -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id: 1,
+                user_id: 1001,
+                timestamp: DateTime::from_timestamp(1_704_200_000, 0).unwrap(),
+                amount: 12.5,
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+        model.split_expense(1, 2, Some(1001)).unwrap();
+        model
+    }
+
+    #[test]
+    fn sums_across_the_boundary_stay_the_same() {
+        let model = model();
+        let dir = TempDir::new("archive-sums");
+        let path = dir.0.join("archive.db");
+        let winter = DateRange::inclusive(date(2023, 12, 1), date(2024, 1, 31));
+        let january = DateRange::inclusive(date(2024, 1, 1), date(2024, 1, 31));
+        let now = DateTime::from_timestamp(1_704_300_000, 0).unwrap();
+        let before = (
+            model.summarize(1, winter, Tz::UTC).unwrap(),
+            model.summarize(1, january, Tz::UTC).unwrap(),
+            model.year_summary(1, 2023, Tz::UTC).unwrap(),
+            model.balances(1, "EUR").unwrap(),
+            model.debts(1).unwrap(),
+            model
+                .month_to_date(1, "USD", Some(1001), now, Tz::UTC)
+                .unwrap(),
+            model.summarize_logged(1, winter, &[], Tz::UTC).unwrap(),
+        );
+        assert_eq!(1, model.debts(1).unwrap().len());
+        let source = SourceMessage {
+            chat_id: -100,
+            message_id: 7,
+            username: Some("family".to_string()),
+        };
+        model.set_expense_source(1, 1, &source).unwrap();
+        // Edited by Hanna.
+        model
+            .connection
+            .execute(
+                "UPDATE Expense SET modifiedBy = 1002, modifiedAt = 1701500000 WHERE id = 1",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(4, model.count_before(date(2024, 1, 1), Tz::UTC).unwrap());
+        assert_eq!(
+            4,
+            model
+                .archive_before(1001, date(2024, 1, 1), &path, Tz::UTC)
+                .unwrap()
+        );
+        assert_eq!(0, model.count_before(date(2024, 1, 1), Tz::UTC).unwrap());
+        assert_eq!(None, model.get_expense_details(1, 1).unwrap());
+        assert_eq!(1, model.audit_entries().unwrap().len());
+
+        let after = (
+            model.summarize(1, winter, Tz::UTC).unwrap(),
+            model.summarize(1, january, Tz::UTC).unwrap(),
+            model.year_summary(1, 2023, Tz::UTC).unwrap(),
+            model.balances(1, "EUR").unwrap(),
+            model.debts(1).unwrap(),
+            model
+                .month_to_date(1, "USD", Some(1001), now, Tz::UTC)
+                .unwrap(),
+            model.summarize_logged(1, winter, &[], Tz::UTC).unwrap(),
+        );
+        assert_eq!(before, after);
+
+        let mut exported = Vec::new();
+        model
+            .export_expenses(1, winter, Tz::UTC, |row| -> Result<()> {
+                exported.push(row.id);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5], exported);
+        // The archive keeps what isn't read back.
+        let archive = rusqlite::Connection::open(&path).unwrap();
+        let kept: (i64, i32, Option<String>) = archive
+            .query_row(
+                "SELECT sourceChatId, sourceMessageId, sourceChatUsername FROM Expense WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((source.chat_id, source.message_id, source.username), kept);
+        let edited: (i64, DbTime) = archive
+            .query_row(
+                "SELECT modifiedBy, modifiedAt FROM Expense WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (
+                1002,
+                DbTime(DateTime::from_timestamp(1_701_500_000, 0).unwrap())
+            ),
+            edited
+        );
+
+        // Only ranges reaching past the cutoff need the archive.
+        let this_month = Period::ThisMonth.resolve(now, Tz::UTC, Calendar::default());
+        let (start, _) = this_month.to_utc(Tz::UTC);
+        assert_eq!(MAIN, model.expense_tables(Some(start)).unwrap());
+        assert_eq!(WITH_ARCHIVES, model.expense_tables(None).unwrap());
+    }
+
+    #[test]
+    fn refuses_what_it_cant_archive() {
+        let model = model();
+        let dir = TempDir::new("archive-refusals");
+        let path = dir.0.join("archive.db");
+        let refused = |cutoff, path: &Path| {
+            matches!(
+                model.archive_before(1001, cutoff, path, Tz::UTC),
+                Err(Error::Refused(_))
+            )
+        };
+
+        assert!(refused(date(2023, 1, 1), &path));
+        assert!(!path.exists());
+        std::fs::write(&path, b"").unwrap();
+        assert!(refused(date(2024, 1, 1), &path));
+        std::fs::remove_file(&path).unwrap();
+
+        model
+            .archive_before(1001, date(2023, 12, 2), &path, Tz::UTC)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(model.expense_tables(None), Err(Error::Refused(_))));
+        // Recent reports don't need it.
+        assert!(model
+            .summarize(
+                1,
+                DateRange::inclusive(date(2024, 1, 1), date(2024, 1, 31)),
+                Tz::UTC
+            )
+            .is_ok());
+    }
+}
@@ -0,0 +1,47 @@
+use crate::time::DbTime;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: chrono::DateTime<Utc>,
+    pub user_id: i64,
+    pub action: String,
+    pub details: String,
+}
+
+/// Records an action. Takes the connection rather than the model, so that
+/// the entry can be written inside the transaction of the change itself.
+pub(super) fn record(
+    connection: &Connection,
+    user_id: i64,
+    action: &str,
+    details: &str,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO AuditLog (timestamp, userId, action, details) VALUES (?1, ?2, ?3, ?4)",
+        params![DbTime(Utc::now()), user_id, action, details],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+impl super::Model {
+    pub fn audit_entries(&self) -> super::Result<Vec<AuditEntry>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT timestamp, userId, action, details FROM AuditLog ORDER BY id")?;
+        let entries = stmt
+            .query_map([], |row| {
+                Ok(AuditEntry {
+                    timestamp: row.get::<_, DbTime>(0)?.0,
+                    user_id: row.get(1)?,
+                    action: row.get(2)?,
+                    details: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+}
@@ -0,0 +1,219 @@
+use super::amount::from_minor;
+use super::{Model, Rate, Result};
+use rusqlite::params;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountBalance {
+    pub id: i64,
+    pub name: String,
+    pub currency: String,
+    /// Decimal places of the currency.
+    pub exponent: u32,
+    pub balance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrencyBalance {
+    pub currency: String,
+    pub exponent: u32,
+    pub subtotal: f64,
+    /// Latest known rate against the base currency, `None` if there is none.
+    /// The base currency itself always has a rate of 1 with no date.
+    pub rate: Option<Rate>,
+    pub is_base: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balances {
+    pub base_currency: String,
+    /// Decimal places of the base currency, 2 if it's not a known currency.
+    pub base_exponent: u32,
+    /// Accounts ordered by currency, then by name.
+    pub accounts: Vec<AccountBalance>,
+    pub currencies: Vec<CurrencyBalance>,
+}
+
+/// Grand total of all balances converted to the base currency.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrandTotal {
+    Total {
+        amount: f64,
+        /// Date of the oldest rate used, `None` if no conversion was needed.
+        rates_as_of: Option<chrono::NaiveDate>,
+    },
+    MissingRates(Vec<String>),
+}
+
+impl Balances {
+    pub fn grand_total(&self) -> GrandTotal {
+        let missing: Vec<String> = self
+            .currencies
+            .iter()
+            .filter(|c| !c.is_base && c.rate.is_none())
+            .map(|c| c.currency.clone())
+            .collect();
+        if !missing.is_empty() {
+            return GrandTotal::MissingRates(missing);
+        }
+
+        let mut amount = 0.0;
+        let mut rates_as_of = None;
+        for c in &self.currencies {
+            match c.rate {
+                Some(rate) if !c.is_base => {
+                    amount += c.subtotal * rate.rate;
+                    rates_as_of = Some(
+                        rates_as_of.map_or(rate.date, |d: chrono::NaiveDate| d.min(rate.date)),
+                    );
+                }
+                _ => amount += c.subtotal,
+            }
+        }
+        GrandTotal::Total {
+            amount,
+            rates_as_of,
+        }
+    }
+}
+
+// Initial balances and spent amounts are summed in minor units and converted
+// in Rust, where the exponent of each currency is at hand.
+const ACCOUNT_BALANCES: &str = "
+    SELECT COALESCE(a.displayName, a.name), c.name, c.exponent, a.initialBalanceMinor,
+           COALESCE((SELECT SUM(e.amountMinor) FROM {expenses} e WHERE e.accountId = a.id), 0),
+           a.id
+    FROM Account a
+    JOIN Currency c ON c.id = a.currencyId
+    WHERE a.householdId = ?1
+    ORDER BY c.name, COALESCE(a.displayName, a.name)
+";
+
+const CURRENCY_BALANCES: &str = "
+    WITH AccountBalance AS (
+        SELECT a.currencyId, a.initialBalanceMinor,
+               COALESCE((SELECT SUM(e.amountMinor) FROM {expenses} e WHERE e.accountId = a.id), 0) AS spent
+        FROM Account a
+        WHERE a.householdId = ?2
+    )
+    SELECT c.name, c.exponent, SUM(ab.initialBalanceMinor), SUM(ab.spent),
+           c.name = ?1 COLLATE NOCASE, r.rate, r.date
+    FROM AccountBalance ab
+    JOIN Currency c ON c.id = ab.currencyId
+    LEFT JOIN ExchangeRate r ON r.currencyId = c.id
+        AND r.date = (SELECT MAX(date) FROM ExchangeRate WHERE currencyId = c.id)
+    GROUP BY c.id
+    ORDER BY c.name
+";
+
+impl Model {
+    /// Balances of the household's accounts.
+    pub fn balances(&self, household: i64, base_currency: &str) -> Result<Balances> {
+        // Balances count every expense ever, archived ones too.
+        let tables = self.expense_tables(None)?;
+        let mut stmt = self.connection.prepare(&tables.apply(ACCOUNT_BALANCES))?;
+        let accounts = stmt
+            .query_map([household], |row| {
+                let exponent = row.get(2)?;
+                let (initial, spent): (i64, i64) = (row.get(3)?, row.get(4)?);
+                Ok(AccountBalance {
+                    id: row.get(5)?,
+                    name: row.get(0)?,
+                    currency: row.get(1)?,
+                    exponent,
+                    balance: from_minor(initial - spent, exponent),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = self.connection.prepare(&tables.apply(CURRENCY_BALANCES))?;
+        let currencies = stmt
+            .query_map(params![base_currency, household], |row| {
+                let exponent = row.get(1)?;
+                let (initial, spent): (i64, i64) = (row.get(2)?, row.get(3)?);
+                let is_base: bool = row.get(4)?;
+                let rate: Option<f64> = row.get(5)?;
+                let date = row.get(6)?;
+                Ok(CurrencyBalance {
+                    currency: row.get(0)?,
+                    exponent,
+                    subtotal: from_minor(initial - spent, exponent),
+                    rate: match (is_base, rate, date) {
+                        (false, Some(rate), Some(date)) => Some(Rate { rate, date }),
+                        _ => None,
+                    },
+                    is_base,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(Balances {
+            base_currency: base_currency.to_string(),
+            base_exponent: self.currency_exponent(base_currency)?.unwrap_or(2),
+            accounts,
+            currencies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn groups_by_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let balances = model.balances(1, "EUR").unwrap();
+        let subtotals: Vec<(&str, f64)> = balances
+            .currencies
+            .iter()
+            .map(|c| (c.currency.as_str(), c.subtotal))
+            .collect();
+        assert_eq!(
+            vec![("BYN", 270.0), ("EUR", 949.25), ("USD", 380.0)],
+            subtotals
+        );
+        assert_eq!(3, balances.accounts.len());
+        assert!(balances.currencies[1].is_base);
+    }
+
+    #[test]
+    fn partial_rates_name_missing_currencies() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_rate("USD", date("2023-12-01"), 0.9).unwrap();
+
+        let balances = model.balances(1, "EUR").unwrap();
+        assert_eq!(
+            GrandTotal::MissingRates(vec!["BYN".to_string()]),
+            balances.grand_total()
+        );
+    }
+
+    #[test]
+    fn grand_total_uses_latest_rates() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_rate("USD", date("2023-11-01"), 0.5).unwrap();
+        model.set_rate("USD", date("2023-12-01"), 0.9).unwrap();
+        model.set_rate("BYN", date("2023-11-20"), 0.25).unwrap();
+
+        let balances = model.balances(1, "EUR").unwrap();
+        match balances.grand_total() {
+            GrandTotal::Total {
+                amount,
+                rates_as_of,
+            } => {
+                assert!((amount - (949.25 + 380.0 * 0.9 + 270.0 * 0.25)).abs() < 1e-9);
+                assert_eq!(Some(date("2023-11-20")), rates_as_of);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
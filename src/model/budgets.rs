@@ -0,0 +1,249 @@
+//! Monthly budgets of categories, one per currency, and how much of them
+//! is left.
+
+use super::amount::{from_minor, to_minor};
+use super::{Error, Model, Result};
+use crate::time::DbTime;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use log::*;
+use rusqlite::params;
+
+/// A budget with what was spent of it in a month, in major units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetRemaining {
+    pub category_id: i64,
+    pub category: String,
+    pub currency: String,
+    pub exponent: u32,
+    pub budget: f64,
+    /// Spent in the category and its subcategories.
+    pub spent: f64,
+}
+
+impl BudgetRemaining {
+    /// What may still be spent, negative once over budget.
+    pub fn remaining(&self) -> f64 {
+        from_minor(
+            to_minor(self.budget, self.exponent) - to_minor(self.spent, self.exponent),
+            self.exponent,
+        )
+    }
+}
+
+impl Model {
+    /// Sets the monthly budget of a category in `currency`, `None` removing
+    /// it.
+    pub fn set_budget(
+        &self,
+        household: i64,
+        category_id: i64,
+        currency: &str,
+        amount: Option<f64>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if self.get_category(household, category_id)?.is_none() {
+            return Err(Error::NotFound(format!("category {}", category_id)));
+        }
+        let Some(exponent) = self.currency_exponent(currency)? else {
+            return Err(Error::NotFound(format!("currency {}", currency)));
+        };
+        match amount {
+            Some(amount) => {
+                let amount = self.amount_limits.for_exponent(exponent).validate(amount)?;
+                self.connection.execute(
+                    "INSERT INTO Budget (categoryId, currencyId, amountMinor)
+                     SELECT ?1, id, ?3 FROM Currency WHERE name = ?2 COLLATE NOCASE
+                     ON CONFLICT (categoryId, currencyId)
+                     DO UPDATE SET amountMinor = excluded.amountMinor",
+                    params![category_id, currency, to_minor(amount, exponent)],
+                )?;
+                info!(
+                    "Set the budget of category {} to {} {}",
+                    category_id, amount, currency
+                );
+            }
+            None => {
+                self.connection.execute(
+                    "DELETE FROM Budget WHERE categoryId = ?1 AND currencyId =
+                        (SELECT id FROM Currency WHERE name = ?2 COLLATE NOCASE)",
+                    params![category_id, currency],
+                )?;
+                info!(
+                    "Removed the {} budget of category {}",
+                    currency, category_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The household's budgets with their spend in the local month starting
+    /// in the month of `month`, where the household's months start, by
+    /// category and currency. One query joins the budgets with the month's
+    /// sums, so that the category picker needs no more. Categories without
+    /// a budget aren't listed, whatever they spent.
+    pub fn budgets_with_remaining(
+        &self,
+        household: i64,
+        month: NaiveDate,
+        tz: Tz,
+    ) -> Result<Vec<BudgetRemaining>> {
+        let (start, end) = self.calendar(household)?.month(month).to_utc(tz);
+        let tables = self.expense_tables(Some(start))?;
+        let mut stmt = self.connection.prepare(&tables.apply(
+            "SELECT b.categoryId, c.name, cur.name, cur.exponent, b.amountMinor,
+                    COALESCE(SUM(s.spent), 0)
+             FROM Budget b
+             JOIN ExpenseCategory c ON c.id = b.categoryId
+             JOIN Currency cur ON cur.id = b.currencyId
+             LEFT JOIN (
+                 SELECT e.categoryId, a.currencyId, SUM(e.amountMinor) AS spent
+                 FROM {expenses} e
+                 JOIN Account a ON a.id = e.accountId
+                 WHERE e.timestamp >= ?2 AND e.timestamp < ?3 AND e.kind = 'expense'
+                 GROUP BY e.categoryId, a.currencyId
+             ) s ON s.currencyId = b.currencyId AND s.categoryId IN (
+                 SELECT id FROM ExpenseCategory WHERE id = b.categoryId OR parentId = b.categoryId
+             )
+             WHERE c.householdId = ?1
+             GROUP BY b.categoryId, b.currencyId
+             ORDER BY c.name, cur.name",
+        ))?;
+        let budgets = stmt
+            .query_map(params![household, DbTime(start), DbTime(end)], |row| {
+                let exponent = row.get(3)?;
+                Ok(BudgetRemaining {
+                    category_id: row.get(0)?,
+                    category: row.get(1)?,
+                    currency: row.get(2)?,
+                    exponent,
+                    budget: from_minor(row.get(4)?, exponent),
+                    spent: from_minor(row.get(5)?, exponent),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(budgets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::DateTime;
+
+    fn december() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 12, 1).unwrap()
+    }
+
+    fn spent(model: &Model, category_id: i64, amount: f64, timestamp: i64) {
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id,
+                user_id: 1001,
+                timestamp: DateTime::from_timestamp(timestamp, 0).unwrap(),
+                amount,
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn joins_budgets_with_the_month() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Groceries spent 50.75 EUR in December, Transportation has spend
+        // but no budget, and Entertainment a budget but no EUR spend.
+        model.set_budget(1, 1, "EUR", Some(130.0)).unwrap();
+        model.set_budget(1, 3, "eur", Some(40.0)).unwrap();
+        model.set_budget(1, 4, "USD", Some(80.0)).unwrap();
+        // January doesn't count for December.
+        spent(&model, 1, 9.0, 1_704_110_400);
+
+        let budgets = model
+            .budgets_with_remaining(1, december(), Tz::UTC)
+            .unwrap();
+        let rows: Vec<(&str, &str, f64, f64)> = budgets
+            .iter()
+            .map(|b| {
+                (
+                    b.category.as_str(),
+                    b.currency.as_str(),
+                    b.spent,
+                    b.remaining(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("Entertainment", "EUR", 0.0, 40.0),
+                ("Groceries", "EUR", 50.75, 79.25),
+                ("Utilities", "USD", 100.0, -20.0),
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn budgets_follow_the_household_months() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .set_calendar(
+                1,
+                crate::model::Calendar {
+                    week_start: chrono::Weekday::Mon,
+                    month_start: 25,
+                },
+            )
+            .unwrap();
+        model.set_budget(1, 1, "EUR", Some(100.0)).unwrap();
+        // On the 1st of December, in the month from November 25th.
+        let november = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let budgets = model.budgets_with_remaining(1, november, Tz::UTC).unwrap();
+        assert_eq!(50.75, budgets[0].spent);
+        let budgets = model
+            .budgets_with_remaining(1, december(), Tz::UTC)
+            .unwrap();
+        assert_eq!(0.0, budgets[0].spent);
+    }
+
+    #[test]
+    fn budgets_cover_subcategories() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let snacks = model.add_category(1, "Snacks", None).unwrap();
+        model.set_category_parent(1, snacks, Some(1)).unwrap();
+        spent(&model, snacks, 10.0, 1_701_500_000);
+        model.set_budget(1, 1, "EUR", Some(100.0)).unwrap();
+        model.set_budget(1, snacks, "EUR", Some(20.0)).unwrap();
+
+        let budgets = model
+            .budgets_with_remaining(1, december(), Tz::UTC)
+            .unwrap();
+        let spent: Vec<f64> = budgets.iter().map(|b| b.spent).collect();
+        assert_eq!(vec![60.75, 10.0], spent);
+        assert!(model
+            .budgets_with_remaining(2, december(), Tz::UTC)
+            .unwrap()
+            .is_empty());
+
+        // Replaced, then removed.
+        model.set_budget(1, 1, "EUR", Some(200.0)).unwrap();
+        model.set_budget(1, snacks, "EUR", None).unwrap();
+        let budgets = model
+            .budgets_with_remaining(1, december(), Tz::UTC)
+            .unwrap();
+        assert_eq!(1, budgets.len());
+        assert_eq!(200.0, budgets[0].budget);
+
+        assert!(model.set_budget(2, 1, "EUR", Some(1.0)).is_err());
+        assert!(model.set_budget(1, 1, "XYZ", Some(1.0)).is_err());
+        assert!(model.set_budget(1, 1, "EUR", Some(0.0)).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+//! Changes applied to many expenses at once.
+
+use super::{audit, closing, DateRange, Error, Model, Result};
+use crate::time::DbTime;
+use chrono::Utc;
+use chrono_tz::Tz;
+use log::*;
+use rusqlite::{params, OptionalExtension, Transaction};
+
+/// Bounds of an optional range as stored timestamps: from local midnight of
+/// the first day (inclusive) to local midnight after the last day
+/// (exclusive). No range means all time.
+fn bounds(range: Option<DateRange>, tz: Tz) -> (Option<DbTime>, Option<DbTime>) {
+    match range {
+        Some(range) => {
+            let (start, end) = range.to_utc(tz);
+            (Some(DbTime(start)), Some(DbTime(end)))
+        }
+        None => (None, None),
+    }
+}
+
+/// Commits the change, or for a dry run rolls it back, so that a preview
+/// goes through exactly what the real change would.
+fn finish(tx: Transaction, dry_run: bool) -> Result<()> {
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// What `merge_categories` moved to the target category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeReport {
+    pub expenses: usize,
+    /// Budgets of the source, added to the target's in the same currency.
+    pub budgets: usize,
+    pub favorites: usize,
+}
+
+/// `?4` is the household the category must belong to.
+const IN_CATEGORY: &str = "categoryId = ?1
+    AND (?2 IS NULL OR timestamp >= ?2)
+    AND (?3 IS NULL OR timestamp < ?3)
+    AND categoryId IN (SELECT id FROM ExpenseCategory WHERE householdId = ?4)";
+
+impl Model {
+    /// Number of expenses `bulk_recategorize` would move.
+    pub fn count_in_category(
+        &self,
+        household: i64,
+        category_id: i64,
+        range: Option<DateRange>,
+        tz: Tz,
+    ) -> Result<usize> {
+        let (start, end) = bounds(range, tz);
+        let count: i64 = self.connection.query_row(
+            &format!("SELECT COUNT(*) FROM Expense WHERE {}", IN_CATEGORY),
+            params![category_id, start, end, household],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Moves the expenses of a category within `range` to another category
+    /// of the household and returns how many were moved. The move and its
+    /// audit entry are a single transaction, rolled back for a `dry_run`.
+    pub fn bulk_recategorize(
+        &self,
+        household: i64,
+        user_id: i64,
+        (from_id, to_id): (i64, i64),
+        range: Option<DateRange>,
+        tz: Tz,
+        dry_run: bool,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        if self.get_category(household, to_id)?.is_none() {
+            return Err(Error::NotFound(format!("category {}", to_id)));
+        }
+        let (start, end) = bounds(range, tz);
+        let closed: Option<String> = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT p.month FROM Expense
+                     JOIN ClosedPeriod p ON p.householdId = ?4
+                         AND p.startsAt <= timestamp AND timestamp < p.endsAt
+                     WHERE {}
+                     ORDER BY p.month LIMIT 1",
+                    IN_CATEGORY
+                ),
+                params![from_id, start, end, household],
+                |row| row.get(0),
+            )
+            .optional()?;
+        closing::closed(closed)?;
+        let tx = self.connection.unchecked_transaction()?;
+        let moved = tx.execute(
+            &format!(
+                "UPDATE Expense SET categoryId = ?5, modifiedBy = ?6, modifiedAt = ?7 WHERE {}",
+                IN_CATEGORY
+            ),
+            params![
+                from_id,
+                start,
+                end,
+                household,
+                to_id,
+                user_id,
+                DbTime(Utc::now())
+            ],
+        )?;
+        let within = match range {
+            Some(range) => format!("{}..{}", range.start, range.last_day()),
+            None => "all time".to_string(),
+        };
+        audit::record(
+            &tx,
+            user_id,
+            "recategorize",
+            &format!(
+                "{} expenses from category {} to {}, {}",
+                moved, from_id, to_id, within
+            ),
+        )?;
+        finish(tx, dry_run)?;
+        if !dry_run {
+            info!(
+                "Moved {} expenses from category {} to {}",
+                moved, from_id, to_id
+            );
+        }
+        Ok(moved)
+    }
+}
+
+impl Model {
+    /// Moves everything of `source_id` to `target_id` and archives the
+    /// source, so that old references to it still resolve. Both must be
+    /// active categories of the household, and different. A `dry_run`
+    /// reports the same but changes nothing.
+    pub fn merge_categories(
+        &self,
+        household: i64,
+        user_id: i64,
+        (source_id, target_id): (i64, i64),
+        dry_run: bool,
+    ) -> Result<MergeReport> {
+        self.check_writable()?;
+        if source_id == target_id {
+            return Err(Error::Refused(
+                "A category can't be merged into itself.".to_string(),
+            ));
+        }
+        for id in [source_id, target_id] {
+            let active: Option<bool> = self
+                .connection
+                .query_row(
+                    "SELECT active FROM ExpenseCategory WHERE id = ?1 AND householdId = ?2",
+                    [id, household],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match active {
+                None => return Err(Error::NotFound(format!("category {}", id))),
+                Some(false) => return Err(Error::Refused(format!("Category {} is archived.", id))),
+                Some(true) => {}
+            }
+        }
+        self.check_expenses_open(household, "e.categoryId = ?1", source_id)?;
+
+        let tx = self.connection.unchecked_transaction()?;
+        let expenses = tx.execute(
+            "UPDATE Expense SET categoryId = ?2, modifiedBy = ?3, modifiedAt = ?4
+             WHERE categoryId = ?1",
+            params![source_id, target_id, user_id, DbTime(Utc::now())],
+        )?;
+        // Subcategories of the source go to the target, unless that would
+        // nest them two levels deep.
+        let target_parent: Option<i64> = tx.query_row(
+            "SELECT parentId FROM ExpenseCategory WHERE id = ?1",
+            [target_id],
+            |row| row.get(0),
+        )?;
+        let adopter = match target_parent {
+            None => Some(target_id),
+            Some(parent) if parent == source_id => Some(target_id),
+            Some(_) => None,
+        };
+        tx.execute(
+            "UPDATE ExpenseCategory SET parentId = NULL WHERE id = ?1 AND parentId = ?2",
+            [target_id, source_id],
+        )?;
+        tx.execute(
+            "UPDATE ExpenseCategory SET parentId = ?2 WHERE parentId = ?1",
+            params![source_id, adopter],
+        )?;
+        tx.execute(
+            "UPDATE ExpenseCategory SET active = 0 WHERE id = ?1",
+            [source_id],
+        )?;
+        tx.execute(
+            "UPDATE Rule SET categoryId = ?2 WHERE categoryId = ?1",
+            [source_id, target_id],
+        )?;
+        tx.execute(
+            "UPDATE TrustedSource SET defaultCategoryId = ?2 WHERE defaultCategoryId = ?1",
+            [source_id, target_id],
+        )?;
+        // A budget of the target in the same currency grows by the source's.
+        let budgets = tx.execute(
+            "INSERT INTO Budget (categoryId, currencyId, amountMinor)
+             SELECT ?2, currencyId, amountMinor FROM Budget WHERE categoryId = ?1
+             ON CONFLICT (categoryId, currencyId)
+             DO UPDATE SET amountMinor = amountMinor + excluded.amountMinor",
+            [source_id, target_id],
+        )?;
+        tx.execute("DELETE FROM Budget WHERE categoryId = ?1", [source_id])?;
+        let favorites = tx.execute(
+            "UPDATE Favorite SET categoryId = ?2 WHERE categoryId = ?1",
+            [source_id, target_id],
+        )?;
+        audit::record(
+            &tx,
+            user_id,
+            "merge_categories",
+            &format!(
+                "category {} into {}, {} expenses, {} budgets, {} favorites",
+                source_id, target_id, expenses, budgets, favorites
+            ),
+        )?;
+        finish(tx, dry_run)?;
+        self.forget_rules();
+        if !dry_run {
+            info!(
+                "Merged category {} into {}, moving {} expenses",
+                source_id, target_id, expenses
+            );
+        }
+        Ok(MergeReport {
+            expenses,
+            budgets,
+            favorites,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::{DateTime, NaiveDate};
+
+    fn day(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn add(model: &Model, category_id: i64, timestamp: &str) {
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id,
+                user_id: 1001,
+                timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap().to_utc(),
+                amount: 1.0,
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn range_start_is_inclusive_and_end_exclusive() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Entertainment already has one expense on 2023-12-03.
+        add(&model, 3, "2023-11-30T23:59:59Z");
+        add(&model, 3, "2023-12-01T00:00:00Z");
+        add(&model, 3, "2023-12-31T23:59:59Z");
+        add(&model, 3, "2024-01-01T00:00:00Z");
+
+        let december = Some(DateRange::inclusive(day("2023-12-01"), day("2023-12-31")));
+        assert_eq!(3, model.count_in_category(1, 3, december, Tz::UTC).unwrap());
+        assert_eq!(5, model.count_in_category(1, 3, None, Tz::UTC).unwrap());
+
+        assert_eq!(
+            3,
+            model
+                .bulk_recategorize(1, 1001, (3, 4), december, Tz::UTC, false)
+                .unwrap()
+        );
+        assert_eq!(2, model.count_in_category(1, 3, None, Tz::UTC).unwrap());
+        assert_eq!(0, model.count_in_category(1, 3, december, Tz::UTC).unwrap());
+    }
+
+    #[test]
+    fn range_follows_time_zone() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Already the 1st of December in Berlin.
+        add(&model, 3, "2023-11-30T23:30:00Z");
+
+        let december = Some(DateRange::inclusive(day("2023-12-01"), day("2023-12-31")));
+        assert_eq!(1, model.count_in_category(1, 3, december, Tz::UTC).unwrap());
+        assert_eq!(
+            2,
+            model
+                .count_in_category(1, 3, december, Tz::Europe__Berlin)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn recategorize_writes_one_audit_entry() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(
+            1,
+            model
+                .bulk_recategorize(1, 1001, (3, 4), None, Tz::UTC, false)
+                .unwrap()
+        );
+        let entries = model.audit_entries().unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(
+            (
+                1001,
+                "recategorize",
+                "1 expenses from category 3 to 4, all time"
+            ),
+            (
+                entries[0].user_id,
+                entries[0].action.as_str(),
+                entries[0].details.as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn moved_expenses_are_stamped_with_the_admin() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .bulk_recategorize(1, 1002, (3, 4), None, Tz::UTC, false)
+            .unwrap();
+        let modified_by = |id| {
+            model
+                .get_expense_details(1, id)
+                .unwrap()
+                .unwrap()
+                .modified
+                .map(|m| m.user_id)
+        };
+        assert_eq!(Some(1002), modified_by(3));
+        assert_eq!(None, modified_by(1));
+
+        model.merge_categories(1, 1001, (1, 2), false).unwrap();
+        assert_eq!(Some(1001), modified_by(1));
+        assert_eq!(Some(1002), modified_by(3));
+    }
+
+    #[test]
+    fn merge_moves_expenses_and_archives_source() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        add(&model, 3, "2024-01-05T12:00:00Z");
+
+        assert_eq!(
+            MergeReport {
+                expenses: 2,
+                budgets: 0,
+                favorites: 0
+            },
+            model.merge_categories(1, 1001, (3, 4), false).unwrap()
+        );
+        assert_eq!(0, model.count_in_category(1, 3, None, Tz::UTC).unwrap());
+        assert_eq!(3, model.count_in_category(1, 4, None, Tz::UTC).unwrap());
+
+        // Archived, but still there for old references.
+        assert!(model.categories(1).unwrap().iter().all(|c| c.id != 3));
+        assert!(model.get_category(1, 3).unwrap().is_some());
+
+        let entries = model.audit_entries().unwrap();
+        assert_eq!(
+            vec!["category 3 into 4, 2 expenses, 0 budgets, 0 favorites"],
+            entries
+                .iter()
+                .map(|e| e.details.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn merge_moves_budgets_and_favorites() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // EUR overlaps with a budget of the target, USD doesn't.
+        model.set_budget(1, 1, "EUR", Some(100.0)).unwrap();
+        model.set_budget(1, 1, "USD", Some(30.0)).unwrap();
+        model.set_budget(1, 2, "EUR", Some(50.0)).unwrap();
+        let favorite = model
+            .add_favorite(
+                1,
+                &crate::model::NewFavorite {
+                    user_id: 1001,
+                    label: "market".to_string(),
+                    amount: 20.0,
+                    account_id: 1,
+                    category_id: 1,
+                    comment: None,
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            MergeReport {
+                expenses: 1,
+                budgets: 2,
+                favorites: 1
+            },
+            model.merge_categories(1, 1001, (1, 2), false).unwrap()
+        );
+        let month = NaiveDate::from_ymd_opt(2023, 12, 1).unwrap();
+        let mut budgets: Vec<(i64, String, f64)> = model
+            .budgets_with_remaining(1, month, Tz::UTC)
+            .unwrap()
+            .into_iter()
+            .map(|b| (b.category_id, b.currency, b.budget))
+            .collect();
+        budgets.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            vec![(2, "EUR".to_string(), 150.0), (2, "USD".to_string(), 30.0)],
+            budgets
+        );
+        let favorite = model.get_favorite(1, favorite).unwrap().unwrap();
+        assert_eq!(2, favorite.category.id);
+        assert_eq!(None, favorite.blocked_reason());
+    }
+
+    #[test]
+    fn dry_runs_report_the_same_and_change_nothing() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        add(&model, 3, "2024-01-05T12:00:00Z");
+        let recategorize = |dry_run| {
+            model
+                .bulk_recategorize(1, 1001, (3, 4), None, Tz::UTC, dry_run)
+                .unwrap()
+        };
+        let dry = recategorize(true);
+        assert_eq!(2, model.count_in_category(1, 3, None, Tz::UTC).unwrap());
+        assert!(model.audit_entries().unwrap().is_empty());
+        assert_eq!(dry, recategorize(false));
+        assert_eq!(2, dry);
+
+        add(&model, 3, "2024-01-06T12:00:00Z");
+        let dry = model.merge_categories(1, 1001, (3, 4), true).unwrap();
+        assert_eq!(1, model.count_in_category(1, 3, None, Tz::UTC).unwrap());
+        assert!(model.categories(1).unwrap().iter().any(|c| c.id == 3));
+        assert_eq!(1, model.audit_entries().unwrap().len());
+        assert_eq!(dry, model.merge_categories(1, 1001, (3, 4), false).unwrap());
+        assert_eq!(1, dry.expenses);
+        assert_eq!(2, model.audit_entries().unwrap().len());
+
+        // Refusals are the same too.
+        assert!(matches!(
+            model.merge_categories(1, 1001, (3, 4), true),
+            Err(Error::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn merge_moves_subcategories() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let parent = |id| model.get_category(1, id).unwrap().unwrap().parent_id;
+        let food = model.add_test_category(1, "Food");
+        let eating = model.add_test_category(1, "Eating");
+        model.set_category_parent(1, 1, Some(food)).unwrap();
+        model.set_category_parent(1, 3, Some(food)).unwrap();
+
+        model
+            .merge_categories(1, 1001, (food, eating), false)
+            .unwrap();
+        assert_eq!((Some(eating), Some(eating)), (parent(1), parent(3)));
+
+        // Into one of its own subcategories, which takes the others.
+        model.merge_categories(1, 1001, (eating, 1), false).unwrap();
+        assert_eq!((None, Some(1)), (parent(1), parent(3)));
+
+        // Into a subcategory of another, which can't take them.
+        let other = model.add_test_category(1, "Other");
+        model.set_category_parent(1, 3, Some(other)).unwrap();
+        model.set_category_parent(1, 2, Some(1)).unwrap();
+        model.merge_categories(1, 1001, (1, 3), false).unwrap();
+        assert_eq!((None, Some(other)), (parent(2), parent(3)));
+    }
+
+    #[test]
+    fn merge_rejects_self_and_archived() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert!(matches!(
+            model.merge_categories(1, 1001, (3, 3), false),
+            Err(Error::Refused(_))
+        ));
+        model.merge_categories(1, 1001, (3, 4), false).unwrap();
+        assert!(matches!(
+            model.merge_categories(1, 1001, (1, 3), false),
+            Err(Error::Refused(_))
+        ));
+        assert!(matches!(
+            model.merge_categories(1, 1001, (3, 1), false),
+            Err(Error::Refused(_))
+        ));
+        assert!(matches!(
+            model.merge_categories(1, 1001, (1, 99), false),
+            Err(Error::NotFound(_))
+        ));
+        // Nothing moved by the refused merges.
+        assert_eq!(1, model.count_in_category(1, 1, None, Tz::UTC).unwrap());
+    }
+}
@@ -0,0 +1,355 @@
+use super::lengths::{check_length, MAX_NAME_LEN};
+use super::resolve::{self, Resolution};
+use super::{Account, Error, Model, Result};
+use rusqlite::OptionalExtension;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Category {
+    pub id: i64,
+    pub name: String,
+    pub icon: Option<String>,
+    /// Expenses in the category can't be saved without a comment.
+    pub require_comment: bool,
+    /// The category this one is a subcategory of. Only top-level categories
+    /// have subcategories.
+    pub parent_id: Option<i64>,
+}
+
+const SELECT_CATEGORY: &str =
+    "SELECT id, name, icon, requireComment, parentId FROM ExpenseCategory";
+
+impl Category {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Category> {
+        Ok(Category {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            icon: row.get(2)?,
+            require_comment: row.get(3)?,
+            parent_id: row.get(4)?,
+        })
+    }
+
+    /// Whether an expense with `comment` is missing the comment the category
+    /// requires. A comment of only whitespace is none.
+    pub fn lacks_comment(&self, comment: Option<&str>) -> bool {
+        self.require_comment && comment.is_none_or(|c| c.trim().is_empty())
+    }
+}
+
+impl Model {
+    /// Adds a category after the household's others, returns its id.
+    pub fn add_category(&self, household: i64, name: &str, icon: Option<&str>) -> Result<i64> {
+        self.check_writable()?;
+        check_length("Category name", name, MAX_NAME_LEN)?;
+        self.connection.execute(
+            "INSERT INTO ExpenseCategory (name, icon, householdId, sortingOrder)
+             SELECT ?1, ?2, ?3, COALESCE(MAX(sortingOrder), 0) + 1
+             FROM ExpenseCategory WHERE householdId = ?3",
+            rusqlite::params![name, icon, household],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Active categories in their sorting order.
+    pub fn categories(&self, household: i64) -> Result<Vec<Category>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE active AND householdId = ?1
+             ORDER BY sortingOrder IS NULL, sortingOrder, id",
+            SELECT_CATEGORY
+        ))?;
+        let categories = stmt
+            .query_map([household], Category::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(categories)
+    }
+
+    /// Resolves a typed name to one of the active categories.
+    pub fn resolve_category(&self, household: i64, name: &str) -> Result<Resolution<Category>> {
+        let candidates = self
+            .categories(household)?
+            .into_iter()
+            .map(|c| {
+                let names = vec![c.name.clone()];
+                (c, names)
+            })
+            .collect();
+        Ok(resolve::resolve(name, candidates))
+    }
+
+    /// The category if it belongs to the household, archived or not.
+    pub fn get_category(&self, household: i64, id: i64) -> Result<Option<Category>> {
+        let category = self
+            .connection
+            .query_row(
+                &format!("{} WHERE id = ?1 AND householdId = ?2", SELECT_CATEGORY),
+                [id, household],
+                Category::from_row,
+            )
+            .optional()?;
+        Ok(category)
+    }
+
+    /// Makes a comment mandatory for new and edited expenses of the category.
+    pub fn set_require_comment(&self, household: i64, id: i64, required: bool) -> Result<()> {
+        self.check_writable()?;
+        let updated = self.connection.execute(
+            "UPDATE ExpenseCategory SET requireComment = ?2 WHERE id = ?1 AND householdId = ?3",
+            rusqlite::params![id, required, household],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("category {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Makes the category a subcategory of `parent_id`, or a top-level one
+    /// again for `None`. Categories nest one level deep, so the parent must
+    /// be a top-level category and the category must have no subcategories
+    /// of its own; cycles can't come about either way.
+    pub fn set_category_parent(
+        &self,
+        household: i64,
+        id: i64,
+        parent_id: Option<i64>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let category = self
+            .get_category(household, id)?
+            .ok_or_else(|| Error::NotFound(format!("category {}", id)))?;
+        if let Some(parent_id) = parent_id {
+            let parent = self
+                .get_category(household, parent_id)?
+                .ok_or_else(|| Error::NotFound(format!("category {}", parent_id)))?;
+            if parent.id == category.id {
+                return Err(Error::Refused(format!(
+                    "{} can't be its own parent.",
+                    category.name
+                )));
+            }
+            if parent.parent_id == Some(category.id) {
+                return Err(Error::Refused(format!(
+                    "{} is a subcategory of {} already.",
+                    parent.name, category.name
+                )));
+            }
+            if parent.parent_id.is_some() {
+                return Err(Error::Refused(format!(
+                    "{} is a subcategory itself, categories nest only one level deep.",
+                    parent.name
+                )));
+            }
+            if self.has_subcategories(category.id)? {
+                return Err(Error::Refused(format!(
+                    "{} has subcategories, categories nest only one level deep.",
+                    category.name
+                )));
+            }
+        }
+        self.connection.execute(
+            "UPDATE ExpenseCategory SET parentId = ?2 WHERE id = ?1",
+            rusqlite::params![id, parent_id],
+        )?;
+        Ok(())
+    }
+
+    /// Binds the category to an account of the household, which drafts
+    /// move to when the category is picked, or unbinds it for `None`.
+    pub fn set_category_account(
+        &self,
+        household: i64,
+        id: i64,
+        account_id: Option<i64>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if let Some(account_id) = account_id {
+            self.get_account(household, account_id)?
+                .ok_or_else(|| Error::NotFound(format!("account {}", account_id)))?;
+        }
+        let updated = self.connection.execute(
+            "UPDATE ExpenseCategory SET defaultAccountId = ?2 WHERE id = ?1 AND householdId = ?3",
+            rusqlite::params![id, account_id, household],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("category {}", id)));
+        }
+        Ok(())
+    }
+
+    /// The account the category is bound to, if any.
+    pub fn category_account(&self, household: i64, id: i64) -> Result<Option<Account>> {
+        let account_id: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT defaultAccountId FROM ExpenseCategory WHERE id = ?1 AND householdId = ?2",
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        match account_id {
+            Some(account_id) => self.get_account(household, account_id),
+            None => Ok(None),
+        }
+    }
+
+    fn has_subcategories(&self, id: i64) -> Result<bool> {
+        let has = self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM ExpenseCategory WHERE parentId = ?1)",
+            [id],
+            |row| row.get(0),
+        )?;
+        Ok(has)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved_name(resolution: Resolution<Category>) -> Option<String> {
+        match resolution {
+            Resolution::Exact(c) | Resolution::UniquePrefix(c) => Some(c.name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn resolves_case_insensitively() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let resolve = |name| resolved_name(model.resolve_category(1, name).unwrap());
+        assert_eq!(Some("Groceries".to_string()), resolve("groceries"));
+        assert_eq!(Some("Groceries".to_string()), resolve("GRO"));
+        assert_eq!(Some("Utilities".to_string()), resolve(" util "));
+        assert_eq!(None, resolve("xyz"));
+        assert_eq!(None, resolve("%"));
+    }
+
+    #[test]
+    fn blank_comments_are_missing() {
+        let mut category = Category {
+            id: 1,
+            name: "Gifts".to_string(),
+            icon: None,
+            require_comment: true,
+            parent_id: None,
+        };
+        assert!(category.lacks_comment(None));
+        assert!(category.lacks_comment(Some("")));
+        assert!(category.lacks_comment(Some("  \n")));
+        assert!(!category.lacks_comment(Some(" for Hanna ")));
+        category.require_comment = false;
+        assert!(!category.lacks_comment(None));
+        assert!(!category.lacks_comment(Some(" ")));
+    }
+
+    #[test]
+    fn exact_match_wins_over_prefix() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .connection
+            .execute_batch(
+                "INSERT INTO ExpenseCategory (name, sortingOrder, householdId)
+                 VALUES ('Eating', 5, 1), ('Eating out', 6, 1);",
+            )
+            .unwrap();
+
+        assert!(matches!(
+            model.resolve_category(1, "eating").unwrap(),
+            Resolution::Exact(c) if c.name == "Eating"
+        ));
+        match model.resolve_category(1, "eat").unwrap() {
+            Resolution::Ambiguous(candidates) => assert_eq!(
+                vec!["Eating", "Eating out"],
+                candidates.into_iter().map(|c| c.name).collect::<Vec<_>>()
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nests_one_level_deep() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let food = model.add_test_category(1, "Food");
+        let parent = |id| model.get_category(1, id).unwrap().unwrap().parent_id;
+
+        model.set_category_parent(1, 1, Some(food)).unwrap();
+        model.set_category_parent(1, 3, Some(food)).unwrap();
+        assert_eq!(Some(food), parent(1));
+
+        // Under a subcategory, or with subcategories of its own.
+        assert!(matches!(
+            model.set_category_parent(1, 2, Some(1)),
+            Err(Error::Refused(_))
+        ));
+        assert!(matches!(
+            model.set_category_parent(1, food, Some(2)),
+            Err(Error::Refused(_))
+        ));
+
+        model.set_category_parent(1, 3, None).unwrap();
+        assert_eq!(None, parent(3));
+        assert!(matches!(
+            model.set_category_parent(1, 1, Some(99)),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.set_category_parent(2, 1, Some(food)),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn binds_accounts() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let bound = |id| model.category_account(1, id).unwrap().map(|a| a.name);
+        assert_eq!(None, bound(4));
+
+        model.set_category_account(1, 4, Some(3)).unwrap();
+        assert_eq!(Some("Family BYN".to_string()), bound(4));
+        assert_eq!(None, bound(1));
+        model.set_category_account(1, 4, None).unwrap();
+        assert_eq!(None, bound(4));
+
+        // Only to accounts of the same household.
+        let other = model.accounts(2).unwrap()[0].id;
+        assert!(matches!(
+            model.set_category_account(1, 4, Some(other)),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.set_category_account(2, 4, None),
+            Err(Error::NotFound(_))
+        ));
+
+        // Deleting the account unbinds the category.
+        let spare = model.add_account(1, "Spare", 1).unwrap();
+        model.set_category_account(1, 4, Some(spare)).unwrap();
+        model.delete_account(1, 1001, spare).unwrap();
+        assert_eq!(None, bound(4));
+    }
+
+    #[test]
+    fn refuses_cycles() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert!(matches!(
+            model.set_category_parent(1, 1, Some(1)),
+            Err(Error::Refused(_))
+        ));
+        model.set_category_parent(1, 2, Some(1)).unwrap();
+        assert!(matches!(
+            model.set_category_parent(1, 1, Some(2)),
+            Err(Error::Refused(_))
+        ));
+        // Re-parenting within the same parent is fine.
+        model.set_category_parent(1, 2, Some(1)).unwrap();
+        assert_eq!(None, model.get_category(1, 1).unwrap().unwrap().parent_id);
+    }
+}
@@ -0,0 +1,443 @@
+//! `/category stats`: how the spend on a category looked over the last few
+//! months, and which comments dominate it.
+
+use super::amount::from_minor;
+use super::year::{expenses, starts, BOUNDS};
+use super::{Error, Model, Result};
+use crate::time::{self, DbTime};
+use chrono::{DateTime, Days, Months, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+use std::collections::HashMap;
+
+/// The windows reported, in months back from today.
+const DETAIL_WINDOWS: [u32; 4] = [1, 3, 6, 12];
+
+/// How many of the most frequent comments are listed.
+const TOP_COMMENTS: usize = 5;
+
+/// Weeks of the trend, the current one included.
+const TREND_WEEKS: usize = 12;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryDetail {
+    pub category: String,
+    /// Currencies with expenses in the longest window, by name.
+    pub currencies: Vec<CurrencyDetail>,
+    /// The most frequent comments of the longest window, folded to lower
+    /// case, with how often they occur.
+    pub top_comments: Vec<(String, i64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrencyDetail {
+    pub currency: String,
+    pub exponent: u32,
+    /// One per `DETAIL_WINDOWS`, in the same order.
+    pub windows: Vec<WindowDetail>,
+    /// Spend per week of the household's calendar, the oldest of
+    /// `TREND_WEEKS` first and the current one last, 0 without any.
+    pub weekly: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowDetail {
+    pub months: u32,
+    /// Length of the window up to now.
+    pub weeks: f64,
+    /// `None` without expenses in the window.
+    pub spend: Option<WindowSpend>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowSpend {
+    pub count: i64,
+    pub total: f64,
+    pub min: (f64, DateTime<Utc>),
+    pub max: (f64, DateTime<Utc>),
+}
+
+impl WindowDetail {
+    pub fn average(&self) -> Option<f64> {
+        self.spend.as_ref().map(|s| s.total / s.count as f64)
+    }
+
+    pub fn per_week(&self) -> Option<f64> {
+        self.spend.as_ref().map(|s| s.total / self.weeks)
+    }
+}
+
+/// An amount and its timestamp in one text that sorts like the amount, then
+/// the time, so that `MIN` and `MAX` find both at once. Amounts are positive
+/// and the windows lie after the epoch.
+fn extreme(aggregate: &str, when: &str) -> String {
+    format!(
+        "{}(CASE WHEN {} THEN printf('%020d %020d', e.amountMinor, e.timestamp) END)",
+        aggregate, when
+    )
+}
+
+/// Parses what `extreme` made back into major units and a time.
+fn parse_extreme(text: &str, exponent: u32) -> Option<(f64, DateTime<Utc>)> {
+    let (amount, timestamp) = text.split_once(' ')?;
+    Some((
+        from_minor(amount.parse().ok()?, exponent),
+        time::from_db(timestamp.parse().ok()?)?,
+    ))
+}
+
+impl Model {
+    /// The spend on the category and its subcategories per local week, by
+    /// currency: `weeks` totals each, the current week last. Weeks start on
+    /// the household's week start, and all come from one query.
+    pub fn weekly_totals(
+        &self,
+        household: i64,
+        category_id: i64,
+        weeks: usize,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<(String, Vec<f64>)>> {
+        let calendar = self.calendar(household)?;
+        let this_week = calendar.week_of(now.with_timezone(&tz).date_naive());
+        let first = this_week - Days::new(7 * (weeks as u64).saturating_sub(1));
+        let week_starts = starts((0..=weeks as u64).map(|n| first + Days::new(7 * n)), tz);
+        let tables = self.expense_tables(Some(time::start_of_day(first, tz)))?;
+        let mut stmt = self.connection.prepare(&format!(
+            "{} SELECT c.name, c.exponent, b.n, SUM(e.amountMinor) {}
+             JOIN bounds b ON e.timestamp >= b.start AND e.timestamp < b.end
+             WHERE e.categoryId IN (
+                 SELECT id FROM ExpenseCategory WHERE id = ?3 OR parentId = ?3
+             )
+             GROUP BY c.id, b.n
+             ORDER BY c.name",
+            BOUNDS,
+            expenses(tables, 2)
+        ))?;
+        let rows = stmt
+            .query_map(params![week_starts, household, category_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, usize>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut totals: Vec<(String, Vec<f64>)> = Vec::new();
+        for (currency, exponent, week, minor) in rows {
+            if totals.last().is_none_or(|(name, _)| *name != currency) {
+                totals.push((currency, vec![0.0; weeks]));
+            }
+            let (_, weekly) = totals.last_mut().expect("pushed above");
+            weekly[week] = from_minor(minor, exponent);
+        }
+        Ok(totals)
+    }
+
+    /// The spend on the category and its subcategories in each of
+    /// `DETAIL_WINDOWS`, per currency. All windows come from one pass over
+    /// the expenses of the longest.
+    pub fn category_detail_stats(
+        &self,
+        household: i64,
+        category_id: i64,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<CategoryDetail> {
+        let category = self
+            .get_category(household, category_id)?
+            .ok_or_else(|| Error::NotFound(format!("category {}", category_id)))?;
+        let today = now.with_timezone(&tz).date_naive();
+        let starts = DETAIL_WINDOWS
+            .iter()
+            .map(|&months| {
+                today
+                    .checked_sub_months(Months::new(months))
+                    .map(|day| time::start_of_day(day, tz))
+                    .ok_or_else(|| Error::NotFound(format!("{} months before {}", months, today)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let longest = *starts.last().expect("there are windows");
+        let tables = self.expense_tables(Some(longest))?;
+
+        // ?1 to ?4 are the starts of the windows.
+        let columns: Vec<String> = (1..=DETAIL_WINDOWS.len())
+            .map(|n| {
+                let when = format!("e.timestamp >= ?{}", n);
+                format!(
+                    "COUNT(CASE WHEN {when} THEN 1 END),
+                     SUM(CASE WHEN {when} THEN e.amountMinor END),
+                     {min}, {max}",
+                    when = when,
+                    min = extreme("MIN", &when),
+                    max = extreme("MAX", &when),
+                )
+            })
+            .collect();
+        let in_category = "e.categoryId IN (
+                SELECT id FROM ExpenseCategory WHERE id = ?6 OR parentId = ?6
+            )
+            AND e.timestamp >= ?4 AND e.timestamp <= ?7";
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT c.name, c.exponent, {}
+             {}
+             WHERE {}
+             GROUP BY c.id
+             ORDER BY c.name",
+            columns.join(", "),
+            expenses(tables, 5),
+            in_category
+        ))?;
+        let weeks: Vec<f64> = starts
+            .iter()
+            .map(|start| (now - *start).num_seconds() as f64 / (7.0 * 86_400.0))
+            .collect();
+        let bound = params![
+            DbTime(starts[0]),
+            DbTime(starts[1]),
+            DbTime(starts[2]),
+            DbTime(starts[3]),
+            household,
+            category_id,
+            DbTime(now)
+        ];
+        let mut currencies = stmt
+            .query_map(bound, |row| {
+                let exponent: u32 = row.get(1)?;
+                let mut windows = Vec::new();
+                for (i, &months) in DETAIL_WINDOWS.iter().enumerate() {
+                    let column = 2 + 4 * i;
+                    let count: i64 = row.get(column)?;
+                    let spend = if count == 0 {
+                        None
+                    } else {
+                        let extreme = |offset: usize| -> rusqlite::Result<_> {
+                            let text: String = row.get(column + offset)?;
+                            parse_extreme(&text, exponent).ok_or_else(|| {
+                                rusqlite::Error::InvalidColumnType(
+                                    column + offset,
+                                    text,
+                                    rusqlite::types::Type::Text,
+                                )
+                            })
+                        };
+                        Some(WindowSpend {
+                            count,
+                            total: from_minor(row.get(column + 1)?, exponent),
+                            min: extreme(2)?,
+                            max: extreme(3)?,
+                        })
+                    };
+                    windows.push(WindowDetail {
+                        months,
+                        weeks: weeks[i],
+                        spend,
+                    });
+                }
+                Ok(CurrencyDetail {
+                    currency: row.get(0)?,
+                    exponent,
+                    windows,
+                    weekly: vec![0.0; TREND_WEEKS],
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (currency, weekly) in
+            self.weekly_totals(household, category_id, TREND_WEEKS, now, tz)?
+        {
+            // The weeks lie within the longest window, so its currencies
+            // have all of theirs.
+            if let Some(c) = currencies.iter_mut().find(|c| c.currency == currency) {
+                c.weekly = weekly;
+            }
+        }
+
+        // SQLite's lower() only folds ASCII, so the comments are counted here.
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT e.comments {} WHERE {} AND e.comments IS NOT NULL",
+            expenses(tables, 5),
+            in_category
+        ))?;
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        let mut rows = stmt.query(bound)?;
+        while let Some(row) = rows.next()? {
+            let comment = row.get::<_, String>(0)?.trim().to_lowercase();
+            if !comment.is_empty() {
+                *counts.entry(comment).or_default() += 1;
+            }
+        }
+        let mut top_comments: Vec<(String, i64)> = counts.into_iter().collect();
+        top_comments.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_comments.truncate(TOP_COMMENTS);
+
+        Ok(CategoryDetail {
+            category: category.name,
+            currencies,
+            top_comments,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::TimeDelta;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-15T12:00:00Z")
+            .unwrap()
+            .to_utc()
+    }
+
+    fn log(model: &Model, category_id: i64, days_ago: i64, amount: f64, comment: &str) {
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id,
+                user_id: 1001,
+                timestamp: now() - TimeDelta::days(days_ago),
+                amount,
+                comment: Some(comment.to_string()),
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn windows_from_one_query() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let snacks = model.add_category(1, "Snacks", None).unwrap();
+        model.set_category_parent(1, snacks, Some(1)).unwrap();
+        log(&model, 1, 5, 10.0, "Coffee");
+        log(&model, 1, 3, 4.0, " coffee ");
+        log(&model, snacks, 2, 6.0, "КОФЕ");
+        log(&model, 1, 300, 20.0, "кофе");
+        // Outside every window.
+        log(&model, 1, 400, 999.0, "coffee");
+
+        let detail = model
+            .category_detail_stats(1, 1, now(), chrono_tz::UTC)
+            .unwrap();
+        assert_eq!("Groceries", detail.category);
+        assert_eq!(1, detail.currencies.len());
+        let eur = &detail.currencies[0];
+        assert_eq!("EUR", eur.currency);
+        let months: Vec<u32> = eur.windows.iter().map(|w| w.months).collect();
+        assert_eq!(DETAIL_WINDOWS.to_vec(), months);
+
+        // The last month has the three January ones, the test data's
+        // December expense is from the 1st.
+        let month = eur.windows[0].spend.as_ref().unwrap();
+        assert_eq!(3, month.count);
+        assert!((month.total - 20.0).abs() < 1e-9);
+        assert_eq!((4.0, now() - TimeDelta::days(3)), month.min);
+        assert_eq!((10.0, now() - TimeDelta::days(5)), month.max);
+        assert!((eur.windows[0].average().unwrap() - 20.0 / 3.0).abs() < 1e-9);
+        assert!((eur.windows[0].per_week().unwrap() - 20.0 / (31.5 / 7.0)).abs() < 1e-9);
+
+        let quarter = eur.windows[1].spend.as_ref().unwrap();
+        assert_eq!(4, quarter.count);
+        assert_eq!(50.75, quarter.max.0);
+        let year = eur.windows[3].spend.as_ref().unwrap();
+        assert_eq!(5, year.count);
+        assert!((year.total - 90.75).abs() < 1e-9);
+
+        assert_eq!(
+            vec![("coffee".to_string(), 2), ("кофе".to_string(), 2)],
+            detail.top_comments[..2].to_vec()
+        );
+    }
+
+    #[test]
+    fn weeks_across_the_new_year() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let snacks = model.add_category(1, "Snacks", None).unwrap();
+        model.set_category_parent(1, snacks, Some(1)).unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        let log_at = |account_id, category_id, timestamp: &str, amount| {
+            model
+                .insert_expense(&NewExpense {
+                    account_id,
+                    category_id,
+                    user_id: 1001,
+                    timestamp: at(timestamp),
+                    amount,
+                    comment: None,
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        };
+        // Sunday evening, Christmas Eve in Berlin.
+        log_at(1, 1, "2023-12-24T22:30:00Z", 1.0);
+        log_at(1, snacks, "2023-12-28T12:00:00Z", 2.0);
+        // Past midnight of the new year in Berlin, not in UTC.
+        log_at(1, 1, "2023-12-31T23:30:00Z", 4.0);
+        log_at(2, 1, "2024-01-02T12:00:00Z", 8.0);
+        // Wednesday 3 January; the test data's December ones are older.
+        let now = at("2024-01-03T12:00:00Z");
+        let weekly = |tz| model.weekly_totals(1, 1, 4, now, tz).unwrap();
+
+        // Weeks from Monday 11, 18 and 25 December and 1 January.
+        assert_eq!(
+            vec![
+                ("EUR".to_string(), vec![0.0, 1.0, 2.0, 4.0]),
+                ("USD".to_string(), vec![0.0, 0.0, 0.0, 8.0]),
+            ],
+            weekly(chrono_tz::Europe::Berlin)
+        );
+        assert_eq!(vec![0.0, 1.0, 6.0, 0.0], weekly(chrono_tz::UTC)[0].1);
+
+        model
+            .set_calendar(
+                1,
+                crate::model::Calendar {
+                    week_start: chrono::Weekday::Sun,
+                    month_start: 1,
+                },
+            )
+            .unwrap();
+        // Weeks from Sunday 10, 17, 24 and 31 December, Christmas Eve
+        // starting one.
+        assert_eq!(
+            vec![0.0, 0.0, 3.0, 4.0],
+            weekly(chrono_tz::Europe::Berlin)[0].1
+        );
+        assert_eq!(vec![0.0, 0.0, 3.0, 4.0], weekly(chrono_tz::UTC)[0].1);
+
+        let detail = model
+            .category_detail_stats(1, 1, now, chrono_tz::UTC)
+            .unwrap();
+        assert_eq!(TREND_WEEKS, detail.currencies[0].weekly.len());
+        assert_eq!(&[3.0, 4.0], &detail.currencies[0].weekly[10..]);
+    }
+
+    #[test]
+    fn windows_without_expenses_are_empty() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        log(&model, 2, 150, 15.0, "bus");
+        let detail = model
+            .category_detail_stats(1, 2, now(), chrono_tz::UTC)
+            .unwrap();
+        let spends: Vec<bool> = detail.currencies[0]
+            .windows
+            .iter()
+            .map(|w| w.spend.is_some())
+            .collect();
+        // The EUR bus ticket is in the last 12 months only.
+        assert_eq!("EUR", detail.currencies[0].currency);
+        assert_eq!(vec![false, false, true, true], spends);
+        assert_eq!(None, detail.currencies[0].windows[0].average());
+
+        // Another household's category isn't found.
+        assert!(model
+            .category_detail_stats(2, 1, now(), chrono_tz::UTC)
+            .is_err());
+    }
+}
@@ -0,0 +1,449 @@
+//! Settings of a chat rather than of its users.
+
+use super::{Error, Model, Result};
+use crate::time;
+use chrono::{DateTime, NaiveTime, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
+use rusqlite::{params, OptionalExtension};
+
+/// How much a chat is told about a committed expense.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmMode {
+    /// The expense, with the month to date and the daily limit.
+    Full,
+    /// The expense on a single line.
+    Compact,
+    /// Only a ✅.
+    Silent,
+}
+
+impl ConfirmMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConfirmMode::Full => "full",
+            ConfirmMode::Compact => "compact",
+            ConfirmMode::Silent => "silent",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<ConfirmMode> {
+        match text.trim().to_lowercase().as_str() {
+            "full" => Some(ConfirmMode::Full),
+            "compact" => Some(ConfirmMode::Compact),
+            "silent" => Some(ConfirmMode::Silent),
+            _ => None,
+        }
+    }
+}
+
+/// The local hours of a chat, from `start` to `end` o'clock, that scheduled
+/// messages wait out. Equal hours are none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl QuietHours {
+    /// The quiet hours of a chat that didn't set any, 22:00 to 08:00.
+    pub const DEFAULT: QuietHours = QuietHours { start: 22, end: 8 };
+
+    pub fn is_off(self) -> bool {
+        self.start == self.end
+    }
+
+    fn contains(self, hour: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&hour)
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+
+    /// When a message scheduled at `now` may go out, local time being in
+    /// `tz`: right away outside the quiet hours, else at their end, the next
+    /// morning for hours across midnight.
+    pub fn release(self, now: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+        let local = now.with_timezone(&tz);
+        if !self.contains(local.hour()) {
+            return now;
+        }
+        let mut date = local.date_naive();
+        if local.hour() >= self.end {
+            date += TimeDelta::days(1);
+        }
+        let end = NaiveTime::from_hms_opt(self.end, 0, 0).unwrap();
+        time::from_local(date.and_time(end), tz)
+    }
+}
+
+impl Model {
+    /// The pinned feed message of the chat, `None` if the feed is off.
+    pub fn feed_message(&self, chat_id: i64) -> Result<Option<i32>> {
+        let message_id = self
+            .connection
+            .query_row(
+                "SELECT feedMessageId FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(message_id.flatten())
+    }
+
+    /// Records the feed message of the chat, `None` turning the feed off.
+    /// The feed shows the totals of `household`, so a chat that isn't bound
+    /// yet is bound to it.
+    pub fn set_feed_message(
+        &self,
+        chat_id: i64,
+        household: i64,
+        message_id: Option<i32>,
+    ) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO ChatSettings (chatId, householdId, feedMessageId) VALUES (?1, ?2, ?3)
+             ON CONFLICT (chatId) DO UPDATE SET feedMessageId = excluded.feedMessageId",
+            params![chat_id, household, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// The chats with a feed of `household`, by id.
+    pub fn feed_chats(&self, household: i64) -> Result<Vec<i64>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT chatId FROM ChatSettings
+             WHERE householdId = ?1 AND feedMessageId IS NOT NULL
+             ORDER BY chatId",
+        )?;
+        let chats = stmt
+            .query_map([household], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(chats)
+    }
+
+    /// Whether the chat masks amounts, see `/settings privacy`.
+    pub fn chat_privacy(&self, chat_id: i64) -> Result<bool> {
+        let mask = self
+            .connection
+            .query_row(
+                "SELECT maskAmounts FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(mask.unwrap_or(false))
+    }
+
+    /// Turns masking amounts in the chat on or off. Like the feed, turning
+    /// it on binds a chat that isn't bound yet to `household`.
+    pub fn set_chat_privacy(&self, chat_id: i64, household: i64, on: bool) -> Result<()> {
+        self.check_writable()?;
+        if on {
+            self.connection.execute(
+                "INSERT INTO ChatSettings (chatId, householdId, maskAmounts) VALUES (?1, ?2, 1)
+                 ON CONFLICT (chatId) DO UPDATE SET maskAmounts = 1",
+                params![chat_id, household],
+            )?;
+        } else {
+            self.connection.execute(
+                "UPDATE ChatSettings SET maskAmounts = 0 WHERE chatId = ?1",
+                [chat_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// How commits are confirmed in the chat, see `/settings confirm`.
+    pub fn chat_confirm_mode(&self, chat_id: i64) -> Result<ConfirmMode> {
+        let mode: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT confirmMode FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(mode
+            .and_then(|mode| ConfirmMode::parse(&mode))
+            .unwrap_or(ConfirmMode::Full))
+    }
+
+    /// Sets how commits are confirmed in the chat. Like the feed, a chat
+    /// that isn't bound yet is bound to `household`.
+    pub fn set_chat_confirm_mode(
+        &self,
+        chat_id: i64,
+        household: i64,
+        mode: ConfirmMode,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT INTO ChatSettings (chatId, householdId, confirmMode) VALUES (?1, ?2, ?3)
+             ON CONFLICT (chatId) DO UPDATE SET confirmMode = excluded.confirmMode",
+            params![chat_id, household, mode.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// The currency bare amounts typed in the chat are in, see
+    /// `/settings currency`.
+    pub fn chat_assumed_currency(&self, chat_id: i64) -> Result<Option<String>> {
+        let currency = self
+            .connection
+            .query_row(
+                "SELECT assumedCurrency FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(currency.flatten())
+    }
+
+    /// Sets the currency bare amounts in the chat are in, `None` for none.
+    /// Like the feed, a chat that isn't bound yet is bound to `household`.
+    pub fn set_chat_assumed_currency(
+        &self,
+        chat_id: i64,
+        household: i64,
+        currency: Option<&str>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT INTO ChatSettings (chatId, householdId, assumedCurrency) VALUES (?1, ?2, ?3)
+             ON CONFLICT (chatId) DO UPDATE SET assumedCurrency = excluded.assumedCurrency",
+            params![chat_id, household, currency],
+        )?;
+        Ok(())
+    }
+
+    /// The quiet hours of the chat, see `/settings quiet`.
+    pub fn chat_quiet_hours(&self, chat_id: i64) -> Result<QuietHours> {
+        let hours = self
+            .connection
+            .query_row(
+                "SELECT quietStart, quietEnd FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| {
+                    Ok(QuietHours {
+                        start: row.get(0)?,
+                        end: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(hours.unwrap_or(QuietHours::DEFAULT))
+    }
+
+    /// Sets the quiet hours of the chat. Like the feed, a chat that isn't
+    /// bound yet is bound to `household`.
+    pub fn set_chat_quiet_hours(
+        &self,
+        chat_id: i64,
+        household: i64,
+        hours: QuietHours,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if hours.start > 23 || hours.end > 23 {
+            return Err(Error::Refused(
+                "Quiet hours go from 0 to 23 o'clock.".to_string(),
+            ));
+        }
+        self.connection.execute(
+            "INSERT INTO ChatSettings (chatId, householdId, quietStart, quietEnd)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (chatId) DO UPDATE
+             SET quietStart = excluded.quietStart, quietEnd = excluded.quietEnd",
+            params![chat_id, household, hours.start, hours.end],
+        )?;
+        Ok(())
+    }
+
+    /// The time zone of the chat, see `/settings timezone`, `None` for the
+    /// bot's. A zone this build doesn't know any more counts as none.
+    pub fn chat_time_zone(&self, chat_id: i64) -> Result<Option<Tz>> {
+        let zone: Option<Option<String>> = self
+            .connection
+            .query_row(
+                "SELECT timeZone FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(zone.flatten().and_then(|zone| zone.parse().ok()))
+    }
+
+    /// Sets the time zone of the chat, `None` for the bot's. Like the feed,
+    /// a chat that isn't bound yet is bound to `household`.
+    pub fn set_chat_time_zone(&self, chat_id: i64, household: i64, tz: Option<Tz>) -> Result<()> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT INTO ChatSettings (chatId, householdId, timeZone) VALUES (?1, ?2, ?3)
+             ON CONFLICT (chatId) DO UPDATE SET timeZone = excluded.timeZone",
+            params![chat_id, household, tz.map(|tz| tz.name())],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_message_per_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        assert_eq!(None, model.feed_message(-100).unwrap());
+
+        model.set_feed_message(-100, 1, Some(42)).unwrap();
+        model.set_feed_message(-200, 1, Some(7)).unwrap();
+        model.set_feed_message(-100, 1, Some(43)).unwrap();
+        assert_eq!(Some(43), model.feed_message(-100).unwrap());
+        assert_eq!(Some(7), model.feed_message(-200).unwrap());
+
+        model.set_feed_message(-300, 2, Some(8)).unwrap();
+        assert_eq!(vec![-200, -100], model.feed_chats(1).unwrap());
+        assert_eq!(vec![-300], model.feed_chats(2).unwrap());
+
+        model.set_feed_message(-100, 1, None).unwrap();
+        assert_eq!(None, model.feed_message(-100).unwrap());
+        assert_eq!(vec![-200], model.feed_chats(1).unwrap());
+    }
+
+    #[test]
+    fn privacy_per_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        assert!(!model.chat_privacy(-100).unwrap());
+        // Turning it off doesn't bind the chat.
+        model.set_chat_privacy(-100, 2, false).unwrap();
+        assert_eq!(None, model.chat_household(-100).unwrap());
+
+        model.set_chat_privacy(-100, 2, true).unwrap();
+        assert!(model.chat_privacy(-100).unwrap());
+        assert!(!model.chat_privacy(-200).unwrap());
+        assert_eq!(Some(2), model.chat_household(-100).unwrap());
+
+        // The feed keeps it, and it keeps the binding.
+        model.set_feed_message(-100, 1, Some(42)).unwrap();
+        model.set_chat_privacy(-100, 1, false).unwrap();
+        assert!(!model.chat_privacy(-100).unwrap());
+        assert_eq!(Some(42), model.feed_message(-100).unwrap());
+        assert_eq!(Some(2), model.chat_household(-100).unwrap());
+    }
+
+    #[test]
+    fn confirm_mode_per_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(ConfirmMode::Full, model.chat_confirm_mode(-100).unwrap());
+
+        model
+            .set_chat_confirm_mode(-100, 1, ConfirmMode::Silent)
+            .unwrap();
+        model.set_feed_message(-100, 1, Some(42)).unwrap();
+        assert_eq!(ConfirmMode::Silent, model.chat_confirm_mode(-100).unwrap());
+        assert_eq!(ConfirmMode::Full, model.chat_confirm_mode(-200).unwrap());
+        assert_eq!(Some(1), model.chat_household(-100).unwrap());
+
+        model
+            .set_chat_confirm_mode(-100, 1, ConfirmMode::Compact)
+            .unwrap();
+        assert_eq!(ConfirmMode::Compact, model.chat_confirm_mode(-100).unwrap());
+        assert_eq!(Some(42), model.feed_message(-100).unwrap());
+        assert_eq!(None, ConfirmMode::parse("loud"));
+    }
+
+    #[test]
+    fn assumed_currency_per_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(None, model.chat_assumed_currency(-100).unwrap());
+
+        model.set_feed_message(-100, 1, Some(42)).unwrap();
+        model
+            .set_chat_assumed_currency(-100, 1, Some("USD"))
+            .unwrap();
+        model
+            .set_chat_assumed_currency(1001, 1, Some("EUR"))
+            .unwrap();
+        assert_eq!(
+            (Some("USD".to_string()), Some("EUR".to_string())),
+            (
+                model.chat_assumed_currency(-100).unwrap(),
+                model.chat_assumed_currency(1001).unwrap()
+            )
+        );
+        assert_eq!(Some(42), model.feed_message(-100).unwrap());
+
+        model.set_chat_assumed_currency(-100, 1, None).unwrap();
+        assert_eq!(None, model.chat_assumed_currency(-100).unwrap());
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn quiet_hours_per_chat() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(QuietHours::DEFAULT, model.chat_quiet_hours(-100).unwrap());
+        assert_eq!(None, model.chat_time_zone(-100).unwrap());
+
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let hours = QuietHours { start: 23, end: 7 };
+        model.set_chat_quiet_hours(-100, 1, hours).unwrap();
+        model.set_chat_time_zone(-100, 1, Some(berlin)).unwrap();
+        assert_eq!(hours, model.chat_quiet_hours(-100).unwrap());
+        assert_eq!(Some(berlin), model.chat_time_zone(-100).unwrap());
+        assert_eq!(QuietHours::DEFAULT, model.chat_quiet_hours(-200).unwrap());
+        assert!(matches!(
+            model.set_chat_quiet_hours(-100, 1, QuietHours { start: 24, end: 7 }),
+            Err(Error::Refused(_))
+        ));
+
+        model.set_chat_time_zone(-100, 1, None).unwrap();
+        assert_eq!(None, model.chat_time_zone(-100).unwrap());
+        assert_eq!(hours, model.chat_quiet_hours(-100).unwrap());
+    }
+
+    #[test]
+    fn release_after_quiet_hours() {
+        let hours = QuietHours::DEFAULT;
+        let utc = Tz::UTC;
+        // Outside them, right away.
+        let noon = at("2023-12-04T12:00:00Z");
+        assert_eq!(noon, hours.release(noon, utc));
+        assert_eq!(
+            at("2023-12-04T21:59:00Z"),
+            hours.release(at("2023-12-04T21:59:00Z"), utc)
+        );
+        // Before midnight, the next morning; after it, the same one.
+        assert_eq!(
+            at("2023-12-05T08:00:00Z"),
+            hours.release(at("2023-12-04T22:00:00Z"), utc)
+        );
+        assert_eq!(
+            at("2023-12-05T08:00:00Z"),
+            hours.release(at("2023-12-05T07:59:00Z"), utc)
+        );
+        // In local time: 23:30 in Berlin is 22:30 UTC.
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            at("2023-12-05T07:00:00Z"),
+            hours.release(at("2023-12-04T22:30:00Z"), berlin)
+        );
+        // Hours within a day, and none.
+        let siesta = QuietHours { start: 13, end: 15 };
+        assert_eq!(
+            at("2023-12-04T15:00:00Z"),
+            siesta.release(at("2023-12-04T13:30:00Z"), utc)
+        );
+        let off = QuietHours { start: 0, end: 0 };
+        assert!(off.is_off());
+        assert_eq!(noon, off.release(noon, utc));
+    }
+}
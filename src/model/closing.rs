@@ -0,0 +1,421 @@
+//! Closed months: once a household's month is reconciled, an admin closes
+//! it and its expenses stay as they are. Every change of an expense checks
+//! here, wherever it comes from, until the month is reopened.
+
+use super::{audit, parse_month, DateRange, Error, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// Months are stored as `YYYY-MM`.
+const MONTH: &str = "%Y-%m";
+
+/// The first day of the month of `date`.
+fn first_day(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a 1st")
+}
+
+impl Model {
+    /// Closes the household's calendar month starting on `month`, its
+    /// bounds the local midnights of `tz`. Only months that are over can be
+    /// closed.
+    pub fn close_month(
+        &self,
+        household: i64,
+        user_id: i64,
+        month: NaiveDate,
+        (now, tz): (DateTime<Utc>, Tz),
+    ) -> Result<()> {
+        self.check_writable()?;
+        let month = first_day(month);
+        let this_month = first_day(now.with_timezone(&tz).date_naive());
+        if month >= this_month {
+            return Err(Error::Refused(format!(
+                "{} isn't over yet, only past months can be closed.",
+                month.format("%B %Y")
+            )));
+        }
+        let (start, end) = DateRange {
+            start: month,
+            end: month + Months::new(1),
+        }
+        .to_utc(tz);
+        let tx = self.connection.unchecked_transaction()?;
+        let inserted = tx.execute(
+            "INSERT INTO ClosedPeriod (householdId, month, startsAt, endsAt, closedBy, closedAt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (householdId, month) DO NOTHING",
+            params![
+                household,
+                month.format(MONTH).to_string(),
+                DbTime(start),
+                DbTime(end),
+                user_id,
+                DbTime(now)
+            ],
+        )?;
+        if inserted == 0 {
+            return Err(Error::Refused(format!(
+                "{} is closed already.",
+                month.format("%B %Y")
+            )));
+        }
+        audit::record(&tx, user_id, "close", &month.format(MONTH).to_string())?;
+        tx.commit()?;
+        info!("Closed {} of household {}", month.format(MONTH), household);
+        Ok(())
+    }
+
+    /// Opens the closed month starting on `month` for changes again.
+    pub fn reopen_month(&self, household: i64, user_id: i64, month: NaiveDate) -> Result<()> {
+        self.check_writable()?;
+        let month = first_day(month);
+        let tx = self.connection.unchecked_transaction()?;
+        let deleted = tx.execute(
+            "DELETE FROM ClosedPeriod WHERE householdId = ?1 AND month = ?2",
+            params![household, month.format(MONTH).to_string()],
+        )?;
+        if deleted == 0 {
+            return Err(Error::Refused(format!(
+                "{} isn't closed.",
+                month.format("%B %Y")
+            )));
+        }
+        audit::record(&tx, user_id, "reopen", &month.format(MONTH).to_string())?;
+        tx.commit()?;
+        info!(
+            "Reopened {} of household {}",
+            month.format(MONTH),
+            household
+        );
+        Ok(())
+    }
+
+    /// The first days of the household's closed months, latest first.
+    pub fn closed_months(&self, household: i64) -> Result<Vec<NaiveDate>> {
+        let months = self
+            .connection
+            .prepare("SELECT month FROM ClosedPeriod WHERE householdId = ?1 ORDER BY month DESC")?
+            .query_map([household], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(months.iter().filter_map(|m| parse_month(m)).collect())
+    }
+
+    /// Whether every calendar month `range` touches is closed, so that its
+    /// figures can't change anymore.
+    pub fn is_closed(&self, household: i64, range: DateRange) -> Result<bool> {
+        let closed = self.closed_months(household)?;
+        let mut month = first_day(range.start);
+        while month < range.end {
+            if !closed.contains(&month) {
+                return Ok(false);
+            }
+            month = month + Months::new(1);
+        }
+        Ok(true)
+    }
+
+    /// Refuses a change of the household's expenses at `at` if its month is
+    /// closed.
+    pub(super) fn check_open(&self, household: i64, at: DateTime<Utc>) -> Result<()> {
+        let month: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT month FROM ClosedPeriod
+                 WHERE householdId = ?1 AND startsAt <= ?2 AND ?2 < endsAt",
+                params![household, DbTime(at)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        closed(month)
+    }
+
+    /// Like [`Model::check_open`] for the stored expense `id`, letting the
+    /// change find out by itself if there is no such expense.
+    pub(super) fn check_expense_open(&self, household: i64, id: i64) -> Result<()> {
+        let month: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT p.month FROM Expense e
+                 JOIN ClosedPeriod p ON p.householdId = ?2
+                     AND p.startsAt <= e.timestamp AND e.timestamp < p.endsAt
+                 WHERE e.id = ?1",
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?;
+        closed(month)
+    }
+
+    /// Like [`Model::check_open`] for every stored expense matching
+    /// `filter`, a condition on `e` with `?1` for `id`, refused with the
+    /// earliest closed month among them.
+    pub(super) fn check_expenses_open(&self, household: i64, filter: &str, id: i64) -> Result<()> {
+        let month: Option<String> = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT p.month FROM Expense e
+                     JOIN ClosedPeriod p ON p.householdId = ?2
+                         AND p.startsAt <= e.timestamp AND e.timestamp < p.endsAt
+                     WHERE {}
+                     ORDER BY p.month LIMIT 1",
+                    filter
+                ),
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?;
+        closed(month)
+    }
+}
+
+/// The refusal for a closed `month`, if any.
+pub(super) fn closed(month: Option<String>) -> Result<()> {
+    match month.as_deref().and_then(parse_month) {
+        Some(month) => Err(Error::Closed(month)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense, SourceMessage};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn december() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 12, 1).unwrap()
+    }
+
+    fn expense(timestamp: &str) -> NewExpense {
+        NewExpense {
+            amount: 12.0,
+            account_id: 1,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: at(timestamp),
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        }
+    }
+
+    fn is_closed<T: std::fmt::Debug>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Closed(month)) if month == december())
+    }
+
+    /// The test data, with December 2023 closed in January.
+    fn closed_december() -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let january = (at("2024-01-10T12:00:00Z"), Tz::UTC);
+        model.close_month(1, 1001, december(), january).unwrap();
+        model
+    }
+
+    #[test]
+    fn closing_and_reopening() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = at("2023-12-20T12:00:00Z");
+        let november = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let refused = |result: Result<()>| match result {
+            Err(Error::Refused(reason)) => reason,
+            other => panic!("{:?}", other),
+        };
+
+        assert_eq!(
+            "December 2023 isn't over yet, only past months can be closed.",
+            refused(model.close_month(1, 1001, december(), (now, Tz::UTC)))
+        );
+        // Nor months to come.
+        let january = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        assert!(model.close_month(1, 1001, january, (now, Tz::UTC)).is_err());
+        // Any day names its month.
+        let mid_november = NaiveDate::from_ymd_opt(2023, 11, 15).unwrap();
+        model
+            .close_month(1, 1001, mid_november, (now, Tz::UTC))
+            .unwrap();
+        assert_eq!(
+            "November 2023 is closed already.",
+            refused(model.close_month(1, 1001, november, (now, Tz::UTC)))
+        );
+        assert_eq!(vec![november], model.closed_months(1).unwrap());
+        assert!(model.closed_months(2).unwrap().is_empty());
+
+        model.reopen_month(1, 1001, november).unwrap();
+        assert_eq!(
+            "November 2023 isn't closed.",
+            refused(model.reopen_month(1, 1001, november))
+        );
+        assert!(model.closed_months(1).unwrap().is_empty());
+        let audited: Vec<(String, String)> = model
+            .audit_entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.action, e.details))
+            .collect();
+        assert_eq!(
+            vec![
+                ("close".to_string(), "2023-11".to_string()),
+                ("reopen".to_string(), "2023-11".to_string())
+            ],
+            audited
+        );
+    }
+
+    #[test]
+    fn closed_months_refuse_every_change() {
+        let model = closed_december();
+        let saved = model.get_expense_details(1, 1).unwrap();
+        let in_december = expense("2023-12-15T12:00:00Z");
+        let in_january = expense("2024-01-05T12:00:00Z");
+        let source = SourceMessage {
+            chat_id: -100,
+            message_id: 7,
+            username: None,
+        };
+
+        // Logging, one at a time, once or in a batch.
+        assert!(is_closed(model.insert_expense(&in_december)));
+        assert!(is_closed(model.insert_expense_once(
+            1,
+            "add:1",
+            &in_december
+        )));
+        let batch = [
+            ("batch:1".to_string(), in_january.clone()),
+            ("batch:2".to_string(), in_december.clone()),
+        ];
+        assert!(is_closed(model.insert_expenses_once(1, &batch)));
+        assert_eq!(None, model.expense_by_key("batch:1").unwrap());
+        // Editing, back-dating into the month or moving out of it.
+        assert!(is_closed(model.update_expense(1, 1001, 1, &in_december)));
+        assert!(is_closed(model.update_expense(1, 1001, 1, &in_january)));
+        let january_id = model.insert_expense(&in_january).unwrap();
+        assert!(is_closed(model.update_expense(
+            1,
+            1001,
+            january_id,
+            &in_december
+        )));
+        // Deleting, and the details of an expense.
+        assert!(is_closed(model.delete_expense(1, 1)));
+        assert!(is_closed(model.split_expense(1, 1, Some(1002))));
+        assert!(is_closed(model.set_original_amount(
+            1,
+            1,
+            Some((13.5, "USD"))
+        )));
+        assert!(is_closed(model.set_expense_location(
+            1,
+            1,
+            Some((52.5, 13.4))
+        )));
+        assert!(is_closed(model.set_expense_source(1, 1, &source)));
+        // Moving categories over the month.
+        assert!(is_closed(model.bulk_recategorize(
+            1,
+            1001,
+            (1, 2),
+            None,
+            Tz::UTC,
+            true
+        )));
+        assert!(is_closed(model.merge_categories(1, 1001, (1, 2), true)));
+        assert!(is_closed(model.merge_categories(1, 1001, (1, 2), false)));
+        // Deleting an account into another.
+        let spare = model.add_account(1, "Spare", 1).unwrap();
+        assert!(is_closed(model.move_and_delete_account(
+            1,
+            1001,
+            (1, spare)
+        )));
+        assert_eq!(saved, model.get_expense_details(1, 1).unwrap());
+        assert!(model.get_account(1, 1).unwrap().is_some());
+        assert!(model.categories(1).unwrap().iter().any(|c| c.id == 1));
+
+        // Outside of it, and in other households, changes go on.
+        model
+            .update_expense(1, 1001, january_id, &in_january)
+            .unwrap();
+        model.set_expense_source(1, january_id, &source).unwrap();
+        let range = DateRange::parse("2024-01..2024-01");
+        model
+            .bulk_recategorize(1, 1001, (1, 2), range, Tz::UTC, false)
+            .unwrap();
+        model.delete_expense(1, january_id).unwrap();
+        // An empty account or category has nothing in the month.
+        let empty = model.add_account(1, "Empty", 1).unwrap();
+        model
+            .move_and_delete_account(1, 1001, (empty, spare))
+            .unwrap();
+        let garden = model.add_category(1, "Garden", None).unwrap();
+        model.merge_categories(1, 1001, (garden, 2), false).unwrap();
+        // Expense of another household, and one that doesn't exist.
+        assert!(!is_closed(model.delete_expense(2, 1)));
+        assert!(matches!(
+            model.update_expense(1, 1001, 99, &in_january),
+            Err(Error::NotFound(_))
+        ));
+
+        model.reopen_month(1, 1001, december()).unwrap();
+        model.update_expense(1, 1001, 1, &in_december).unwrap();
+        model.delete_expense(1, 1).unwrap();
+    }
+
+    #[test]
+    fn months_are_local() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let minsk: Tz = "Europe/Minsk".parse().unwrap();
+        let january = (at("2024-01-10T12:00:00Z"), minsk);
+        model.close_month(1, 1001, december(), january).unwrap();
+
+        // 30 November in UTC is 1 December in Minsk.
+        assert!(is_closed(
+            model.insert_expense(&expense("2023-11-30T21:00:00Z"))
+        ));
+        assert!(model
+            .insert_expense(&expense("2023-11-30T20:59:00Z"))
+            .is_ok());
+        assert!(model
+            .insert_expense(&expense("2023-12-31T21:00:00Z"))
+            .is_ok());
+        assert_eq!(
+            "December 2023 is closed — ask the admin to reopen.",
+            model
+                .insert_expense(&expense("2023-12-31T20:59:00Z"))
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn ranges_over_closed_months() {
+        let model = closed_december();
+        let november = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let now = (at("2024-01-10T12:00:00Z"), Tz::UTC);
+        let closed = |range| {
+            model
+                .is_closed(1, DateRange::parse(range).unwrap())
+                .unwrap()
+        };
+
+        assert!(closed("2023-12-01..2023-12-31"));
+        assert!(closed("2023-12-05..2023-12-06"));
+        assert!(!closed("2023-11..2023-12"));
+        model.close_month(1, 1001, november, now).unwrap();
+        assert!(closed("2023-11..2023-12"));
+        assert!(!closed("2023-11..2024-01"));
+        assert!(!model
+            .is_closed(2, DateRange::parse("2023-12..2023-12").unwrap())
+            .unwrap());
+    }
+}
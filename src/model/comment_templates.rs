@@ -0,0 +1,204 @@
+//! Comments with blanks, like `fuel {liters}L at {station}`, filled in each
+//! time they are used. Stored with the settings under `template:<name>`.
+
+use super::lengths::{check_length, MAX_COMMENT_LEN, MAX_NAME_LEN};
+use super::{Error, Model, Result};
+use rusqlite::params;
+
+const PREFIX: &str = "template:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommentTemplate {
+    pub name: String,
+    pub text: String,
+    /// Names of the `{placeholders}` in the order they first appear, each
+    /// once.
+    pub placeholders: Vec<String>,
+}
+
+/// The placeholders of `text` in order, each once, or why it isn't a
+/// template: every `{` must be closed by a `}` before the next one, and
+/// placeholders have names.
+fn placeholders(text: &str) -> std::result::Result<Vec<String>, String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut open: Option<(usize, String)> = None;
+    for (position, c) in text.chars().enumerate() {
+        match (c, &mut open) {
+            ('{', None) => open = Some((position + 1, String::new())),
+            ('{', Some((start, _))) => {
+                return Err(format!("The {{ at {} is never closed.", start));
+            }
+            ('}', None) => {
+                return Err(format!("The }} at {} closes nothing.", position + 1));
+            }
+            ('}', Some((start, name))) => {
+                let name = name.trim().to_string();
+                if name.is_empty() {
+                    return Err(format!("The placeholder at {} has no name.", start));
+                }
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+                open = None;
+            }
+            (c, Some((_, name))) => name.push(c),
+            (_, None) => {}
+        }
+    }
+    match open {
+        Some((start, _)) => Err(format!("The {{ at {} is never closed.", start)),
+        None => Ok(names),
+    }
+}
+
+impl CommentTemplate {
+    /// The template `name` stands for, or why `text` isn't one.
+    pub fn parse(name: &str, text: &str) -> std::result::Result<CommentTemplate, String> {
+        Ok(CommentTemplate {
+            name: name.to_string(),
+            text: text.to_string(),
+            placeholders: placeholders(text)?,
+        })
+    }
+
+    /// The comment with each placeholder replaced by its value, `values`
+    /// being in the order of `placeholders`.
+    pub fn fill(&self, values: &[String]) -> String {
+        let mut comment = String::new();
+        let mut name: Option<String> = None;
+        for c in self.text.chars() {
+            match (c, &mut name) {
+                ('{', None) => name = Some(String::new()),
+                ('}', Some(n)) => {
+                    let n = n.trim();
+                    let index = self.placeholders.iter().position(|p| p == n);
+                    if let Some(value) = index.and_then(|i| values.get(i)) {
+                        comment.push_str(value);
+                    }
+                    name = None;
+                }
+                (c, Some(n)) => n.push(c),
+                (c, None) => comment.push(c),
+            }
+        }
+        comment
+    }
+}
+
+impl Model {
+    /// The user's comment templates, by name. Stored ones that stopped being
+    /// templates are skipped.
+    pub fn comment_templates(&self, user_id: i64) -> Result<Vec<CommentTemplate>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT substr(key, ?2), value FROM UserSetting
+             WHERE userId = ?1 AND key LIKE 'template:%'
+             ORDER BY key",
+        )?;
+        let rows = stmt
+            .query_map(params![user_id, PREFIX.len() + 1], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(name, text)| CommentTemplate::parse(&name, &text).ok())
+            .collect())
+    }
+
+    /// Adds the template or replaces its text, refused if `text` isn't one.
+    pub fn set_comment_template(&self, user_id: i64, name: &str, text: &str) -> Result<()> {
+        self.check_writable()?;
+        check_length("Template name", name, MAX_NAME_LEN)?;
+        check_length("Template", text, MAX_COMMENT_LEN)?;
+        CommentTemplate::parse(name, text).map_err(Error::Refused)?;
+        self.connection.execute(
+            "INSERT INTO UserSetting (userId, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (userId, key) DO UPDATE SET value = excluded.value",
+            params![user_id, format!("{}{}", PREFIX, name), text],
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether there was such a template.
+    pub fn delete_comment_template(&self, user_id: i64, name: &str) -> Result<bool> {
+        self.check_writable()?;
+        let deleted = self.connection.execute(
+            "DELETE FROM UserSetting WHERE userId = ?1 AND key = ?2",
+            params![user_id, format!("{}{}", PREFIX, name)],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Settings;
+
+    #[test]
+    fn placeholders_in_order() {
+        let strings = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Ok(strings(&["liters", "station"])),
+            placeholders("fuel {liters}L at {station}")
+        );
+        assert_eq!(Ok(strings(&["a", "b"])), placeholders("{a} {b} { a }"));
+        assert_eq!(Ok(Vec::new()), placeholders("plain"));
+        assert_eq!(Ok(strings(&["ёмкость"])), placeholders("{ёмкость}"));
+
+        assert_eq!(
+            Err("The { at 6 is never closed.".to_string()),
+            placeholders("fuel {liters L")
+        );
+        assert_eq!(
+            Err("The { at 1 is never closed.".to_string()),
+            placeholders("{a {b}")
+        );
+        assert_eq!(
+            Err("The } at 7 closes nothing.".to_string()),
+            placeholders("liters}")
+        );
+        assert_eq!(
+            Err("The placeholder at 6 has no name.".to_string()),
+            placeholders("fuel { }")
+        );
+    }
+
+    #[test]
+    fn fills_the_blanks() {
+        let template = CommentTemplate::parse("fuel", "fuel {liters}L at { station }").unwrap();
+        assert_eq!(
+            "fuel 40L at Shell",
+            template.fill(&["40".to_string(), "Shell".to_string()])
+        );
+        let twice = CommentTemplate::parse("x", "{a}-{a}").unwrap();
+        assert_eq!("1-1", twice.fill(&["1".to_string()]));
+    }
+
+    #[test]
+    fn templates_are_per_user_and_apart_from_settings() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .set_comment_template(1001, "fuel", "fuel {liters}L at {station}")
+            .unwrap();
+        model.set_comment_template(1001, "bus", "bus").unwrap();
+        assert!(matches!(
+            model.set_comment_template(1001, "bad", "fuel {liters"),
+            Err(Error::Refused(_))
+        ));
+        model.set_alias(1001, "rl", "report lastmonth").unwrap();
+
+        let templates = model.comment_templates(1001).unwrap();
+        let names: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(vec!["bus", "fuel"], names);
+        assert_eq!(vec!["liters", "station"], templates[1].placeholders);
+        assert!(model.comment_templates(1002).unwrap().is_empty());
+        assert_eq!(Settings::default(), model.get_settings(1001).unwrap());
+        assert_eq!(1, model.aliases(1001).unwrap().len());
+
+        assert!(model.delete_comment_template(1001, "bus").unwrap());
+        assert!(!model.delete_comment_template(1001, "bus").unwrap());
+        assert_eq!(1, model.comment_templates(1001).unwrap().len());
+    }
+}
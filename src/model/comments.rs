@@ -0,0 +1,173 @@
+//! Comments a user keeps typing, suggested when they edit the comment of a
+//! draft.
+
+use super::{Model, Result};
+use std::collections::HashMap;
+
+/// How many of the user's latest commented expenses suggestions come from,
+/// so that what they stopped writing drops out.
+const RECENT_COMMENTS: usize = 200;
+
+#[derive(Debug)]
+struct Tally {
+    /// The latest spelling, as typed.
+    comment: String,
+    count: usize,
+    in_category: usize,
+    /// Order of the latest use, 0 the most recent.
+    latest: usize,
+}
+
+/// Ranks `comments`, latest first with their category, by how often they
+/// were used in `category` and then at all. Comments differing in case or
+/// surrounding spaces only are one, used once they aren't suggested.
+fn rank(comments: &[(String, i64)], category: Option<i64>, limit: usize) -> Vec<String> {
+    let mut tallies: HashMap<String, Tally> = HashMap::new();
+    for (latest, (comment, category_id)) in comments.iter().enumerate() {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            continue;
+        }
+        let tally = tallies.entry(comment.to_lowercase()).or_insert(Tally {
+            comment: comment.to_string(),
+            count: 0,
+            in_category: 0,
+            latest,
+        });
+        tally.count += 1;
+        if category == Some(*category_id) {
+            tally.in_category += 1;
+        }
+    }
+    let mut tallies: Vec<Tally> = tallies.into_values().filter(|t| t.count > 1).collect();
+    tallies.sort_by_key(|t| {
+        (
+            std::cmp::Reverse(t.in_category),
+            std::cmp::Reverse(t.count),
+            t.latest,
+        )
+    });
+    tallies.into_iter().take(limit).map(|t| t.comment).collect()
+}
+
+impl Model {
+    /// The `limit` comments the user wrote most often lately, preferring
+    /// those in `category_id`, in their latest spelling.
+    pub fn frequent_comments(
+        &self,
+        user_id: i64,
+        category_id: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT comments, categoryId FROM Expense
+             WHERE userId = ?1 AND TRIM(COALESCE(comments, '')) != ''
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?2",
+        )?;
+        let comments = stmt
+            .query_map([user_id, RECENT_COMMENTS as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+        Ok(rank(&comments, category_id, limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    fn comments(list: &[(&str, i64)]) -> Vec<(String, i64)> {
+        list.iter().map(|(c, id)| (c.to_string(), *id)).collect()
+    }
+
+    #[test]
+    fn ranking() {
+        let latest_first = comments(&[
+            ("Fuel", 2),
+            ("weekly shop", 1),
+            ("  Weekly Shop ", 1),
+            ("fuel", 2),
+            ("lunch", 3),
+            ("school lunch", 1),
+            ("WEEKLY SHOP", 1),
+            ("School lunch", 1),
+            ("lunch", 3),
+            ("fuel", 1),
+            ("birthday cake", 1),
+        ]);
+        // Case and spaces aside, used more than once, in the latest spelling.
+        assert_eq!(
+            vec!["Fuel", "weekly shop", "lunch", "school lunch"],
+            rank(&latest_first, None, 5)
+        );
+        // Those of the category first, even if used less overall.
+        assert_eq!(
+            vec!["lunch", "Fuel", "weekly shop", "school lunch"],
+            rank(&latest_first, Some(3), 5)
+        );
+        assert_eq!(
+            vec!["weekly shop", "school lunch", "Fuel"],
+            rank(&latest_first, Some(1), 3)
+        );
+        assert!(rank(&latest_first, None, 0).is_empty());
+    }
+
+    #[test]
+    fn ties_go_to_the_latest() {
+        let latest_first = comments(&[("b", 1), ("a", 2), ("B", 1), ("A", 2), ("c", 1)]);
+        assert_eq!(vec!["b", "a"], rank(&latest_first, None, 5));
+        // Unless the category tells them apart.
+        assert_eq!(vec!["a", "b"], rank(&latest_first, Some(2), 5));
+        assert_eq!(vec!["b", "a"], rank(&latest_first, Some(3), 5));
+    }
+
+    #[test]
+    fn frequent_comments_of_the_user() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let add = |user_id, category_id, minutes, comment: Option<&str>| {
+            model
+                .insert_expense(&NewExpense {
+                    account_id: 1,
+                    category_id,
+                    user_id,
+                    timestamp: start + TimeDelta::minutes(minutes),
+                    amount: 5.0,
+                    comment: comment.map(str::to_string),
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        };
+        // The test data has one-offs only.
+        assert!(model.frequent_comments(1001, None, 5).unwrap().is_empty());
+        add(1001, 2, 1, Some("Fuel"));
+        add(1001, 2, 2, Some("fuel "));
+        add(1001, 1, 3, Some("Bread"));
+        add(1001, 1, 4, Some("bread"));
+        add(1001, 1, 5, None);
+        add(1001, 1, 6, Some("  "));
+        // Another user's comments aren't suggested.
+        add(1002, 2, 7, Some("Bread"));
+        add(1002, 2, 8, Some("Parking"));
+        add(1002, 2, 9, Some("Parking"));
+
+        assert_eq!(
+            vec!["bread", "fuel"],
+            model.frequent_comments(1001, None, 5).unwrap()
+        );
+        assert_eq!(
+            vec!["fuel"],
+            model.frequent_comments(1001, Some(2), 1).unwrap()
+        );
+        assert_eq!(
+            vec!["Parking"],
+            model.frequent_comments(1002, None, 5).unwrap()
+        );
+    }
+}
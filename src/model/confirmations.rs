@@ -0,0 +1,93 @@
+//! Which bot message confirmed which expense, so that reacting to the
+//! confirmation can find the expense again.
+
+use super::{ExpenseDetails, Model, Result};
+use rusqlite::OptionalExtension;
+
+impl Model {
+    /// Remembers that the message confirms the expense. A message confirms
+    /// one expense, the last recorded.
+    pub fn record_confirmation(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        expense_id: i64,
+    ) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO Confirmation (chatId, messageId, expenseId) VALUES (?1, ?2, ?3)",
+            rusqlite::params![chat_id, message_id, expense_id],
+        )?;
+        Ok(())
+    }
+
+    /// The expense of the household the message confirms, `None` for other
+    /// messages and for expenses deleted or archived since.
+    pub fn confirmed_expense(
+        &self,
+        household: i64,
+        chat_id: i64,
+        message_id: i32,
+    ) -> Result<Option<ExpenseDetails>> {
+        let id: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT expenseId FROM Confirmation WHERE chatId = ?1 AND messageId = ?2",
+                rusqlite::params![chat_id, message_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match id {
+            Some(id) => self.get_expense_details(household, id),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn confirmed(model: &Model, household: i64, message_id: i32) -> Option<i64> {
+        model
+            .confirmed_expense(household, -100, message_id)
+            .unwrap()
+            .map(|e| e.id)
+    }
+
+    #[test]
+    fn messages_map_to_their_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        model.record_confirmation(-100, 7, 2).unwrap();
+        model.record_confirmation(-100, 8, 4).unwrap();
+        assert_eq!(Some(2), confirmed(&model, 1, 7));
+        assert_eq!(Some(4), confirmed(&model, 1, 8));
+        assert_eq!(None, confirmed(&model, 1, 9));
+        // The same message id in another chat is another message.
+        assert_eq!(None, model.confirmed_expense(1, -200, 7).unwrap());
+        // Other households don't see the expense.
+        assert_eq!(None, confirmed(&model, 2, 7));
+
+        // Recorded again, e.g. after an undo and a new commit.
+        model.record_confirmation(-100, 7, 1).unwrap();
+        assert_eq!(Some(1), confirmed(&model, 1, 7));
+    }
+
+    #[test]
+    fn deleted_expenses_are_forgotten() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.record_confirmation(-100, 7, 2).unwrap();
+        model
+            .connection
+            .execute("DELETE FROM Expense WHERE id = 2", [])
+            .unwrap();
+        assert_eq!(None, confirmed(&model, 1, 7));
+        let rows: i64 = model
+            .connection
+            .query_row("SELECT COUNT(*) FROM Confirmation", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(0, rows);
+    }
+}
@@ -0,0 +1,156 @@
+//! Opening SQLite connections. Foreign keys are a setting of each
+//! connection, off unless asked for, so every connection the bot makes is
+//! opened here to have them enforced. Files opened for writing are put in
+//! WAL mode, so that reading doesn't wait for writing and a commit only
+//! appends to the log.
+
+use super::Result;
+use log::*;
+use rusqlite::{Connection, OpenFlags};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a statement waits for a lock held by another connection, like
+/// that of a `sqlite3` shell, before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a connection opens.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Storage<'a> {
+    /// A database of its own, gone when the connection closes.
+    #[cfg(test)]
+    InMemory,
+    /// The file, created if missing, in WAL mode.
+    File(&'a Path),
+    /// The file, not even a journal written.
+    ReadOnly(&'a Path),
+}
+
+/// A connection to `storage`, enforcing foreign keys.
+pub(super) fn open_connection(storage: Storage) -> Result<Connection> {
+    let connection = match storage {
+        #[cfg(test)]
+        Storage::InMemory => Connection::open_in_memory()?,
+        Storage::File(path) => {
+            let connection = Connection::open(path)?;
+            // The mode is kept in the file, readers opening it later use the
+            // log as well.
+            let mode: String =
+                connection
+                    .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+            if mode != "wal" {
+                warn!("{} stays in {} journal mode", path.display(), mode);
+            }
+            connection
+        }
+        Storage::ReadOnly(path) => {
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?
+        }
+    };
+    connection.pragma_update(None, "foreign_keys", "ON")?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(connection)
+}
+
+/// Panics in debug builds if `connection` doesn't enforce foreign keys,
+/// whoever opened it. Release builds don't check.
+pub(super) fn assert_fk_enabled(connection: &Connection) {
+    if cfg!(debug_assertions) {
+        let enabled: bool = connection
+            .pragma_query_value(None, "foreign_keys", |row| row.get(0))
+            .unwrap_or(false);
+        assert!(enabled, "foreign keys aren't enforced on this connection");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::restore::tests::TempDir;
+    use crate::model::{archive_file, Model};
+    use chrono::NaiveDate;
+    use chrono_tz::Tz;
+
+    /// An expense on an account that doesn't exist.
+    const ORPHAN: &str = "INSERT INTO Expense
+         (timestamp, createdAt, amountMinor, accountId, categoryId, userId)
+         VALUES (0, 0, 100, 99, 1, 1001)";
+
+    #[test]
+    fn connections_enforce_foreign_keys() {
+        let dir = TempDir::new("connection-fk");
+        let path = dir.0.join("fk.db");
+        Model::open(&path).fill_test_data();
+
+        for storage in [Storage::File(&path), Storage::ReadOnly(&path)] {
+            let connection = open_connection(storage).unwrap();
+            assert_fk_enabled(&connection);
+        }
+        let connection = open_connection(Storage::File(&path)).unwrap();
+        assert!(connection.execute(ORPHAN, []).is_err());
+        assert_eq!(
+            4,
+            connection
+                .query_row("SELECT COUNT(*) FROM Expense", [], |row| row
+                    .get::<_, i64>(0))
+                .unwrap()
+        );
+    }
+
+    fn journal_mode(connection: &Connection) -> String {
+        connection
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn only_files_opened_for_writing_turn_wal_on() {
+        let dir = TempDir::new("connection-wal");
+        let path = dir.0.join("wal.db");
+        let plain = dir.0.join("plain.db");
+        Connection::open(&plain)
+            .unwrap()
+            .execute_batch("CREATE TABLE T (x)")
+            .unwrap();
+
+        let connection = open_connection(Storage::File(&path)).unwrap();
+        assert_eq!("wal", journal_mode(&connection));
+        let read_only = open_connection(Storage::ReadOnly(&plain)).unwrap();
+        assert_eq!("delete", journal_mode(&read_only));
+        let in_memory = open_connection(Storage::InMemory).unwrap();
+        assert_eq!("memory", journal_mode(&in_memory));
+        // Readers see the mode the file was left in.
+        drop(connection);
+        let read_only = open_connection(Storage::ReadOnly(&path)).unwrap();
+        assert_eq!("wal", journal_mode(&read_only));
+    }
+
+    #[test]
+    #[should_panic(expected = "foreign keys aren't enforced")]
+    fn connections_without_foreign_keys_are_caught() {
+        let connection = open_connection(Storage::InMemory).unwrap();
+        connection
+            .pragma_update(None, "foreign_keys", "OFF")
+            .unwrap();
+        assert_fk_enabled(&connection);
+    }
+
+    #[test]
+    fn archives_keep_foreign_keys_enforced() {
+        let dir = TempDir::new("connection-archive");
+        let db = dir.0.join("live.db");
+        let model = Model::open(&db);
+        model.fill_test_data();
+        let cutoff = NaiveDate::from_ymd_opt(2023, 12, 3).unwrap();
+        model
+            .archive_before(1001, cutoff, &archive_file(&db, cutoff), Tz::UTC)
+            .unwrap();
+        // Reaching back before the cutoff attaches the archive.
+        model
+            .expense_tables(Some(chrono::DateTime::UNIX_EPOCH))
+            .unwrap();
+
+        assert_fk_enabled(&model.connection);
+        assert!(model.connection.execute(ORPHAN, []).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Long operations on the database file, like vacuuming or archiving to an
+//! attached database, taking turns with the queries of handlers. Queries
+//! share the database, a long operation has it to itself: it waits for the
+//! queries in flight, and queries coming in meanwhile wait for it, or give
+//! up after [`QUERY_WAIT`] for the user to try again. Only one long
+//! operation runs or waits at a time, another is refused rather than
+//! stacked behind it.
+
+use super::{Error, Result};
+use log::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+/// How long a query waits for a long operation before giving up.
+pub const QUERY_WAIT: Duration = Duration::from_secs(3);
+
+/// Hands out the permits. Waiting queries queue behind a waiting long
+/// operation, so that a steady stream of them can't starve it.
+pub struct Coordinator {
+    lock: Arc<RwLock<()>>,
+    /// The long operation running or waiting to, like `Maintenance`.
+    running: Arc<Mutex<Option<&'static str>>>,
+    query_wait: Duration,
+}
+
+impl Default for Coordinator {
+    fn default() -> Self {
+        Coordinator::new(QUERY_WAIT)
+    }
+}
+
+/// Held by a query for as long as it uses the database.
+pub struct SharedPermit {
+    _guard: OwnedRwLockReadGuard<()>,
+}
+
+/// Held by a long operation, the database is its own until dropped.
+pub struct ExclusivePermit {
+    _guard: OwnedRwLockWriteGuard<()>,
+    running: Running,
+    acquired: Instant,
+}
+
+/// Marks a long operation as running or waiting, until dropped, also when
+/// the wait is given up.
+struct Running {
+    running: Arc<Mutex<Option<&'static str>>>,
+    operation: &'static str,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().take();
+    }
+}
+
+impl Drop for ExclusivePermit {
+    fn drop(&mut self) {
+        info!(
+            "{} had the database for {:?}",
+            self.running.operation,
+            self.acquired.elapsed()
+        );
+    }
+}
+
+impl Coordinator {
+    pub fn new(query_wait: Duration) -> Self {
+        Coordinator {
+            lock: Arc::default(),
+            running: Arc::default(),
+            query_wait,
+        }
+    }
+
+    /// A permit to query, waiting for a long operation up to the query wait.
+    /// Gives up with [`Error::Busy`] naming the operation.
+    pub async fn shared(&self) -> Result<SharedPermit> {
+        match tokio::time::timeout(self.query_wait, self.lock.clone().read_owned()).await {
+            Ok(guard) => Ok(SharedPermit { _guard: guard }),
+            Err(_) => {
+                let operation = self.running.lock().unwrap().unwrap_or("Maintenance");
+                Err(Error::Busy(operation.to_string()))
+            }
+        }
+    }
+
+    /// A permit for `operation`, once the queries in flight are done.
+    /// Refused with [`Error::Busy`] while another long operation runs or
+    /// waits.
+    pub async fn exclusive(&self, operation: &'static str) -> Result<ExclusivePermit> {
+        let running = {
+            let mut running = self.running.lock().unwrap();
+            if let Some(other) = *running {
+                return Err(Error::Busy(other.to_string()));
+            }
+            *running = Some(operation);
+            Running {
+                running: self.running.clone(),
+                operation,
+            }
+        };
+        let started = Instant::now();
+        let guard = self.lock.clone().write_owned().await;
+        info!(
+            "{} waited {:?} for the queries in flight",
+            operation,
+            started.elapsed()
+        );
+        Ok(ExclusivePermit {
+            _guard: guard,
+            running,
+            acquired: Instant::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn coordinator() -> Arc<Coordinator> {
+        Arc::new(Coordinator::new(Duration::from_millis(50)))
+    }
+
+    fn busy(result: Result<impl Sized>) -> String {
+        match result {
+            Err(Error::Busy(operation)) => operation,
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("not busy"),
+        }
+    }
+
+    #[tokio::test]
+    async fn long_operations_wait_for_queries_in_flight() {
+        let coordinator = coordinator();
+        let query = coordinator.shared().await.unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let operation = tokio::spawn({
+            let (coordinator, done) = (coordinator.clone(), done.clone());
+            async move {
+                let _permit = coordinator.exclusive("Archiving").await.unwrap();
+                done.store(true, Ordering::SeqCst);
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!done.load(Ordering::SeqCst));
+        // Queries that come in meanwhile wait behind it, and give up.
+        assert_eq!("Archiving", busy(coordinator.shared().await));
+
+        drop(query);
+        operation.await.unwrap();
+        assert!(done.load(Ordering::SeqCst));
+        assert!(coordinator.shared().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn queries_give_up_on_a_running_operation() {
+        let coordinator = coordinator();
+        let permit = coordinator.exclusive("Maintenance").await.unwrap();
+        let started = Instant::now();
+        assert_eq!("Maintenance", busy(coordinator.shared().await));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // Queries waiting less than the wait get their turn.
+        let query = tokio::spawn({
+            let coordinator = coordinator.clone();
+            async move { coordinator.shared().await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(permit);
+        assert!(query.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn long_operations_do_not_stack() {
+        let coordinator = coordinator();
+        let query = coordinator.shared().await.unwrap();
+        // Waiting for the query counts as running.
+        let waiting = tokio::spawn({
+            let coordinator = coordinator.clone();
+            async move { coordinator.exclusive("Maintenance").await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            "Maintenance",
+            busy(coordinator.exclusive("Archiving").await)
+        );
+
+        // Giving up the wait lets the next one in.
+        waiting.abort();
+        assert!(waiting.await.unwrap_err().is_cancelled());
+        drop(query);
+        let permit = coordinator.exclusive("Archiving").await.unwrap();
+        assert_eq!(
+            "Archiving",
+            busy(coordinator.exclusive("Maintenance").await)
+        );
+        drop(permit);
+        assert!(coordinator.exclusive("Maintenance").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn queries_share_the_database() {
+        let coordinator = coordinator();
+        let first = coordinator.shared().await.unwrap();
+        let second = coordinator.shared().await.unwrap();
+        drop((first, second));
+        assert!(coordinator.exclusive("Maintenance").await.is_ok());
+    }
+}
@@ -0,0 +1,197 @@
+use super::{Error, Model, Result};
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// Largest exponent the schema accepts.
+pub const MAX_EXPONENT: u32 = 6;
+
+impl Model {
+    /// The id of the currency `name`, adding it with two decimal places if
+    /// it is new.
+    pub fn add_currency(&self, name: &str) -> Result<i64> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT INTO Currency (name)
+             SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM Currency WHERE name = ?1 COLLATE NOCASE)",
+            [name],
+        )?;
+        let id = self.connection.query_row(
+            "SELECT id FROM Currency WHERE name = ?1 COLLATE NOCASE",
+            [name],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    /// Decimal places of a currency, `None` for an unknown currency.
+    pub fn currency_exponent(&self, currency: &str) -> Result<Option<u32>> {
+        let exponent = self
+            .connection
+            .query_row(
+                "SELECT exponent FROM Currency WHERE name = ?1 COLLATE NOCASE",
+                [currency],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(exponent)
+    }
+
+    /// Changes the decimal places of a currency. Stored amounts are minor
+    /// units of the old exponent, so this is refused once the currency has
+    /// expenses.
+    pub fn set_currency_exponent(&self, currency: &str, exponent: u32) -> Result<()> {
+        self.check_writable()?;
+        if exponent > MAX_EXPONENT {
+            return Err(Error::Refused(format!(
+                "An exponent can be at most {}.",
+                MAX_EXPONENT
+            )));
+        }
+        let Some(current) = self.currency_exponent(currency)? else {
+            return Err(Error::NotFound(format!("currency {}", currency)));
+        };
+        if current == exponent {
+            return Ok(());
+        }
+
+        let expenses: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM Expense e
+             JOIN Account a ON a.id = e.accountId
+             JOIN Currency c ON c.id = a.currencyId
+             WHERE c.name = ?1 COLLATE NOCASE",
+            [currency],
+            |row| row.get(0),
+        )?;
+        if expenses > 0 {
+            return Err(Error::Refused(format!(
+                "{} already has expenses stored with {} decimal places.",
+                currency, current
+            )));
+        }
+
+        info!("Setting exponent of {} to {}", currency, exponent);
+        self.connection.execute(
+            "UPDATE Currency SET exponent = ?2 WHERE name = ?1 COLLATE NOCASE",
+            params![currency, exponent],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{AmountError, DateRange, ExpenseSource, NewExpense};
+    use chrono::{DateTime, NaiveDate};
+    use chrono_tz::Tz;
+
+    /// Adds a currency with its own account and returns the account id.
+    fn add_currency(model: &Model, name: &str) -> i64 {
+        model
+            .connection
+            .execute_batch(&format!(
+                "INSERT INTO Currency (name) VALUES ('{0}');
+                 INSERT INTO Account (name, currencyId, householdId)
+                     SELECT '{0} cash', id, 1 FROM Currency WHERE name = '{0}';",
+                name
+            ))
+            .unwrap();
+        model.connection.last_insert_rowid()
+    }
+
+    fn expense(account_id: i64, amount: f64) -> NewExpense {
+        NewExpense {
+            account_id,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+            amount,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        }
+    }
+
+    fn december() -> DateRange {
+        DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        )
+    }
+
+    #[test]
+    fn zero_exponent_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let account = add_currency(&model, "JPY");
+        model.set_currency_exponent("JPY", 0).unwrap();
+
+        assert!(matches!(
+            model.insert_expense(&expense(account, 1500.5)),
+            Err(Error::InvalidAmount(AmountError::TooPrecise(0)))
+        ));
+        let id = model.insert_expense(&expense(account, 1500.0)).unwrap();
+
+        let minor: i64 = model
+            .connection
+            .query_row("SELECT amountMinor FROM Expense WHERE id = ?1", [id], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(1500, minor);
+        let details = model.get_expense_details(1, id).unwrap().unwrap();
+        assert_eq!((1500.0, 0), (details.amount, details.exponent));
+
+        let summary = model.summarize(1, december(), Tz::UTC).unwrap();
+        let jpy = summary.categories.iter().find(|c| c.currency == "JPY");
+        assert_eq!(Some((1500.0, 0)), jpy.map(|c| (c.total, c.exponent)));
+    }
+
+    #[test]
+    fn three_exponent_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let account = add_currency(&model, "KWD");
+        model.set_currency_exponent("KWD", 3).unwrap();
+
+        model.insert_expense(&expense(account, 1.234)).unwrap();
+        model.insert_expense(&expense(account, 0.001)).unwrap();
+        assert!(matches!(
+            model.insert_expense(&expense(account, 0.0001)),
+            Err(Error::InvalidAmount(AmountError::TooPrecise(3)))
+        ));
+
+        let summary = model.summarize(1, december(), Tz::UTC).unwrap();
+        let kwd = summary.categories.iter().find(|c| c.currency == "KWD");
+        assert_eq!(Some((1.235, 3)), kwd.map(|c| (c.total, c.exponent)));
+
+        let balances = model.balances(1, "KWD").unwrap();
+        assert_eq!(3, balances.base_exponent);
+        let kwd = balances.accounts.iter().find(|a| a.currency == "KWD");
+        assert_eq!(Some((-1.235, 3)), kwd.map(|a| (a.balance, a.exponent)));
+    }
+
+    #[test]
+    fn exponent_change_is_refused_once_expenses_exist() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert!(matches!(
+            model.set_currency_exponent("BYN", 3),
+            Err(Error::Refused(_))
+        ));
+        assert_eq!(Some(2), model.currency_exponent("BYN").unwrap());
+        // Setting the current value is fine.
+        model.set_currency_exponent("byn", 2).unwrap();
+
+        assert!(matches!(
+            model.set_currency_exponent("XYZ", 2),
+            Err(Error::NotFound(_))
+        ));
+        add_currency(&model, "JPY");
+        assert!(matches!(
+            model.set_currency_exponent("JPY", 7),
+            Err(Error::Refused(_))
+        ));
+    }
+}
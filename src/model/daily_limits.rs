@@ -0,0 +1,250 @@
+//! Daily spending limits of accounts, like the cap of a prepaid card: what
+//! was spent on an account over a local calendar day, against its limit.
+
+use super::amount::{from_minor, to_minor};
+use super::{DateRange, Error, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// An account's spend over a day, in its currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyUsage {
+    pub account_id: i64,
+    pub date: NaiveDate,
+    pub exponent: u32,
+    pub limit: f64,
+    pub spent: f64,
+}
+
+impl DailyUsage {
+    /// The day's total with `amount` spent on top.
+    pub fn with(&self, amount: f64) -> DailyUsage {
+        DailyUsage {
+            spent: self.spent + amount,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the day's total is over the limit. Reaching it exactly isn't.
+    pub fn is_over(&self) -> bool {
+        to_minor(self.spent, self.exponent) > to_minor(self.limit, self.exponent)
+    }
+}
+
+impl Model {
+    /// Sets the daily limit of the household's account, or with `None`
+    /// removes it.
+    pub fn set_daily_limit(
+        &self,
+        household: i64,
+        account_id: i64,
+        limit: Option<f64>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let Some(account) = self.get_account(household, account_id)? else {
+            return Err(Error::NotFound(format!("account {}", account_id)));
+        };
+        let limit = match limit {
+            Some(limit) => Some(to_minor(
+                self.amount_limits
+                    .for_exponent(account.exponent)
+                    .validate(limit)?,
+                account.exponent,
+            )),
+            None => None,
+        };
+        self.connection.execute(
+            "UPDATE Account SET dailyLimitMinor = ?2 WHERE id = ?1",
+            params![account_id, limit],
+        )?;
+        info!(
+            "Set the daily limit of account {} to {:?}",
+            account_id, limit
+        );
+        Ok(())
+    }
+
+    /// What was spent on the account over the local day of `at`, `None` if
+    /// it has no limit. Leaving out `excluding`, an expense being edited,
+    /// gives the day as it would be without it.
+    pub fn daily_usage(
+        &self,
+        household: i64,
+        account_id: i64,
+        (at, tz): (DateTime<Utc>, Tz),
+        excluding: Option<i64>,
+    ) -> Result<Option<DailyUsage>> {
+        let date = at.with_timezone(&tz).date_naive();
+        let (start, end) = DateRange::inclusive(date, date).to_utc(tz);
+        let tables = self.expense_tables(Some(start))?;
+        self.connection
+            .query_row(
+                &tables.apply(
+                    "SELECT c.exponent, a.dailyLimitMinor,
+                            COALESCE((SELECT SUM(e.amountMinor) FROM {expenses} e
+                                      WHERE e.accountId = a.id
+                                        AND e.timestamp >= ?3 AND e.timestamp < ?4
+                                        AND e.kind = 'expense'
+                                        AND e.id IS NOT ?5), 0)
+                     FROM Account a
+                     JOIN Currency c ON c.id = a.currencyId
+                     WHERE a.id = ?1 AND a.householdId = ?2 AND a.dailyLimitMinor IS NOT NULL",
+                ),
+                params![account_id, household, DbTime(start), DbTime(end), excluding],
+                |row| {
+                    let exponent = row.get(0)?;
+                    Ok(DailyUsage {
+                        account_id,
+                        date,
+                        exponent,
+                        limit: from_minor(row.get(1)?, exponent),
+                        spent: from_minor(row.get(2)?, exponent),
+                    })
+                },
+            )
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// The day of `now` of each of the household's limited accounts.
+    pub fn daily_usages(
+        &self,
+        household: i64,
+        (now, tz): (DateTime<Utc>, Tz),
+    ) -> Result<Vec<DailyUsage>> {
+        let ids = self
+            .connection
+            .prepare(
+                "SELECT id FROM Account
+                 WHERE householdId = ?1 AND dailyLimitMinor IS NOT NULL ORDER BY id",
+            )?
+            .query_map([household], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        let mut usages = Vec::new();
+        for id in ids {
+            usages.extend(self.daily_usage(household, id, (now, tz), None)?);
+        }
+        Ok(usages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn spend(model: &Model, amount: f64, timestamp: &str) -> i64 {
+        model
+            .insert_expense(&NewExpense {
+                amount,
+                account_id: 1,
+                category_id: 1,
+                user_id: 1001,
+                timestamp: at(timestamp),
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn limits_are_set_and_removed() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let day = (at("2023-12-01T12:00:00Z"), Tz::UTC);
+        assert_eq!(None, model.daily_usage(1, 1, day, None).unwrap());
+
+        model.set_daily_limit(1, 1, Some(60.0)).unwrap();
+        let usage = model.daily_usage(1, 1, day, None).unwrap().unwrap();
+        // The 50.75 EUR of groceries on 1 December.
+        assert_eq!((60.0, 50.75), (usage.limit, usage.spent));
+        assert_eq!(vec![usage], model.daily_usages(1, day).unwrap());
+
+        model.set_daily_limit(1, 1, None).unwrap();
+        assert!(model.daily_usages(1, day).unwrap().is_empty());
+        assert!(matches!(
+            model.set_daily_limit(1, 1, Some(-5.0)),
+            Err(Error::InvalidAmount(_))
+        ));
+        // Another household's account.
+        assert!(matches!(
+            model.set_daily_limit(2, 1, Some(5.0)),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn days_are_local() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_daily_limit(1, 1, Some(20.0)).unwrap();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        // Late on 9 December in Berlin.
+        spend(&model, 5.0, "2023-12-09T22:30:00Z");
+        // Early on the 10th in Berlin, still the 9th in UTC.
+        spend(&model, 7.0, "2023-12-09T23:10:00Z");
+        // The last minute of the 10th in Berlin, and its first of the 11th.
+        spend(&model, 3.0, "2023-12-10T22:59:00Z");
+        spend(&model, 11.0, "2023-12-10T23:00:00Z");
+
+        let spent = |at_: &str, tz| {
+            model
+                .daily_usage(1, 1, (at(at_), tz), None)
+                .unwrap()
+                .unwrap()
+                .spent
+        };
+        assert_eq!(5.0, spent("2023-12-09T12:00:00Z", berlin));
+        assert_eq!(10.0, spent("2023-12-10T12:00:00Z", berlin));
+        assert_eq!(11.0, spent("2023-12-11T08:00:00Z", berlin));
+        assert_eq!(12.0, spent("2023-12-09T12:00:00Z", Tz::UTC));
+        assert_eq!(14.0, spent("2023-12-10T12:00:00Z", Tz::UTC));
+        let date = model
+            .daily_usage(1, 1, (at("2023-12-10T23:30:00Z"), berlin), None)
+            .unwrap()
+            .unwrap()
+            .date;
+        assert_eq!(NaiveDate::from_ymd_opt(2023, 12, 11).unwrap(), date);
+    }
+
+    #[test]
+    fn edited_expenses_count_once() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_daily_limit(1, 1, Some(20.0)).unwrap();
+        let id = spend(&model, 15.0, "2023-12-10T10:00:00Z");
+        let day = (at("2023-12-10T12:00:00Z"), Tz::UTC);
+        let usage = model.daily_usage(1, 1, day, Some(id)).unwrap().unwrap();
+        assert_eq!(0.0, usage.spent);
+        assert_eq!(
+            15.0,
+            model.daily_usage(1, 1, day, None).unwrap().unwrap().spent
+        );
+    }
+
+    #[test]
+    fn crossing_the_limit() {
+        let day = DailyUsage {
+            account_id: 1,
+            date: NaiveDate::from_ymd_opt(2023, 12, 10).unwrap(),
+            exponent: 2,
+            limit: 20.0,
+            spent: 14.5,
+        };
+        assert!(!day.is_over());
+        assert!(!day.with(5.5).is_over());
+        assert!(day.with(5.51).is_over());
+        // Float sums that only look over.
+        assert!(!day.with(0.1).with(0.2).with(5.2).is_over());
+        // Once over, every further expense stays over.
+        assert!(day.with(10.0).with(1.0).is_over());
+    }
+}
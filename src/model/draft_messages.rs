@@ -0,0 +1,88 @@
+//! The messages showing open drafts, or batches of them. Drafts live in
+//! memory, so after a restart these messages are all that is left of them.
+
+use super::{Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+
+impl Model {
+    /// Remembers that the message shows a draft opened at `now`.
+    pub fn record_draft_message(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO DraftMessage (chatId, messageId, openedAt) VALUES (?1, ?2, ?3)",
+            params![chat_id, message_id, DbTime(now)],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the message once its draft is committed or dropped.
+    pub fn forget_draft_message(&self, chat_id: i64, message_id: i32) -> Result<()> {
+        self.connection.execute(
+            "DELETE FROM DraftMessage WHERE chatId = ?1 AND messageId = ?2",
+            params![chat_id, message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the messages of drafts opened before `before` and returns
+    /// them, oldest first.
+    pub fn take_draft_messages(&self, before: DateTime<Utc>) -> Result<Vec<(i64, i32)>> {
+        let tx = self.connection.unchecked_transaction()?;
+        let messages = tx
+            .prepare(
+                "SELECT chatId, messageId FROM DraftMessage WHERE openedAt < ?1
+                 ORDER BY openedAt, chatId, messageId",
+            )?
+            .query_map([DbTime(before)], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        tx.execute(
+            "DELETE FROM DraftMessage WHERE openedAt < ?1",
+            [DbTime(before)],
+        )?;
+        tx.commit()?;
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn takes_the_messages_of_drafts_before() {
+        let model = Model::new(true);
+        model
+            .record_draft_message(-100, 7, at("2023-12-01T10:00:00Z"))
+            .unwrap();
+        model
+            .record_draft_message(1001, 3, at("2023-12-01T09:00:00Z"))
+            .unwrap();
+        model
+            .record_draft_message(-100, 8, at("2023-12-01T11:00:00Z"))
+            .unwrap();
+        model
+            .record_draft_message(-100, 9, at("2023-12-01T12:00:00Z"))
+            .unwrap();
+        model.forget_draft_message(-100, 8).unwrap();
+
+        let before = at("2023-12-01T12:00:00Z");
+        assert_eq!(
+            vec![(1001, 3), (-100, 7)],
+            model.take_draft_messages(before).unwrap()
+        );
+        assert!(model.take_draft_messages(before).unwrap().is_empty());
+        // Opened since, still open.
+        let later = at("2023-12-01T13:00:00Z");
+        assert_eq!(vec![(-100, 9)], model.take_draft_messages(later).unwrap());
+    }
+}
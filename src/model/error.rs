@@ -0,0 +1,38 @@
+use super::AmountError;
+use chrono::NaiveDate;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("database error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("{0} not found")]
+    NotFound(String),
+    /// The change is not allowed in the current state of the data.
+    #[error("{0}")]
+    Refused(String),
+    /// Expenses of the category need a comment, and this one has none.
+    #[error("Add a comment first, {0} expenses need one.")]
+    CommentRequired(String),
+    /// A text over the length the database takes for it.
+    #[error("{what} too long ({length}/{max} chars).")]
+    TooLong {
+        what: &'static str,
+        length: usize,
+        max: usize,
+    },
+    /// A long operation on the database, like `Maintenance`, has it to itself.
+    #[error("{0} in progress, try again in a minute.")]
+    Busy(String),
+    /// Read-only mode is on, changes wait until an admin turns it off.
+    #[error("Maintenance in progress, changes are paused until an admin runs /readonly off.")]
+    ReadOnly,
+    /// The month, starting on the date, is closed: its expenses stay as
+    /// they are until an admin reopens it.
+    #[error("{} is closed — ask the admin to reopen.", .0.format("%B %Y"))]
+    Closed(NaiveDate),
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] AmountError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
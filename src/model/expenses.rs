@@ -0,0 +1,1094 @@
+use super::amount::{from_minor, to_minor};
+use super::{Error, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewExpense {
+    pub account_id: i64,
+    pub category_id: i64,
+    pub user_id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub amount: f64,
+    pub comment: Option<String>,
+    /// The category was chosen, not just left at the default.
+    pub category_confirmed: bool,
+    pub source: ExpenseSource,
+}
+
+/// How an expense came to be logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpenseSource {
+    /// Typed, one or several at once.
+    Manual,
+    /// Read from a forward of a trusted source, like a bank's notification.
+    Forward,
+    Favorite,
+    /// 🔁 on the confirmation of another expense.
+    Repeat,
+}
+
+impl ExpenseSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExpenseSource::Manual => "manual",
+            ExpenseSource::Forward => "forward",
+            ExpenseSource::Favorite => "favorite",
+            ExpenseSource::Repeat => "repeat",
+        }
+    }
+}
+
+/// Outcome of `insert_expense_once`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inserted {
+    New(i64),
+    /// The key was committed before; nothing was inserted.
+    Existing(i64),
+}
+
+/// A stored expense with everything needed to show it. Related rows are
+/// joined optionally, so a dangling reference shows up as `None` instead of
+/// hiding the expense.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpenseDetails {
+    pub id: i64,
+    pub amount: f64,
+    /// Decimal places of the currency, 2 if the currency is unknown.
+    pub exponent: u32,
+    pub account_id: i64,
+    pub account: Option<String>,
+    pub currency: Option<String>,
+    pub category_id: i64,
+    pub category: Option<String>,
+    pub category_icon: Option<String>,
+    pub user_id: i64,
+    pub user: Option<String>,
+    /// When it was made, as its user says.
+    pub timestamp: DateTime<Utc>,
+    /// When it was logged, set on saving it.
+    pub created_at: DateTime<Utc>,
+    pub comment: Option<String>,
+    pub category_confirmed: bool,
+    /// The user sharing the expense, if it is split.
+    pub split_user_id: Option<i64>,
+    pub split_user: Option<String>,
+    /// What was paid in another currency, `amount` being what the account
+    /// was charged for it.
+    pub original: Option<OriginalAmount>,
+    /// Latitude and longitude of where it was made, if shared.
+    pub location: Option<(f64, f64)>,
+    /// The last change since it was saved, `None` if it never changed.
+    pub modified: Option<Modification>,
+    /// The group message it was logged from, if any.
+    pub source_message: Option<SourceMessage>,
+}
+
+/// A message in a group chat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMessage {
+    pub chat_id: i64,
+    pub message_id: i32,
+    /// The chat's username, which only public groups have.
+    pub username: Option<String>,
+}
+
+/// Who changed an expense, and when.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modification {
+    pub user_id: i64,
+    pub user: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// An amount in a currency other than the account's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginalAmount {
+    pub amount: f64,
+    pub currency: String,
+    /// Decimal places of the currency.
+    pub exponent: u32,
+}
+
+/// Selects what `ExpenseDetails::from_row` reads, to be followed by a
+/// `WHERE` clause on `e`.
+pub(super) const SELECT_DETAILS: &str = "
+    SELECT e.id, e.amountMinor, e.accountId, COALESCE(a.displayName, a.name), c.name,
+           e.categoryId, ec.name, ec.icon, e.userId, u.displayName, e.timestamp, e.comments,
+           COALESCE(c.exponent, 2), e.categoryConfirmed, s.userId, su.displayName,
+           e.originalAmountMinor, oc.name, oc.exponent, e.latitude, e.longitude,
+           e.modifiedBy, mu.displayName, e.modifiedAt, e.sourceChatId, e.sourceMessageId,
+           e.sourceChatUsername, e.createdAt
+    FROM Expense e
+    LEFT JOIN Account a ON a.id = e.accountId
+    LEFT JOIN Currency c ON c.id = a.currencyId
+    LEFT JOIN ExpenseCategory ec ON ec.id = e.categoryId
+    LEFT JOIN User u ON u.telegramId = e.userId
+    LEFT JOIN ExpenseShare s ON s.expenseId = e.id AND s.userId <> e.userId
+    LEFT JOIN User su ON su.telegramId = s.userId
+    LEFT JOIN Currency oc ON oc.id = e.originalCurrencyId
+    LEFT JOIN User mu ON mu.telegramId = e.modifiedBy
+";
+
+/// Restricts expenses to those on accounts of the household bound to
+/// parameter `?n`.
+pub(super) fn of_household(n: usize) -> String {
+    format!(
+        "accountId IN (SELECT id FROM Account WHERE householdId = ?{})",
+        n
+    )
+}
+
+impl ExpenseDetails {
+    pub(super) fn from_row(row: &rusqlite::Row) -> rusqlite::Result<ExpenseDetails> {
+        let exponent = row.get(12)?;
+        let original = match (row.get(16)?, row.get(17)?, row.get(18)?) {
+            (Some(minor), Some(currency), Some(exponent)) => Some(OriginalAmount {
+                amount: from_minor(minor, exponent),
+                currency,
+                exponent,
+            }),
+            _ => None,
+        };
+        let modified = match (row.get(21)?, row.get::<_, Option<DbTime>>(23)?) {
+            (Some(user_id), Some(at)) => Some(Modification {
+                user_id,
+                user: row.get(22)?,
+                at: at.0,
+            }),
+            _ => None,
+        };
+        let source_message = match (row.get(24)?, row.get(25)?) {
+            (Some(chat_id), Some(message_id)) => Some(SourceMessage {
+                chat_id,
+                message_id,
+                username: row.get(26)?,
+            }),
+            _ => None,
+        };
+        Ok(ExpenseDetails {
+            id: row.get(0)?,
+            amount: from_minor(row.get(1)?, exponent),
+            exponent,
+            account_id: row.get(2)?,
+            account: row.get(3)?,
+            currency: row.get(4)?,
+            category_id: row.get(5)?,
+            category: row.get(6)?,
+            category_icon: row.get(7)?,
+            user_id: row.get(8)?,
+            user: row.get(9)?,
+            timestamp: row.get::<_, DbTime>(10)?.0,
+            created_at: row.get::<_, DbTime>(27)?.0,
+            comment: row.get(11)?,
+            category_confirmed: row.get(13)?,
+            split_user_id: row.get(14)?,
+            split_user: row.get(15)?,
+            original,
+            location: row.get::<_, Option<f64>>(19)?.zip(row.get(20)?),
+            modified,
+            source_message,
+        })
+    }
+}
+
+impl Model {
+    /// Validates the amount against the limits and the decimal places of the
+    /// account's currency, returning it in minor units. The account must be
+    /// of the household.
+    fn checked_minor(&self, household: i64, expense: &NewExpense) -> Result<i64> {
+        let account = self
+            .get_account(household, expense.account_id)?
+            .ok_or_else(|| Error::NotFound(format!("account {}", expense.account_id)))?;
+        let amount = self
+            .amount_limits
+            .for_exponent(account.exponent)
+            .validate(expense.amount)?;
+        Ok(to_minor(amount, account.exponent))
+    }
+
+    /// Refuses expenses without a comment in categories requiring one, and
+    /// categories of other households.
+    fn check_comment(&self, household: i64, expense: &NewExpense) -> Result<()> {
+        super::check_comment(expense.comment.as_deref())?;
+        match self.get_category(household, expense.category_id)? {
+            None => Err(Error::NotFound(format!("category {}", expense.category_id))),
+            Some(category) if category.lacks_comment(expense.comment.as_deref()) => {
+                Err(Error::CommentRequired(category.name))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Stores the expense of the default household without a key and
+    /// returns its id. The bot always commits with a key.
+    #[cfg(test)]
+    pub fn insert_expense(&self, expense: &NewExpense) -> Result<i64> {
+        match self.insert_keyed(super::DEFAULT_HOUSEHOLD, None, expense)? {
+            Inserted::New(id) | Inserted::Existing(id) => Ok(id),
+        }
+    }
+
+    /// Stores the expense unless one was already stored under `key`. The
+    /// unique index makes this a single atomic step, so a commit replayed
+    /// after a crash finds the expense of the first attempt.
+    pub fn insert_expense_once(
+        &self,
+        household: i64,
+        key: &str,
+        expense: &NewExpense,
+    ) -> Result<Inserted> {
+        self.insert_keyed(household, Some(key), expense)
+    }
+
+    /// Stores the expenses like [`Model::insert_expense_once`], in one
+    /// transaction: if any of them is refused, none is stored.
+    pub fn insert_expenses_once(
+        &self,
+        household: i64,
+        expenses: &[(String, NewExpense)],
+    ) -> Result<Vec<Inserted>> {
+        let tx = self.connection.unchecked_transaction()?;
+        let inserted = expenses
+            .iter()
+            .map(|(key, expense)| self.insert_keyed(household, Some(key), expense))
+            .collect::<Result<Vec<_>>>()?;
+        tx.commit()?;
+        Ok(inserted)
+    }
+
+    fn insert_keyed(
+        &self,
+        household: i64,
+        key: Option<&str>,
+        expense: &NewExpense,
+    ) -> Result<Inserted> {
+        self.check_writable()?;
+        self.check_open(household, expense.timestamp)?;
+        let minor = self.checked_minor(household, expense)?;
+        self.check_comment(household, expense)?;
+        let inserted = self.connection.execute(
+            "INSERT INTO Expense (accountId, categoryId, userId, timestamp, amountMinor, comments,
+                categoryConfirmed, idempotencyKey, source, createdAt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT (idempotencyKey) DO NOTHING",
+            params![
+                expense.account_id,
+                expense.category_id,
+                expense.user_id,
+                DbTime(expense.timestamp),
+                minor,
+                expense.comment,
+                expense.category_confirmed,
+                key,
+                expense.source.as_str(),
+                DbTime(Utc::now()),
+            ],
+        )?;
+        if inserted == 0 {
+            let key = key.expect("only keyed inserts conflict");
+            let id = self
+                .expense_by_key(key)?
+                .ok_or_else(|| Error::NotFound(format!("expense {}", key)))?;
+            info!("Expense {} already committed as {}", key, id);
+            return Ok(Inserted::Existing(id));
+        }
+        let id = self.connection.last_insert_rowid();
+        info!("Inserted expense {}", id);
+        Ok(Inserted::New(id))
+    }
+
+    /// The expense committed under `key`, if any.
+    pub fn expense_by_key(&self, key: &str) -> Result<Option<i64>> {
+        let id = self
+            .connection
+            .query_row(
+                "SELECT id FROM Expense WHERE idempotencyKey = ?1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// Overwrites a stored expense of the household, `NotFound` if it's gone
+    /// meanwhile, stamping it as modified by `user_id` now. The shares of a
+    /// split expense follow in the same transaction, so a refused split
+    /// leaves the expense as it was.
+    pub fn update_expense(
+        &self,
+        household: i64,
+        user_id: i64,
+        id: i64,
+        expense: &NewExpense,
+    ) -> Result<()> {
+        self.check_writable()?;
+        // Neither out of a closed month nor into one.
+        self.check_expense_open(household, id)?;
+        self.check_open(household, expense.timestamp)?;
+        let minor = self.checked_minor(household, expense)?;
+        self.check_comment(household, expense)?;
+        let tx = self.connection.unchecked_transaction()?;
+        let updated = self.connection.execute(
+            &format!(
+                "UPDATE Expense SET accountId = ?2, categoryId = ?3, userId = ?4, timestamp = ?5,
+                    amountMinor = ?6, comments = ?7, categoryConfirmed = ?8,
+                    modifiedBy = ?10, modifiedAt = ?11
+                 WHERE id = ?1 AND {}",
+                of_household(9)
+            ),
+            params![
+                id,
+                expense.account_id,
+                expense.category_id,
+                expense.user_id,
+                DbTime(expense.timestamp),
+                minor,
+                expense.comment,
+                expense.category_confirmed,
+                household,
+                user_id,
+                DbTime(Utc::now()),
+            ],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("expense {}", id)));
+        }
+        // The shares follow the new amount.
+        if let Some(partner) = self.split_partner(id)? {
+            let partner = Some(partner).filter(|p| *p != expense.user_id);
+            self.write_shares(household, id, (expense.user_id, minor), partner)?;
+        }
+        tx.commit()?;
+        info!("Updated expense {}", id);
+        Ok(())
+    }
+
+    /// Records what the expense of the household was paid in another
+    /// currency than its account's, or clears that for `None`.
+    pub fn set_original_amount(
+        &self,
+        household: i64,
+        id: i64,
+        original: Option<(f64, &str)>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.check_expense_open(household, id)?;
+        let account_currency: String = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT c.name FROM Expense e
+                     JOIN Account a ON a.id = e.accountId
+                     JOIN Currency c ON c.id = a.currencyId
+                     WHERE e.id = ?1 AND e.{}",
+                    of_household(2)
+                ),
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("expense {}", id)))?;
+        let stored = match original {
+            None => None,
+            Some((amount, currency)) => {
+                if currency.eq_ignore_ascii_case(&account_currency) {
+                    return Err(Error::Refused(format!(
+                        "The account is in {} already, there's nothing to convert.",
+                        account_currency
+                    )));
+                }
+                let (currency_id, exponent): (i64, u32) = self
+                    .connection
+                    .query_row(
+                        "SELECT id, exponent FROM Currency WHERE name = ?1 COLLATE NOCASE",
+                        [currency],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?
+                    .ok_or_else(|| Error::NotFound(format!("currency {}", currency)))?;
+                let amount = self.amount_limits.for_exponent(exponent).validate(amount)?;
+                Some((to_minor(amount, exponent), currency_id))
+            }
+        };
+        self.connection.execute(
+            "UPDATE Expense SET originalAmountMinor = ?2, originalCurrencyId = ?3 WHERE id = ?1",
+            params![id, stored.map(|s| s.0), stored.map(|s| s.1)],
+        )?;
+        Ok(())
+    }
+
+    /// Records where the expense of the household was made, or forgets it
+    /// for `None`.
+    pub fn set_expense_location(
+        &self,
+        household: i64,
+        id: i64,
+        location: Option<(f64, f64)>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.check_expense_open(household, id)?;
+        if let Some((latitude, longitude)) = location {
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(Error::Refused(format!(
+                    "{}, {} is not a location.",
+                    latitude, longitude
+                )));
+            }
+        }
+        let updated = self.connection.execute(
+            &format!(
+                "UPDATE Expense SET latitude = ?2, longitude = ?3 WHERE id = ?1 AND {}",
+                of_household(4)
+            ),
+            params![id, location.map(|l| l.0), location.map(|l| l.1), household],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("expense {}", id)));
+        }
+        info!("Set the location of expense {}", id);
+        Ok(())
+    }
+
+    /// Records the group message the expense of the household was logged
+    /// from.
+    pub fn set_expense_source(
+        &self,
+        household: i64,
+        id: i64,
+        source: &SourceMessage,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.check_expense_open(household, id)?;
+        let updated = self.connection.execute(
+            &format!(
+                "UPDATE Expense SET sourceChatId = ?2, sourceMessageId = ?3, sourceChatUsername = ?4
+                 WHERE id = ?1 AND {}",
+                of_household(5)
+            ),
+            params![
+                id,
+                source.chat_id,
+                source.message_id,
+                source.username,
+                household
+            ],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("expense {}", id)));
+        }
+        Ok(())
+    }
+
+    pub fn get_expense_details(&self, household: i64, id: i64) -> Result<Option<ExpenseDetails>> {
+        let details = self
+            .connection
+            .query_row(
+                &format!("{} WHERE e.id = ?1 AND {}", SELECT_DETAILS, of_household(2)),
+                [id, household],
+                ExpenseDetails::from_row,
+            )
+            .optional()?;
+        Ok(details)
+    }
+
+    /// The user who logged the expense, `None` if the household has no such
+    /// expense.
+    pub fn expense_user_id(&self, household: i64, id: i64) -> Result<Option<i64>> {
+        let user_id = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT userId FROM Expense e WHERE id = ?1 AND {}",
+                    of_household(2)
+                ),
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(user_id)
+    }
+
+    pub fn delete_expense(&self, household: i64, id: i64) -> Result<()> {
+        self.check_writable()?;
+        self.check_expense_open(household, id)?;
+        info!("Deleting expense {}", id);
+        self.connection.execute(
+            &format!("DELETE FROM Expense WHERE id = ?1 AND {}", of_household(2)),
+            [id, household],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{AmountError, AmountLimits};
+    use chrono::SubsecRound;
+
+    #[test]
+    fn insert_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let id = model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id: 2,
+                user_id: 1001,
+                timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                amount: 12.5,
+                comment: Some("bus".to_string()),
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+
+        let (timestamp, amount): (DbTime, i64) = model
+            .connection
+            .query_row(
+                "SELECT timestamp, amountMinor FROM Expense WHERE id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (DateTime::from_timestamp(1_700_000_000, 0).unwrap(), 1250),
+            (timestamp.0, amount)
+        );
+    }
+
+    #[test]
+    fn inserts_record_when_they_were_logged() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let before = Utc::now().trunc_subsecs(0);
+        // Last week's cash, back-filled.
+        let mut expense = NewExpense {
+            account_id: 3,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: before - chrono::TimeDelta::days(7),
+            amount: 12.5,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        let id = model.insert_expense(&expense).unwrap();
+        let logged = model.get_expense_details(1, id).unwrap().unwrap();
+        assert_eq!(expense.timestamp, logged.timestamp);
+        assert!(logged.created_at >= before && logged.created_at <= Utc::now());
+
+        // Editing when it was made keeps when it was logged.
+        expense.timestamp -= chrono::TimeDelta::days(1);
+        model.update_expense(1, 1001, id, &expense).unwrap();
+        let edited = model.get_expense_details(1, id).unwrap().unwrap();
+        assert_eq!(
+            (expense.timestamp, logged.created_at),
+            (edited.timestamp, edited.created_at)
+        );
+    }
+
+    #[test]
+    fn insert_rejects_invalid_amounts() {
+        let mut model = Model::new(true);
+        model.fill_test_data();
+        model.set_amount_limits(AmountLimits {
+            max: 100.0,
+            decimals: 2,
+        });
+
+        let expense = |amount| NewExpense {
+            account_id: 1,
+            category_id: 2,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            amount,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        for (amount, expected) in [
+            (f64::NAN, AmountError::NotFinite),
+            (-5.0, AmountError::Negative),
+            (0.0, AmountError::Zero),
+            (100.5, AmountError::TooLarge(100.0)),
+            (1.234, AmountError::TooPrecise(2)),
+        ] {
+            match model.insert_expense(&expense(amount)) {
+                Err(Error::InvalidAmount(e)) => assert_eq!(expected, e),
+                other => panic!("inserting {}: {:?}", amount, other),
+            }
+        }
+        let count: i64 = model
+            .connection
+            .query_row("SELECT COUNT(*) FROM Expense", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(4, count);
+    }
+
+    #[test]
+    fn expense_details() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(
+            Some(ExpenseDetails {
+                id: 1,
+                amount: 50.75,
+                exponent: 2,
+                account_id: 1,
+                account: Some("Alex Savings".to_string()),
+                currency: Some("EUR".to_string()),
+                category_id: 1,
+                category: Some("Groceries".to_string()),
+                category_icon: Some("🛒".to_string()),
+                user_id: 1001,
+                user: Some("Alex".to_string()),
+                timestamp: DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+                created_at: DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+                comment: Some("Bought groceries for the week".to_string()),
+                category_confirmed: true,
+                split_user_id: None,
+                split_user: None,
+                original: None,
+                location: None,
+                modified: None,
+                source_message: None,
+            }),
+            model.get_expense_details(1, 1).unwrap()
+        );
+        assert_eq!(None, model.get_expense_details(1, 99).unwrap());
+    }
+
+    #[test]
+    fn details_survive_missing_references() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .connection
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 DELETE FROM User WHERE telegramId = 1002;
+                 DELETE FROM ExpenseCategory WHERE id = 2;",
+            )
+            .unwrap();
+
+        let details = model.get_expense_details(1, 2).unwrap().unwrap();
+        assert_eq!((None, None), (details.user, details.category));
+        assert_eq!(Some("USD".to_string()), details.currency);
+    }
+
+    #[test]
+    fn update_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let expense = NewExpense {
+            account_id: 3,
+            category_id: 4,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            amount: 7.5,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        model.update_expense(1, 1001, 1, &expense).unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(
+            (3, 4, 7.5, None),
+            (
+                details.account_id,
+                details.category_id,
+                details.amount,
+                details.comment
+            )
+        );
+
+        assert!(matches!(
+            model.update_expense(1, 1001, 99, &expense),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.update_expense(
+                1,
+                1001,
+                1,
+                &NewExpense {
+                    amount: -1.0,
+                    ..expense
+                }
+            ),
+            Err(Error::InvalidAmount(AmountError::Negative))
+        ));
+    }
+
+    #[test]
+    fn refused_split_rolls_the_edit_back() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        model.split_expense(1, 1, Some(1002)).unwrap();
+        // Hanna moved to the other household since.
+        model
+            .connection
+            .execute(
+                "UPDATE User SET householdId = 2 WHERE telegramId = 1002",
+                [],
+            )
+            .unwrap();
+
+        let expense = NewExpense {
+            account_id: 1,
+            category_id: 2,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+            amount: 60.0,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        assert!(matches!(
+            model.update_expense(1, 1001, 1, &expense),
+            Err(Error::NotFound(_))
+        ));
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(
+            (1, 50.75, None),
+            (details.category_id, details.amount, details.modified)
+        );
+        let shares: Vec<(i64, i64)> = model
+            .connection
+            .prepare(
+                "SELECT userId, shareMinor FROM ExpenseShare WHERE expenseId = 1 ORDER BY userId",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(vec![(1001, 2538), (1002, 2537)], shares);
+    }
+
+    #[test]
+    fn edits_are_stamped() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(
+            None,
+            model.get_expense_details(1, 1).unwrap().unwrap().modified
+        );
+
+        let mut expense = NewExpense {
+            account_id: 1,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+            amount: 50.75,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        let before = Utc::now().trunc_subsecs(0);
+        // Hanna, an admin, fixes Alex's expense.
+        model.update_expense(1, 1002, 1, &expense).unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        let modified = details.modified.unwrap();
+        assert_eq!(
+            (1002, Some("Hanna")),
+            (modified.user_id, modified.user.as_deref())
+        );
+        assert!(before <= modified.at && modified.at <= Utc::now());
+        // Still Alex's, only edited by Hanna.
+        assert_eq!(1001, details.user_id);
+
+        let id = model.insert_expense(&expense).unwrap();
+        assert_eq!(
+            None,
+            model.get_expense_details(1, id).unwrap().unwrap().modified
+        );
+        expense.amount = 1.0;
+        model.update_expense(1, 1001, id, &expense).unwrap();
+        let modified = model.get_expense_details(1, id).unwrap().unwrap().modified;
+        assert_eq!(Some(1001), modified.map(|m| m.user_id));
+    }
+
+    #[test]
+    fn categories_can_require_a_comment() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_require_comment(1, 4, true).unwrap();
+        let expense = NewExpense {
+            account_id: 1,
+            category_id: 4,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            amount: 12.5,
+            comment: Some("  ".to_string()),
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+
+        assert!(matches!(
+            model.insert_expense(&expense),
+            Err(Error::CommentRequired(name)) if name == "Utilities"
+        ));
+        assert!(matches!(
+            model.update_expense(1, 1001, 1, &expense),
+            Err(Error::CommentRequired(_))
+        ));
+        let commented = NewExpense {
+            comment: Some("water".to_string()),
+            ..expense.clone()
+        };
+        assert!(model.insert_expense(&commented).is_ok());
+        // Other categories are not affected.
+        assert!(model
+            .insert_expense(&NewExpense {
+                category_id: 3,
+                ..expense.clone()
+            })
+            .is_ok());
+
+        model.set_require_comment(1, 4, false).unwrap();
+        assert!(model.insert_expense(&expense).is_ok());
+        assert!(matches!(
+            model.set_require_comment(1, 99, true),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn replayed_commit_finds_the_first_insert() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let expense = NewExpense {
+            account_id: 1,
+            category_id: 2,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            amount: 12.5,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+
+        assert_eq!(None, model.expense_by_key("draft:1:10").unwrap());
+        let Inserted::New(id) = model
+            .insert_expense_once(1, "draft:1:10", &expense)
+            .unwrap()
+        else {
+            panic!("first commit must insert");
+        };
+        // The process dies here, before the confirmation is sent. The user
+        // presses Commit again once the bot is back.
+        assert_eq!(Some(id), model.expense_by_key("draft:1:10").unwrap());
+        assert_eq!(
+            Inserted::Existing(id),
+            model
+                .insert_expense_once(1, "draft:1:10", &expense)
+                .unwrap()
+        );
+        let count: i64 = model
+            .connection
+            .query_row("SELECT COUNT(*) FROM Expense", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(5, count);
+
+        // Expenses without a key never conflict.
+        model.insert_expense(&expense).unwrap();
+        model.insert_expense(&expense).unwrap();
+        assert!(matches!(
+            model.insert_expense_once(1, "draft:1:11", &expense),
+            Ok(Inserted::New(_))
+        ));
+    }
+
+    #[test]
+    fn batch_inserts_all_or_nothing() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let count = || -> i64 {
+            model
+                .connection
+                .query_row("SELECT COUNT(*) FROM Expense", [], |row| row.get(0))
+                .unwrap()
+        };
+        let expense = |amount| NewExpense {
+            account_id: 1,
+            category_id: 2,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            amount,
+            comment: None,
+            category_confirmed: false,
+            source: ExpenseSource::Manual,
+        };
+        let batch = |amounts: &[f64]| -> Vec<(String, NewExpense)> {
+            amounts
+                .iter()
+                .enumerate()
+                .map(|(i, &amount)| (format!("batch:1:10:{}", i + 1), expense(amount)))
+                .collect()
+        };
+
+        // The third one is refused, so the first two are rolled back.
+        assert!(matches!(
+            model.insert_expenses_once(1, &batch(&[12.5, 40.0, -8.0])),
+            Err(Error::InvalidAmount(_))
+        ));
+        assert_eq!(4, count());
+
+        let inserted = model
+            .insert_expenses_once(1, &batch(&[12.5, 40.0, 8.0]))
+            .unwrap();
+        assert!(inserted.iter().all(|i| matches!(i, Inserted::New(_))));
+        assert_eq!(7, count());
+        // Pressing the button again finds them stored.
+        let again = model
+            .insert_expenses_once(1, &batch(&[12.5, 40.0, 8.0]))
+            .unwrap();
+        assert!(again.iter().all(|i| matches!(i, Inserted::Existing(_))));
+        assert_eq!(7, count());
+    }
+
+    #[test]
+    fn comments_have_a_length_limit() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let expense = |length| NewExpense {
+            account_id: 1,
+            category_id: 2,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            amount: 5.0,
+            comment: Some("x".repeat(length)),
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        let id = model.insert_expense(&expense(1000)).unwrap();
+        assert!(matches!(
+            model.insert_expense(&expense(1001)),
+            Err(Error::TooLong {
+                what: "Comment",
+                length: 1001,
+                max: 1000
+            })
+        ));
+        assert!(matches!(
+            model.update_expense(1, 1001, id, &expense(1001)),
+            Err(Error::TooLong { .. })
+        ));
+        model.update_expense(1, 1001, id, &expense(999)).unwrap();
+    }
+
+    #[test]
+    fn original_amount() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        model
+            .set_original_amount(1, 1, Some((55.5, "usd")))
+            .unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(50.75, details.amount);
+        assert_eq!(
+            Some(OriginalAmount {
+                amount: 55.5,
+                currency: "USD".to_string(),
+                exponent: 2
+            }),
+            details.original
+        );
+
+        assert!(matches!(
+            model.set_original_amount(1, 1, Some((10.0, "EUR"))),
+            Err(Error::Refused(reason))
+                if reason == "The account is in EUR already, there's nothing to convert."
+        ));
+        assert!(matches!(
+            model.set_original_amount(1, 1, Some((10.125, "USD"))),
+            Err(Error::InvalidAmount(AmountError::TooPrecise(2)))
+        ));
+        assert!(matches!(
+            model.set_original_amount(1, 1, Some((10.0, "XYZ"))),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.set_original_amount(2, 1, None),
+            Err(Error::NotFound(_))
+        ));
+
+        model.set_original_amount(1, 1, None).unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(None, details.original);
+    }
+
+    #[test]
+    fn expense_location() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+
+        model
+            .set_expense_location(1, 1, Some((52.52, 13.405)))
+            .unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(Some((52.52, 13.405)), details.location);
+
+        assert!(matches!(
+            model.set_expense_location(1, 1, Some((91.0, 0.0))),
+            Err(Error::Refused(_))
+        ));
+        assert!(matches!(
+            model.set_expense_location(2, 1, Some((0.0, 0.0))),
+            Err(Error::NotFound(_))
+        ));
+
+        model.set_expense_location(1, 1, None).unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(None, details.location);
+    }
+
+    #[test]
+    fn expense_source() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let source = SourceMessage {
+            chat_id: -1001234567890,
+            message_id: 42,
+            username: Some("flat_expenses".to_string()),
+        };
+        model.set_expense_source(1, 1, &source).unwrap();
+        let details = model.get_expense_details(1, 1).unwrap().unwrap();
+        assert_eq!(Some(source.clone()), details.source_message);
+        assert_eq!(
+            None,
+            model
+                .get_expense_details(1, 2)
+                .unwrap()
+                .unwrap()
+                .source_message
+        );
+        assert!(matches!(
+            model.set_expense_source(2, 1, &source),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn delete_expense() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(Some(1002), model.expense_user_id(1, 2).unwrap());
+        model.delete_expense(1, 2).unwrap();
+        assert_eq!(None, model.expense_user_id(1, 2).unwrap());
+    }
+}
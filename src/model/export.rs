@@ -0,0 +1,133 @@
+//! Expenses one row at a time, for files written while they are read.
+
+use super::{DateRange, Error, Model};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportRow {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    /// The amount in minor units of the currency, as stored.
+    pub amount_minor: i64,
+    pub currency: String,
+    pub exponent: u32,
+    pub account: String,
+    pub category: String,
+    pub user: String,
+    pub comment: Option<String>,
+}
+
+impl Model {
+    /// Calls `row` for every expense of the household over the local days of
+    /// `range`, oldest first, without collecting them. Stops at the first
+    /// error `row` returns.
+    pub fn export_expenses<E: From<Error>>(
+        &self,
+        household: i64,
+        range: DateRange,
+        tz: Tz,
+        mut row: impl FnMut(ExportRow) -> std::result::Result<(), E>,
+    ) -> std::result::Result<(), E> {
+        let (start, end) = range.to_utc(tz);
+        let tables = self.expense_tables(Some(start))?;
+        let mut stmt = self
+            .connection
+            .prepare(&tables.apply(
+                "SELECT e.id, e.timestamp, e.amountMinor, c.name, c.exponent,
+                        COALESCE(a.displayName, a.name), ec.name,
+                        COALESCE(u.displayName, CAST(e.userId AS TEXT)), e.comments
+                 FROM {expenses} e
+                 JOIN Account a ON a.id = e.accountId
+                 JOIN Currency c ON c.id = a.currencyId
+                 JOIN ExpenseCategory ec ON ec.id = e.categoryId
+                 LEFT JOIN User u ON u.telegramId = e.userId
+                 WHERE e.timestamp >= ?1 AND e.timestamp < ?2 AND a.householdId = ?3
+                     AND e.kind = 'expense'
+                 ORDER BY e.timestamp, e.id",
+            ))
+            .map_err(Error::from)?;
+        let mut rows = stmt
+            .query(params![DbTime(start), DbTime(end), household])
+            .map_err(Error::from)?;
+        while let Some(r) = rows.next().map_err(Error::from)? {
+            let read = || -> rusqlite::Result<ExportRow> {
+                let exponent = r.get(4)?;
+                Ok(ExportRow {
+                    id: r.get(0)?,
+                    timestamp: r.get::<_, DbTime>(1)?.0,
+                    amount_minor: r.get(2)?,
+                    currency: r.get(3)?,
+                    exponent,
+                    account: r.get(5)?,
+                    category: r.get(6)?,
+                    user: r.get(7)?,
+                    comment: r.get(8)?,
+                })
+            };
+            row(read().map_err(Error::from)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn december() -> DateRange {
+        DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        )
+    }
+
+    #[test]
+    fn streams_rows_in_order() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let mut rows = Vec::new();
+        model
+            .export_expenses(1, december(), Tz::UTC, |row| -> Result<(), Error> {
+                rows.push(row);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            vec![1, 2, 3, 4],
+            rows.iter().map(|r| r.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ExportRow {
+                id: 2,
+                timestamp: DateTime::from_timestamp(1_701_520_200, 0).unwrap(),
+                amount_minor: 2000,
+                currency: "USD".to_string(),
+                exponent: 2,
+                account: "Hanna Daily".to_string(),
+                category: "Transportation".to_string(),
+                user: "Hanna".to_string(),
+                comment: Some("Taxi ride to the office".to_string()),
+            },
+            rows[1]
+        );
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let mut seen = 0;
+        let result = model.export_expenses(1, december(), Tz::UTC, |_| {
+            seen += 1;
+            Err(Error::Refused("full".to_string()))
+        });
+        assert!(matches!(result, Err(Error::Refused(_))));
+        assert_eq!(1, seen);
+    }
+}
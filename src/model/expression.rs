@@ -0,0 +1,325 @@
+//! Amounts typed as a calculation, like `12.40+3.60` or `3*4.5`: numbers,
+//! `+ - * /` and parentheses. They are evaluated as exact fractions and only
+//! rounded to the currency's decimal places at the very end.
+
+use super::amount::{number_parts, AmountError, Locale};
+
+/// Characters that make a typed amount a calculation.
+const OPERATORS: [char; 6] = ['+', '-', '*', '/', '(', ')'];
+
+/// Deepest nesting of parentheses and signs, so that no input can exhaust
+/// the stack.
+const MAX_DEPTH: usize = 32;
+
+/// An exact value, `num / den` with `den` positive and the two coprime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fraction {
+    num: i128,
+    den: i128,
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+
+impl Fraction {
+    fn new(num: i128, den: i128) -> Option<Fraction> {
+        let (num, den) = if den < 0 {
+            (num.checked_neg()?, den.checked_neg()?)
+        } else {
+            (num, den)
+        };
+        let divisor = gcd(num, den).max(1);
+        Some(Fraction {
+            num: num / divisor,
+            den: den / divisor,
+        })
+    }
+
+    fn add(self, other: Fraction) -> Option<Fraction> {
+        let num = self
+            .num
+            .checked_mul(other.den)?
+            .checked_add(other.num.checked_mul(self.den)?)?;
+        Fraction::new(num, self.den.checked_mul(other.den)?)
+    }
+
+    fn neg(self) -> Option<Fraction> {
+        Fraction::new(self.num.checked_neg()?, self.den)
+    }
+
+    fn mul(self, other: Fraction) -> Option<Fraction> {
+        // Cross-reducing first keeps the products small.
+        let (a, b) = (
+            gcd(self.num, other.den).max(1),
+            gcd(other.num, self.den).max(1),
+        );
+        Fraction::new(
+            (self.num / a).checked_mul(other.num / b)?,
+            (self.den / b).checked_mul(other.den / a)?,
+        )
+    }
+
+    /// `None` when `other` is zero too.
+    fn div(self, other: Fraction) -> Option<Fraction> {
+        if other.num == 0 {
+            return None;
+        }
+        self.mul(Fraction::new(other.den, other.num)?)
+    }
+
+    /// The value in minor units of `decimals` places, halves rounded away
+    /// from zero. `None` if it doesn't fit.
+    pub fn to_minor(self, decimals: u32) -> Option<i128> {
+        let scaled = self.num.checked_mul(10i128.checked_pow(decimals)?)?;
+        let (whole, rest) = (scaled / self.den, scaled % self.den);
+        if rest.checked_abs()?.checked_mul(2)? >= self.den {
+            whole.checked_add(scaled.signum())
+        } else {
+            Some(whole)
+        }
+    }
+}
+
+/// An amount typed as a calculation, kept exact so that it can be rounded
+/// again once the currency is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calculation {
+    /// What the user typed.
+    pub text: String,
+    pub value: Fraction,
+}
+
+/// Whether `text` is meant as a calculation rather than a single number.
+pub fn is_calculation(text: &str) -> bool {
+    text.contains(OPERATORS)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(Fraction),
+    Operator(char),
+}
+
+/// A number as `locale` writes it, or else the other way round, like
+/// single amounts are read.
+fn number(text: &str, locale: Locale) -> Option<Fraction> {
+    let (decimal, group) = locale.separators();
+    let (integer, fraction) =
+        number_parts(text, decimal, group).or_else(|| number_parts(text, group, decimal))?;
+    let digits: i128 = format!("{}{}", integer, fraction).parse().ok()?;
+    Fraction::new(digits, 10i128.checked_pow(fraction.len() as u32)?)
+}
+
+fn tokenize(text: &str, locale: Locale) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if OPERATORS.contains(&c) {
+            tokens.push(Token::Operator(c));
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest.find(OPERATORS).unwrap_or(rest.len());
+        tokens.push(Token::Number(number(&rest[..end], locale)?));
+        rest = &rest[end..];
+    }
+    Some(tokens)
+}
+
+/// Why an evaluation stopped.
+enum Failure {
+    Invalid,
+    DivisionByZero,
+}
+
+/// Recursive descent over `expression = term {(+|-) term}`,
+/// `term = factor {(*|/) factor}` and `factor = -factor | (expression) | number`.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.next).copied()
+    }
+
+    fn operator(&mut self, choices: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(Token::Operator(c)) if choices.contains(&c) => {
+                self.next += 1;
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self) -> Result<Fraction, Failure> {
+        let mut value = self.term()?;
+        while let Some(op) = self.operator(&['+', '-']) {
+            let term = self.term()?;
+            let term = if op == '-' { term.neg() } else { Some(term) };
+            value = term.and_then(|t| value.add(t)).ok_or(Failure::Invalid)?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<Fraction, Failure> {
+        let mut value = self.factor()?;
+        while let Some(op) = self.operator(&['*', '/']) {
+            let factor = self.factor()?;
+            value = match op {
+                '*' => value.mul(factor).ok_or(Failure::Invalid)?,
+                _ if factor.num == 0 => return Err(Failure::DivisionByZero),
+                _ => value.div(factor).ok_or(Failure::Invalid)?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<Fraction, Failure> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(Failure::Invalid);
+        }
+        let value = if self.operator(&['-']).is_some() {
+            self.factor()?.neg().ok_or(Failure::Invalid)?
+        } else if self.operator(&['(']).is_some() {
+            let value = self.expression()?;
+            self.operator(&[')']).ok_or(Failure::Invalid)?;
+            value
+        } else {
+            match self.peek() {
+                Some(Token::Number(n)) => {
+                    self.next += 1;
+                    n
+                }
+                _ => return Err(Failure::Invalid),
+            }
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+}
+
+/// The exact value of the calculation `text`, its numbers written the way
+/// `locale` writes them.
+pub fn evaluate(text: &str, locale: Locale) -> Result<Fraction, AmountError> {
+    let invalid = || AmountError::InvalidCalculation(text.to_string());
+    let tokens = tokenize(text, locale).ok_or_else(invalid)?;
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        depth: 0,
+    };
+    match parser.expression() {
+        Ok(value) if parser.next == parser.tokens.len() => Ok(value),
+        Ok(_) | Err(Failure::Invalid) => Err(invalid()),
+        Err(Failure::DivisionByZero) => Err(AmountError::DivisionByZero),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minor(text: &str, decimals: u32) -> Result<i128, AmountError> {
+        evaluate(text, Locale::DecimalPoint).map(|v| v.to_minor(decimals).unwrap())
+    }
+
+    #[test]
+    fn evaluates_with_precedence() {
+        assert_eq!(Ok(1600), minor("12.40+3.60", 2));
+        assert_eq!(Ok(1350), minor("3*4.5", 2));
+        assert_eq!(Ok(1100), minor("3+4*2", 2));
+        assert_eq!(Ok(1400), minor("(3+4)*2", 2));
+        assert_eq!(Ok(500), minor("10-2-3", 2));
+        assert_eq!(Ok(500), minor("20/2/2", 2));
+        assert_eq!(Ok(-500), minor("-(2+3)", 2));
+        assert_eq!(Ok(100), minor("--1", 2));
+        // Grouped thousands and the other decimal separator.
+        assert_eq!(Ok(123_500), minor("1,234.5+0.5", 2));
+        assert_eq!(
+            Ok(1600),
+            evaluate("12,40+3,60", Locale::DecimalComma).map(|v| v.to_minor(2).unwrap())
+        );
+    }
+
+    #[test]
+    fn rounds_only_at_the_end() {
+        // 3.33 three times would make 9.99.
+        assert_eq!(Ok(1000), minor("10/3*3", 2));
+        assert_eq!(Ok(333), minor("10/3", 2));
+        assert_eq!(Ok(667), minor("20/3", 2));
+        // Halves round up.
+        assert_eq!(Ok(1), minor("1/200", 2));
+        assert_eq!(Ok(0), minor("1/201", 2));
+        assert_eq!(Ok(288), minor("2.5*1.15", 2));
+        assert_eq!(Ok(3), minor("5/2", 0));
+        assert_eq!(Ok(-3), minor("-5/2", 0));
+    }
+
+    #[test]
+    fn rejects_anything_but_numbers_and_operators() {
+        let invalid = |s: &str| Err(AmountError::InvalidCalculation(s.to_string()));
+        for text in [
+            "2+x", "abs(2)", "2**3", "2+", "*2", "()", "(2", "2)", "2(3)", "1e3+1", "2 + 3",
+            "1..2+1", "+", "",
+        ] {
+            assert_eq!(invalid(text), minor(text, 2), "{:?}", text);
+        }
+        assert_eq!(Err(AmountError::DivisionByZero), minor("5/0", 2));
+        assert_eq!(Err(AmountError::DivisionByZero), minor("5/(2-2)", 2));
+        assert!(minor(&format!("{}1{}", "(".repeat(100), ")".repeat(100)), 2).is_err());
+        assert!(minor(&"-".repeat(1000), 2).is_err());
+        assert!(minor(&format!("{}+1", "9".repeat(60)), 2).is_err());
+        assert!(minor(&format!("1{}", "/3".repeat(100)), 2).is_err());
+    }
+
+    /// A small xorshift generator, so that the fuzzing is repeatable.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn never_panics() {
+        let alphabet: Vec<char> = "0123456789.,+-*/()  x\u{a0}é9".chars().collect();
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..20_000 {
+            let len = (random.next() % 24) as usize;
+            let text: String = (0..len)
+                .map(|_| alphabet[(random.next() % alphabet.len() as u64) as usize])
+                .collect();
+            for locale in [Locale::DecimalPoint, Locale::DecimalComma] {
+                if let Ok(value) = evaluate(&text, locale) {
+                    let _ = value.to_minor(6);
+                }
+            }
+        }
+        // Extremes of every operation.
+        let max = i128::MAX.to_string();
+        for text in [
+            format!("{}*{}", max, max),
+            format!("{}+{}", max, max),
+            format!("-{}-{}", max, max),
+            format!("1/0.{}1", "0".repeat(40)),
+            format!("0.{}1*0.{}1", "0".repeat(30), "0".repeat(30)),
+        ] {
+            if let Ok(value) = evaluate(&text, Locale::DecimalPoint) {
+                let _ = value.to_minor(6);
+            }
+        }
+    }
+}
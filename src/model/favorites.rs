@@ -0,0 +1,301 @@
+//! Expenses a user logs often, kept to be committed again with one tap.
+
+use super::amount::{from_minor, to_minor};
+use super::lengths::{check_comment, check_length, MAX_NAME_LEN};
+use super::{Account, Category, Error, Model, Result};
+use rusqlite::{params, OptionalExtension};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewFavorite {
+    pub user_id: i64,
+    pub label: String,
+    pub amount: f64,
+    pub account_id: i64,
+    pub category_id: i64,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Favorite {
+    pub id: i64,
+    pub user_id: i64,
+    pub label: String,
+    pub amount: f64,
+    /// The currency the amount was typed in, the account's at the time.
+    pub currency: String,
+    pub exponent: u32,
+    pub account: Account,
+    pub category: Category,
+    /// Archived categories take no new expenses.
+    pub category_active: bool,
+    pub comment: Option<String>,
+}
+
+impl Favorite {
+    /// Why the favorite can't be committed as it is, `None` if it can.
+    pub fn blocked_reason(&self) -> Option<String> {
+        if !self.category_active {
+            Some(format!("The category {} is archived.", self.category.name))
+        } else if self.category.lacks_comment(self.comment.as_deref()) {
+            Some(format!(
+                "{} expenses need a comment, this favorite has none.",
+                self.category.name
+            ))
+        } else if self.account.currency != self.currency {
+            Some(format!(
+                "The account {} is no longer in {}.",
+                self.account.name, self.currency
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Favorite> {
+        let exponent = row.get(5)?;
+        Ok(Favorite {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            label: row.get(2)?,
+            amount: from_minor(row.get(3)?, exponent),
+            currency: row.get(4)?,
+            exponent,
+            account: Account {
+                id: row.get(6)?,
+                name: row.get(7)?,
+                currency: row.get(8)?,
+                exponent: row.get(9)?,
+            },
+            category: Category {
+                id: row.get(10)?,
+                name: row.get(11)?,
+                icon: row.get(12)?,
+                require_comment: row.get(15)?,
+                parent_id: row.get(16)?,
+            },
+            category_active: row.get(13)?,
+            comment: row.get(14)?,
+        })
+    }
+}
+
+const SELECT_FAVORITE: &str = "
+    SELECT f.id, f.userId, f.label, f.amountMinor, fc.name, fc.exponent,
+           a.id, COALESCE(a.displayName, a.name), ac.name, ac.exponent,
+           ec.id, ec.name, ec.icon, ec.active, f.comment, ec.requireComment, ec.parentId
+    FROM Favorite f
+    JOIN Currency fc ON fc.id = f.currencyId
+    JOIN Account a ON a.id = f.accountId
+    JOIN Currency ac ON ac.id = a.currencyId
+    JOIN ExpenseCategory ec ON ec.id = f.categoryId
+";
+
+impl Model {
+    /// Stores a favorite in the currency of its account. Labels are unique
+    /// per user, ignoring case. The account and category must be of the
+    /// household.
+    pub fn add_favorite(&self, household: i64, favorite: &NewFavorite) -> Result<i64> {
+        self.check_writable()?;
+        let account = self
+            .get_account(household, favorite.account_id)?
+            .ok_or_else(|| Error::NotFound(format!("account {}", favorite.account_id)))?;
+        let amount = self
+            .amount_limits
+            .for_exponent(account.exponent)
+            .validate(favorite.amount)?;
+        let category = self
+            .get_category(household, favorite.category_id)?
+            .ok_or_else(|| Error::NotFound(format!("category {}", favorite.category_id)))?;
+        if category.lacks_comment(favorite.comment.as_deref()) {
+            return Err(Error::CommentRequired(category.name));
+        }
+        check_length("Label", &favorite.label, MAX_NAME_LEN)?;
+        check_comment(favorite.comment.as_deref())?;
+        if self
+            .favorite_by_label(favorite.user_id, &favorite.label)?
+            .is_some()
+        {
+            return Err(Error::Refused(format!(
+                "There already is a favorite called '{}'.",
+                favorite.label
+            )));
+        }
+        self.connection.execute(
+            "INSERT INTO Favorite (userId, label, amountMinor, currencyId, accountId, categoryId, comment)
+             SELECT ?1, ?2, ?3, a.currencyId, a.id, ?5, ?6 FROM Account a WHERE a.id = ?4",
+            params![
+                favorite.user_id,
+                favorite.label,
+                to_minor(amount, account.exponent),
+                favorite.account_id,
+                favorite.category_id,
+                favorite.comment,
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Favorites of the user, by label.
+    pub fn favorites(&self, user_id: i64) -> Result<Vec<Favorite>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE f.userId = ?1 ORDER BY f.label COLLATE NOCASE",
+            SELECT_FAVORITE
+        ))?;
+        let favorites = stmt
+            .query_map([user_id], Favorite::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(favorites)
+    }
+
+    pub fn get_favorite(&self, household: i64, id: i64) -> Result<Option<Favorite>> {
+        let favorite = self
+            .connection
+            .query_row(
+                &format!("{} WHERE f.id = ?1 AND a.householdId = ?2", SELECT_FAVORITE),
+                [id, household],
+                Favorite::from_row,
+            )
+            .optional()?;
+        Ok(favorite)
+    }
+
+    fn favorite_by_label(&self, user_id: i64, label: &str) -> Result<Option<i64>> {
+        let id = self
+            .connection
+            .query_row(
+                "SELECT id FROM Favorite WHERE userId = ?1 AND label = ?2 COLLATE NOCASE",
+                params![user_id, label],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// Removes the favorite of the user with this label, ignoring case.
+    /// Returns whether there was one.
+    pub fn delete_favorite(&self, user_id: i64, label: &str) -> Result<bool> {
+        self.check_writable()?;
+        let deleted = self.connection.execute(
+            "DELETE FROM Favorite WHERE userId = ?1 AND label = ?2 COLLATE NOCASE",
+            params![user_id, label],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::AmountError;
+
+    fn coffee() -> NewFavorite {
+        NewFavorite {
+            user_id: 1001,
+            label: "morning coffee".to_string(),
+            amount: 2.5,
+            account_id: 1,
+            category_id: 1,
+            comment: Some("morning coffee".to_string()),
+        }
+    }
+
+    #[test]
+    fn add_list_and_delete() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let id = model.add_favorite(1, &coffee()).unwrap();
+        model
+            .add_favorite(
+                1,
+                &NewFavorite {
+                    label: "Bus".to_string(),
+                    amount: 1.25,
+                    account_id: 3,
+                    category_id: 2,
+                    comment: None,
+                    ..coffee()
+                },
+            )
+            .unwrap();
+
+        let favorite = model.get_favorite(1, id).unwrap().unwrap();
+        assert_eq!(
+            (2.5, "EUR", "Alex Savings", "Groceries"),
+            (
+                favorite.amount,
+                favorite.currency.as_str(),
+                favorite.account.name.as_str(),
+                favorite.category.name.as_str()
+            )
+        );
+        assert_eq!(None, favorite.blocked_reason());
+
+        let labels = |user| -> Vec<String> {
+            model
+                .favorites(user)
+                .unwrap()
+                .into_iter()
+                .map(|f| f.label)
+                .collect()
+        };
+        assert_eq!(vec!["Bus", "morning coffee"], labels(1001));
+        assert!(labels(1002).is_empty());
+
+        assert!(!model.delete_favorite(1002, "bus").unwrap());
+        assert!(model.delete_favorite(1001, "bus").unwrap());
+        assert_eq!(vec!["morning coffee"], labels(1001));
+    }
+
+    #[test]
+    fn rejects_duplicates_and_bad_amounts() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.add_favorite(1, &coffee()).unwrap();
+
+        let duplicate = NewFavorite {
+            label: "Morning Coffee".to_string(),
+            ..coffee()
+        };
+        assert!(matches!(
+            model.add_favorite(1, &duplicate),
+            Err(Error::Refused(_))
+        ));
+        // Someone else may use the same label.
+        model
+            .add_favorite(
+                1,
+                &NewFavorite {
+                    user_id: 1002,
+                    ..coffee()
+                },
+            )
+            .unwrap();
+
+        let fractional = NewFavorite {
+            label: "gum".to_string(),
+            amount: 0.125,
+            ..coffee()
+        };
+        assert!(matches!(
+            model.add_favorite(1, &fractional),
+            Err(Error::InvalidAmount(AmountError::TooPrecise(2)))
+        ));
+    }
+
+    #[test]
+    fn archived_category_blocks_the_favorite() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let id = model.add_favorite(1, &coffee()).unwrap();
+        model
+            .connection
+            .execute("UPDATE ExpenseCategory SET active = 0 WHERE id = 1", [])
+            .unwrap();
+
+        assert_eq!(
+            Some("The category Groceries is archived.".to_string()),
+            model.get_favorite(1, id).unwrap().unwrap().blocked_reason()
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Where the month is heading at the pace of its spend so far.
+
+use super::{Calendar, Period};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+/// The spend of the whole local month of `today`, as `calendar` has its
+/// months, if it goes on at the pace of `mtd`, the spend so far in minor
+/// units. Today counts as a full day. On the first day of the month there is
+/// too little to go by, so the forecast is the spend so far rather than 30
+/// times the first purchase.
+pub fn forecast(mtd: i64, today: DateTime<Utc>, tz: Tz, calendar: Calendar) -> i64 {
+    let month = Period::ThisMonth.resolve(today, tz, calendar);
+    let elapsed = i128::from((today.with_timezone(&tz).date_naive() - month.start).num_days() + 1);
+    let days = (month.end - month.start).num_days() as i128;
+    if elapsed <= 1 {
+        return mtd;
+    }
+    // Rounded half away from zero, like amounts typed in.
+    let scaled = i128::from(mtd) * days;
+    let forecast = (scaled + scaled.signum() * elapsed / 2) / elapsed;
+    forecast.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    /// In calendar months.
+    fn forecast(mtd: i64, today: DateTime<Utc>, tz: Tz) -> i64 {
+        super::forecast(mtd, today, tz, Calendar::default())
+    }
+
+    #[test]
+    fn custom_months_count_their_own_days() {
+        let salary = Calendar {
+            week_start: chrono::Weekday::Mon,
+            month_start: 25,
+        };
+        // Day 15 of the 30 from November 25th.
+        assert_eq!(
+            20_000,
+            super::forecast(10_000, at("2023-12-09T12:00:00Z"), Tz::UTC, salary)
+        );
+        // The 25th is its first day.
+        assert_eq!(
+            5_000,
+            super::forecast(5_000, at("2023-12-25T12:00:00Z"), Tz::UTC, salary)
+        );
+    }
+
+    #[test]
+    fn scales_by_the_days_left() {
+        // Halfway through a 30 day month.
+        assert_eq!(
+            20_000,
+            forecast(10_000, at("2023-11-15T12:00:00Z"), Tz::UTC)
+        );
+        // 100 over 10 of 31 days, 310 rounded.
+        assert_eq!(310, forecast(100, at("2023-12-10T00:00:00Z"), Tz::UTC));
+        // 1000 over 3 of 31 days is 10333.3.
+        assert_eq!(10_333, forecast(1_000, at("2023-12-03T08:00:00Z"), Tz::UTC));
+        // The last day of the month changes nothing.
+        assert_eq!(4_567, forecast(4_567, at("2023-12-31T23:59:00Z"), Tz::UTC));
+        assert_eq!(0, forecast(0, at("2023-12-20T00:00:00Z"), Tz::UTC));
+        // Refunds round the same way as spend.
+        assert_eq!(-310, forecast(-100, at("2023-12-10T00:00:00Z"), Tz::UTC));
+    }
+
+    #[test]
+    fn first_day_is_not_extrapolated() {
+        assert_eq!(5_000, forecast(5_000, at("2023-12-01T20:00:00Z"), Tz::UTC));
+        // Still November 30th in New York: 30 of 30 days.
+        assert_eq!(
+            5_000,
+            forecast(5_000, at("2023-12-01T02:00:00Z"), Tz::America__New_York)
+        );
+        // Already the 2nd in Tokyo: 2 of 31 days.
+        assert_eq!(
+            77_500,
+            forecast(5_000, at("2023-12-01T20:00:00Z"), Tz::Asia__Tokyo)
+        );
+    }
+
+    #[test]
+    fn february_lengths() {
+        // 14 of 28 days, then 14 of 29 days in a leap year.
+        assert_eq!(2_000, forecast(1_000, at("2023-02-14T12:00:00Z"), Tz::UTC));
+        assert_eq!(2_071, forecast(1_000, at("2024-02-14T12:00:00Z"), Tz::UTC));
+        assert_eq!(1_000, forecast(1_000, at("2023-02-28T12:00:00Z"), Tz::UTC));
+        assert_eq!(1_000, forecast(1_000, at("2024-02-29T12:00:00Z"), Tz::UTC));
+    }
+
+    #[test]
+    fn huge_amounts_saturate() {
+        assert_eq!(
+            i64::MAX,
+            forecast(i64::MAX, at("2023-12-02T00:00:00Z"), Tz::UTC)
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! `/fun`: light-hearted records of a local month, like who logged the most
+//! and the longest run of days with spending.
+
+use super::amount::from_minor;
+use super::year::{expenses, starts, BOUNDS};
+use super::{Error, Model, Result};
+use crate::time::{self, DbTime};
+use chrono::{DateTime, Days, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use rusqlite::{params, OptionalExtension};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunStats {
+    /// The first day of the month.
+    pub month: NaiveDate,
+    /// Who logged the most expenses, and how many.
+    pub top_logger: Option<(String, i64)>,
+    /// The largest expense of each currency, by currency name.
+    pub largest: Vec<LargestExpense>,
+    /// The category with the most expenses, and how many.
+    pub top_category: Option<(String, i64)>,
+    pub streak: Option<Streak>,
+    /// The expenses logged earliest and latest in their local day.
+    pub earliest: Option<TimedExpense>,
+    pub latest: Option<TimedExpense>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargestExpense {
+    pub currency: String,
+    pub exponent: u32,
+    pub amount: f64,
+    pub user: String,
+    pub comment: Option<String>,
+}
+
+/// Consecutive local days with at least one expense, within the month only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Streak {
+    pub first: NaiveDate,
+    pub days: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedExpense {
+    pub timestamp: DateTime<Utc>,
+    pub user: String,
+    pub category: String,
+}
+
+/// Display name of the expense's user, the id if the user is gone.
+const USER_NAME: &str = "COALESCE(u.displayName, CAST(e.userId AS TEXT))";
+
+impl Model {
+    /// The records of the household in the local month starting on
+    /// `year_month`, its first day.
+    pub fn fun_stats(&self, household: i64, year_month: NaiveDate, tz: Tz) -> Result<FunStats> {
+        let next = year_month
+            .checked_add_months(Months::new(1))
+            .ok_or_else(|| Error::NotFound(format!("month {}", year_month)))?;
+        let (start, end) = (
+            DbTime(time::start_of_day(year_month, tz)),
+            DbTime(time::start_of_day(next, tz)),
+        );
+        let tables = self.expense_tables(Some(start.0))?;
+        let in_month = "WHERE e.timestamp >= ?1 AND e.timestamp < ?2";
+
+        let top_logger = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT {user}, COUNT(*) {expenses}
+                     LEFT JOIN User u ON u.telegramId = e.userId
+                     {in_month}
+                     GROUP BY e.userId
+                     ORDER BY COUNT(*) DESC, {user}
+                     LIMIT 1",
+                    user = USER_NAME,
+                    expenses = expenses(tables, 3),
+                    in_month = in_month
+                ),
+                params![start, end, household],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if top_logger.is_none() {
+            return Ok(FunStats {
+                month: year_month,
+                top_logger,
+                largest: Vec::new(),
+                top_category: None,
+                streak: None,
+                earliest: None,
+                latest: None,
+            });
+        }
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT currency, exponent, amountMinor, user, comments FROM (
+                 SELECT c.name AS currency, c.exponent, e.amountMinor, {user} AS user,
+                        e.comments,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY c.id ORDER BY e.amountMinor DESC, e.timestamp, e.id
+                        ) AS n
+                 {expenses}
+                 LEFT JOIN User u ON u.telegramId = e.userId
+                 {in_month}
+             )
+             WHERE n = 1
+             ORDER BY currency",
+            user = USER_NAME,
+            expenses = expenses(tables, 3),
+            in_month = in_month
+        ))?;
+        let largest = stmt
+            .query_map(params![start, end, household], |row| {
+                let exponent = row.get(1)?;
+                Ok(LargestExpense {
+                    currency: row.get(0)?,
+                    exponent,
+                    amount: from_minor(row.get(2)?, exponent),
+                    user: row.get(3)?,
+                    comment: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let top_category = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT ec.name, COUNT(*) {}
+                     JOIN ExpenseCategory ec ON ec.id = e.categoryId
+                     {}
+                     GROUP BY ec.id
+                     ORDER BY COUNT(*) DESC, ec.name
+                     LIMIT 1",
+                    expenses(tables, 3),
+                    in_month
+                ),
+                params![start, end, household],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        let day_starts = starts(year_month.iter_days().take_while(|d| *d <= next), tz);
+        // Day numbers minus their rank are the same along a run of
+        // consecutive days. Only the month's days are in `bounds`, so runs
+        // reaching into the months around are cut at its ends.
+        let streak = self
+            .connection
+            .query_row(
+                &format!(
+                    "{}, days AS (
+                         SELECT DISTINCT b.n {}
+                         JOIN bounds b ON e.timestamp >= b.start AND e.timestamp < b.end
+                     )
+                     SELECT MIN(n), COUNT(*) FROM (
+                         SELECT n, n - ROW_NUMBER() OVER (ORDER BY n) AS run FROM days
+                     )
+                     GROUP BY run
+                     ORDER BY COUNT(*) DESC, MIN(n)
+                     LIMIT 1",
+                    BOUNDS,
+                    expenses(tables, 2)
+                ),
+                params![day_starts, household],
+                |row| Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?)),
+            )
+            .optional()?
+            .map(|(first, days)| Streak {
+                first: year_month + Days::new(first),
+                days,
+            });
+
+        // Seconds since the start of the local day, which is what the
+        // clock says but for days with a DST change.
+        let timed = |order: &str| {
+            self.connection
+                .query_row(
+                    &format!(
+                        "{} SELECT e.timestamp, {}, ec.name {}
+                         JOIN bounds b ON e.timestamp >= b.start AND e.timestamp < b.end
+                         LEFT JOIN User u ON u.telegramId = e.userId
+                         JOIN ExpenseCategory ec ON ec.id = e.categoryId
+                         ORDER BY e.timestamp - b.start {}, e.timestamp, e.id
+                         LIMIT 1",
+                        BOUNDS,
+                        USER_NAME,
+                        expenses(tables, 2),
+                        order
+                    ),
+                    params![day_starts, household],
+                    |row| {
+                        Ok(TimedExpense {
+                            timestamp: row.get::<_, DbTime>(0)?.0,
+                            user: row.get(1)?,
+                            category: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()
+        };
+        let earliest = timed("ASC")?;
+        let latest = timed("DESC")?;
+
+        Ok(FunStats {
+            month: year_month,
+            top_logger,
+            largest,
+            top_category,
+            streak,
+            earliest,
+            latest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::TimeDelta;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Logs an expense of Alex `minutes` after midnight UTC of each of `days`.
+    fn log_at(model: &Model, days: &[NaiveDate], minutes: i64) {
+        for d in days {
+            model
+                .insert_expense(&NewExpense {
+                    account_id: 1,
+                    category_id: 1,
+                    user_id: 1001,
+                    timestamp: time::start_of_day(*d, Tz::UTC) + TimeDelta::minutes(minutes),
+                    amount: 1.0,
+                    comment: None,
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+    }
+
+    fn log_on(model: &Model, days: &[NaiveDate], hour: i64) {
+        log_at(model, days, hour * 60);
+    }
+
+    fn streak(model: &Model, month: NaiveDate, tz: Tz) -> Option<(NaiveDate, u64)> {
+        let stats = model.fun_stats(1, month, tz).unwrap();
+        stats.streak.map(|s| (s.first, s.days))
+    }
+
+    #[test]
+    fn records_of_a_month() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+
+        let stats = model.fun_stats(1, day(2023, 12, 1), Tz::UTC).unwrap();
+        // Alex and Hanna both logged two, names break the tie.
+        assert_eq!(Some(("Alex".to_string(), 2)), stats.top_logger);
+        assert_eq!(Some(("Entertainment".to_string(), 1)), stats.top_category);
+        let largest: Vec<(&str, f64, &str)> = stats
+            .largest
+            .iter()
+            .map(|l| (l.currency.as_str(), l.amount, l.user.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("BYN", 30.0, "Alex"),
+                ("EUR", 50.75, "Alex"),
+                ("USD", 100.0, "Hanna")
+            ],
+            largest
+        );
+        assert_eq!(
+            Some("Paid electricity bill"),
+            stats.largest[2].comment.as_deref()
+        );
+        assert_eq!(
+            Some(Streak {
+                first: day(2023, 12, 1),
+                days: 4
+            }),
+            stats.streak
+        );
+        let earliest = stats.earliest.unwrap();
+        assert_eq!(
+            (1701424800, "Alex", "Groceries"),
+            (
+                earliest.timestamp.timestamp(),
+                earliest.user.as_str(),
+                earliest.category.as_str()
+            )
+        );
+        let latest = stats.latest.unwrap();
+        assert_eq!(
+            (1701720000, "Hanna", "Utilities"),
+            (
+                latest.timestamp.timestamp(),
+                latest.user.as_str(),
+                latest.category.as_str()
+            )
+        );
+
+        // Nora's seeds are another household's.
+        assert_eq!(
+            1,
+            model
+                .fun_stats(2, day(2023, 12, 1), Tz::UTC)
+                .unwrap()
+                .largest
+                .len()
+        );
+    }
+
+    #[test]
+    fn months_without_expenses_have_no_records() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(
+            FunStats {
+                month: day(2023, 11, 1),
+                top_logger: None,
+                largest: Vec::new(),
+                top_category: None,
+                streak: None,
+                earliest: None,
+                latest: None,
+            },
+            model.fun_stats(1, day(2023, 11, 1), Tz::UTC).unwrap()
+        );
+    }
+
+    #[test]
+    fn streaks_are_cut_at_the_month_ends() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // January 29th to February 3rd, twice on the 30th.
+        let run: Vec<NaiveDate> = day(2024, 1, 29).iter_days().take(6).collect();
+        log_on(&model, &run, 12);
+        log_on(&model, &[day(2024, 1, 30)], 13);
+        assert_eq!(
+            Some((day(2024, 1, 29), 3)),
+            streak(&model, day(2024, 1, 1), Tz::UTC)
+        );
+        assert_eq!(
+            Some((day(2024, 2, 1), 3)),
+            streak(&model, day(2024, 2, 1), Tz::UTC)
+        );
+
+        // A longer run later in February wins, one as long doesn't.
+        log_on(&model, &[10, 11, 12].map(|d| day(2024, 2, d)), 12);
+        assert_eq!(
+            Some((day(2024, 2, 1), 3)),
+            streak(&model, day(2024, 2, 1), Tz::UTC)
+        );
+        log_on(&model, &[day(2024, 2, 13)], 12);
+        assert_eq!(
+            Some((day(2024, 2, 10), 4)),
+            streak(&model, day(2024, 2, 1), Tz::UTC)
+        );
+
+        // A run reaching the last day of a leap February.
+        log_on(&model, &[27, 28, 29].map(|d| day(2024, 2, d)), 12);
+        log_on(&model, &[day(2024, 3, 1)], 12);
+        assert_eq!(
+            Some((day(2024, 3, 1), 1)),
+            streak(&model, day(2024, 3, 1), Tz::UTC)
+        );
+    }
+
+    #[test]
+    fn streaks_follow_local_days() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // 23:30 UTC on the 5th is already the 6th in Berlin.
+        log_on(&model, &[day(2024, 5, 4)], 12);
+        log_at(&model, &[day(2024, 5, 5)], 23 * 60 + 30);
+        assert_eq!(
+            Some((day(2024, 5, 4), 2)),
+            streak(&model, day(2024, 5, 1), Tz::UTC)
+        );
+        assert_eq!(
+            Some((day(2024, 5, 4), 1)),
+            streak(&model, day(2024, 5, 1), Tz::Europe__Berlin)
+        );
+
+        // The latest in the day by the local clock.
+        let stats = model
+            .fun_stats(1, day(2024, 5, 1), Tz::Europe__Berlin)
+            .unwrap();
+        assert_eq!(
+            day(2024, 5, 4),
+            stats.latest.unwrap().timestamp.date_naive()
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Households sharing one bot. Users, accounts and categories belong to
+//! exactly one, and everything read or changed is scoped to the household of
+//! the user asking, so that two families never see each other's data.
+
+use super::lengths::{check_length, MAX_NAME_LEN};
+use super::period::{Calendar, MAX_MONTH_START};
+use super::{Error, Model, Result, Role};
+use chrono::Weekday;
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// The household everything belonged to before there were several. Its
+/// admins look after what all households share: currencies, rates and the
+/// database itself.
+pub const DEFAULT_HOUSEHOLD: i64 = 1;
+
+impl Model {
+    pub fn household_name(&self, household: i64) -> Result<String> {
+        self.connection
+            .query_row(
+                "SELECT name FROM Household WHERE id = ?1",
+                [household],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("household {}", household)))
+    }
+
+    /// Looks a household up by name, ignoring case.
+    pub fn find_household(&self, name: &str) -> Result<Option<i64>> {
+        let id = self
+            .connection
+            .query_row(
+                "SELECT id FROM Household WHERE name = ?1",
+                [name.trim()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// Creates a household with `admin_id` as its admin. The admin must not
+    /// belong to a household yet.
+    pub fn create_household(&self, name: &str, admin_id: i64) -> Result<i64> {
+        self.check_writable()?;
+        let name = name.trim();
+        check_length("Household name", name, MAX_NAME_LEN)?;
+        if self.find_household(name)?.is_some() {
+            return Err(Error::Refused(format!(
+                "There already is a household called '{}'.",
+                name
+            )));
+        }
+        if self.get_user(admin_id)?.is_some() {
+            return Err(Error::Refused(format!(
+                "User {} belongs to a household already.",
+                admin_id
+            )));
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute("INSERT INTO Household (name) VALUES (?1)", [name])?;
+        let household = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO User (telegramId, telegramName, displayName, role, householdId)
+             VALUES (?1, '', ?1, ?2, ?3)",
+            params![admin_id, Role::Admin, household],
+        )?;
+        tx.commit()?;
+        info!("Created household {} with admin {}", name, admin_id);
+        Ok(household)
+    }
+
+    /// The household the chat is bound to, `None` if it isn't.
+    pub fn chat_household(&self, chat_id: i64) -> Result<Option<i64>> {
+        let household = self
+            .connection
+            .query_row(
+                "SELECT householdId FROM ChatSettings WHERE chatId = ?1",
+                [chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(household)
+    }
+
+    /// Binds the chat to a household. A chat bound to another household
+    /// stays with it.
+    pub fn bind_chat(&self, chat_id: i64, household: i64) -> Result<()> {
+        self.check_writable()?;
+        let bound = self.connection.execute(
+            "INSERT INTO ChatSettings (chatId, householdId) VALUES (?1, ?2)
+             ON CONFLICT (chatId) DO UPDATE SET householdId = excluded.householdId
+             WHERE householdId = excluded.householdId",
+            params![chat_id, household],
+        )?;
+        if bound == 0 {
+            return Err(Error::Refused(
+                "This chat belongs to another household.".to_string(),
+            ));
+        }
+        info!("Bound chat {} to household {}", chat_id, household);
+        Ok(())
+    }
+
+    /// Where the household's weeks and months start.
+    pub fn calendar(&self, household: i64) -> Result<Calendar> {
+        let (week_start, month_start): (u8, u32) = self
+            .connection
+            .query_row(
+                "SELECT weekStart, monthStart FROM Household WHERE id = ?1",
+                [household],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("household {}", household)))?;
+        Ok(Calendar {
+            week_start: Weekday::try_from(week_start).unwrap_or(Weekday::Mon),
+            month_start,
+        })
+    }
+
+    pub fn set_calendar(&self, household: i64, calendar: Calendar) -> Result<()> {
+        self.check_writable()?;
+        if !(1..=MAX_MONTH_START).contains(&calendar.month_start) {
+            return Err(Error::Refused(format!(
+                "Months can start on a day from 1 to {}, which every month has.",
+                MAX_MONTH_START
+            )));
+        }
+        self.connection.execute(
+            "UPDATE Household SET weekStart = ?2, monthStart = ?3 WHERE id = ?1",
+            params![
+                household,
+                calendar.week_start.num_days_from_monday(),
+                calendar.month_start
+            ],
+        )?;
+        info!(
+            "Set the calendar of household {} to {:?}",
+            household, calendar
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{DateRange, ExpenseSource, NewExpense};
+    use chrono::{DateTime, NaiveDate, Utc};
+    use chrono_tz::Tz;
+
+    const NEIGHBOURS: i64 = 2;
+
+    fn december() -> DateRange {
+        DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        )
+    }
+
+    fn two_households() -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        model
+    }
+
+    #[test]
+    fn existing_data_is_in_the_default_household() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!("default", model.household_name(DEFAULT_HOUSEHOLD).unwrap());
+        assert_eq!(
+            DEFAULT_HOUSEHOLD,
+            model.get_user(1001).unwrap().unwrap().household
+        );
+        assert_eq!(3, model.accounts(DEFAULT_HOUSEHOLD).unwrap().len());
+    }
+
+    #[test]
+    fn reads_never_cross_households() {
+        let model = two_households();
+
+        let names = |h| -> Vec<String> {
+            model
+                .accounts(h)
+                .unwrap()
+                .into_iter()
+                .map(|a| a.name)
+                .collect()
+        };
+        assert_eq!(vec!["Alex Savings", "Hanna Daily", "Family BYN"], names(1));
+        assert_eq!(vec!["Neighbours Cash"], names(NEIGHBOURS));
+        let categories: Vec<String> = model
+            .categories(NEIGHBOURS)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(vec!["Garden"], categories);
+        assert!(model.get_account(NEIGHBOURS, 1).unwrap().is_none());
+        assert!(model.get_category(1, 5).unwrap().is_none());
+        assert!(model.get_expense_details(NEIGHBOURS, 1).unwrap().is_none());
+        assert!(matches!(
+            model.resolve_category(1, "garden").unwrap(),
+            crate::model::Resolution::NotFound(_)
+        ));
+
+        let theirs = model.summarize(NEIGHBOURS, december(), Tz::UTC).unwrap();
+        assert_eq!(vec![("EUR".to_string(), 12.0)], theirs.currency_totals());
+        let ours = model.summarize(1, december(), Tz::UTC).unwrap();
+        assert_eq!(4, ours.categories.len());
+
+        let balances = model.balances(NEIGHBOURS, "EUR").unwrap();
+        assert_eq!(1, balances.accounts.len());
+        assert_eq!(88.0, balances.currencies[0].subtotal);
+        assert_eq!(
+            1,
+            model
+                .year_summary(NEIGHBOURS, 2023, Tz::UTC)
+                .unwrap()
+                .currencies
+                .len()
+        );
+
+        let mut exported = Vec::new();
+        model
+            .export_expenses(NEIGHBOURS, december(), Tz::UTC, |row| -> Result<()> {
+                exported.push(row.id);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(vec![5], exported);
+
+        let now: DateTime<Utc> = DateTime::from_timestamp(1_702_000_000, 0).unwrap();
+        let mtd = model
+            .month_to_date(NEIGHBOURS, "USD", None, now, Tz::UTC)
+            .unwrap();
+        assert_eq!(0, mtd.count);
+    }
+
+    #[test]
+    fn writes_never_cross_households() {
+        let model = two_households();
+
+        let garden_on_ours = NewExpense {
+            account_id: 1,
+            category_id: 5,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+            amount: 1.0,
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        assert!(matches!(
+            model.insert_expense_once(1, "k1", &garden_on_ours),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.insert_expense_once(NEIGHBOURS, "k2", &garden_on_ours),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.update_expense(
+                1,
+                1001,
+                5,
+                &NewExpense {
+                    category_id: 1,
+                    ..garden_on_ours
+                }
+            ),
+            Err(Error::NotFound(_))
+        ));
+
+        model.delete_expense(1, 5).unwrap();
+        assert!(model.get_expense_details(NEIGHBOURS, 5).unwrap().is_some());
+        assert!(matches!(
+            model.set_require_comment(1, 5, true),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.merge_categories(1, 1001, (5, 1), false),
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(0, model.count_in_category(1, 5, None, Tz::UTC).unwrap());
+        assert_eq!(
+            0,
+            model
+                .bulk_recategorize(1, 1001, (5, 1), None, Tz::UTC, false)
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            model
+                .count_in_category(NEIGHBOURS, 5, None, Tz::UTC)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn admins_only_manage_their_own_users() {
+        let model = two_households();
+
+        assert!(matches!(
+            model.set_role(1, 2001, Role::Viewer),
+            Err(Error::Refused(_))
+        ));
+        assert_eq!(Role::Admin, model.get_user(2001).unwrap().unwrap().role);
+        model.set_role(NEIGHBOURS, 2002, Role::Member).unwrap();
+        assert_eq!(NEIGHBOURS, model.get_user(2002).unwrap().unwrap().household);
+        assert!(matches!(
+            model.set_role(NEIGHBOURS, 1001, Role::Admin),
+            Err(Error::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn calendars_are_per_household() {
+        let model = two_households();
+        assert_eq!(Calendar::default(), model.calendar(1).unwrap());
+        let salary = Calendar {
+            week_start: Weekday::Sun,
+            month_start: 25,
+        };
+        model.set_calendar(NEIGHBOURS, salary).unwrap();
+        assert_eq!(salary, model.calendar(NEIGHBOURS).unwrap());
+        assert_eq!(Calendar::default(), model.calendar(1).unwrap());
+
+        for month_start in [0, 29] {
+            let calendar = Calendar {
+                month_start,
+                ..salary
+            };
+            assert!(matches!(
+                model.set_calendar(1, calendar),
+                Err(Error::Refused(_))
+            ));
+        }
+        assert!(matches!(model.calendar(99), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn creates_households_with_a_fresh_admin() {
+        let model = two_households();
+        assert!(matches!(
+            model.create_household("NEIGHBOURS", 3001),
+            Err(Error::Refused(_))
+        ));
+        assert!(matches!(
+            model.create_household("Others", 1001),
+            Err(Error::Refused(_))
+        ));
+
+        let others = model.create_household(" Others ", 3001).unwrap();
+        assert_eq!(Some(others), model.find_household("others").unwrap());
+        let admin = model.get_user(3001).unwrap().unwrap();
+        assert_eq!((others, Role::Admin), (admin.household, admin.role));
+        assert!(model.accounts(others).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chats_stay_with_their_household() {
+        let model = two_households();
+        assert_eq!(None, model.chat_household(-100).unwrap());
+
+        model.bind_chat(-100, NEIGHBOURS).unwrap();
+        model.bind_chat(-100, NEIGHBOURS).unwrap();
+        assert!(matches!(model.bind_chat(-100, 1), Err(Error::Refused(_))));
+        assert_eq!(Some(NEIGHBOURS), model.chat_household(-100).unwrap());
+
+        model.set_feed_message(-200, 1, Some(7)).unwrap();
+        assert_eq!(Some(1), model.chat_household(-200).unwrap());
+    }
+}
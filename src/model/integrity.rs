@@ -0,0 +1,196 @@
+//! Checks that the database file is sound, e.g. after the bot was killed
+//! in the middle of a write.
+
+use super::{Model, Result};
+use std::fmt;
+
+/// Tables whose household ids are foreign keys only since V46, which left
+/// the ids of missing households NULL. Foreign key checks pass NULL.
+const HOUSEHOLD_TABLES: &[&str] = &["User", "Account", "ExpenseCategory", "ChatSettings"];
+
+/// A row referring to a row that doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orphan {
+    pub table: String,
+    pub rowid: Option<i64>,
+    /// The column holding the missing reference.
+    pub column: String,
+    pub parent: String,
+}
+
+impl fmt::Display for Orphan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rowid {
+            Some(rowid) => write!(f, "{} {}", self.table, rowid)?,
+            None => write!(f, "{}", self.table)?,
+        }
+        write!(f, ": {} refers to a missing {}", self.column, self.parent)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// What `PRAGMA integrity_check` found, empty for a sound file.
+    pub problems: Vec<String>,
+    pub orphans: Vec<Orphan>,
+    /// Every table with its number of rows, by name.
+    pub row_counts: Vec<(String, i64)>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty() && self.orphans.is_empty()
+    }
+}
+
+impl Model {
+    /// The full check. It reads the whole file, so it is only run on request.
+    pub fn check_integrity(&self) -> Result<IntegrityReport> {
+        Ok(IntegrityReport {
+            problems: self.pragma_check("integrity_check")?,
+            orphans: self.orphans()?,
+            row_counts: self.row_counts()?,
+        })
+    }
+
+    /// The quick checks done on startup, one line per problem. Anything
+    /// found means the file shouldn't be written to as it is.
+    pub fn startup_problems(&self) -> Result<Vec<String>> {
+        let mut problems = self.pragma_check("quick_check")?;
+        problems.extend(self.orphans()?.iter().map(Orphan::to_string));
+        Ok(problems)
+    }
+
+    /// The lines printed by a checking pragma, except its "ok".
+    fn pragma_check(&self, pragma: &str) -> Result<Vec<String>> {
+        let mut stmt = self.connection.prepare(&format!("PRAGMA {}", pragma))?;
+        let lines = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(lines.into_iter().filter(|line| line != "ok").collect())
+    }
+
+    fn orphans(&self) -> Result<Vec<Orphan>> {
+        let mut stmt = self.connection.prepare(
+            r#"SELECT c."table", c.rowid, f."from", c.parent
+               FROM pragma_foreign_key_check() c
+               JOIN pragma_foreign_key_list(c."table") f ON f.id = c.fkid AND f.seq = 0
+               ORDER BY c."table", c.rowid"#,
+        )?;
+        let mut orphans = stmt
+            .query_map([], |row| {
+                Ok(Orphan {
+                    table: row.get(0)?,
+                    rowid: row.get(1)?,
+                    column: row.get(2)?,
+                    parent: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for table in HOUSEHOLD_TABLES {
+            let mut stmt = self.connection.prepare(&format!(
+                "SELECT rowid FROM \"{}\" WHERE householdId IS NULL ORDER BY rowid",
+                table
+            ))?;
+            let rows = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<i64>>>()?;
+            orphans.extend(rows.into_iter().map(|rowid| Orphan {
+                table: table.to_string(),
+                rowid: Some(rowid),
+                column: "householdId".to_string(),
+                parent: "Household".to_string(),
+            }));
+        }
+        orphans.sort_by(|a, b| (&a.table, a.rowid).cmp(&(&b.table, b.rowid)));
+        Ok(orphans)
+    }
+
+    fn row_counts(&self) -> Result<Vec<(String, i64)>> {
+        let tables = self
+            .connection
+            .prepare(
+                "SELECT name FROM sqlite_master
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+                 ORDER BY name",
+            )?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        tables
+            .into_iter()
+            .map(|table| {
+                let count = self.connection.query_row(
+                    &format!("SELECT COUNT(*) FROM \"{}\"", table),
+                    [],
+                    |row| row.get(0),
+                )?;
+                Ok((table, count))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sound_database() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let report = model.check_integrity().unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(report
+            .row_counts
+            .iter()
+            .any(|(table, count)| table == "Expense" && *count > 0));
+        assert_eq!(Vec::<String>::new(), model.startup_problems().unwrap());
+    }
+
+    #[test]
+    fn finds_broken_references() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .connection
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 UPDATE Expense SET categoryId = 99 WHERE id = 2;
+                 PRAGMA foreign_keys = ON;",
+            )
+            .unwrap();
+
+        let report = model.check_integrity().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(Vec::<String>::new(), report.problems);
+        let orphan = Orphan {
+            table: "Expense".to_string(),
+            rowid: Some(2),
+            column: "categoryId".to_string(),
+            parent: "ExpenseCategory".to_string(),
+        };
+        assert_eq!(vec![orphan.clone()], report.orphans);
+        assert_eq!(
+            vec!["Expense 2: categoryId refers to a missing ExpenseCategory".to_string()],
+            model.startup_problems().unwrap()
+        );
+    }
+
+    #[test]
+    fn finds_rows_without_a_household() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+            .connection
+            .execute_batch(
+                "DROP TRIGGER AccountUpdateHousehold;
+                 UPDATE Account SET householdId = NULL WHERE id = 3;",
+            )
+            .unwrap();
+        assert_eq!(
+            vec!["Account 3: householdId refers to a missing Household".to_string()],
+            model.startup_problems().unwrap()
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Longest texts the database takes. Triggers of the schema refuse longer
+//! ones as well, these checks come first so that the user is told how long
+//! their text was instead of seeing a constraint error.
+
+use super::{Error, Result};
+
+/// Longest comment of an expense or a favorite, in characters.
+pub const MAX_COMMENT_LEN: usize = 1000;
+
+/// Longest name of an account, category, household or user and label of a
+/// favorite, in characters.
+pub const MAX_NAME_LEN: usize = 100;
+
+/// Refuses `text` if it is longer than `max` characters. `what` names it in
+/// the message, like "Comment".
+pub fn check_length(what: &'static str, text: &str, max: usize) -> Result<()> {
+    // Characters are counted the way SQLite's length() does.
+    let length = text.chars().count();
+    if length > max {
+        return Err(Error::TooLong { what, length, max });
+    }
+    Ok(())
+}
+
+pub fn check_comment(comment: Option<&str>) -> Result<()> {
+    comment.map_or(Ok(()), |c| check_length("Comment", c, MAX_COMMENT_LEN))
+}
+
+/// `name` cut to the longest a name may be, for names taken from Telegram
+/// rather than typed. The cut is logged and marked with an ellipsis.
+pub(super) fn shorten_name(name: &str) -> String {
+    if name.chars().count() <= MAX_NAME_LEN {
+        return name.to_string();
+    }
+    log::warn!(
+        "Shortening the name '{}' to {} characters",
+        name,
+        MAX_NAME_LEN
+    );
+    let mut short: String = name.chars().take(MAX_NAME_LEN - 1).collect();
+    short.push('…');
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_inclusive() {
+        assert!(check_comment(Some(&"x".repeat(MAX_COMMENT_LEN))).is_ok());
+        let err = check_comment(Some(&"x".repeat(MAX_COMMENT_LEN + 1))).unwrap_err();
+        assert_eq!("Comment too long (1001/1000 chars).", err.to_string());
+        assert!(check_comment(None).is_ok());
+        // Characters, not bytes.
+        assert!(check_length("Name", &"é".repeat(MAX_NAME_LEN), MAX_NAME_LEN).is_ok());
+    }
+
+    #[test]
+    fn shortens_long_names() {
+        assert_eq!("Alex", shorten_name("Alex"));
+        let exact = "a".repeat(MAX_NAME_LEN);
+        assert_eq!(exact, shorten_name(&exact));
+        let short = shorten_name(&"ж".repeat(MAX_NAME_LEN + 20));
+        assert_eq!(MAX_NAME_LEN, short.chars().count());
+        assert!(short.ends_with('…'));
+    }
+}
@@ -0,0 +1,224 @@
+//! The latest expenses of a household, narrowed down and ordered for
+//! `/last`.
+
+use super::currencies::MAX_EXPONENT;
+use super::expenses::{of_household, ExpenseDetails, SELECT_DETAILS};
+use super::{Model, Result};
+
+/// Which expenses to list. Filters combine, an expense has to pass all of
+/// them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpenseFilter {
+    /// Only this category and its subcategories.
+    pub category_id: Option<i64>,
+    pub account_id: Option<i64>,
+    /// Only expenses this user logged.
+    pub user_id: Option<i64>,
+}
+
+/// The order of a listing, the first shown first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExpenseSort {
+    /// Latest first.
+    #[default]
+    Date,
+    /// Largest first, each in its own currency.
+    Amount,
+}
+
+impl ExpenseSort {
+    pub fn parse(text: &str) -> Option<ExpenseSort> {
+        match text.to_lowercase().as_str() {
+            "date" => Some(ExpenseSort::Date),
+            "amount" => Some(ExpenseSort::Amount),
+            _ => None,
+        }
+    }
+
+    /// The `ORDER BY` of the sort, the only SQL a listing takes from what
+    /// the user typed.
+    fn order_by(self) -> String {
+        match self {
+            ExpenseSort::Date => "e.timestamp DESC, e.id DESC".to_string(),
+            ExpenseSort::Amount => {
+                // Minor units of currencies with more decimal places are
+                // smaller, and SQLite has no pow().
+                let scales: Vec<String> = (0..=MAX_EXPONENT)
+                    .map(|exponent| format!("WHEN {} THEN {}", exponent, 10i64.pow(exponent)))
+                    .collect();
+                format!(
+                    "e.amountMinor * 1.0 / CASE COALESCE(c.exponent, 2) {} END DESC, e.timestamp DESC, e.id DESC",
+                    scales.join(" ")
+                )
+            }
+        }
+    }
+}
+
+impl ExpenseFilter {
+    /// The conditions of the filters set, numbering their parameters from
+    /// `?first`, with the values to bind.
+    fn conditions(&self, first: usize) -> (Vec<String>, Vec<i64>) {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        let mut add = |condition: &str, value| {
+            let n = first + values.len();
+            conditions.push(condition.replace("?n", &format!("?{}", n)));
+            values.push(value);
+        };
+        if let Some(id) = self.category_id {
+            add(
+                "e.categoryId IN (SELECT id FROM ExpenseCategory WHERE id = ?n OR parentId = ?n)",
+                id,
+            );
+        }
+        if let Some(id) = self.account_id {
+            add("e.accountId = ?n", id);
+        }
+        if let Some(id) = self.user_id {
+            add("e.userId = ?n", id);
+        }
+        (conditions, values)
+    }
+}
+
+/// The query listing the household's expenses, with the values of its
+/// parameters: the household is `?1`, the limit `?2`.
+fn listing_query(
+    household: i64,
+    filter: &ExpenseFilter,
+    sort: ExpenseSort,
+    limit: usize,
+) -> (String, Vec<i64>) {
+    let (conditions, values) = filter.conditions(3);
+    let mut sql = format!("{} WHERE e.{}", SELECT_DETAILS, of_household(1));
+    for condition in conditions {
+        sql.push_str(" AND ");
+        sql.push_str(&condition);
+    }
+    sql.push_str(&format!(" ORDER BY {} LIMIT ?2", sort.order_by()));
+    let mut params = vec![household, limit as i64];
+    params.extend(values);
+    (sql, params)
+}
+
+impl Model {
+    /// Up to `limit` expenses of the household passing the filter, in the
+    /// order of `sort`. Archived expenses are not listed.
+    pub fn last_expenses(
+        &self,
+        household: i64,
+        filter: &ExpenseFilter,
+        sort: ExpenseSort,
+        limit: usize,
+    ) -> Result<Vec<ExpenseDetails>> {
+        let (sql, params) = listing_query(household, filter, sort, limit);
+        let mut stmt = self.connection.prepare(&sql)?;
+        let expenses = stmt
+            .query_map(rusqlite::params_from_iter(params), ExpenseDetails::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(expenses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(model: &Model, filter: ExpenseFilter, sort: ExpenseSort, limit: usize) -> Vec<i64> {
+        model
+            .last_expenses(1, &filter, sort, limit)
+            .unwrap()
+            .iter()
+            .map(|e| e.id)
+            .collect()
+    }
+
+    #[test]
+    fn queries_bind_only_the_filters_set() {
+        let (sql, params) = listing_query(1, &ExpenseFilter::default(), ExpenseSort::Date, 10);
+        assert!(sql.ends_with(
+            "WHERE e.accountId IN (SELECT id FROM Account WHERE householdId = ?1) ORDER BY e.timestamp DESC, e.id DESC LIMIT ?2"
+        ));
+        assert_eq!(vec![1, 10], params);
+
+        let filter = ExpenseFilter {
+            account_id: Some(3),
+            user_id: Some(1001),
+            ..ExpenseFilter::default()
+        };
+        let (sql, params) = listing_query(1, &filter, ExpenseSort::Amount, 5);
+        assert!(sql.contains("AND e.accountId = ?3 AND e.userId = ?4 ORDER BY e.amountMinor"));
+        assert!(sql.contains("WHEN 0 THEN 1 WHEN 1 THEN 10 WHEN 2 THEN 100"));
+        assert_eq!(vec![1, 5, 3, 1001], params);
+
+        let filter = ExpenseFilter {
+            category_id: Some(2),
+            ..ExpenseFilter::default()
+        };
+        let (sql, params) = listing_query(1, &filter, ExpenseSort::Date, 5);
+        assert!(sql.contains("WHERE id = ?3 OR parentId = ?3)"));
+        assert_eq!(vec![1, 5, 2], params);
+    }
+
+    #[test]
+    fn filters_combine() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let all = ExpenseFilter::default();
+        assert_eq!(vec![4, 3, 2, 1], ids(&model, all, ExpenseSort::Date, 10));
+        assert_eq!(vec![4, 3], ids(&model, all, ExpenseSort::Date, 2));
+        // 100 USD, 50.75 EUR, 30 BYN, 20 USD.
+        assert_eq!(vec![4, 1, 3, 2], ids(&model, all, ExpenseSort::Amount, 10));
+
+        let hanna = ExpenseFilter {
+            user_id: Some(1002),
+            ..all
+        };
+        assert_eq!(vec![4, 2], ids(&model, hanna, ExpenseSort::Date, 10));
+        let transport = ExpenseFilter {
+            category_id: Some(2),
+            ..hanna
+        };
+        assert_eq!(vec![2], ids(&model, transport, ExpenseSort::Date, 10));
+        let elsewhere = ExpenseFilter {
+            account_id: Some(1),
+            ..transport
+        };
+        assert!(ids(&model, elsewhere, ExpenseSort::Date, 10).is_empty());
+    }
+
+    #[test]
+    fn categories_include_their_subcategories() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_category_parent(1, 2, Some(3)).unwrap();
+        let entertainment = ExpenseFilter {
+            category_id: Some(3),
+            ..ExpenseFilter::default()
+        };
+        assert_eq!(
+            vec![3, 2],
+            ids(&model, entertainment, ExpenseSort::Date, 10)
+        );
+    }
+
+    #[test]
+    fn amounts_compare_across_exponents() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // 30 BYN as 30.000 once the currency has three decimal places.
+        model
+            .connection
+            .execute_batch(
+                "UPDATE Expense SET amountMinor = 30000 WHERE id = 3;
+                 UPDATE Currency SET exponent = 3 WHERE name = 'BYN';",
+            )
+            .unwrap();
+        assert_eq!(
+            vec![4, 1, 3, 2],
+            ids(&model, ExpenseFilter::default(), ExpenseSort::Amount, 10)
+        );
+    }
+}
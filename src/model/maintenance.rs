@@ -0,0 +1,238 @@
+//! Keeping the database file in shape: fresh query planner statistics, and
+//! the pages freed by deletes and archiving given back to the file system.
+
+use super::{audit, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use log::*;
+use rusqlite::OptionalExtension;
+use std::time::{Duration, Instant};
+
+/// Free pages the file may keep before they are vacuumed, of 4 KiB each by
+/// default.
+pub const FREE_PAGES_THRESHOLD: i64 = 256;
+
+/// Pages an incremental vacuum frees at most in one go, so that it doesn't
+/// hold the database for long.
+const VACUUM_PAGES: i64 = 25_000;
+
+/// `PRAGMA auto_vacuum` of a file that frees pages on request.
+const INCREMENTAL: i64 = 2;
+
+/// What checkpointing the write-ahead log did, in pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Whether another connection held it back, leaving pages in the log.
+    pub busy: bool,
+    pub log_pages: i64,
+    /// Those written to the database file.
+    pub checkpointed: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Bytes the database took before and after.
+    pub size_before: u64,
+    pub size_after: u64,
+    /// Pages unused before, whether they were vacuumed or not.
+    pub free_pages: i64,
+    pub vacuumed: bool,
+    /// `None` for a file not in WAL mode, with no log to checkpoint.
+    pub checkpoint: Option<Checkpoint>,
+    pub took: Duration,
+}
+
+impl Model {
+    fn pragma_value(&self, pragma: &str) -> Result<i64> {
+        let value = self
+            .connection
+            .query_row(&format!("PRAGMA {}", pragma), [], |row| row.get(0))?;
+        Ok(value)
+    }
+
+    fn database_size(&self) -> Result<u64> {
+        Ok((self.pragma_value("page_count")? * self.pragma_value("page_size")?) as u64)
+    }
+
+    /// Refreshes the statistics, vacuums once more than
+    /// [`FREE_PAGES_THRESHOLD`] pages are free, and checkpoints the
+    /// write-ahead log, if the file is in WAL mode. The first vacuum of a
+    /// file is a full one that makes it incremental, the later ones free up
+    /// to [`VACUUM_PAGES`] at a time. `user_id` is who
+    /// asked, 0 for the schedule.
+    pub fn maintenance(&self, user_id: i64) -> Result<MaintenanceReport> {
+        self.check_writable()?;
+        let started = Instant::now();
+        let size_before = self.database_size()?;
+        let free_pages = self.pragma_value("freelist_count")?;
+        self.connection.execute_batch("PRAGMA optimize")?;
+        let vacuumed = free_pages > FREE_PAGES_THRESHOLD;
+        if vacuumed {
+            if self.pragma_value("auto_vacuum")? == INCREMENTAL {
+                // Frees a page per step.
+                let mut stmt = self
+                    .connection
+                    .prepare(&format!("PRAGMA incremental_vacuum({})", VACUUM_PAGES))?;
+                let mut rows = stmt.query([])?;
+                while rows.next()?.is_some() {}
+            } else {
+                self.connection
+                    .execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")?;
+            }
+        }
+        // Last, so that the file shrinks by what the vacuum wrote to the log.
+        let checkpoint = self.checkpoint()?;
+        if let Some(checkpoint) = checkpoint.filter(|c| c.busy) {
+            warn!(
+                "Checkpoint held back, {} of {} pages left in the log",
+                checkpoint.log_pages - checkpoint.checkpointed,
+                checkpoint.log_pages
+            );
+        }
+        let report = MaintenanceReport {
+            size_before,
+            size_after: self.database_size()?,
+            free_pages,
+            vacuumed,
+            checkpoint,
+            took: started.elapsed(),
+        };
+        audit::record(
+            &self.connection,
+            user_id,
+            "maintenance",
+            &format!(
+                "{} to {} bytes, {} free pages",
+                report.size_before, report.size_after, report.free_pages
+            ),
+        )?;
+        info!(
+            "Maintenance took {:?}: {} to {} bytes, {} free pages{}",
+            report.took,
+            report.size_before,
+            report.size_after,
+            report.free_pages,
+            if vacuumed { ", vacuumed" } else { "" }
+        );
+        Ok(report)
+    }
+
+    /// Checkpoints the write-ahead log and truncates it, `None` if the file
+    /// isn't in WAL mode.
+    fn checkpoint(&self) -> Result<Option<Checkpoint>> {
+        let mode: String = self
+            .connection
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+        if mode != "wal" {
+            return Ok(None);
+        }
+        let checkpoint =
+            self.connection
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                    Ok(Checkpoint {
+                        busy: row.get(0)?,
+                        log_pages: row.get(1)?,
+                        checkpointed: row.get(2)?,
+                    })
+                })?;
+        Ok(Some(checkpoint))
+    }
+
+    /// When the database was last maintained, by schedule or by hand.
+    pub fn last_maintenance(&self) -> Result<Option<DateTime<Utc>>> {
+        let timestamp = self
+            .connection
+            .query_row(
+                "SELECT MAX(timestamp) FROM AuditLog WHERE action = 'maintenance'",
+                [],
+                |row| row.get::<_, Option<DbTime>>(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(timestamp.map(|t| t.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::restore::tests::TempDir;
+
+    fn file_size(path: &std::path::Path) -> u64 {
+        std::fs::metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn deleted_rows_give_the_space_back() {
+        let dir = TempDir::new("maintenance-vacuum");
+        let path = dir.0.join("spending-tracker.db");
+        let model = Model::open(&path);
+        model.fill_test_data();
+        assert_eq!(None, model.last_maintenance().unwrap());
+        model
+            .connection
+            .execute(
+                "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+                 INSERT INTO Expense (accountId, categoryId, userId, timestamp, amountMinor, comments)
+                 SELECT 1, 1, 1001, 1704200000 + i, 100, hex(randomblob(200)) FROM n",
+                [],
+            )
+            .unwrap();
+        model
+            .connection
+            .execute("DELETE FROM Expense WHERE timestamp >= 1704200000", [])
+            .unwrap();
+        let before = file_size(&path);
+
+        let report = model.maintenance(1001).unwrap();
+        assert!(report.vacuumed);
+        assert!(report.free_pages > FREE_PAGES_THRESHOLD);
+        assert!(report.size_after < report.size_before / 2, "{:?}", report);
+        assert!(file_size(&path) < before / 2);
+        assert_eq!(INCREMENTAL, model.pragma_value("auto_vacuum").unwrap());
+        assert!(model.last_maintenance().unwrap().is_some());
+
+        let checkpoint = report.checkpoint.unwrap();
+        assert!(!checkpoint.busy);
+        assert_eq!(checkpoint.log_pages, checkpoint.checkpointed);
+
+        // Nothing left to free, the next one only refreshes statistics.
+        let report = model.maintenance(0).unwrap();
+        assert!(!report.vacuumed);
+        assert_eq!(report.size_before, report.size_after);
+    }
+
+    #[test]
+    fn incremental_files_free_their_pages() {
+        let dir = TempDir::new("maintenance-incremental");
+        let path = dir.0.join("spending-tracker.db");
+        let model = Model::open(&path);
+        model.fill_test_data();
+        model
+            .connection
+            .execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")
+            .unwrap();
+        model
+            .connection
+            .execute_batch(
+                "CREATE TABLE Filler (data BLOB);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+                 INSERT INTO Filler SELECT randomblob(2000) FROM n;
+                 DROP TABLE Filler;",
+            )
+            .unwrap();
+        assert!(model.pragma_value("freelist_count").unwrap() > FREE_PAGES_THRESHOLD);
+
+        let report = model.maintenance(1001).unwrap();
+        assert!(report.vacuumed);
+        assert_eq!(0, model.pragma_value("freelist_count").unwrap());
+        assert!(report.size_after < report.size_before / 2, "{:?}", report);
+    }
+
+    #[test]
+    fn no_checkpoint_without_a_log() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(None, model.maintenance(1001).unwrap().checkpoint);
+    }
+}
@@ -0,0 +1,236 @@
+//! Per-user subscriptions to the expenses others in the household log,
+//! stored with the settings under `notify` and `notify:<filter>`.
+
+use super::amount::to_minor;
+use super::{ExpenseDetails, Model, Result};
+use rusqlite::params;
+
+const ON: &str = "notify";
+const OVER: &str = "notify:over";
+const CATEGORY: &str = "notify:category";
+
+/// What expenses a user wants to hear about. Filters combine, an expense
+/// has to pass all that are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subscription {
+    /// Only amounts of at least this many minor units of the currency.
+    pub over: Option<(String, i64)>,
+    /// Only this category and its subcategories.
+    pub category_id: Option<i64>,
+}
+
+impl Subscription {
+    /// Whether `expense` passes the filters. `parent_id` is the parent of
+    /// its category, if any. An amount limit never passes other currencies.
+    pub fn matches(&self, expense: &ExpenseDetails, parent_id: Option<i64>) -> bool {
+        let amount = self.over.as_ref().is_none_or(|(currency, minor)| {
+            expense.currency.as_deref() == Some(currency.as_str())
+                && to_minor(expense.amount, expense.exponent) >= *minor
+        });
+        let category = self
+            .category_id
+            .is_none_or(|id| expense.category_id == id || parent_id == Some(id));
+        amount && category
+    }
+
+    /// Reads the subscription from its stored settings, `None` if it is off.
+    fn decode<'a>(settings: impl IntoIterator<Item = (&'a str, &'a str)>) -> Option<Subscription> {
+        let mut on = false;
+        let mut subscription = Subscription::default();
+        for (key, value) in settings {
+            match key {
+                ON => on = true,
+                OVER => {
+                    subscription.over = value.split_once(' ').and_then(|(currency, minor)| {
+                        Some((currency.to_string(), minor.parse().ok()?))
+                    })
+                }
+                CATEGORY => subscription.category_id = value.parse().ok(),
+                _ => log::warn!("Ignoring notification setting {}={}", key, value),
+            }
+        }
+        on.then_some(subscription)
+    }
+}
+
+impl Model {
+    fn notification_settings(&self, user_id: i64) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT key, value FROM UserSetting WHERE userId = ?1 AND key LIKE 'notify%'",
+        )?;
+        let rows = stmt
+            .query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// The user's subscription, `None` if they get no notifications.
+    pub fn subscription(&self, user_id: i64) -> Result<Option<Subscription>> {
+        let settings = self.notification_settings(user_id)?;
+        Ok(Subscription::decode(
+            settings.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+        ))
+    }
+
+    /// Turns notifications on with `subscription`'s filters, replacing the
+    /// previous ones.
+    pub fn subscribe(&self, user_id: i64, subscription: &Subscription) -> Result<()> {
+        self.check_writable()?;
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM UserSetting WHERE userId = ?1 AND key LIKE 'notify%'",
+            [user_id],
+        )?;
+        let over = subscription
+            .over
+            .as_ref()
+            .map(|(currency, minor)| format!("{} {}", currency, minor));
+        let category = subscription.category_id.map(|id| id.to_string());
+        for (key, value) in [
+            (ON, Some("on".to_string())),
+            (OVER, over),
+            (CATEGORY, category),
+        ] {
+            if let Some(value) = value {
+                tx.execute(
+                    "INSERT INTO UserSetting (userId, key, value) VALUES (?1, ?2, ?3)",
+                    params![user_id, key, value],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns whether notifications were on.
+    pub fn unsubscribe(&self, user_id: i64) -> Result<bool> {
+        self.check_writable()?;
+        let deleted = self.connection.execute(
+            "DELETE FROM UserSetting WHERE userId = ?1 AND key LIKE 'notify%'",
+            [user_id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// The users of the household to notify of `expense`, never the one who
+    /// logged it.
+    pub fn subscribers(&self, household: i64, expense: &ExpenseDetails) -> Result<Vec<i64>> {
+        let parent_id: Option<i64> = self.connection.query_row(
+            "SELECT (SELECT parentId FROM ExpenseCategory WHERE id = ?1)",
+            [expense.category_id],
+            |row| row.get(0),
+        )?;
+        let mut subscribers = Vec::new();
+        for user in self.users(household)? {
+            if user.telegram_id == expense.user_id {
+                continue;
+            }
+            if self
+                .subscription(user.telegram_id)?
+                .is_some_and(|s| s.matches(expense, parent_id))
+            {
+                subscribers.push(user.telegram_id);
+            }
+        }
+        Ok(subscribers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Setting, Settings};
+
+    fn expense(currency: &str, amount: f64, category_id: i64) -> ExpenseDetails {
+        ExpenseDetails {
+            id: 1,
+            amount,
+            exponent: 2,
+            account_id: 1,
+            account: None,
+            currency: Some(currency.to_string()),
+            category_id,
+            category: None,
+            category_icon: None,
+            user_id: 1001,
+            user: None,
+            timestamp: chrono::DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+            created_at: chrono::DateTime::from_timestamp(1_701_424_800, 0).unwrap(),
+            comment: None,
+            category_confirmed: true,
+            split_user_id: None,
+            split_user: None,
+            original: None,
+            location: None,
+            modified: None,
+            source_message: None,
+        }
+    }
+
+    #[test]
+    fn filters_combine() {
+        let everything = Subscription::default();
+        assert!(everything.matches(&expense("EUR", 0.01, 1), None));
+
+        let over = Subscription {
+            over: Some(("EUR".to_string(), 5000)),
+            category_id: None,
+        };
+        assert!(over.matches(&expense("EUR", 50.0, 1), None));
+        assert!(over.matches(&expense("EUR", 120.0, 1), None));
+        assert!(!over.matches(&expense("EUR", 49.99, 1), None));
+        // Other currencies aren't converted.
+        assert!(!over.matches(&expense("USD", 500.0, 1), None));
+
+        let category = Subscription {
+            over: None,
+            category_id: Some(4),
+        };
+        assert!(category.matches(&expense("USD", 1.0, 4), None));
+        assert!(!category.matches(&expense("USD", 1.0, 3), None));
+        // Subcategories count as their parent.
+        assert!(category.matches(&expense("USD", 1.0, 3), Some(4)));
+
+        let both = Subscription {
+            over: Some(("USD".to_string(), 5000)),
+            category_id: Some(4),
+        };
+        assert!(both.matches(&expense("USD", 100.0, 4), None));
+        assert!(!both.matches(&expense("USD", 20.0, 4), None));
+        assert!(!both.matches(&expense("USD", 100.0, 2), None));
+    }
+
+    #[test]
+    fn subscribers_exclude_the_author() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let utilities = model.get_expense_details(1, 4).unwrap().unwrap();
+        let groceries = model.get_expense_details(1, 1).unwrap().unwrap();
+
+        assert_eq!(None, model.subscription(1001).unwrap());
+        assert!(model.subscribers(1, &utilities).unwrap().is_empty());
+
+        let subscription = Subscription {
+            over: Some(("USD".to_string(), 5000)),
+            category_id: None,
+        };
+        model.subscribe(1001, &subscription).unwrap();
+        model.subscribe(1002, &Subscription::default()).unwrap();
+        assert_eq!(Some(subscription), model.subscription(1001).unwrap());
+        // Hanna logged the utilities, Alex the groceries in EUR.
+        assert_eq!(vec![1001], model.subscribers(1, &utilities).unwrap());
+        assert_eq!(vec![1002], model.subscribers(1, &groceries).unwrap());
+        // Other households hear nothing.
+        assert!(model.subscribers(2, &utilities).unwrap().is_empty());
+
+        // Notifications are no settings.
+        assert_eq!(Settings::default(), model.get_settings(1001).unwrap());
+        model
+            .set_setting(1001, Setting::MonthToDate(false))
+            .unwrap();
+        assert!(model.unsubscribe(1001).unwrap());
+        assert!(!model.unsubscribe(1001).unwrap());
+        assert_eq!(None, model.subscription(1001).unwrap());
+        assert!(!model.get_settings(1001).unwrap().month_to_date);
+    }
+}
@@ -0,0 +1,96 @@
+//! Callback payloads too long for a button, kept in the database behind a
+//! short id for a while.
+
+use super::{Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, TimeDelta, Utc};
+use rusqlite::{params, OptionalExtension};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+/// How long a stored payload answers its button.
+pub const PAYLOAD_TTL: TimeDelta = TimeDelta::hours(48);
+
+const ID_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// A random id of 11 characters, 64 bits of it.
+fn short_id(payload: &str, now: DateTime<Utc>) -> String {
+    let mut n = RandomState::new().hash_one((payload, now.timestamp_nanos_opt()));
+    let mut id = String::with_capacity(11);
+    for _ in 0..11 {
+        id.push(ID_ALPHABET[(n % 62) as usize] as char);
+        n /= 62;
+    }
+    id
+}
+
+impl Model {
+    /// Stores the payload and returns the id to look it up with.
+    pub fn store_callback_payload(&self, payload: &str, now: DateTime<Utc>) -> Result<String> {
+        loop {
+            let id = short_id(payload, now);
+            let inserted = self.connection.execute(
+                "INSERT OR IGNORE INTO CallbackPayload (shortId, payload, createdAt)
+                 VALUES (?1, json_quote(?2), ?3)",
+                params![id, payload, DbTime(now)],
+            )?;
+            if inserted > 0 {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// The payload stored under `id`, `None` if there is none or it is older
+    /// than `PAYLOAD_TTL` and awaits the sweep.
+    pub fn callback_payload(&self, id: &str, now: DateTime<Utc>) -> Result<Option<String>> {
+        let payload = self
+            .connection
+            .query_row(
+                "SELECT payload ->> '$' FROM CallbackPayload
+                 WHERE shortId = ?1 AND createdAt > ?2",
+                params![id, DbTime(now - PAYLOAD_TTL)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(payload)
+    }
+
+    /// Deletes the payloads older than `PAYLOAD_TTL`, returns how many.
+    pub fn sweep_callback_payloads(&self, now: DateTime<Utc>) -> Result<usize> {
+        let deleted = self.connection.execute(
+            "DELETE FROM CallbackPayload WHERE createdAt <= ?1",
+            [DbTime(now - PAYLOAD_TTL)],
+        )?;
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_resolve_and_sweep() {
+        let model = Model::new(true);
+        let now = DateTime::from_timestamp(1_701_424_800, 0).unwrap();
+        let old = model
+            .store_callback_payload("recat:1:2", now - PAYLOAD_TTL)
+            .unwrap();
+        let fresh = model
+            .store_callback_payload("it's \"quoted\"", now - TimeDelta::hours(47))
+            .unwrap();
+        assert_eq!(11, fresh.len());
+        assert_ne!(old, fresh);
+
+        assert_eq!(None, model.callback_payload(&old, now).unwrap());
+        assert_eq!(
+            Some("it's \"quoted\"".to_string()),
+            model.callback_payload(&fresh, now).unwrap()
+        );
+        assert_eq!(None, model.callback_payload("nonsense", now).unwrap());
+
+        assert_eq!(1, model.sweep_callback_payloads(now).unwrap());
+        assert_eq!(0, model.sweep_callback_payloads(now).unwrap());
+        assert!(model.callback_payload(&fresh, now).unwrap().is_some());
+    }
+}
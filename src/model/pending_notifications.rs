@@ -0,0 +1,125 @@
+//! Scheduled messages waiting to be sent, kept in the database so that a
+//! restart doesn't lose them: held back by the quiet hours of their chat, or
+//! to be tried again after failing to send.
+
+use super::{Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingNotification {
+    pub id: i64,
+    pub chat_id: i64,
+    /// In MarkdownV2.
+    pub text: String,
+    pub due_at: DateTime<Utc>,
+    /// Sends that failed so far.
+    pub attempts: u32,
+}
+
+impl Model {
+    /// Queues `text` for the chat, to be sent from `due_at` on. Returns its
+    /// id.
+    pub fn queue_notification(
+        &self,
+        chat_id: i64,
+        text: &str,
+        due_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<i64> {
+        self.connection.execute(
+            "INSERT INTO PendingNotification (chatId, text, dueAt, createdAt)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![chat_id, text, DbTime(due_at), DbTime(now)],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// The notifications due at `now`, those queued first first.
+    pub fn due_notifications(&self, now: DateTime<Utc>) -> Result<Vec<PendingNotification>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, chatId, text, dueAt, attempts FROM PendingNotification
+             WHERE dueAt <= ?1
+             ORDER BY id",
+        )?;
+        let notifications = stmt
+            .query_map([DbTime(now)], |row| {
+                Ok(PendingNotification {
+                    id: row.get(0)?,
+                    chat_id: row.get(1)?,
+                    text: row.get(2)?,
+                    due_at: row.get::<_, DbTime>(3)?.0,
+                    attempts: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(notifications)
+    }
+
+    /// Removes a notification that was sent, or given up on.
+    pub fn remove_notification(&self, id: i64) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM PendingNotification WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Puts a notification off until `due_at`, counting a failed send if
+    /// `failed`.
+    pub fn postpone_notification(
+        &self,
+        id: i64,
+        due_at: DateTime<Utc>,
+        failed: bool,
+    ) -> Result<()> {
+        self.connection.execute(
+            "UPDATE PendingNotification SET dueAt = ?2, attempts = attempts + ?3 WHERE id = ?1",
+            params![id, DbTime(due_at), failed as u32],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn queue_postpone_and_remove() {
+        let model = Model::new(true);
+        let now = at("2023-12-04T23:00:00Z");
+        let morning = at("2023-12-05T08:00:00Z");
+        let report = model
+            .queue_notification(1001, "report", morning, now)
+            .unwrap();
+        let alert = model.queue_notification(-100, "alert", now, now).unwrap();
+
+        let due = model.due_notifications(now).unwrap();
+        assert_eq!(vec![alert], due.iter().map(|n| n.id).collect::<Vec<_>>());
+        assert_eq!(
+            (-100, "alert", 0),
+            (due[0].chat_id, due[0].text.as_str(), due[0].attempts)
+        );
+
+        model.postpone_notification(alert, morning, true).unwrap();
+        assert!(model.due_notifications(now).unwrap().is_empty());
+        let due = model.due_notifications(morning).unwrap();
+        assert_eq!(
+            vec![(report, 0), (alert, 1)],
+            due.iter().map(|n| (n.id, n.attempts)).collect::<Vec<_>>()
+        );
+        assert_eq!(morning, due[1].due_at);
+
+        model.remove_notification(report).unwrap();
+        model.postpone_notification(alert, morning, false).unwrap();
+        let due = model.due_notifications(morning).unwrap();
+        assert_eq!(
+            vec![(alert, 1)],
+            due.iter().map(|n| (n.id, n.attempts)).collect::<Vec<_>>()
+        );
+    }
+}
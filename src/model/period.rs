@@ -0,0 +1,481 @@
+use crate::time;
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+
+/// Local calendar days from `start` (inclusive) to `end` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl DateRange {
+    /// Range covering `first..=last`.
+    pub fn inclusive(first: NaiveDate, last: NaiveDate) -> DateRange {
+        DateRange {
+            start: first,
+            end: last + Days::new(1),
+        }
+    }
+
+    /// Parses `first..last`, both ends included, where the ends are either
+    /// days (`YYYY-MM-DD`) or whole months (`YYYY-MM`).
+    pub fn parse(text: &str) -> Option<DateRange> {
+        let (first, last) = text.trim().split_once("..")?;
+        let (first, last) = (first.trim(), last.trim());
+        let range = match (parse_day(first), parse_day(last)) {
+            (Some(first), Some(last)) => DateRange::inclusive(first, last),
+            _ => {
+                let first = parse_month(first)?;
+                DateRange {
+                    start: first,
+                    end: parse_month(last)? + Months::new(1),
+                }
+            }
+        };
+        (range.start < range.end).then_some(range)
+    }
+
+    /// Last day within the range.
+    pub fn last_day(self) -> NaiveDate {
+        self.end - Days::new(1)
+    }
+
+    /// Like `25 Nov – 24 Dec`, with the years if the ends are in different
+    /// ones.
+    pub fn label(self) -> String {
+        let last = self.last_day();
+        if self.start.year() == last.year() {
+            format!(
+                "{} – {}",
+                self.start.format("%-d %b"),
+                last.format("%-d %b")
+            )
+        } else {
+            format!(
+                "{} – {}",
+                self.start.format("%-d %b %Y"),
+                last.format("%-d %b %Y")
+            )
+        }
+    }
+
+    /// The name of a month of a calendar: `December 2023` for a calendar
+    /// month, its days otherwise.
+    pub fn month_name(self) -> String {
+        if self.start.day() == 1 && self.end == self.start + Months::new(1) {
+            self.start.format("%B %Y").to_string()
+        } else {
+            self.label()
+        }
+    }
+
+    /// The instants of local midnight at both ends, so that the range can be
+    /// compared with stored UTC timestamps. Where a DST jump skips midnight,
+    /// the first existing instant of the day is used.
+    pub fn to_utc(self, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        (
+            time::start_of_day(self.start, tz),
+            time::start_of_day(self.end, tz),
+        )
+    }
+}
+
+fn parse_day(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+}
+
+/// The first day of a `YYYY-MM` month.
+pub fn parse_month(text: &str) -> Option<NaiveDate> {
+    parse_day(&format!("{}-01", text))
+}
+
+/// Latest day of the month a household's months may start on, so that
+/// every month has it.
+pub const MAX_MONTH_START: u32 = 28;
+
+/// Where a household's weeks and months start, for the periods of reports
+/// and budgets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calendar {
+    pub week_start: Weekday,
+    /// Day of the month, from 1 to [`MAX_MONTH_START`].
+    pub month_start: u32,
+}
+
+impl Default for Calendar {
+    /// ISO weeks and calendar months.
+    fn default() -> Calendar {
+        Calendar {
+            week_start: Weekday::Mon,
+            month_start: 1,
+        }
+    }
+}
+
+impl Calendar {
+    /// The month starting in the month of `date`, like 25 Nov – 24 Dec for
+    /// November with months starting on the 25th.
+    pub fn month(self, date: NaiveDate) -> DateRange {
+        let start = date
+            .with_day(self.month_start)
+            .expect("months have the days up to the 28th");
+        DateRange {
+            start,
+            end: start + Months::new(1),
+        }
+    }
+
+    /// The month `date` is in, which may have started in the month before.
+    fn month_of(self, date: NaiveDate) -> DateRange {
+        if date.day() >= self.month_start {
+            self.month(date)
+        } else {
+            self.month(date - Months::new(1))
+        }
+    }
+
+    /// The first day of the week `date` is in.
+    pub(super) fn week_of(self, date: NaiveDate) -> NaiveDate {
+        let into_week = (7 + date.weekday().num_days_from_monday()
+            - self.week_start.num_days_from_monday())
+            % 7;
+        date - Days::new(into_week.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    ThisWeek,
+    LastWeek,
+    ThisMonth,
+    LastMonth,
+    /// From the 1st of January up to and including today.
+    Ytd,
+    Custom(DateRange),
+}
+
+impl Period {
+    /// Parses a report argument: `week`, `lastweek`, `month`, `lastmonth`,
+    /// `ytd` or a range as accepted by [`DateRange::parse`].
+    pub fn parse(text: &str) -> Option<Period> {
+        match text.trim().to_lowercase().as_str() {
+            "week" | "thisweek" => Some(Period::ThisWeek),
+            "lastweek" => Some(Period::LastWeek),
+            "" | "month" | "thismonth" => Some(Period::ThisMonth),
+            "lastmonth" => Some(Period::LastMonth),
+            "ytd" => Some(Period::Ytd),
+            other => DateRange::parse(other).map(Period::Custom),
+        }
+    }
+
+    /// Resolves the period to local days, weeks and months starting where
+    /// `calendar` has them and "today" being taken in `tz`.
+    pub fn resolve(&self, now: DateTime<Utc>, tz: Tz, calendar: Calendar) -> DateRange {
+        let today = now.with_timezone(&tz).date_naive();
+        let week_start = calendar.week_of(today);
+        let month = calendar.month_of(today);
+
+        match self {
+            Period::ThisWeek => DateRange {
+                start: week_start,
+                end: week_start + Days::new(7),
+            },
+            Period::LastWeek => DateRange {
+                start: week_start - Days::new(7),
+                end: week_start,
+            },
+            Period::ThisMonth => month,
+            Period::LastMonth => calendar.month(month.start - Months::new(1)),
+            Period::Ytd => DateRange {
+                start: NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap(),
+                end: today + Days::new(1),
+            },
+            Period::Custom(range) => *range,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn range(start: &str, end: &str) -> DateRange {
+        DateRange {
+            start: date(start),
+            end: date(end),
+        }
+    }
+
+    #[test]
+    fn parses_periods() {
+        assert_eq!(Some(Period::ThisMonth), Period::parse(""));
+        assert_eq!(Some(Period::ThisWeek), Period::parse("week"));
+        assert_eq!(Some(Period::LastWeek), Period::parse("LastWeek"));
+        assert_eq!(Some(Period::LastMonth), Period::parse("lastmonth"));
+        assert_eq!(Some(Period::Ytd), Period::parse("ytd"));
+        assert_eq!(
+            Some(Period::Custom(range("2023-12-01", "2024-01-01"))),
+            Period::parse("2023-12-01..2023-12-31")
+        );
+        assert_eq!(None, Period::parse("2023-12-31..2023-12-01"));
+        assert_eq!(None, Period::parse("fortnight"));
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            Some(range("2023-12-01", "2023-12-02")),
+            DateRange::parse("2023-12-01..2023-12-01")
+        );
+        assert_eq!(
+            Some(range("2023-01-01", "2024-01-01")),
+            DateRange::parse("2023-01..2023-12")
+        );
+        assert_eq!(
+            Some(range("2023-12-01", "2024-01-01")),
+            DateRange::parse(" 2023-12 .. 2023-12 ")
+        );
+        assert_eq!(None, DateRange::parse("2023-12..2023-01"));
+        assert_eq!(None, DateRange::parse("2023-13..2024-01"));
+        assert_eq!(None, DateRange::parse("2023-12-01..2023-12"));
+        assert_eq!(None, DateRange::parse("2023-12"));
+    }
+
+    #[test]
+    fn week_one_spanning_two_years() {
+        // 2021-01-01 is a Friday of ISO week 53 of 2020.
+        let now = at("2021-01-01T12:00:00Z");
+        assert_eq!(
+            range("2020-12-28", "2021-01-04"),
+            Period::ThisWeek.resolve(now, Tz::UTC, Calendar::default())
+        );
+        assert_eq!(
+            range("2020-12-21", "2020-12-28"),
+            Period::LastWeek.resolve(now, Tz::UTC, Calendar::default())
+        );
+
+        // 2024-12-31 is a Tuesday of ISO week 1 of 2025.
+        let now = at("2024-12-31T12:00:00Z");
+        assert_eq!(
+            range("2024-12-30", "2025-01-06"),
+            Period::ThisWeek.resolve(now, Tz::UTC, Calendar::default())
+        );
+    }
+
+    #[test]
+    fn months_across_year_boundary() {
+        let now = at("2024-01-15T12:00:00Z");
+        assert_eq!(
+            range("2024-01-01", "2024-02-01"),
+            Period::ThisMonth.resolve(now, Tz::UTC, Calendar::default())
+        );
+        assert_eq!(
+            range("2023-12-01", "2024-01-01"),
+            Period::LastMonth.resolve(now, Tz::UTC, Calendar::default())
+        );
+        assert_eq!(
+            range("2024-01-01", "2024-01-16"),
+            Period::Ytd.resolve(now, Tz::UTC, Calendar::default())
+        );
+    }
+
+    #[test]
+    fn today_is_taken_in_time_zone() {
+        // Still 2023 in UTC, already 2024 in Berlin.
+        let now = at("2023-12-31T23:30:00Z");
+        assert_eq!(
+            range("2023-12-01", "2024-01-01"),
+            Period::ThisMonth.resolve(now, Tz::UTC, Calendar::default())
+        );
+        assert_eq!(
+            range("2024-01-01", "2024-02-01"),
+            Period::ThisMonth.resolve(now, Tz::Europe__Berlin, Calendar::default())
+        );
+        assert_eq!(
+            range("2024-01-01", "2024-01-02"),
+            Period::Ytd.resolve(now, Tz::Europe__Berlin, Calendar::default())
+        );
+    }
+
+    #[test]
+    fn utc_bounds_follow_dst() {
+        // Berlin switches to summer time on 2024-03-31.
+        let week = Period::ThisWeek.resolve(
+            at("2024-03-28T12:00:00Z"),
+            Tz::Europe__Berlin,
+            Calendar::default(),
+        );
+        assert_eq!(range("2024-03-25", "2024-04-01"), week);
+        assert_eq!(
+            (at("2024-03-24T23:00:00Z"), at("2024-03-31T22:00:00Z")),
+            week.to_utc(Tz::Europe__Berlin)
+        );
+
+        // And back on 2024-10-27.
+        let month = Period::ThisMonth.resolve(
+            at("2024-10-27T12:00:00Z"),
+            Tz::Europe__Berlin,
+            Calendar::default(),
+        );
+        assert_eq!(
+            (at("2024-09-30T22:00:00Z"), at("2024-10-31T23:00:00Z")),
+            month.to_utc(Tz::Europe__Berlin)
+        );
+    }
+
+    /// Weeks from Sunday, months from the 25th.
+    const SALARY: Calendar = Calendar {
+        week_start: Weekday::Sun,
+        month_start: 25,
+    };
+
+    #[test]
+    fn weeks_start_on_the_configured_day() {
+        // 2023-12-10 is a Sunday, 2023-12-16 a Saturday.
+        for (today, start) in [
+            ("2023-12-10", "2023-12-10"),
+            ("2023-12-13", "2023-12-10"),
+            ("2023-12-16", "2023-12-10"),
+            ("2023-12-17", "2023-12-17"),
+        ] {
+            let now = at(&format!("{}T12:00:00Z", today));
+            let week = Period::ThisWeek.resolve(now, Tz::UTC, SALARY);
+            assert_eq!(date(start), week.start, "{}", today);
+            assert_eq!(date(start) + Days::new(7), week.end, "{}", today);
+            let last = Period::LastWeek.resolve(now, Tz::UTC, SALARY);
+            assert_eq!(
+                (date(start) - Days::new(7), date(start)),
+                (last.start, last.end)
+            );
+        }
+        // Every other start day follows the same rule.
+        let wednesday = date("2023-12-13");
+        for week_start in [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ] {
+            let calendar = Calendar {
+                week_start,
+                month_start: 1,
+            };
+            let start = calendar.week_of(wednesday);
+            assert_eq!(week_start, start.weekday());
+            assert!(start <= wednesday && wednesday < start + Days::new(7));
+        }
+    }
+
+    #[test]
+    fn months_start_on_the_configured_day() {
+        let resolve = |period: Period, today: &str| {
+            period.resolve(at(&format!("{}T12:00:00Z", today)), Tz::UTC, SALARY)
+        };
+        // Before the 25th the month started in the month before.
+        assert_eq!(
+            range("2023-11-25", "2023-12-25"),
+            resolve(Period::ThisMonth, "2023-12-24")
+        );
+        assert_eq!(
+            range("2023-12-25", "2024-01-25"),
+            resolve(Period::ThisMonth, "2023-12-25")
+        );
+        assert_eq!(
+            range("2023-10-25", "2023-11-25"),
+            resolve(Period::LastMonth, "2023-12-01")
+        );
+        // Across the turn of the year.
+        assert_eq!(
+            range("2023-12-25", "2024-01-25"),
+            resolve(Period::ThisMonth, "2024-01-10")
+        );
+        assert_eq!(
+            range("2023-11-25", "2023-12-25"),
+            resolve(Period::LastMonth, "2024-01-10")
+        );
+        // The year to date keeps starting on the 1st of January.
+        assert_eq!(
+            range("2024-01-01", "2024-01-11"),
+            resolve(Period::Ytd, "2024-01-10")
+        );
+    }
+
+    #[test]
+    fn months_starting_on_the_28th_in_february() {
+        let calendar = Calendar {
+            week_start: Weekday::Mon,
+            month_start: MAX_MONTH_START,
+        };
+        let resolve = |today: &str| {
+            Period::ThisMonth.resolve(at(&format!("{}T12:00:00Z", today)), Tz::UTC, calendar)
+        };
+        assert_eq!(range("2023-01-28", "2023-02-28"), resolve("2023-02-27"));
+        assert_eq!(range("2023-02-28", "2023-03-28"), resolve("2023-02-28"));
+        assert_eq!(range("2023-02-28", "2023-03-28"), resolve("2023-03-01"));
+        // The 29th of a leap year is in the month from the 28th.
+        assert_eq!(range("2024-02-28", "2024-03-28"), resolve("2024-02-29"));
+        assert_eq!(
+            range("2024-01-28", "2024-02-28"),
+            Period::LastMonth.resolve(at("2024-03-01T12:00:00Z"), Tz::UTC, calendar)
+        );
+        assert_eq!(
+            range("2023-02-28", "2023-03-28"),
+            calendar.month(date("2023-02-01"))
+        );
+    }
+
+    #[test]
+    fn custom_months_follow_the_local_day() {
+        // The 25th already in Tokyo, still the 24th in UTC.
+        let now = at("2023-12-24T20:00:00Z");
+        assert_eq!(
+            range("2023-11-25", "2023-12-25"),
+            Period::ThisMonth.resolve(now, Tz::UTC, SALARY)
+        );
+        assert_eq!(
+            range("2023-12-25", "2024-01-25"),
+            Period::ThisMonth.resolve(now, Tz::Asia__Tokyo, SALARY)
+        );
+    }
+
+    #[test]
+    fn labels_state_the_days() {
+        assert_eq!("25 Nov – 24 Dec", range("2023-11-25", "2023-12-25").label());
+        assert_eq!(
+            "25 Dec 2023 – 24 Jan 2024",
+            range("2023-12-25", "2024-01-25").label()
+        );
+        assert_eq!(
+            "December 2023",
+            range("2023-12-01", "2024-01-01").month_name()
+        );
+        assert_eq!(
+            "25 Nov – 24 Dec",
+            range("2023-11-25", "2023-12-25").month_name()
+        );
+        assert_eq!(
+            "1 Dec – 10 Dec",
+            range("2023-12-01", "2023-12-11").month_name()
+        );
+    }
+
+    #[test]
+    fn skipped_midnight_uses_first_instant_of_day() {
+        // Santiago skips from 00:00 to 01:00 on 2023-09-03.
+        let day = range("2023-09-03", "2023-09-04");
+        let (start, _) = day.to_utc(Tz::America__Santiago);
+        assert_eq!(at("2023-09-03T04:00:00Z"), start);
+    }
+}
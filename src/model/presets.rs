@@ -0,0 +1,210 @@
+//! The sets of categories `/setup` offers to start with. The admin picks
+//! one and may leave some of its categories out before they are created.
+
+use super::{Model, Result};
+
+/// A category of a preset, as it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresetCategory {
+    pub name: &'static str,
+    pub icon: &'static str,
+    /// Its place among the preset's others. Created categories come after
+    /// those the household has, in this order.
+    pub sorting_order: u32,
+    pub require_comment: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Preset {
+    /// What the wizard stores of the choice.
+    pub key: &'static str,
+    pub name: &'static str,
+    /// Listed in their sorting order.
+    pub categories: &'static [PresetCategory],
+}
+
+const fn category(
+    name: &'static str,
+    icon: &'static str,
+    sorting_order: u32,
+    require_comment: bool,
+) -> PresetCategory {
+    PresetCategory {
+        name,
+        icon,
+        sorting_order,
+        require_comment,
+    }
+}
+
+pub const PRESETS: [Preset; 3] = [
+    Preset {
+        key: "minimal",
+        name: "Minimal",
+        categories: &[
+            category("Groceries", "🛒", 1, false),
+            category("Transport", "🚌", 2, false),
+            category("Utilities", "💡", 3, false),
+            category("Entertainment", "🎬", 4, false),
+            category("Other", "📦", 5, false),
+        ],
+    },
+    Preset {
+        key: "family",
+        name: "Family detailed",
+        categories: &[
+            category("Groceries", "🛒", 1, false),
+            category("Eating out", "🍽", 2, false),
+            category("Housing", "🏠", 3, false),
+            category("Utilities", "💡", 4, false),
+            category("Transport", "🚌", 5, false),
+            category("Car", "🚗", 6, false),
+            category("Health", "💊", 7, false),
+            category("Kids", "🧸", 8, false),
+            category("Clothes", "👕", 9, false),
+            category("Entertainment", "🎬", 10, false),
+            // Who they were for is easily forgotten by the end of the month.
+            category("Gifts", "🎁", 11, true),
+            category("Other", "📦", 12, true),
+        ],
+    },
+    Preset {
+        key: "student",
+        name: "Student",
+        categories: &[
+            category("Groceries", "🛒", 1, false),
+            category("Eating out", "🍽", 2, false),
+            category("Rent", "🏠", 3, false),
+            category("Transport", "🚌", 4, false),
+            category("Study", "📚", 5, false),
+            category("Phone and internet", "📱", 6, false),
+            category("Entertainment", "🎬", 7, false),
+            category("Other", "📦", 8, true),
+        ],
+    },
+];
+
+/// The preset stored as `key`.
+pub fn preset(key: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.key == key)
+}
+
+impl Preset {
+    /// The categories left after leaving out those named in `left_out`.
+    pub fn chosen(&self, left_out: &[String]) -> Vec<PresetCategory> {
+        self.categories
+            .iter()
+            .filter(|c| !left_out.iter().any(|name| name == c.name))
+            .copied()
+            .collect()
+    }
+}
+
+impl Model {
+    /// Adds the categories after the household's others, leaving out those
+    /// it has already, by name in any case, so that running the wizard again
+    /// doesn't add them twice. Returns the names of the added ones.
+    pub fn add_preset_categories(
+        &self,
+        household: i64,
+        categories: &[PresetCategory],
+    ) -> Result<Vec<String>> {
+        self.check_writable()?;
+        let existing = self.categories(household)?;
+        let mut categories = categories.to_vec();
+        categories.sort_by_key(|c| c.sorting_order);
+        let mut added = Vec::new();
+        for category in categories {
+            let name = category.name;
+            let taken = existing.iter().any(|c| c.name.eq_ignore_ascii_case(name))
+                || added.iter().any(|a: &String| a.eq_ignore_ascii_case(name));
+            if taken {
+                continue;
+            }
+            let id = self.add_category(household, name, Some(category.icon))?;
+            if category.require_comment {
+                self.set_require_comment(household, id, true)?;
+            }
+            added.push(name.to_string());
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::MAX_NAME_LEN;
+
+    #[test]
+    fn presets_are_consistent() {
+        for p in &PRESETS {
+            assert_eq!(Some(p), preset(p.key));
+            assert!(!p.categories.is_empty());
+            let orders: Vec<u32> = p.categories.iter().map(|c| c.sorting_order).collect();
+            assert!(orders.windows(2).all(|w| w[0] < w[1]), "{}", p.name);
+            for (i, category) in p.categories.iter().enumerate() {
+                assert!(category.name.chars().count() <= MAX_NAME_LEN);
+                assert!(!category.icon.is_empty());
+                let same_name = p.categories[..i]
+                    .iter()
+                    .any(|c| c.name.eq_ignore_ascii_case(category.name));
+                assert!(!same_name, "{} twice in {}", category.name, p.name);
+            }
+        }
+        assert_eq!(None, preset("deluxe"));
+    }
+
+    #[test]
+    fn leaving_categories_out() {
+        let student = preset("student").unwrap();
+        let names = |left_out: &[&str]| -> Vec<&str> {
+            let left_out: Vec<String> = left_out.iter().map(|s| s.to_string()).collect();
+            student.chosen(&left_out).iter().map(|c| c.name).collect()
+        };
+        assert_eq!(8, names(&[]).len());
+        assert_eq!(
+            vec!["Groceries", "Eating out", "Transport", "Study"],
+            names(&[
+                "Rent",
+                "Phone and internet",
+                "Entertainment",
+                "Other",
+                "Unknown"
+            ])
+        );
+    }
+
+    #[test]
+    fn presets_are_added_once() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let family = preset("family").unwrap();
+        let added = model.add_preset_categories(1, family.categories).unwrap();
+        // Groceries, Utilities and Entertainment are in the test data already.
+        assert_eq!(9, added.len());
+        assert!(!added.contains(&"Groceries".to_string()));
+        let categories = model.categories(1).unwrap();
+        assert_eq!(13, categories.len());
+        let gifts = categories.iter().find(|c| c.name == "Gifts").unwrap();
+        assert!(gifts.require_comment);
+        assert_eq!(Some("🎁"), gifts.icon.as_deref());
+        // After the household's own, in the preset's order.
+        assert_eq!(
+            vec!["Eating out", "Housing"],
+            categories[4..6].iter().map(|c| &c.name).collect::<Vec<_>>()
+        );
+
+        // Again, and with another preset sharing some.
+        assert!(model
+            .add_preset_categories(1, family.categories)
+            .unwrap()
+            .is_empty());
+        let student = preset("student").unwrap();
+        assert_eq!(
+            vec!["Rent", "Study", "Phone and internet"],
+            model.add_preset_categories(1, student.categories).unwrap()
+        );
+        assert_eq!(16, model.categories(1).unwrap().len());
+    }
+}
@@ -0,0 +1,119 @@
+use super::{Error, Model, Result};
+use chrono::NaiveDate;
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// Value of one unit of a currency in the base currency, effective from `date`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub rate: f64,
+    pub date: NaiveDate,
+}
+
+impl Rate {
+    /// Days from the rate's date to `as_of`, 0 for a rate newer than that.
+    pub fn age(&self, as_of: NaiveDate) -> i64 {
+        (as_of - self.date).num_days().max(0)
+    }
+
+    /// Whether the rate is more than `stale_after` days old on `as_of`. That
+    /// is the day the converted amounts are of, not necessarily today.
+    pub fn is_stale(&self, as_of: NaiveDate, stale_after: u32) -> bool {
+        self.age(as_of) > i64::from(stale_after)
+    }
+}
+
+impl Model {
+    /// Every currency but `base_currency` with its latest rate, `None` if it
+    /// has none, ordered by name.
+    pub fn latest_rates(&self, base_currency: &str) -> Result<Vec<(String, Option<Rate>)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT c.name, r.rate, r.date
+             FROM Currency c
+             LEFT JOIN ExchangeRate r ON r.currencyId = c.id
+                 AND r.date = (SELECT MAX(date) FROM ExchangeRate WHERE currencyId = c.id)
+             WHERE c.name <> ?1 COLLATE NOCASE
+             ORDER BY c.name",
+        )?;
+        let rates = stmt
+            .query_map([base_currency], |row| {
+                let rate = match (row.get(1)?, row.get(2)?) {
+                    (Some(rate), Some(date)) => Some(Rate { rate, date }),
+                    _ => None,
+                };
+                Ok((row.get(0)?, rate))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rates)
+    }
+
+    /// Stores the rate of `currency` against the base currency on `date`,
+    /// replacing a rate already stored for that day.
+    pub fn set_rate(&self, currency: &str, date: NaiveDate, rate: f64) -> Result<()> {
+        self.check_writable()?;
+        let currency_id: i64 = self
+            .connection
+            .query_row(
+                "SELECT id FROM Currency WHERE name = ?1 COLLATE NOCASE",
+                [currency],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("currency {}", currency)))?;
+
+        info!("Setting {} rate on {} to {}", currency, date, rate);
+        self.connection.execute(
+            "INSERT INTO ExchangeRate (currencyId, date, rate) VALUES (?1, ?2, ?3)
+             ON CONFLICT (currencyId, date) DO UPDATE SET rate = excluded.rate",
+            params![currency_id, date, rate],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn staleness_is_of_the_day_converted() {
+        let rate = Rate {
+            rate: 0.9,
+            date: date("2023-11-02"),
+        };
+        // A report of November is fine with it, one of December isn't.
+        assert!(!rate.is_stale(date("2023-11-30"), 28));
+        assert!(rate.is_stale(date("2023-12-31"), 28));
+        assert!(!rate.is_stale(date("2023-11-16"), 14));
+        assert!(rate.is_stale(date("2023-11-17"), 14));
+        assert_eq!(15, rate.age(date("2023-11-17")));
+        // A report older than the rate.
+        assert_eq!(0, rate.age(date("2023-10-31")));
+        assert!(!rate.is_stale(date("2023-10-01"), 14));
+    }
+
+    #[test]
+    fn latest_rates_of_every_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_rate("USD", date("2023-11-01"), 0.5).unwrap();
+        model.set_rate("USD", date("2023-12-01"), 0.9).unwrap();
+        assert_eq!(
+            vec![
+                ("BYN".to_string(), None),
+                (
+                    "USD".to_string(),
+                    Some(Rate {
+                        rate: 0.9,
+                        date: date("2023-12-01")
+                    })
+                ),
+            ],
+            model.latest_rates("eur").unwrap()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Read-only mode, for an admin to freeze the data before a restore or a
+//! risky migration. While it is on, whatever changes expenses, accounts,
+//! categories, roles or the database file is refused with
+//! [`Error::ReadOnly`], and so are the settings of users and chats; reports,
+//! listings and exports keep working, and so does the bookkeeping of the bot
+//! itself, like drafts and buttons. The flag is stored, so a restart doesn't
+//! end it.
+
+use super::connection::assert_fk_enabled;
+use super::{audit, Error, Model, Result};
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+const READ_ONLY: &str = "readOnly";
+
+impl Model {
+    /// Whether changes are refused.
+    pub fn read_only(&self) -> Result<bool> {
+        let value = self
+            .connection
+            .query_row(
+                "SELECT value FROM BotSetting WHERE key = ?1",
+                [READ_ONLY],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        Ok(value.as_deref() == Some("on"))
+    }
+
+    /// Turns read-only mode on or off for `user_id`, an admin, returning
+    /// whether that changed it.
+    pub fn set_read_only(&self, user_id: i64, on: bool) -> Result<bool> {
+        if self.read_only()? == on {
+            return Ok(false);
+        }
+        let value = if on { "on" } else { "off" };
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO BotSetting (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![READ_ONLY, value],
+        )?;
+        audit::record(&tx, user_id, "readonly", value)?;
+        tx.commit()?;
+        warn!("User {} turned read-only mode {}", user_id, value);
+        Ok(true)
+    }
+
+    /// Refuses a change while read-only mode is on. Called by every method
+    /// changing the data, so that background work is held back as well as
+    /// handlers. Debug builds check here, too, that foreign keys are
+    /// enforced.
+    ///
+    /// Only what the bot keeps for itself is exempt: stored button payloads
+    /// and their sweep, the last runs of scheduled tasks and reports, the
+    /// queue of scheduled messages, the draft journal, feed and confirmation
+    /// messages, and the names `touch_user` refreshes. Those change nothing a user asked for.
+    pub(super) fn check_writable(&self) -> Result<()> {
+        assert_fk_enabled(&self.connection);
+        match self.read_only()? {
+            true => Err(Error::ReadOnly),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Cadence, Setting, Setup, Subscription};
+    use crate::model::{ConfirmMode, ExpenseSource, NewExpense, Role};
+    use chrono::DateTime;
+
+    fn expense() -> NewExpense {
+        NewExpense {
+            amount: 12.0,
+            account_id: 1,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+            comment: None,
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        }
+    }
+
+    #[test]
+    fn changes_wait_for_read_only_mode_to_end() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert!(model.set_read_only(1001, true).unwrap());
+        assert!(!model.set_read_only(1001, true).unwrap());
+        assert!(model.read_only().unwrap());
+
+        let committed = model.insert_expense_once(1, "draft:-100:7", &expense());
+        assert!(matches!(committed, Err(Error::ReadOnly)), "{:?}", committed);
+        assert!(matches!(model.delete_expense(1, 1), Err(Error::ReadOnly)));
+        assert!(matches!(
+            model.add_category(1, "Garden", None),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(
+            model.set_role(1, 1003, Role::Member),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(model.maintenance(0), Err(Error::ReadOnly)));
+        // Reading goes on.
+        assert!(model.get_expense_details(1, 1).unwrap().is_some());
+
+        assert!(model.set_read_only(1001, false).unwrap());
+        assert!(model
+            .insert_expense_once(1, "draft:-100:7", &expense())
+            .is_ok());
+        let audited: Vec<(String, String)> = model
+            .audit_entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.action, e.details))
+            .collect();
+        assert_eq!(
+            vec![
+                ("readonly".to_string(), "on".to_string()),
+                ("readonly".to_string(), "off".to_string())
+            ],
+            audited
+        );
+    }
+
+    #[test]
+    fn settings_wait_for_read_only_mode_to_end() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_alias(1001, "rl", "report lastmonth").unwrap();
+        model.set_comment_template(1001, "bus", "bus").unwrap();
+        model.set_read_only(1001, true).unwrap();
+        let now = DateTime::from_timestamp(1_702_000_000, 0).unwrap();
+
+        let refused = [
+            (
+                "set_setting",
+                model.set_setting(1001, Setting::MonthToDate(false)),
+            ),
+            ("set_alias", model.set_alias(1001, "bal", "balance")),
+            ("delete_alias", model.delete_alias(1001, "rl").map(drop)),
+            (
+                "set_comment_template",
+                model.set_comment_template(1001, "fuel", "fuel"),
+            ),
+            (
+                "delete_comment_template",
+                model.delete_comment_template(1001, "bus").map(drop),
+            ),
+            (
+                "set_report_exclusions",
+                model.set_report_exclusions(1001, &[4]),
+            ),
+            ("subscribe", model.subscribe(1001, &Subscription::default())),
+            ("unsubscribe", model.unsubscribe(1001).map(drop)),
+            (
+                "subscribe_report",
+                model.subscribe_report(1001, Cadence::Weekly, now).map(drop),
+            ),
+            (
+                "unsubscribe_reports",
+                model.unsubscribe_reports(1001).map(drop),
+            ),
+            ("set_chat_privacy", model.set_chat_privacy(-100, 1, true)),
+            (
+                "set_chat_confirm_mode",
+                model.set_chat_confirm_mode(-100, 1, ConfirmMode::Silent),
+            ),
+            (
+                "set_chat_assumed_currency",
+                model.set_chat_assumed_currency(-100, 1, Some("USD")),
+            ),
+            ("save_setup", model.save_setup(-100, &Setup::new(1001, 1))),
+            ("cancel_setup", model.cancel_setup(-100).map(drop)),
+        ];
+        for (writer, result) in refused {
+            assert!(
+                matches!(result, Err(Error::ReadOnly)),
+                "{}: {:?}",
+                writer,
+                result
+            );
+        }
+        assert!(model.get_settings(1001).unwrap().month_to_date);
+        assert_eq!(1, model.aliases(1001).unwrap().len());
+        assert_eq!(1, model.comment_templates(1001).unwrap().len());
+        assert!(model.report_exclusions(1001).unwrap().is_empty());
+        assert!(!model.chat_privacy(-100).unwrap());
+        assert_eq!(None, model.setup(-100).unwrap());
+
+        // The bot's own bookkeeping goes on.
+        model.store_callback_payload("recat:1:2", now).unwrap();
+        model.set_task_last_run("maintenance", now).unwrap();
+        model
+            .touch_user(1001, ("alex", "Alex"), Some("en"))
+            .unwrap();
+    }
+}
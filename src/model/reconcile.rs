@@ -0,0 +1,406 @@
+//! `/reconcile`: the balance the bank shows for an account against the one
+//! computed from its expenses, with adjustments that bring the two in line.
+
+use super::amount::from_minor;
+use super::{Error, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// The category adjustments are logged in, created when first needed.
+pub const ADJUSTMENTS: &str = "Adjustments";
+
+const ADJUSTMENT_COMMENT: &str = "Reconciliation adjustment";
+
+/// A balance check, with amounts in minor units of the account's currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    pub id: i64,
+    pub account: String,
+    pub currency: String,
+    pub exponent: u32,
+    pub timestamp: DateTime<Utc>,
+    pub computed: i64,
+    pub actual: i64,
+    /// Whether an adjustment was recorded for it.
+    pub adjusted: bool,
+}
+
+impl Reconciliation {
+    /// What the bank has more than the bot thinks, negative if less.
+    pub fn delta(&self) -> i64 {
+        self.actual - self.computed
+    }
+}
+
+const SELECT_RECONCILIATION: &str = "
+    SELECT r.id, COALESCE(a.displayName, a.name), c.name, c.exponent, r.timestamp,
+           r.computedMinor, r.actualMinor, r.expenseId IS NOT NULL
+    FROM Reconciliation r
+    JOIN Account a ON a.id = r.accountId
+    JOIN Currency c ON c.id = a.currencyId
+";
+
+impl Reconciliation {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Reconciliation> {
+        Ok(Reconciliation {
+            id: row.get(0)?,
+            account: row.get(1)?,
+            currency: row.get(2)?,
+            exponent: row.get(3)?,
+            timestamp: row.get::<_, DbTime>(4)?.0,
+            computed: row.get(5)?,
+            actual: row.get(6)?,
+            adjusted: row.get(7)?,
+        })
+    }
+}
+
+impl Model {
+    /// The balance of the account in minor units: its initial balance less
+    /// every expense, archived ones and adjustments included.
+    fn computed_balance(&self, account_id: i64) -> Result<i64> {
+        let tables = self.expense_tables(None)?;
+        let balance = self.connection.query_row(
+            &tables.apply(
+                "SELECT a.initialBalanceMinor
+                        - COALESCE((SELECT SUM(e.amountMinor) FROM {expenses} e
+                                    WHERE e.accountId = a.id), 0)
+                 FROM Account a
+                 WHERE a.id = ?1",
+            ),
+            [account_id],
+            |row| row.get(0),
+        )?;
+        Ok(balance)
+    }
+
+    /// Compares `actual`, in minor units, with the account's computed
+    /// balance at `now` and logs the comparison.
+    pub fn reconcile(
+        &self,
+        household: i64,
+        account_id: i64,
+        actual: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Reconciliation> {
+        self.check_writable()?;
+        if self.get_account(household, account_id)?.is_none() {
+            return Err(Error::NotFound(format!("account {}", account_id)));
+        }
+        let computed = self.computed_balance(account_id)?;
+        self.connection.execute(
+            "INSERT INTO Reconciliation (accountId, timestamp, computedMinor, actualMinor)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![account_id, DbTime(now), computed, actual],
+        )?;
+        let id = self.connection.last_insert_rowid();
+        info!("Reconciled account {} as {}", account_id, id);
+        self.reconciliation(household, id)?
+            .ok_or_else(|| Error::NotFound(format!("reconciliation {}", id)))
+    }
+
+    pub fn reconciliation(&self, household: i64, id: i64) -> Result<Option<Reconciliation>> {
+        let reconciliation = self
+            .connection
+            .query_row(
+                &format!(
+                    "{} WHERE r.id = ?1 AND a.householdId = ?2",
+                    SELECT_RECONCILIATION
+                ),
+                [id, household],
+                Reconciliation::from_row,
+            )
+            .optional()?;
+        Ok(reconciliation)
+    }
+
+    /// The household's reconciliations, latest first.
+    pub fn reconciliations(&self, household: i64, limit: usize) -> Result<Vec<Reconciliation>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE a.householdId = ?1 ORDER BY r.timestamp DESC, r.id DESC LIMIT ?2",
+            SELECT_RECONCILIATION
+        ))?;
+        let reconciliations = stmt
+            .query_map(params![household, limit as i64], Reconciliation::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(reconciliations)
+    }
+
+    /// The household's category for adjustments, added if it has none.
+    fn adjustments_category(&self, household: i64) -> Result<i64> {
+        let id = self
+            .connection
+            .query_row(
+                "SELECT id FROM ExpenseCategory
+                 WHERE householdId = ?1 AND name = ?2 COLLATE NOCASE",
+                params![household, ADJUSTMENTS],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match id {
+            Some(id) => Ok(id),
+            None => self.add_category(household, ADJUSTMENTS, Some("⚖️")),
+        }
+    }
+
+    /// Logs an adjustment by `user_id` that makes the computed balance of
+    /// the reconciled account what the bank showed. Its amount is what the
+    /// computed balance is over, so money the bot doesn't know of is a
+    /// negative amount. Changes since the reconciliation are taken into
+    /// account. Like any expense it must fit the amount limits, in either
+    /// direction, and fall in an open month. Returns the id of the
+    /// adjustment.
+    pub fn record_adjustment(
+        &self,
+        household: i64,
+        reconciliation_id: i64,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<i64> {
+        self.check_writable()?;
+        let (account_id, actual, adjusted): (i64, i64, bool) = self
+            .connection
+            .query_row(
+                "SELECT r.accountId, r.actualMinor, r.expenseId IS NOT NULL
+                 FROM Reconciliation r
+                 JOIN Account a ON a.id = r.accountId
+                 WHERE r.id = ?1 AND a.householdId = ?2",
+                [reconciliation_id, household],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("reconciliation {}", reconciliation_id)))?;
+        if adjusted {
+            return Err(Error::Refused(
+                "The adjustment has been recorded already.".to_string(),
+            ));
+        }
+        self.check_open(household, now)?;
+        let exponent = self
+            .get_account(household, account_id)?
+            .ok_or_else(|| Error::NotFound(format!("account {}", account_id)))?
+            .exponent;
+        let tx = self.connection.unchecked_transaction()?;
+        let amount = self.computed_balance(account_id)? - actual;
+        if amount == 0 {
+            return Err(Error::Refused(
+                "The balance matches already, nothing to adjust.".to_string(),
+            ));
+        }
+        self.amount_limits
+            .for_exponent(exponent)
+            .validate(from_minor(amount.abs(), exponent))?;
+        super::check_comment(Some(ADJUSTMENT_COMMENT))?;
+        let category_id = self.adjustments_category(household)?;
+        self.connection.execute(
+            "INSERT INTO Expense (accountId, categoryId, userId, timestamp, amountMinor, comments,
+                categoryConfirmed, kind, createdAt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1, 'adjustment', ?4)",
+            params![
+                account_id,
+                category_id,
+                user_id,
+                DbTime(now),
+                amount,
+                ADJUSTMENT_COMMENT
+            ],
+        )?;
+        let id = self.connection.last_insert_rowid();
+        self.connection.execute(
+            "UPDATE Reconciliation SET expenseId = ?1 WHERE id = ?2",
+            [id, reconciliation_id],
+        )?;
+        tx.commit()?;
+        info!(
+            "Recorded adjustment {} of {} for reconciliation {}",
+            id, amount, reconciliation_id
+        );
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{AmountLimits, Calendar, ExpenseSource, NewExpense, Period};
+    use chrono::TimeDelta;
+    use chrono_tz::Tz;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_705_320_000, 0).unwrap()
+    }
+
+    /// Alex Savings starts at 1000 EUR and has 50.75 spent.
+    fn model() -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model
+    }
+
+    fn balance(model: &Model) -> f64 {
+        from_minor(model.computed_balance(1).unwrap(), 2)
+    }
+
+    #[test]
+    fn bank_has_less_than_computed() {
+        let model = model();
+        assert_eq!(949.25, balance(&model));
+        let r = model.reconcile(1, 1, 94000, now()).unwrap();
+        assert_eq!(
+            ("Alex Savings", "EUR", 94925, 94000, false),
+            (
+                r.account.as_str(),
+                r.currency.as_str(),
+                r.computed,
+                r.actual,
+                r.adjusted
+            )
+        );
+        assert_eq!(-925, r.delta());
+
+        let id = model.record_adjustment(1, r.id, 1001, now()).unwrap();
+        assert_eq!(940.0, balance(&model));
+        // Missing money is spent in the adjustments category.
+        let expense = model.get_expense_details(1, id).unwrap().unwrap();
+        assert_eq!(9.25, expense.amount);
+        assert_eq!(Some(ADJUSTMENTS), expense.category.as_deref());
+        let kind: String = model
+            .connection
+            .query_row("SELECT kind FROM Expense WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!("adjustment", kind);
+        assert!(model.reconciliation(1, r.id).unwrap().unwrap().adjusted);
+        assert!(matches!(
+            model.record_adjustment(1, r.id, 1001, now()),
+            Err(Error::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn bank_has_more_than_computed() {
+        let model = model();
+        let r = model.reconcile(1, 1, 100050, now()).unwrap();
+        assert_eq!(5125, r.delta());
+        let id = model.record_adjustment(1, r.id, 1001, now()).unwrap();
+        assert_eq!(1000.5, balance(&model));
+        // Money the bot didn't know of is a negative expense.
+        let expense = model.get_expense_details(1, id).unwrap().unwrap();
+        assert_eq!(-51.25, expense.amount);
+
+        // A second one reuses the category.
+        let r = model.reconcile(1, 1, 100000, now()).unwrap();
+        model.record_adjustment(1, r.id, 1001, now()).unwrap();
+        let adjustments = model
+            .categories(1)
+            .unwrap()
+            .into_iter()
+            .filter(|c| c.name == ADJUSTMENTS)
+            .count();
+        assert_eq!(1, adjustments);
+        assert_eq!(1000.0, balance(&model));
+    }
+
+    #[test]
+    fn adjusts_to_the_balance_at_the_time_of_the_tap() {
+        let model = model();
+        let r = model.reconcile(1, 1, 90000, now()).unwrap();
+        // An expense logged since then makes up part of the difference.
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id: 1,
+                user_id: 1001,
+                timestamp: now(),
+                amount: 40.0,
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+        model.record_adjustment(1, r.id, 1001, now()).unwrap();
+        assert_eq!(900.0, balance(&model));
+
+        // Nothing is left to adjust for a balance that matches.
+        let r = model.reconcile(1, 1, 90000, now()).unwrap();
+        assert_eq!(0, r.delta());
+        assert!(matches!(
+            model.record_adjustment(1, r.id, 1001, now()),
+            Err(Error::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn adjustments_change_balances_but_not_spending() {
+        let model = model();
+        let january = Period::ThisMonth.resolve(now(), Tz::UTC, Calendar::default());
+        let r = model.reconcile(1, 1, 90000, now()).unwrap();
+        model.record_adjustment(1, r.id, 1001, now()).unwrap();
+
+        let account = |model: &Model| {
+            let balances = model.balances(1, "EUR").unwrap();
+            balances.accounts.into_iter().find(|a| a.id == 1).unwrap()
+        };
+        assert_eq!(900.0, account(&model).balance);
+        assert!(model
+            .summarize(1, january, Tz::UTC)
+            .unwrap()
+            .categories
+            .is_empty());
+        let month = january.start;
+        assert_eq!(None, model.fun_stats(1, month, Tz::UTC).unwrap().top_logger);
+        let rules = crate::model::ReviewRules::default();
+        let completeness = model
+            .completeness_stats(1, january, &rules, Tz::UTC)
+            .unwrap();
+        assert_eq!(0, completeness.expenses);
+    }
+
+    #[test]
+    fn adjustments_are_checked_like_expenses() {
+        let mut model = model();
+        // December is closed, so is an adjustment dated in it.
+        let december = DateTime::from_timestamp(1_702_000_000, 0).unwrap();
+        let r = model.reconcile(1, 1, 94000, december).unwrap();
+        let month = chrono::NaiveDate::from_ymd_opt(2023, 12, 1).unwrap();
+        model.close_month(1, 1001, month, (now(), Tz::UTC)).unwrap();
+        assert!(matches!(
+            model.record_adjustment(1, r.id, 1001, december),
+            Err(Error::Closed(_))
+        ));
+
+        // Nor may it be over the amount limits, either way.
+        model.set_amount_limits(AmountLimits {
+            max: 5.0,
+            ..AmountLimits::default()
+        });
+        for actual in [94000, 96000] {
+            let r = model.reconcile(1, 1, actual, now()).unwrap();
+            assert!(matches!(
+                model.record_adjustment(1, r.id, 1001, now()),
+                Err(Error::InvalidAmount(_))
+            ));
+        }
+        assert_eq!(949.25, balance(&model));
+    }
+
+    #[test]
+    fn history_is_per_household_latest_first() {
+        let model = model();
+        model.fill_second_household();
+        model
+            .reconcile(1, 1, 94000, now() - TimeDelta::days(30))
+            .unwrap();
+        model.reconcile(1, 1, 94925, now()).unwrap();
+        model.reconcile(2, 4, 8800, now()).unwrap();
+        let history = model.reconciliations(1, 10).unwrap();
+        let deltas: Vec<i64> = history.iter().map(|r| r.delta()).collect();
+        assert_eq!(vec![0, -925], deltas);
+        assert_eq!(1, model.reconciliations(1, 1).unwrap().len());
+        assert!(model.reconcile(2, 1, 100, now()).is_err());
+        assert_eq!(None, model.reconciliation(2, history[0].id).unwrap());
+    }
+}
@@ -0,0 +1,192 @@
+//! Moving the database file to where `ST_DB_PATH` says, before the bot
+//! opens it. A configured path with no file behind it would quietly start
+//! an empty database while the old one sits in the working directory, so
+//! that takes a decision: migrate the old file or move it away.
+
+use super::connection::{open_connection, Storage};
+use super::restore::{io_refused, rename_database, suffixed, validate_backup};
+use super::{Error, Result};
+use log::*;
+use rusqlite::backup::Backup;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What there is to open at the configured path.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Location {
+    /// The database is there.
+    Existing,
+    /// Nothing is there, nor at the legacy path: a new database.
+    Fresh,
+    /// Copied there from `from`, which was renamed to `moved_to`.
+    Migrated { from: PathBuf, moved_to: PathBuf },
+}
+
+/// Makes sure `db` is the database to open. A missing `db` is migrated
+/// from `migrate_from` if given, and otherwise refused while the `legacy`
+/// file exists.
+pub fn locate(db: &Path, legacy: &Path, migrate_from: Option<&Path>) -> Result<Location> {
+    if db.exists() {
+        if let Some(from) = migrate_from {
+            warn!(
+                "{} exists, not migrating from {}; unset ST_DB_MIGRATE_FROM",
+                db.display(),
+                from.display()
+            );
+        }
+        info!("Using the database at {}", db.display());
+        return Ok(Location::Existing);
+    }
+    if let Some(from) = migrate_from {
+        return migrate(from, db);
+    }
+    if legacy.exists() && legacy != db {
+        return Err(Error::Refused(format!(
+            "There is no database at {} but there is one at {}. Start with ST_DB_MIGRATE_FROM={} \
+             to move it, or move it away to start with an empty database.",
+            db.display(),
+            legacy.display(),
+            legacy.display()
+        )));
+    }
+    info!(
+        "No database at {}, starting with an empty one",
+        db.display()
+    );
+    Ok(Location::Fresh)
+}
+
+/// Copies `from` to `db` with SQLite's backup API, checks the copy, and
+/// renames `from` so that it isn't used again. Until the copy is checked
+/// nothing is at `db` and `from` is as it was.
+fn migrate(from: &Path, db: &Path) -> Result<Location> {
+    if !from.exists() {
+        return Err(Error::Refused(format!(
+            "{} doesn't exist, there is nothing to migrate.",
+            from.display()
+        )));
+    }
+    info!(
+        "Migrating the database from {} to {}",
+        from.display(),
+        db.display()
+    );
+    let staged = suffixed(db, "migrating");
+    let copied = copy(from, &staged).and_then(|()| validate_backup(&staged));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    fs::rename(&staged, db).map_err(|e| io_refused("Moving the copy", db, e))?;
+    let moved_to = suffixed(from, "migrated");
+    rename_database(from, &moved_to)?;
+    info!(
+        "Migrated the database to {}, the old one is now {}",
+        db.display(),
+        moved_to.display()
+    );
+    Ok(Location::Migrated {
+        from: from.to_path_buf(),
+        moved_to,
+    })
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    if to.exists() {
+        // Left by a migration that didn't finish.
+        fs::remove_file(to).map_err(|e| io_refused("Clearing the copy", to, e))?;
+    }
+    let source = open_connection(Storage::ReadOnly(from))?;
+    let mut target = open_connection(Storage::File(to))?;
+    Backup::new(&source, &mut target)?.run_to_completion(100, Duration::ZERO, None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::restore::tests::{row_count, TempDir};
+    use crate::model::Model;
+
+    #[test]
+    fn existing_and_fresh_databases() {
+        let dir = TempDir::new("locate-fresh");
+        let (db, legacy) = (dir.0.join("volume.db"), dir.0.join("legacy.db"));
+        assert_eq!(Location::Fresh, locate(&db, &legacy, None).unwrap());
+        // The legacy path configured as the new one is just that database.
+        Model::open(&legacy).fill_test_data();
+        assert_eq!(Location::Existing, locate(&legacy, &legacy, None).unwrap());
+
+        Model::open(&db);
+        assert_eq!(Location::Existing, locate(&db, &legacy, None).unwrap());
+        // A migration left configured after it was done changes nothing.
+        assert_eq!(
+            Location::Existing,
+            locate(&db, &legacy, Some(&legacy)).unwrap()
+        );
+        assert!(legacy.exists());
+    }
+
+    #[test]
+    fn refuses_to_leave_the_legacy_database_behind() {
+        let dir = TempDir::new("locate-refuse");
+        let (db, legacy) = (dir.0.join("volume.db"), dir.0.join("legacy.db"));
+        Model::open(&legacy).fill_test_data();
+        match locate(&db, &legacy, None) {
+            Err(Error::Refused(reason)) => {
+                assert!(reason.contains("ST_DB_MIGRATE_FROM"), "{}", reason)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(!db.exists());
+        assert_eq!(4, row_count(&legacy, "Expense"));
+    }
+
+    #[test]
+    fn migrates_the_legacy_database() {
+        let dir = TempDir::new("locate-migrate");
+        let (db, legacy) = (dir.0.join("volume.db"), dir.0.join("legacy.db"));
+        Model::open(&legacy).fill_test_data();
+
+        let moved_to = dir.0.join("legacy.db.migrated");
+        assert_eq!(
+            Location::Migrated {
+                from: legacy.clone(),
+                moved_to: moved_to.clone()
+            },
+            locate(&db, &legacy, Some(&legacy)).unwrap()
+        );
+        assert_eq!(4, row_count(&db, "Expense"));
+        assert!(!legacy.exists());
+        assert_eq!(4, row_count(&moved_to, "Expense"));
+        assert!(!suffixed(&db, "migrating").exists());
+        // From then on it is the database.
+        assert_eq!(Location::Existing, locate(&db, &legacy, None).unwrap());
+    }
+
+    #[test]
+    fn broken_migrations_change_nothing() {
+        let dir = TempDir::new("locate-broken");
+        let db = dir.0.join("volume.db");
+        let missing = dir.0.join("missing.db");
+        assert!(matches!(
+            locate(&db, &missing, Some(&missing)),
+            Err(Error::Refused(_))
+        ));
+
+        let garbage = dir.0.join("garbage.db");
+        fs::write(&garbage, b"not a database at all, just some bytes").unwrap();
+        assert!(locate(&db, &missing, Some(&garbage)).is_err());
+        assert!(!db.exists());
+        assert!(!suffixed(&db, "migrating").exists());
+        assert!(garbage.exists());
+
+        // A directory that isn't there, like a volume not mounted.
+        let unmounted = dir.0.join("volume").join("volume.db");
+        let legacy = dir.0.join("legacy.db");
+        Model::open(&legacy).fill_test_data();
+        assert!(locate(&unmounted, &legacy, Some(&legacy)).is_err());
+        assert_eq!(4, row_count(&legacy, "Expense"));
+    }
+}
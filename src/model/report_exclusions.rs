@@ -0,0 +1,68 @@
+//! The categories a user's reports leave out unless told otherwise, stored
+//! with the settings under `report-exclude` as a list of category ids.
+
+use super::{Model, Result};
+use rusqlite::{params, OptionalExtension};
+
+const KEY: &str = "report-exclude";
+
+impl Model {
+    /// The ids of the categories the user's reports leave out.
+    pub fn report_exclusions(&self, user_id: i64) -> Result<Vec<i64>> {
+        let value: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT value FROM UserSetting WHERE userId = ?1 AND key = ?2",
+                params![user_id, KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value
+            .iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|id| id.parse().ok())
+            .collect())
+    }
+
+    /// Replaces the categories the user's reports leave out, none removing
+    /// the setting.
+    pub fn set_report_exclusions(&self, user_id: i64, category_ids: &[i64]) -> Result<()> {
+        self.check_writable()?;
+        if category_ids.is_empty() {
+            self.connection.execute(
+                "DELETE FROM UserSetting WHERE userId = ?1 AND key = ?2",
+                params![user_id, KEY],
+            )?;
+            return Ok(());
+        }
+        let value: Vec<String> = category_ids.iter().map(|id| id.to_string()).collect();
+        self.connection.execute(
+            "INSERT INTO UserSetting (userId, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (userId, key) DO UPDATE SET value = excluded.value",
+            params![user_id, KEY, value.join(",")],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Settings;
+
+    #[test]
+    fn exclusions_roundtrip() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert!(model.report_exclusions(1001).unwrap().is_empty());
+
+        model.set_report_exclusions(1001, &[4, 1]).unwrap();
+        assert_eq!(vec![4, 1], model.report_exclusions(1001).unwrap());
+        assert!(model.report_exclusions(1002).unwrap().is_empty());
+        // Not mistaken for one of the other settings.
+        assert_eq!(Settings::default(), model.get_settings(1001).unwrap());
+
+        model.set_report_exclusions(1001, &[]).unwrap();
+        assert!(model.report_exclusions(1001).unwrap().is_empty());
+    }
+}
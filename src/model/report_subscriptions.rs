@@ -0,0 +1,253 @@
+//! Personal reports users subscribe to with `/subscribe`: a report of their
+//! own expenses of the week or month just finished, sent to them privately
+//! when the next one starts.
+
+use super::{Calendar, Model, Period, Result};
+use crate::time::{self, DbTime};
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+
+/// The local hour reports go out at, on the first day of a week or month.
+pub const DELIVERY_HOUR: u32 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cadence {
+    Weekly,
+    Monthly,
+}
+
+impl Cadence {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cadence::Weekly => "weekly",
+            Cadence::Monthly => "monthly",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Cadence> {
+        match text.trim().to_lowercase().as_str() {
+            "weekly" | "week" => Some(Cadence::Weekly),
+            "monthly" | "month" => Some(Cadence::Monthly),
+            _ => None,
+        }
+    }
+
+    /// The period a report sent at some time covers: the one finished last.
+    pub fn period(self) -> Period {
+        match self {
+            Cadence::Weekly => Period::LastWeek,
+            Cadence::Monthly => Period::LastMonth,
+        }
+    }
+
+    /// When the report after `now` goes out: at [`DELIVERY_HOUR`] on the
+    /// first day of the next week or month.
+    pub fn next_run(self, now: DateTime<Utc>, tz: Tz, calendar: Calendar) -> DateTime<Utc> {
+        let current = match self {
+            Cadence::Weekly => Period::ThisWeek,
+            Cadence::Monthly => Period::ThisMonth,
+        };
+        let next = current.resolve(now, tz, calendar).end;
+        let at = NaiveTime::from_hms_opt(DELIVERY_HOUR, 0, 0).unwrap();
+        time::from_local(next.and_time(at), tz)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportSubscription {
+    pub user_id: i64,
+    pub household: i64,
+    pub cadence: Cadence,
+    pub next_run: DateTime<Utc>,
+}
+
+impl ReportSubscription {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<ReportSubscription> {
+        let cadence: String = row.get(2)?;
+        Ok(ReportSubscription {
+            user_id: row.get(0)?,
+            household: row.get(1)?,
+            cadence: Cadence::parse(&cadence).ok_or_else(|| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    2,
+                    rusqlite::types::Type::Text,
+                    format!("unknown cadence: {}", cadence).into(),
+                )
+            })?,
+            next_run: row.get::<_, DbTime>(3)?.0,
+        })
+    }
+}
+
+const SELECT_SUBSCRIPTION: &str = "
+    SELECT s.userId, u.householdId, s.cadence, s.nextRun
+    FROM Subscription s
+    JOIN User u ON u.telegramId = s.userId
+";
+
+impl Model {
+    /// Subscribes the user to `cadence` reports, the first at `next_run`.
+    /// Returns `false` if they were subscribed already, which keeps their
+    /// next report where it was.
+    pub fn subscribe_report(
+        &self,
+        user_id: i64,
+        cadence: Cadence,
+        next_run: DateTime<Utc>,
+    ) -> Result<bool> {
+        self.check_writable()?;
+        let added = self.connection.execute(
+            "INSERT INTO Subscription (userId, cadence, nextRun) VALUES (?1, ?2, ?3)
+             ON CONFLICT (userId, cadence) DO NOTHING",
+            params![user_id, cadence.as_str(), DbTime(next_run)],
+        )?;
+        Ok(added > 0)
+    }
+
+    /// Returns how many subscriptions the user had.
+    pub fn unsubscribe_reports(&self, user_id: i64) -> Result<usize> {
+        self.check_writable()?;
+        let removed = self
+            .connection
+            .execute("DELETE FROM Subscription WHERE userId = ?1", [user_id])?;
+        Ok(removed)
+    }
+
+    /// The user's subscriptions, weekly first.
+    pub fn report_subscriptions(&self, user_id: i64) -> Result<Vec<ReportSubscription>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE s.userId = ?1 ORDER BY s.cadence = 'monthly'",
+            SELECT_SUBSCRIPTION
+        ))?;
+        let subscriptions = stmt
+            .query_map([user_id], ReportSubscription::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(subscriptions)
+    }
+
+    /// Subscriptions whose next report is due at `now`, however long ago.
+    pub fn due_report_subscriptions(&self, now: DateTime<Utc>) -> Result<Vec<ReportSubscription>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE s.nextRun <= ?1 ORDER BY s.nextRun, s.userId",
+            SELECT_SUBSCRIPTION
+        ))?;
+        let subscriptions = stmt
+            .query_map([DbTime(now)], ReportSubscription::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(subscriptions)
+    }
+
+    pub fn set_report_next_run(
+        &self,
+        user_id: i64,
+        cadence: Cadence,
+        next_run: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connection.execute(
+            "UPDATE Subscription SET nextRun = ?3 WHERE userId = ?1 AND cadence = ?2",
+            params![user_id, cadence.as_str(), DbTime(next_run)],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Weekday};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn next_runs() {
+        let calendar = Calendar::default();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        // Wednesday 29 November 2023.
+        let now = at("2023-11-29T12:00:00Z");
+        assert_eq!(
+            at("2023-12-04T08:00:00Z"),
+            Cadence::Weekly.next_run(now, berlin, calendar)
+        );
+        assert_eq!(
+            at("2023-12-01T08:00:00Z"),
+            Cadence::Monthly.next_run(now, berlin, calendar)
+        );
+        // Delivered, the next one is a period later.
+        assert_eq!(
+            at("2024-01-01T08:00:00Z"),
+            Cadence::Monthly.next_run(at("2023-12-01T08:00:00Z"), berlin, calendar)
+        );
+        let custom = Calendar {
+            week_start: Weekday::Sun,
+            month_start: 25,
+        };
+        assert_eq!(
+            Utc.with_ymd_and_hms(2023, 12, 3, 9, 0, 0).unwrap(),
+            Cadence::Weekly.next_run(now, Tz::UTC, custom)
+        );
+        assert_eq!(
+            Utc.with_ymd_and_hms(2023, 12, 25, 9, 0, 0).unwrap(),
+            Cadence::Monthly.next_run(now, Tz::UTC, custom)
+        );
+    }
+
+    #[test]
+    fn subscriptions() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let first = at("2023-12-04T09:00:00Z");
+
+        assert!(model
+            .subscribe_report(1001, Cadence::Monthly, first)
+            .unwrap());
+        assert!(model
+            .subscribe_report(1001, Cadence::Weekly, first)
+            .unwrap());
+        // Subscribing again keeps the next report where it was.
+        assert!(!model
+            .subscribe_report(1001, Cadence::Weekly, at("2024-01-01T09:00:00Z"))
+            .unwrap());
+        model
+            .subscribe_report(1002, Cadence::Weekly, at("2023-12-11T09:00:00Z"))
+            .unwrap();
+        let cadences: Vec<Cadence> = model
+            .report_subscriptions(1001)
+            .unwrap()
+            .iter()
+            .map(|s| s.cadence)
+            .collect();
+        assert_eq!(vec![Cadence::Weekly, Cadence::Monthly], cadences);
+
+        assert!(model
+            .due_report_subscriptions(first - chrono::TimeDelta::seconds(1))
+            .unwrap()
+            .is_empty());
+        let due = model.due_report_subscriptions(first).unwrap();
+        assert_eq!(
+            vec![(1001, 1), (1001, 1)],
+            due.iter()
+                .map(|s| (s.user_id, s.household))
+                .collect::<Vec<_>>()
+        );
+        model
+            .set_report_next_run(1001, Cadence::Weekly, at("2023-12-11T09:00:00Z"))
+            .unwrap();
+        assert_eq!(
+            vec![Cadence::Monthly],
+            model
+                .due_report_subscriptions(first)
+                .unwrap()
+                .iter()
+                .map(|s| s.cadence)
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(2, model.unsubscribe_reports(1001).unwrap());
+        assert!(model.report_subscriptions(1001).unwrap().is_empty());
+        assert_eq!(1, model.report_subscriptions(1002).unwrap().len());
+        assert_eq!(0, model.unsubscribe_reports(1001).unwrap());
+    }
+}
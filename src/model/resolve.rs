@@ -0,0 +1,228 @@
+//! Turning user typed names into accounts and categories.
+//!
+//! Names are compared folded: case, diacritics and a leading emoji icon are
+//! ignored, so "éating", "🍔 Eating" and "EATING" all mean the same.
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Number of suggestions offered when nothing matches.
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution<T> {
+    Exact(T),
+    /// The only candidate starting with the typed name.
+    UniquePrefix(T),
+    Ambiguous(Vec<T>),
+    /// Closest candidates by edit distance, best first.
+    NotFound(Vec<T>),
+}
+
+/// Normal form names are compared in.
+pub fn fold(name: &str) -> String {
+    let folded: String = name
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .skip_while(|c| !c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Resolves `query` against `candidates`, each known under one or more names.
+pub fn resolve<T>(query: &str, candidates: Vec<(T, Vec<String>)>) -> Resolution<T> {
+    let query = fold(query);
+    let candidates: Vec<(T, Vec<String>)> = candidates
+        .into_iter()
+        .map(|(item, names)| (item, names.iter().map(|n| fold(n)).collect()))
+        .collect();
+
+    let (exact, rest): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|(_, names)| names.contains(&query));
+    if !exact.is_empty() {
+        return single_or_ambiguous(exact, Resolution::Exact);
+    }
+
+    let (prefixed, rest): (Vec<_>, Vec<_>) = rest
+        .into_iter()
+        .partition(|(_, names)| !query.is_empty() && names.iter().any(|n| n.starts_with(&query)));
+    if !prefixed.is_empty() {
+        return single_or_ambiguous(prefixed, Resolution::UniquePrefix);
+    }
+
+    let mut scored: Vec<(usize, usize, T)> = rest
+        .into_iter()
+        .enumerate()
+        .map(|(index, (item, names))| {
+            let distance = names
+                .iter()
+                .map(|n| levenshtein(&query, n))
+                .min()
+                .unwrap_or(usize::MAX);
+            (distance, index, item)
+        })
+        .collect();
+    scored.sort_by_key(|(distance, index, _)| (*distance, *index));
+    Resolution::NotFound(
+        scored
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, _, item)| item)
+            .collect(),
+    )
+}
+
+fn single_or_ambiguous<T>(
+    mut matches: Vec<(T, Vec<String>)>,
+    single: fn(T) -> Resolution<T>,
+) -> Resolution<T> {
+    if matches.len() == 1 {
+        single(matches.remove(0).0)
+    } else {
+        Resolution::Ambiguous(matches.into_iter().map(|(item, _)| item).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names chosen to trip up naive matching: shared prefixes, a name that
+    /// only differs by an accent and icons in front.
+    fn tricky() -> Vec<(&'static str, Vec<String>)> {
+        [
+            "Eating out",
+            "Eating in",
+            "Éating",
+            "🛒 Groceries",
+            "🚌 Transportation",
+            "Utilities",
+        ]
+        .into_iter()
+        .map(|name| (name, vec![name.to_string()]))
+        .collect()
+    }
+
+    #[test]
+    fn folds_case_diacritics_and_icons() {
+        assert_eq!("eating", fold("Éating"));
+        assert_eq!("groceries", fold("🛒 Groceries"));
+        assert_eq!("groceries", fold("  GROCERIES "));
+        assert_eq!("eating out", fold("eating   out"));
+        assert_eq!("creme brulee", fold("Crème Brûlée"));
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(0, levenshtein("abc", "abc"));
+        assert_eq!(3, levenshtein("", "abc"));
+        assert_eq!(1, levenshtein("groceries", "grocries"));
+        assert_eq!(3, levenshtein("kitten", "sitting"));
+    }
+
+    #[test]
+    fn exact_matches() {
+        assert_eq!(Resolution::Exact("Éating"), resolve("eating", tricky()));
+        assert_eq!(Resolution::Exact("Éating"), resolve("ÉATING", tricky()));
+        assert_eq!(
+            Resolution::Exact("Eating out"),
+            resolve("éating out", tricky())
+        );
+        assert_eq!(
+            Resolution::Exact("🛒 Groceries"),
+            resolve("groceries", tricky())
+        );
+        assert_eq!(
+            Resolution::Exact("🛒 Groceries"),
+            resolve("🛒 groceries", tricky())
+        );
+    }
+
+    #[test]
+    fn unique_prefix() {
+        assert_eq!(
+            Resolution::UniquePrefix("Eating out"),
+            resolve("eating o", tricky())
+        );
+        assert_eq!(
+            Resolution::UniquePrefix("Eating in"),
+            resolve("Eating I", tricky())
+        );
+        assert_eq!(
+            Resolution::UniquePrefix("🛒 Groceries"),
+            resolve("gro", tricky())
+        );
+    }
+
+    #[test]
+    fn ambiguous_prefix() {
+        assert_eq!(
+            Resolution::Ambiguous(vec!["Eating out", "Eating in", "Éating"]),
+            resolve("eat", tricky())
+        );
+    }
+
+    #[test]
+    fn duplicate_folded_names_are_ambiguous() {
+        let mut names = tricky();
+        names.push(("Eating", vec!["Eating".to_string()]));
+        assert_eq!(
+            Resolution::Ambiguous(vec!["Éating", "Eating"]),
+            resolve("eating", names)
+        );
+    }
+
+    #[test]
+    fn not_found_suggests_closest() {
+        assert_eq!(
+            Resolution::NotFound(vec!["🛒 Groceries", "Utilities", "Éating"]),
+            resolve("grocries", tricky())
+        );
+        assert_eq!(
+            Resolution::NotFound(vec!["Eating in", "Eating out", "Éating"]),
+            resolve("eatting inn", tricky())
+        );
+        assert_eq!(
+            Resolution::NotFound(Vec::<&str>::new()),
+            resolve("anything", Vec::<(&str, Vec<String>)>::new())
+        );
+    }
+
+    #[test]
+    fn matches_any_of_several_names() {
+        let accounts = vec![
+            (
+                "savings",
+                vec!["Savings".to_string(), "Alex Savings".to_string()],
+            ),
+            (
+                "family",
+                vec!["Family Fund".to_string(), "Family BYN".to_string()],
+            ),
+        ];
+        assert_eq!(
+            Resolution::Exact("family"),
+            resolve("family byn", accounts.clone())
+        );
+        assert_eq!(
+            Resolution::UniquePrefix("savings"),
+            resolve("alex", accounts)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Putting a backup of the database file back in place of the live one,
+//! before the bot opens it.
+
+use super::connection::{open_connection, Storage};
+use super::{schema, AmountLimits, Error, Model, Result};
+use chrono::{DateTime, Utc};
+use log::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Opens a database file without changing it, not even migrating it.
+fn inspect(path: &Path) -> Result<Model> {
+    let connection = open_connection(Storage::ReadOnly(path))?;
+    Ok(Model {
+        connection,
+        amount_limits: AmountLimits::default(),
+        rule_sets: Default::default(),
+    })
+}
+
+impl Model {
+    fn schema_version(&self) -> Result<usize> {
+        let version = self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        Ok(version)
+    }
+
+    /// When the newest expense was made, as stored.
+    fn newest_expense(&self) -> Result<Option<i64>> {
+        let newest =
+            self.connection
+                .query_row("SELECT MAX(timestamp) FROM Expense", [], |row| row.get(0))?;
+        Ok(newest)
+    }
+}
+
+/// Checks that the file at `path` is a sound database the bot can migrate,
+/// returns when its newest expense was made.
+pub(super) fn validate_backup(path: &Path) -> Result<Option<i64>> {
+    let backup = inspect(path)?;
+    let version = backup.schema_version()?;
+    if version == 0 || version > schema::latest_version() {
+        return Err(Error::Refused(format!(
+            "{} has schema version {}, this bot reads versions 1 to {}.",
+            path.display(),
+            version,
+            schema::latest_version()
+        )));
+    }
+    info!("Backup schema version: {}", version);
+    let report = backup.check_integrity()?;
+    if !report.is_ok() {
+        for problem in &report.problems {
+            error!("Backup integrity check: {}", problem);
+        }
+        for orphan in &report.orphans {
+            error!("Backup integrity check: {}", orphan);
+        }
+        return Err(Error::Refused(format!(
+            "{} failed the integrity check.",
+            path.display()
+        )));
+    }
+    info!("Backup passed the integrity check");
+    backup.newest_expense()
+}
+
+/// Replaces the database at `db` with the backup at `backup`. The current
+/// file, if any, is kept next to it with `now` appended to its name, and is
+/// returned. A database with expenses newer than the backup's would lose
+/// them, so it is only replaced when `force` is set.
+pub fn restore(
+    db: &Path,
+    backup: &Path,
+    force: bool,
+    now: DateTime<Utc>,
+) -> Result<Option<PathBuf>> {
+    info!("Restoring {} from {}", db.display(), backup.display());
+    let backup_newest = validate_backup(backup)?;
+
+    if db.exists() && !force {
+        let current_newest = inspect(db)
+            .and_then(|current| current.newest_expense())
+            .map_err(|e| {
+                Error::Refused(format!(
+                    "Can't read expenses of {} ({}), use --force to replace it anyway.",
+                    db.display(),
+                    e
+                ))
+            })?;
+        if current_newest > backup_newest {
+            return Err(Error::Refused(format!(
+                "{} has expenses newer than the backup, use --force to replace it anyway.",
+                db.display()
+            )));
+        }
+    }
+
+    // The copy is complete before the current file goes anywhere.
+    let staged = suffixed(db, "restoring");
+    fs::copy(backup, &staged).map_err(|e| io_refused("Copying the backup", &staged, e))?;
+    info!("Copied the backup to {}", staged.display());
+
+    let aside = if db.exists() {
+        let aside = suffixed(db, &now.format("%Y%m%d-%H%M%S").to_string());
+        rename_database(db, &aside)?;
+        info!("Moved the current database to {}", aside.display());
+        Some(aside)
+    } else {
+        None
+    };
+    fs::rename(&staged, db).map_err(|e| io_refused("Moving the backup", db, e))?;
+    info!("Restored {} from {}", db.display(), backup.display());
+    Ok(aside)
+}
+
+/// Renames the database file at `from`, and the write-ahead log and shared
+/// memory files next to it if there are any, so that a log left by a crash
+/// isn't replayed on whatever takes its place.
+pub(super) fn rename_database(from: &Path, to: &Path) -> Result<()> {
+    fs::rename(from, to).map_err(|e| io_refused("Moving the database", to, e))?;
+    for journal in ["-wal", "-shm"] {
+        let (from, to) = (appended(from, journal), appended(to, journal));
+        if from.exists() {
+            fs::rename(&from, &to).map_err(|e| io_refused("Moving the journal", &to, e))?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn appended(path: &Path, text: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(text);
+    path.with_file_name(name)
+}
+
+/// `path` with `.suffix` appended to its file name.
+pub(super) fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    appended(path, &format!(".{}", suffix))
+}
+
+pub(super) fn io_refused(what: &str, path: &Path, e: std::io::Error) -> Error {
+    Error::Refused(format!("{} to {} failed: {}", what, path.display(), e))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense, DEFAULT_HOUSEHOLD};
+
+    /// A fresh directory for one test, removed when it ends.
+    pub(in crate::model) struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!(
+                "spending-tracker-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_702_000_000, 0).unwrap()
+    }
+
+    pub(in crate::model) fn row_count(path: &Path, table: &str) -> i64 {
+        let report = Model::open(path).check_integrity().unwrap();
+        report
+            .row_counts
+            .into_iter()
+            .find(|(name, _)| name == table)
+            .unwrap()
+            .1
+    }
+
+    fn write_backup(path: &Path) {
+        Model::open(path).fill_test_data();
+    }
+
+    #[test]
+    fn restores_into_an_empty_directory() {
+        let dir = TempDir::new("restore-empty");
+        let (db, backup) = (dir.0.join("live.db"), dir.0.join("backup.db"));
+        write_backup(&backup);
+
+        assert_eq!(None, restore(&db, &backup, false, now()).unwrap());
+        assert_eq!(4, row_count(&db, "Expense"));
+        assert_eq!(3, row_count(&db, "Account"));
+        // The backup itself is left alone.
+        assert_eq!(4, row_count(&backup, "Expense"));
+        assert!(!suffixed(&db, "restoring").exists());
+    }
+
+    #[test]
+    fn keeps_the_replaced_database() {
+        let dir = TempDir::new("restore-aside");
+        let (db, backup) = (dir.0.join("live.db"), dir.0.join("backup.db"));
+        write_backup(&backup);
+        Model::open(&db);
+
+        let aside = restore(&db, &backup, false, now()).unwrap().unwrap();
+        assert_eq!(dir.0.join("live.db.20231208-014640"), aside);
+        assert_eq!(0, row_count(&aside, "Expense"));
+        assert_eq!(4, row_count(&db, "Expense"));
+    }
+
+    #[test]
+    fn journal_left_by_a_crash_moves_aside() {
+        let dir = TempDir::new("restore-journal");
+        let (db, backup) = (dir.0.join("live.db"), dir.0.join("backup.db"));
+        write_backup(&backup);
+        // Never closed, as if the bot crashed: the accounts are only in the
+        // write-ahead log.
+        let crashed = Model::open(&db);
+        crashed.fill_test_data();
+        std::mem::forget(crashed);
+        assert!(appended(&db, "-wal").exists());
+
+        let aside = restore(&db, &backup, true, now()).unwrap().unwrap();
+        assert!(!appended(&db, "-wal").exists());
+        assert_eq!(4, row_count(&db, "Expense"));
+        assert_eq!(3, row_count(&aside, "Account"));
+    }
+
+    #[test]
+    fn newer_expenses_need_force() {
+        let dir = TempDir::new("restore-newer");
+        let (db, backup) = (dir.0.join("live.db"), dir.0.join("backup.db"));
+        write_backup(&backup);
+        let live = Model::open(&db);
+        live.fill_test_data();
+        live.insert_expense_once(
+            DEFAULT_HOUSEHOLD,
+            "newer",
+            &NewExpense {
+                account_id: 1,
+                category_id: 1,
+                user_id: 1001,
+                timestamp: now(),
+                amount: 3.0,
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            },
+        )
+        .unwrap();
+        drop(live);
+
+        assert!(matches!(
+            restore(&db, &backup, false, now()),
+            Err(Error::Refused(_))
+        ));
+        assert_eq!(5, row_count(&db, "Expense"));
+
+        let aside = restore(&db, &backup, true, now()).unwrap().unwrap();
+        assert_eq!(4, row_count(&db, "Expense"));
+        assert_eq!(5, row_count(&aside, "Expense"));
+    }
+
+    #[test]
+    fn refuses_unusable_backups() {
+        let dir = TempDir::new("restore-unusable");
+        let db = dir.0.join("live.db");
+        Model::open(&db).fill_test_data();
+
+        let future = dir.0.join("future.db");
+        write_backup(&future);
+        rusqlite::Connection::open(&future)
+            .unwrap()
+            .pragma_update(None, "user_version", 999)
+            .unwrap();
+        assert!(matches!(
+            restore(&db, &future, true, now()),
+            Err(Error::Refused(_))
+        ));
+
+        let garbage = dir.0.join("garbage.db");
+        fs::write(&garbage, b"not a database at all, just some bytes").unwrap();
+        assert!(restore(&db, &garbage, true, now()).is_err());
+
+        let missing = dir.0.join("missing.db");
+        assert!(restore(&db, &missing, true, now()).is_err());
+
+        // Nothing was moved or staged, the journal files of the live
+        // database aside.
+        assert_eq!(4, row_count(&db, "Expense"));
+        let files = fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| !name.ends_with("-wal") && !name.ends_with("-shm"))
+            .count();
+        assert_eq!(3, files);
+    }
+}
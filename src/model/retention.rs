@@ -0,0 +1,229 @@
+//! Deleting the rows of tables that otherwise only grow, once they are
+//! older than they are kept for: the audit log and the stored callback
+//! payloads.
+
+use super::{audit, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, TimeDelta, Utc};
+use log::*;
+use std::time::{Duration, Instant};
+
+/// Rows deleted in one transaction, so that pruning a large table doesn't
+/// hold the database for long.
+const PRUNE_BATCH: usize = 1000;
+
+/// A table whose old rows are pruned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retained {
+    AuditLog,
+    CallbackPayload,
+}
+
+impl Retained {
+    fn table(self) -> &'static str {
+        match self {
+            Retained::AuditLog => "AuditLog",
+            Retained::CallbackPayload => "CallbackPayload",
+        }
+    }
+
+    /// The column of when a row was written.
+    fn written_at(self) -> &'static str {
+        match self {
+            Retained::AuditLog => "timestamp",
+            Retained::CallbackPayload => "createdAt",
+        }
+    }
+}
+
+/// How many days the rows of `table` are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub table: Retained,
+    pub days: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    /// Rows deleted under each policy, in the order given.
+    pub pruned: Vec<(RetentionPolicy, usize)>,
+    pub took: Duration,
+}
+
+impl Model {
+    /// Deletes the rows written before `cutoff`, `batch` at a time, and
+    /// returns how many. A row written at `cutoff` itself is kept.
+    fn prune_table(&self, table: Retained, cutoff: DateTime<Utc>, batch: usize) -> Result<usize> {
+        let sql = format!(
+            "DELETE FROM {table} WHERE rowid IN
+                 (SELECT rowid FROM {table} WHERE {column} < ?1 LIMIT ?2)",
+            table = table.table(),
+            column = table.written_at(),
+        );
+        let mut total = 0;
+        loop {
+            let tx = self.connection.unchecked_transaction()?;
+            let deleted = tx.execute(&sql, rusqlite::params![DbTime(cutoff), batch as i64])?;
+            tx.commit()?;
+            total += deleted;
+            if deleted < batch {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Deletes the rows older than their policy keeps them at `now`, in
+    /// batches of [`PRUNE_BATCH`]. `user_id` is who asked, 0 for the
+    /// schedule; what was deleted goes to the audit log after pruning it.
+    pub fn prune_retention(
+        &self,
+        policies: &[RetentionPolicy],
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<PruneReport> {
+        self.check_writable()?;
+        let started = Instant::now();
+        let mut pruned = Vec::new();
+        for policy in policies {
+            let cutoff = now - TimeDelta::days(policy.days.into());
+            let deleted = self.prune_table(policy.table, cutoff, PRUNE_BATCH)?;
+            pruned.push((*policy, deleted));
+        }
+        let counts: Vec<String> = pruned
+            .iter()
+            .map(|(policy, deleted)| format!("{} {}", policy.table.table(), deleted))
+            .collect();
+        audit::record(&self.connection, user_id, "prune", &counts.join(", "))?;
+        let report = PruneReport {
+            pruned,
+            took: started.elapsed(),
+        };
+        info!("Pruning took {:?}: {}", report.took, counts.join(", "));
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Error;
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    /// An audit log of one entry, numbered by its id, per timestamp.
+    fn model_with_audit_log(timestamps: &[i64]) -> Model {
+        let model = Model::new(true);
+        for timestamp in timestamps {
+            model
+                .connection
+                .execute(
+                    "INSERT INTO AuditLog (timestamp, userId, action, details)
+                     VALUES (?1, 1001, 'test', '')",
+                    [timestamp],
+                )
+                .unwrap();
+        }
+        model
+    }
+
+    fn audit_ids(model: &Model) -> Vec<i64> {
+        let mut stmt = model
+            .connection
+            .prepare("SELECT id FROM AuditLog ORDER BY id")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn deletes_in_batches_up_to_the_cutoff() {
+        let cutoff = 1_700_000_000;
+        // Older, older, at the cutoff, newer, and so on.
+        let timestamps = [
+            cutoff - 86_400,
+            cutoff - 1,
+            cutoff,
+            cutoff + 1,
+            cutoff - 5,
+            cutoff - 3,
+            cutoff - 2,
+            cutoff + 86_400,
+            cutoff - 7,
+        ];
+        let older = timestamps.iter().filter(|t| **t < cutoff).count();
+        assert_eq!(6, older);
+        // Fewer than a batch, a batch and more, exactly two, one at a time.
+        for batch in [10, 4, 3, 1] {
+            let model = model_with_audit_log(&timestamps);
+            let deleted = model
+                .prune_table(Retained::AuditLog, at(cutoff), batch)
+                .unwrap();
+            assert_eq!(older, deleted, "batch {}", batch);
+            assert_eq!(vec![3, 4, 8], audit_ids(&model), "batch {}", batch);
+            // Nothing is left to delete, and nothing more is.
+            assert_eq!(
+                0,
+                model
+                    .prune_table(Retained::AuditLog, at(cutoff), batch)
+                    .unwrap()
+            );
+            assert_eq!(3, audit_ids(&model).len());
+        }
+
+        let model = model_with_audit_log(&[]);
+        assert_eq!(
+            0,
+            model
+                .prune_table(Retained::AuditLog, at(cutoff), 3)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn prunes_every_table_of_the_policies() {
+        let now = at(1_700_000_000);
+        let day = TimeDelta::days(1);
+        let model = model_with_audit_log(&[
+            (now - day * 400).timestamp(),
+            (now - day * 365).timestamp(),
+            (now - day * 10).timestamp(),
+        ]);
+        let old = model
+            .store_callback_payload("recat:1:2", now - day * 3)
+            .unwrap();
+        let fresh = model.store_callback_payload("recat:1:3", now).unwrap();
+        let policies = [
+            RetentionPolicy {
+                table: Retained::AuditLog,
+                days: 365,
+            },
+            RetentionPolicy {
+                table: Retained::CallbackPayload,
+                days: 2,
+            },
+        ];
+
+        let report = model.prune_retention(&policies, 1001, now).unwrap();
+        assert_eq!(vec![(policies[0], 1), (policies[1], 1)], report.pruned);
+        assert_eq!(None, model.callback_payload(&old, now - day * 3).unwrap());
+        assert!(model.callback_payload(&fresh, now).unwrap().is_some());
+        // The one at the cutoff and the newer one stay, and the pruning is
+        // logged after them.
+        let entries = model.audit_entries().unwrap();
+        assert_eq!(vec![2, 3, 4], audit_ids(&model));
+        assert_eq!(
+            ("prune", "AuditLog 1, CallbackPayload 1"),
+            (entries[2].action.as_str(), entries[2].details.as_str())
+        );
+
+        model.set_read_only(1001, true).unwrap();
+        assert!(matches!(
+            model.prune_retention(&policies, 1001, now),
+            Err(Error::ReadOnly)
+        ));
+    }
+}
@@ -0,0 +1,302 @@
+//! Expenses that were probably logged in a hurry and deserve a second look.
+
+use super::amount::from_minor;
+use super::expenses::{of_household, ExpenseDetails, ExpenseSource, SELECT_DETAILS};
+use super::{DateRange, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+
+/// What makes an expense worth reviewing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReviewRules {
+    /// Expenses above this amount, in their own currency, should say what
+    /// they were for.
+    pub comment_over: f64,
+}
+
+impl Default for ReviewRules {
+    fn default() -> Self {
+        ReviewRules { comment_over: 50.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewReason {
+    /// Saved with the default category, the picker was never opened.
+    DefaultCategory,
+    MissingComment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewCandidate {
+    pub expense: ExpenseDetails,
+    pub reasons: Vec<ReviewReason>,
+}
+
+/// How far a period's expenses can be taken at face value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Completeness {
+    pub expenses: usize,
+    /// Read from forwards rather than typed.
+    pub imported: usize,
+    /// Those matching any of the review rules.
+    pub needs_review: usize,
+}
+
+impl ReviewRules {
+    fn reasons(&self, expense: &ExpenseDetails) -> Vec<ReviewReason> {
+        self.reasons_of(
+            expense.category_confirmed,
+            expense.comment.is_some(),
+            expense.amount,
+        )
+    }
+
+    fn reasons_of(
+        &self,
+        category_confirmed: bool,
+        commented: bool,
+        amount: f64,
+    ) -> Vec<ReviewReason> {
+        let mut reasons = Vec::new();
+        if !category_confirmed {
+            reasons.push(ReviewReason::DefaultCategory);
+        }
+        if !commented && amount > self.comment_over {
+            reasons.push(ReviewReason::MissingComment);
+        }
+        reasons
+    }
+}
+
+impl Model {
+    /// Expenses of the household logged since `since` matching any of the
+    /// rules, oldest first.
+    pub fn find_review_candidates(
+        &self,
+        household: i64,
+        since: DateTime<Utc>,
+        rules: &ReviewRules,
+    ) -> Result<Vec<ReviewCandidate>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE e.timestamp >= ?1 AND (e.categoryConfirmed = 0 OR e.comments IS NULL)
+                AND {}
+             ORDER BY e.timestamp, e.id",
+            SELECT_DETAILS,
+            of_household(2)
+        ))?;
+        let expenses = stmt
+            .query_map(params![DbTime(since), household], ExpenseDetails::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(expenses
+            .into_iter()
+            .map(|expense| ReviewCandidate {
+                reasons: rules.reasons(&expense),
+                expense,
+            })
+            .filter(|c| !c.reasons.is_empty())
+            .collect())
+    }
+
+    /// How many of the household's expenses over the local days of `range`
+    /// were imported, and how many match the review rules.
+    pub fn completeness_stats(
+        &self,
+        household: i64,
+        range: DateRange,
+        rules: &ReviewRules,
+        tz: Tz,
+    ) -> Result<Completeness> {
+        let (start, end) = range.to_utc(tz);
+        let tables = self.expense_tables(Some(start))?;
+        // Archives aren't read back with their sources, archived expenses
+        // count as typed.
+        let mut stmt = self.connection.prepare(&tables.apply(
+            "SELECT e.amountMinor, COALESCE(c.exponent, 2), e.categoryConfirmed,
+                    e.comments IS NOT NULL, m.source IS ?4
+             FROM {expenses} e
+             JOIN Account a ON a.id = e.accountId
+             LEFT JOIN Currency c ON c.id = a.currencyId
+             LEFT JOIN main.Expense m ON m.id = e.id
+             WHERE e.timestamp >= ?1 AND e.timestamp < ?2 AND a.householdId = ?3
+                 AND e.kind = 'expense'",
+        ))?;
+        let params = params![
+            DbTime(start),
+            DbTime(end),
+            household,
+            ExpenseSource::Forward.as_str()
+        ];
+        let mut rows = stmt.query(params)?;
+        let mut completeness = Completeness::default();
+        while let Some(row) = rows.next()? {
+            let amount = from_minor(row.get(0)?, row.get(1)?);
+            completeness.expenses += 1;
+            if row.get(4)? {
+                completeness.imported += 1;
+            }
+            if !rules
+                .reasons_of(row.get(2)?, row.get(3)?, amount)
+                .is_empty()
+            {
+                completeness.needs_review += 1;
+            }
+        }
+        Ok(completeness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::NewExpense;
+
+    fn add(model: &Model, amount: f64, comment: Option<&str>, confirmed: bool) -> i64 {
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id: 1,
+                user_id: 1001,
+                timestamp: DateTime::from_timestamp(1_704_067_200, 0).unwrap(),
+                amount,
+                comment: comment.map(str::to_string),
+                category_confirmed: confirmed,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_lazy_expenses() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let unconfirmed = add(&model, 5.0, Some("lunch"), false);
+        let big = add(&model, 80.0, None, true);
+        let both = add(&model, 60.0, None, false);
+        add(&model, 20.0, None, true);
+        add(&model, 80.0, Some("shoes"), true);
+
+        let since = DateTime::from_timestamp(1_704_000_000, 0).unwrap();
+        let found: Vec<(i64, Vec<ReviewReason>)> = model
+            .find_review_candidates(1, since, &ReviewRules::default())
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.expense.id, c.reasons))
+            .collect();
+        assert_eq!(
+            vec![
+                (unconfirmed, vec![ReviewReason::DefaultCategory]),
+                (big, vec![ReviewReason::MissingComment]),
+                (
+                    both,
+                    vec![ReviewReason::DefaultCategory, ReviewReason::MissingComment]
+                ),
+            ],
+            found
+        );
+
+        let strict = ReviewRules { comment_over: 0.0 };
+        assert_eq!(
+            4,
+            model
+                .find_review_candidates(1, since, &strict)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn counts_how_complete_a_month_is() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let december = DateRange::inclusive(
+            chrono::NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            chrono::NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+        let stats = |household| {
+            model
+                .completeness_stats(household, december, &ReviewRules::default(), Tz::UTC)
+                .unwrap()
+        };
+        // The test data is all typed, commented and categorized.
+        assert_eq!(
+            Completeness {
+                expenses: 4,
+                imported: 0,
+                needs_review: 0
+            },
+            stats(1)
+        );
+
+        let log = |amount: f64, confirmed: bool, source: ExpenseSource| {
+            model
+                .insert_expense(&NewExpense {
+                    account_id: 1,
+                    category_id: 1,
+                    user_id: 1001,
+                    timestamp: DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+                    amount,
+                    comment: None,
+                    category_confirmed: confirmed,
+                    source,
+                })
+                .unwrap()
+        };
+        let forwarded = log(80.0, true, ExpenseSource::Forward);
+        log(5.0, false, ExpenseSource::Manual);
+        log(10.0, true, ExpenseSource::Favorite);
+        log(12.0, true, ExpenseSource::Forward);
+        assert_eq!(
+            Completeness {
+                expenses: 8,
+                imported: 2,
+                needs_review: 2
+            },
+            stats(1)
+        );
+        let source: String = model
+            .connection
+            .query_row(
+                "SELECT source FROM Expense WHERE id = ?1",
+                [forwarded],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("forward", source);
+        // Only the household's own.
+        assert_eq!(1, stats(2).expenses);
+        let january = DateRange::inclusive(
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+        );
+        assert_eq!(
+            Completeness::default(),
+            model
+                .completeness_stats(1, january, &ReviewRules::default(), Tz::UTC)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn only_looks_at_recent_expenses() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        add(&model, 80.0, None, false);
+
+        let count = |since| {
+            model
+                .find_review_candidates(
+                    1,
+                    DateTime::from_timestamp(since, 0).unwrap(),
+                    &ReviewRules::default(),
+                )
+                .unwrap()
+                .len()
+        };
+        assert_eq!(1, count(1_704_067_200));
+        assert_eq!(0, count(1_704_067_201));
+    }
+}
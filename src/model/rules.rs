@@ -0,0 +1,255 @@
+//! Rules an admin sets up to pick the category of new drafts from their
+//! comment, like `uber|bolt|taxi` for Transportation.
+
+use super::lengths::{check_length, MAX_NAME_LEN};
+use super::{Error, Model, Result};
+use log::*;
+use regex::{Regex, RegexBuilder};
+use rusqlite::params;
+
+/// Largest compiled pattern, so that no rule makes matching slow.
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub id: i64,
+    pub pattern: String,
+    pub category_id: i64,
+    pub category: String,
+    /// Rules are tried from the lowest priority on.
+    pub priority: i64,
+}
+
+impl Rule {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Rule> {
+        Ok(Rule {
+            id: row.get(0)?,
+            pattern: row.get(1)?,
+            category_id: row.get(2)?,
+            category: row.get(3)?,
+            priority: row.get(4)?,
+        })
+    }
+}
+
+/// Patterns match anywhere in the comment, ignoring case.
+pub(super) fn compile(pattern: &str) -> std::result::Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(MAX_COMPILED_SIZE)
+        .build()
+}
+
+/// The rules of a household, compiled and in the order they are tried.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<(Regex, Rule)>,
+}
+
+impl RuleSet {
+    /// `rules` are expected by priority. Those whose pattern doesn't
+    /// compile, which creating them refuses, are left out.
+    pub fn new(rules: Vec<Rule>) -> RuleSet {
+        let rules = rules
+            .into_iter()
+            .filter_map(|rule| match compile(&rule.pattern) {
+                Ok(regex) => Some((regex, rule)),
+                Err(e) => {
+                    warn!("Skipping rule {}: {}", rule.id, e);
+                    None
+                }
+            })
+            .collect();
+        RuleSet { rules }
+    }
+
+    /// The first rule matching `text`.
+    pub fn first_match(&self, text: &str) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(text))
+            .map(|(_, rule)| rule)
+    }
+}
+
+const SELECT_RULE: &str = "
+    SELECT r.id, r.pattern, r.categoryId, ec.name, r.priority
+    FROM Rule r
+    JOIN ExpenseCategory ec ON ec.id = r.categoryId
+";
+
+impl Model {
+    /// Adds a rule after the household's others, returns its id. Patterns
+    /// that aren't a valid regular expression are refused.
+    pub fn add_rule(&self, household: i64, pattern: &str, category_id: i64) -> Result<i64> {
+        self.check_writable()?;
+        check_length("Pattern", pattern, MAX_NAME_LEN)?;
+        if let Err(e) = compile(pattern) {
+            return Err(Error::Refused(format!("Invalid pattern: {}", e)));
+        }
+        self.get_category(household, category_id)?
+            .ok_or_else(|| Error::NotFound(format!("category {}", category_id)))?;
+        self.connection.execute(
+            "INSERT INTO Rule (householdId, pattern, categoryId, priority)
+             SELECT ?1, ?2, ?3, COALESCE(MAX(priority), 0) + 1
+             FROM Rule WHERE householdId = ?1",
+            params![household, pattern, category_id],
+        )?;
+        let id = self.connection.last_insert_rowid();
+        self.rule_sets.borrow_mut().remove(&household);
+        info!("Added rule {} of household {}", id, household);
+        Ok(id)
+    }
+
+    /// Rules of the household by priority.
+    pub fn rules(&self, household: i64) -> Result<Vec<Rule>> {
+        self.load_rules(household, false)
+    }
+
+    fn load_rules(&self, household: i64, only_active: bool) -> Result<Vec<Rule>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE r.householdId = ?1 AND (ec.active OR NOT ?2)
+             ORDER BY r.priority, r.id",
+            SELECT_RULE
+        ))?;
+        let rules = stmt
+            .query_map(params![household, only_active], Rule::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rules)
+    }
+
+    /// Returns whether the household had the rule.
+    pub fn delete_rule(&self, household: i64, id: i64) -> Result<bool> {
+        self.check_writable()?;
+        let deleted = self.connection.execute(
+            "DELETE FROM Rule WHERE id = ?1 AND householdId = ?2",
+            [id, household],
+        )?;
+        self.rule_sets.borrow_mut().remove(&household);
+        Ok(deleted > 0)
+    }
+
+    /// The first of the household's rules matching `text`. Rules of
+    /// archived categories don't match. The compiled rules are kept until
+    /// they change.
+    pub fn matching_rule(&self, household: i64, text: &str) -> Result<Option<Rule>> {
+        if let Some(rules) = self.rule_sets.borrow().get(&household) {
+            return Ok(rules.first_match(text).cloned());
+        }
+        let rules = RuleSet::new(self.load_rules(household, true)?);
+        let matched = rules.first_match(text).cloned();
+        self.rule_sets.borrow_mut().insert(household, rules);
+        Ok(matched)
+    }
+
+    /// Forgets the compiled rules of all households, after their
+    /// categories changed.
+    pub(super) fn forget_rules(&self) {
+        self.rule_sets.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: i64, pattern: &str, category: &str) -> Rule {
+        Rule {
+            id,
+            pattern: pattern.to_string(),
+            category_id: id,
+            category: category.to_string(),
+            priority: id,
+        }
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let rules = RuleSet::new(vec![
+            rule(1, "taxi|uber", "Transportation"),
+            rule(2, "(", "Broken"),
+            rule(3, "airport", "Travel"),
+            rule(4, "^coffee$", "Eating out"),
+        ]);
+        let matched = |text| rules.first_match(text).map(|r| r.id);
+        assert_eq!(Some(1), matched("Uber to the airport"));
+        assert_eq!(Some(3), matched("airport parking"));
+        assert_eq!(Some(4), matched("Coffee"));
+        // Nothing matches, the broken rule included.
+        assert_eq!(None, matched("coffee and cake"));
+        assert_eq!(None, matched("("));
+        assert_eq!(None, RuleSet::default().first_match("taxi"));
+    }
+
+    #[test]
+    fn rules_are_stored_by_priority() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+
+        let taxi = model.add_rule(1, "taxi|bolt", 2).unwrap();
+        assert_eq!(
+            Some(taxi),
+            model.matching_rule(1, "Bolt home").unwrap().map(|r| r.id)
+        );
+        assert_eq!(None, model.matching_rule(1, "electricity").unwrap());
+        assert_eq!(None, model.matching_rule(2, "taxi").unwrap());
+
+        // Adding and deleting drop the compiled rules.
+        let bills = model.add_rule(1, "electricity|taxi", 4).unwrap();
+        assert_eq!(
+            Some(bills),
+            model.matching_rule(1, "electricity").unwrap().map(|r| r.id)
+        );
+        assert_eq!(
+            Some(taxi),
+            model.matching_rule(1, "taxi").unwrap().map(|r| r.id)
+        );
+        let rules = model.rules(1).unwrap();
+        assert_eq!(
+            vec![(taxi, 1, "Transportation"), (bills, 2, "Utilities")],
+            rules
+                .iter()
+                .map(|r| (r.id, r.priority, r.category.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert!(!model.delete_rule(2, taxi).unwrap());
+        assert!(model.delete_rule(1, taxi).unwrap());
+        assert_eq!(
+            Some(bills),
+            model.matching_rule(1, "taxi").unwrap().map(|r| r.id)
+        );
+
+        assert!(matches!(
+            model.add_rule(1, "taxi(", 2),
+            Err(Error::Refused(reason)) if reason.starts_with("Invalid pattern: ")
+        ));
+        assert!(matches!(
+            model.add_rule(1, &"a".repeat(101), 2),
+            Err(Error::TooLong { .. })
+        ));
+        assert!(matches!(
+            model.add_rule(1, "seeds", 5),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn merging_moves_the_rules() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let id = model.add_rule(1, "cinema", 3).unwrap();
+        assert_eq!(
+            3,
+            model
+                .matching_rule(1, "cinema")
+                .unwrap()
+                .unwrap()
+                .category_id
+        );
+
+        model.merge_categories(1, 1001, (3, 1), false).unwrap();
+        let rule = model.matching_rule(1, "cinema").unwrap().unwrap();
+        assert_eq!((id, 1), (rule.id, rule.category_id));
+    }
+}
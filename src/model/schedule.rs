@@ -0,0 +1,61 @@
+//! When each background task last ran, so that a restart doesn't run them
+//! all again at once. Kept in `BotSetting` as `lastRun:<task>`, the unix
+//! seconds as text.
+
+use super::{Model, Result};
+use crate::time;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension};
+
+fn key(task: &str) -> String {
+    format!("lastRun:{}", task)
+}
+
+impl Model {
+    /// When the task last ran, `None` if never.
+    pub fn task_last_run(&self, task: &str) -> Result<Option<DateTime<Utc>>> {
+        let value = self
+            .connection
+            .query_row(
+                "SELECT value FROM BotSetting WHERE key = ?1",
+                [key(task)],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        Ok(value
+            .and_then(|value| value.parse().ok())
+            .and_then(time::from_db))
+    }
+
+    /// Records that the task ran at `at`. This is bookkeeping of the bot,
+    /// read-only mode doesn't hold it back.
+    pub fn set_task_last_run(&self, task: &str, at: DateTime<Utc>) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO BotSetting (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key(task), time::to_db(at).to_string()],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_runs_per_task() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let at = DateTime::from_timestamp(1_702_000_000, 0).unwrap();
+        assert_eq!(None, model.task_last_run("callback sweep").unwrap());
+
+        model.set_task_last_run("callback sweep", at).unwrap();
+        model.set_read_only(1001, true).unwrap();
+        let later = at + chrono::TimeDelta::hours(1);
+        model.set_task_last_run("callback sweep", later).unwrap();
+        assert_eq!(Some(later), model.task_last_run("callback sweep").unwrap());
+        assert_eq!(None, model.task_last_run("maintenance").unwrap());
+        assert!(model.read_only().unwrap());
+    }
+}
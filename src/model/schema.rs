@@ -1,7 +1,6 @@
 use log::*;
 
-const SCHEMA_V1: &str =
-"
+const SCHEMA_V1: &str = "
 CREATE TABLE Currency (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name TEXT NOT NULL UNIQUE
@@ -43,33 +42,860 @@ CREATE TABLE Expense (
 );
 ";
 
+const SCHEMA_V2: &str = "
+ALTER TABLE User ADD COLUMN role TEXT NOT NULL DEFAULT 'member'
+    CHECK (role IN ('admin', 'member', 'viewer'));
+";
+
+const SCHEMA_V3: &str = "
+ALTER TABLE Account ADD COLUMN initialBalance REAL NOT NULL DEFAULT 0;
+
+-- Rates are relative to the configured base currency: one unit of currencyId
+-- is worth `rate` units of the base currency from `date` (YYYY-MM-DD) on.
+CREATE TABLE ExchangeRate (
+    currencyId INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    rate REAL NOT NULL,
+    PRIMARY KEY (currencyId, date),
+    FOREIGN KEY (currencyId) REFERENCES Currency (id) ON DELETE CASCADE
+);
+";
+
+const SCHEMA_V4: &str = "
+ALTER TABLE ExpenseCategory ADD COLUMN icon TEXT;
+";
+
+// Amounts move to integer minor units of their currency, `exponent` being
+// the number of decimal places. The old float column is kept as
+// `amountLegacy` so converted values can be checked against it.
+const SCHEMA_V5: &str = "
+ALTER TABLE Currency ADD COLUMN exponent INTEGER NOT NULL DEFAULT 2
+    CHECK (exponent BETWEEN 0 AND 6);
+
+CREATE TABLE ExpenseV5 (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    accountId INTEGER NOT NULL,
+    categoryId INTEGER NOT NULL,
+    userId INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    amountMinor INTEGER NOT NULL,
+    amountLegacy REAL,
+    comments TEXT,
+    FOREIGN KEY (accountId) REFERENCES Account (id) ON DELETE RESTRICT,
+    FOREIGN KEY (categoryId) REFERENCES ExpenseCategory (id) ON DELETE RESTRICT,
+    FOREIGN KEY (userId) REFERENCES User (telegramId) ON DELETE RESTRICT
+);
+
+INSERT INTO ExpenseV5 (id, accountId, categoryId, userId, timestamp, amountMinor, amountLegacy, comments)
+SELECT id, accountId, categoryId, userId, timestamp, CAST(ROUND(amount * 100) AS INTEGER), amount, comments
+FROM Expense;
+
+DROP TABLE Expense;
+ALTER TABLE ExpenseV5 RENAME TO Expense;
+";
+
+const SCHEMA_V6: &str = "
+-- Who did what, for changes touching many rows at once.
+CREATE TABLE AuditLog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    userId INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    details TEXT NOT NULL
+);
+";
+
+const SCHEMA_V7: &str = "
+-- Whether the category was chosen rather than left at the default. Older
+-- expenses are given the benefit of the doubt.
+ALTER TABLE Expense ADD COLUMN categoryConfirmed INTEGER NOT NULL DEFAULT 1;
+";
+
+const SCHEMA_V8: &str = "
+-- Identifies what an expense was committed from, so that committing the
+-- same draft or command again finds it instead of saving a duplicate.
+ALTER TABLE Expense ADD COLUMN idempotencyKey TEXT;
+CREATE UNIQUE INDEX ExpenseIdempotencyKey ON Expense (idempotencyKey);
+";
+
+const SCHEMA_V9: &str = "
+-- Preferences differing from the defaults, one row per user and setting.
+CREATE TABLE UserSetting (
+    userId INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (userId, key)
+);
+";
+
+const SCHEMA_V10: &str = "
+-- Expenses a user logs often, committed again with one tap. The amount is in
+-- minor units of currencyId, the currency of the account when it was added.
+CREATE TABLE Favorite (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    userId INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    amountMinor INTEGER NOT NULL,
+    currencyId INTEGER NOT NULL,
+    accountId INTEGER NOT NULL,
+    categoryId INTEGER NOT NULL,
+    comment TEXT,
+    FOREIGN KEY (userId) REFERENCES User (telegramId) ON DELETE CASCADE,
+    FOREIGN KEY (currencyId) REFERENCES Currency (id) ON DELETE RESTRICT,
+    FOREIGN KEY (accountId) REFERENCES Account (id) ON DELETE RESTRICT,
+    FOREIGN KEY (categoryId) REFERENCES ExpenseCategory (id) ON DELETE RESTRICT
+);
+CREATE UNIQUE INDEX FavoriteLabel ON Favorite (userId, label COLLATE NOCASE);
+";
+
+const SCHEMA_V11: &str = "
+-- Categories like a catch-all 'Other' whose expenses must say what they were.
+ALTER TABLE ExpenseCategory ADD COLUMN requireComment BOOLEAN NOT NULL DEFAULT 0;
+";
+
+const SCHEMA_V12: &str = "
+-- Callback data over Telegram's 64 bytes, buttons carry 'cb:<shortId>'
+-- instead. Rows are swept once they expire.
+CREATE TABLE CallbackPayload (
+    shortId TEXT PRIMARY KEY,
+    payload JSON NOT NULL,
+    createdAt INTEGER NOT NULL
+);
+CREATE INDEX CallbackPayloadCreatedAt ON CallbackPayload (createdAt);
+";
+
+const SCHEMA_V13: &str = "
+-- Settings of a chat, such as the pinned message the bot keeps updated with
+-- the month's totals.
+CREATE TABLE ChatSettings (
+    chatId INTEGER PRIMARY KEY,
+    feedMessageId INTEGER
+);
+";
+
+// Households sharing the bot without seeing each other's data. Whatever
+// exists already belongs to the first one. SQLite can't add a column with a
+// foreign key and a default other than NULL, so the ids are only enforced
+// since V46, which adds the columns anew.
+const SCHEMA_V14: &str = "
+CREATE TABLE Household (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE
+);
+INSERT INTO Household (id, name) VALUES (1, 'default');
+
+ALTER TABLE User ADD COLUMN householdId INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE Account ADD COLUMN householdId INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE ExpenseCategory ADD COLUMN householdId INTEGER NOT NULL DEFAULT 1;
+-- A chat with a row is bound to its household, others are used by each
+-- sender with their own.
+ALTER TABLE ChatSettings ADD COLUMN householdId INTEGER NOT NULL DEFAULT 1;
+";
+
+const SCHEMA_V15: &str = "
+-- Expenses split between users, with what each one's share is in minor
+-- units. The payer, userId of the expense, has a share too, so that the
+-- shares of an expense add up to its amount.
+CREATE TABLE ExpenseShare (
+    expenseId INTEGER NOT NULL,
+    userId INTEGER NOT NULL,
+    shareMinor INTEGER NOT NULL,
+    PRIMARY KEY (expenseId, userId),
+    FOREIGN KEY (expenseId) REFERENCES Expense (id) ON DELETE CASCADE,
+    FOREIGN KEY (userId) REFERENCES User (telegramId) ON DELETE RESTRICT
+);
+
+-- Money fromUserId handed to toUserId to settle what they owed, in minor
+-- units of currencyId. Expenses stay as they are.
+CREATE TABLE Settlement (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    householdId INTEGER NOT NULL,
+    fromUserId INTEGER NOT NULL,
+    toUserId INTEGER NOT NULL,
+    currencyId INTEGER NOT NULL,
+    amountMinor INTEGER NOT NULL,
+    recordedBy INTEGER NOT NULL,
+    FOREIGN KEY (householdId) REFERENCES Household (id) ON DELETE RESTRICT,
+    FOREIGN KEY (fromUserId) REFERENCES User (telegramId) ON DELETE RESTRICT,
+    FOREIGN KEY (toUserId) REFERENCES User (telegramId) ON DELETE RESTRICT,
+    FOREIGN KEY (currencyId) REFERENCES Currency (id) ON DELETE RESTRICT
+);
+";
+
+/// Categories nest one level deep: `parentId` is a top-level category.
+const SCHEMA_V16: &str = "
+ALTER TABLE ExpenseCategory ADD COLUMN parentId INTEGER REFERENCES ExpenseCategory (id);
+";
+
+/// Files holding the expenses from before `cutoff` that were moved out of
+/// the main database, see `archive.rs`.
+const SCHEMA_V17: &str = "
+CREATE TABLE ArchiveCatalog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL UNIQUE,
+    cutoff INTEGER NOT NULL,
+    expenses INTEGER NOT NULL,
+    createdAt INTEGER NOT NULL
+);
+";
+
+/// The `/setup` wizard of a chat, what its admin answered so far. The row
+/// goes once the wizard finishes.
+const SCHEMA_V18: &str = "
+CREATE TABLE SetupWizard (
+    chatId INTEGER PRIMARY KEY,
+    userId INTEGER NOT NULL,
+    householdId INTEGER NOT NULL,
+    step TEXT NOT NULL,
+    baseCurrency TEXT,
+    accountName TEXT,
+    accountCurrency TEXT
+);
+";
+
+/// What an expense paid in another currency than its account's was, in
+/// minor units of `originalCurrencyId`. `amountMinor` stays what the account
+/// was charged, which is what reports count.
+const SCHEMA_V19: &str = "
+ALTER TABLE Expense ADD COLUMN originalAmountMinor INTEGER;
+ALTER TABLE Expense ADD COLUMN originalCurrencyId INTEGER REFERENCES Currency (id);
+";
+
+/// Limits the length of comments and names to what `lengths.rs` checks.
+/// SQLite can't add CHECK constraints to existing tables without copying
+/// them, so triggers refuse longer texts instead. Rows stored before are
+/// left as they are.
+const SCHEMA_V20: &str = "
+CREATE TRIGGER ExpenseInsertLengths BEFORE INSERT ON Expense
+WHEN length(NEW.comments) > 1000
+BEGIN SELECT RAISE(ABORT, 'Expense comments longer than 1000 characters'); END;
+CREATE TRIGGER ExpenseUpdateLengths BEFORE UPDATE OF comments ON Expense
+WHEN length(NEW.comments) > 1000
+BEGIN SELECT RAISE(ABORT, 'Expense comments longer than 1000 characters'); END;
+
+CREATE TRIGGER FavoriteInsertLengths BEFORE INSERT ON Favorite
+WHEN length(NEW.comment) > 1000 OR length(NEW.label) > 100
+BEGIN SELECT RAISE(ABORT, 'Favorite comment or label too long'); END;
+CREATE TRIGGER FavoriteUpdateLengths BEFORE UPDATE OF comment, label ON Favorite
+WHEN length(NEW.comment) > 1000 OR length(NEW.label) > 100
+BEGIN SELECT RAISE(ABORT, 'Favorite comment or label too long'); END;
+
+CREATE TRIGGER AccountInsertLengths BEFORE INSERT ON Account
+WHEN length(NEW.name) > 100 OR length(NEW.displayName) > 100
+BEGIN SELECT RAISE(ABORT, 'Account name longer than 100 characters'); END;
+CREATE TRIGGER AccountUpdateLengths BEFORE UPDATE OF name, displayName ON Account
+WHEN length(NEW.name) > 100 OR length(NEW.displayName) > 100
+BEGIN SELECT RAISE(ABORT, 'Account name longer than 100 characters'); END;
+
+CREATE TRIGGER CategoryInsertLengths BEFORE INSERT ON ExpenseCategory
+WHEN length(NEW.name) > 100
+BEGIN SELECT RAISE(ABORT, 'Category name longer than 100 characters'); END;
+CREATE TRIGGER CategoryUpdateLengths BEFORE UPDATE OF name ON ExpenseCategory
+WHEN length(NEW.name) > 100
+BEGIN SELECT RAISE(ABORT, 'Category name longer than 100 characters'); END;
+
+CREATE TRIGGER HouseholdInsertLengths BEFORE INSERT ON Household
+WHEN length(NEW.name) > 100
+BEGIN SELECT RAISE(ABORT, 'Household name longer than 100 characters'); END;
+CREATE TRIGGER HouseholdUpdateLengths BEFORE UPDATE OF name ON Household
+WHEN length(NEW.name) > 100
+BEGIN SELECT RAISE(ABORT, 'Household name longer than 100 characters'); END;
+
+CREATE TRIGGER UserInsertLengths BEFORE INSERT ON User
+WHEN length(NEW.displayName) > 100
+BEGIN SELECT RAISE(ABORT, 'User displayName longer than 100 characters'); END;
+CREATE TRIGGER UserUpdateLengths BEFORE UPDATE OF displayName ON User
+WHEN length(NEW.displayName) > 100
+BEGIN SELECT RAISE(ABORT, 'User displayName longer than 100 characters'); END;
+";
+
+/// Rules picking the category of a draft whose comment matches `pattern`, a
+/// regular expression. Lower priorities are tried first.
+const SCHEMA_V21: &str = "
+CREATE TABLE Rule (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    householdId INTEGER NOT NULL,
+    pattern TEXT NOT NULL CHECK (length(pattern) <= 100),
+    categoryId INTEGER NOT NULL,
+    priority INTEGER NOT NULL,
+    FOREIGN KEY (householdId) REFERENCES Household (id) ON DELETE CASCADE,
+    FOREIGN KEY (categoryId) REFERENCES ExpenseCategory (id) ON DELETE CASCADE
+);
+";
+
+/// Balances the bank showed for an account against the computed ones, in
+/// minor units. `expenseId` is the adjustment recorded for the difference,
+/// an expense of kind 'adjustment' that may be negative.
+const SCHEMA_V22: &str = "
+ALTER TABLE Expense ADD COLUMN kind TEXT NOT NULL DEFAULT 'expense'
+    CHECK (kind IN ('expense', 'adjustment'));
+
+CREATE TABLE Reconciliation (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    accountId INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    computedMinor INTEGER NOT NULL,
+    actualMinor INTEGER NOT NULL,
+    expenseId INTEGER,
+    FOREIGN KEY (accountId) REFERENCES Account (id) ON DELETE CASCADE,
+    FOREIGN KEY (expenseId) REFERENCES Expense (id) ON DELETE SET NULL
+);
+";
+
+/// Groups where amounts are shown as 🔒 in confirmations and the feed.
+const SCHEMA_V23: &str = "
+ALTER TABLE ChatSettings ADD COLUMN maskAmounts INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Monthly budgets of categories, in minor units of a currency. The budget
+/// of a category with subcategories covers their spend as well.
+const SCHEMA_V24: &str = "
+CREATE TABLE Budget (
+    categoryId INTEGER NOT NULL,
+    currencyId INTEGER NOT NULL,
+    amountMinor INTEGER NOT NULL CHECK (amountMinor > 0),
+    PRIMARY KEY (categoryId, currencyId),
+    FOREIGN KEY (categoryId) REFERENCES ExpenseCategory (id) ON DELETE CASCADE,
+    FOREIGN KEY (currencyId) REFERENCES Currency (id) ON DELETE CASCADE
+);
+";
+
+/// Where an expense was made, as shared from Telegram.
+const SCHEMA_V25: &str = "
+ALTER TABLE Expense ADD COLUMN latitude REAL;
+ALTER TABLE Expense ADD COLUMN longitude REAL;
+";
+
+/// Where a household's weeks and months start: days from Monday, and a day
+/// of the month every month has.
+const SCHEMA_V26: &str = "
+ALTER TABLE Household ADD COLUMN weekStart INTEGER NOT NULL DEFAULT 0
+    CHECK (weekStart BETWEEN 0 AND 6);
+ALTER TABLE Household ADD COLUMN monthStart INTEGER NOT NULL DEFAULT 1
+    CHECK (monthStart BETWEEN 1 AND 28);
+";
+
+/// Sources of forwarded messages, like a utilities bot posting to a channel,
+/// whose forwards become drafts in their default category and account. A
+/// source is a chat or a user, never both. Templates read the amount of a
+/// forward with a regular expression.
+const SCHEMA_V27: &str = "
+CREATE TABLE ParseTemplate (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    householdId INTEGER NOT NULL,
+    pattern TEXT NOT NULL CHECK (length(pattern) <= 100),
+    FOREIGN KEY (householdId) REFERENCES Household (id) ON DELETE CASCADE
+);
+
+CREATE TABLE TrustedSource (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    householdId INTEGER NOT NULL,
+    sourceChatId INTEGER,
+    sourceUserId INTEGER,
+    defaultCategoryId INTEGER NOT NULL,
+    defaultAccountId INTEGER NOT NULL,
+    parseTemplateId INTEGER,
+    CHECK ((sourceChatId IS NULL) != (sourceUserId IS NULL)),
+    UNIQUE (householdId, sourceChatId),
+    UNIQUE (householdId, sourceUserId),
+    FOREIGN KEY (householdId) REFERENCES Household (id) ON DELETE CASCADE,
+    FOREIGN KEY (defaultCategoryId) REFERENCES ExpenseCategory (id) ON DELETE CASCADE,
+    FOREIGN KEY (defaultAccountId) REFERENCES Account (id) ON DELETE CASCADE,
+    FOREIGN KEY (parseTemplateId) REFERENCES ParseTemplate (id) ON DELETE SET NULL
+);
+";
+
+/// Whether a user got the welcome of newly allowed users. Those allowed
+/// before know their way around already.
+const SCHEMA_V28: &str = "
+ALTER TABLE User ADD COLUMN welcomed INTEGER NOT NULL DEFAULT 0;
+UPDATE User SET welcomed = 1;
+";
+
+/// Personal reports: `nextRun` is when the user's next one is due, in unix
+/// seconds.
+const SCHEMA_V29: &str = "
+CREATE TABLE Subscription (
+    userId INTEGER NOT NULL,
+    cadence TEXT NOT NULL CHECK (cadence IN ('weekly', 'monthly')),
+    nextRun INTEGER NOT NULL,
+    PRIMARY KEY (userId, cadence),
+    FOREIGN KEY (userId) REFERENCES User (telegramId) ON DELETE CASCADE
+);
+";
+
+/// Who last changed an expense after it was saved, and when, in unix
+/// seconds. Both stay NULL for expenses never edited.
+const SCHEMA_V30: &str = "
+ALTER TABLE Expense ADD COLUMN modifiedBy INTEGER;
+ALTER TABLE Expense ADD COLUMN modifiedAt INTEGER;
+";
+
+// The group message an expense was logged from, for a link back to it. The
+// username is the chat's at the time, public groups link by it.
+const SCHEMA_V31: &str = "
+ALTER TABLE Expense ADD COLUMN sourceChatId INTEGER;
+ALTER TABLE Expense ADD COLUMN sourceMessageId INTEGER;
+ALTER TABLE Expense ADD COLUMN sourceChatUsername TEXT;
+";
+
+/// The account drafts move to when the category is picked, unless one was
+/// picked for them already.
+const SCHEMA_V32: &str = "
+ALTER TABLE ExpenseCategory ADD COLUMN defaultAccountId INTEGER
+    REFERENCES Account (id) ON DELETE SET NULL;
+";
+
+/// The bot messages confirming a saved expense, which a 🔁 reaction logs
+/// again.
+const SCHEMA_V33: &str = "
+CREATE TABLE Confirmation (
+    chatId INTEGER NOT NULL,
+    messageId INTEGER NOT NULL,
+    expenseId INTEGER NOT NULL,
+    PRIMARY KEY (chatId, messageId),
+    FOREIGN KEY (expenseId) REFERENCES Expense (id) ON DELETE CASCADE
+);
+";
+
+/// Daily spending limits of accounts, in minor units of their currency.
+const SCHEMA_V34: &str = "
+ALTER TABLE Account ADD COLUMN dailyLimitMinor INTEGER;
+";
+
+/// The messages of open drafts, whose keyboards a restart leaves leading
+/// nowhere since drafts are kept in memory.
+const SCHEMA_V35: &str = "
+CREATE TABLE DraftMessage (
+    chatId INTEGER NOT NULL,
+    messageId INTEGER NOT NULL,
+    openedAt INTEGER NOT NULL,
+    PRIMARY KEY (chatId, messageId)
+);
+";
+
+/// The set of categories a `/setup` wizard picked, and the names of those
+/// the admin left out of it, one per line.
+const SCHEMA_V36: &str = "
+ALTER TABLE SetupWizard ADD COLUMN preset TEXT;
+ALTER TABLE SetupWizard ADD COLUMN leftOut TEXT;
+";
+
+/// How an expense was logged, see `ExpenseSource`. Those from before were
+/// typed.
+const SCHEMA_V37: &str = "
+ALTER TABLE Expense ADD COLUMN source TEXT NOT NULL DEFAULT 'manual';
+";
+
+const SCHEMA_V38: &str = "
+-- Settings of the whole bot rather than of a user or chat, like read-only
+-- mode.
+CREATE TABLE BotSetting (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// How commits are confirmed in a chat, see `/settings confirm`.
+const SCHEMA_V39: &str = "
+ALTER TABLE ChatSettings ADD COLUMN confirmMode TEXT NOT NULL DEFAULT 'full';
+";
+
+/// When an expense was logged, apart from when it was made: `timestamp`.
+/// Nothing tells for the expenses so far, they count as logged on the spot.
+const SCHEMA_V40: &str = "
+ALTER TABLE Expense ADD COLUMN createdAt INTEGER NOT NULL DEFAULT 0;
+UPDATE Expense SET createdAt = timestamp;
+";
+
+/// The Telegram language of users, refreshed like their names, for dates in
+/// messages sent to them.
+const SCHEMA_V41: &str = "
+ALTER TABLE User ADD COLUMN languageCode TEXT;
+";
+
+/// Months an admin closed, whose expenses can't change until reopened.
+/// `month` is `YYYY-MM`, the bounds its local midnights when it was closed
+/// in unix seconds, so that checking an expense needs no time zone.
+const SCHEMA_V42: &str = "
+CREATE TABLE ClosedPeriod (
+    householdId INTEGER NOT NULL,
+    month TEXT NOT NULL,
+    startsAt INTEGER NOT NULL,
+    endsAt INTEGER NOT NULL,
+    closedBy INTEGER NOT NULL,
+    closedAt INTEGER NOT NULL,
+    PRIMARY KEY (householdId, month),
+    FOREIGN KEY (householdId) REFERENCES Household (id) ON DELETE CASCADE
+);
+";
+
+/// The currency bare amounts typed in a chat are in, see
+/// `/settings currency`. `NULL` for none.
+const SCHEMA_V43: &str = "
+ALTER TABLE ChatSettings ADD COLUMN assumedCurrency TEXT;
+";
+
+/// Initial balances in minor units of the account's currency, like amounts
+/// since V5, in place of the floats V3 added.
+const SCHEMA_V44: &str = "
+ALTER TABLE Account ADD COLUMN initialBalanceMinor INTEGER NOT NULL DEFAULT 0;
+UPDATE Account SET initialBalanceMinor = CAST(ROUND(initialBalance * COALESCE((
+    SELECT CASE exponent
+        WHEN 0 THEN 1 WHEN 1 THEN 10 WHEN 2 THEN 100 WHEN 3 THEN 1000
+        WHEN 4 THEN 10000 WHEN 5 THEN 100000 ELSE 1000000
+    END
+    FROM Currency WHERE id = Account.currencyId
+), 100)) AS INTEGER);
+ALTER TABLE Account DROP COLUMN initialBalance;
+";
+
+/// The quiet hours of a chat, local hours from `quietStart` to `quietEnd`
+/// o'clock that scheduled messages wait out, equal hours for none, and the
+/// time zone they are in, `NULL` for the bot's. Messages waiting, or to be
+/// sent again after failing, are kept in `PendingNotification`.
+const SCHEMA_V45: &str = "
+ALTER TABLE ChatSettings ADD COLUMN quietStart INTEGER NOT NULL DEFAULT 22
+    CHECK (quietStart BETWEEN 0 AND 23);
+ALTER TABLE ChatSettings ADD COLUMN quietEnd INTEGER NOT NULL DEFAULT 8
+    CHECK (quietEnd BETWEEN 0 AND 23);
+ALTER TABLE ChatSettings ADD COLUMN timeZone TEXT;
+
+CREATE TABLE PendingNotification (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chatId INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    dueAt INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    createdAt INTEGER NOT NULL
+);
+CREATE INDEX PendingNotificationDue ON PendingNotification (dueAt);
+";
+
+/// Household ids enforced as foreign keys, which V14 couldn't add with its
+/// default. SQLite only adds a column referencing another table with a
+/// default of NULL, so each one is added anew and filled from the old one;
+/// triggers refuse NULL from then on, as a NOT NULL constraint would. A row
+/// of a household that doesn't exist is left NULL, for `/integrity` to list.
+const SCHEMA_V46: &str = "
+ALTER TABLE User RENAME COLUMN householdId TO householdIdV14;
+ALTER TABLE User ADD COLUMN householdId INTEGER
+    REFERENCES Household (id) ON DELETE RESTRICT;
+UPDATE User SET householdId = (SELECT id FROM Household WHERE id = householdIdV14);
+ALTER TABLE User DROP COLUMN householdIdV14;
+CREATE TRIGGER UserInsertHousehold BEFORE INSERT ON User
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'User without a household'); END;
+CREATE TRIGGER UserUpdateHousehold BEFORE UPDATE OF householdId ON User
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'User without a household'); END;
+
+ALTER TABLE Account RENAME COLUMN householdId TO householdIdV14;
+ALTER TABLE Account ADD COLUMN householdId INTEGER
+    REFERENCES Household (id) ON DELETE RESTRICT;
+UPDATE Account SET householdId = (SELECT id FROM Household WHERE id = householdIdV14);
+ALTER TABLE Account DROP COLUMN householdIdV14;
+CREATE TRIGGER AccountInsertHousehold BEFORE INSERT ON Account
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'Account without a household'); END;
+CREATE TRIGGER AccountUpdateHousehold BEFORE UPDATE OF householdId ON Account
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'Account without a household'); END;
+
+ALTER TABLE ExpenseCategory RENAME COLUMN householdId TO householdIdV14;
+ALTER TABLE ExpenseCategory ADD COLUMN householdId INTEGER
+    REFERENCES Household (id) ON DELETE RESTRICT;
+UPDATE ExpenseCategory SET householdId = (SELECT id FROM Household WHERE id = householdIdV14);
+ALTER TABLE ExpenseCategory DROP COLUMN householdIdV14;
+CREATE TRIGGER CategoryInsertHousehold BEFORE INSERT ON ExpenseCategory
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'ExpenseCategory without a household'); END;
+CREATE TRIGGER CategoryUpdateHousehold BEFORE UPDATE OF householdId ON ExpenseCategory
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'ExpenseCategory without a household'); END;
+
+ALTER TABLE ChatSettings RENAME COLUMN householdId TO householdIdV14;
+ALTER TABLE ChatSettings ADD COLUMN householdId INTEGER
+    REFERENCES Household (id) ON DELETE CASCADE;
+UPDATE ChatSettings SET householdId = (SELECT id FROM Household WHERE id = householdIdV14);
+ALTER TABLE ChatSettings DROP COLUMN householdIdV14;
+CREATE TRIGGER ChatInsertHousehold BEFORE INSERT ON ChatSettings
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'ChatSettings without a household'); END;
+CREATE TRIGGER ChatUpdateHousehold BEFORE UPDATE OF householdId ON ChatSettings
+WHEN NEW.householdId IS NULL
+BEGIN SELECT RAISE(ABORT, 'ChatSettings without a household'); END;
+";
+
+/// The base currency is global, `ST_BASE_CURRENCY`, rather than one of each
+/// household: the column the `/setup` wizard once stored its answer in goes.
+const SCHEMA_V47: &str = "
+ALTER TABLE SetupWizard DROP COLUMN baseCurrency;
+";
+
+/// Drops the float amounts V5 kept, once an admin has checked the converted
+/// ones against them. It waits for them, so it isn't one of the migrations.
+pub(super) const DROP_LEGACY_AMOUNTS: &str = "
+ALTER TABLE Expense DROP COLUMN amountLegacy;
+";
+
+/// Migrations in order: `MIGRATIONS[n]` upgrades the schema from version `n`
+/// to version `n + 1`.
+const MIGRATIONS: &[&str] = &[
+    SCHEMA_V1, SCHEMA_V2, SCHEMA_V3, SCHEMA_V4, SCHEMA_V5, SCHEMA_V6, SCHEMA_V7, SCHEMA_V8,
+    SCHEMA_V9, SCHEMA_V10, SCHEMA_V11, SCHEMA_V12, SCHEMA_V13, SCHEMA_V14, SCHEMA_V15, SCHEMA_V16,
+    SCHEMA_V17, SCHEMA_V18, SCHEMA_V19, SCHEMA_V20, SCHEMA_V21, SCHEMA_V22, SCHEMA_V23, SCHEMA_V24,
+    SCHEMA_V25, SCHEMA_V26, SCHEMA_V27, SCHEMA_V28, SCHEMA_V29, SCHEMA_V30, SCHEMA_V31, SCHEMA_V32,
+    SCHEMA_V33, SCHEMA_V34, SCHEMA_V35, SCHEMA_V36, SCHEMA_V37, SCHEMA_V38, SCHEMA_V39, SCHEMA_V40,
+    SCHEMA_V41, SCHEMA_V42, SCHEMA_V43, SCHEMA_V44, SCHEMA_V45, SCHEMA_V46, SCHEMA_V47,
+];
+
+/// The version a fully migrated database is at.
+pub(super) fn latest_version() -> usize {
+    MIGRATIONS.len()
+}
+
 pub(super) fn init_schema(conn: &rusqlite::Connection) {
     info!("Initialising schema...");
-    let version: i32 = conn
+    let version: usize = conn
         .pragma_query_value(None, "user_version", |row| row.get(0))
         .unwrap();
 
     info!("Current version: {}", version);
 
-    match version {
-        0 => {
-            init_schema_v1(&conn);
-        }
-        1 => {
-            // schema is up to date, do nothing
-        }
-        _ => {
-            panic!("Unsupported schema version: {}", version);
-        }
+    if version > MIGRATIONS.len() {
+        panic!("Unsupported schema version: {}", version);
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        migrate(conn, index + 1, migration);
     }
 }
 
-pub(super) fn init_schema_v1(conn: &rusqlite::Connection) {
-    info!("Initializing schema v1");
-    conn.execute_batch(SCHEMA_V1).unwrap();
-    conn.pragma_update(None, "user_version", 1).unwrap();
+fn migrate(conn: &rusqlite::Connection, version: usize, migration: &str) {
+    info!("Migrating schema to v{}", version);
+    conn.execute_batch(&format!(
+        "BEGIN;\n{}\nPRAGMA user_version = {};\nCOMMIT;",
+        migration, version
+    ))
+    .unwrap();
+}
+
+/// Brings a new database to `version`, for tests of later migrations.
+#[cfg(test)]
+pub(super) fn migrate_to(conn: &rusqlite::Connection, version: usize) {
+    for (index, migration) in MIGRATIONS.iter().enumerate().take(version) {
+        migrate(conn, index + 1, migration);
+    }
 }
 
+#[cfg(test)]
 pub(super) fn fill_test_data(conn: &rusqlite::Connection) {
     conn.execute_batch(super::test_data::TEST_DATA).unwrap()
 }
+
+#[cfg(test)]
+pub(super) fn fill_second_household(conn: &rusqlite::Connection) {
+    conn.execute_batch(super::test_data::SECOND_HOUSEHOLD)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_to_latest_version() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        init_schema(&conn);
+        let version: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(MIGRATIONS.len(), version);
+
+        // Running again on an up to date schema is a no-op.
+        init_schema(&conn);
+    }
+
+    #[test]
+    fn triggers_refuse_long_texts() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        init_schema(&conn);
+        let execute = |sql: &str, text: String| conn.execute(sql, [text]).map(|_| ());
+        let x = |length| "x".repeat(length);
+
+        execute("INSERT INTO Currency (name) VALUES (?1)", "EUR".to_string()).unwrap();
+        execute("INSERT INTO Household (name) VALUES (?1)", x(100)).unwrap();
+        assert!(execute("INSERT INTO Household (name) VALUES (?1)", x(101)).is_err());
+        execute(
+            "INSERT INTO User (telegramId, telegramName, displayName, householdId)
+             VALUES (1, 'a', ?1, 1)",
+            x(100),
+        )
+        .unwrap();
+        assert!(execute("UPDATE User SET displayName = ?1", x(101)).is_err());
+        execute(
+            "INSERT INTO Account (name, currencyId, householdId) VALUES (?1, 1, 1)",
+            x(100),
+        )
+        .unwrap();
+        assert!(execute(
+            "INSERT INTO Account (name, currencyId, householdId) VALUES (?1, 1, 1)",
+            x(101)
+        )
+        .is_err());
+        assert!(execute("UPDATE Account SET displayName = ?1", x(101)).is_err());
+        execute(
+            "INSERT INTO ExpenseCategory (name, householdId) VALUES (?1, 1)",
+            x(100),
+        )
+        .unwrap();
+        assert!(execute("UPDATE ExpenseCategory SET name = ?1", x(101)).is_err());
+
+        let insert_expense =
+            "INSERT INTO Expense (accountId, categoryId, userId, timestamp, amountMinor, comments)
+            VALUES (1, 1, 1, 0, 100, ?1)";
+        execute(insert_expense, x(1000)).unwrap();
+        assert!(execute(insert_expense, x(1001)).is_err());
+        assert!(execute("UPDATE Expense SET comments = ?1", x(1001)).is_err());
+        // Characters are counted, not bytes.
+        execute("UPDATE Expense SET comments = ?1", "ж".repeat(1000)).unwrap();
+
+        let insert_favorite = "INSERT INTO Favorite (userId, label, amountMinor, currencyId, accountId, categoryId, comment)
+            VALUES (1, 'coffee', 100, 1, 1, 1, ?1)";
+        execute(insert_favorite, x(1000)).unwrap();
+        assert!(execute("UPDATE Favorite SET comment = ?1", x(1001)).is_err());
+        assert!(execute("UPDATE Favorite SET label = ?1", x(101)).is_err());
+        execute("UPDATE Favorite SET label = ?1", x(100)).unwrap();
+    }
+
+    #[test]
+    fn v5_converts_amounts_to_minor_units() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        migrate_to(&conn, 4);
+        conn.execute_batch(
+            "INSERT INTO Currency (name) VALUES ('EUR');
+             INSERT INTO User (telegramId, telegramName, displayName) VALUES (1, 'a', 'A');
+             INSERT INTO Account (name, currencyId) VALUES ('Cash', 1);
+             INSERT INTO ExpenseCategory (name) VALUES ('Food');
+             INSERT INTO Expense (accountId, categoryId, userId, timestamp, amount) VALUES
+                 (1, 1, 1, 0, 50.75), (1, 1, 1, 0, 0.1), (1, 1, 1, 0, 19.99);",
+        )
+        .unwrap();
+
+        init_schema(&conn);
+        let mut stmt = conn
+            .prepare("SELECT amountMinor, amountLegacy FROM Expense ORDER BY id")
+            .unwrap();
+        let amounts: Vec<(i64, f64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(vec![(5075, 50.75), (10, 0.1), (1999, 19.99)], amounts);
+    }
+
+    #[test]
+    fn v46_enforces_households() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate_to(&conn, 45);
+        conn.execute_batch(
+            "INSERT INTO Currency (name) VALUES ('EUR');
+             INSERT INTO Household (id, name) VALUES (2, 'Neighbours');
+             INSERT INTO User (telegramId, telegramName, displayName, householdId)
+                 VALUES (1, 'a', 'A', 2);
+             INSERT INTO Account (name, currencyId) VALUES ('Cash', 1);
+             INSERT INTO ExpenseCategory (name, householdId) VALUES ('Food', 2);
+             INSERT INTO ChatSettings (chatId, householdId) VALUES (-100, 2);",
+        )
+        .unwrap();
+        // A household deleted while the ids weren't enforced.
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO Account (name, currencyId, householdId) VALUES ('Gone', 1, 9);
+             PRAGMA foreign_keys = ON;",
+        )
+        .unwrap();
+
+        init_schema(&conn);
+        let households = |sql: &str| -> Vec<Option<i64>> {
+            let mut stmt = conn.prepare(sql).unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        assert_eq!(
+            vec![Some(2), Some(1), None, Some(2), Some(2)],
+            households(
+                "SELECT householdId FROM User
+                 UNION ALL SELECT householdId FROM (SELECT * FROM Account ORDER BY id)
+                 UNION ALL SELECT householdId FROM ExpenseCategory
+                 UNION ALL SELECT householdId FROM ChatSettings"
+            )
+        );
+        let insert = |sql: &str| conn.execute(sql, []).map(|_| ());
+        assert!(
+            insert("INSERT INTO Account (name, currencyId, householdId) VALUES ('X', 1, 9)")
+                .is_err()
+        );
+        assert!(insert("INSERT INTO Account (name, currencyId) VALUES ('X', 1)").is_err());
+        assert!(insert("UPDATE User SET householdId = NULL").is_err());
+        assert!(insert("INSERT INTO ChatSettings (chatId, householdId) VALUES (-200, 9)").is_err());
+        insert("INSERT INTO Account (name, currencyId, householdId) VALUES ('X', 1, 2)").unwrap();
+    }
+
+    #[test]
+    fn v44_converts_initial_balances_to_minor_units() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        migrate_to(&conn, 43);
+        conn.execute_batch(
+            "INSERT INTO Currency (name, exponent) VALUES ('EUR', 2), ('JPY', 0), ('BHD', 3);
+             INSERT INTO Account (name, currencyId, initialBalance) VALUES
+                 ('Cash', 1, 1000.1), ('Yen', 2, 1500), ('Dinar', 3, -12.345), ('Empty', 1, 0);",
+        )
+        .unwrap();
+
+        init_schema(&conn);
+        let mut stmt = conn
+            .prepare("SELECT initialBalanceMinor FROM Account ORDER BY id")
+            .unwrap();
+        let balances: Vec<i64> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(vec![100010, 1500, -12345, 0], balances);
+    }
+
+    #[test]
+    fn v40_logs_expenses_when_they_were_made() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        migrate_to(&conn, 39);
+        conn.execute_batch(
+            "INSERT INTO Currency (name) VALUES ('EUR');
+             INSERT INTO User (telegramId, telegramName, displayName) VALUES (1, 'a', 'A');
+             INSERT INTO Account (name, currencyId) VALUES ('Cash', 1);
+             INSERT INTO ExpenseCategory (name) VALUES ('Food');
+             INSERT INTO Expense (accountId, categoryId, userId, timestamp, amountMinor) VALUES
+                 (1, 1, 1, 1701424800, 100), (1, 1, 1, 1701520200, 200);",
+        )
+        .unwrap();
+
+        init_schema(&conn);
+        let mut stmt = conn
+            .prepare("SELECT timestamp, createdAt FROM Expense ORDER BY id")
+            .unwrap();
+        let times: Vec<(i64, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            vec![(1701424800, 1701424800), (1701520200, 1701520200)],
+            times
+        );
+    }
+}
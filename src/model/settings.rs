@@ -0,0 +1,175 @@
+//! Per-user preferences. Only values set by the user are stored, everything
+//! else comes from [`Settings::default`], so adding a setting needs no
+//! migration.
+
+use super::{Locale, Model, Result};
+use rusqlite::params;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Show the month-to-date total after saving an expense.
+    pub month_to_date: bool,
+    /// How the user writes amounts, `None` to go by their Telegram language.
+    pub number_format: Option<Locale>,
+    /// Commit drafts only once their category was picked.
+    pub strict_commit: bool,
+    /// Show the amounts of reports rounded to whole units.
+    pub round_totals: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            month_to_date: true,
+            number_format: None,
+            strict_commit: false,
+            round_totals: false,
+        }
+    }
+}
+
+/// A setting together with its new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    MonthToDate(bool),
+    NumberFormat(Option<Locale>),
+    StrictCommit(bool),
+    RoundTotals(bool),
+}
+
+impl Setting {
+    /// Short name, also used in callback data.
+    pub fn key(self) -> &'static str {
+        match self {
+            Setting::MonthToDate(_) => "mtd",
+            Setting::NumberFormat(_) => "num",
+            Setting::StrictCommit(_) => "strict",
+            Setting::RoundTotals(_) => "round",
+        }
+    }
+
+    pub fn value(self) -> String {
+        match self {
+            Setting::MonthToDate(on) | Setting::StrictCommit(on) | Setting::RoundTotals(on) => {
+                on.to_string()
+            }
+            Setting::NumberFormat(None) => "auto".to_string(),
+            Setting::NumberFormat(Some(Locale::DecimalPoint)) => "point".to_string(),
+            Setting::NumberFormat(Some(Locale::DecimalComma)) => "comma".to_string(),
+        }
+    }
+
+    /// The setting `key` and `value` encode, `None` for keys or values this
+    /// version doesn't know.
+    pub fn decode(key: &str, value: &str) -> Option<Setting> {
+        match (key, value) {
+            ("mtd", value) => value.parse().ok().map(Setting::MonthToDate),
+            ("num", "auto") => Some(Setting::NumberFormat(None)),
+            ("num", "point") => Some(Setting::NumberFormat(Some(Locale::DecimalPoint))),
+            ("num", "comma") => Some(Setting::NumberFormat(Some(Locale::DecimalComma))),
+            ("strict", value) => value.parse().ok().map(Setting::StrictCommit),
+            ("round", value) => value.parse().ok().map(Setting::RoundTotals),
+            _ => None,
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::MonthToDate(on) => self.month_to_date = on,
+            Setting::NumberFormat(locale) => self.number_format = locale,
+            Setting::StrictCommit(on) => self.strict_commit = on,
+            Setting::RoundTotals(on) => self.round_totals = on,
+        }
+    }
+}
+
+impl Model {
+    pub fn get_settings(&self, user_id: i64) -> Result<Settings> {
+        let mut stmt = self.connection.prepare(
+            "SELECT key, value FROM UserSetting
+             WHERE userId = ?1 AND key NOT LIKE 'alias:%' AND key NOT LIKE 'notify%'
+                 AND key NOT LIKE 'template:%' AND key <> 'report-exclude'",
+        )?;
+        let rows = stmt
+            .query_map([user_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut settings = Settings::default();
+        for (key, value) in rows {
+            match Setting::decode(&key, &value) {
+                Some(setting) => settings.apply(setting),
+                None => log::warn!("Ignoring setting {}={} of user {}", key, value, user_id),
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn set_setting(&self, user_id: i64, setting: Setting) -> Result<()> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT INTO UserSetting (userId, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (userId, key) DO UPDATE SET value = excluded.value",
+            params![user_id, setting.key(), setting.value()],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_until_set() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(Settings::default(), model.get_settings(1001).unwrap());
+        model
+            .set_setting(1001, Setting::MonthToDate(false))
+            .unwrap();
+        assert!(!model.get_settings(1001).unwrap().month_to_date);
+        assert!(model.get_settings(1002).unwrap().month_to_date);
+        model.set_setting(1001, Setting::MonthToDate(true)).unwrap();
+        assert!(model.get_settings(1001).unwrap().month_to_date);
+    }
+
+    #[test]
+    fn settings_roundtrip_through_storage() {
+        let model = Model::new(true);
+        for setting in [
+            Setting::MonthToDate(false),
+            Setting::NumberFormat(Some(Locale::DecimalComma)),
+            Setting::NumberFormat(Some(Locale::DecimalPoint)),
+            Setting::NumberFormat(None),
+            Setting::StrictCommit(true),
+            Setting::StrictCommit(false),
+        ] {
+            assert_eq!(
+                Some(setting),
+                Setting::decode(setting.key(), &setting.value())
+            );
+            model.set_setting(1, setting).unwrap();
+            let mut expected = Settings::default();
+            expected.apply(Setting::MonthToDate(false));
+            expected.apply(setting);
+            assert_eq!(expected, model.get_settings(1).unwrap());
+        }
+    }
+
+    #[test]
+    fn ignores_unknown_rows() {
+        let model = Model::new(true);
+        model
+            .connection
+            .execute_batch(
+                "INSERT INTO UserSetting VALUES (1, 'mtd', 'maybe'), (1, 'removed', 'x');",
+            )
+            .unwrap();
+        assert_eq!(Settings::default(), model.get_settings(1).unwrap());
+    }
+}
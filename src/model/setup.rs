@@ -0,0 +1,285 @@
+//! State of the `/setup` wizard, kept in the database so that a restart in
+//! the middle doesn't lose the answers, and what it creates at the end.
+
+use super::presets::PresetCategory;
+use super::{Model, Result};
+use rusqlite::{params, OptionalExtension};
+
+/// The question the wizard waits for an answer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupStep {
+    AccountName,
+    AccountCurrency,
+    /// Which preset of categories to start with.
+    Categories,
+    /// Which categories of the preset to leave out.
+    Customize,
+}
+
+impl SetupStep {
+    fn as_str(self) -> &'static str {
+        match self {
+            SetupStep::AccountName => "account_name",
+            SetupStep::AccountCurrency => "account_currency",
+            SetupStep::Categories => "categories",
+            SetupStep::Customize => "customize",
+        }
+    }
+
+    fn parse(s: &str) -> Option<SetupStep> {
+        [
+            SetupStep::AccountName,
+            SetupStep::AccountCurrency,
+            SetupStep::Categories,
+            SetupStep::Customize,
+        ]
+        .into_iter()
+        .find(|step| step.as_str() == s)
+    }
+}
+
+/// A wizard in progress in a chat. Only `user_id` answers it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setup {
+    pub user_id: i64,
+    pub household: i64,
+    pub step: SetupStep,
+    pub account_name: Option<String>,
+    pub account_currency: Option<String>,
+    /// The key of the preset picked.
+    pub preset: Option<String>,
+    /// Names of the preset's categories not to create.
+    pub left_out: Vec<String>,
+}
+
+impl Setup {
+    pub fn new(user_id: i64, household: i64) -> Setup {
+        Setup {
+            user_id,
+            household,
+            step: SetupStep::AccountName,
+            account_name: None,
+            account_currency: None,
+            preset: None,
+            left_out: Vec::new(),
+        }
+    }
+}
+
+/// What finishing the wizard created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupResult {
+    pub account_id: i64,
+    /// Categories added, leaving out those the household had.
+    pub categories: Vec<String>,
+}
+
+impl Model {
+    /// Whether the household has neither accounts nor categories yet, so
+    /// that nothing can be logged.
+    pub fn is_unconfigured(&self, household: i64) -> Result<bool> {
+        let configured: bool = self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM Account WHERE householdId = ?1)
+                 OR EXISTS (SELECT 1 FROM ExpenseCategory WHERE householdId = ?1)",
+            [household],
+            |row| row.get(0),
+        )?;
+        Ok(!configured)
+    }
+
+    /// The wizard in progress in the chat, if any.
+    pub fn setup(&self, chat_id: i64) -> Result<Option<Setup>> {
+        let row = self
+            .connection
+            .query_row(
+                "SELECT userId, householdId, step, accountName, accountCurrency, preset, leftOut
+                 FROM SetupWizard WHERE chatId = ?1",
+                [chat_id],
+                |row| {
+                    Ok((
+                        Setup {
+                            user_id: row.get(0)?,
+                            household: row.get(1)?,
+                            step: SetupStep::AccountName,
+                            account_name: row.get(3)?,
+                            account_currency: row.get(4)?,
+                            preset: row.get(5)?,
+                            left_out: row
+                                .get::<_, Option<String>>(6)?
+                                .map(|names| names.lines().map(str::to_string).collect())
+                                .unwrap_or_default(),
+                        },
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        Ok(row.map(|(setup, step)| match SetupStep::parse(&step) {
+            Some(step) => Setup { step, ..setup },
+            // The wizard asked for a base currency first once, which it
+            // didn't keep; those in progress start at the account.
+            None if step == "base_currency" => setup,
+            None => {
+                log::warn!(
+                    "Restarting setup of chat {} at unknown step {}",
+                    chat_id,
+                    step
+                );
+                setup
+            }
+        }))
+    }
+
+    /// Stores the wizard's progress, replacing what the chat had.
+    pub fn save_setup(&self, chat_id: i64, setup: &Setup) -> Result<()> {
+        self.check_writable()?;
+        self.connection.execute(
+            "INSERT OR REPLACE INTO SetupWizard
+                 (chatId, userId, householdId, step, accountName, accountCurrency, preset,
+                  leftOut)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                chat_id,
+                setup.user_id,
+                setup.household,
+                setup.step.as_str(),
+                setup.account_name,
+                setup.account_currency,
+                setup.preset,
+                setup.left_out.join("\n")
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether the chat had a wizard in progress.
+    pub fn cancel_setup(&self, chat_id: i64) -> Result<bool> {
+        self.check_writable()?;
+        let deleted = self
+            .connection
+            .execute("DELETE FROM SetupWizard WHERE chatId = ?1", [chat_id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Creates the account the wizard of the chat collected, with its
+    /// currency, and the categories chosen, all or nothing.
+    pub fn finish_setup(
+        &self,
+        chat_id: i64,
+        setup: &Setup,
+        categories: &[PresetCategory],
+    ) -> Result<SetupResult> {
+        self.check_writable()?;
+        let (Some(name), Some(currency)) = (&setup.account_name, &setup.account_currency) else {
+            return Err(super::Error::Refused(
+                "The setup isn't complete yet.".to_string(),
+            ));
+        };
+        let tx = self.connection.unchecked_transaction()?;
+        let currency_id = self.add_currency(currency)?;
+        let account_id = self.add_account(setup.household, name, currency_id)?;
+        let categories = self.add_preset_categories(setup.household, categories)?;
+        self.cancel_setup(chat_id)?;
+        tx.commit()?;
+        log::info!(
+            "Setup of household {} by {} added account {}",
+            setup.household,
+            setup.user_id,
+            account_id
+        );
+        Ok(SetupResult {
+            account_id,
+            categories,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finishing_creates_everything_at_once() {
+        let model = Model::new(true);
+        assert!(model.is_unconfigured(1).unwrap());
+        assert_eq!(None, model.setup(-5).unwrap());
+
+        let minimal = crate::model::preset("minimal").unwrap().categories;
+        let mut setup = Setup::new(1001, 1);
+        model.save_setup(-5, &setup).unwrap();
+        assert_eq!(Some(setup.clone()), model.setup(-5).unwrap());
+        assert!(model.finish_setup(-5, &setup, minimal).is_err());
+        setup.step = SetupStep::Customize;
+        setup.preset = Some("student".to_string());
+        setup.left_out = vec!["Rent".to_string(), "Phone and internet".to_string()];
+        model.save_setup(-5, &setup).unwrap();
+        assert_eq!(Some(setup.clone()), model.setup(-5).unwrap());
+
+        setup.step = SetupStep::Categories;
+        setup.account_name = Some("Cash".to_string());
+        setup.account_currency = Some("BYN".to_string());
+        model.save_setup(-5, &setup).unwrap();
+        let result = model.finish_setup(-5, &setup, minimal).unwrap();
+        assert_eq!(5, result.categories.len());
+        assert_eq!(None, model.setup(-5).unwrap());
+        assert!(!model.is_unconfigured(1).unwrap());
+
+        let accounts = model.accounts(1).unwrap();
+        assert_eq!(1, accounts.len());
+        assert_eq!(
+            ("Cash", "BYN", 2),
+            (
+                accounts[0].name.as_str(),
+                accounts[0].currency.as_str(),
+                accounts[0].exponent
+            )
+        );
+        let names: Vec<String> = model
+            .categories(1)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(
+            vec![
+                "Groceries",
+                "Transport",
+                "Utilities",
+                "Entertainment",
+                "Other"
+            ],
+            names
+        );
+
+        // Running it again adds an account but no duplicate categories.
+        setup.account_name = Some("Card".to_string());
+        setup.account_currency = Some("eur".to_string());
+        let again = model.finish_setup(-5, &setup, minimal).unwrap();
+        assert!(again.categories.is_empty());
+        assert_eq!(2, model.accounts(1).unwrap().len());
+        assert_eq!(5, model.categories(1).unwrap().len());
+    }
+
+    #[test]
+    fn wizards_at_the_base_currency_resume_at_the_account() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::model::schema::migrate_to(&conn, 46);
+        conn.execute(
+            "INSERT INTO SetupWizard (chatId, userId, householdId, step, baseCurrency)
+             VALUES (-5, 1001, 1, 'base_currency', 'EUR')",
+            [],
+        )
+        .unwrap();
+        // Migrated the rest of the way.
+        let model = Model::with_connection(conn);
+        assert_eq!(Some(Setup::new(1001, 1)), model.setup(-5).unwrap());
+    }
+
+    #[test]
+    fn other_households_stay_unconfigured() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert!(!model.is_unconfigured(1).unwrap());
+        assert!(model.is_unconfigured(7).unwrap());
+    }
+}
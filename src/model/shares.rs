@@ -0,0 +1,370 @@
+//! Expenses one user paid for and another one shares, and what the users
+//! owe each other because of them until they settle up.
+
+use super::amount::from_minor;
+use super::expenses::of_household;
+use super::{audit, Error, Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use log::*;
+use rusqlite::{params, OptionalExtension};
+
+/// What one user owes another in one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Debt {
+    pub debtor: String,
+    pub creditor: String,
+    pub currency: String,
+    pub exponent: u32,
+    pub amount: f64,
+}
+
+/// The payer's and the other user's share of `minor`, split in half. An odd
+/// minor unit is the payer's.
+pub fn split_half(minor: i64) -> (i64, i64) {
+    let other = minor / 2;
+    (minor - other, other)
+}
+
+/// Net amounts owed between pairs of users of the household `?1`, as
+/// `debtor, creditor, currencyId, amount` with the amount positive. Shares
+/// of expenses are owed to the payer, and a settlement counts as a debt the
+/// other way.
+const OWED: &str = "
+    WITH debts (debtor, creditor, currencyId, amount) AS (
+        SELECT s.userId, e.userId, a.currencyId, s.shareMinor
+        FROM {shares} s
+        JOIN {expenses} e ON e.id = s.expenseId
+        JOIN Account a ON a.id = e.accountId
+        WHERE s.userId <> e.userId AND a.householdId = ?1
+        UNION ALL
+        SELECT toUserId, fromUserId, currencyId, amountMinor
+        FROM Settlement
+        WHERE householdId = ?1
+    ),
+    net (low, high, currencyId, owed) AS (
+        -- What `low` owes `high`, negative when it's the other way round.
+        SELECT MIN(debtor, creditor), MAX(debtor, creditor), currencyId,
+               SUM(CASE WHEN debtor < creditor THEN amount ELSE -amount END)
+        FROM debts
+        GROUP BY 1, 2, 3
+    ),
+    owed (debtor, creditor, currencyId, amount) AS (
+        SELECT CASE WHEN owed > 0 THEN low ELSE high END,
+               CASE WHEN owed > 0 THEN high ELSE low END,
+               currencyId, ABS(owed)
+        FROM net
+        WHERE owed <> 0
+    )
+";
+
+impl Model {
+    /// Splits the expense of the household half and half between its payer
+    /// and `with`, or makes it the payer's alone again for `None`. Splits
+    /// follow later changes of the amount, see `update_expense`.
+    pub fn split_expense(&self, household: i64, expense_id: i64, with: Option<i64>) -> Result<()> {
+        self.check_writable()?;
+        self.check_expense_open(household, expense_id)?;
+        let (payer, minor): (i64, i64) = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT userId, amountMinor FROM Expense WHERE id = ?1 AND {}",
+                    of_household(2)
+                ),
+                [expense_id, household],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("expense {}", expense_id)))?;
+        let tx = self.connection.unchecked_transaction()?;
+        self.write_shares(household, expense_id, (payer, minor), with)?;
+        tx.commit()?;
+        info!("Split expense {} with {:?}", expense_id, with);
+        Ok(())
+    }
+
+    /// Replaces the shares of the expense of the household, of `minor` paid
+    /// by `payer`, with halves for `payer` and `with`, or none for `None`.
+    /// Runs in the transaction of the caller, which rolls it back with the
+    /// rest of the change if `with` is refused.
+    pub(super) fn write_shares(
+        &self,
+        household: i64,
+        expense_id: i64,
+        (payer, minor): (i64, i64),
+        with: Option<i64>,
+    ) -> Result<()> {
+        if let Some(with) = with {
+            if with == payer {
+                return Err(Error::Refused(
+                    "An expense can't be split with its payer.".to_string(),
+                ));
+            }
+            if self
+                .get_user(with)?
+                .is_none_or(|user| user.household != household)
+            {
+                return Err(Error::NotFound(format!("user {}", with)));
+            }
+        }
+
+        self.connection.execute(
+            "DELETE FROM ExpenseShare WHERE expenseId = ?1",
+            [expense_id],
+        )?;
+        if let Some(with) = with {
+            let (payer_share, other_share) = split_half(minor);
+            let mut insert = self.connection.prepare(
+                "INSERT INTO ExpenseShare (expenseId, userId, shareMinor) VALUES (?1, ?2, ?3)",
+            )?;
+            insert.execute(params![expense_id, payer, payer_share])?;
+            insert.execute(params![expense_id, with, other_share])?;
+        }
+        Ok(())
+    }
+
+    /// The user the expense is split with, if it is.
+    pub fn split_partner(&self, expense_id: i64) -> Result<Option<i64>> {
+        let partner = self
+            .connection
+            .query_row(
+                "SELECT s.userId FROM ExpenseShare s JOIN Expense e ON e.id = s.expenseId
+                 WHERE s.expenseId = ?1 AND s.userId <> e.userId",
+                [expense_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(partner)
+    }
+
+    /// Who owes whom how much in the household, by debtor, creditor and
+    /// currency.
+    pub fn debts(&self, household: i64) -> Result<Vec<Debt>> {
+        let owed = self.expense_tables(None)?.apply(OWED);
+        let mut stmt = self.connection.prepare(&format!(
+            "{}
+             SELECT COALESCE(d.displayName, CAST(o.debtor AS TEXT)),
+                    COALESCE(cr.displayName, CAST(o.creditor AS TEXT)),
+                    c.name, c.exponent, o.amount
+             FROM owed o
+             JOIN Currency c ON c.id = o.currencyId
+             LEFT JOIN User d ON d.telegramId = o.debtor
+             LEFT JOIN User cr ON cr.telegramId = o.creditor
+             ORDER BY 1, 2, c.name",
+            owed
+        ))?;
+        let debts = stmt
+            .query_map([household], |row| {
+                let exponent = row.get(3)?;
+                Ok(Debt {
+                    debtor: row.get(0)?,
+                    creditor: row.get(1)?,
+                    currency: row.get(2)?,
+                    exponent,
+                    amount: from_minor(row.get(4)?, exponent),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(debts)
+    }
+
+    /// Records that `user_id` and everyone they have debts with settled up,
+    /// zeroing those debts. Returns how many debts were settled.
+    pub fn settle(&self, household: i64, user_id: i64, now: DateTime<Utc>) -> Result<usize> {
+        self.check_writable()?;
+        let owed = self.expense_tables(None)?.apply(OWED);
+        let tx = self.connection.unchecked_transaction()?;
+        let settled = tx.execute(
+            &format!(
+                "{}
+                 INSERT INTO Settlement (timestamp, householdId, fromUserId, toUserId,
+                     currencyId, amountMinor, recordedBy)
+                 SELECT ?3, ?1, debtor, creditor, currencyId, amount, ?2
+                 FROM owed
+                 WHERE ?2 IN (debtor, creditor)",
+                owed
+            ),
+            params![household, user_id, DbTime(now)],
+        )?;
+        if settled > 0 {
+            audit::record(&tx, user_id, "settle", &format!("{} debts", settled))?;
+        }
+        tx.commit()?;
+        info!("User {} settled {} debts", user_id, settled);
+        Ok(settled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{DateRange, ExpenseSource, NewExpense};
+    use chrono::NaiveDate;
+    use chrono_tz::Tz;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_702_000_000, 0).unwrap()
+    }
+
+    fn debts(model: &Model) -> Vec<(String, String, String, f64)> {
+        model
+            .debts(1)
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.debtor, d.creditor, d.currency, d.amount))
+            .collect()
+    }
+
+    fn owes(
+        debtor: &str,
+        creditor: &str,
+        currency: &str,
+        amount: f64,
+    ) -> (String, String, String, f64) {
+        (
+            debtor.to_string(),
+            creditor.to_string(),
+            currency.to_string(),
+            amount,
+        )
+    }
+
+    fn shares(model: &Model, expense_id: i64) -> Vec<(i64, i64)> {
+        model
+            .connection
+            .prepare(
+                "SELECT userId, shareMinor FROM ExpenseShare WHERE expenseId = ?1 ORDER BY userId",
+            )
+            .unwrap()
+            .query_map([expense_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn odd_minor_units_go_to_the_payer() {
+        assert_eq!((5038, 5037), split_half(10075));
+        assert_eq!((1, 0), split_half(1));
+        assert_eq!((2000, 2000), split_half(4000));
+
+        let model = Model::new(true);
+        model.fill_test_data();
+        // 50.75 EUR paid by Alex.
+        model.split_expense(1, 1, Some(1002)).unwrap();
+        assert_eq!(vec![(1001, 2538), (1002, 2537)], shares(&model, 1));
+        assert_eq!(Some(1002), model.split_partner(1).unwrap());
+        assert_eq!(vec![owes("Hanna", "Alex", "EUR", 25.37)], debts(&model));
+    }
+
+    #[test]
+    fn debts_net_out_per_pair_and_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.split_expense(1, 1, Some(1002)).unwrap(); // Hanna owes 25.37 EUR
+        model.split_expense(1, 2, Some(1001)).unwrap(); // Alex owes 10 USD
+        model.split_expense(1, 4, Some(1001)).unwrap(); // and 50 USD more
+        model
+            .insert_expense(&NewExpense {
+                account_id: 1,
+                category_id: 1,
+                user_id: 1002,
+                timestamp: now(),
+                amount: 10.0,
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .and_then(|id| model.split_expense(1, id, Some(1001)))
+            .unwrap(); // Alex owes 5 EUR back
+
+        assert_eq!(
+            vec![
+                owes("Alex", "Hanna", "USD", 60.0),
+                owes("Hanna", "Alex", "EUR", 20.37),
+            ],
+            debts(&model)
+        );
+
+        // Splitting again replaces the split, undoing removes it.
+        model.split_expense(1, 4, Some(1001)).unwrap();
+        model.split_expense(1, 2, None).unwrap();
+        assert!(shares(&model, 2).is_empty());
+        assert_eq!(owes("Alex", "Hanna", "USD", 50.0), debts(&model)[0]);
+    }
+
+    #[test]
+    fn settling_zeroes_debts_and_keeps_expenses() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.split_expense(1, 1, Some(1002)).unwrap();
+        model.split_expense(1, 4, Some(1001)).unwrap();
+
+        assert_eq!(2, model.settle(1, 1002, now()).unwrap());
+        assert!(debts(&model).is_empty());
+        assert_eq!(0, model.settle(1, 1002, now()).unwrap());
+        assert_eq!(vec![(1001, 2538), (1002, 2537)], shares(&model, 1));
+        assert_eq!(
+            50.75,
+            model.get_expense_details(1, 1).unwrap().unwrap().amount
+        );
+
+        // New splits start from zero.
+        model.split_expense(1, 3, Some(1002)).unwrap();
+        assert_eq!(vec![owes("Hanna", "Alex", "BYN", 15.0)], debts(&model));
+        assert_eq!("settle", model.audit_entries().unwrap()[0].action);
+    }
+
+    #[test]
+    fn splits_stay_in_the_household() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+
+        assert!(matches!(
+            model.split_expense(1, 1, Some(2001)),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.split_expense(2, 1, Some(1002)),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            model.split_expense(1, 1, Some(1001)),
+            Err(Error::Refused(_))
+        ));
+        model.split_expense(1, 1, Some(1002)).unwrap();
+        assert!(model.debts(2).unwrap().is_empty());
+        assert_eq!(0, model.settle(2, 2001, now()).unwrap());
+    }
+
+    #[test]
+    fn personal_reports_count_shares_only() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.split_expense(1, 1, Some(1002)).unwrap();
+
+        let eur = |user| {
+            model
+                .month_to_date(1, "EUR", user, now(), Tz::UTC)
+                .unwrap()
+                .total
+        };
+        assert_eq!(25.38, eur(Some(1001)));
+        assert_eq!(25.37, eur(Some(1002)));
+        assert_eq!(50.75, eur(None));
+        let december = DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+        assert_eq!(
+            4,
+            model
+                .summarize(1, december, Tz::UTC)
+                .unwrap()
+                .categories
+                .len()
+        );
+    }
+}
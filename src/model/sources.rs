@@ -0,0 +1,367 @@
+//! Trusted sources of forwarded messages, like a utilities bot posting
+//! bills to a channel. A forward from one becomes a draft in the source's
+//! category and account, with the amount its template reads, if any.
+
+use super::lengths::{check_length, MAX_NAME_LEN};
+use super::rules::compile;
+use super::{Error, Model, Result};
+use log::*;
+use regex::Regex;
+use rusqlite::{params, OptionalExtension};
+use std::fmt;
+
+/// Where a forward came from. Forwards from users who hide their account
+/// have neither, so they can't be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceId {
+    /// A channel, or a group its anonymous admins post to.
+    Chat(i64),
+    User(i64),
+}
+
+impl fmt::Display for SourceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceId::Chat(id) => write!(f, "chat:{}", id),
+            SourceId::User(id) => write!(f, "user:{}", id),
+        }
+    }
+}
+
+impl SourceId {
+    /// Parses `chat:<id>` or `user:<id>`, as shown.
+    pub fn parse(text: &str) -> Option<SourceId> {
+        let (kind, id) = text.split_once(':')?;
+        let id = id.parse().ok()?;
+        match kind {
+            "chat" => Some(SourceId::Chat(id)),
+            "user" => Some(SourceId::User(id)),
+            _ => None,
+        }
+    }
+
+    fn columns(self) -> (Option<i64>, Option<i64>) {
+        match self {
+            SourceId::Chat(id) => (Some(id), None),
+            SourceId::User(id) => (None, Some(id)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedSource {
+    pub id: i64,
+    pub source: SourceId,
+    pub category_id: i64,
+    pub category: String,
+    pub account_id: i64,
+    pub account: String,
+    /// The pattern of the template reading the amount.
+    pub template: Option<String>,
+}
+
+impl TrustedSource {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<TrustedSource> {
+        let chat: Option<i64> = row.get(1)?;
+        let source = match chat {
+            Some(id) => SourceId::Chat(id),
+            None => SourceId::User(row.get(2)?),
+        };
+        Ok(TrustedSource {
+            id: row.get(0)?,
+            source,
+            category_id: row.get(3)?,
+            category: row.get(4)?,
+            account_id: row.get(5)?,
+            account: row.get(6)?,
+            template: row.get(7)?,
+        })
+    }
+
+    /// The amount the source's template reads from `text`, as written: the
+    /// group named `amount`, or else the first one. `None` without a
+    /// template or when it doesn't match.
+    pub fn read_amount<'t>(&self, text: &'t str) -> Option<&'t str> {
+        let regex = compile(self.template.as_deref()?).ok()?;
+        amount_group(&regex, text)
+    }
+}
+
+fn amount_group<'t>(regex: &Regex, text: &'t str) -> Option<&'t str> {
+    let captures = regex.captures(text)?;
+    captures
+        .name("amount")
+        .or_else(|| captures.get(1))
+        .map(|m| m.as_str())
+}
+
+const SELECT_SOURCE: &str = "
+    SELECT s.id, s.sourceChatId, s.sourceUserId, s.defaultCategoryId, ec.name,
+           s.defaultAccountId, COALESCE(a.displayName, a.name), t.pattern
+    FROM TrustedSource s
+    JOIN ExpenseCategory ec ON ec.id = s.defaultCategoryId
+    JOIN Account a ON a.id = s.defaultAccountId
+    LEFT JOIN ParseTemplate t ON t.id = s.parseTemplateId
+";
+
+impl Model {
+    /// Trusts forwards from `source`, returns the id of the source. Each
+    /// source is trusted once per household.
+    pub fn add_trusted_source(
+        &self,
+        household: i64,
+        source: SourceId,
+        category_id: i64,
+        account_id: i64,
+    ) -> Result<i64> {
+        self.check_writable()?;
+        self.get_category(household, category_id)?
+            .ok_or_else(|| Error::NotFound(format!("category {}", category_id)))?;
+        self.get_account(household, account_id)?
+            .ok_or_else(|| Error::NotFound(format!("account {}", account_id)))?;
+        if let Some(existing) = self.trusted_source(household, source)? {
+            return Err(Error::Refused(format!(
+                "{} is trusted already, as source #{}.",
+                source, existing.id
+            )));
+        }
+        let (chat, user) = source.columns();
+        self.connection.execute(
+            "INSERT INTO TrustedSource
+                (householdId, sourceChatId, sourceUserId, defaultCategoryId, defaultAccountId)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![household, chat, user, category_id, account_id],
+        )?;
+        let id = self.connection.last_insert_rowid();
+        info!("Household {} trusts {} as source {}", household, source, id);
+        Ok(id)
+    }
+
+    pub fn trusted_sources(&self, household: i64) -> Result<Vec<TrustedSource>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "{} WHERE s.householdId = ?1 ORDER BY s.id",
+            SELECT_SOURCE
+        ))?;
+        let sources = stmt
+            .query_map([household], TrustedSource::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sources)
+    }
+
+    /// The household's trust in `source`, if any.
+    pub fn trusted_source(
+        &self,
+        household: i64,
+        source: SourceId,
+    ) -> Result<Option<TrustedSource>> {
+        let (chat, user) = source.columns();
+        let source = self
+            .connection
+            .query_row(
+                &format!(
+                    "{} WHERE s.householdId = ?1
+                       AND (s.sourceChatId = ?2 OR s.sourceUserId = ?3)",
+                    SELECT_SOURCE
+                ),
+                params![household, chat, user],
+                TrustedSource::from_row,
+            )
+            .optional()?;
+        Ok(source)
+    }
+
+    /// Returns whether the household had the source.
+    pub fn delete_trusted_source(&self, household: i64, id: i64) -> Result<bool> {
+        self.check_writable()?;
+        let tx = self.connection.unchecked_transaction()?;
+        let template: Option<Option<i64>> = tx
+            .query_row(
+                "SELECT parseTemplateId FROM TrustedSource WHERE id = ?1 AND householdId = ?2",
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(template) = template else {
+            return Ok(false);
+        };
+        tx.execute("DELETE FROM TrustedSource WHERE id = ?1", [id])?;
+        tx.execute("DELETE FROM ParseTemplate WHERE id = ?1", [template])?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Reads the amount of the source's forwards with `pattern`, a regular
+    /// expression whose group named `amount`, or else its first group, is
+    /// the amount. `None` removes the template. Returns whether the
+    /// household had the source.
+    pub fn set_source_template(
+        &self,
+        household: i64,
+        id: i64,
+        pattern: Option<&str>,
+    ) -> Result<bool> {
+        self.check_writable()?;
+        if let Some(pattern) = pattern {
+            check_length("Pattern", pattern, MAX_NAME_LEN)?;
+            let regex =
+                compile(pattern).map_err(|e| Error::Refused(format!("Invalid pattern: {}", e)))?;
+            if regex.captures_len() < 2 {
+                return Err(Error::Refused(
+                    "The pattern needs a group around the amount, like (\\d+[.,]\\d\\d)."
+                        .to_string(),
+                ));
+            }
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        let old: Option<Option<i64>> = tx
+            .query_row(
+                "SELECT parseTemplateId FROM TrustedSource WHERE id = ?1 AND householdId = ?2",
+                [id, household],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(old) = old else {
+            return Ok(false);
+        };
+        let template = match pattern {
+            Some(pattern) => {
+                tx.execute(
+                    "INSERT INTO ParseTemplate (householdId, pattern) VALUES (?1, ?2)",
+                    params![household, pattern],
+                )?;
+                Some(tx.last_insert_rowid())
+            }
+            None => None,
+        };
+        tx.execute(
+            "UPDATE TrustedSource SET parseTemplateId = ?2 WHERE id = ?1",
+            params![id, template],
+        )?;
+        tx.execute("DELETE FROM ParseTemplate WHERE id = ?1", [old])?;
+        tx.commit()?;
+        info!("Set the template of source {} to {:?}", id, pattern);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_ids() {
+        assert_eq!(
+            Some(SourceId::Chat(-100_123)),
+            SourceId::parse("chat:-100123")
+        );
+        assert_eq!(Some(SourceId::User(42)), SourceId::parse("user:42"));
+        assert_eq!(None, SourceId::parse("channel:42"));
+        assert_eq!(None, SourceId::parse("user:alex"));
+        assert_eq!("chat:-100123", SourceId::Chat(-100_123).to_string());
+    }
+
+    #[test]
+    fn sources_are_trusted_per_household() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let channel = SourceId::Chat(-100_123);
+
+        let id = model.add_trusted_source(1, channel, 4, 3).unwrap();
+        let source = model.trusted_source(1, channel).unwrap().unwrap();
+        assert_eq!(
+            (id, "Utilities", "Family BYN", None),
+            (
+                source.id,
+                source.category.as_str(),
+                source.account.as_str(),
+                source.template.as_deref()
+            )
+        );
+        // The same id as a user is another source.
+        assert_eq!(
+            None,
+            model.trusted_source(1, SourceId::User(-100_123)).unwrap()
+        );
+        assert_eq!(None, model.trusted_source(2, channel).unwrap());
+        assert!(matches!(
+            model.add_trusted_source(1, channel, 1, 1),
+            Err(Error::Refused(reason)) if reason == format!("chat:-100123 is trusted already, as source #{}.", id)
+        ));
+        assert!(matches!(
+            model.add_trusted_source(2, channel, 4, 3),
+            Err(Error::NotFound(_))
+        ));
+        let user = model
+            .add_trusted_source(1, SourceId::User(7), 1, 1)
+            .unwrap();
+        let ids: Vec<i64> = model
+            .trusted_sources(1)
+            .unwrap()
+            .iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(vec![id, user], ids);
+
+        // Merging its category moves the source along.
+        model.merge_categories(1, 1001, (1, 3), false).unwrap();
+        assert_eq!(
+            3,
+            model
+                .trusted_source(1, SourceId::User(7))
+                .unwrap()
+                .unwrap()
+                .category_id
+        );
+
+        assert!(!model.delete_trusted_source(2, id).unwrap());
+        assert!(model.delete_trusted_source(1, id).unwrap());
+        assert_eq!(None, model.trusted_source(1, channel).unwrap());
+        assert!(!model.delete_trusted_source(1, id).unwrap());
+    }
+
+    #[test]
+    fn templates_read_the_amount() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let id = model
+            .add_trusted_source(1, SourceId::Chat(-1), 4, 3)
+            .unwrap();
+        let source = || {
+            model
+                .trusted_source(1, SourceId::Chat(-1))
+                .unwrap()
+                .unwrap()
+        };
+        let bill = "Bill for November\nWater: 12.00\nTo pay: 84,50 BYN";
+        assert_eq!(None, source().read_amount(bill));
+
+        assert!(model
+            .set_source_template(1, id, Some(r"to pay: (\d+[.,]\d\d)"))
+            .unwrap());
+        assert_eq!(Some("84,50"), source().read_amount(bill));
+        assert_eq!(None, source().read_amount("Thanks for paying"));
+        // A named group is the amount, whichever it is.
+        model
+            .set_source_template(1, id, Some(r"(Water): (?P<amount>[\d.]+)"))
+            .unwrap();
+        assert_eq!(Some("12.00"), source().read_amount(bill));
+        let templates: i64 = model
+            .connection
+            .query_row("SELECT COUNT(*) FROM ParseTemplate", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(1, templates, "replaced templates are dropped");
+
+        assert!(matches!(
+            model.set_source_template(1, id, Some("to pay")),
+            Err(Error::Refused(reason)) if reason.starts_with("The pattern needs a group")
+        ));
+        assert!(matches!(
+            model.set_source_template(1, id, Some("(")),
+            Err(Error::Refused(reason)) if reason.starts_with("Invalid pattern: ")
+        ));
+        assert!(!model.set_source_template(1, id + 1, None).unwrap());
+        assert!(model.set_source_template(1, id, None).unwrap());
+        assert_eq!(None, source().template);
+    }
+}
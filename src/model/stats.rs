@@ -0,0 +1,188 @@
+//! How much a user usually spends on a category, to catch amounts typed
+//! wrong, like 5000 for 50.00.
+
+use super::amount::from_minor;
+use super::{Model, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, TimeDelta, Utc};
+use rusqlite::params;
+
+/// How far back the usual amounts are looked for.
+pub const STATS_WINDOW: TimeDelta = TimeDelta::days(90);
+
+/// Fewer expenses than this say nothing about what is usual.
+const MIN_HISTORY: i64 = 3;
+
+/// Amounts more standard deviations than this above the mean are unusual.
+const MAX_DEVIATIONS: f64 = 3.0;
+
+/// Amounts of one user's expenses in a category and currency, in major
+/// units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CategoryStats {
+    pub count: i64,
+    pub mean: f64,
+    /// Population standard deviation.
+    pub std_dev: f64,
+}
+
+impl CategoryStats {
+    /// Whether `amount` is far above the usual ones. Without enough history
+    /// nothing is.
+    pub fn is_unusual(&self, amount: f64) -> bool {
+        self.count >= MIN_HISTORY && amount > self.mean + MAX_DEVIATIONS * self.std_dev
+    }
+}
+
+impl Model {
+    /// Statistics of the amounts the user spent on the category in
+    /// `currency` within `window` before `now`.
+    pub fn category_stats(
+        &self,
+        user_id: i64,
+        category_id: i64,
+        currency: &str,
+        window: TimeDelta,
+        now: DateTime<Utc>,
+    ) -> Result<CategoryStats> {
+        let (count, mean, mean_of_squares, exponent): (i64, Option<f64>, Option<f64>, Option<u32>) =
+            self.connection.query_row(
+                "SELECT COUNT(*), AVG(e.amountMinor), AVG(e.amountMinor * e.amountMinor),
+                        MAX(c.exponent)
+                 FROM Expense e
+                 JOIN Account a ON a.id = e.accountId
+                 JOIN Currency c ON c.id = a.currencyId
+                 WHERE e.userId = ?1 AND e.categoryId = ?2 AND c.name = ?3
+                     AND e.timestamp >= ?4 AND e.timestamp <= ?5 AND e.kind = 'expense'",
+                params![
+                    user_id,
+                    category_id,
+                    currency,
+                    DbTime(now - window),
+                    DbTime(now)
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )?;
+        let exponent = exponent.unwrap_or(2);
+        let mean = mean.unwrap_or(0.0);
+        // Rounding can make the variance of equal amounts a hair negative.
+        let variance = (mean_of_squares.unwrap_or(0.0) - mean * mean).max(0.0);
+        Ok(CategoryStats {
+            count,
+            mean: from_minor(1, exponent) * mean,
+            std_dev: from_minor(1, exponent) * variance.sqrt(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_710_000_000, 0).unwrap()
+    }
+
+    /// A model with Alex's Groceries in EUR of the given amounts, one a day
+    /// up to yesterday.
+    fn with_groceries(amounts: &[f64]) -> Model {
+        let model = Model::new(true);
+        model.fill_test_data();
+        for (days, amount) in amounts.iter().rev().enumerate() {
+            model
+                .insert_expense(&NewExpense {
+                    account_id: 1,
+                    category_id: 1,
+                    user_id: 1001,
+                    timestamp: now() - TimeDelta::days(days as i64 + 1),
+                    amount: *amount,
+                    comment: None,
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+        model
+    }
+
+    fn stats(model: &Model) -> CategoryStats {
+        model
+            .category_stats(1001, 1, "EUR", STATS_WINDOW, now())
+            .unwrap()
+    }
+
+    #[test]
+    fn mean_and_deviation_of_the_window() {
+        let model = with_groceries(&[40.0, 42.0, 44.0, 46.0]);
+        let s = stats(&model);
+        assert_eq!(4, s.count);
+        assert!((s.mean - 43.0).abs() < 1e-9, "{:?}", s);
+        assert!((s.std_dev - 5f64.sqrt()).abs() < 1e-9, "{:?}", s);
+
+        // Older expenses, other users, categories and currencies are left out.
+        assert_eq!(
+            0,
+            model
+                .category_stats(1001, 1, "EUR", STATS_WINDOW, now() - TimeDelta::days(120))
+                .unwrap()
+                .count
+        );
+        assert_eq!(
+            4,
+            model
+                .category_stats(1001, 1, "EUR", TimeDelta::days(10), now())
+                .unwrap()
+                .count
+        );
+        assert_eq!(
+            0,
+            model
+                .category_stats(1002, 1, "EUR", STATS_WINDOW, now())
+                .unwrap()
+                .count
+        );
+        assert_eq!(
+            0,
+            model
+                .category_stats(1001, 1, "USD", STATS_WINDOW, now())
+                .unwrap()
+                .count
+        );
+    }
+
+    #[test]
+    fn flags_amounts_far_above_the_usual() {
+        let s = stats(&with_groceries(&[40.0, 42.0, 44.0, 46.0]));
+        // 43 + 3 × 2.236 = 49.71
+        assert!(!s.is_unusual(49.7));
+        assert!(s.is_unusual(49.75));
+        assert!(s.is_unusual(5000.0));
+        assert!(!s.is_unusual(0.5));
+    }
+
+    #[test]
+    fn equal_amounts_flag_anything_larger() {
+        let s = stats(&with_groceries(&[19.99, 19.99, 19.99]));
+        assert_eq!(0.0, s.std_dev);
+        assert!(!s.is_unusual(19.99));
+        assert!(s.is_unusual(20.0));
+    }
+
+    #[test]
+    fn skewed_history_widens_the_band() {
+        let s = stats(&with_groceries(&[5.0, 5.0, 5.0, 5.0, 200.0]));
+        assert!(!s.is_unusual(200.0));
+        assert!(s.is_unusual(500.0));
+    }
+
+    #[test]
+    fn little_history_flags_nothing() {
+        let s = stats(&with_groceries(&[10.0, 10.0]));
+        assert_eq!(2, s.count);
+        assert!(!s.is_unusual(5000.0));
+        let s = stats(&with_groceries(&[]));
+        assert_eq!((0, 0.0, 0.0), (s.count, s.mean, s.std_dev));
+        assert!(!s.is_unusual(5000.0));
+    }
+}
@@ -0,0 +1,579 @@
+use super::amount::{from_minor, to_minor};
+use super::review::Completeness;
+use super::{DateRange, Model, Period, Result};
+use crate::time::DbTime;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryTotal {
+    pub category: String,
+    pub currency: String,
+    /// Decimal places of the currency.
+    pub exponent: u32,
+    pub total: f64,
+    pub count: i64,
+    /// The category this is a subcategory of.
+    pub parent: Option<String>,
+}
+
+/// Spend of a top-level category in one currency, its own and that of its
+/// subcategories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryGroup<'a> {
+    pub category: &'a str,
+    pub exponent: u32,
+    /// Spend in the category itself, `None` if there was none.
+    pub own: Option<&'a CategoryTotal>,
+    /// Subcategories with spend, largest first.
+    pub children: Vec<&'a CategoryTotal>,
+    /// Own and subcategory spend together.
+    pub total: f64,
+}
+
+/// Which time of an expense puts it into a report's period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportDate {
+    /// When it was made.
+    Spent,
+    /// When it was logged, to see who back-fills.
+    Logged,
+}
+
+impl ReportDate {
+    fn column(self) -> &'static str {
+        match self {
+            ReportDate::Spent => "timestamp",
+            ReportDate::Logged => "createdAt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub range: DateRange,
+    pub date: ReportDate,
+    /// Totals ordered by currency, then by the largest spend first.
+    pub categories: Vec<CategoryTotal>,
+    /// Names of the categories left out, which leaves out their
+    /// subcategories too.
+    pub excluded: Vec<String>,
+    /// Totals of the categories left out, ordered like `categories`.
+    pub excluded_spend: Vec<CategoryTotal>,
+    /// How far the expenses can be trusted, for reports that tell.
+    pub completeness: Option<Completeness>,
+    /// Whether every month of the range is closed, its figures final.
+    pub closed: bool,
+}
+
+/// Total per currency of `categories` ordered by currency.
+fn currency_totals(categories: &[CategoryTotal]) -> Vec<(String, f64)> {
+    let mut totals: Vec<(String, f64)> = Vec::new();
+    for c in categories {
+        match totals.last_mut() {
+            Some((currency, total)) if *currency == c.currency => *total += c.total,
+            _ => totals.push((c.currency.clone(), c.total)),
+        }
+    }
+    totals
+}
+
+impl Summary {
+    /// Total per currency, in the order currencies appear in `categories`.
+    pub fn currency_totals(&self) -> Vec<(String, f64)> {
+        currency_totals(&self.categories)
+    }
+
+    /// Total per currency of the categories left out.
+    pub fn excluded_totals(&self) -> Vec<(String, f64)> {
+        currency_totals(&self.excluded_spend)
+    }
+
+    /// Spend in `currency` rolled up to top-level categories, largest first.
+    pub fn groups(&self, currency: &str) -> Vec<CategoryGroup<'_>> {
+        let mut groups: Vec<CategoryGroup> = Vec::new();
+        for c in self.categories.iter().filter(|c| c.currency == currency) {
+            let name = c.parent.as_deref().unwrap_or(&c.category);
+            let index = match groups.iter().position(|g| g.category == name) {
+                Some(index) => index,
+                None => {
+                    groups.push(CategoryGroup {
+                        category: name,
+                        exponent: c.exponent,
+                        own: None,
+                        children: Vec::new(),
+                        total: 0.0,
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            match c.parent {
+                Some(_) => group.children.push(c),
+                None => group.own = Some(c),
+            }
+        }
+        for group in &mut groups {
+            let minor: i64 = group
+                .own
+                .iter()
+                .chain(&group.children)
+                .map(|c| to_minor(c.total, c.exponent))
+                .sum();
+            group.total = from_minor(minor, group.exponent);
+        }
+        groups.sort_by(|a, b| {
+            b.total
+                .total_cmp(&a.total)
+                .then_with(|| a.category.cmp(b.category))
+        });
+        groups
+    }
+}
+
+/// Spend of the current month in one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthToDate {
+    pub currency: String,
+    pub exponent: u32,
+    pub total: f64,
+    pub count: i64,
+    pub categories: Vec<CategoryTotal>,
+}
+
+impl MonthToDate {
+    pub fn category(&self, name: &str) -> Option<&CategoryTotal> {
+        self.categories.iter().find(|c| c.category == name)
+    }
+}
+
+/// `?3` optionally restricts the summary to one user's spend: their share
+/// of split expenses, and the others they paid. `?4` is the household.
+const SUMMARY: &str = "
+    SELECT ec.name, c.name, c.exponent, SUM(x.amount), COUNT(*), pc.name
+    FROM (
+        SELECT e.categoryId, e.accountId,
+            CASE
+                WHEN ?3 IS NULL THEN e.amountMinor
+                WHEN EXISTS (SELECT 1 FROM {shares} s WHERE s.expenseId = e.id) THEN (
+                    SELECT s.shareMinor FROM {shares} s
+                    WHERE s.expenseId = e.id AND s.userId = ?3
+                )
+                WHEN e.userId = ?3 THEN e.amountMinor
+            END AS amount
+        FROM {expenses} e
+        WHERE e.{date} >= ?1 AND e.{date} < ?2 AND e.kind = 'expense'
+    ) x
+    JOIN ExpenseCategory ec ON ec.id = x.categoryId
+    LEFT JOIN ExpenseCategory pc ON pc.id = ec.parentId
+    JOIN Account a ON a.id = x.accountId
+    JOIN Currency c ON c.id = a.currencyId
+    WHERE x.amount IS NOT NULL AND a.householdId = ?4
+    GROUP BY ec.id, c.id
+    ORDER BY c.name, SUM(x.amount) DESC, ec.name
+";
+
+impl Model {
+    /// Spend of the household per category and currency over the local days
+    /// of `range`.
+    pub fn summarize(&self, household: i64, range: DateRange, tz: Tz) -> Result<Summary> {
+        self.summarize_for(household, range, (None, &[]), tz)
+    }
+
+    /// Like [`Model::summarize`], with the spend of the `exclude`d
+    /// categories and their subcategories set apart. Categories of other
+    /// households are ignored.
+    pub fn summarize_excluding(
+        &self,
+        household: i64,
+        range: DateRange,
+        exclude: &[i64],
+        tz: Tz,
+    ) -> Result<Summary> {
+        self.summarize_for(household, range, (None, exclude), tz)
+    }
+
+    /// Like [`Model::summarize_excluding`], of the expenses logged rather
+    /// than made over the local days of `range`.
+    pub fn summarize_logged(
+        &self,
+        household: i64,
+        range: DateRange,
+        exclude: &[i64],
+        tz: Tz,
+    ) -> Result<Summary> {
+        self.summarize_by(household, range, (None, exclude, ReportDate::Logged), tz)
+    }
+
+    /// Like [`Model::summarize_excluding`], of the expenses of `user_id`
+    /// only.
+    pub fn summarize_user(
+        &self,
+        household: i64,
+        user_id: i64,
+        range: DateRange,
+        exclude: &[i64],
+        tz: Tz,
+    ) -> Result<Summary> {
+        self.summarize_for(household, range, (Some(user_id), exclude), tz)
+    }
+
+    fn summarize_for(
+        &self,
+        household: i64,
+        range: DateRange,
+        (user_id, exclude): (Option<i64>, &[i64]),
+        tz: Tz,
+    ) -> Result<Summary> {
+        self.summarize_by(household, range, (user_id, exclude, ReportDate::Spent), tz)
+    }
+
+    fn summarize_by(
+        &self,
+        household: i64,
+        range: DateRange,
+        (user_id, exclude, date): (Option<i64>, &[i64], ReportDate),
+        tz: Tz,
+    ) -> Result<Summary> {
+        let (start, end) = range.to_utc(tz);
+        // Archived expenses count as logged when they were made, so they
+        // are only needed as far back as the period.
+        let tables = self.expense_tables(Some(start))?;
+        let sql = tables.apply(SUMMARY).replace("{date}", date.column());
+        let mut stmt = self.connection.prepare(&sql)?;
+        let params = params![DbTime(start), DbTime(end), user_id, household];
+        let categories = stmt
+            .query_map(params, |row| {
+                let exponent = row.get(2)?;
+                Ok(CategoryTotal {
+                    category: row.get(0)?,
+                    currency: row.get(1)?,
+                    exponent,
+                    total: from_minor(row.get(3)?, exponent),
+                    count: row.get(4)?,
+                    parent: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<CategoryTotal>>>()?;
+        let mut excluded = Vec::new();
+        for &id in exclude {
+            if let Some(category) = self.get_category(household, id)? {
+                excluded.push(category.name);
+            }
+        }
+        let (excluded_spend, categories) = categories.into_iter().partition(|c| {
+            excluded.contains(&c.category)
+                || c.parent.as_ref().is_some_and(|p| excluded.contains(p))
+        });
+        Ok(Summary {
+            range,
+            date,
+            categories,
+            excluded,
+            excluded_spend,
+            completeness: None,
+            closed: false,
+        })
+    }
+
+    /// Spend of the household in `currency` since the 1st of the local month
+    /// of `now`, by everybody or only by `user_id`.
+    pub fn month_to_date(
+        &self,
+        household: i64,
+        currency: &str,
+        user_id: Option<i64>,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<MonthToDate> {
+        let range = Period::ThisMonth.resolve(now, tz, self.calendar(household)?);
+        let summary = self.summarize_for(household, range, (user_id, &[]), tz)?;
+        let categories: Vec<CategoryTotal> = summary
+            .categories
+            .into_iter()
+            .filter(|c| c.currency == currency)
+            .collect();
+        let exponent = match categories.first() {
+            Some(c) => c.exponent,
+            None => self.currency_exponent(currency)?.unwrap_or(2),
+        };
+        Ok(MonthToDate {
+            currency: currency.to_string(),
+            exponent,
+            total: categories.iter().map(|c| c.total).sum(),
+            count: categories.iter().map(|c| c.count).sum(),
+            categories,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn december() -> DateRange {
+        DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        )
+    }
+
+    #[test]
+    fn summarizes_per_category_and_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let summary = model.summarize(1, december(), Tz::UTC).unwrap();
+        let rows: Vec<(&str, &str, f64)> = summary
+            .categories
+            .iter()
+            .map(|c| (c.category.as_str(), c.currency.as_str(), c.total))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Entertainment", "BYN", 30.0),
+                ("Groceries", "EUR", 50.75),
+                ("Utilities", "USD", 100.0),
+                ("Transportation", "USD", 20.0),
+            ],
+            rows
+        );
+        assert_eq!(
+            vec![
+                ("BYN".to_string(), 30.0),
+                ("EUR".to_string(), 50.75),
+                ("USD".to_string(), 120.0)
+            ],
+            summary.currency_totals()
+        );
+    }
+
+    #[test]
+    fn summarizes_the_expenses_of_a_user() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        let summary = model
+            .summarize_user(1, 1002, december(), &[], Tz::UTC)
+            .unwrap();
+        let rows: Vec<(&str, f64)> = summary
+            .categories
+            .iter()
+            .map(|c| (c.category.as_str(), c.total))
+            .collect();
+        assert_eq!(vec![("Utilities", 100.0), ("Transportation", 20.0)], rows);
+    }
+
+    #[test]
+    fn summarizes_by_date_logged() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Cash spent in December, back-filled today.
+        model
+            .insert_expense(&crate::model::NewExpense {
+                account_id: 3,
+                category_id: 1,
+                user_id: 1001,
+                timestamp: DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+                amount: 12.0,
+                comment: None,
+                category_confirmed: true,
+                source: crate::model::ExpenseSource::Manual,
+            })
+            .unwrap();
+        let byn = |summary: &Summary| {
+            summary
+                .categories
+                .iter()
+                .filter(|c| c.currency == "BYN")
+                .map(|c| (c.category.clone(), c.total))
+                .collect::<Vec<_>>()
+        };
+
+        let spent = model.summarize(1, december(), Tz::UTC).unwrap();
+        assert_eq!(ReportDate::Spent, spent.date);
+        assert_eq!(
+            vec![
+                ("Entertainment".to_string(), 30.0),
+                ("Groceries".to_string(), 12.0)
+            ],
+            byn(&spent)
+        );
+        let logged = model.summarize_logged(1, december(), &[], Tz::UTC).unwrap();
+        assert_eq!(ReportDate::Logged, logged.date);
+        assert_eq!(vec![("Entertainment".to_string(), 30.0)], byn(&logged));
+
+        let today = Utc::now().date_naive();
+        let today = DateRange::inclusive(today, today);
+        let logged = model.summarize_logged(1, today, &[], Tz::UTC).unwrap();
+        assert_eq!(vec![("Groceries".to_string(), 12.0)], byn(&logged));
+        assert!(model
+            .summarize(1, today, Tz::UTC)
+            .unwrap()
+            .categories
+            .is_empty());
+    }
+
+    #[test]
+    fn rolls_subcategories_up() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let food = model.add_test_category(1, "Food");
+        model.set_category_parent(1, 1, Some(food)).unwrap();
+        model.set_category_parent(1, 3, Some(food)).unwrap();
+        for (category_id, amount) in [(food, 0.1), (3, 0.2)] {
+            model
+                .insert_expense(&crate::model::NewExpense {
+                    account_id: 1,
+                    category_id,
+                    user_id: 1001,
+                    timestamp: DateTime::from_timestamp(1_702_000_000, 0).unwrap(),
+                    amount,
+                    comment: None,
+                    category_confirmed: true,
+                    source: crate::model::ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+
+        let summary = model.summarize(1, december(), Tz::UTC).unwrap();
+        let eur = summary.groups("EUR");
+        assert_eq!(1, eur.len());
+        assert_eq!(("Food", 51.05), (eur[0].category, eur[0].total));
+        assert_eq!(Some(0.1), eur[0].own.map(|c| c.total));
+        assert_eq!(
+            vec![("Groceries", 50.75), ("Entertainment", 0.2)],
+            eur[0]
+                .children
+                .iter()
+                .map(|c| (c.category.as_str(), c.total))
+                .collect::<Vec<_>>()
+        );
+
+        // Only a subcategory spent in, no spend of the parent itself.
+        let byn = summary.groups("BYN");
+        assert_eq!(
+            ("Food", 30.0, None),
+            (byn[0].category, byn[0].total, byn[0].own)
+        );
+        assert_eq!(1, byn[0].children.len());
+
+        let usd: Vec<(&str, f64, usize)> = summary
+            .groups("USD")
+            .iter()
+            .map(|g| (g.category, g.total, g.children.len()))
+            .collect();
+        assert_eq!(
+            vec![("Utilities", 100.0, 0), ("Transportation", 20.0, 0)],
+            usd
+        );
+        assert!(summary.groups("JPY").is_empty());
+    }
+
+    #[test]
+    fn sets_excluded_categories_apart() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.fill_second_household();
+        let housing = model.add_test_category(1, "Housing");
+        model.set_category_parent(1, 4, Some(housing)).unwrap();
+        // Per currency, in cents: all the test currencies have two decimals.
+        let totals = |summary: &Summary| {
+            let mut totals = std::collections::BTreeMap::new();
+            let spent = summary.currency_totals().into_iter();
+            for (currency, total) in spent.chain(summary.excluded_totals()) {
+                *totals.entry(currency).or_insert(0) += to_minor(total, 2);
+            }
+            totals
+        };
+        let all = model.summarize(1, december(), Tz::UTC).unwrap();
+
+        // Housing takes its Utilities subcategory along.
+        let summary = model
+            .summarize_excluding(1, december(), &[housing, 1], Tz::UTC)
+            .unwrap();
+        assert_eq!(vec!["Housing", "Groceries"], summary.excluded);
+        let left: Vec<&str> = summary
+            .categories
+            .iter()
+            .map(|c| c.category.as_str())
+            .collect();
+        assert_eq!(vec!["Entertainment", "Transportation"], left);
+        assert_eq!(
+            vec![("EUR".to_string(), 50.75), ("USD".to_string(), 100.0)],
+            summary.excluded_totals()
+        );
+        assert_eq!(totals(&all), totals(&summary));
+
+        // Garden is the neighbours'.
+        let summary = model
+            .summarize_excluding(1, december(), &[5], Tz::UTC)
+            .unwrap();
+        assert!(summary.excluded.is_empty());
+        assert_eq!(all.categories, summary.categories);
+
+        let hanna = model
+            .summarize_user(1, 1002, december(), &[2], Tz::UTC)
+            .unwrap();
+        assert_eq!(vec![("USD".to_string(), 20.0)], hanna.excluded_totals());
+        assert_eq!(vec![("USD".to_string(), 100.0)], hanna.currency_totals());
+    }
+
+    #[test]
+    fn range_bounds_are_local_days() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        // The groceries at 2023-12-01 10:00 UTC are still on the 1st in
+        // Tokyo (19:00), but the range below starts on the 2nd.
+        let range = DateRange::inclusive(
+            NaiveDate::from_ymd_opt(2023, 12, 2).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+        );
+        let summary = model.summarize(1, range, Tz::Asia__Tokyo).unwrap();
+        assert!(summary.categories.iter().all(|c| c.category != "Groceries"));
+        assert_eq!(3, summary.categories.len());
+    }
+
+    #[test]
+    fn month_to_date_in_one_currency() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let now = DateTime::parse_from_rfc3339("2023-12-15T12:00:00Z")
+            .unwrap()
+            .to_utc();
+
+        let usd = model.month_to_date(1, "USD", None, now, Tz::UTC).unwrap();
+        assert_eq!((120.0, 2, 2), (usd.total, usd.count, usd.exponent));
+        assert_eq!(20.0, usd.category("Transportation").unwrap().total);
+        assert_eq!(None, usd.category("Groceries"));
+
+        let alex = model
+            .month_to_date(1, "USD", Some(1001), now, Tz::UTC)
+            .unwrap();
+        assert_eq!((0.0, 0), (alex.total, alex.count));
+        let hanna = model
+            .month_to_date(1, "USD", Some(1002), now, Tz::UTC)
+            .unwrap();
+        assert_eq!(120.0, hanna.total);
+    }
+
+    #[test]
+    fn month_to_date_uses_local_month() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Still November in New York, already December in UTC.
+        let now = DateTime::parse_from_rfc3339("2023-12-01T03:00:00Z")
+            .unwrap()
+            .to_utc();
+
+        let eur = model.month_to_date(1, "EUR", None, now, Tz::UTC).unwrap();
+        assert_eq!(1, eur.count);
+        let eur = model
+            .month_to_date(1, "EUR", None, now, Tz::America__New_York)
+            .unwrap();
+        assert_eq!((0, 2), (eur.count, eur.exponent));
+    }
+}
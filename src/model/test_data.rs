@@ -6,27 +6,41 @@ INSERT INTO Currency (name) VALUES ('USD');
 INSERT INTO Currency (name) VALUES ('BYN');
 
 -- Insert test data for User
-INSERT INTO User (telegramId, telegramName, displayName) VALUES (1001, 'alex_bot', 'Alex');
-INSERT INTO User (telegramId, telegramName, displayName) VALUES (1002, 'hanna_bot', 'Hanna');
+INSERT INTO User (telegramId, telegramName, displayName, householdId) VALUES (1001, 'alex_bot', 'Alex', 1);
+INSERT INTO User (telegramId, telegramName, displayName, householdId) VALUES (1002, 'hanna_bot', 'Hanna', 1);
 
 -- Insert test data for Account
-INSERT INTO Account (name, displayName, currencyId) VALUES
-('Savings', 'Alex Savings', 1), -- EUR
-('Expenses', 'Hanna Daily', 2), -- USD
-('Family Fund', 'Family BYN', 3); -- BYN
+INSERT INTO Account (name, displayName, currencyId, initialBalanceMinor, householdId) VALUES
+('Savings', 'Alex Savings', 1, 100000, 1), -- EUR
+('Expenses', 'Hanna Daily', 2, 50000, 1), -- USD
+('Family Fund', 'Family BYN', 3, 30000, 1); -- BYN
 
 -- Insert test data for ExpenseCategory
-INSERT INTO ExpenseCategory (name, active, comments, sortingOrder) VALUES
-('Groceries', 1, 'Food and daily groceries', 1),
-('Transportation', 1, 'Bus, taxi, fuel expenses', 2),
-('Entertainment', 1, 'Movies, concerts, etc.', 3),
-('Utilities', 1, 'Electricity, water, internet bills', 4);
+INSERT INTO ExpenseCategory (name, icon, active, comments, sortingOrder, householdId) VALUES
+('Groceries', '🛒', 1, 'Food and daily groceries', 1, 1),
+('Transportation', '🚌', 1, 'Bus, taxi, fuel expenses', 2, 1),
+('Entertainment', '🎬', 1, 'Movies, concerts, etc.', 3, 1),
+('Utilities', '💡', 1, 'Electricity, water, internet bills', 4, 1);
 
--- Insert test data for Expense
-INSERT INTO Expense (accountId, categoryId, userId, timestamp, amount, comments) VALUES
-(1, 1, 1001, strftime('%s', '2023-12-01 10:00:00'), 50.75, 'Bought groceries for the week'), -- Alex, EUR, Groceries
-(2, 2, 1002, strftime('%s', '2023-12-02 12:30:00'), 20.00, 'Taxi ride to the office'),       -- Hanna, USD, Transportation
-(3, 3, 1001, strftime('%s', '2023-12-03 18:00:00'), 30.00, 'Cinema tickets for family'),    -- Alex, BYN, Entertainment
-(2, 4, 1002, strftime('%s', '2023-12-04 20:00:00'), 100.00, 'Paid electricity bill');        -- Hanna, USD, Utilities
+-- Insert test data for Expense, timestamps are unix seconds and amounts cents,
+-- each logged when it was made
+INSERT INTO Expense (accountId, categoryId, userId, timestamp, createdAt, amountMinor, comments) VALUES
+(1, 1, 1001, 1701424800, 1701424800, 5075, 'Bought groceries for the week'), -- 2023-12-01 10:00 UTC, Alex, EUR, Groceries
+(2, 2, 1002, 1701520200, 1701520200, 2000, 'Taxi ride to the office'),       -- 2023-12-02 12:30 UTC, Hanna, USD, Transportation
+(3, 3, 1001, 1701626400, 1701626400, 3000, 'Cinema tickets for family'),    -- 2023-12-03 18:00 UTC, Alex, BYN, Entertainment
+(2, 4, 1002, 1701720000, 1701720000, 10000, 'Paid electricity bill');        -- 2023-12-04 20:00 UTC, Hanna, USD, Utilities
 ";
 
+/// A second household next to the one of `TEST_DATA`, with its own admin,
+/// account 4, category 5 and expense 5.
+pub const SECOND_HOUSEHOLD: &str = "
+INSERT INTO Household (id, name) VALUES (2, 'Neighbours');
+INSERT INTO User (telegramId, telegramName, displayName, role, householdId) VALUES
+(2001, 'nora_bot', 'Nora', 'admin', 2);
+INSERT INTO Account (name, currencyId, initialBalanceMinor, householdId) VALUES
+('Neighbours Cash', 1, 10000, 2); -- EUR
+INSERT INTO ExpenseCategory (name, icon, sortingOrder, householdId) VALUES
+('Garden', '🌱', 1, 2);
+INSERT INTO Expense (accountId, categoryId, userId, timestamp, createdAt, amountMinor, comments) VALUES
+(4, 5, 2001, 1701500000, 1701500000, 1200, 'Seeds'); -- 2023-12-02 06:53 UTC, Nora, EUR, Garden
+";
@@ -0,0 +1,385 @@
+use super::lengths::shorten_name;
+use super::{Error, Model, Result};
+use log::*;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, OptionalExtension};
+use std::fmt;
+
+/// Access level of a user. Variants are ordered from the least to the most
+/// privileged, so a higher role includes everything the lower ones can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Member,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Member => "member",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        match s.trim().to_lowercase().as_str() {
+            "viewer" => Some(Role::Viewer),
+            "member" => Some(Role::Member),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn allows(self, required: Role) -> bool {
+        self >= required
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        Role::parse(s).ok_or_else(|| FromSqlError::Other(format!("unknown role: {}", s).into()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub telegram_id: i64,
+    pub telegram_name: String,
+    pub display_name: String,
+    pub role: Role,
+    /// Everything the user sees and changes is of this household.
+    pub household: i64,
+}
+
+impl User {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+        Ok(User {
+            telegram_id: row.get(0)?,
+            telegram_name: row.get(1)?,
+            display_name: row.get(2)?,
+            role: row.get(3)?,
+            household: row.get(4)?,
+        })
+    }
+}
+
+impl Model {
+    /// Returns the user if they are allowed to use the bot.
+    pub fn get_user(&self, telegram_id: i64) -> Result<Option<User>> {
+        let user = self
+            .connection
+            .query_row(
+                "SELECT telegramId, telegramName, displayName, role, householdId FROM User
+                 WHERE telegramId = ?1",
+                [telegram_id],
+                User::from_row,
+            )
+            .optional()?;
+        Ok(user)
+    }
+
+    /// The users of the household, by name.
+    pub fn users(&self, household: i64) -> Result<Vec<User>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT telegramId, telegramName, displayName, role, householdId FROM User
+             WHERE householdId = ?1
+             ORDER BY displayName, telegramId",
+        )?;
+        let users = stmt
+            .query_map([household], User::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(users)
+    }
+
+    /// The users named `name`: by telegram username with a leading `@`,
+    /// and without it by username or display name, ignoring case. Names are
+    /// only refreshed as users interact, so a name may have moved on to
+    /// someone else meanwhile and match several, by id.
+    pub fn find_user_by_name(&self, name: &str) -> Result<Vec<User>> {
+        let name = name.trim();
+        let (name, usernames_only) = match name.strip_prefix('@') {
+            Some(username) => (username, true),
+            None => (name, false),
+        };
+        // Users allowed by id have no username until they show up.
+        if name.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.connection.prepare(
+            "SELECT telegramId, telegramName, displayName, role, householdId FROM User
+             WHERE telegramName = ?1 COLLATE NOCASE
+                OR (NOT ?2 AND displayName = ?1 COLLATE NOCASE)
+             ORDER BY telegramId",
+        )?;
+        let users = stmt
+            .query_map(params![name, usernames_only], User::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(users)
+    }
+
+    /// Refreshes the names and language of an already known user. Unknown
+    /// users are left alone: being allowed is decided by an admin, not by
+    /// messaging the bot. Telegram allows longer names than are stored,
+    /// those are shortened.
+    pub fn touch_user(
+        &self,
+        telegram_id: i64,
+        (telegram_name, display_name): (&str, &str),
+        language: Option<&str>,
+    ) -> Result<()> {
+        self.connection.execute(
+            "UPDATE User SET telegramName = ?2, displayName = ?3, languageCode = ?4
+             WHERE telegramId = ?1",
+            params![
+                telegram_id,
+                telegram_name,
+                shorten_name(display_name),
+                language
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The Telegram language the user was last seen with, if they have one.
+    pub fn user_language(&self, telegram_id: i64) -> Result<Option<String>> {
+        let language = self
+            .connection
+            .query_row(
+                "SELECT languageCode FROM User WHERE telegramId = ?1",
+                [telegram_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(language.flatten())
+    }
+
+    /// Sets the role of a user in the household, allowing them first if
+    /// needed. Names of a freshly allowed user are filled in on their first
+    /// interaction. Users of other households are refused.
+    pub fn set_role(&self, household: i64, telegram_id: i64, role: Role) -> Result<()> {
+        self.check_writable()?;
+        info!("Setting role of {} to {}", telegram_id, role);
+        let changed = self.connection.execute(
+            "INSERT INTO User (telegramId, telegramName, displayName, role, householdId)
+             VALUES (?1, '', ?1, ?2, ?3)
+             ON CONFLICT (telegramId) DO UPDATE SET role = excluded.role
+             WHERE householdId = excluded.householdId",
+            params![telegram_id, role, household],
+        )?;
+        if changed == 0 {
+            return Err(Error::Refused(format!(
+                "User {} belongs to another household.",
+                telegram_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Marks whether the user got their welcome, returns whether that
+    /// changed: marking it first claims the welcome, so that it goes out
+    /// once, and unmarking gives it back when it couldn't be sent.
+    pub fn set_welcomed(&self, telegram_id: i64, welcomed: bool) -> Result<bool> {
+        let changed = self.connection.execute(
+            "UPDATE User SET welcomed = ?2 WHERE telegramId = ?1 AND welcomed != ?2",
+            params![telegram_id, welcomed],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Resolves an admin command argument: a numeric telegram id, which
+    /// needn't be known yet, or a name of [`Model::find_user_by_name`].
+    /// Refused naming the candidates if several users go by it.
+    pub fn resolve_user_id(&self, user: &str) -> Result<i64> {
+        if let Ok(id) = user.trim().parse::<i64>() {
+            return Ok(id);
+        }
+        match self.find_user_by_name(user)?.as_slice() {
+            [] => Err(Error::NotFound(format!("user {}", user))),
+            [found] => Ok(found.telegram_id),
+            several => {
+                let candidates: Vec<String> = several
+                    .iter()
+                    .map(|u| match u.telegram_name.as_str() {
+                        "" => format!("{} ({})", u.display_name, u.telegram_id),
+                        name => format!("{} (@{}, {})", u.display_name, name, u.telegram_id),
+                    })
+                    .collect();
+                Err(Error::Refused(format!(
+                    "'{}' matches several users: {}. Use their numeric id.",
+                    user.trim(),
+                    candidates.join(", ")
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_are_ordered() {
+        assert!(Role::Admin.allows(Role::Member));
+        assert!(Role::Admin.allows(Role::Viewer));
+        assert!(Role::Member.allows(Role::Viewer));
+        assert!(Role::Member.allows(Role::Member));
+        assert!(!Role::Member.allows(Role::Admin));
+        assert!(!Role::Viewer.allows(Role::Member));
+    }
+
+    #[test]
+    fn role_parse_roundtrip() {
+        for role in [Role::Viewer, Role::Member, Role::Admin] {
+            assert_eq!(Some(role), Role::parse(role.as_str()));
+        }
+        assert_eq!(Some(Role::Viewer), Role::parse(" Viewer "));
+        assert_eq!(None, Role::parse("owner"));
+    }
+
+    #[test]
+    fn set_role_allows_and_updates() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(Role::Member, model.get_user(1001).unwrap().unwrap().role);
+        model.set_role(1, 1001, Role::Viewer).unwrap();
+        assert_eq!(Role::Viewer, model.get_user(1001).unwrap().unwrap().role);
+
+        assert_eq!(None, model.get_user(2000).unwrap());
+        model.set_role(1, 2000, Role::Member).unwrap();
+        assert_eq!(Role::Member, model.get_user(2000).unwrap().unwrap().role);
+    }
+
+    #[test]
+    fn welcomes_are_claimed_once() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        model.set_role(1, 2000, Role::Member).unwrap();
+
+        assert!(model.set_welcomed(2000, true).unwrap());
+        assert!(!model.set_welcomed(2000, true).unwrap());
+        // Given back, it can be claimed again.
+        assert!(model.set_welcomed(2000, false).unwrap());
+        assert!(model.set_welcomed(2000, true).unwrap());
+        assert!(!model.set_welcomed(3000, true).unwrap());
+    }
+
+    #[test]
+    fn resolve_user_by_name_or_id() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        assert_eq!(1002, model.resolve_user_id("@hanna_bot").unwrap());
+        assert_eq!(1002, model.resolve_user_id("Hanna_Bot").unwrap());
+        assert_eq!(42, model.resolve_user_id("42").unwrap());
+        assert!(matches!(
+            model.resolve_user_id("@nobody"),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn users_by_display_name() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(1001, model.resolve_user_id(" alex ").unwrap());
+        // A display name isn't a username.
+        assert!(matches!(
+            model.resolve_user_id("@Alex"),
+            Err(Error::NotFound(_))
+        ));
+
+        // Allowed by id, with no names yet: neither matches them.
+        model.set_role(1, 1003, Role::Member).unwrap();
+        assert!(model.find_user_by_name("@").unwrap().is_empty());
+        assert!(model.find_user_by_name("").unwrap().is_empty());
+
+        // Another Alex shows up.
+        model.touch_user(1003, ("", "Alex"), None).unwrap();
+        assert_eq!(
+            Err("'Alex' matches several users: Alex (@alex_bot, 1001), Alex (1003). Use their numeric id.".to_string()),
+            model.resolve_user_id("Alex").map_err(|e| e.to_string())
+        );
+        assert_eq!(1001, model.resolve_user_id("@alex_bot").unwrap());
+    }
+
+    #[test]
+    fn renamed_users_keep_their_expenses() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let logged = |model: &Model| {
+            (1..=4)
+                .filter_map(|id| model.get_expense_details(1, id).unwrap())
+                .filter(|e| e.user_id == 1002)
+                .count()
+        };
+        assert_eq!(2, logged(&model));
+
+        // Hanna changes her username and name on Telegram between two
+        // messages.
+        model
+            .touch_user(1002, ("hanna_b", "Hanna B"), None)
+            .unwrap();
+        let hanna = model.get_user(1002).unwrap().unwrap();
+        assert_eq!(
+            ("hanna_b", "Hanna B", Role::Member),
+            (
+                hanna.telegram_name.as_str(),
+                hanna.display_name.as_str(),
+                hanna.role
+            )
+        );
+        assert_eq!(1002, model.resolve_user_id("@hanna_b").unwrap());
+        assert!(matches!(
+            model.resolve_user_id("@hanna_bot"),
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(2, logged(&model));
+        assert_eq!(
+            Some("Hanna B"),
+            model
+                .get_expense_details(1, 2)
+                .unwrap()
+                .unwrap()
+                .user
+                .as_deref()
+        );
+    }
+
+    #[test]
+    fn touch_user_ignores_unknown() {
+        let model = Model::new(true);
+        model.fill_test_data();
+
+        model
+            .touch_user(1001, ("alex_new", "Alex N"), Some("ru"))
+            .unwrap();
+        model
+            .touch_user(3000, ("stranger", "Stranger"), Some("en"))
+            .unwrap();
+
+        let alex = model.get_user(1001).unwrap().unwrap();
+        assert_eq!("alex_new", alex.telegram_name);
+        assert_eq!("Alex N", alex.display_name);
+        assert_eq!(Some("ru".to_string()), model.user_language(1001).unwrap());
+        assert_eq!(None, model.user_language(1002).unwrap());
+        assert_eq!(None, model.get_user(3000).unwrap());
+        assert_eq!(None, model.user_language(3000).unwrap());
+    }
+}
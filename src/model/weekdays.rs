@@ -0,0 +1,146 @@
+//! `/report heatmap`: what a household spends on an average Monday,
+//! Tuesday and so on, to show patterns like groceries bought on weekends.
+
+use super::amount::from_minor;
+use super::year::{expenses, starts, BOUNDS};
+use super::{Model, Result};
+use crate::time;
+use chrono::{DateTime, Datelike, Days, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+
+/// The average spend of each local weekday in one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeekdayAverages {
+    pub currency: String,
+    pub exponent: u32,
+    /// Monday first, 0 for weekdays without any.
+    pub averages: [f64; 7],
+}
+
+impl Model {
+    /// The average spend per local weekday over the `weeks` weeks up to
+    /// yesterday, so that each weekday counts as many whole days, one entry
+    /// per currency by name. With a category, only its expenses and those
+    /// of its subcategories.
+    pub fn weekday_averages(
+        &self,
+        household: i64,
+        category: Option<i64>,
+        weeks: u32,
+        (now, tz): (DateTime<Utc>, Tz),
+    ) -> Result<Vec<WeekdayAverages>> {
+        let today = now.with_timezone(&tz).date_naive();
+        let first = today - Days::new(7 * u64::from(weeks));
+        let day_starts = starts(first.iter_days().take_while(|d| *d <= today), tz);
+        let tables = self.expense_tables(Some(time::start_of_day(first, tz)))?;
+        // Day `n` of the window falls on weekday `n + offset`, counted from
+        // Monday.
+        let offset = first.weekday().num_days_from_monday();
+        let mut stmt = self.connection.prepare(&format!(
+            "{} SELECT c.name, c.exponent, (b.n + ?3) % 7, SUM(e.amountMinor) {}
+             JOIN bounds b ON e.timestamp >= b.start AND e.timestamp < b.end
+             WHERE ?4 IS NULL OR e.categoryId IN (
+                 SELECT id FROM ExpenseCategory WHERE id = ?4 OR parentId = ?4
+             )
+             GROUP BY c.id, (b.n + ?3) % 7
+             ORDER BY c.name",
+            BOUNDS,
+            expenses(tables, 2)
+        ))?;
+        let rows = stmt.query_map(params![day_starts, household, offset, category], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, usize>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+        let mut currencies: Vec<WeekdayAverages> = Vec::new();
+        for row in rows {
+            let (currency, exponent, weekday, total) = row?;
+            if currencies.last().map(|c| &c.currency) != Some(&currency) {
+                currencies.push(WeekdayAverages {
+                    currency,
+                    exponent,
+                    averages: [0.0; 7],
+                });
+            }
+            let last = currencies.last_mut().expect("pushed above");
+            last.averages[weekday] = from_minor(total, exponent) / f64::from(weeks.max(1));
+        }
+        Ok(currencies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn spend(model: &Model, (account_id, category_id): (i64, i64), amount: f64, timestamp: &str) {
+        model
+            .insert_expense(&NewExpense {
+                amount,
+                account_id,
+                category_id,
+                user_id: 1001,
+                timestamp: at(timestamp),
+                comment: None,
+                category_confirmed: true,
+                source: ExpenseSource::Manual,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn averages_per_local_weekday() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        // Saturdays 6 and 13 January 2024, and Tuesday the 9th.
+        spend(&model, (1, 1), 30.0, "2024-01-06T10:00:00Z");
+        spend(&model, (1, 1), 10.0, "2024-01-13T10:00:00Z");
+        spend(&model, (1, 2), 4.0, "2024-01-09T10:00:00Z");
+        // Late on Sunday the 14th in UTC, Monday in Minsk.
+        spend(&model, (2, 1), 7.0, "2024-01-14T22:30:00Z");
+        // Today, Tuesday the 16th, isn't counted yet.
+        spend(&model, (1, 1), 100.0, "2024-01-16T08:00:00Z");
+        let now = at("2024-01-16T12:00:00Z");
+        let minsk: Tz = "Europe/Minsk".parse().unwrap();
+
+        let weeks = model.weekday_averages(1, None, 2, (now, minsk)).unwrap();
+        assert_eq!(
+            vec![
+                WeekdayAverages {
+                    currency: "EUR".to_string(),
+                    exponent: 2,
+                    averages: [0.0, 2.0, 0.0, 0.0, 0.0, 20.0, 0.0],
+                },
+                WeekdayAverages {
+                    currency: "USD".to_string(),
+                    exponent: 2,
+                    averages: [3.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                },
+            ],
+            weeks
+        );
+        let utc = model.weekday_averages(1, None, 2, (now, Tz::UTC)).unwrap();
+        assert_eq!(3.5, utc[1].averages[6]);
+
+        let groceries = model.weekday_averages(1, Some(1), 2, (now, minsk)).unwrap();
+        assert_eq!([0.0, 0.0, 0.0, 0.0, 0.0, 20.0, 0.0], groceries[0].averages);
+        // Nothing on utilities over the last week.
+        assert!(model
+            .weekday_averages(1, Some(4), 1, (now, minsk))
+            .unwrap()
+            .is_empty());
+        assert!(model
+            .weekday_averages(2, None, 2, (now, minsk))
+            .unwrap()
+            .is_empty());
+    }
+}
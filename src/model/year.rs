@@ -0,0 +1,397 @@
+//! The year in review: where the money went over a whole local year.
+
+use super::amount::from_minor;
+use super::archive::Tables;
+use super::{Error, Model, Result};
+use crate::time::{self, DbTime};
+use chrono::{DateTime, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct YearSummary {
+    pub year: i32,
+    /// One per currency with expenses in the year, by name.
+    pub currencies: Vec<CurrencyYear>,
+    /// The local day with the most expenses and their number.
+    pub busiest_day: Option<(NaiveDate, i64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrencyYear {
+    pub currency: String,
+    pub exponent: u32,
+    pub total: f64,
+    /// Spend per local month from January on, 0 for months without any.
+    pub months: Vec<f64>,
+    /// The five categories with the largest spend, largest first.
+    pub top_categories: Vec<(String, f64)>,
+    pub biggest: Option<BiggestExpense>,
+    /// Spend per user, largest first.
+    pub users: Vec<(String, f64)>,
+}
+
+impl CurrencyYear {
+    /// Percentage of the year's total.
+    pub fn share(&self, amount: f64) -> f64 {
+        if self.total > 0.0 {
+            amount / self.total * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiggestExpense {
+    pub amount: f64,
+    pub category: String,
+    pub timestamp: DateTime<Utc>,
+    pub comment: Option<String>,
+}
+
+/// Local days and months don't line up with UTC, nor with each other across
+/// DST changes, so their starts are passed in as a JSON array of unix
+/// seconds. `bounds` numbers the intervals between them from 0.
+pub(super) const BOUNDS: &str = "
+    WITH bounds AS (
+        SELECT key AS n, value AS start, LEAD(value) OVER (ORDER BY key) AS end
+        FROM json_each(?1)
+    )
+";
+
+/// The expenses of the household bound to parameter `?n`, without
+/// reconciliation adjustments, which aren't spending.
+pub(super) fn expenses(tables: Tables, n: usize) -> String {
+    tables.apply(&format!(
+        "FROM {{expenses}} e
+         JOIN Account a ON a.id = e.accountId AND a.householdId = ?{}
+             AND e.kind = 'expense'
+         JOIN Currency c ON c.id = a.currencyId",
+        n
+    ))
+}
+
+/// Starts of the local `dates` as a JSON array for `BOUNDS`.
+pub(super) fn starts(dates: impl Iterator<Item = NaiveDate>, tz: Tz) -> String {
+    let starts: Vec<String> = dates
+        .map(|d| time::to_db(time::start_of_day(d, tz)).to_string())
+        .collect();
+    format!("[{}]", starts.join(","))
+}
+
+fn of<'a>(currencies: &'a mut [CurrencyYear], currency: &str) -> &'a mut CurrencyYear {
+    currencies
+        .iter_mut()
+        .find(|c| c.currency == currency)
+        .expect("currencies come from the same expenses")
+}
+
+impl Model {
+    pub fn year_summary(&self, household: i64, year: i32, tz: Tz) -> Result<YearSummary> {
+        let first = NaiveDate::from_ymd_opt(year, 1, 1)
+            .ok_or_else(|| Error::NotFound(format!("year {}", year)))?;
+        let next = first + Months::new(12);
+        let (start, end) = (
+            DbTime(time::start_of_day(first, tz)),
+            DbTime(time::start_of_day(next, tz)),
+        );
+        let tables = self.expense_tables(Some(start.0))?;
+        let month_starts = starts((0..=12).map(|m| first + Months::new(m)), tz);
+        let day_starts = starts(first.iter_days().take_while(|d| *d <= next), tz);
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT c.name, c.exponent, SUM(e.amountMinor) {}
+             WHERE e.timestamp >= ?1 AND e.timestamp < ?2
+             GROUP BY c.id ORDER BY c.name",
+            expenses(tables, 3)
+        ))?;
+        let mut currencies = stmt
+            .query_map(params![start, end, household], |row| {
+                let exponent = row.get(1)?;
+                Ok(CurrencyYear {
+                    currency: row.get(0)?,
+                    exponent,
+                    total: from_minor(row.get(2)?, exponent),
+                    months: vec![0.0; 12],
+                    top_categories: Vec::new(),
+                    biggest: None,
+                    users: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if currencies.is_empty() {
+            return Ok(YearSummary {
+                year,
+                currencies,
+                busiest_day: None,
+            });
+        }
+
+        let mut stmt = self.connection.prepare(&format!(
+            "{} SELECT c.name, b.n, SUM(e.amountMinor) {}
+             JOIN bounds b ON e.timestamp >= b.start AND e.timestamp < b.end
+             GROUP BY c.id, b.n",
+            BOUNDS,
+            expenses(tables, 2)
+        ))?;
+        let months = stmt
+            .query_map(params![month_starts, household], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, usize>(1)?,
+                    row.get(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, usize, i64)>>>()?;
+        for (currency, month, minor) in months {
+            let c = of(&mut currencies, &currency);
+            c.months[month] = from_minor(minor, c.exponent);
+        }
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT currency, category, total FROM (
+                 SELECT c.name AS currency, ec.name AS category, SUM(e.amountMinor) AS total,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY c.id ORDER BY SUM(e.amountMinor) DESC, ec.name
+                        ) AS n
+                 {}
+                 JOIN ExpenseCategory ec ON ec.id = e.categoryId
+                 WHERE e.timestamp >= ?1 AND e.timestamp < ?2
+                 GROUP BY c.id, ec.id
+             )
+             WHERE n <= 5
+             ORDER BY currency, n",
+            expenses(tables, 3)
+        ))?;
+        let categories = stmt
+            .query_map(params![start, end, household], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, String, i64)>>>()?;
+        for (currency, category, minor) in categories {
+            let c = of(&mut currencies, &currency);
+            c.top_categories
+                .push((category, from_minor(minor, c.exponent)));
+        }
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT currency, amountMinor, category, timestamp, comments FROM (
+                 SELECT c.name AS currency, e.amountMinor, ec.name AS category, e.timestamp,
+                        e.comments,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY c.id ORDER BY e.amountMinor DESC, e.timestamp, e.id
+                        ) AS n
+                 {}
+                 JOIN ExpenseCategory ec ON ec.id = e.categoryId
+                 WHERE e.timestamp >= ?1 AND e.timestamp < ?2
+             )
+             WHERE n = 1",
+            expenses(tables, 3)
+        ))?;
+        let biggest = stmt
+            .query_map(params![start, end, household], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, DbTime>(3)?.0,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (currency, minor, category, timestamp, comment) in biggest {
+            let c = of(&mut currencies, &currency);
+            c.biggest = Some(BiggestExpense {
+                amount: from_minor(minor, c.exponent),
+                category,
+                timestamp,
+                comment,
+            });
+        }
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT c.name, COALESCE(u.displayName, CAST(e.userId AS TEXT)), SUM(e.amountMinor)
+             {}
+             LEFT JOIN User u ON u.telegramId = e.userId
+             WHERE e.timestamp >= ?1 AND e.timestamp < ?2
+             GROUP BY c.id, e.userId
+             ORDER BY c.name, SUM(e.amountMinor) DESC, e.userId",
+            expenses(tables, 3)
+        ))?;
+        let users = stmt
+            .query_map(params![start, end, household], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, String, i64)>>>()?;
+        for (currency, user, minor) in users {
+            let c = of(&mut currencies, &currency);
+            c.users.push((user, from_minor(minor, c.exponent)));
+        }
+
+        let busiest_day = self
+            .connection
+            .prepare(&format!(
+                "{} SELECT b.n, COUNT(*) {}
+                 JOIN bounds b ON e.timestamp >= b.start AND e.timestamp < b.end
+                 GROUP BY b.n
+                 ORDER BY COUNT(*) DESC, b.n
+                 LIMIT 1",
+                BOUNDS,
+                expenses(tables, 2)
+            ))?
+            .query_map(params![day_starts, household], |row| {
+                Ok((row.get::<_, u64>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .next()
+            .transpose()?
+            .map(|(day, count)| (first + chrono::Days::new(day), count));
+
+        Ok(YearSummary {
+            year,
+            currencies,
+            busiest_day,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ExpenseSource, NewExpense};
+    use chrono::Datelike;
+
+    /// A year of expenses following a fixed pattern: every third day,
+    /// amounts growing with the day of the year, no expenses in August.
+    fn synthetic_year(model: &Model, year: i32) {
+        let first = NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        for (n, day) in first
+            .iter_days()
+            .take_while(|d| d.year() == year)
+            .enumerate()
+            .filter(|(n, d)| n % 3 == 0 && d.month() != 8)
+        {
+            let timestamp = time::start_of_day(day, Tz::UTC) + chrono::TimeDelta::hours(12);
+            let (account_id, user_id) = if n % 2 == 0 { (1, 1001) } else { (2, 1002) };
+            model
+                .insert_expense(&NewExpense {
+                    account_id,
+                    category_id: (n % 4) as i64 + 1,
+                    user_id,
+                    timestamp,
+                    amount: 1.0 + n as f64 / 100.0,
+                    comment: None,
+                    category_confirmed: true,
+                    source: ExpenseSource::Manual,
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn summarizes_a_synthetic_year() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        synthetic_year(&model, 2022);
+
+        let summary = model.year_summary(1, 2022, Tz::UTC).unwrap();
+        let currencies: Vec<&str> = summary
+            .currencies
+            .iter()
+            .map(|c| c.currency.as_str())
+            .collect();
+        assert_eq!(vec!["EUR", "USD"], currencies);
+
+        let eur = &summary.currencies[0];
+        assert_eq!(12, eur.months.len());
+        assert_eq!(0.0, eur.months[7]);
+        // Days 0, 6, 12, 18, 24 and 30 of January.
+        assert!((eur.months[0] - 6.9).abs() < 1e-9, "{}", eur.months[0]);
+        assert!((eur.total - eur.months.iter().sum::<f64>()).abs() < 1e-9);
+        // Every sixth day alternates between categories 1 and 3.
+        let categories: Vec<&str> = eur
+            .top_categories
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(vec!["Entertainment", "Groceries"], categories);
+        let shares: f64 = eur.top_categories.iter().map(|(_, t)| eur.share(*t)).sum();
+        assert!((shares - 100.0).abs() < 1e-9);
+        assert_eq!(vec![("Alex".to_string(), eur.total)], eur.users);
+
+        let biggest = eur.biggest.as_ref().unwrap();
+        // Day 360 (December 27th) is the last even multiple of three.
+        assert_eq!(
+            (4.6, "Groceries"),
+            (biggest.amount, biggest.category.as_str())
+        );
+        assert_eq!(
+            NaiveDate::from_ymd_opt(2022, 12, 27).unwrap(),
+            biggest.timestamp.date_naive()
+        );
+
+        // A single expense per day, so the first one is the busiest.
+        assert_eq!(
+            Some((NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(), 1)),
+            summary.busiest_day
+        );
+    }
+
+    #[test]
+    fn months_and_days_are_local() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        let expense = |timestamp: i64, amount: f64| NewExpense {
+            account_id: 1,
+            category_id: 1,
+            user_id: 1001,
+            timestamp: DateTime::from_timestamp(timestamp, 0).unwrap(),
+            amount,
+            comment: Some("late".to_string()),
+            category_confirmed: true,
+            source: ExpenseSource::Manual,
+        };
+        // 2022-12-31 23:30 UTC is already 2023 in Berlin.
+        model.insert_expense(&expense(1_672_529_400, 5.0)).unwrap();
+        // 2023-03-31 22:30 UTC is April 1st in Berlin, twice.
+        model.insert_expense(&expense(1_680_301_800, 7.0)).unwrap();
+        model.insert_expense(&expense(1_680_302_400, 3.0)).unwrap();
+
+        // The test data has a few more expenses in December.
+        let eur = |summary: &YearSummary| {
+            summary
+                .currencies
+                .iter()
+                .find(|c| c.currency == "EUR")
+                .unwrap()
+                .clone()
+        };
+        let berlin = model.year_summary(1, 2023, Tz::Europe__Berlin).unwrap();
+        let eur_berlin = eur(&berlin);
+        assert_eq!(5.0, eur_berlin.months[0]);
+        assert_eq!(0.0, eur_berlin.months[2]);
+        assert_eq!(10.0, eur_berlin.months[3]);
+        assert_eq!(
+            Some((NaiveDate::from_ymd_opt(2023, 4, 1).unwrap(), 2)),
+            berlin.busiest_day
+        );
+
+        let eur_utc = eur(&model.year_summary(1, 2023, Tz::UTC).unwrap());
+        assert_eq!((0.0, 10.0), (eur_utc.months[0], eur_utc.months[2]));
+        assert_eq!(60.75, eur_utc.total);
+    }
+
+    #[test]
+    fn empty_year() {
+        let model = Model::new(true);
+        model.fill_test_data();
+        assert_eq!(
+            YearSummary {
+                year: 2020,
+                currencies: Vec::new(),
+                busiest_day: None,
+            },
+            model.year_summary(1, 2020, Tz::UTC).unwrap()
+        );
+    }
+}
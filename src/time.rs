@@ -0,0 +1,399 @@
+//! Timestamps are `DateTime<Utc>` in memory and unix seconds in the
+//! database. Conversions between the two, and to and from the local time
+//! users read and type, all go through here.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+pub fn to_db(dt: DateTime<Utc>) -> i64 {
+    dt.timestamp()
+}
+
+/// `None` for values outside of what `DateTime` can represent.
+pub fn from_db(seconds: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, 0)
+}
+
+/// A timestamp as stored in an `INTEGER` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DbTime(pub DateTime<Utc>);
+
+impl ToSql for DbTime {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(to_db(self.0)))
+    }
+}
+
+impl FromSql for DbTime {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let seconds = value.as_i64()?;
+        from_db(seconds)
+            .map(DbTime)
+            .ok_or(FromSqlError::OutOfRange(seconds))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Date,
+    DateTime,
+    /// Month and day only, like `Dec 3`, for tight spaces such as buttons.
+    ShortDate,
+    /// Hours and minutes only.
+    Time,
+}
+
+impl Style {
+    fn format(self) -> &'static str {
+        match self {
+            Style::Date => "%Y-%m-%d",
+            Style::DateTime => "%Y-%m-%d %H:%M",
+            Style::ShortDate => "%b %-d",
+            Style::Time => "%H:%M",
+        }
+    }
+}
+
+/// The timestamp as local time in `tz`.
+pub fn render(dt: DateTime<Utc>, tz: Tz, style: Style) -> String {
+    dt.with_timezone(&tz).format(style.format()).to_string()
+}
+
+/// The language dates are written in for a user, from their Telegram
+/// language. Everyone else reads English.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Lang {
+    /// The language of an IETF language tag like `ru` or `en-GB`.
+    pub fn from_language(code: Option<&str>) -> Lang {
+        let language = code
+            .and_then(|code| code.split(['-', '_']).next())
+            .unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "ru" => Lang::Ru,
+            _ => Lang::En,
+        }
+    }
+
+    fn short_month(self, month0: usize) -> &'static str {
+        const EN: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        const RU: [&str; 12] = [
+            "янв", "фев", "мар", "апр", "мая", "июн", "июл", "авг", "сен", "окт", "ноя", "дек",
+        ];
+        match self {
+            Lang::En => EN[month0],
+            Lang::Ru => RU[month0],
+        }
+    }
+
+    /// The full name, in Russian as of a date: "3 декабря".
+    fn month(self, month0: usize) -> &'static str {
+        const EN: [&str; 12] = [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ];
+        const RU: [&str; 12] = [
+            "января",
+            "февраля",
+            "марта",
+            "апреля",
+            "мая",
+            "июня",
+            "июля",
+            "августа",
+            "сентября",
+            "октября",
+            "ноября",
+            "декабря",
+        ];
+        match self {
+            Lang::En => EN[month0],
+            Lang::Ru => RU[month0],
+        }
+    }
+
+    /// Like "2 hours ago", `unit` being the English unit and its Russian
+    /// forms for 1, 2 and 5.
+    fn ago(self, n: i64, unit: (&str, [&str; 3])) -> String {
+        match self {
+            Lang::En if n == 1 => format!("1 {} ago", unit.0),
+            Lang::En => format!("{} {}s ago", n, unit.0),
+            Lang::Ru => {
+                let form = match (n % 10, n % 100) {
+                    (1, 11) | (2..=4, 12..=14) => 2,
+                    (1, _) => 0,
+                    (2..=4, _) => 1,
+                    _ => 2,
+                };
+                format!("{} {} назад", n, unit.1[form])
+            }
+        }
+    }
+}
+
+/// How [`render_datetime`] writes a timestamp for people to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateStyle {
+    /// Like `Dec 3, 18:00`, without the year.
+    Short,
+    /// Like `December 3, 2023, 18:00`.
+    Long,
+    /// Like `2 hours ago` as seen at the given time. Anything older than a
+    /// week, or ahead of it, is [`DateStyle::Short`].
+    Relative(DateTime<Utc>),
+}
+
+/// How long before now [`DateStyle::Relative`] still counts back.
+const RELATIVE_LIMIT: Duration = Duration::days(7);
+
+/// The timestamp as local time in `tz`, for users of `lang` to read. Times
+/// are on the 24-hour clock in both languages.
+pub fn render_datetime(dt: DateTime<Utc>, tz: Tz, lang: Lang, style: DateStyle) -> String {
+    let local = dt.with_timezone(&tz);
+    let month0 = local.month0() as usize;
+    let clock = local.format("%H:%M");
+    match style {
+        DateStyle::Short => match lang {
+            Lang::En => format!("{} {}, {}", lang.short_month(month0), local.day(), clock),
+            Lang::Ru => format!("{} {}, {}", local.day(), lang.short_month(month0), clock),
+        },
+        DateStyle::Long => match lang {
+            Lang::En => format!(
+                "{} {}, {}, {}",
+                lang.month(month0),
+                local.day(),
+                local.year(),
+                clock
+            ),
+            Lang::Ru => format!(
+                "{} {} {}, {}",
+                local.day(),
+                lang.month(month0),
+                local.year(),
+                clock
+            ),
+        },
+        DateStyle::Relative(now) => {
+            let ago = now - dt;
+            if ago < Duration::zero() || ago >= RELATIVE_LIMIT {
+                return render_datetime(dt, tz, lang, DateStyle::Short);
+            }
+            if ago < Duration::minutes(1) {
+                return match lang {
+                    Lang::En => "just now".to_string(),
+                    Lang::Ru => "только что".to_string(),
+                };
+            }
+            if ago < Duration::hours(1) {
+                return lang.ago(ago.num_minutes(), ("minute", ["минуту", "минуты", "минут"]));
+            }
+            if ago < Duration::days(1) {
+                return lang.ago(ago.num_hours(), ("hour", ["час", "часа", "часов"]));
+            }
+            lang.ago(ago.num_days(), ("day", ["день", "дня", "дней"]))
+        }
+    }
+}
+
+/// The instant a local wall clock time refers to. Ambiguous times (clocks
+/// going back) take the earlier instant; times skipped by a DST jump move
+/// forward to the first time that exists.
+pub fn from_local(local: NaiveDateTime, tz: Tz) -> DateTime<Utc> {
+    let mut time = local;
+    loop {
+        if let Some(dt) = tz.from_local_datetime(&time).earliest() {
+            return dt.with_timezone(&Utc);
+        }
+        time += Duration::minutes(15);
+    }
+}
+
+/// Start of the local day.
+pub fn start_of_day(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    from_local(date.and_hms_opt(0, 0, 0).unwrap(), tz)
+}
+
+/// Parses a typed local time in either `Style`, a bare date meaning midnight.
+pub fn parse_local(text: &str, tz: Tz) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(local) = NaiveDateTime::parse_from_str(text, Style::DateTime.format()) {
+        return Some(from_local(local, tz));
+    }
+    NaiveDate::parse_from_str(text, Style::Date.format())
+        .ok()
+        .map(|date| start_of_day(date, tz))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn roundtrip(dt: DateTime<Utc>) -> (i64, DateTime<Utc>) {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (ts INTEGER NOT NULL)")
+            .unwrap();
+        conn.execute("INSERT INTO t (ts) VALUES (?1)", [DbTime(dt)])
+            .unwrap();
+        conn.query_row("SELECT ts, ts FROM t", [], |row| {
+            Ok((row.get(0)?, row.get::<_, DbTime>(1)?.0))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn stored_as_unix_seconds() {
+        for (dt, seconds) in [
+            (at("1970-01-01T00:00:00Z"), 0),
+            (at("2023-12-01T10:00:00Z"), 1_701_424_800),
+            // Before the epoch.
+            (at("1969-07-20T20:17:00Z"), -14_182_980),
+            (at("1900-01-01T00:00:00Z"), -2_208_988_800),
+            // Both sides of the 32-bit time_t overflow.
+            (at("2038-01-19T03:14:07Z"), i32::MAX as i64),
+            (at("2038-01-19T03:14:08Z"), i32::MAX as i64 + 1),
+            (at("2106-02-07T06:28:16Z"), u32::MAX as i64 + 1),
+        ] {
+            assert_eq!(seconds, to_db(dt));
+            assert_eq!(Some(dt), from_db(seconds));
+            assert_eq!((seconds, dt), roundtrip(dt));
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert_eq!(None, from_db(i64::MAX));
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let result = conn.query_row("SELECT ?1", [i64::MAX], |row| row.get::<_, DbTime>(0));
+        assert!(result.is_err());
+        let result = conn.query_row("SELECT 'yesterday'", [], |row| row.get::<_, DbTime>(0));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn renders_local_time() {
+        let dt = at("2023-12-31T23:30:00Z");
+        assert_eq!("2023-12-31 23:30", render(dt, Tz::UTC, Style::DateTime));
+        assert_eq!(
+            "2024-01-01 00:30",
+            render(dt, Tz::Europe__Berlin, Style::DateTime)
+        );
+        assert_eq!("2024-01-01", render(dt, Tz::Europe__Berlin, Style::Date));
+    }
+
+    #[test]
+    fn renders_dates_in_both_languages() {
+        let render = |dt: &str, lang, style| render_datetime(at(dt), Tz::UTC, lang, style);
+        let evening = "2023-12-03T18:00:00Z";
+        assert_eq!("Dec 3, 18:00", render(evening, Lang::En, DateStyle::Short));
+        assert_eq!("3 дек, 18:00", render(evening, Lang::Ru, DateStyle::Short));
+        assert_eq!(
+            "December 3, 2023, 18:00",
+            render(evening, Lang::En, DateStyle::Long)
+        );
+        assert_eq!(
+            "3 декабря 2023, 18:00",
+            render(evening, Lang::Ru, DateStyle::Long)
+        );
+        // Midnight and noon on the 24-hour clock, midnight starting the day.
+        let midnight = "2024-05-01T00:00:00Z";
+        assert_eq!("May 1, 00:00", render(midnight, Lang::En, DateStyle::Short));
+        assert_eq!("1 мая, 00:00", render(midnight, Lang::Ru, DateStyle::Short));
+        assert_eq!(
+            "1 мая 2024, 12:00",
+            render("2024-05-01T12:00:00Z", Lang::Ru, DateStyle::Long)
+        );
+        // Local time, a new year in Berlin.
+        assert_eq!(
+            "January 1, 2024, 00:30",
+            render_datetime(
+                at("2023-12-31T23:30:00Z"),
+                Tz::Europe__Berlin,
+                Lang::En,
+                DateStyle::Long
+            )
+        );
+    }
+
+    #[test]
+    fn relative_dates_stop_after_a_week() {
+        let now = at("2023-12-10T12:00:00Z");
+        let ago = |dt: &str, lang| render_datetime(at(dt), Tz::UTC, lang, DateStyle::Relative(now));
+        assert_eq!("just now", ago("2023-12-10T11:59:30Z", Lang::En));
+        assert_eq!("только что", ago("2023-12-10T11:59:30Z", Lang::Ru));
+        assert_eq!("1 minute ago", ago("2023-12-10T11:59:00Z", Lang::En));
+        assert_eq!("21 минуту назад", ago("2023-12-10T11:39:00Z", Lang::Ru));
+        assert_eq!("2 hours ago", ago("2023-12-10T10:00:00Z", Lang::En));
+        assert_eq!("2 часа назад", ago("2023-12-10T10:00:00Z", Lang::Ru));
+        assert_eq!("11 часов назад", ago("2023-12-10T01:00:00Z", Lang::Ru));
+        assert_eq!("1 day ago", ago("2023-12-09T12:00:00Z", Lang::En));
+        assert_eq!("5 дней назад", ago("2023-12-05T12:00:00Z", Lang::Ru));
+        assert_eq!("6 days ago", ago("2023-12-03T12:00:01Z", Lang::En));
+        // A week back and beyond, or ahead, are dates.
+        assert_eq!("Dec 3, 12:00", ago("2023-12-03T12:00:00Z", Lang::En));
+        assert_eq!("1 ноя, 08:00", ago("2023-11-01T08:00:00Z", Lang::Ru));
+        assert_eq!("Dec 11, 09:00", ago("2023-12-11T09:00:00Z", Lang::En));
+    }
+
+    #[test]
+    fn languages_from_telegram() {
+        assert_eq!(Lang::En, Lang::from_language(None));
+        assert_eq!(Lang::En, Lang::from_language(Some("en-GB")));
+        assert_eq!(Lang::Ru, Lang::from_language(Some("ru")));
+        assert_eq!(Lang::Ru, Lang::from_language(Some("RU-ru")));
+        assert_eq!(Lang::En, Lang::from_language(Some("de")));
+    }
+
+    #[test]
+    fn parses_local_time() {
+        assert_eq!(
+            Some(at("2023-12-01T10:00:00Z")),
+            parse_local("2023-12-01 10:00", Tz::UTC)
+        );
+        assert_eq!(
+            Some(at("2023-12-01T09:00:00Z")),
+            parse_local(" 2023-12-01 10:00 ", Tz::Europe__Berlin)
+        );
+        assert_eq!(
+            Some(at("2023-11-30T23:00:00Z")),
+            parse_local("2023-12-01", Tz::Europe__Berlin)
+        );
+        assert_eq!(None, parse_local("yesterday", Tz::UTC));
+    }
+
+    #[test]
+    fn dst_gaps_and_overlaps() {
+        // 02:30 doesn't exist in Berlin on 2024-03-31, 03:00 CEST is next.
+        assert_eq!(
+            Some(at("2024-03-31T01:00:00Z")),
+            parse_local("2024-03-31 02:30", Tz::Europe__Berlin)
+        );
+        // 02:30 happens twice on 2024-10-27, the first one is taken.
+        assert_eq!(
+            Some(at("2024-10-27T00:30:00Z")),
+            parse_local("2024-10-27 02:30", Tz::Europe__Berlin)
+        );
+    }
+}